@@ -1,13 +1,14 @@
 use bitcoind::bitcoincore_rpc::Auth;
 use clap::Parser;
 use coinswap::{
-    maker::{start_maker_server, Maker, MakerBehavior},
+    maker::{Maker, MakerBehavior, MakerServer},
     utill::{
-        parse_proxy_auth, read_bitcoin_network_string, read_connection_network_string, setup_logger,
+        parse_proxy_auth, read_bitcoin_network_string, read_connection_network_string,
+        register_shutdown_signals, setup_logger,
     },
     wallet::RPCConfig,
 };
-use std::{path::PathBuf, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 
 /// The Maker Server.
 ///
@@ -50,10 +51,15 @@ struct Cli {
     /// Sets the maker wallet's name. If the wallet file already exists at data-directory, it will load that wallet.
     #[clap(name = "WALLET", long, short = 'w', default_value = "maker")]
     pub wallet_name: String,
+    /// Optional Tor control port address. If set, the maker is served as an ephemeral onion service of that Tor.
+    #[clap(name = "TOR_CONTROL", long)]
+    pub tor_control: Option<SocketAddr>,
 }
 
 fn main() -> std::io::Result<()> {
     setup_logger();
+    // SIGINT/SIGTERM drain the swaps in flight, a second one stops right away.
+    register_shutdown_signals();
 
     let args = Cli::parse();
 
@@ -66,6 +72,7 @@ fn main() -> std::io::Result<()> {
         auth: Auth::UserPass(args.auth.0, args.auth.1),
         network: rpc_network,
         wallet_name: args.wallet_name.clone(),
+        ..Default::default()
     };
 
     let maker = Arc::new(
@@ -82,7 +89,11 @@ fn main() -> std::io::Result<()> {
         .unwrap(),
     );
 
-    start_maker_server(maker).unwrap();
+    let mut server = MakerServer::new(maker);
+    if let Some(tor_control) = args.tor_control {
+        server = server.with_onion(tor_control);
+    }
+    server.run().unwrap();
 
     Ok(())
 }
@@ -1,14 +1,16 @@
-use std::path::PathBuf;
+use std::{collections::HashSet, path::PathBuf, process};
 
-use bitcoin::Amount;
+use bitcoin::{Amount, FeeRate};
 use bitcoind::bitcoincore_rpc::{json::ListUnspentResultEntry, Auth};
 use clap::Parser;
 use coinswap::{
-    taker::{SwapParams, Taker, TakerBehavior},
+    protocol::contract::funding_tx_fee,
+    taker::{error::TakerError, SwapParams, Taker, TakerBehavior},
     utill::{
-        parse_proxy_auth, read_bitcoin_network_string, read_connection_network_string, setup_logger,
+        parse_amount, parse_proxy_auth, read_bitcoin_network_string,
+        read_connection_network_string, register_shutdown_signals, setup_logger,
     },
-    wallet::RPCConfig,
+    wallet::{RPCConfig, SwapCoin},
 };
 
 /// taker-cli is a command line app to use taker client API's.
@@ -86,6 +88,120 @@ enum Commands {
     SyncOfferBook,
     /// Initiate the coinswap process
     DoCoinswap,
+    /// Do a coinswap of the given amount through the given number of makers, and print the
+    /// resulting swap outputs. On failure, exits with code 3 if the wallet can't load, 4 if it
+    /// can't sync, 5 without enough suitable makers, 6 if the protocol fails, and 7 if the swap
+    /// was aborted and the funds recovered.
+    Swap {
+        /// Amount to swap, like `0.01btc` or `500000sat`. Plain numbers are in sats.
+        #[clap(long, value_parser = parse_amount)]
+        amount: Amount,
+        /// Number of makers to hop through.
+        #[clap(long, default_value = "2")]
+        makers: usize,
+        /// Fee rate of the funding txs, in sats/vbyte.
+        #[clap(long, default_value = "3")]
+        fee_rate: u64,
+        /// Number of funding txs the amount is split into.
+        #[clap(long, default_value = "3")]
+        tx_count: u32,
+    },
+}
+
+/// Exit codes of a failed [Commands::Swap], by phase.
+#[derive(Debug, Clone, Copy)]
+enum FailureExit {
+    /// The taker wallet couldn't be loaded.
+    Load = 3,
+    /// The wallet couldn't sync with the node.
+    Sync = 4,
+    /// The directory server is unreachable, or there aren't enough suitable makers.
+    MakerSelection = 5,
+    /// The swap protocol failed.
+    Protocol = 6,
+    /// The swap was aborted, and the funds recovered from the contracts.
+    Aborted = 7,
+}
+
+impl FailureExit {
+    fn from_swap_error(error: &TakerError) -> Self {
+        match error {
+            TakerError::NotEnoughMakersInOfferBook | TakerError::Directory(_) => {
+                Self::MakerSelection
+            }
+            // The wallet is synced before the swap, so wallet and node errors here are mid-swap.
+            _ => Self::Protocol,
+        }
+    }
+
+    fn exit(self, message: &str) -> ! {
+        eprintln!("{}", message);
+        process::exit(self as i32)
+    }
+}
+
+/// The miner fee of each funding tx at `sat_per_vb`, which is what [SwapParams] carry.
+fn funding_fee(sat_per_vb: u64) -> Amount {
+    funding_tx_fee(FeeRate::from_sat_per_vb(sat_per_vb).unwrap_or(FeeRate::MAX))
+}
+
+/// Runs [Commands::Swap], and prints the incoming swapcoins it completed and the total fee paid
+/// to the makers and miners.
+fn swap(taker: &mut Taker, swap_params: SwapParams) {
+    // SIGINT/SIGTERM let the swap in flight finish, a second one stops at its next checkpoint.
+    register_shutdown_signals();
+    if let Err(e) = taker.get_wallet_mut().sync() {
+        FailureExit::Sync.exit(&format!("Wallet sync failed: {:?}", e));
+    }
+    let previous_incomings = taker
+        .get_wallet()
+        .get_incoming_swapcoin_list()
+        .map(|swapcoins| swapcoins.keys().cloned().collect::<HashSet<_>>())
+        .unwrap_or_default();
+    let previous_balance = taker
+        .get_wallet()
+        .balance()
+        .unwrap_or_else(|e| FailureExit::Sync.exit(&format!("Balance lookup failed: {:?}", e)));
+
+    if let Err(e) = taker.do_coinswap(swap_params) {
+        FailureExit::from_swap_error(&e).exit(&format!("Coinswap failed: {:?}", e));
+    }
+
+    let wallet = taker.get_wallet();
+    let outputs = wallet
+        .get_incoming_swapcoin_list()
+        .map(|swapcoins| {
+            swapcoins
+                .iter()
+                .filter(|(multisig_redeemscript, swapcoin)| {
+                    !previous_incomings.contains(*multisig_redeemscript)
+                        && swapcoin.other_privkey.is_some()
+                })
+                .map(|(_, swapcoin)| swapcoin)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    if outputs.is_empty() {
+        FailureExit::Aborted.exit("Coinswap aborted, the funds are recovered from the contracts");
+    }
+    let balance = wallet
+        .balance()
+        .unwrap_or_else(|e| FailureExit::Sync.exit(&format!("Balance lookup failed: {:?}", e)));
+
+    println!("Coinswap completed. Swap outputs:");
+    for swapcoin in outputs {
+        println!(
+            "  {} {}",
+            swapcoin.contract_tx.input[0].previous_output,
+            swapcoin.get_funding_amount()
+        );
+    }
+    println!(
+        "Total fee paid: {}",
+        previous_balance
+            .checked_sub(balance)
+            .unwrap_or(Amount::ZERO)
+    );
 }
 
 fn main() {
@@ -98,6 +214,7 @@ fn main() {
         auth: Auth::UserPass(args.auth.0, args.auth.1),
         network: rpc_network,
         wallet_name: args.wallet_name.clone(),
+        ..Default::default()
     };
 
     let swap_params = SwapParams {
@@ -115,7 +232,7 @@ fn main() {
         TakerBehavior::Normal,
         Some(connection_type),
     )
-    .unwrap();
+    .unwrap_or_else(|e| FailureExit::Load.exit(&format!("Failed to load the taker: {:?}", e)));
 
     match args.command {
         Commands::SeedUtxo => {
@@ -181,7 +298,64 @@ fn main() {
             taker.sync_offerbook(&config, args.maker_count).unwrap();
         }
         Commands::DoCoinswap => {
+            register_shutdown_signals();
             taker.do_coinswap(swap_params).unwrap();
         }
+        Commands::Swap {
+            amount,
+            makers,
+            fee_rate,
+            tx_count,
+        } => swap(
+            &mut taker,
+            SwapParams {
+                send_amount: amount,
+                maker_count: makers,
+                tx_count,
+                required_confirms: args.required_confirms,
+                fee_rate: funding_fee(fee_rate),
+            },
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoind::bitcoincore_rpc::Error as RpcError;
+    use coinswap::{error::ProtocolError, wallet::WalletError};
+
+    #[test]
+    fn test_swap_failure_exit_codes() {
+        let exit_code = |e: TakerError| FailureExit::from_swap_error(&e) as i32;
+
+        assert_eq!(exit_code(TakerError::NotEnoughMakersInOfferBook), 5);
+        // Mid-swap wallet and node errors are protocol failures, not sync failures.
+        assert_eq!(
+            exit_code(TakerError::Wallet(WalletError::Protocol(
+                "fail".to_string()
+            ))),
+            6
+        );
+        assert_eq!(
+            exit_code(TakerError::RPCError(RpcError::ReturnedError(
+                "fail".to_string()
+            ))),
+            6
+        );
+        assert_eq!(
+            exit_code(TakerError::Protocol(ProtocolError::WrongMessage {
+                expected: "a".to_string(),
+                received: "b".to_string(),
+            })),
+            6
+        );
+        assert_eq!(exit_code(TakerError::FundingTxWaitTimeOut), 6);
+    }
+
+    #[test]
+    fn test_funding_fee() {
+        assert_eq!(funding_fee(3), Amount::from_sat(3 * 372));
+        assert_eq!(funding_fee(u64::MAX), Amount::MAX_MONEY);
     }
 }
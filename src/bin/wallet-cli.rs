@@ -0,0 +1,186 @@
+use std::{convert::TryFrom, path::PathBuf};
+
+use bitcoin::Amount;
+use bitcoind::bitcoincore_rpc::{Auth, Client};
+use clap::Parser;
+use coinswap::{
+    utill::{
+        get_taker_dir, parse_amount, parse_proxy_auth, read_bitcoin_network_string, setup_logger,
+    },
+    wallet::{
+        FallbackFeeEstimator, FeeEstimator, PaymentRequest, RPCConfig, StaticFeeEstimator,
+        UtxoOrigin, Wallet, WalletError, DEFAULT_CONFIRMATION_TARGET, FALLBACK_FEE_RATE,
+    },
+};
+
+/// wallet-cli is a command line app to inspect, receive to and send from a coinswap wallet file.
+#[derive(Parser, Debug)]
+#[clap(version = option_env ! ("CARGO_PKG_VERSION").unwrap_or("unknown"),
+author = option_env ! ("CARGO_PKG_AUTHORS").unwrap_or(""))]
+struct Cli {
+    /// Path of the wallet file. Default value : "~/.coinswap/taker/wallets/taker"
+    #[clap(long, short = 'w')]
+    wallet: Option<PathBuf>,
+    /// Sets the full node address for rpc connection.
+    #[clap(
+        name = "ADDRESS:PORT",
+        long,
+        short = 'r',
+        default_value = "127.0.0.1:18443"
+    )]
+    pub rpc: String,
+    /// Sets the rpc basic authentication.
+    #[clap(name="USER:PASSWORD",short='a',long, value_parser = parse_proxy_auth, default_value = "user:password")]
+    pub auth: (String, String),
+    /// Sets the full node network, this should match with the network of the running node.
+    #[clap(
+        name = "NETWORK",
+        long,
+        short = 'n',
+        default_value = "regtest", possible_values = &["regtest", "signet", "mainnet"]
+    )]
+    pub rpc_network: String,
+    /// List of sub commands of the wallet cli app.
+    #[clap(subcommand)]
+    command: Commands,
+}
+
+#[derive(Parser, Debug)]
+enum Commands {
+    /// Returns the next receive address, and moves the receive index past it
+    NewAddress,
+    /// Returns the wallet balance by category
+    Balance,
+    /// Returns the spendable utxos, labelled with their origin
+    ListUtxos,
+    /// Sends to a `bitcoin:` URI or an address, and returns the txid
+    Send {
+        /// BIP21 URI, like `bitcoin:<address>?amount=0.01`, or a plain address.
+        destination: PaymentRequest,
+        /// Amount to send, like `0.01btc` or `500000sat`. Required if the URI has no amount.
+        #[clap(long, value_parser = parse_amount)]
+        amount: Option<Amount>,
+        /// Fee rate, in sats/vbyte. Defaults to the node's estimate, or 2 sats/vbyte without one.
+        #[clap(long)]
+        fee_rate: Option<f64>,
+    },
+    /// Sends every spendable utxo to a `bitcoin:` URI or an address, and returns the txid.
+    /// Locked utxos and fidelity bonds are left in the wallet.
+    Sweep {
+        /// BIP21 URI without an amount, or a plain address.
+        destination: PaymentRequest,
+        /// Fee rate, in sats/vbyte. Defaults to the node's estimate, or 2 sats/vbyte without one.
+        #[clap(long)]
+        fee_rate: Option<f64>,
+    },
+}
+
+fn main() -> Result<(), WalletError> {
+    setup_logger();
+    let args = Cli::parse();
+
+    let wallet_path = args
+        .wallet
+        .unwrap_or_else(|| get_taker_dir().join("wallets").join("taker"));
+    let wallet_name = wallet_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| WalletError::Protocol("Invalid wallet file path".to_string()))?
+        .to_string();
+    let rpc_config = RPCConfig {
+        url: args.rpc,
+        auth: Auth::UserPass(args.auth.0, args.auth.1),
+        network: read_bitcoin_network_string(&args.rpc_network).map_err(WalletError::Protocol)?,
+        wallet_name,
+        ..Default::default()
+    };
+
+    let mut wallet = Wallet::load(&rpc_config, &wallet_path)?;
+
+    match args.command {
+        Commands::NewAddress => {
+            let address = wallet.get_next_external_address()?;
+            println!("{}", address);
+        }
+        Commands::Balance => {
+            wallet.sync()?;
+            let balances = wallet.get_balances()?;
+            println!("spendable: {}", balances.spendable);
+            println!("incoming swapcoins: {}", balances.incoming_swapcoins);
+            println!("outgoing swapcoins: {}", balances.outgoing_swapcoins);
+            println!("fidelity: {}", balances.fidelity);
+            println!("unconfirmed: {}", balances.unconfirmed);
+        }
+        Commands::ListUtxos => {
+            wallet.sync()?;
+            for (utxo, origin) in wallet.list_utxo_with_origin()? {
+                let origin = match origin {
+                    UtxoOrigin::SwapOutput { swap_id } => format!("swap {}", swap_id),
+                    UtxoOrigin::Change => "change".to_string(),
+                    UtxoOrigin::External => "deposit".to_string(),
+                    UtxoOrigin::FidelityRedeem => "fidelity redeem".to_string(),
+                };
+                println!(
+                    "{}:{} {} {} confs, {}",
+                    utxo.txid, utxo.vout, utxo.amount, utxo.confirmations, origin
+                );
+            }
+        }
+        Commands::Send {
+            destination,
+            amount,
+            fee_rate,
+        } => {
+            let amount = match (amount, destination.amount) {
+                (Some(amount), Some(requested)) if amount != requested => {
+                    return Err(WalletError::Protocol(format!(
+                        "Amount {} differs from the requested {}",
+                        amount, requested
+                    )))
+                }
+                (Some(amount), _) | (None, Some(amount)) => amount,
+                (None, None) => {
+                    return Err(WalletError::Protocol(
+                        "No amount given, and none requested".to_string(),
+                    ))
+                }
+            };
+            wallet.sync()?;
+            let fee_estimator = fee_estimator(&rpc_config, fee_rate)?;
+            let txid = wallet.send_to(destination.address, amount, &fee_estimator)?;
+            println!("{}", txid);
+        }
+        Commands::Sweep {
+            destination,
+            fee_rate,
+        } => {
+            if let Some(requested) = destination.amount {
+                return Err(WalletError::Protocol(format!(
+                    "Can't sweep to a request of {}",
+                    requested
+                )));
+            }
+            wallet.sync()?;
+            let fee_estimator = fee_estimator(&rpc_config, fee_rate)?;
+            let txid = wallet.sweep_all(destination.address, &fee_estimator)?;
+            println!("{}", txid);
+        }
+    }
+
+    Ok(())
+}
+
+/// The given `fee_rate`, or else the node's estimate, falling back to 2 sats/vbyte.
+fn fee_estimator(
+    rpc_config: &RPCConfig,
+    fee_rate: Option<f64>,
+) -> Result<StaticFeeEstimator, WalletError> {
+    if let Some(fee_rate) = fee_rate {
+        return Ok(StaticFeeEstimator::from_sat_per_vb(fee_rate));
+    }
+    let client = Client::try_from(rpc_config)?;
+    let fallback = StaticFeeEstimator(FALLBACK_FEE_RATE);
+    let fee_rate =
+        FallbackFeeEstimator(vec![&client, &fallback]).estimate(DEFAULT_CONFIRMATION_TARGET)?;
+    Ok(StaticFeeEstimator(fee_rate))
+}
@@ -1,6 +1,6 @@
 //! High-level network and protocol errors.
 
-use bitcoin::Amount;
+use bitcoin::{Amount, FeeRate};
 
 use crate::protocol::error::ContractError;
 
@@ -29,11 +29,33 @@ impl From<serde_cbor::Error> for NetError {
 /// Includes all Protocol-level errors.
 #[derive(Debug)]
 pub enum ProtocolError {
-    WrongMessage { expected: String, received: String },
-    WrongNumOfSigs { expected: usize, received: usize },
-    WrongNumOfContractTxs { expected: usize, received: usize },
-    WrongNumOfPrivkeys { expected: usize, received: usize },
-    IncorrectFundingAmount { expected: Amount, found: Amount },
+    WrongMessage {
+        expected: String,
+        received: String,
+    },
+    WrongNumOfSigs {
+        expected: usize,
+        received: usize,
+    },
+    WrongNumOfContractTxs {
+        expected: usize,
+        received: usize,
+    },
+    WrongNumOfPrivkeys {
+        expected: usize,
+        received: usize,
+    },
+    IncorrectFundingAmount {
+        expected: Amount,
+        found: Amount,
+    },
+    /// The preimage was about to be revealed, or was received, before the swap allows it.
+    IllegalPreimageReveal,
+    /// No fee rate within the bounds of both sides, or a fee rate used outside of them.
+    FeeRateOutOfBounds(FeeRate),
+    /// An [Offer](crate::protocol::messages::Offer) of a version other than
+    /// [OFFER_VERSION](crate::protocol::messages::OFFER_VERSION).
+    UnsupportedOfferVersion(u32),
     Contract(ContractError),
 }
 
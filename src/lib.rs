@@ -8,7 +8,17 @@ pub mod maker;
 pub mod market;
 pub mod protocol;
 pub mod taker;
+#[cfg(feature = "test-utils")]
+pub mod test_framework;
 #[cfg(feature = "tor")]
 pub mod tor;
 pub mod utill;
 pub mod wallet;
+#[cfg(not(feature = "tor"))]
+pub mod tor {
+    pub fn spawn_tor(_: u16, _: u16, _: String) -> mitosis::JoinHandle<()> {
+        unimplemented!()
+    }
+    pub fn kill_tor_handles(_: mitosis::JoinHandle<()>) {}
+    pub fn setup_mitosis() {}
+}
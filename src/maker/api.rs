@@ -7,8 +7,9 @@
 //! contract transactions and claiming funds after an unsuccessful swap event.
 
 use std::{
-    collections::HashMap,
-    net::IpAddr,
+    collections::{HashMap, HashSet},
+    io::Write,
+    net::{IpAddr, TcpStream},
     path::PathBuf,
     sync::{Arc, Mutex, RwLock},
     time::Instant,
@@ -18,20 +19,22 @@ use bip39::Mnemonic;
 use bitcoin::{
     absolute::LockTime,
     ecdsa::Signature,
+    hashes::hash160::Hash as Hash160,
+    hex::DisplayHex,
     secp256k1::{self, Secp256k1},
-    Amount, OutPoint, PublicKey, ScriptBuf, Transaction,
+    Amount, OutPoint, PublicKey, ScriptBuf, Transaction, TxOut, Txid,
 };
-use bitcoind::bitcoincore_rpc::RpcApi;
+use socks::Socks5Stream;
 use std::time::Duration;
 
 use crate::{
     protocol::{
-        contract::check_hashvalues_are_equal,
-        messages::{FidelityProof, ReqContractSigsForSender},
-        Hash160,
+        contract::{check_hashlocks_are_equal, Hashlock},
+        messages::{FidelityProof, Offer, Preimage, ReqContractSigsForSender, OFFER_VERSION},
     },
     utill::{
         get_maker_dir, redeemscript_to_scriptpubkey, seed_phrase_to_unique_id, ConnectionType,
+        ShutdownHandle, ShutdownMode, NET_TIMEOUT,
     },
     wallet::{RPCConfig, SwapCoin, WalletSwapCoin},
 };
@@ -39,14 +42,17 @@ use crate::{
 use crate::{
     protocol::{
         contract::{
-            check_hashlock_has_pubkey, check_multisig_has_pubkey, check_reedemscript_is_multisig,
-            find_funding_output_index, read_contract_locktime,
+            calculate_pubkey_from_nonce, check_multisig_has_pubkey, check_reedemscript_is_multisig,
+            find_funding_output_index, read_contract_locktime, read_hashvalue_from_contract,
+            read_timelock_pubkey_from_contract, verify_contract_redeemscript, MakerFeePolicy,
         },
         messages::ProofOfFunding,
     },
-    wallet::{IncomingSwapCoin, OutgoingSwapCoin, Wallet, WalletError},
+    wallet::{IncomingSwapCoin, OutgoingSwapCoin, SwapOutcome, SwapRecord, Wallet, WalletError},
 };
 
+#[cfg(feature = "metrics")]
+use super::metrics::MakerMetrics;
 use super::{config::MakerConfig, error::MakerError};
 
 /// Used to configure the maker for testing purposes.
@@ -82,9 +88,25 @@ pub enum ExpectedMessage {
 #[derive(Debug, Default, Clone)]
 pub struct ConnectionState {
     pub allowed_message: ExpectedMessage,
+    /// Protocol version agreed on with the Taker in the hello messages.
+    pub protocol_version: u32,
     pub incoming_swapcoins: Vec<IncomingSwapCoin>,
     pub outgoing_swapcoins: Vec<OutgoingSwapCoin>,
     pub pending_funding_txes: Vec<Transaction>,
+    /// Revenue of the swap, net of the funding transaction fees. Known after the proof of funding.
+    pub coinswap_fee: Amount,
+    /// Funding outputs of the contracts we signed for the Taker. The funding txs we join must pay
+    /// them, see [CollabFunding](crate::protocol::messages::CollabFunding).
+    pub agreed_funding_outputs: Vec<TxOut>,
+    /// Our coins joined to the Taker's funding txs, locked until the swap is over.
+    pub collab_funding_coins: Vec<OutPoint>,
+}
+
+impl ConnectionState {
+    /// Whether the swap got as far as exchanging swapcoins, and needs a recovery if it fails.
+    pub fn has_swapcoins(&self) -> bool {
+        !self.incoming_swapcoins.is_empty() || !self.outgoing_swapcoins.is_empty()
+    }
 }
 
 /// Represents the maker in the swap protocol.
@@ -97,12 +119,19 @@ pub struct Maker {
     pub wallet: RwLock<Wallet>,
     /// A flag to trigger shutdown event
     pub shutdown: RwLock<bool>,
+    /// Requested [ShutdownMode], set by [Maker::shutdown_with] or by a signal.
+    pub shutdown_handle: ShutdownHandle,
     /// Map of IP address to Connection State + last Connected instant
     pub connection_state: Mutex<HashMap<IpAddr, (ConnectionState, Instant)>>,
     /// Highest Value Fidelity Proof
     pub highest_fidelity_proof: RwLock<Option<FidelityProof>>,
     /// Is setup complete
     pub is_setup_complete: RwLock<bool>,
+    /// Multisig redeemscripts of the outgoing swapcoins refunded by a [recover_from_swap] thread
+    pub swapcoins_in_recovery: Mutex<HashSet<ScriptBuf>>,
+    /// Counters and gauges of the maker operations
+    #[cfg(feature = "metrics")]
+    pub metrics: MakerMetrics,
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -111,7 +140,7 @@ impl Maker {
     ///
     /// The `data_dir` and `wallet_name_path` can be selectively provided to perform wallet load/creation.
     /// data_dir: Some(value) = Create data directory at given value.
-    /// data_dir: None = Create default data directory. For linux "~/.coinswap"
+    /// data_dir: None = Use the `data_dir` of the config at the default data directory. For linux "~/.coinswap/maker"
     /// wallet_file_name: Some(value) = Try loading wallet file with name "value". If doesn't exist create a new wallet with the given name.
     /// wallet_file_name: None = Create a new default wallet file. Ex: "9d317f933-maker".
     ///
@@ -135,18 +164,33 @@ impl Maker {
             MakerBehavior::Normal
         };
 
-        // Get provided data directory or the default data directory.
         let data_dir = if cfg!(feature = "integration-test") {
             // We only append port number in data-dir for integration test
             let port = port.expect("port value expected in Int tests");
-            data_dir.map_or(get_maker_dir().join(port.to_string()), |d| {
-                d.join("maker").join(port.to_string())
-            })
+            Some(
+                data_dir.map_or(get_maker_dir().join(port.to_string()), |d| {
+                    d.join("maker").join(port.to_string())
+                }),
+            )
         } else {
-            data_dir.unwrap_or(get_maker_dir())
+            data_dir
         };
 
-        let wallet_dir = data_dir.join("wallets");
+        // The config is in the provided data directory or the default data directory.
+        // If config file doesn't exist, default config will be loaded.
+        let mut config = MakerConfig::new(Some(
+            &data_dir
+                .clone()
+                .unwrap_or(get_maker_dir())
+                .join("config.toml"),
+        ))?;
+
+        // The provided data directory overrides the configured one.
+        if let Some(data_dir) = data_dir {
+            config.data_dir = data_dir;
+        }
+
+        let wallet_dir = config.data_dir.join("wallets");
 
         let mut rpc_config = rpc_config.unwrap_or_default();
 
@@ -184,9 +228,6 @@ impl Maker {
             wallet
         };
 
-        // If config file doesn't exist, default config will be loaded.
-        let mut config = MakerConfig::new(Some(&data_dir.join("config.toml")))?;
-
         if let Some(port) = port {
             config.port = port;
         }
@@ -214,9 +255,13 @@ impl Maker {
             config,
             wallet: RwLock::new(wallet),
             shutdown: RwLock::new(false),
+            shutdown_handle: ShutdownHandle::default(),
             connection_state: Mutex::new(HashMap::new()),
             highest_fidelity_proof: RwLock::new(None),
             is_setup_complete: RwLock::new(false),
+            swapcoins_in_recovery: Mutex::new(HashSet::new()),
+            #[cfg(feature = "metrics")]
+            metrics: MakerMetrics::default(),
         })
     }
 
@@ -227,6 +272,48 @@ impl Maker {
         Ok(())
     }
 
+    /// Requests a shutdown.
+    ///
+    /// A [ShutdownMode::Graceful] shutdown refuses new swaps, and shuts down once the in-flight
+    /// swaps are over. A [ShutdownMode::Immediate] one shuts down right away: the swapcoins are
+    /// already saved, and the in-flight swaps are recovered on the next start.
+    pub fn shutdown_with(&self, mode: ShutdownMode) -> Result<(), MakerError> {
+        self.shutdown_handle.shutdown(mode);
+        if mode == ShutdownMode::Immediate {
+            self.shutdown()?;
+        }
+        Ok(())
+    }
+
+    /// Whether a shutdown was requested, and new swaps are refused.
+    pub fn is_draining(&self) -> bool {
+        self.shutdown_handle.requested().is_some()
+    }
+
+    /// Releases the `coins` we joined to the funding txs of a swap that is over. Spent coins are
+    /// only forgotten.
+    pub(crate) fn release_collab_funding_coins(
+        &self,
+        coins: &[OutPoint],
+    ) -> Result<(), MakerError> {
+        if coins.is_empty() {
+            return Ok(());
+        }
+        let mut wallet = self.wallet.write()?;
+        for coin in coins {
+            wallet.unlock_utxo(*coin)?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the maker's wallet. Recorded in the [MakerMetrics] with the `metrics` feature.
+    pub(crate) fn sync_wallet(&self, wallet: &mut Wallet) -> Result<(), WalletError> {
+        #[cfg(feature = "metrics")]
+        return self.metrics.record_sync(wallet);
+        #[cfg(not(feature = "metrics"))]
+        wallet.sync()
+    }
+
     /// Triggers a setup complete event for the Maker.
     pub fn setup_complete(&self) -> Result<(), MakerError> {
         let mut flag = self.is_setup_complete.write()?;
@@ -239,6 +326,78 @@ impl Maker {
         &self.wallet
     }
 
+    /// The configured fee policy, with the maximum swap amount capped by the wallet's liquidity.
+    pub fn offered_fee_policy(&self) -> Result<MakerFeePolicy, MakerError> {
+        let fee_policy = self.config.fee_policy;
+        let liquidity = Amount::from_sat(self.wallet.read()?.get_offer_maxsize());
+        Ok(MakerFeePolicy {
+            max_swap_amount: fee_policy.max_swap_amount.min(liquidity),
+            ..fee_policy
+        })
+    }
+
+    /// Our current [Offer], signed with the key of our highest value fidelity bond.
+    ///
+    /// The offer carries the fidelity proof, so takers can check the bond's UTXO on-chain.
+    pub fn make_offer(&self) -> Result<Offer, MakerError> {
+        let fidelity = self
+            .highest_fidelity_proof
+            .read()?
+            .clone()
+            .ok_or(MakerError::General(
+                "No fidelity proof to make an offer with",
+            ))?;
+        let fee_policy = self.offered_fee_policy()?;
+        let wallet = self.wallet.read()?;
+        let mut offer = Offer {
+            version: OFFER_VERSION,
+            fee_policy,
+            time_relative_fee_ppb: self.config.time_relative_fee_ppb,
+            required_confirms: self.config.required_confirms,
+            minimum_locktime: self.config.min_contract_reaction_time,
+            tweakable_point: wallet.get_tweakable_keypair().1,
+            network: wallet.store.network,
+            // Overwritten by the signature of the offer terms.
+            offer_sig: fidelity.cert_sig,
+            fidelity,
+        };
+        wallet.sign_offer(&mut offer)?;
+        Ok(offer)
+    }
+
+    /// Publishes our current [Offer] to the directory server at `directory_url`, for takers to
+    /// fetch. `maker_address` is the address the fidelity proof was made for.
+    pub fn publish_offer(
+        &self,
+        directory_url: &str,
+        maker_address: &str,
+    ) -> Result<(), MakerError> {
+        let offer = self.make_offer()?;
+        let mut stream = match self.config.connection_type {
+            ConnectionType::CLEARNET => TcpStream::connect(directory_url)?,
+            ConnectionType::TOR => Socks5Stream::connect(
+                format!("127.0.0.1:{}", self.config.socks_port),
+                directory_url,
+            )?
+            .into_inner(),
+        };
+        stream.set_write_timeout(Some(NET_TIMEOUT))?;
+
+        let request_line = format!(
+            "POST_OFFER {} {}\n",
+            maker_address,
+            serde_cbor::to_vec(&offer)?.to_lower_hex_string()
+        );
+        stream.write_all(request_line.as_bytes())?;
+        stream.flush()?;
+        log::info!(
+            "[{}] Published offer to directory {}",
+            self.config.port,
+            directory_url
+        );
+        Ok(())
+    }
+
     /// Generates Fidelity bond from existing utxos
     /// Errors if not enough balance
     pub fn create_fidelity_bond(&self) -> Result<(), MakerError> {
@@ -258,12 +417,16 @@ impl Maker {
         Ok(())
     }
 
-    /// Checks consistency of the [ProofOfFunding] message and return the Hashvalue
+    /// Checks consistency of the [ProofOfFunding] message and return the Hashlock
     /// used in hashlock transaction.
-    pub fn verify_proof_of_funding(&self, message: &ProofOfFunding) -> Result<Hash160, MakerError> {
+    pub fn verify_proof_of_funding(
+        &self,
+        message: &ProofOfFunding,
+    ) -> Result<Hashlock, MakerError> {
         if message.confirmed_funding_txes.is_empty() {
             return Err(MakerError::General("No funding txs provided by Taker"));
         }
+        let hashlock = check_hashlocks_are_equal(message)?;
 
         for funding_info in &message.confirmed_funding_txes {
             // check that the new locktime is sufficently short enough compared to the
@@ -278,25 +441,23 @@ impl Maker {
             let funding_output_index = find_funding_output_index(funding_info)?;
 
             //check the funding_tx is confirmed to required depth
-            if let Some(txout) = self
-                .wallet
-                .read()?
-                .rpc
-                .get_tx_out(
-                    &funding_info.funding_tx.compute_txid(),
-                    funding_output_index,
-                    None,
-                )
-                .map_err(WalletError::Rpc)?
-            {
-                if txout.confirmations < (self.config.required_confirms as u32) {
+            let funding_outpoint =
+                OutPoint::new(funding_info.funding_tx.compute_txid(), funding_output_index);
+            let wallet = self.wallet.read()?;
+            if let Some((_, conf_height)) = wallet.backend.get_tx_out(&funding_outpoint)? {
+                let confirmations =
+                    (wallet.backend.get_block_count()? + 1).saturating_sub(conf_height);
+                if confirmations < self.config.required_confirms {
                     return Err(MakerError::General(
                         "funding tx not confirmed to required depth",
                     ));
                 }
             } else {
-                return Err(MakerError::General("funding tx output doesnt exist"));
+                return Err(MakerError::General(
+                    "funding tx output doesnt exist or is unconfirmed",
+                ));
             }
+            drop(wallet);
 
             check_reedemscript_is_multisig(&funding_info.multisig_redeemscript)?;
 
@@ -308,10 +469,13 @@ impl Maker {
                 &funding_info.multisig_nonce,
             )?;
 
-            check_hashlock_has_pubkey(
+            // Our key must be in the hashlock branch, the sender's in the timelock one.
+            verify_contract_redeemscript(
                 &funding_info.contract_redeemscript,
-                &tweabale_pubkey,
-                &funding_info.hashlock_nonce,
+                &hashlock,
+                locktime,
+                &calculate_pubkey_from_nonce(&tweabale_pubkey, &funding_info.hashlock_nonce)?,
+                &read_timelock_pubkey_from_contract(&funding_info.contract_redeemscript)?,
             )?;
 
             //check that the provided contract matches the scriptpubkey from the
@@ -331,7 +495,7 @@ impl Maker {
             }
         }
 
-        Ok(check_hashvalues_are_equal(message)?)
+        Ok(hashlock)
     }
 
     /// Verify the contract transaction for Sender and return the signatures.
@@ -339,6 +503,7 @@ impl Maker {
         &self,
         message: &ReqContractSigsForSender,
     ) -> Result<Vec<Signature>, MakerError> {
+        let hashlock = message.hashlock()?;
         let mut sigs = Vec::<Signature>::new();
         for txinfo in &message.txs_info {
             if txinfo.senders_contract_tx.input.len() != 1
@@ -379,7 +544,7 @@ impl Maker {
                 &txinfo.senders_contract_tx.output[0],
                 &hashlock_pubkey,
                 &txinfo.timelock_pubkey,
-                &message.hashvalue,
+                &hashlock,
                 &message.locktime,
                 &self.config.min_contract_reaction_time,
             )?;
@@ -432,13 +597,7 @@ pub fn check_for_broadcasted_contracts(maker: Arc<Maker>) -> Result<(), MakerErr
                 // No need to check for other contracts in the connection state, if any one of them
                 // is ever observed in the mempool/block, run recovery routine.
                 for txid in txids_to_watch {
-                    if maker
-                        .wallet
-                        .read()?
-                        .rpc
-                        .get_raw_transaction_info(&txid, None)
-                        .is_ok()
-                    {
+                    if maker.wallet.read()?.get_tx_confirmations(&txid)?.is_some() {
                         let mut outgoings = Vec::new();
                         let mut incomings = Vec::new();
                         // Something is broadcasted. Report, Recover and Abort.
@@ -496,6 +655,8 @@ pub fn check_for_broadcasted_contracts(maker: Arc<Maker>) -> Result<(), MakerErr
                         std::thread::spawn(move || {
                             recover_from_swap(maker_clone, outgoings, incomings).unwrap();
                         });
+                        maker
+                            .release_collab_funding_coins(&connection_state.collab_funding_coins)?;
                         // Clear the state value here
                         *connection_state = ConnectionState::default();
                         break;
@@ -542,7 +703,17 @@ pub fn check_for_idle_states(maker: Arc<Maker>) -> Result<(), MakerError> {
                     ip,
                     no_response_since
                 );
-                if no_response_since > std::time::Duration::from_secs(60) {
+                if no_response_since > std::time::Duration::from_secs(60) && !state.has_swapcoins()
+                {
+                    // Only joined its funding txs, nothing to recover.
+                    log::info!(
+                        "[{}] Taker {} didn't go on with the swap, releasing our joined coins",
+                        maker.config.port,
+                        ip
+                    );
+                    maker.release_collab_funding_coins(&state.collab_funding_coins)?;
+                    bad_ip.push(*ip);
+                } else if no_response_since > std::time::Duration::from_secs(60) {
                     log::error!(
                         "[{}] Potential Dropped Connection from {}",
                         maker.config.port,
@@ -577,6 +748,7 @@ pub fn check_for_idle_states(maker: Arc<Maker>) -> Result<(), MakerError> {
                     std::thread::spawn(move || {
                         recover_from_swap(maker_clone, outgoings, incomings).unwrap();
                     });
+                    maker.release_collab_funding_coins(&state.collab_funding_coins)?;
                     // Clear the state values here
                     *state = ConnectionState::default();
                     break;
@@ -595,35 +767,242 @@ pub fn check_for_idle_states(maker: Arc<Maker>) -> Result<(), MakerError> {
     Ok(())
 }
 
+/// Refunds the outgoing swapcoins left behind by a dropped swap, once their timelock matures.
+///
+/// Swaps in progress are tracked in [Maker::connection_state], and refunded by a
+/// [recover_from_swap] thread if they fail. This watchdog picks up all the other unfinished
+/// outgoing swapcoins of the wallet, like the ones of swaps interrupted by a restart of the maker.
+pub fn check_for_expired_swaps(maker: Arc<Maker>) -> Result<(), MakerError> {
+    // Timelocks are counted in blocks, no need to look up more often.
+    let block_lookup_interval = if cfg!(feature = "integration-test") {
+        Duration::from_secs(10)
+    } else {
+        Duration::from_secs(10 * 60)
+    };
+    let mut next_lookup = Instant::now();
+
+    while !*maker.shutdown.read()? {
+        if Instant::now() >= next_lookup {
+            next_lookup = Instant::now() + block_lookup_interval;
+            refund_expired_swaps(&maker)?;
+        }
+
+        std::thread::sleep(Duration::from_secs(maker.config.heart_beat_interval_secs));
+    }
+
+    Ok(())
+}
+
+/// One lookup of the [check_for_expired_swaps] watchdog. Returns the txids of the broadcasted
+/// refunds and hashlock claims.
+///
+/// The outgoing swapcoins of completed swaps are left alone: the maker only learns the preimage
+/// on its incoming swapcoins, and its outgoing ones then belong to the next hop. If the next hop
+/// claimed an outgoing contract through its hashlock instead, the preimage is read from the
+/// claim, and the incoming swapcoins of the swap are claimed the same way. Only the other
+/// outgoing swapcoins get refunded through their timelock.
+pub fn refund_expired_swaps(maker: &Maker) -> Result<Vec<Txid>, MakerError> {
+    let tracked = {
+        let lock_on_state = maker.connection_state.lock()?;
+        let mut tracked = maker.swapcoins_in_recovery.lock()?.clone();
+        tracked.extend(lock_on_state.values().flat_map(|(state, _)| {
+            state
+                .outgoing_swapcoins
+                .iter()
+                .map(|oc| oc.get_multisig_redeemscript())
+        }));
+        tracked
+    };
+
+    let outgoings = maker.wallet.read()?.find_unfinished_swapcoins().1;
+    let mut refund_txids = Vec::new();
+    for outgoing in outgoings {
+        // Our funding tx isn't broadcasted before the contract is fully signed.
+        if outgoing.others_contract_sig.is_none()
+            || tracked.contains(&outgoing.get_multisig_redeemscript())
+        {
+            continue;
+        }
+        let mut wallet = maker.wallet.write()?;
+        // Checked under the write lock, the swap may have completed meanwhile.
+        if wallet
+            .find_outgoing_swapcoin(&outgoing.get_multisig_redeemscript())
+            .is_none()
+            || is_swap_completed(&wallet, &outgoing.get_hashvalue())?
+        {
+            continue;
+        }
+        let contract_outpoint = OutPoint::new(outgoing.contract_tx.compute_txid(), 0);
+        let revealed_preimage = match wallet.watch_contract_spend(contract_outpoint) {
+            Ok(spend) => spend.and_then(|spend| spend.preimage),
+            Err(e) => {
+                log::error!(
+                    "[{}] Error looking up the spend of outgoing contract: {:?}",
+                    maker.config.port,
+                    e
+                );
+                continue;
+            }
+        };
+        if let Some(preimage) = revealed_preimage {
+            match claim_with_revealed_preimage(&mut wallet, &outgoing, preimage) {
+                Ok(claim_txids) => {
+                    for claim_txid in &claim_txids {
+                        log::info!(
+                            "[{}] Claimed incoming swapcoin with the revealed preimage: {}",
+                            maker.config.port,
+                            claim_txid
+                        );
+                    }
+                    refund_txids.extend(claim_txids);
+                }
+                Err(e) => log::error!(
+                    "[{}] Error claiming incoming swapcoins with the revealed preimage: {:?}",
+                    maker.config.port,
+                    e
+                ),
+            }
+            continue;
+        }
+        match wallet.refund_outgoing_swapcoin(&outgoing) {
+            Ok(Some(refund_txid)) => {
+                log::info!(
+                    "[{}] Refunded expired outgoing swapcoin: {}",
+                    maker.config.port,
+                    refund_txid
+                );
+                refund_txids.push(refund_txid);
+            }
+            Ok(None) => {}
+            Err(e) => log::error!(
+                "[{}] Error refunding expired outgoing swapcoin: {:?}",
+                maker.config.port,
+                e
+            ),
+        }
+    }
+    maker.wallet.read()?.save_to_disk()?;
+    Ok(refund_txids)
+}
+
+/// Claims the incoming swapcoins of the swap of `outgoing` through their hashlock, with the
+/// `preimage` the next hop revealed claiming the contract of `outgoing`. Returns the claim txids.
+///
+/// The preimage isn't saved in the incoming swapcoins, the swap would look completed and the
+/// claims wouldn't be retried. `outgoing` is removed once all of them are claimed.
+fn claim_with_revealed_preimage(
+    wallet: &mut Wallet,
+    outgoing: &OutgoingSwapCoin,
+    preimage: Preimage,
+) -> Result<Vec<Txid>, WalletError> {
+    let hashvalue = outgoing.get_hashvalue();
+    let incomings = wallet
+        .get_incoming_swapcoin_list()?
+        .values()
+        .filter(|ic| ic.get_hashvalue() == hashvalue)
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut claim_txids = Vec::new();
+    let mut all_claimed = true;
+    for mut incoming in incomings {
+        incoming.hash_preimage = Some(preimage);
+        match wallet.claim_incoming_swapcoin(&incoming)? {
+            Some(claim_txid) => claim_txids.push(claim_txid),
+            None => all_claimed = false,
+        }
+    }
+    if all_claimed {
+        wallet.remove_outgoing_swapcoin(&outgoing.get_multisig_redeemscript())?;
+    }
+    Ok(claim_txids)
+}
+
+/// Whether we know the preimage of the swap of `hashvalue`, from its incoming swapcoins.
+fn is_swap_completed(wallet: &Wallet, hashvalue: &Hash160) -> Result<bool, MakerError> {
+    Ok(wallet
+        .get_incoming_swapcoin_list()?
+        .values()
+        .any(|ic| ic.is_hash_preimage_known() && ic.get_hashvalue() == *hashvalue))
+}
+
 /// Broadcast Incoming and Outgoing Contract transactions & timelock transactions after maturity.
 /// Remove contract transactions from the wallet.
+///
+/// The outgoing swapcoins are registered in [Maker::swapcoins_in_recovery] meanwhile, so the
+/// [check_for_expired_swaps] watchdog leaves them alone.
 pub fn recover_from_swap(
     maker: Arc<Maker>,
     // Tuple of ((Multisig_reedemscript, Contract Tx), (Timelock, Timelock Tx))
     outgoings: Vec<((ScriptBuf, Transaction), (u16, Transaction))>,
     // Tuple of (Multisig Reedemscript, Contract Tx)
     incomings: Vec<(ScriptBuf, Transaction)>,
+) -> Result<(), MakerError> {
+    let outgoing_reedemscripts = outgoings
+        .iter()
+        .map(|((reedemscript, _), _)| reedemscript.clone())
+        .collect::<Vec<_>>();
+    maker
+        .swapcoins_in_recovery
+        .lock()?
+        .extend(outgoing_reedemscripts.iter().cloned());
+    #[cfg(feature = "metrics")]
+    maker.metrics.record_swap_failed();
+    {
+        let wallet = maker.wallet.read()?;
+        let incoming_swapcoins = incomings
+            .iter()
+            .filter_map(|(reedemscript, _)| wallet.find_incoming_swapcoin(reedemscript))
+            .collect::<Vec<_>>();
+        let swap_id = incoming_swapcoins
+            .iter()
+            .find_map(|sc| read_hashvalue_from_contract(&sc.contract_redeemscript).ok())
+            .map(|hashvalue| hashvalue.to_string())
+            .unwrap_or_default();
+        let amount = incoming_swapcoins.iter().map(|sc| sc.funding_amount).sum();
+        let record = SwapRecord::new(
+            swap_id,
+            amount,
+            Vec::new(),
+            Amount::ZERO,
+            SwapOutcome::Failed,
+        );
+        if let Err(e) = wallet.record_swap(record) {
+            log::error!(
+                "[{}] Failed to record the swap in the swap history: {:?}",
+                maker.config.port,
+                e
+            );
+        }
+    }
+
+    let result = broadcast_contracts_and_refund(maker.clone(), outgoings, incomings);
+
+    let mut swapcoins_in_recovery = maker.swapcoins_in_recovery.lock()?;
+    for reedemscript in &outgoing_reedemscripts {
+        swapcoins_in_recovery.remove(reedemscript);
+    }
+    result
+}
+
+fn broadcast_contracts_and_refund(
+    maker: Arc<Maker>,
+    outgoings: Vec<((ScriptBuf, Transaction), (u16, Transaction))>,
+    incomings: Vec<(ScriptBuf, Transaction)>,
 ) -> Result<(), MakerError> {
     // broadcast all the incoming contracts and remove them from the wallet.
     for (incoming_reedemscript, tx) in incomings {
         if maker
             .wallet
             .read()?
-            .rpc
-            .get_raw_transaction_info(&tx.compute_txid(), None)
-            .is_ok()
+            .get_tx_confirmations(&tx.compute_txid())?
+            .is_some()
         {
             log::info!(
                 "[{}] Incoming Contract Already Broadcasted",
                 maker.config.port
             );
         } else {
-            maker
-                .wallet
-                .read()?
-                .rpc
-                .send_raw_transaction(&tx)
-                .map_err(WalletError::Rpc)?;
+            maker.wallet.read()?.backend.broadcast(&tx)?;
             log::info!(
                 "[{}] Broadcasted Incoming Contract : {}",
                 maker.config.port,
@@ -650,21 +1029,15 @@ pub fn recover_from_swap(
         if maker
             .wallet
             .read()?
-            .rpc
-            .get_raw_transaction_info(&tx.compute_txid(), None)
-            .is_ok()
+            .get_tx_confirmations(&tx.compute_txid())?
+            .is_some()
         {
             log::info!(
                 "[{}] Outgoing Contract already broadcasted",
                 maker.config.port
             );
         } else {
-            maker
-                .wallet
-                .read()?
-                .rpc
-                .send_raw_transaction(tx)
-                .map_err(WalletError::Rpc)?;
+            maker.wallet.read()?.backend.broadcast(tx)?;
             log::info!(
                 "[{}] Broadcasted Outgoing Contract : {}",
                 maker.config.port,
@@ -683,20 +1056,19 @@ pub fn recover_from_swap(
             }
             // Check if the contract tx has reached required maturity
             // Failure here means the transaction hasn't been broadcasted yet. So do nothing and try again.
-            if let Ok(result) = maker
+            if let Some(confirmations) = maker
                 .wallet
                 .read()?
-                .rpc
-                .get_raw_transaction_info(&contract.compute_txid(), None)
+                .get_tx_confirmations(&contract.compute_txid())?
             {
                 log::info!(
                     "[{}] Contract Tx : {}, reached confirmation : {:?}, Required Confirmation : {}",
                     maker.config.port,
                     contract.compute_txid(),
-                    result.confirmations,
+                    confirmations,
                     timelock
                 );
-                if let Some(confirmation) = result.confirmations {
+                if let Some(confirmation) = confirmations {
                     // Now the transaction is confirmed in a block, check for required maturity
                     if confirmation > (*timelock as u32) {
                         log::info!(
@@ -710,12 +1082,7 @@ pub fn recover_from_swap(
                             maker.config.port,
                             timelocked_tx.compute_txid()
                         );
-                        maker
-                            .wallet
-                            .read()?
-                            .rpc
-                            .send_raw_transaction(timelocked_tx)
-                            .map_err(WalletError::Rpc)?;
+                        maker.wallet.read()?.backend.broadcast(timelocked_tx)?;
                         timelock_boardcasted.push(timelocked_tx);
                     }
                 }
@@ -739,7 +1106,7 @@ pub fn recover_from_swap(
             log::info!("initializing Wallet Sync.");
             {
                 let mut wallet_write = maker.wallet.write()?;
-                wallet_write.sync()?;
+                maker.sync_wallet(&mut wallet_write)?;
                 wallet_write.save_to_disk()?;
             }
             log::info!("Completed Wallet Sync.");
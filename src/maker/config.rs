@@ -2,9 +2,12 @@
 
 use std::{io, path::PathBuf};
 
-use bitcoin::Amount;
+use bitcoin::{Amount, FeeRate};
 
-use crate::utill::{get_maker_dir, parse_field, parse_toml, write_default_config, ConnectionType};
+use crate::{
+    protocol::contract::MakerFeePolicy,
+    utill::{get_maker_dir, parse_field, parse_toml, write_default_config, ConnectionType},
+};
 
 /// Maker Configuration, controlling various maker behavior.
 #[derive(Debug, Clone, PartialEq)]
@@ -21,18 +24,28 @@ pub struct MakerConfig {
     pub directory_servers_refresh_interval_secs: u64,
     /// Time interval to close a connection if no response is received
     pub idle_connection_timeout: u64,
-    /// Absolute coinswap fee
-    pub absolute_fee_sats: Amount,
-    /// Fee rate per swap amount in ppb.
-    pub amount_relative_fee_ppb: Amount,
+    /// Maximum number of taker connections served at the same time
+    pub max_concurrent_swaps: usize,
+    /// Maximum number of connections accepted from a single peer within `rate_limit_window_secs`.
+    /// Not applied over Tor, where all peers come from localhost
+    pub max_requests_per_peer: usize,
+    /// Length of the sliding window of the per-peer rate limit
+    pub rate_limit_window_secs: u64,
+    /// Coinswap fee and swap amount limits
+    pub fee_policy: MakerFeePolicy,
     /// Fee rate for timelocked contract in ppb
     pub time_relative_fee_ppb: Amount,
-    /// No of confirmation required for funding transaction
+    /// No of confirmation required for the incoming funding transactions, checked with
+    /// `gettxout` before the maker funds its side of the swap. Low values are faster, but expose
+    /// the maker to a reorg double-spending its incoming funding.
     pub required_confirms: u64,
+    /// Lowest fee rate the maker funds its transactions with, read in sat/vB. Takers proposing
+    /// less get countered.
+    pub min_fee_rate: FeeRate,
+    /// Highest fee rate the maker funds its transactions with, read in sat/vB.
+    pub max_fee_rate: FeeRate,
     // Minimum timelock difference between contract transaction of two hops
     pub min_contract_reaction_time: u16,
-    /// Minimum coinswap amount size in sats
-    pub min_size: u64,
     /// Socks port
     pub socks_port: u16,
     /// Directory server onion address
@@ -43,8 +56,19 @@ pub struct MakerConfig {
     pub fidelity_value: u64,
     /// Fidelity Bond timelock in Block heights.
     pub fidelity_timelock: u32,
+    /// No of confirmations required on a fidelity bond before advertising it
+    pub fidelity_required_confirms: u64,
     /// Connection type
     pub connection_type: ConnectionType,
+    /// Listening address of the control server, with the `control-server` feature
+    pub control_address: String,
+    /// Token the control requests must carry, if any
+    pub control_token: Option<String>,
+    /// Listening address of the metrics server, with the `metrics` feature
+    pub metrics_address: String,
+    /// Directory of the wallet files, with their swap history. A data directory given to
+    /// [Maker::init](super::Maker::init) takes precedence.
+    pub data_dir: PathBuf,
 }
 
 impl Default for MakerConfig {
@@ -56,18 +80,26 @@ impl Default for MakerConfig {
             rpc_ping_interval_secs: 60,
             directory_servers_refresh_interval_secs: 60 * 60 * 12, //12 Hours
             idle_connection_timeout: 300,
-            absolute_fee_sats: Amount::from_sat(1000),
-            amount_relative_fee_ppb: Amount::from_sat(10_000_000),
+            max_concurrent_swaps: 10,
+            max_requests_per_peer: 30,
+            rate_limit_window_secs: 60,
+            fee_policy: MakerFeePolicy::default(),
             time_relative_fee_ppb: Amount::from_sat(100_000),
             required_confirms: 1,
+            min_fee_rate: FeeRate::from_sat_per_vb_u32(1),
+            max_fee_rate: FeeRate::from_sat_per_vb_u32(100),
             min_contract_reaction_time: 48,
-            min_size: 10_000,
             socks_port: 19050,
             directory_server_onion_address: "directoryhiddenserviceaddress.onion:8080".to_string(),
             directory_server_clearnet_address: "127.0.0.1:8080".to_string(),
             fidelity_value: 5_000_000, // 5 million  sats
             fidelity_timelock: 26_000, // Approx 6 months of blocks
+            fidelity_required_confirms: 1,
             connection_type: ConnectionType::TOR,
+            control_address: "127.0.0.1:6104".to_string(),
+            control_token: None,
+            metrics_address: "127.0.0.1:6105".to_string(),
+            data_dir: get_maker_dir(),
         }
     }
 }
@@ -133,16 +165,56 @@ impl MakerConfig {
                 default_config.idle_connection_timeout,
             )
             .unwrap_or(default_config.idle_connection_timeout),
-            absolute_fee_sats: parse_field(
-                maker_config_section.get("absolute_fee_sats"),
-                default_config.absolute_fee_sats,
+            max_concurrent_swaps: parse_field(
+                maker_config_section.get("max_concurrent_swaps"),
+                default_config.max_concurrent_swaps,
             )
-            .unwrap_or(default_config.absolute_fee_sats),
-            amount_relative_fee_ppb: parse_field(
-                maker_config_section.get("amount_relative_fee_ppb"),
-                default_config.amount_relative_fee_ppb,
+            .unwrap_or(default_config.max_concurrent_swaps),
+            max_requests_per_peer: parse_field(
+                maker_config_section.get("max_requests_per_peer"),
+                default_config.max_requests_per_peer,
             )
-            .unwrap_or(default_config.amount_relative_fee_ppb),
+            .unwrap_or(default_config.max_requests_per_peer),
+            rate_limit_window_secs: parse_field(
+                maker_config_section.get("rate_limit_window_secs"),
+                default_config.rate_limit_window_secs,
+            )
+            .unwrap_or(default_config.rate_limit_window_secs),
+            // The fee keys were renamed, the old ones are still read if the new ones are missing.
+            fee_policy: MakerFeePolicy {
+                base_fee_sats: parse_field(
+                    maker_config_section
+                        .get("base_fee_sats")
+                        .or_else(|| maker_config_section.get("absolute_fee_sats")),
+                    default_config.fee_policy.base_fee_sats,
+                )
+                .unwrap_or(default_config.fee_policy.base_fee_sats),
+                relative_fee_ppm: match maker_config_section.get("relative_fee_ppm") {
+                    Some(relative_fee_ppm) => parse_field(Some(relative_fee_ppm), 0),
+                    None => parse_field(
+                        maker_config_section.get("amount_relative_fee_ppb"),
+                        default_config.fee_policy.relative_fee_ppm * 1000,
+                    )
+                    .map(|relative_fee_ppb: u64| relative_fee_ppb / 1000),
+                }
+                .unwrap_or(default_config.fee_policy.relative_fee_ppm),
+                min_swap_amount: Amount::from_sat(
+                    parse_field(
+                        maker_config_section
+                            .get("min_swap_amount")
+                            .or_else(|| maker_config_section.get("min_size")),
+                        default_config.fee_policy.min_swap_amount.to_sat(),
+                    )
+                    .unwrap_or(default_config.fee_policy.min_swap_amount.to_sat()),
+                ),
+                max_swap_amount: Amount::from_sat(
+                    parse_field(
+                        maker_config_section.get("max_swap_amount"),
+                        default_config.fee_policy.max_swap_amount.to_sat(),
+                    )
+                    .unwrap_or(default_config.fee_policy.max_swap_amount.to_sat()),
+                ),
+            },
             time_relative_fee_ppb: parse_field(
                 maker_config_section.get("time_relative_fee_ppb"),
                 default_config.time_relative_fee_ppb,
@@ -153,16 +225,25 @@ impl MakerConfig {
                 default_config.required_confirms,
             )
             .unwrap_or(default_config.required_confirms),
+            min_fee_rate: FeeRate::from_sat_per_vb_u32(
+                parse_field(
+                    maker_config_section.get("min_fee_rate"),
+                    default_config.min_fee_rate.to_sat_per_vb_floor() as u32,
+                )
+                .unwrap_or(default_config.min_fee_rate.to_sat_per_vb_floor() as u32),
+            ),
+            max_fee_rate: FeeRate::from_sat_per_vb_u32(
+                parse_field(
+                    maker_config_section.get("max_fee_rate"),
+                    default_config.max_fee_rate.to_sat_per_vb_floor() as u32,
+                )
+                .unwrap_or(default_config.max_fee_rate.to_sat_per_vb_floor() as u32),
+            ),
             min_contract_reaction_time: parse_field(
                 maker_config_section.get("min_contract_reaction_time"),
                 default_config.min_contract_reaction_time,
             )
             .unwrap_or(default_config.min_contract_reaction_time),
-            min_size: parse_field(
-                maker_config_section.get("min_size"),
-                default_config.min_size,
-            )
-            .unwrap_or(default_config.min_size),
             socks_port: parse_field(
                 maker_config_section.get("socks_port"),
                 default_config.socks_port,
@@ -186,11 +267,32 @@ impl MakerConfig {
                 default_config.fidelity_timelock,
             )
             .unwrap_or(default_config.fidelity_timelock),
+            fidelity_required_confirms: parse_field(
+                maker_config_section.get("fidelity_required_confirms"),
+                default_config.fidelity_required_confirms,
+            )
+            .unwrap_or(default_config.fidelity_required_confirms),
             connection_type: parse_field(
                 maker_config_section.get("connection_type"),
                 default_config.connection_type,
             )
             .unwrap_or(default_config.connection_type),
+            control_address: maker_config_section
+                .get("control_address")
+                .map(|s| s.trim_matches('"').to_string())
+                .unwrap_or(default_config.control_address),
+            control_token: maker_config_section
+                .get("control_token")
+                .map(|s| s.trim_matches('"').to_string())
+                .or(default_config.control_token),
+            metrics_address: maker_config_section
+                .get("metrics_address")
+                .map(|s| s.trim_matches('"').to_string())
+                .unwrap_or(default_config.metrics_address),
+            data_dir: maker_config_section
+                .get("data_dir")
+                .map(|s| PathBuf::from(s.trim_matches('"')))
+                .unwrap_or(default_config.data_dir),
         })
     }
 }
@@ -205,17 +307,25 @@ fn write_default_maker_config(config_path: &PathBuf) {
             rpc_ping_interval_secs = 60\n\
             directory_servers_refresh_interval_secs = 43200\n\
             idle_connection_timeout = 300\n\
+            max_concurrent_swaps = 10\n\
+            max_requests_per_peer = 30\n\
+            rate_limit_window_secs = 60\n\
             onion_addrs = myhiddenserviceaddress.onion\n\
-            absolute_fee_sats = 1000\n\
-            amount_relative_fee_ppb = 10000000\n\
+            base_fee_sats = 1000\n\
+            relative_fee_ppm = 10000\n\
             time_relative_fee_ppb = 100000\n\
             required_confirms = 1\n\
+            min_fee_rate = 1\n\
+            max_fee_rate = 100\n\
             min_contract_reaction_time = 48\n\
-            min_size = 10000\n\
+            min_swap_amount = 10000\n\
+            max_swap_amount = 2100000000000000\n\
             socks_port = 19050\n\
             directory_server_onion_address = directoryhiddenserviceaddress.onion:8080\n\
             directory_server_clearnet_address = 127.0.0.1:8080\n\
-            connection_type = tor
+            connection_type = tor\n\
+            control_address = 127.0.0.1:6104\n\
+            metrics_address = 127.0.0.1:6105
             ",
     );
 
@@ -252,12 +362,17 @@ mod tests {
             watchtower_ping_interval_secs = 300
             directory_servers_refresh_interval_secs = 43200
             idle_connection_timeout = 300
-            absolute_fee_sats = 1000
-            amount_relative_fee_ppb = 10000000
+            max_concurrent_swaps = 10
+            max_requests_per_peer = 30
+            rate_limit_window_secs = 60
+            base_fee_sats = 1000
+            relative_fee_ppm = 10000
             time_relative_fee_ppb = 100000
             required_confirms = 1
+            min_fee_rate = 1
+            max_fee_rate = 100
             min_contract_reaction_time = 48
-            min_size = 10000
+            min_swap_amount = 10000
             socks_port = 19050
         "#;
         let config_path = create_temp_config(contents, "valid_maker_config.toml");
@@ -268,6 +383,38 @@ mod tests {
         assert_eq!(config, default_config);
     }
 
+    #[test]
+    fn test_legacy_fee_keys() {
+        let contents = r#"
+            [maker_config]
+            absolute_fee_sats = 2000
+            amount_relative_fee_ppb = 20000000
+            min_size = 30000
+        "#;
+        let config_path = create_temp_config(contents, "legacy_fee_keys_maker_config.toml");
+        let config = MakerConfig::new(Some(&config_path)).unwrap();
+        remove_temp_config(&config_path);
+
+        assert_eq!(config.fee_policy.base_fee_sats, 2000);
+        assert_eq!(config.fee_policy.relative_fee_ppm, 20_000);
+        assert_eq!(config.fee_policy.min_swap_amount, Amount::from_sat(30_000));
+
+        // The new keys take precedence.
+        let contents = r#"
+            [maker_config]
+            base_fee_sats = 3000
+            absolute_fee_sats = 2000
+            relative_fee_ppm = 5000
+            amount_relative_fee_ppb = 20000000
+        "#;
+        let config_path = create_temp_config(contents, "both_fee_keys_maker_config.toml");
+        let config = MakerConfig::new(Some(&config_path)).unwrap();
+        remove_temp_config(&config_path);
+
+        assert_eq!(config.fee_policy.base_fee_sats, 3000);
+        assert_eq!(config.fee_policy.relative_fee_ppm, 5000);
+    }
+
     #[test]
     fn test_missing_fields() {
         let contents = r#"
@@ -308,4 +455,34 @@ mod tests {
         remove_temp_config(&config_path);
         assert_eq!(config, MakerConfig::default());
     }
+
+    #[test]
+    fn test_data_dir() {
+        let contents = r#"
+            [maker_config]
+            data_dir = "/var/lib/coinswap/maker"
+        "#;
+        let config_path = create_temp_config(contents, "data_dir_maker_config.toml");
+        let config = MakerConfig::new(Some(&config_path)).unwrap();
+        remove_temp_config(&config_path);
+
+        assert_eq!(config.data_dir, PathBuf::from("/var/lib/coinswap/maker"));
+    }
+
+    #[test]
+    fn test_quoted_control_fields() {
+        let contents = r#"
+            [maker_config]
+            control_address = "127.0.0.1:7104"
+            control_token = "changeme"
+            metrics_address = "127.0.0.1:7105"
+        "#;
+        let config_path = create_temp_config(contents, "quoted_control_maker_config.toml");
+        let config = MakerConfig::new(Some(&config_path)).unwrap();
+        remove_temp_config(&config_path);
+
+        assert_eq!(config.control_address, "127.0.0.1:7104");
+        assert_eq!(config.control_token.as_deref(), Some("changeme"));
+        assert_eq!(config.metrics_address, "127.0.0.1:7105");
+    }
 }
@@ -0,0 +1,395 @@
+//! The maker control server, enabled with the `control-server` feature.
+//!
+//! A JSON-RPC 2.0 interface over HTTP, for operational tooling. Requests are `POST`ed to the
+//! [`control_address`](super::config::MakerConfig::control_address), which is on localhost by
+//! default. If a [`control_token`](super::config::MakerConfig::control_token) is set, requests
+//! must carry it in an `Authorization: Bearer <token>` header. Requests must be sent with
+//! `Content-Type: application/json`, which a browser won't send cross-origin without a preflight.
+//!
+//! Methods:
+//! - `get_status`: maker port, network, setup status and count of active swaps.
+//! - `list_swaps`: the [active swaps](crate::wallet::Wallet::active_swaps) of the maker wallet.
+//! - `get_balances`: the [wallet balances](crate::wallet::Balances), in sats.
+//! - `redeem_expired_fidelity`: redeems the expired fidelity bonds in the background, paying the
+//!   optional `fee_rate` param (sats/vbyte). Defaults to the node's fee estimate, or 2 sats/vbyte
+//!   without one. Returns the indices of the bonds.
+//! - `stop`: shuts the maker down. With the `graceful` param set, new swaps are refused and the
+//!   maker shuts down once the in-flight swaps are over.
+
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread::{self, sleep},
+    time::Duration,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    utill::ShutdownMode,
+    wallet::{
+        wait_for_fidelity_spend, FallbackFeeEstimator, FeeEstimator, StaticFeeEstimator,
+        FALLBACK_FEE_RATE, FIDELITY_SPEND_TIMEOUT,
+    },
+};
+
+use super::{error::MakerError, Maker};
+
+/// Request bodies are small, anything bigger is rejected.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// An HTTP request, reduced to what the control server needs.
+#[derive(Debug, Default, PartialEq)]
+struct HttpRequest {
+    method: String,
+    authorization: Option<String>,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+fn read_http_request<R: BufRead>(reader: &mut R) -> Result<HttpRequest, MakerError> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut request = HttpRequest {
+        method: request_line
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        ..Default::default()
+    };
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Err(MakerError::General("Incomplete HTTP request"));
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(MakerError::General("Malformed HTTP header"));
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value
+                .parse()
+                .map_err(|_| MakerError::General("Malformed HTTP Content-Length"))?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            request.authorization = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("content-type") {
+            request.content_type = Some(value.to_string());
+        }
+    }
+
+    if content_length > MAX_BODY_SIZE {
+        return Err(MakerError::General("HTTP body too large"));
+    }
+    request.body = vec![0; content_length];
+    reader.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+/// Whether the `Authorization` header matches the configured token, if any.
+fn is_authorized(token: Option<&str>, authorization: Option<&str>) -> bool {
+    match token {
+        None => true,
+        Some(token) => authorization
+            .and_then(|a| a.strip_prefix("Bearer "))
+            .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes())),
+    }
+}
+
+/// Compares in a time independent of where the inputs differ, so the response time doesn't
+/// leak how much of the token a guess got right. Only the length leaks.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Whether the `Content-Type` header is JSON, ignoring any parameters like the charset.
+fn is_json(content_type: Option<&str>) -> bool {
+    content_type
+        .and_then(|c| c.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"))
+}
+
+fn write_http_response(stream: &mut TcpStream, status: &str, body: &Value) -> std::io::Result<()> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn jsonrpc_error(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": { "code": code, "message": message },
+        "id": id,
+    })
+}
+
+/// Runs a control method, see the [module docs](self). `None` if there's no such method.
+fn call_method(
+    maker: &Arc<Maker>,
+    method: &str,
+    params: &Value,
+) -> Result<Option<Value>, MakerError> {
+    let result = match method {
+        "get_status" => {
+            let wallet = maker.get_wallet().read()?;
+            json!({
+                "port": maker.config.port,
+                "network": wallet.store.network.to_string(),
+                "connection_type": format!("{:?}", maker.config.connection_type),
+                "setup_complete": *maker.is_setup_complete.read()?,
+                "active_swaps": wallet.active_swaps().len(),
+            })
+        }
+        "list_swaps" => serde_json::to_value(maker.get_wallet().read()?.active_swaps())
+            .map_err(|_| MakerError::General("Failed to serialize the active swaps"))?,
+        "get_balances" => {
+            let balances = maker.get_wallet().read()?.get_balances()?;
+            json!({
+                "spendable": balances.spendable.to_sat(),
+                "incoming_swapcoins": balances.incoming_swapcoins.to_sat(),
+                "outgoing_swapcoins": balances.outgoing_swapcoins.to_sat(),
+                "fidelity": balances.fidelity.to_sat(),
+                "unconfirmed": balances.unconfirmed.to_sat(),
+            })
+        }
+        "redeem_expired_fidelity" => {
+            let fee_rate = params.get("fee_rate").and_then(Value::as_f64);
+            let (indices, rpc, backend) = {
+                let wallet = maker.get_wallet().read()?;
+                let expired = wallet.expired_fidelity_bonds()?;
+                let mut indices = wallet
+                    .get_fidelity_bonds()
+                    .iter()
+                    .filter(|(_, (bond, _, _))| expired.contains(&bond))
+                    .map(|(index, _)| *index)
+                    .collect::<Vec<_>>();
+                indices.sort();
+                (indices, wallet.rpc.clone(), wallet.backend.clone())
+            };
+            // Redeeming waits for the confirmation, don't hold the request meanwhile. Nor the
+            // wallet: it's only locked to broadcast, and then to mark the bond spent.
+            let maker_clone = maker.clone();
+            let redeemed = indices.clone();
+            thread::spawn(move || {
+                let fallback = StaticFeeEstimator(FALLBACK_FEE_RATE);
+                let mut estimators: Vec<&dyn FeeEstimator> = Vec::new();
+                if let Some(rpc) = &rpc {
+                    estimators.push(rpc.as_ref());
+                }
+                estimators.push(&fallback);
+                let node_estimator = FallbackFeeEstimator(estimators);
+                let given = fee_rate.map(StaticFeeEstimator::from_sat_per_vb);
+                let fee_estimator: &dyn FeeEstimator = match &given {
+                    Some(given) => given,
+                    None => &node_estimator,
+                };
+                for index in redeemed {
+                    let result = maker_clone
+                        .get_wallet()
+                        .write()
+                        .map_err(MakerError::from)
+                        .and_then(|mut wallet| {
+                            Ok(wallet.broadcast_fidelity_redemption(index, fee_estimator)?)
+                        })
+                        .and_then(|txid| {
+                            wait_for_fidelity_spend(
+                                backend.as_ref(),
+                                txid,
+                                FIDELITY_SPEND_TIMEOUT,
+                            )?;
+                            let mut wallet = maker_clone.get_wallet().write()?;
+                            wallet.mark_fidelity_bond_spent(index)?;
+                            wallet.save_to_disk()?;
+                            Ok(txid)
+                        });
+                    match result {
+                        Ok(txid) => log::info!(
+                            "[{}] Redeemed fidelity bond {}: {}",
+                            maker_clone.config.port,
+                            index,
+                            txid
+                        ),
+                        Err(e) => log::error!(
+                            "[{}] Error redeeming fidelity bond {}: {:?}",
+                            maker_clone.config.port,
+                            index,
+                            e
+                        ),
+                    }
+                }
+            });
+            json!({ "redeeming": indices })
+        }
+        "stop" => {
+            let mode = if params.get("graceful").and_then(Value::as_bool) == Some(true) {
+                ShutdownMode::Graceful
+            } else {
+                ShutdownMode::Immediate
+            };
+            maker.shutdown_with(mode)?;
+            json!(true)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(result))
+}
+
+fn handle_request(maker: &Arc<Maker>, stream: &mut TcpStream) -> Result<(), MakerError> {
+    let request = read_http_request(&mut BufReader::new(stream.try_clone()?))?;
+
+    if !is_authorized(
+        maker.config.control_token.as_deref(),
+        request.authorization.as_deref(),
+    ) {
+        log::warn!(
+            "[{}] Unauthorized control request from {}",
+            maker.config.port,
+            stream.peer_addr()?
+        );
+        write_http_response(
+            stream,
+            "401 Unauthorized",
+            &jsonrpc_error(Value::Null, -32001, "Unauthorized"),
+        )?;
+        return Ok(());
+    }
+    if request.method != "POST" {
+        write_http_response(
+            stream,
+            "405 Method Not Allowed",
+            &jsonrpc_error(Value::Null, -32600, "Only POST is allowed"),
+        )?;
+        return Ok(());
+    }
+    if !is_json(request.content_type.as_deref()) {
+        write_http_response(
+            stream,
+            "415 Unsupported Media Type",
+            &jsonrpc_error(Value::Null, -32600, "Content-Type must be application/json"),
+        )?;
+        return Ok(());
+    }
+
+    let response = match serde_json::from_slice::<Value>(&request.body) {
+        Err(_) => jsonrpc_error(Value::Null, -32700, "Parse error"),
+        Ok(call) => {
+            let id = call.get("id").cloned().unwrap_or(Value::Null);
+            match call.get("method").and_then(Value::as_str) {
+                None => jsonrpc_error(id, -32600, "Invalid request"),
+                Some(method) => {
+                    log::info!("[{}] Control request: {}", maker.config.port, method);
+                    let params = call.get("params").cloned().unwrap_or(Value::Null);
+                    match call_method(maker, method, &params) {
+                        Ok(Some(result)) => {
+                            json!({ "jsonrpc": "2.0", "result": result, "id": id })
+                        }
+                        Ok(None) => jsonrpc_error(id, -32601, "Method not found"),
+                        Err(e) => jsonrpc_error(id, -32000, &format!("{:?}", e)),
+                    }
+                }
+            }
+        }
+    };
+    write_http_response(stream, "200 OK", &response)?;
+    Ok(())
+}
+
+/// Serves control requests until the maker shuts down.
+pub fn start_control_server(maker: Arc<Maker>) -> Result<(), MakerError> {
+    let listener = TcpListener::bind(&maker.config.control_address)?;
+    log::info!(
+        "[{}] Control server listening at {}",
+        maker.config.port,
+        listener.local_addr()?
+    );
+    if maker.config.control_token.is_none() && !listener.local_addr()?.ip().is_loopback() {
+        log::warn!(
+            "[{}] Control server reachable beyond localhost without a control_token",
+            maker.config.port
+        );
+    }
+
+    listener.set_nonblocking(true)?;
+
+    while !*maker.shutdown.read()? {
+        match listener.accept() {
+            Ok((mut stream, _)) => {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(Duration::from_secs(20)))?;
+                stream.set_write_timeout(Some(Duration::from_secs(20)))?;
+                if let Err(e) = handle_request(&maker, &mut stream) {
+                    log::error!(
+                        "[{}] Error handling control request: {:?}",
+                        maker.config.port,
+                        e
+                    );
+                }
+            }
+            Err(e) => {
+                if e.kind() != ErrorKind::WouldBlock {
+                    log::error!("Error accepting control connection: {:?}", e);
+                    return Err(e.into());
+                }
+                sleep(Duration::from_millis(500));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_http_request() {
+        let raw = "POST / HTTP/1.1\r\nHost: localhost\r\nauthorization: Bearer secret\r\ncontent-type: application/json\r\nContent-Length: 15\r\n\r\n{\"method\":\"x\"}\nextra";
+        let request = read_http_request(&mut raw.as_bytes()).unwrap();
+        assert_eq!(
+            request,
+            HttpRequest {
+                method: "POST".to_string(),
+                authorization: Some("Bearer secret".to_string()),
+                content_type: Some("application/json".to_string()),
+                body: b"{\"method\":\"x\"}\n".to_vec(),
+            }
+        );
+
+        let truncated = "POST / HTTP/1.1\r\nContent-Length: 15\r\n";
+        assert!(read_http_request(&mut truncated.as_bytes()).is_err());
+        let too_large = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_SIZE + 1
+        );
+        assert!(read_http_request(&mut too_large.as_bytes()).is_err());
+
+        assert!(is_authorized(None, None));
+        assert!(is_authorized(Some("secret"), Some("Bearer secret")));
+        assert!(!is_authorized(Some("secret"), Some("Bearer wrong")));
+        assert!(!is_authorized(Some("secret"), Some("secret")));
+        assert!(!is_authorized(Some("secret"), None));
+        assert!(!is_authorized(Some("secret"), Some("Bearer secret2")));
+        assert!(!is_authorized(Some("secret"), Some("Bearer secre")));
+        assert!(!is_authorized(Some("secret"), Some("Bearer ")));
+
+        assert!(is_json(Some("application/json")));
+        assert!(is_json(Some("Application/JSON; charset=utf-8")));
+        assert!(!is_json(Some("text/plain")));
+        assert!(!is_json(Some("application/x-www-form-urlencoded")));
+        assert!(!is_json(None));
+    }
+}
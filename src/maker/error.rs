@@ -26,6 +26,9 @@ pub enum MakerError {
     Deserialize(serde_cbor::Error),
     SpecialBehaviour(MakerBehavior),
     Protocol(ProtocolError),
+    TorControl(String),
+    TooBusy,
+    ShuttingDown,
 }
 
 impl From<std::io::Error> for MakerError {
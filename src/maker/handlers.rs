@@ -12,9 +12,8 @@ use std::{net::IpAddr, sync::Arc, time::Instant};
 use bitcoin::{
     hashes::Hash,
     secp256k1::{self, Secp256k1},
-    Amount, OutPoint, PublicKey, Transaction, Txid,
+    Amount, FeeRate, OutPoint, PublicKey, Transaction, TxOut,
 };
-use bitcoind::bitcoincore_rpc::RpcApi;
 
 use crate::{
     error::ProtocolError,
@@ -23,7 +22,7 @@ use crate::{
         messages::{MakerHello, MultisigPrivkey, PrivKeyHandover},
         Hash160,
     },
-    wallet::WalletSwapCoin,
+    wallet::{OutputShaping, WalletSwapCoin},
 };
 
 use crate::{
@@ -34,17 +33,21 @@ use crate::{
     protocol::{
         contract::{
             calculate_coinswap_fee, create_receivers_contract_tx, find_funding_output_index,
-            read_contract_locktime, read_hashvalue_from_contract,
+            funding_tx_fee_rate, read_contract_locktime, read_hashvalue_from_contract,
             read_pubkeys_from_multisig_redeemscript, FUNDING_TX_VBYTE_SIZE,
         },
         messages::{
-            ContractSigsAsRecvrAndSender, ContractSigsForRecvr, ContractSigsForRecvrAndSender,
-            ContractSigsForSender, HashPreimage, MakerToTakerMessage, Offer, ProofOfFunding,
-            ReqContractSigsForRecvr, ReqContractSigsForSender, SenderContractTxInfo,
-            TakerToMakerMessage,
+            negotiate_protocol_version, PROTOCOL_VERSION_COLLAB_FUNDING,
+            PROTOCOL_VERSION_FEE_NEGOTIATION, SUPPORTED_PROTOCOL_VERSIONS,
+        },
+        messages::{
+            CollabFunding, ContractSigsAsRecvrAndSender, ContractSigsForRecvr,
+            ContractSigsForRecvrAndSender, ContractSigsForSender, HashPreimage,
+            MakerToTakerMessage, ProofOfFunding, ReqContractSigsForRecvr, ReqContractSigsForSender,
+            SenderContractTxInfo, TakerToMakerMessage,
         },
     },
-    wallet::{IncomingSwapCoin, SwapCoin},
+    wallet::{IncomingSwapCoin, SwapCoin, SwapOutcome, SwapRecord},
 };
 
 /// The Global Handle Message function. Takes in a [`Arc<Maker>`] and handle messages
@@ -58,20 +61,29 @@ pub fn handle_message(
     let outgoing_message = match connection_state.allowed_message {
         ExpectedMessage::TakerHello => {
             if let TakerToMakerMessage::TakerHello(m) = message {
-                if m.protocol_version_min != 1 && m.protocol_version_max != 1 {
+                let Some(protocol_version) = negotiate_protocol_version(
+                    SUPPORTED_PROTOCOL_VERSIONS,
+                    (m.protocol_version_min, m.protocol_version_max),
+                ) else {
                     return Err(ProtocolError::WrongMessage {
-                        expected: "Only protocol version 1 is allowed".to_string(),
+                        expected: format!(
+                            "A protocol version within {}/{}",
+                            SUPPORTED_PROTOCOL_VERSIONS.0, SUPPORTED_PROTOCOL_VERSIONS.1
+                        ),
                         received: format!(
                             "min/max version  = {}/{}",
                             m.protocol_version_min, m.protocol_version_max
                         ),
                     }
                     .into());
-                }
+                };
+                connection_state.protocol_version = protocol_version;
                 connection_state.allowed_message = ExpectedMessage::NewlyConnectedTaker;
+                // Only the negotiated version, a Taker speaking a lower one than ours would
+                // otherwise find no overlap with our reply.
                 let reply = MakerToTakerMessage::MakerHello(MakerHello {
-                    protocol_version_min: 1,
-                    protocol_version_max: 1,
+                    protocol_version_min: protocol_version,
+                    protocol_version_max: protocol_version,
                 });
                 Some(reply)
             } else {
@@ -82,31 +94,37 @@ pub fn handle_message(
             }
         }
         ExpectedMessage::NewlyConnectedTaker => match message {
+            TakerToMakerMessage::ReqGiveOffer(_)
+            | TakerToMakerMessage::ReqContractSigsForSender(_)
+                if maker.is_draining() =>
+            {
+                // A new swap, nothing is at stake yet.
+                log::info!(
+                    "[{}] Shutting down, refusing a new swap from {}",
+                    maker.config.port,
+                    ip
+                );
+                return Err(MakerError::ShuttingDown);
+            }
             TakerToMakerMessage::ReqGiveOffer(_) => {
-                let (tweakable_point, max_size) = {
-                    let wallet_reader = maker.wallet.read()?;
-                    let max_size = wallet_reader.store.offer_maxsize;
-                    let tweakable_point = wallet_reader.get_tweakable_keypair().1;
-                    (tweakable_point, max_size)
-                };
                 connection_state.allowed_message = ExpectedMessage::ReqContractSigsForSender;
-                let fidelity = maker.highest_fidelity_proof.read()?;
-                let fidelity = fidelity.as_ref().expect("proof expected");
-                Some(MakerToTakerMessage::RespOffer(Box::new(Offer {
-                    absolute_fee_sat: maker.config.absolute_fee_sats,
-                    amount_relative_fee_ppb: maker.config.amount_relative_fee_ppb,
-                    time_relative_fee_ppb: maker.config.time_relative_fee_ppb,
-                    required_confirms: maker.config.required_confirms,
-                    minimum_locktime: maker.config.min_contract_reaction_time,
-                    max_size,
-                    min_size: maker.config.min_size,
-                    tweakable_point,
-                    fidelity: fidelity.clone(),
-                })))
+                Some(MakerToTakerMessage::RespOffer(Box::new(
+                    maker.make_offer()?,
+                )))
+            }
+            TakerToMakerMessage::ReqFeeNegotiation(negotiation)
+                if connection_state.protocol_version >= PROTOCOL_VERSION_FEE_NEGOTIATION =>
+            {
+                Some(MakerToTakerMessage::RespFeeNegotiation(
+                    negotiation.respond(maker.config.min_fee_rate, maker.config.max_fee_rate),
+                ))
             }
             TakerToMakerMessage::ReqContractSigsForSender(message) => {
                 connection_state.allowed_message = ExpectedMessage::ProofOfFunding;
-                Some(maker.handle_req_contract_sigs_for_sender(message)?)
+                let funding_outputs = agreed_funding_outputs(&message);
+                let reply = maker.handle_req_contract_sigs_for_sender(message)?;
+                connection_state.agreed_funding_outputs = funding_outputs;
+                Some(reply)
             }
             TakerToMakerMessage::RespProofOfFunding(proof) => {
                 connection_state.allowed_message =
@@ -130,8 +148,14 @@ pub fn handle_message(
         },
         ExpectedMessage::ReqContractSigsForSender => {
             if let TakerToMakerMessage::ReqContractSigsForSender(message) = message {
+                if maker.is_draining() {
+                    return Err(MakerError::ShuttingDown);
+                }
                 connection_state.allowed_message = ExpectedMessage::ProofOfFunding;
-                Some(maker.handle_req_contract_sigs_for_sender(message)?)
+                let funding_outputs = agreed_funding_outputs(&message);
+                let reply = maker.handle_req_contract_sigs_for_sender(message)?;
+                connection_state.agreed_funding_outputs = funding_outputs;
+                Some(reply)
             } else {
                 return Err(MakerError::UnexpectedMessage {
                     expected: "ReqContractSigsForSender".to_string(),
@@ -139,18 +163,28 @@ pub fn handle_message(
                 });
             }
         }
-        ExpectedMessage::ProofOfFunding => {
-            if let TakerToMakerMessage::RespProofOfFunding(proof) = message {
+        ExpectedMessage::ProofOfFunding => match message {
+            TakerToMakerMessage::RespProofOfFunding(proof) => {
                 connection_state.allowed_message =
                     ExpectedMessage::ProofOfFundingORContractSigsForRecvrAndSender;
                 Some(maker.handle_proof_of_funding(connection_state, proof, ip)?)
-            } else {
+            }
+            // Joined once, after signing the contracts of the funding txs.
+            TakerToMakerMessage::ReqCollabFunding(message)
+                if connection_state.protocol_version >= PROTOCOL_VERSION_COLLAB_FUNDING
+                    && connection_state.collab_funding_coins.is_empty() =>
+            {
+                // The contracts are signed again, on the joined funding txs.
+                connection_state.allowed_message = ExpectedMessage::ReqContractSigsForSender;
+                Some(maker.handle_req_collab_funding(connection_state, message, ip)?)
+            }
+            _ => {
                 return Err(MakerError::UnexpectedMessage {
                     expected: "Proof OF Funding".to_string(),
                     got: format!("{}", message),
                 });
             }
-        }
+        },
         ExpectedMessage::ProofOfFundingORContractSigsForRecvrAndSender => {
             match message {
                 TakerToMakerMessage::RespProofOfFunding(proof) => {
@@ -202,6 +236,47 @@ pub fn handle_message(
             if let TakerToMakerMessage::RespPrivKeyHandover(message) = message {
                 // Nothing to send. Succesfully completed swap
                 maker.handle_private_key_handover(message)?;
+                // The swap is over, stop watching it. Its state was saved by the earlier
+                // connections of the Taker.
+                let swap_state = maker
+                    .connection_state
+                    .lock()?
+                    .remove(&ip)
+                    .map(|(state, _)| state)
+                    .unwrap_or_default();
+                maker.release_collab_funding_coins(&swap_state.collab_funding_coins)?;
+                let received = swap_state
+                    .incoming_swapcoins
+                    .iter()
+                    .map(|sc| sc.funding_amount)
+                    .sum();
+                let swap_id = swap_state
+                    .incoming_swapcoins
+                    .first()
+                    .and_then(|sc| read_hashvalue_from_contract(&sc.contract_redeemscript).ok())
+                    .map(|hashvalue| hashvalue.to_string())
+                    .unwrap_or_default();
+                let record = SwapRecord::new(
+                    swap_id,
+                    received,
+                    vec![ip.to_string()],
+                    swap_state.coinswap_fee,
+                    SwapOutcome::Completed,
+                );
+                if let Err(e) = maker.wallet.read()?.record_swap(record) {
+                    log::error!(
+                        "[{}] Failed to record the swap in the swap history: {:?}",
+                        maker.config.port,
+                        e
+                    );
+                }
+                #[cfg(feature = "metrics")]
+                {
+                    maker.metrics.record_swap_completed(swap_state.coinswap_fee);
+                    maker
+                        .metrics
+                        .update_locked_liquidity(&*maker.wallet.read()?);
+                }
                 None
             } else {
                 return Err(MakerError::General("expected privatekey handover"));
@@ -212,6 +287,19 @@ pub fn handle_message(
     Ok(outgoing_message)
 }
 
+/// The funding outputs of the contracts the Taker asks us to sign: the agreed 2-of-2 multisigs,
+/// with our key, and their amounts.
+fn agreed_funding_outputs(message: &ReqContractSigsForSender) -> Vec<TxOut> {
+    message
+        .txs_info
+        .iter()
+        .map(|txinfo| TxOut {
+            value: txinfo.funding_input_value,
+            script_pubkey: txinfo.multisig_redeemscript.to_p2wsh(),
+        })
+        .collect()
+}
+
 impl Maker {
     /// This is the first message handler for the Maker. It receives a [ReqContractSigsForSender] message,
     /// checks the validity of contract transactions, and provide's the signature for the sender side.
@@ -238,21 +326,79 @@ impl Maker {
             acc + txinfo.funding_input_value.to_sat()
         });
 
-        if total_funding_amount >= self.config.min_size
-            && total_funding_amount < self.wallet.read()?.store.offer_maxsize
+        // Rejects swap amounts out of the bounds of the fee policy we offered.
+        self.offered_fee_policy()?
+            .swap_fee(Amount::from_sat(total_funding_amount))?;
+        log::info!(
+            "[{}] Total Funding Amount = {} | Funding Txids = {:?}",
+            self.config.port,
+            Amount::from_sat(total_funding_amount),
+            funding_txids
+        );
+        Ok(MakerToTakerMessage::RespContractSigsForSender(
+            ContractSigsForSender { sigs },
+        ))
+    }
+
+    /// Joins the Taker's first hop funding transactions with our coins, see [CollabFunding].
+    /// Refuses fee rates above our `max_fee_rate`, we pay for our part.
+    ///
+    /// Each transaction must pay one of the contracts we signed in `connection_state`. The joined
+    /// coins are locked until the swap is over, spending them would invalidate the funding.
+    pub fn handle_req_collab_funding(
+        &self,
+        connection_state: &mut ConnectionState,
+        message: CollabFunding,
+        ip: IpAddr,
+    ) -> Result<MakerToTakerMessage, MakerError> {
+        if message.fee_rate > self.config.max_fee_rate {
+            return Err(MakerError::General(
+                "Collaborative funding fee rate above our maximum",
+            ));
+        }
+        let mut unpaid_outputs = connection_state.agreed_funding_outputs.clone();
+        if message.psbts.len() > unpaid_outputs.len() {
+            return Err(MakerError::General(
+                "More collaborative funding txs than contracts we signed",
+            ));
+        }
+        for psbt in &message.psbts {
+            let Some(paid) = unpaid_outputs
+                .iter()
+                .position(|agreed| psbt.unsigned_tx.output.contains(agreed))
+            else {
+                return Err(MakerError::General(
+                    "Collaborative funding tx paying none of the contracts we signed",
+                ));
+            };
+            unpaid_outputs.swap_remove(paid);
+        }
+
+        let mut psbts = message.psbts;
+        let joined_coins = self
+            .wallet
+            .read()?
+            .join_collab_funding(&mut psbts, message.fee_rate)?;
         {
-            log::info!(
-                "[{}] Total Funding Amount = {} | Funding Txids = {:?}",
-                self.config.port,
-                Amount::from_sat(total_funding_amount),
-                funding_txids
-            );
-            Ok(MakerToTakerMessage::RespContractSigsForSender(
-                ContractSigsForSender { sigs },
-            ))
-        } else {
-            Err(MakerError::General("not enough funds"))
+            let mut wallet = self.wallet.write()?;
+            for coin in &joined_coins {
+                wallet.lock_utxo(*coin)?;
+            }
         }
+        connection_state.collab_funding_coins = joined_coins;
+        // Tracked from now on, so the coins are released if the Taker goes away.
+        self.connection_state
+            .lock()?
+            .insert(ip, (connection_state.clone(), Instant::now()));
+        log::info!(
+            "[{}] Joined {} funding transactions of the taker",
+            self.config.port,
+            psbts.len()
+        );
+        Ok(MakerToTakerMessage::RespCollabFunding(CollabFunding {
+            psbts,
+            fee_rate: message.fee_rate,
+        }))
     }
 
     /// Validates the [ProofOfFunding] message, initiate the next hop,
@@ -269,7 +415,14 @@ impl Maker {
 
         // Basic verification of ProofOfFunding Message.
         // Check function definition for all the checks performed.
-        let hashvalue = self.verify_proof_of_funding(&message)?;
+        let hashlock = self.verify_proof_of_funding(&message)?;
+
+        // Only fund the next hop at a fee rate we could have agreed on.
+        let next_fee_rate = funding_tx_fee_rate(Amount::from_sat(message.next_fee_rate))
+            .ok_or(ProtocolError::FeeRateOutOfBounds(FeeRate::MAX))?;
+        if !(self.config.min_fee_rate..=self.config.max_fee_rate).contains(&next_fee_rate) {
+            return Err(ProtocolError::FeeRateOutOfBounds(next_fee_rate).into());
+        }
         log::info!(
             "[{}] Validated Proof of Funding of receiving swap. Adding Incoming Swaps.",
             self.config.port
@@ -288,7 +441,7 @@ impl Maker {
                 .get(funding_output_index as usize)
                 .expect("funding output expected at this index");
 
-            self.wallet.write()?.sync()?;
+            self.sync_wallet(&mut *self.wallet.write()?)?;
 
             let receiver_contract_tx = create_receivers_contract_tx(
                 OutPoint {
@@ -353,12 +506,11 @@ impl Maker {
         });
 
         let calc_coinswap_fees = calculate_coinswap_fee(
-            self.config.absolute_fee_sats,
-            self.config.amount_relative_fee_ppb,
+            &self.offered_fee_policy()?,
             self.config.time_relative_fee_ppb,
             Amount::from_sat(incoming_amount),
             self.config.required_confirms, //time_in_blocks just 1 for now
-        );
+        )?;
 
         let calc_funding_tx_fees = (FUNDING_TX_VBYTE_SIZE
             * message.next_fee_rate
@@ -381,9 +533,12 @@ impl Maker {
                     .iter()
                     .map(|next_hop| next_hop.next_hashlock_pubkey)
                     .collect::<Vec<PublicKey>>(),
-                hashvalue,
+                hashlock,
                 message.next_locktime,
                 Amount::from_sat(message.next_fee_rate),
+                // The outgoing amount is what the taker agreed to, it can't be shaped.
+                OutputShaping::None,
+                None,
             )?
         };
 
@@ -422,6 +577,7 @@ impl Maker {
 
         connection_state.pending_funding_txes = my_funding_txes;
         connection_state.outgoing_swapcoins = outgoing_swapcoins;
+        connection_state.coinswap_fee = Amount::from_sat(act_coinswap_fees);
 
         // Save things to disk after Proof of Funding is confirmed.
         {
@@ -435,6 +591,8 @@ impl Maker {
                 wallet_writer.add_outgoing_swapcoin(outgoing_sc);
             }
             wallet_writer.save_to_disk()?;
+            #[cfg(feature = "metrics")]
+            self.metrics.update_locked_liquidity(&wallet_writer);
         }
 
         // Craft ReqContractSigsAsRecvrAndSender message to send to the Taker.
@@ -455,10 +613,12 @@ impl Maker {
             })
             .collect::<Vec<SenderContractTxInfo>>();
 
-        // Update the connection state.
-        self.connection_state
-            .lock()?
-            .insert(ip, (connection_state.clone(), Instant::now()));
+        // Update the connection state, keeping the coins we joined to its funding txs.
+        let mut connection_states = self.connection_state.lock()?;
+        if let Some((previous_state, _)) = connection_states.get(&ip) {
+            connection_state.collab_funding_coins = previous_state.collab_funding_coins.clone();
+        }
+        connection_states.insert(ip, (connection_state.clone(), Instant::now()));
 
         Ok(MakerToTakerMessage::ReqContractSigsAsRecvrAndSender(
             ContractSigsAsRecvrAndSender {
@@ -507,17 +667,11 @@ impl Maker {
             outgoing_swapcoin.others_contract_sig = Some(*senders_sig);
         }
 
-        let mut my_funding_txids = Vec::<Txid>::new();
-        for my_funding_tx in &connection_state.pending_funding_txes {
-            let txid = self
-                .wallet
-                .read()?
-                .rpc
-                .send_raw_transaction(my_funding_tx)
-                .map_err(|e| MakerError::Wallet(e.into()))?;
-            assert_eq!(txid, my_funding_tx.compute_txid());
-            my_funding_txids.push(txid);
-        }
+        // None goes out if the mempool rejects one of them.
+        let my_funding_txids = self
+            .wallet
+            .read()?
+            .broadcast_all_checked(&connection_state.pending_funding_txes)?;
         log::info!(
             "[{}] Outgoing Funding Txids: {:?}",
             self.config.port,
@@ -535,6 +689,8 @@ impl Maker {
                 wallet_writer.add_outgoing_swapcoin(outgoing_sc);
             }
             wallet_writer.save_to_disk()?;
+            #[cfg(feature = "metrics")]
+            self.metrics.update_locked_liquidity(&wallet_writer);
         }
 
         // Update the connection state.
@@ -582,8 +738,35 @@ impl Maker {
         }
 
         let hashvalue = Hash160::hash(&message.preimage);
+        let mut wallet_write = self.wallet.write()?;
+
+        // The preimage lets the next hop claim our outgoing funds, so only take it once both sides
+        // of this hop are funded, with fully signed contracts.
+        let is_committed = message.senders_multisig_redeemscripts.iter().all(|script| {
+            wallet_write
+                .find_incoming_swapcoin(script)
+                .is_some_and(|swapcoin| swapcoin.others_contract_sig.is_some())
+        }) && message
+            .receivers_multisig_redeemscripts
+            .iter()
+            .all(|script| {
+                wallet_write
+                    .find_outgoing_swapcoin(script)
+                    .is_some_and(|swapcoin| swapcoin.others_contract_sig.is_some())
+            });
+        if message.senders_multisig_redeemscripts.is_empty()
+            || message.receivers_multisig_redeemscripts.is_empty()
+            || !is_committed
+        {
+            log::warn!(
+                "[{}] received preimage for hashvalue={} before the swap is set up",
+                self.config.port,
+                hashvalue
+            );
+            return Err(ProtocolError::IllegalPreimageReveal.into());
+        }
+
         for multisig_redeemscript in &message.senders_multisig_redeemscripts {
-            let mut wallet_write = self.wallet.write()?;
             let incoming_swapcoin = wallet_write
                 .find_incoming_swapcoin_mut(multisig_redeemscript)
                 .expect("Incoming swampcoin expected");
@@ -602,8 +785,7 @@ impl Maker {
         let mut swapcoin_private_keys = Vec::<MultisigPrivkey>::new();
 
         for multisig_redeemscript in &message.receivers_multisig_redeemscripts {
-            let wallet_read = self.wallet.read()?;
-            let outgoing_swapcoin = wallet_read
+            let outgoing_swapcoin = wallet_write
                 .find_outgoing_swapcoin(multisig_redeemscript)
                 .expect("outgoing swapcoin expected");
             if read_hashvalue_from_contract(&outgoing_swapcoin.contract_redeemscript)? != hashvalue
@@ -617,7 +799,7 @@ impl Maker {
             });
         }
 
-        self.wallet.write()?.save_to_disk()?;
+        wallet_write.save_to_disk()?;
         Ok(MakerToTakerMessage::RespPrivKeyHandover(PrivKeyHandover {
             multisig_privkeys: swapcoin_private_keys,
         }))
@@ -636,7 +818,7 @@ impl Maker {
         log::info!("initializing Wallet Sync.");
         {
             let mut wallet_write = self.wallet.write()?;
-            wallet_write.sync()?;
+            self.sync_wallet(&mut wallet_write)?;
             wallet_write.save_to_disk()?;
         }
         log::info!("Completed Wallet Sync.");
@@ -0,0 +1,303 @@
+//! Prometheus metrics of the maker, enabled with the `metrics` feature.
+//!
+//! Served in the Prometheus text format on `GET /metrics` at the
+//! [`metrics_address`](super::config::MakerConfig::metrics_address), which is on localhost by
+//! default.
+//!
+//! Metrics:
+//! - `coinswap_maker_swaps_completed_total`: swaps completed with the private key handover.
+//! - `coinswap_maker_swaps_failed_total`: swaps whose contracts had to be recovered.
+//! - `coinswap_maker_fees_earned_sats_total`: swap revenue of the completed swaps, net of the
+//!   funding transaction fees.
+//! - `coinswap_maker_locked_liquidity_sats`: funds locked in the outgoing swapcoins, as of the
+//!   last wallet sync or swapcoin change. Scrapes never wait on the wallet.
+//! - `coinswap_maker_last_sync_duration_seconds`: duration of the last wallet sync.
+//! - `coinswap_maker_sync_duration_seconds`: histogram of the wallet sync durations.
+//! - `coinswap_maker_rescan_in_progress`: 1 while a wallet sync rescans the chain.
+
+use std::{
+    fmt::Write as _,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use bitcoin::Amount;
+
+use crate::wallet::{Wallet, WalletError};
+
+use super::{error::MakerError, Maker};
+
+/// Upper bounds of the sync duration histogram buckets, in seconds.
+const SYNC_DURATION_BUCKETS: [f64; 8] = [0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+/// Observations of the sync duration histogram.
+#[derive(Debug, Default)]
+struct SyncHistogram {
+    /// Count of observations per bucket of [SYNC_DURATION_BUCKETS]. Not cumulative.
+    buckets: [u64; SYNC_DURATION_BUCKETS.len()],
+    count: u64,
+    sum_secs: f64,
+    last_secs: f64,
+}
+
+impl SyncHistogram {
+    fn observe(&mut self, secs: f64) {
+        if let Some(bucket) = SYNC_DURATION_BUCKETS.iter().position(|le| secs <= *le) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum_secs += secs;
+        self.last_secs = secs;
+    }
+}
+
+/// Counters and gauges of the maker operations.
+#[derive(Debug, Default)]
+pub struct MakerMetrics {
+    swaps_completed: AtomicU64,
+    swaps_failed: AtomicU64,
+    fees_earned: AtomicU64,
+    locked_liquidity: AtomicU64,
+    rescan_in_progress: AtomicBool,
+    sync_durations: Mutex<SyncHistogram>,
+}
+
+impl MakerMetrics {
+    /// Records a completed swap, and the revenue it earned.
+    pub(crate) fn record_swap_completed(&self, fee: Amount) {
+        self.swaps_completed.fetch_add(1, Ordering::Relaxed);
+        self.fees_earned.fetch_add(fee.to_sat(), Ordering::Relaxed);
+    }
+
+    /// Records a swap whose contracts had to be recovered.
+    pub(crate) fn record_swap_failed(&self) {
+        self.swaps_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Updates the funds locked in the outgoing swapcoins of `wallet`. Called by the holders of
+    /// the wallet lock when the swapcoins change, so the scrapes don't need it.
+    pub(crate) fn update_locked_liquidity(&self, wallet: &Wallet) {
+        match wallet.get_balances() {
+            Ok(balances) => self
+                .locked_liquidity
+                .store(balances.outgoing_swapcoins.to_sat(), Ordering::Relaxed),
+            Err(e) => log::warn!("Failed to update the locked liquidity metric: {:?}", e),
+        }
+    }
+
+    /// Syncs the wallet, recording the duration and whether a rescan is ongoing.
+    pub(crate) fn record_sync(&self, wallet: &mut Wallet) -> Result<(), WalletError> {
+        let start = Instant::now();
+        let result = wallet.sync_with_progress(Some(&|_| {
+            self.rescan_in_progress.store(true, Ordering::Relaxed);
+        }));
+        self.rescan_in_progress.store(false, Ordering::Relaxed);
+        if let Ok(mut sync_durations) = self.sync_durations.lock() {
+            sync_durations.observe(start.elapsed().as_secs_f64());
+        }
+        if result.is_ok() {
+            self.update_locked_liquidity(wallet);
+        }
+        result
+    }
+
+    /// The metrics in the Prometheus text format.
+    fn render(&self) -> Result<String, MakerError> {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = write!(
+                out,
+                "# HELP {0} {1}\n# TYPE {0} {2}\n{0} {3}\n",
+                name, help, kind, value
+            );
+        };
+        metric(
+            "coinswap_maker_swaps_completed_total",
+            "counter",
+            "Swaps completed by the maker.",
+            self.swaps_completed.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "coinswap_maker_swaps_failed_total",
+            "counter",
+            "Swaps whose contracts had to be recovered.",
+            self.swaps_failed.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "coinswap_maker_fees_earned_sats_total",
+            "counter",
+            "Revenue of the completed swaps, in sats.",
+            self.fees_earned.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "coinswap_maker_locked_liquidity_sats",
+            "gauge",
+            "Funds locked in the outgoing swapcoins, in sats.",
+            self.locked_liquidity.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "coinswap_maker_rescan_in_progress",
+            "gauge",
+            "Whether a wallet sync is rescanning the chain.",
+            u8::from(self.rescan_in_progress.load(Ordering::Relaxed)).to_string(),
+        );
+
+        let sync_durations = self.sync_durations.lock()?;
+        metric(
+            "coinswap_maker_last_sync_duration_seconds",
+            "gauge",
+            "Duration of the last wallet sync.",
+            sync_durations.last_secs.to_string(),
+        );
+
+        let name = "coinswap_maker_sync_duration_seconds";
+        let _ = writeln!(out, "# HELP {} Durations of the wallet syncs.", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut cumulative = 0;
+        for (le, count) in SYNC_DURATION_BUCKETS.iter().zip(sync_durations.buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{le=\"+Inf\"}} {}",
+            name, sync_durations.count
+        );
+        let _ = writeln!(out, "{}_sum {}", name, sync_durations.sum_secs);
+        let _ = writeln!(out, "{}_count {}", name, sync_durations.count);
+
+        Ok(out)
+    }
+}
+
+fn write_http_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+fn handle_request(maker: &Arc<Maker>, stream: &mut TcpStream) -> Result<(), MakerError> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Scrapes have no body, skip the headers.
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let mut request_line = request_line.split_whitespace();
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = maker.metrics.render()?;
+            write_http_response(stream, "200 OK", "text/plain; version=0.0.4", &body)?;
+        }
+        (Some("GET"), _) => write_http_response(stream, "404 Not Found", "text/plain", "")?,
+        _ => write_http_response(stream, "405 Method Not Allowed", "text/plain", "")?,
+    }
+    Ok(())
+}
+
+/// Serves the metrics until the maker shuts down.
+pub fn start_metrics_server(maker: Arc<Maker>) -> Result<(), MakerError> {
+    let listener = TcpListener::bind(&maker.config.metrics_address)?;
+    log::info!(
+        "[{}] Metrics server listening at {}",
+        maker.config.port,
+        listener.local_addr()?
+    );
+
+    listener.set_nonblocking(true)?;
+
+    while !*maker.shutdown.read()? {
+        match listener.accept() {
+            Ok((mut stream, _)) => {
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(Duration::from_secs(20)))?;
+                stream.set_write_timeout(Some(Duration::from_secs(20)))?;
+                if let Err(e) = handle_request(&maker, &mut stream) {
+                    log::error!(
+                        "[{}] Error handling metrics request: {:?}",
+                        maker.config.port,
+                        e
+                    );
+                }
+            }
+            Err(e) => {
+                if e.kind() != ErrorKind::WouldBlock {
+                    log::error!("Error accepting metrics connection: {:?}", e);
+                    return Err(e.into());
+                }
+                sleep(Duration::from_millis(500));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = MakerMetrics::default();
+        metrics.record_swap_completed(Amount::from_sat(1500));
+        metrics.record_swap_completed(Amount::from_sat(500));
+        metrics.record_swap_failed();
+        {
+            let mut sync_durations = metrics.sync_durations.lock().unwrap();
+            sync_durations.observe(0.05);
+            sync_durations.observe(2.0);
+            sync_durations.observe(1000.0);
+        }
+
+        metrics.locked_liquidity.store(70_000, Ordering::Relaxed);
+
+        let rendered = metrics.render().unwrap();
+        for line in [
+            "# TYPE coinswap_maker_swaps_completed_total counter",
+            "coinswap_maker_swaps_completed_total 2",
+            "coinswap_maker_swaps_failed_total 1",
+            "coinswap_maker_fees_earned_sats_total 2000",
+            "coinswap_maker_locked_liquidity_sats 70000",
+            "coinswap_maker_rescan_in_progress 0",
+            "coinswap_maker_last_sync_duration_seconds 1000",
+            "# TYPE coinswap_maker_sync_duration_seconds histogram",
+            "coinswap_maker_sync_duration_seconds_bucket{le=\"0.1\"} 1",
+            "coinswap_maker_sync_duration_seconds_bucket{le=\"1\"} 1",
+            "coinswap_maker_sync_duration_seconds_bucket{le=\"5\"} 2",
+            "coinswap_maker_sync_duration_seconds_bucket{le=\"300\"} 2",
+            "coinswap_maker_sync_duration_seconds_bucket{le=\"+Inf\"} 3",
+            "coinswap_maker_sync_duration_seconds_sum 1002.05",
+            "coinswap_maker_sync_duration_seconds_count 3",
+        ] {
+            assert!(
+                rendered.lines().any(|l| l == line),
+                "missing {:?} in:\n{}",
+                line,
+                rendered
+            );
+        }
+    }
+}
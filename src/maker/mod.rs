@@ -12,12 +12,22 @@
 
 mod api;
 mod config;
+#[cfg(feature = "control-server")]
+mod control;
 mod error;
 mod handlers;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod onion;
+mod rate_limit;
 mod rpc;
 mod server;
 
-pub use api::{Maker, MakerBehavior};
+pub use api::{refund_expired_swaps, Maker, MakerBehavior};
+#[cfg(feature = "control-server")]
+pub use control::start_control_server;
 pub use error::MakerError;
+#[cfg(feature = "metrics")]
+pub use metrics::{start_metrics_server, MakerMetrics};
 pub use rpc::{RpcMsgReq, RpcMsgResp};
-pub use server::start_maker_server;
+pub use server::{start_maker_server, MakerServer};
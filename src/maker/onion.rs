@@ -0,0 +1,161 @@
+//! Ephemeral onion service of the maker, set up over the Tor control protocol.
+//!
+//! The service is created with `ADD_ONION` on an already running Tor, and lives as long as the
+//! control connection. It's removed with `DEL_ONION` at server shutdown.
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+};
+
+use bitcoin::hex::DisplayHex;
+
+use super::error::MakerError;
+
+/// A v3 onion service forwarding to a local port of the maker.
+#[derive(Debug)]
+pub struct OnionService {
+    control: BufReader<TcpStream>,
+    service_id: String,
+}
+
+impl OnionService {
+    /// Authenticates to the Tor control port at `tor_control`, and creates an onion service
+    /// forwarding its `port` to the same port on localhost.
+    ///
+    /// Supports the `NULL` and `COOKIE` authentication methods.
+    pub fn add(tor_control: SocketAddr, port: u16) -> Result<Self, MakerError> {
+        let mut control = BufReader::new(TcpStream::connect(tor_control)?);
+
+        let protocol_info = command(&mut control, "PROTOCOLINFO 1")?;
+        let auth_line = protocol_info
+            .iter()
+            .find_map(|line| line.strip_prefix("AUTH METHODS="))
+            .ok_or_else(|| MakerError::TorControl("no auth methods in PROTOCOLINFO".to_string()))?;
+        let (methods, cookie_file) = match auth_line.split_once(" COOKIEFILE=") {
+            Some((methods, file)) => (methods, Some(file.trim_matches('"'))),
+            None => (auth_line, None),
+        };
+        let methods = methods.split(',').collect::<Vec<_>>();
+
+        let authenticate = if methods.contains(&"NULL") {
+            "AUTHENTICATE".to_string()
+        } else if let (true, Some(cookie_file)) = (methods.contains(&"COOKIE"), cookie_file) {
+            let cookie = fs::read(cookie_file)?;
+            format!("AUTHENTICATE {}", cookie.to_lower_hex_string())
+        } else {
+            return Err(MakerError::TorControl(format!(
+                "unsupported auth methods: {}",
+                auth_line
+            )));
+        };
+        command(&mut control, &authenticate)?;
+
+        let reply = command(
+            &mut control,
+            &format!(
+                "ADD_ONION NEW:ED25519-V3 Flags=DiscardPK Port={},127.0.0.1:{}",
+                port, port
+            ),
+        )?;
+        let service_id = reply
+            .iter()
+            .find_map(|line| line.strip_prefix("ServiceID="))
+            .ok_or_else(|| MakerError::TorControl("no ServiceID in ADD_ONION reply".to_string()))?
+            .to_string();
+
+        Ok(Self {
+            control,
+            service_id,
+        })
+    }
+
+    /// The `.onion` hostname of the service.
+    pub fn hostname(&self) -> String {
+        format!("{}.onion", self.service_id)
+    }
+
+    /// Removes the onion service.
+    pub fn remove(mut self) -> Result<(), MakerError> {
+        let del_onion = format!("DEL_ONION {}", self.service_id);
+        command(&mut self.control, &del_onion)?;
+        Ok(())
+    }
+}
+
+/// Sends a control command, and returns the lines of its reply without the status codes.
+///
+/// Errors on any status code other than `250`.
+fn command(control: &mut BufReader<TcpStream>, command: &str) -> Result<Vec<String>, MakerError> {
+    let stream = control.get_mut();
+    stream.write_all(format!("{}\r\n", command).as_bytes())?;
+    stream.flush()?;
+
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        if control.read_line(&mut line)? == 0 {
+            return Err(MakerError::TorControl(
+                "control connection closed".to_string(),
+            ));
+        }
+        let line = line.trim_end();
+        let (status, separator, content) = match (line.get(..3), line.get(3..4), line.get(4..)) {
+            (Some(status), Some(separator), Some(content)) => (status, separator, content),
+            _ => return Err(MakerError::TorControl(format!("malformed reply: {}", line))),
+        };
+        if status != "250" {
+            return Err(MakerError::TorControl(line.to_string()));
+        }
+        lines.push(content.to_string());
+        if separator == " " {
+            return Ok(lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+
+    #[test]
+    fn test_onion_service_lifecycle() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tor_control = listener.local_addr().unwrap();
+
+        // Mock Tor control port, returning the commands it got.
+        let tor = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut commands = Vec::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let reply: &[u8] = match line.split_whitespace().next().unwrap() {
+                    "PROTOCOLINFO" => b"250-PROTOCOLINFO 1\r\n250-AUTH METHODS=NULL\r\n250-VERSION Tor=\"0.4.8\"\r\n250 OK\r\n",
+                    "ADD_ONION" => b"250-ServiceID=makeronionaddress\r\n250 OK\r\n",
+                    _ => b"250 OK\r\n",
+                };
+                reader.get_mut().write_all(reply).unwrap();
+                commands.push(line.trim_end().to_string());
+                line.clear();
+            }
+            commands
+        });
+
+        let onion = OnionService::add(tor_control, 6102).unwrap();
+        assert_eq!(onion.hostname(), "makeronionaddress.onion");
+        onion.remove().unwrap();
+
+        assert_eq!(
+            tor.join().unwrap(),
+            [
+                "PROTOCOLINFO 1",
+                "AUTHENTICATE",
+                "ADD_ONION NEW:ED25519-V3 Flags=DiscardPK Port=6102,127.0.0.1:6102",
+                "DEL_ONION makeronionaddress",
+            ]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Admission control of the maker server.
+//!
+//! Caps the number of taker connections served at once, and the rate of new connections of each
+//! peer over a sliding window. Connections through the maker's onion service all come from
+//! localhost, so they are only held to the concurrency cap.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use super::{config::MakerConfig, error::MakerError};
+
+/// Tracks the recent connections of each peer, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    max_concurrent_swaps: usize,
+    max_requests_per_peer: usize,
+    window: Duration,
+    requests: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &MakerConfig) -> Self {
+        Self {
+            max_concurrent_swaps: config.max_concurrent_swaps,
+            max_requests_per_peer: config.max_requests_per_peer,
+            window: Duration::from_secs(config.rate_limit_window_secs),
+            requests: HashMap::new(),
+        }
+    }
+
+    /// Records a new connection of `peer` at `now`, while `active_clients` connections are served.
+    /// The per-peer limit is skipped if the peer is unknown (`None`).
+    ///
+    /// Errors with [MakerError::TooBusy] if the connection is over capacity. Rejected connections
+    /// don't count against the peer.
+    pub fn admit(
+        &mut self,
+        peer: Option<IpAddr>,
+        active_clients: usize,
+        now: Instant,
+    ) -> Result<(), MakerError> {
+        let window = self.window;
+        self.requests.retain(|_, requests| {
+            while let Some(oldest) = requests.front() {
+                if now.saturating_duration_since(*oldest) < window {
+                    break;
+                }
+                requests.pop_front();
+            }
+            !requests.is_empty()
+        });
+
+        if active_clients >= self.max_concurrent_swaps {
+            return Err(MakerError::TooBusy);
+        }
+        let Some(peer) = peer else {
+            return Ok(());
+        };
+        let requests = self.requests.entry(peer).or_default();
+        if requests.len() >= self.max_requests_per_peer {
+            return Err(MakerError::TooBusy);
+        }
+        requests.push_back(now);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_rate_limiter() {
+        let config = MakerConfig {
+            max_concurrent_swaps: 2,
+            max_requests_per_peer: 3,
+            rate_limit_window_secs: 60,
+            ..Default::default()
+        };
+        let mut limiter = RateLimiter::new(&config);
+        let peer = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let other_peer = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let start = Instant::now();
+
+        for i in 0..3 {
+            limiter
+                .admit(Some(peer), 0, start + Duration::from_secs(i * 10))
+                .unwrap();
+        }
+        let later = start + Duration::from_secs(30);
+        assert!(matches!(
+            limiter.admit(Some(peer), 0, later),
+            Err(MakerError::TooBusy)
+        ));
+        limiter.admit(Some(other_peer), 1, later).unwrap();
+
+        // Over the concurrency cap, whoever the peer.
+        assert!(matches!(
+            limiter.admit(Some(other_peer), 2, later),
+            Err(MakerError::TooBusy)
+        ));
+
+        // The first connection slid out of the window.
+        limiter
+            .admit(Some(peer), 0, start + Duration::from_secs(60))
+            .unwrap();
+        assert!(matches!(
+            limiter.admit(Some(peer), 0, start + Duration::from_secs(65)),
+            Err(MakerError::TooBusy)
+        ));
+        assert!(limiter.requests.contains_key(&other_peer));
+        limiter
+            .admit(Some(peer), 0, start + Duration::from_secs(200))
+            .unwrap();
+        assert!(!limiter.requests.contains_key(&other_peer));
+
+        // Onion connections are only held to the concurrency cap.
+        for _ in 0..10 {
+            limiter.admit(None, 1, later).unwrap();
+        }
+        assert!(matches!(
+            limiter.admit(None, 2, later),
+            Err(MakerError::TooBusy)
+        ));
+    }
+}
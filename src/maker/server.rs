@@ -8,14 +8,12 @@ use std::{
     fs,
     io::{ErrorKind, Read, Write},
     net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
-    path::{Path, PathBuf},
     sync::{Arc, Mutex},
     thread::{self, sleep},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use bitcoin::{absolute::LockTime, Amount};
-use bitcoind::bitcoincore_rpc::RpcApi;
 
 use socks::Socks5Stream;
 
@@ -24,31 +22,66 @@ pub use super::Maker;
 use crate::{
     error::NetError,
     maker::{
-        api::{check_for_broadcasted_contracts, check_for_idle_states, ConnectionState},
+        api::{
+            check_for_broadcasted_contracts, check_for_expired_swaps, check_for_idle_states,
+            ConnectionState,
+        },
         handlers::handle_message,
+        onion::OnionService,
+        rate_limit::RateLimiter,
         rpc::start_rpc_server,
     },
     protocol::messages::TakerToMakerMessage,
-    utill::{monitor_log_for_completion, read_message, send_message, ConnectionType},
+    utill::{
+        get_dns_dir, monitor_log_for_completion, read_message, send_message, ConnectionType,
+        ShutdownMode,
+    },
     wallet::{FidelityError, WalletError},
 };
 
 use crate::maker::error::MakerError;
 
+/// Whether the setup must stop. No swap is in flight yet, so any shutdown request stops it.
+fn setup_interrupted(maker: &Maker) -> Result<bool, MakerError> {
+    Ok(*maker.shutdown.read()? || maker.is_draining())
+}
+
+/// Sleeps for `duration`, waking up early once `interrupted`.
+fn sleep_unless(
+    duration: Duration,
+    interrupted: impl Fn() -> Result<bool, MakerError>,
+) -> Result<(), MakerError> {
+    let wake_up = Instant::now() + duration;
+    while !interrupted()? {
+        let now = Instant::now();
+        if now >= wake_up {
+            break;
+        }
+        sleep((wake_up - now).min(Duration::from_secs(1)));
+    }
+    Ok(())
+}
+
 /// Fetches the Maker and DNS address, and sends maker address to the DNS server.
 /// Depending upon ConnectionType and test/prod environment, different maker address and DNS addresses are returned.
-/// Return the Maker address and an optional tor thread handle.
+/// Return the Maker address, the DNS address and an optional tor thread handle.
 ///
-/// Tor thread is spawned only if ConnectionType=TOR and --feature=tor is enabled.
-/// Errors if ConncetionType=TOR but, the tor feature is not enabled.
+/// If `onion_hostname` is given, it's advertised as the maker address.
+///
+/// Tor thread is spawned only if ConnectionType=TOR, no onion hostname is given and --feature=tor is enabled.
+/// Errors if a Tor thread is needed but, the tor feature is not enabled.
 fn network_bootstrap(
     maker: Arc<Maker>,
-) -> Result<(String, Option<mitosis::JoinHandle<()>>), MakerError> {
+    onion_hostname: Option<String>,
+) -> Result<(String, String, Option<mitosis::JoinHandle<()>>), MakerError> {
     let maker_port = maker.config.port;
     let mut tor_handle = None;
     let (maker_address, dns_address) = match maker.config.connection_type {
         ConnectionType::CLEARNET => {
-            let maker_address = format!("127.0.0.1:{}", maker_port);
+            let maker_address = match onion_hostname {
+                Some(hostname) => format!("{}:{}", hostname, maker_port),
+                None => format!("127.0.0.1:{}", maker_port),
+            };
             let dns_address = if cfg!(feature = "integration-test") {
                 format!("127.0.0.1:{}", 8080)
             } else {
@@ -65,51 +98,57 @@ fn network_bootstrap(
             (maker_address, dns_address)
         }
         ConnectionType::TOR => {
-            if !cfg!(feature = "tor") {
+            if onion_hostname.is_none() && !cfg!(feature = "tor") {
                 return Err(MakerError::General(
                     "Tor setup failure. Please compile with Tor feature enabled.",
                 ));
             } else {
-                let maker_socks_port = maker.config.socks_port;
-
-                let tor_log_dir = format!("/tmp/tor-rust-maker{}/log", maker_port);
-
-                if Path::new(tor_log_dir.as_str()).exists() {
-                    match fs::remove_file(Path::new(tor_log_dir.as_str())) {
-                        Ok(_) => log::info!(
-                            "[{}] Previous Maker log file deleted successfully",
-                            maker_port
-                        ),
-                        Err(_) => log::error!("[{}] Error deleting Maker log file", maker_port),
-                    }
-                }
+                let maker_onion_addr = match onion_hostname {
+                    Some(hostname) => hostname,
+                    None => {
+                        let maker_socks_port = maker.config.socks_port;
+
+                        let tor_dir = maker.config.data_dir.join("tor");
+                        let tor_log_dir = tor_dir.join("log");
+
+                        if tor_log_dir.exists() {
+                            match fs::remove_file(&tor_log_dir) {
+                                Ok(_) => log::info!(
+                                    "[{}] Previous Maker log file deleted successfully",
+                                    maker_port
+                                ),
+                                Err(_) => {
+                                    log::error!("[{}] Error deleting Maker log file", maker_port)
+                                }
+                            }
+                        }
 
-                tor_handle = Some(crate::tor::spawn_tor(
-                    maker_socks_port,
-                    maker_port,
-                    format!("/tmp/tor-rust-maker{}", maker_port),
-                ));
-                thread::sleep(Duration::from_secs(10));
+                        tor_handle = Some(crate::tor::spawn_tor(
+                            maker_socks_port,
+                            maker_port,
+                            tor_dir.display().to_string(),
+                        ));
+                        thread::sleep(Duration::from_secs(10));
 
-                if let Err(e) = monitor_log_for_completion(&PathBuf::from(tor_log_dir), "100%") {
-                    log::error!("[{}] Error monitoring log file: {}", maker_port, e);
-                }
+                        if let Err(e) = monitor_log_for_completion(&tor_log_dir, "100%") {
+                            log::error!("[{}] Error monitoring log file: {}", maker_port, e);
+                        }
 
-                log::info!("[{}] Maker tor is instantiated", maker_port);
+                        log::info!("[{}] Maker tor is instantiated", maker_port);
 
-                let maker_hs_path_str =
-                    format!("/tmp/tor-rust-maker{}/hs-dir/hostname", maker.config.port);
-                let maker_hs_path = PathBuf::from(maker_hs_path_str);
-                let mut maker_file = fs::File::open(&maker_hs_path).unwrap();
-                let mut maker_onion_addr: String = String::new();
-                maker_file.read_to_string(&mut maker_onion_addr).unwrap();
-                maker_onion_addr.pop();
+                        let maker_hs_path = tor_dir.join("hs-dir/hostname");
+                        let mut maker_file = fs::File::open(&maker_hs_path).unwrap();
+                        let mut maker_onion_addr: String = String::new();
+                        maker_file.read_to_string(&mut maker_onion_addr).unwrap();
+                        maker_onion_addr.pop();
+                        maker_onion_addr
+                    }
+                };
                 let maker_address = format!("{}:{}", maker_onion_addr, maker.config.port);
 
                 let directory_onion_address = if cfg!(feature = "integration-test") {
-                    let directory_hs_path_str =
-                        "/tmp/tor-rust-directory/hs-dir/hostname".to_string();
-                    let directory_hs_path = PathBuf::from(directory_hs_path_str);
+                    // The test directory server runs from the default data directory.
+                    let directory_hs_path = get_dns_dir().join("tor/hs-dir/hostname");
                     let mut directory_file = fs::File::open(directory_hs_path).unwrap();
                     let mut directory_onion_addr: String = String::new();
                     directory_file
@@ -135,7 +174,7 @@ fn network_bootstrap(
     };
 
     // Keep trying until send is successful.
-    loop {
+    while !setup_interrupted(&maker)? {
         let mut stream = match maker.config.connection_type {
             ConnectionType::CLEARNET => match TcpStream::connect(&dns_address) {
                 Ok(s) => s,
@@ -190,13 +229,18 @@ fn network_bootstrap(
         break;
     }
 
-    Ok((maker_address, tor_handle))
+    Ok((maker_address, dns_address, tor_handle))
 }
 
 /// Checks if the wallet already has fidelity bonds. if not, create the first fidelity bond.
 fn setup_fidelity_bond(maker: &Arc<Maker>, maker_address: &str) -> Result<(), MakerError> {
-    let highest_index = maker.get_wallet().read()?.get_highest_fidelity_index()?;
+    let highest_index = {
+        let wallet = maker.get_wallet().read()?;
+        let tip_height = wallet.backend.get_block_count()?;
+        wallet.best_fidelity_index(tip_height)
+    };
     if let Some(i) = highest_index {
+        wait_for_fidelity_confirmations(maker, i)?;
         let highest_proof = maker
             .get_wallet()
             .read()?
@@ -206,12 +250,7 @@ fn setup_fidelity_bond(maker: &Arc<Maker>, maker_address: &str) -> Result<(), Ma
     } else {
         // No bond in the wallet. Lets attempt to create one.
         let amount = Amount::from_sat(maker.config.fidelity_value);
-        let current_height = maker
-            .get_wallet()
-            .read()?
-            .rpc
-            .get_block_count()
-            .map_err(WalletError::Rpc)? as u32;
+        let current_height = maker.get_wallet().read()?.backend.get_block_count()? as u32;
 
         // Set 100 blocks locktime for test
         let locktime = if cfg!(feature = "integration-test") {
@@ -219,7 +258,7 @@ fn setup_fidelity_bond(maker: &Arc<Maker>, maker_address: &str) -> Result<(), Ma
         } else {
             LockTime::from_height(maker.config.fidelity_timelock + current_height).unwrap()
         };
-        while !*maker.shutdown.read()? {
+        while !setup_interrupted(maker)? {
             let fidelity_result = maker
                 .get_wallet()
                 .write()?
@@ -240,11 +279,12 @@ fn setup_fidelity_bond(maker: &Arc<Maker>, maker_address: &str) -> Result<(), Ma
                             .read()?
                             .get_next_fidelity_address(locktime)?;
                         log::info!("Send {} sats to {}", amount, addr);
-                        if cfg!(feature = "integration-test") {
-                            sleep(Duration::from_secs(3));
+                        let wait = if cfg!(feature = "integration-test") {
+                            Duration::from_secs(3)
                         } else {
-                            sleep(Duration::from_secs(300)); // Wait for 5 mins in production
-                        }
+                            Duration::from_secs(300) // Wait for 5 mins in production
+                        };
+                        sleep_unless(wait, || setup_interrupted(maker))?;
                         continue;
                     } else {
                         log::error!(
@@ -257,6 +297,7 @@ fn setup_fidelity_bond(maker: &Arc<Maker>, maker_address: &str) -> Result<(), Ma
                 }
                 Ok(i) => {
                     log::info!("[{}] Successfully created fidelity bond", maker.config.port);
+                    wait_for_fidelity_confirmations(maker, i)?;
                     let highest_proof = maker
                         .get_wallet()
                         .read()?
@@ -264,7 +305,7 @@ fn setup_fidelity_bond(maker: &Arc<Maker>, maker_address: &str) -> Result<(), Ma
                     let mut proof = maker.highest_fidelity_proof.write()?;
                     *proof = Some(highest_proof);
                     log::info!("[{}] Syncing and saving wallet data", maker.config.port);
-                    maker.get_wallet().write()?.sync()?;
+                    maker.sync_wallet(&mut *maker.get_wallet().write()?)?;
                     maker.get_wallet().read()?.save_to_disk()?;
                     log::info!("[{}] Sync and save successful", maker.config.port);
                     break;
@@ -275,6 +316,36 @@ fn setup_fidelity_bond(maker: &Arc<Maker>, maker_address: &str) -> Result<(), Ma
     Ok(())
 }
 
+/// Waits until the fidelity bond at `index` has [`MakerConfig::fidelity_required_confirms`](super::config::MakerConfig) confirmations,
+/// so that a bond whose funding transaction got reorged out is never advertised.
+fn wait_for_fidelity_confirmations(maker: &Arc<Maker>, index: u32) -> Result<(), MakerError> {
+    while !setup_interrupted(maker)? {
+        let confirmations = {
+            let mut wallet = maker.get_wallet().write()?;
+            wallet.refresh_fidelity_confirmations()?;
+            let tip = wallet.backend.get_block_count()?;
+            wallet.fidelity_bond_confirmations(index, tip).unwrap_or(0)
+        };
+        if confirmations >= maker.config.fidelity_required_confirms {
+            return Ok(());
+        }
+        log::info!(
+            "[{}] Fidelity bond {} has {} confirmations, waiting for {}",
+            maker.config.port,
+            index,
+            confirmations,
+            maker.config.fidelity_required_confirms
+        );
+        let wait = if cfg!(feature = "integration-test") {
+            Duration::from_secs(3)
+        } else {
+            Duration::from_secs(300) // Wait for 5 mins in production
+        };
+        sleep_unless(wait, || setup_interrupted(maker))?;
+    }
+    Ok(())
+}
+
 /// Keep checking if the Bitcoin Core RPC connection is live. Sets the global `accepting_client` flag as per RPC connection status.
 ///
 /// This will not block. Once Core RPC connection is live, accepting_client will set as `true` again.
@@ -286,15 +357,12 @@ fn check_connection_with_core(
     while !*maker.shutdown.read()? {
         // If connection is disrupted keep trying at heart_beat_interval (3 sec).
         // If connection is live, keep tring at rpc_ping_interval (60 sec).
-        match rpc_ping_success {
-            true => {
-                sleep(Duration::from_secs(maker.config.rpc_ping_interval_secs));
-            }
-            false => {
-                sleep(Duration::from_secs(maker.config.heart_beat_interval_secs));
-            }
-        }
-        if let Err(e) = maker.wallet.read()?.rpc.get_blockchain_info() {
+        let wait = match rpc_ping_success {
+            true => Duration::from_secs(maker.config.rpc_ping_interval_secs),
+            false => Duration::from_secs(maker.config.heart_beat_interval_secs),
+        };
+        sleep_unless(wait, || Ok(*maker.shutdown.read()?))?;
+        if let Err(e) = maker.wallet.read()?.backend.get_block_count() {
             log::info!(
                 "[{}] RPC Connection failed. Reattempting {}",
                 maker.config.port,
@@ -328,11 +396,16 @@ fn handle_client(
             Err(e) => {
                 if let NetError::IO(e) = e {
                     if e.kind() == ErrorKind::UnexpectedEof {
-                        continue;
+                        log::info!(
+                            "[{}] Client {} disconnected",
+                            maker.config.port,
+                            client_addr
+                        );
+                        return Ok(());
                     } else {
                         // For any other errors, report them
                         log::error!("[{}] Net Error: {}", maker.config.port, e);
-                        continue;
+                        return Err(MakerError::IO(e));
                     }
                 }
             }
@@ -380,8 +453,68 @@ fn handle_client(
 
 // The main Maker Server process.
 pub fn start_maker_server(maker: Arc<Maker>) -> Result<(), MakerError> {
+    MakerServer::new(maker).run()
+}
+
+/// The Maker server, with its network options.
+///
+/// [start_maker_server] runs a server with the defaults.
+pub struct MakerServer {
+    maker: Arc<Maker>,
+    tor_control: Option<SocketAddr>,
+}
+
+impl MakerServer {
+    pub fn new(maker: Arc<Maker>) -> Self {
+        Self {
+            maker,
+            tor_control: None,
+        }
+    }
+
+    /// Makes the maker reachable through an ephemeral onion service, created on the Tor instance
+    /// controlled at `tor_control`. Its `.onion` address is advertised in place of the clearnet one.
+    ///
+    /// No Tor instance is spawned by the server then, the directory is reached through the
+    /// `socks_port` of the config.
+    pub fn with_onion(mut self, tor_control: SocketAddr) -> Self {
+        self.tor_control = Some(tor_control);
+        self
+    }
+
+    /// Runs the server until the maker is shut down.
+    pub fn run(self) -> Result<(), MakerError> {
+        let maker = self.maker;
+        let port = maker.config.port;
+
+        let onion_service = self
+            .tor_control
+            .map(|tor_control| OnionService::add(tor_control, port))
+            .transpose()?;
+        if let Some(onion_service) = &onion_service {
+            log::info!(
+                "[{}] Onion service created: {}",
+                port,
+                onion_service.hostname()
+            );
+        }
+
+        let result = serve(maker, onion_service.as_ref().map(OnionService::hostname));
+
+        if let Some(onion_service) = onion_service {
+            if let Err(e) = onion_service.remove() {
+                log::warn!("[{}] Failed to remove onion service: {:?}", port, e);
+            }
+        }
+
+        result
+    }
+}
+
+fn serve(maker: Arc<Maker>, onion_hostname: Option<String>) -> Result<(), MakerError> {
     // Initialize network connections.
-    let (maker_address, tor_thread) = network_bootstrap(maker.clone())?;
+    let (maker_address, dns_address, tor_thread) =
+        network_bootstrap(maker.clone(), onion_hostname)?;
     let port = maker.config.port;
 
     let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, maker.config.port))?;
@@ -398,7 +531,7 @@ pub fn start_maker_server(maker: Arc<Maker>) -> Result<(), MakerError> {
     );
 
     // Setup the wallet with fidelity bond.
-    maker.get_wallet().write()?.sync()?;
+    maker.sync_wallet(&mut *maker.get_wallet().write()?)?;
     let network = maker.get_wallet().read()?.store.network;
     let balance = maker.get_wallet().read()?.balance()?;
     log::info!("[{}] Currency Network: {:?}", port, network);
@@ -407,6 +540,10 @@ pub fn start_maker_server(maker: Arc<Maker>) -> Result<(), MakerError> {
     setup_fidelity_bond(&maker, &maker_address)?;
     maker.wallet.write()?.refresh_offer_maxsize_cache()?;
 
+    if let Err(e) = maker.publish_offer(&dns_address, &maker_address) {
+        log::warn!("[{}] Failed to publish offer to directory: {:?}", port, e);
+    }
+
     // Global server Mutex, to switch on/off p2p network.
     let accepting_clients = Arc::new(Mutex::new(false));
 
@@ -455,7 +592,18 @@ pub fn start_maker_server(maker: Arc<Maker>) -> Result<(), MakerError> {
         })?;
     thread_pool.push(contract_watcher_thread);
 
-    // 4: The RPC server thread.
+    // 4. Swap watchdog thread.
+    // Refunds the outgoing swapcoins of dropped swaps not tracked by the threads above, once their timelock matures.
+    let maker_clone = maker.clone();
+    let swap_watchdog_thread = thread::Builder::new()
+        .name("Swap Watchdog Thread".to_string())
+        .spawn(move || {
+            log::info!("[{}] Spawning swap watchdog thread", port);
+            check_for_expired_swaps(maker_clone)
+        })?;
+    thread_pool.push(swap_watchdog_thread);
+
+    // 5: The RPC server thread.
     // User for responding back to `maker-cli` apps.
     let maker_clone = maker.clone();
     let rpc_thread = thread::Builder::new()
@@ -467,17 +615,77 @@ pub fn start_maker_server(maker: Arc<Maker>) -> Result<(), MakerError> {
 
     thread_pool.push(rpc_thread);
 
+    // 6: The control server thread.
+    // JSON-RPC interface for operational tooling.
+    #[cfg(feature = "control-server")]
+    {
+        let maker_clone = maker.clone();
+        let control_thread = thread::Builder::new()
+            .name("Control Server Thread".to_string())
+            .spawn(move || {
+                log::info!("[{}] Spawning control server", port);
+                super::start_control_server(maker_clone)
+            })?;
+        thread_pool.push(control_thread);
+    }
+
+    // 7: The metrics server thread.
+    // Prometheus endpoint for monitoring.
+    #[cfg(feature = "metrics")]
+    {
+        let maker_clone = maker.clone();
+        let metrics_thread = thread::Builder::new()
+            .name("Metrics Server Thread".to_string())
+            .spawn(move || {
+                log::info!("[{}] Spawning metrics server", port);
+                super::start_metrics_server(maker_clone)
+            })?;
+        thread_pool.push(metrics_thread);
+    }
+
     maker.setup_complete()?;
 
     log::info!("[{}] Maker setup is ready", maker.config.port);
 
+    // Each client connection must be admitted by the rate limiter.
+    let mut rate_limiter = RateLimiter::new(&maker.config);
+    let mut client_threads = Vec::new();
+
     // The P2P Client connection loop.
-    // Each client connection will spawn a new handler thread, which is added back in the global thread_pool.
+    // Each client connection will spawn a new handler thread, which is joined once finished, or at shutdown.
     // This loop beats at `maker.config.heart_beat_interval_secs`
     while !*maker.shutdown.read()? {
         let maker = maker.clone(); // This clone is needed to avoid moving the Arc<Maker> in each iterations.
         let heart_beat_interval = maker.config.heart_beat_interval_secs;
 
+        match maker.shutdown_handle.requested() {
+            Some(ShutdownMode::Immediate) => {
+                log::info!("[{}] Immediate shutdown requested.", port);
+                maker.shutdown()?;
+                continue;
+            }
+            Some(ShutdownMode::Graceful) => {
+                // Connections that haven't exchanged swapcoins yet hold nothing to drain.
+                let in_flight = maker
+                    .connection_state
+                    .lock()?
+                    .values()
+                    .filter(|(state, _)| state.has_swapcoins())
+                    .count();
+                if in_flight == 0 {
+                    log::info!("[{}] No swap in flight, shutting down.", port);
+                    maker.shutdown()?;
+                    continue;
+                }
+                log::info!(
+                    "[{}] Graceful shutdown requested, waiting for {} swaps to finish.",
+                    port,
+                    in_flight
+                );
+            }
+            None => {}
+        }
+
         // Block client connections if accepting_client=false
         if !*accepting_clients.lock()? {
             log::warn!(
@@ -490,6 +698,30 @@ pub fn start_maker_server(maker: Arc<Maker>) -> Result<(), MakerError> {
 
         match listener.accept() {
             Ok((mut stream, client_addr)) => {
+                let (finished, running): (Vec<_>, Vec<_>) = client_threads
+                    .into_iter()
+                    .partition(|thread: &thread::JoinHandle<_>| thread.is_finished());
+                // Their errors are already logged.
+                finished.into_iter().for_each(|thread| {
+                    let _ = thread.join();
+                });
+                client_threads = running;
+
+                // Onion connections all come from the local tor proxy.
+                let peer = match maker.config.connection_type {
+                    ConnectionType::CLEARNET => Some(client_addr.ip()),
+                    ConnectionType::TOR => None,
+                };
+                if let Err(e) = rate_limiter.admit(peer, client_threads.len(), Instant::now()) {
+                    log::warn!(
+                        "[{}] Rejecting client {}: {:?}",
+                        maker.config.port,
+                        client_addr,
+                        e
+                    );
+                    continue;
+                }
+
                 log::info!("[{}] Spawning Client Handler thread", maker.config.port);
 
                 let client_handler_thread = thread::Builder::new()
@@ -502,7 +734,7 @@ pub fn start_maker_server(maker: Arc<Maker>) -> Result<(), MakerError> {
                             Ok(())
                         }
                     })?;
-                thread_pool.push(client_handler_thread);
+                client_threads.push(client_handler_thread);
             }
 
             Err(e) => {
@@ -525,6 +757,7 @@ pub fn start_maker_server(maker: Arc<Maker>) -> Result<(), MakerError> {
     log::info!("[{}] Maker is shutting down.", port);
 
     // Shuting down. Join all the threads.
+    thread_pool.extend(client_threads);
     for thread in thread_pool {
         log::info!(
             "[{}] Closing Thread: {}",
@@ -539,12 +772,12 @@ pub fn start_maker_server(maker: Arc<Maker>) -> Result<(), MakerError> {
         }
     }
 
-    if maker.config.connection_type == ConnectionType::TOR && cfg!(feature = "tor") {
-        crate::tor::kill_tor_handles(tor_thread.unwrap());
+    if let Some(tor_thread) = tor_thread {
+        crate::tor::kill_tor_handles(tor_thread);
     }
 
     log::info!("Shutdown wallet sync initiated.");
-    maker.get_wallet().write()?.sync()?;
+    maker.sync_wallet(&mut *maker.get_wallet().write()?)?;
     log::info!("Shutdown wallet syncing completed.");
     maker.get_wallet().read()?.save_to_disk()?;
     log::info!("Wallet file saved to disk.");
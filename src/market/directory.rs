@@ -4,17 +4,20 @@
 //! maker addresses from directory servers, post maker addresses to directory servers,
 
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fs::{self, OpenOptions},
     io::{self, BufRead, BufReader, Write},
     net::{Ipv4Addr, TcpListener, TcpStream},
-    path::Path,
     sync::{Arc, RwLock},
     thread::{self, sleep},
     time::Duration,
 };
 
-use crate::market::rpc::start_rpc_server_thread;
+use crate::{
+    market::rpc::start_rpc_server_thread,
+    protocol::messages::{Offer, OFFER_VERSION},
+};
+use bitcoin::hex::FromHex;
 use std::path::PathBuf;
 
 use crate::utill::{
@@ -142,6 +145,8 @@ pub fn start_directory_server(directory: Arc<DirectoryServer>) {
     let address_file = directory.data_dir.join("addresses.dat");
 
     let addresses = Arc::new(RwLock::new(HashSet::new()));
+    // Hex encoded offers published by the makers, by maker address.
+    let mut offers = HashMap::new();
 
     let mut tor_handle = None;
 
@@ -149,9 +154,10 @@ pub fn start_directory_server(directory: Arc<DirectoryServer>) {
         ConnectionType::CLEARNET => {}
         ConnectionType::TOR => {
             if cfg!(feature = "tor") {
-                let tor_log_dir = "/tmp/tor-rust-directory/log".to_string();
-                if Path::new(tor_log_dir.as_str()).exists() {
-                    match fs::remove_file(Path::new(tor_log_dir.clone().as_str())) {
+                let tor_dir = directory.data_dir.join("tor");
+                let tor_log_dir = tor_dir.join("log");
+                if tor_log_dir.exists() {
+                    match fs::remove_file(&tor_log_dir) {
                         Ok(_) => log::info!("Previous directory log file deleted successfully"),
                         Err(_) => log::error!("Error deleting directory log file"),
                     }
@@ -162,18 +168,18 @@ pub fn start_directory_server(directory: Arc<DirectoryServer>) {
                 tor_handle = Some(crate::tor::spawn_tor(
                     socks_port,
                     tor_port,
-                    "/tmp/tor-rust-directory".to_string(),
+                    tor_dir.display().to_string(),
                 ));
 
                 thread::sleep(Duration::from_secs(10));
 
-                if let Err(e) = monitor_log_for_completion(&PathBuf::from(tor_log_dir), "100%") {
+                if let Err(e) = monitor_log_for_completion(&tor_log_dir, "100%") {
                     log::error!("Error monitoring Directory log file: {}", e);
                 }
 
                 log::info!("Directory tor is instantiated");
 
-                let onion_addr = get_tor_addrs(&PathBuf::from("/tmp/tor-rust-directory"));
+                let onion_addr = get_tor_addrs(&tor_dir);
 
                 log::info!(
                     "Directory Server is listening at {}:{}",
@@ -203,7 +209,7 @@ pub fn start_directory_server(directory: Arc<DirectoryServer>) {
                 stream
                     .set_write_timeout(Some(Duration::from_secs(20)))
                     .unwrap();
-                handle_client(&mut stream, address_arc);
+                handle_client(&mut stream, address_arc, &mut offers);
             }
 
             // If no connection received, check for shutdown or save addresses to disk
@@ -242,14 +248,38 @@ pub fn start_directory_server(directory: Arc<DirectoryServer>) {
 
 // The stream should have read and write timeout set.
 // TODO: Use serde encoded data instead of string.
-fn handle_client(stream: &mut TcpStream, addresses: Arc<RwLock<HashSet<String>>>) {
+fn handle_client(
+    stream: &mut TcpStream,
+    addresses: Arc<RwLock<HashSet<String>>>,
+    offers: &mut HashMap<String, String>,
+) {
     let reader_stream = stream.try_clone().unwrap();
     let mut reader = BufReader::new(reader_stream);
     let mut request_line = String::new();
 
     reader.read_line(&mut request_line).unwrap();
 
-    if request_line.starts_with("POST") {
+    if let Some(offer_line) = request_line.strip_prefix("POST_OFFER ") {
+        // Only offers with a fidelity proof for the maker's address are stored. The directory
+        // has no node, takers still look the bonds up on chain.
+        if let Some((addr, offer)) = offer_line.trim().split_once(' ') {
+            match verify_posted_offer(addr, offer) {
+                Ok(()) => {
+                    addresses.write().unwrap().insert(addr.to_string());
+                    offers.insert(addr.to_string(), offer.to_string());
+                    log::info!("Got new offer from maker: {}", addr);
+                }
+                Err(e) => log::warn!("Rejected offer from maker {}: {}", addr, e),
+            }
+        }
+    } else if request_line.starts_with("GET_OFFERS") {
+        log::info!("Taker fetched the offers from the directory server");
+        let response = offers.iter().fold(String::new(), |acc, (addr, offer)| {
+            acc + addr + " " + offer + "\n"
+        });
+        stream.write_all(response.as_bytes()).unwrap();
+        stream.flush().unwrap();
+    } else if request_line.starts_with("POST") {
         let addr: String = request_line.replace("POST ", "").trim().to_string();
         addresses.write().unwrap().insert(addr.clone());
         log::info!("Got new maker address: {}", addr);
@@ -265,11 +295,105 @@ fn handle_client(stream: &mut TcpStream, addresses: Arc<RwLock<HashSet<String>>>
     }
 }
 
+/// Decodes a hex encoded [Offer] posted for `addr`, and verifies its fidelity proof.
+fn verify_posted_offer(addr: &str, offer: &str) -> Result<(), &'static str> {
+    let offer = Vec::<u8>::from_hex(offer)
+        .ok()
+        .and_then(|bytes| serde_cbor::from_slice::<Offer>(&bytes).ok())
+        .ok_or("malformed offer")?;
+    if offer.version != OFFER_VERSION {
+        return Err("unsupported offer version");
+    }
+    offer
+        .verify_fidelity_cert(addr)
+        .map_err(|_| "invalid fidelity proof")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        protocol::{contract::MakerFeePolicy, messages::FidelityProof},
+        wallet::FidelityBond,
+    };
+    use bitcoin::{
+        absolute::LockTime,
+        hashes::Hash,
+        hex::DisplayHex,
+        secp256k1::{Message, Secp256k1, SecretKey},
+        Amount, Network, OutPoint, PublicKey,
+    };
     use std::{fs::File, io::Write};
 
+    /// An offer with a fidelity proof for `addr`, signed by `secret_key`.
+    fn signed_offer(secret_key: &SecretKey, addr: &str) -> Offer {
+        let secp = Secp256k1::new();
+        let pubkey = PublicKey::new(secret_key.public_key(&secp));
+        let bond = FidelityBond {
+            outpoint: OutPoint::null(),
+            amount: Amount::from_sat(1_000_000),
+            lock_time: LockTime::from_height(500).unwrap(),
+            pubkey,
+            conf_height: 100,
+            cert_expiry: 1,
+        };
+        let cert_hash = bond.generate_cert_hash(addr);
+        let cert_sig =
+            secp.sign_ecdsa(&Message::from_digest(cert_hash.to_byte_array()), secret_key);
+        let mut offer = Offer {
+            version: OFFER_VERSION,
+            fee_policy: MakerFeePolicy::default(),
+            time_relative_fee_ppb: Amount::from_sat(100_000),
+            required_confirms: 1,
+            minimum_locktime: 48,
+            tweakable_point: pubkey,
+            fidelity: FidelityProof {
+                bond,
+                cert_hash,
+                cert_sig,
+            },
+            network: Network::Regtest,
+            offer_sig: cert_sig,
+        };
+        offer.offer_sig = secp.sign_ecdsa(
+            &Message::from_digest(offer.terms_hash().to_byte_array()),
+            secret_key,
+        );
+        offer
+    }
+
+    #[test]
+    fn test_verify_posted_offer() {
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let addr = "127.0.0.1:6102";
+        let encode = |offer: &Offer| serde_cbor::to_vec(offer).unwrap().to_lower_hex_string();
+
+        let offer = signed_offer(&secret_key, addr);
+        assert_eq!(verify_posted_offer(addr, &encode(&offer)), Ok(()));
+
+        // The certificate is for another address.
+        assert_eq!(
+            verify_posted_offer("127.0.0.1:16102", &encode(&offer)),
+            Err("invalid fidelity proof")
+        );
+
+        // The terms were altered after signing.
+        let mut altered = offer.clone();
+        altered.fee_policy.base_fee_sats += 1;
+        assert_eq!(
+            verify_posted_offer(addr, &encode(&altered)),
+            Err("invalid fidelity proof")
+        );
+
+        let mut old = offer;
+        old.version = 1;
+        assert_eq!(
+            verify_posted_offer(addr, &encode(&old)),
+            Err("unsupported offer version")
+        );
+        assert_eq!(verify_posted_offer(addr, "00"), Err("malformed offer"));
+    }
+
     fn create_temp_config(contents: &str, file_name: &str) -> PathBuf {
         let file_path = PathBuf::from(file_name);
         let mut file = File::create(&file_path).unwrap();
@@ -2,24 +2,25 @@
 //!
 //! This module includes most of the fundamental functions defining the coinswap protocol.
 
-use std::convert::TryInto;
+use std::{convert::TryInto, str::FromStr};
 
 use bitcoin::{
     absolute::LockTime,
     blockdata::{
-        opcodes::{self, all},
-        script::{Builder, Instruction, Script},
+        opcodes::{self, all, Opcode},
+        script::{Builder, Instruction, PushBytes, Script},
     },
     ecdsa::Signature,
-    hashes::Hash,
+    hashes::{ripemd160, sha256, Hash},
     secp256k1::{
         rand::{rngs::OsRng, RngCore},
         Message, Secp256k1, SecretKey,
     },
     sighash::{EcdsaSighashType, SighashCache},
     transaction::Version,
-    Amount, OutPoint, PublicKey, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+    Amount, FeeRate, OutPoint, PublicKey, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
 };
+use serde::{Deserialize, Serialize};
 
 pub use bitcoin::hashes::hash160::Hash as Hash160;
 
@@ -46,6 +47,33 @@ use super::{
 
 /// Constant representing the virtual byte size of a funding transaction.
 pub const FUNDING_TX_VBYTE_SIZE: u64 = 372;
+
+/// Virtual byte size of a contract transaction, spending the 2of2 multisig of a funding output
+/// into the P2WSH contract.
+pub const CONTRACT_TX_VBYTE_SIZE: u64 = 150;
+
+/// Virtual byte size of a transaction spending a completed swapcoin, with its 2of2 multisig
+/// input, to a single p2wpkh output.
+pub const REDEEM_TX_VBYTE_SIZE: u64 = 138;
+
+/// Fee rate of a funding transaction of [FUNDING_TX_VBYTE_SIZE] paying `fee`. `None` if it
+/// overflows.
+///
+/// Swaps still carry the miner fee of each funding and contract transaction as an absolute
+/// amount. The fee rate is what the parties negotiate, see
+/// [FeeNegotiation](super::messages::FeeNegotiation).
+pub fn funding_tx_fee_rate(fee: Amount) -> Option<FeeRate> {
+    fee.to_sat()
+        .checked_mul(250)
+        .map(|fee_kwu| FeeRate::from_sat_per_kwu(fee_kwu / FUNDING_TX_VBYTE_SIZE))
+}
+
+/// Miner fee of a funding transaction of [FUNDING_TX_VBYTE_SIZE] at `fee_rate`.
+pub fn funding_tx_fee(fee_rate: FeeRate) -> Amount {
+    fee_rate
+        .fee_vb(FUNDING_TX_VBYTE_SIZE)
+        .unwrap_or(Amount::MAX_MONEY)
+}
 const MIN_HASHV_LEN: usize = 25;
 
 // Used in read_pubkeys_from_multisig_redeemscript() function.
@@ -53,17 +81,140 @@ const PUBKEY_LENGTH: usize = 33;
 const PUBKEY1_OFFSET: usize = 2;
 const PUBKEY2_OFFSET: usize = PUBKEY1_OFFSET + PUBKEY_LENGTH + 1;
 
-/// Calculate the coin swap fee based on various parameters.
+/// Hash function of the hashlock branch of the contracts, chosen by the Taker for a whole swap.
+///
+/// A swap is identified by the HASH160 of its preimage, its hashvalue, whatever the hash function
+/// of its hashlocks. HASH160 is RIPEMD160 of SHA256, so the hashvalue of a SHA256 hashlock follows
+/// from the hash in the contract, without knowing the preimage.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HashlockType {
+    /// `OP_HASH160`, which the contracts always used.
+    #[default]
+    Hash160,
+    /// `OP_SHA256`, like Lightning HTLCs.
+    Sha256,
+}
+
+impl FromStr for HashlockType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hash160" => Ok(HashlockType::Hash160),
+            "sha256" => Ok(HashlockType::Sha256),
+            _ => Err("Invalid hashlock type".to_string()),
+        }
+    }
+}
+
+/// The hash a contract's hashlock checks the preimage against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hashlock {
+    Hash160(Hash160),
+    Sha256(sha256::Hash),
+}
+
+impl Hashlock {
+    /// Hashlock of `preimage` with the `hashlock_type` hash function.
+    pub fn new(hashlock_type: HashlockType, preimage: &[u8]) -> Self {
+        match hashlock_type {
+            HashlockType::Hash160 => Hashlock::Hash160(Hash160::hash(preimage)),
+            HashlockType::Sha256 => Hashlock::Sha256(sha256::Hash::hash(preimage)),
+        }
+    }
+
+    pub fn hashlock_type(&self) -> HashlockType {
+        match self {
+            Hashlock::Hash160(_) => HashlockType::Hash160,
+            Hashlock::Sha256(_) => HashlockType::Sha256,
+        }
+    }
+
+    /// HASH160 of the preimage, identifying the swap.
+    pub fn hashvalue(&self) -> Hash160 {
+        match self {
+            Hashlock::Hash160(hash) => *hash,
+            Hashlock::Sha256(hash) => Hash160::from_byte_array(
+                ripemd160::Hash::hash(hash.as_byte_array()).to_byte_array(),
+            ),
+        }
+    }
+
+    /// Whether `preimage` unlocks the hashlock.
+    pub fn is_preimage(&self, preimage: &[u8]) -> bool {
+        Hashlock::new(self.hashlock_type(), preimage) == *self
+    }
+
+    fn opcode(&self) -> Opcode {
+        match self {
+            Hashlock::Hash160(_) => opcodes::all::OP_HASH160,
+            Hashlock::Sha256(_) => opcodes::all::OP_SHA256,
+        }
+    }
+
+    fn as_push_bytes(&self) -> &PushBytes {
+        match self {
+            Hashlock::Hash160(hash) => hash.as_byte_array().as_ref(),
+            Hashlock::Sha256(hash) => hash.as_byte_array().as_ref(),
+        }
+    }
+}
+
+impl From<Hash160> for Hashlock {
+    fn from(hashvalue: Hash160) -> Self {
+        Hashlock::Hash160(hashvalue)
+    }
+}
+
+/// Fee policy of a maker, advertised in its [Offer](super::messages::Offer).
+///
+/// The maker takes swaps of `min_swap_amount` up to `max_swap_amount` and charges `base_fee_sats`
+/// plus `relative_fee_ppm` parts per million of the swap amount, on top of the time relative fee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct MakerFeePolicy {
+    pub base_fee_sats: u64,
+    pub relative_fee_ppm: u64,
+    pub min_swap_amount: Amount,
+    pub max_swap_amount: Amount,
+}
+
+impl Default for MakerFeePolicy {
+    fn default() -> Self {
+        Self {
+            base_fee_sats: 1000,
+            relative_fee_ppm: 10_000,
+            min_swap_amount: Amount::from_sat(10_000),
+            max_swap_amount: Amount::MAX_MONEY,
+        }
+    }
+}
+
+impl MakerFeePolicy {
+    /// Fee for a swap of `swap_amount`. Errors if the amount is out of the policy bounds.
+    pub fn swap_fee(&self, swap_amount: Amount) -> Result<Amount, ContractError> {
+        if swap_amount < self.min_swap_amount || swap_amount > self.max_swap_amount {
+            return Err(ContractError::SwapAmountOutOfBounds {
+                amount: swap_amount,
+                min: self.min_swap_amount,
+                max: self.max_swap_amount,
+            });
+        }
+        let relative_fee = swap_amount.to_sat() as u128 * self.relative_fee_ppm as u128 / 1_000_000;
+        Ok(Amount::from_sat(
+            self.base_fee_sats + relative_fee.min(u64::MAX as u128) as u64,
+        ))
+    }
+}
+
+/// Calculate the coin swap fee of a maker, for its [MakerFeePolicy] and time relative fee.
 pub fn calculate_coinswap_fee(
-    absolute_fee_sat: Amount,
-    amount_relative_fee_ppb: Amount,
+    fee_policy: &MakerFeePolicy,
     time_relative_fee_ppb: Amount,
     total_funding_amount: Amount,
     time_in_blocks: u64,
-) -> u64 {
-    absolute_fee_sat.to_sat()
-        + (total_funding_amount.to_sat() * amount_relative_fee_ppb.to_sat()) / 1_000_000_000
-        + (time_in_blocks * time_relative_fee_ppb.to_sat()) / 1_000_000_000
+) -> Result<u64, ContractError> {
+    Ok(fee_policy.swap_fee(total_funding_amount)?.to_sat()
+        + (time_in_blocks * time_relative_fee_ppb.to_sat()) / 1_000_000_000)
 }
 
 /// Apply two signatures to a 2-of-2 multisig spend.
@@ -202,7 +353,7 @@ pub fn check_hashlock_has_pubkey(
 pub fn create_contract_redeemscript(
     pub_hashlock: &PublicKey,
     pub_timelock: &PublicKey,
-    hashvalue: &Hash160,
+    hashlock: &Hashlock,
     locktime: &u16,
 ) -> ScriptBuf {
     //avoid the malleability from OP_IF attack, see:
@@ -227,7 +378,7 @@ pub fn create_contract_redeemscript(
                              | <sig> <preimage>
     OP_SIZE                  | <sig> <preimage> <size>
     OP_SWAP                  | <sig> <size> <preimage>
-    OP_HASH160               | <sig> <size> <hash>
+    OP_HASH160|OP_SHA256     | <sig> <size> <hash>
     H(X)                     | <sig> <size> <hash> H(X)
     OP_EQUAL                 | <sig> <size> 1|0
     OP_IF                    |
@@ -255,8 +406,8 @@ pub fn create_contract_redeemscript(
     Builder::new()
         .push_opcode(opcodes::all::OP_SIZE)
         .push_opcode(opcodes::all::OP_SWAP)
-        .push_opcode(opcodes::all::OP_HASH160)
-        .push_slice(hashvalue.to_byte_array())
+        .push_opcode(hashlock.opcode())
+        .push_slice(hashlock.as_push_bytes())
         .push_opcode(opcodes::all::OP_EQUAL)
         .push_opcode(opcodes::all::OP_IF)
             .push_key(pub_hashlock)
@@ -275,38 +426,58 @@ pub fn create_contract_redeemscript(
         .into_script()
 }
 
-/// Read the hash value from a contract redeem script.
-pub fn read_hashvalue_from_contract(redeemscript: &Script) -> Result<Hash160, ContractError> {
+/// Read the hashlock from a contract redeem script.
+pub fn read_hashlock_from_contract(redeemscript: &Script) -> Result<Hashlock, ContractError> {
     if redeemscript.to_bytes().len() < MIN_HASHV_LEN {
         return Err(ContractError::Protocol("Contract reedemscript too short!"));
     }
     let mut instrs = redeemscript.instructions().skip(2);
     // Unwrap Safety: length is checked
-    let Instruction::Op(opcodes::all::OP_HASH160) = instrs.next().unwrap()? else {
+    let Instruction::Op(hash_opcode) = instrs.next().unwrap()? else {
         return Err(ContractError::Protocol("Hash is not present!"));
     };
     let Instruction::PushBytes(hash_b) = instrs.next().unwrap()? else {
         return Err(ContractError::Protocol("Invalid script!"));
     };
 
-    Ok(Hash160::from_slice(hash_b.as_bytes())?)
+    match hash_opcode {
+        opcodes::all::OP_HASH160 => Ok(Hashlock::Hash160(Hash160::from_slice(hash_b.as_bytes())?)),
+        opcodes::all::OP_SHA256 => Ok(Hashlock::Sha256(sha256::Hash::from_slice(
+            hash_b.as_bytes(),
+        )?)),
+        _ => Err(ContractError::Protocol("Hash is not present!")),
+    }
+}
+
+/// Read the hash value from a contract redeem script, see [Hashlock::hashvalue].
+pub fn read_hashvalue_from_contract(redeemscript: &Script) -> Result<Hash160, ContractError> {
+    Ok(read_hashlock_from_contract(redeemscript)?.hashvalue())
 }
 
-/// Check that all the contract redeemscripts involve the same hashvalue.
-pub fn check_hashvalues_are_equal(message: &ProofOfFunding) -> Result<Hash160, ContractError> {
-    let hashvalues = message
+/// Check that all the contract redeemscripts involve the same hashlock.
+pub fn check_hashlocks_are_equal(message: &ProofOfFunding) -> Result<Hashlock, ContractError> {
+    let hashlocks = message
         .confirmed_funding_txes
         .iter()
-        .map(|funding_info| read_hashvalue_from_contract(&funding_info.contract_redeemscript))
+        .map(|funding_info| read_hashlock_from_contract(&funding_info.contract_redeemscript))
         .collect::<Result<Vec<_>, ContractError>>()?;
 
-    if !hashvalues.iter().all(|value| value == &hashvalues[0]) {
+    if !hashlocks.iter().all(|value| value == &hashlocks[0]) {
         return Err(ContractError::Protocol(
-            "contract reedemscript doesn't have equal hashvalues",
+            "contract reedemscript doesn't have equal hashlocks",
         ));
     }
 
-    Ok(hashvalues[0])
+    Ok(hashlocks[0])
+}
+
+/// Find the hashlock preimage in a witness: the 32 bytes element unlocking `hashlock`.
+/// `None` if no element matches, like in a timelock spend.
+pub fn extract_preimage_from_witness(witness: &Witness, hashlock: &Hashlock) -> Option<[u8; 32]> {
+    witness
+        .iter()
+        .filter_map(|element| -> Option<[u8; 32]> { element.try_into().ok() })
+        .find(|element| hashlock.is_preimage(element))
 }
 
 /// Read the locktime from a contract redeem script.
@@ -342,24 +513,81 @@ pub fn read_contract_locktime(redeemscript: &Script) -> Result<u16, ContractErro
     }
 }
 
+/// Byte offset of the hashlock pubkey in a contract redeem script. It follows the hash, whose
+/// length depends on the [HashlockType].
+fn hashlock_pubkey_offset(redeemscript: &Script) -> usize {
+    // OP_SIZE OP_SWAP OP_HASH <hash len> <hash> OP_EQUAL OP_IF <pubkey len>
+    7 + redeemscript.as_bytes().get(3).copied().unwrap_or(20) as usize
+}
+
 /// Read the hashlock pubkey from a contract redeem script.
 pub fn read_hashlock_pubkey_from_contract(
     redeemscript: &Script,
 ) -> Result<PublicKey, ContractError> {
-    if redeemscript.to_bytes().len() < 61 {
+    let offset = hashlock_pubkey_offset(redeemscript);
+    if redeemscript.to_bytes().len() < offset + 34 {
         return Err(ContractError::Protocol("contract reedemscript too short"));
     }
-    Ok(PublicKey::from_slice(&redeemscript.to_bytes()[27..60])?)
+    Ok(PublicKey::from_slice(
+        &redeemscript.to_bytes()[offset..offset + 33],
+    )?)
 }
 
 /// Read the timelock pubkey from a contract redeem script.
 pub fn read_timelock_pubkey_from_contract(
     redeemscript: &Script,
 ) -> Result<PublicKey, ContractError> {
-    if redeemscript.to_bytes().len() < 99 {
+    // <hashlock pubkey> 32 1 OP_ELSE <pubkey len>
+    let offset = hashlock_pubkey_offset(redeemscript) + 38;
+    if redeemscript.to_bytes().len() < offset + 34 {
         return Err(ContractError::Protocol("contract reedemscript too short"));
     }
-    Ok(PublicKey::from_slice(&redeemscript.to_bytes()[65..98])?)
+    Ok(PublicKey::from_slice(
+        &redeemscript.to_bytes()[offset..offset + 33],
+    )?)
+}
+
+/// Verify a contract redeem script received from a counterparty encodes the agreed hashlock,
+/// locktime and pubkeys, byte for byte.
+///
+/// Each pubkey is checked against its own branch: a contract paying our hashlock key in its
+/// timelock branch is rejected.
+pub fn verify_contract_redeemscript(
+    redeemscript: &Script,
+    expected_hashlock: &Hashlock,
+    expected_timelock: u16,
+    hashlock_pubkey: &PublicKey,
+    timelock_pubkey: &PublicKey,
+) -> Result<(), ContractError> {
+    if read_hashlock_from_contract(redeemscript)? != *expected_hashlock {
+        return Err(ContractError::Protocol(
+            "contract reedemscript hashlock doesn't match",
+        ));
+    }
+    if read_hashlock_pubkey_from_contract(redeemscript)? != *hashlock_pubkey {
+        return Err(ContractError::Protocol(
+            "contract reedemscript hashlock pubkey doesn't match",
+        ));
+    }
+    if read_timelock_pubkey_from_contract(redeemscript)? != *timelock_pubkey {
+        return Err(ContractError::Protocol(
+            "contract reedemscript timelock pubkey doesn't match",
+        ));
+    }
+
+    let expected = create_contract_redeemscript(
+        hashlock_pubkey,
+        timelock_pubkey,
+        expected_hashlock,
+        &expected_timelock,
+    );
+    if expected.as_script() == redeemscript {
+        Ok(())
+    } else {
+        Err(ContractError::Protocol(
+            "contract reedemscript doesn't match the agreed locktime",
+        ))
+    }
 }
 
 /// Read the pubkeys from a multisig redeem script.
@@ -416,7 +644,7 @@ pub fn is_contract_out_valid(
     contract_output: &TxOut,
     hashlock_pubkey: &PublicKey,
     timelock_pubkey: &PublicKey,
-    hashvalue: &Hash160,
+    hashlock: &Hashlock,
     locktime: &u16,
     minimum_locktime: &u16,
 ) -> Result<(), ContractError> {
@@ -425,7 +653,7 @@ pub fn is_contract_out_valid(
     }
 
     let redeemscript_from_request =
-        create_contract_redeemscript(hashlock_pubkey, timelock_pubkey, hashvalue, locktime);
+        create_contract_redeemscript(hashlock_pubkey, timelock_pubkey, hashlock, locktime);
     let contract_spk_from_request = redeemscript_to_scriptpubkey(&redeemscript_from_request);
     if contract_output.script_pubkey != contract_spk_from_request {
         return Err(ContractError::Protocol(
@@ -583,8 +811,12 @@ mod test {
         let locktime = random::<u16>();
         println!("randomly chosen locktime = {}", locktime);
 
-        let contract_script =
-            create_contract_redeemscript(&pub_hashlock, &pub_timelock, &hashvalue, &locktime);
+        let contract_script = create_contract_redeemscript(
+            &pub_hashlock,
+            &pub_timelock,
+            &hashvalue.into(),
+            &locktime,
+        );
 
         // Get the byte encoded locktime for script
         let locktime_bytecode = Builder::new().push_int(locktime as i64).into_script();
@@ -610,6 +842,213 @@ mod test {
         assert_eq!(read_contract_locktime(&contract_script).unwrap(), locktime);
     }
 
+    #[test]
+    fn test_sha256_contract_script() {
+        let preimage = [7u8; 32];
+        let hashlock = Hashlock::new(HashlockType::Sha256, &preimage);
+        let pub_hashlock = PublicKey::from_str(
+            "032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af",
+        )
+        .unwrap();
+        let pub_timelock = PublicKey::from_str(
+            "039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef",
+        )
+        .unwrap();
+
+        let contract_script =
+            create_contract_redeemscript(&pub_hashlock, &pub_timelock, &hashlock, &300);
+
+        // OP_SIZE OP_SWAP OP_SHA256 <32 bytes>
+        let expected = "827ca820".to_owned()
+            + &sha256::Hash::hash(&preimage).to_string()
+            + "876321"
+            + &pub_hashlock.to_string()
+            + "0120516721"
+            + &pub_timelock.to_string()
+            + "00022c01"
+            + "68b2757b88ac";
+        assert_eq!(format!("{:x}", contract_script), expected);
+
+        assert_eq!(
+            read_hashlock_from_contract(&contract_script).unwrap(),
+            hashlock
+        );
+        // The hashvalue is the HASH160 of the preimage, like with HASH160 hashlocks.
+        assert_eq!(
+            read_hashvalue_from_contract(&contract_script).unwrap(),
+            Hash160::hash(&preimage)
+        );
+        assert_eq!(
+            read_hashlock_pubkey_from_contract(&contract_script).unwrap(),
+            pub_hashlock
+        );
+        assert_eq!(
+            read_timelock_pubkey_from_contract(&contract_script).unwrap(),
+            pub_timelock
+        );
+        assert_eq!(read_contract_locktime(&contract_script).unwrap(), 300);
+
+        assert!(hashlock.is_preimage(&preimage));
+        assert!(!hashlock.is_preimage(&[8u8; 32]));
+        assert_ne!(Hashlock::new(HashlockType::Hash160, &preimage), hashlock);
+        assert_eq!("SHA256".parse::<HashlockType>(), Ok(HashlockType::Sha256));
+        assert_eq!("hash160".parse::<HashlockType>(), Ok(HashlockType::Hash160));
+        assert!("md5".parse::<HashlockType>().is_err());
+    }
+
+    #[test]
+    fn test_tx_vbyte_sizes() {
+        let secp = Secp256k1::new();
+        let (privkey1, privkey2) = (
+            SecretKey::from_slice(&[1; 32]).unwrap(),
+            SecretKey::from_slice(&[2; 32]).unwrap(),
+        );
+        let (pubkey1, pubkey2) = (
+            PublicKey::new(privkey1.public_key(&secp)),
+            PublicKey::new(privkey2.public_key(&secp)),
+        );
+        let multisig_redeemscript = create_multisig_redeemscript(&pubkey1, &pubkey2);
+        let contract_redeemscript = create_contract_redeemscript(
+            &pubkey1,
+            &pubkey2,
+            &Hash160::from_byte_array([7; 20]).into(),
+            &144,
+        );
+        // Largest DER signatures, with the sighash byte.
+        let sig = Signature::from_slice(
+            &[
+                [0x30, 70, 2, 33, 0].as_slice(),
+                &[0x80; 32],
+                &[2, 33, 0],
+                &[0x80; 32],
+                &[1],
+            ]
+            .concat(),
+        )
+        .unwrap();
+
+        let mut contract_tx = create_senders_contract_tx(
+            OutPoint::null(),
+            Amount::from_sat(100_000),
+            &contract_redeemscript,
+            Amount::from_sat(1000),
+        );
+        apply_two_signatures_to_2of2_multisig_spend(
+            &pubkey1,
+            &pubkey2,
+            &sig,
+            &sig,
+            &mut contract_tx.input[0],
+            &multisig_redeemscript,
+        );
+        assert_eq!(contract_tx.vsize() as u64, CONTRACT_TX_VBYTE_SIZE);
+
+        let mut redeem_tx = contract_tx.clone();
+        redeem_tx.output[0].script_pubkey = ScriptBuf::new_p2wpkh(&pubkey1.wpubkey_hash().unwrap());
+        assert_eq!(redeem_tx.vsize() as u64, REDEEM_TX_VBYTE_SIZE);
+    }
+
+    #[test]
+    fn test_funding_tx_fee_rate() {
+        assert_eq!(
+            funding_tx_fee_rate(Amount::from_sat(1000)),
+            Some(FeeRate::from_sat_per_kwu(672))
+        );
+        assert_eq!(funding_tx_fee_rate(Amount::from_sat(u64::MAX)), None);
+        // Whole sat/vB rates round trip.
+        for sat_vb in [1, 2, 50, 1000] {
+            let fee_rate = FeeRate::from_sat_per_vb_u32(sat_vb);
+            assert_eq!(
+                funding_tx_fee(fee_rate),
+                Amount::from_sat(sat_vb as u64 * FUNDING_TX_VBYTE_SIZE)
+            );
+            assert_eq!(
+                funding_tx_fee_rate(funding_tx_fee(fee_rate)),
+                Some(fee_rate)
+            );
+        }
+    }
+
+    #[test]
+    fn test_contract_redeemscript_vectors() {
+        let pub_hashlock = PublicKey::from_str(
+            "032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af",
+        )
+        .unwrap();
+        let pub_timelock = PublicKey::from_str(
+            "039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef",
+        )
+        .unwrap();
+        let hashvalue = Hash160::from_str("1a6d1b7eb06ea8bd6d0b22a19e0b20e1c4d4a7f3").unwrap();
+
+        // (locktime, redeemscript, p2wsh scriptpubkey). The locktimes cover the small int opcodes,
+        // and pushes of one, two and three bytes, the last ones padded to keep the sign bit clear.
+        let vectors = [
+            (
+                16,
+                "827ca9141a6d1b7eb06ea8bd6d0b22a19e0b20e1c4d4a7f3876321032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af0120516721039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef006068b2757b88ac",
+                "002081b91f0b341015b142a9b7508f16614fe34d32b2c13c5096dd42ef36da9cf1ba",
+            ),
+            (
+                20,
+                "827ca9141a6d1b7eb06ea8bd6d0b22a19e0b20e1c4d4a7f3876321032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af0120516721039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef00011468b2757b88ac",
+                "002010612e4422feb30a9abebd72cce0d7ccb27d907a40335002d7877f565358c42a",
+            ),
+            (
+                144,
+                "827ca9141a6d1b7eb06ea8bd6d0b22a19e0b20e1c4d4a7f3876321032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af0120516721039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef0002900068b2757b88ac",
+                "002053543be4e11b54430c212dc655f2a5093af0eaf0220e0d66e59c57cace305035",
+            ),
+            (
+                1000,
+                "827ca9141a6d1b7eb06ea8bd6d0b22a19e0b20e1c4d4a7f3876321032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af0120516721039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef0002e80368b2757b88ac",
+                "00206b915a4ae994f2088337445cb0f97e8129492cae725b1a301bbdf01ddf66ada4",
+            ),
+            (
+                65535,
+                "827ca9141a6d1b7eb06ea8bd6d0b22a19e0b20e1c4d4a7f3876321032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af0120516721039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef0003ffff0068b2757b88ac",
+                "00209b82e3fd2dce32646c6b4276cb86b099eee0a88283d62c5d341fc02893a9fe63",
+            ),
+        ];
+
+        for (locktime, redeemscript, scriptpubkey) in vectors {
+            let contract_script = create_contract_redeemscript(
+                &pub_hashlock,
+                &pub_timelock,
+                &hashvalue.into(),
+                &locktime,
+            );
+            assert_eq!(format!("{:x}", contract_script), redeemscript);
+            assert_eq!(
+                format!("{:x}", redeemscript_to_scriptpubkey(&contract_script)),
+                scriptpubkey
+            );
+            assert_eq!(read_contract_locktime(&contract_script).unwrap(), locktime);
+        }
+
+        // Cross-check against the script assembled byte by byte from the opcode table, and its
+        // BIP141 P2WSH output: OP_0 followed by the sha256 of the witness script.
+        let mut hand_made = vec![0x82, 0x7c, 0xa9, 0x14]; // OP_SIZE OP_SWAP OP_HASH160 <20 bytes>
+        hand_made.extend_from_slice(&hashvalue.to_byte_array());
+        hand_made.extend_from_slice(&[0x87, 0x63, 0x21]); // OP_EQUAL OP_IF <33 bytes>
+        hand_made.extend_from_slice(&pub_hashlock.to_bytes());
+        hand_made.extend_from_slice(&[0x01, 0x20, 0x51, 0x67, 0x21]); // 32 OP_1 OP_ELSE <33 bytes>
+        hand_made.extend_from_slice(&pub_timelock.to_bytes());
+        hand_made.extend_from_slice(&[0x00, 0x02, 0x90, 0x00]); // OP_0 144
+                                                                // OP_ENDIF OP_CSV OP_DROP OP_ROT OP_EQUALVERIFY OP_CHECKSIG
+        hand_made.extend_from_slice(&[0x68, 0xb2, 0x75, 0x7b, 0x88, 0xac]);
+        let contract_script =
+            create_contract_redeemscript(&pub_hashlock, &pub_timelock, &hashvalue.into(), &144);
+        assert_eq!(contract_script.as_bytes(), &hand_made[..]);
+
+        let mut p2wsh = vec![0x00, 0x20];
+        p2wsh.extend_from_slice(&bitcoin::hashes::sha256::Hash::hash(&hand_made).to_byte_array());
+        assert_eq!(
+            redeemscript_to_scriptpubkey(&contract_script).as_bytes(),
+            &p2wsh[..]
+        );
+    }
+
     #[test]
     fn test_pubkey_extraction_from_2of2_multisig() {
         // Create pubkeys to contruct 2of2 multi
@@ -738,7 +1177,7 @@ mod test {
             &contract_tx.output[0],
             &pub1,
             &pub2,
-            &hashvalue,
+            &hashvalue.into(),
             &locktime,
             &2
         )
@@ -945,8 +1384,12 @@ mod test {
 
         let locktime = random::<u16>();
 
-        let contract_script =
-            create_contract_redeemscript(&pub_hashlock, &pub_timelock, &hash_value, &locktime);
+        let contract_script = create_contract_redeemscript(
+            &pub_hashlock,
+            &pub_timelock,
+            &hash_value.into(),
+            &locktime,
+        );
         let contract_redeemscript = contract_script.as_script();
 
         let error_message = check_hashlock_has_pubkey(contract_redeemscript, &public_key_1, &nonce);
@@ -982,8 +1425,12 @@ mod test {
 
         let locktime = random::<u16>();
 
-        let contract_script =
-            create_contract_redeemscript(&pub_hashlock, &pub_timelock, &hashvalue, &locktime);
+        let contract_script = create_contract_redeemscript(
+            &pub_hashlock,
+            &pub_timelock,
+            &hashvalue.into(),
+            &locktime,
+        );
 
         let test_hashlock_pubkey_1 = read_hashlock_pubkey_from_contract(&contract_script).unwrap();
 
@@ -1022,8 +1469,12 @@ mod test {
 
         let locktime = random::<u16>();
 
-        let contract_script =
-            create_contract_redeemscript(&pub_hashlock, &pub_timelock, &hashvalue, &locktime);
+        let contract_script = create_contract_redeemscript(
+            &pub_hashlock,
+            &pub_timelock,
+            &hashvalue.into(),
+            &locktime,
+        );
 
         let test_timelock_pubkey_1 = read_timelock_pubkey_from_contract(&contract_script).unwrap();
 
@@ -1045,6 +1496,105 @@ mod test {
 
         assert_eq!(error_message, "contract reedemscript too short");
     }
+    #[test]
+    fn test_verify_contract_redeemscript() {
+        let hashlock = Hashlock::Hash160(Hash160::from_byte_array(thread_rng().gen::<[u8; 20]>()));
+
+        let pub_hashlock = PublicKey::from_str(
+            "032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af",
+        )
+        .unwrap();
+
+        let pub_timelock = PublicKey::from_str(
+            "039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef",
+        )
+        .unwrap();
+
+        let locktime = 100;
+
+        let contract_script =
+            create_contract_redeemscript(&pub_hashlock, &pub_timelock, &hashlock, &locktime);
+
+        verify_contract_redeemscript(
+            &contract_script,
+            &hashlock,
+            locktime,
+            &pub_hashlock,
+            &pub_timelock,
+        )
+        .unwrap();
+
+        let error_message = |result: Result<(), ContractError>| match result.unwrap_err() {
+            ContractError::Protocol(msg) => msg,
+            _ => "Not the correct Path",
+        };
+
+        // The keys in swapped roles.
+        assert_eq!(
+            error_message(verify_contract_redeemscript(
+                &contract_script,
+                &hashlock,
+                locktime,
+                &pub_timelock,
+                &pub_hashlock,
+            )),
+            "contract reedemscript hashlock pubkey doesn't match"
+        );
+
+        assert_eq!(
+            error_message(verify_contract_redeemscript(
+                &contract_script,
+                &Hashlock::Hash160(Hash160::all_zeros()),
+                locktime,
+                &pub_hashlock,
+                &pub_timelock,
+            )),
+            "contract reedemscript hashlock doesn't match"
+        );
+        assert_eq!(
+            error_message(verify_contract_redeemscript(
+                &contract_script,
+                &Hashlock::Sha256(sha256::Hash::all_zeros()),
+                locktime,
+                &pub_hashlock,
+                &pub_timelock,
+            )),
+            "contract reedemscript hashlock doesn't match"
+        );
+        assert_eq!(
+            error_message(verify_contract_redeemscript(
+                &contract_script,
+                &hashlock,
+                locktime + 1,
+                &pub_hashlock,
+                &pub_timelock,
+            )),
+            "contract reedemscript doesn't match the agreed locktime"
+        );
+        assert_eq!(
+            error_message(verify_contract_redeemscript(
+                &contract_script,
+                &hashlock,
+                locktime,
+                &pub_hashlock,
+                &pub_hashlock,
+            )),
+            "contract reedemscript timelock pubkey doesn't match"
+        );
+
+        // Anything appended to the script is rejected.
+        let mut bytes = contract_script.to_bytes();
+        bytes.push(0x51);
+        assert!(verify_contract_redeemscript(
+            &ScriptBuf::from_bytes(bytes),
+            &hashlock,
+            locktime,
+            &pub_hashlock,
+            &pub_timelock,
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_check_reedemscript_is_multisig() {
         let initial_redeem_script = ScriptBuf::from(
@@ -1087,57 +1637,105 @@ mod test {
     #[test]
     fn calculate_coinswap_fee_normal() {
         // Test with typical values
-        let absolute_fee_sat = Amount::from_sat(1000);
-        let amount_relative_fee_ppb = Amount::from_sat(500_000_000);
+        let fee_policy = MakerFeePolicy {
+            base_fee_sats: 1000,
+            relative_fee_ppm: 500_000,
+            min_swap_amount: Amount::ZERO,
+            max_swap_amount: Amount::MAX_MONEY,
+        };
         let time_relative_fee_ppb = Amount::from_sat(200_000_000);
         let total_funding_amount = Amount::from_sat(1_000_000_000);
         let time_in_blocks = 100;
 
-        let expected_fee = 1000
-            + (1_000_000_000 * 500_000_000) / 1_000_000_000
-            + (100 * 200_000_000) / 1_000_000_000;
+        let expected_fee =
+            1000 + (1_000_000_000 * 500_000) / 1_000_000 + (100 * 200_000_000) / 1_000_000_000;
 
         let calculated_fee = calculate_coinswap_fee(
-            absolute_fee_sat,
-            amount_relative_fee_ppb,
+            &fee_policy,
             time_relative_fee_ppb,
             total_funding_amount,
             time_in_blocks,
-        );
+        )
+        .unwrap();
 
         assert_eq!(calculated_fee, expected_fee);
 
         // Test with zero values
+        let zero_policy = MakerFeePolicy {
+            base_fee_sats: 0,
+            relative_fee_ppm: 0,
+            ..fee_policy
+        };
         assert_eq!(
-            calculate_coinswap_fee(Amount::ZERO, Amount::ZERO, Amount::ZERO, Amount::ZERO, 0),
+            calculate_coinswap_fee(&zero_policy, Amount::ZERO, Amount::ZERO, 0).unwrap(),
             0
         );
 
         // Test with only the absolute fee being non-zero
         assert_eq!(
             calculate_coinswap_fee(
-                Amount::from_sat(1000),
-                Amount::ZERO,
+                &MakerFeePolicy {
+                    base_fee_sats: 1000,
+                    ..zero_policy
+                },
                 Amount::ZERO,
                 Amount::ZERO,
                 0
-            ),
+            )
+            .unwrap(),
             1000
         );
 
         // Test with only the relative fees being non-zero
         assert_eq!(
             calculate_coinswap_fee(
-                Amount::ZERO,
-                Amount::from_sat(1_000_000_000),
+                &MakerFeePolicy {
+                    relative_fee_ppm: 1_000_000,
+                    ..zero_policy
+                },
                 Amount::from_sat(1_000_000_000),
                 Amount::from_sat(1000),
                 10
-            ),
+            )
+            .unwrap(),
             1010
         );
     }
 
+    #[test]
+    fn test_maker_fee_policy_bounds() {
+        let fee_policy = MakerFeePolicy {
+            base_fee_sats: 500,
+            relative_fee_ppm: 1_000,
+            min_swap_amount: Amount::from_sat(10_000),
+            max_swap_amount: Amount::from_sat(1_000_000),
+        };
+
+        assert_eq!(
+            fee_policy.swap_fee(Amount::from_sat(10_000)).unwrap(),
+            Amount::from_sat(510)
+        );
+        assert_eq!(
+            fee_policy.swap_fee(Amount::from_sat(1_000_000)).unwrap(),
+            Amount::from_sat(1500)
+        );
+
+        for amount in [Amount::from_sat(9_999), Amount::from_sat(1_000_001)] {
+            match fee_policy.swap_fee(amount) {
+                Err(ContractError::SwapAmountOutOfBounds {
+                    amount: a,
+                    min,
+                    max,
+                }) => {
+                    assert_eq!(a, amount);
+                    assert_eq!(min, fee_policy.min_swap_amount);
+                    assert_eq!(max, fee_policy.max_swap_amount);
+                }
+                other => panic!("expected out of bounds error, got {:?}", other),
+            }
+        }
+    }
+
     #[test]
     fn test_apply_two_signatures_to_2of2_multisig_spend() {
         let secp = Secp256k1::new();
@@ -1205,7 +1803,64 @@ mod test {
     }
 
     #[test]
-    fn test_check_hashvalues_are_equal() {
+    fn test_extract_preimage_from_witness() {
+        let preimage = [7u8; 32];
+        let hashlock = Hashlock::new(HashlockType::Hash160, &preimage);
+        let sig = [0x30u8; 72];
+
+        // Hashlock spend: <sig> <preimage> <redeemscript>.
+        let witness = Witness::from_slice(&[&sig[..], &preimage[..], &[0x63; 100][..]]);
+        assert_eq!(
+            extract_preimage_from_witness(&witness, &hashlock),
+            Some(preimage)
+        );
+        // Found wherever it is.
+        let witness = Witness::from_slice(&[&preimage[..], &sig[..]]);
+        assert_eq!(
+            extract_preimage_from_witness(&witness, &hashlock),
+            Some(preimage)
+        );
+
+        // Timelock spend: <sig> <> <redeemscript>.
+        let witness = Witness::from_slice(&[&sig[..], &[][..], &[0x63; 100][..]]);
+        assert_eq!(extract_preimage_from_witness(&witness, &hashlock), None);
+        // Another preimage.
+        let witness = Witness::from_slice(&[&sig[..], &[8u8; 32][..]]);
+        assert_eq!(extract_preimage_from_witness(&witness, &hashlock), None);
+        // The right bytes, but not 32 of them.
+        let witness = Witness::from_slice(&[&preimage[..31]]);
+        assert_eq!(
+            extract_preimage_from_witness(
+                &witness,
+                &Hashlock::new(HashlockType::Hash160, &preimage[..31])
+            ),
+            None
+        );
+        assert_eq!(
+            extract_preimage_from_witness(&Witness::new(), &hashlock),
+            None
+        );
+
+        // SHA256 hashlock.
+        let witness = Witness::from_slice(&[&sig[..], &preimage[..], &[0x63; 100][..]]);
+        assert_eq!(
+            extract_preimage_from_witness(
+                &witness,
+                &Hashlock::new(HashlockType::Sha256, &preimage)
+            ),
+            Some(preimage)
+        );
+        assert_eq!(
+            extract_preimage_from_witness(
+                &witness,
+                &Hashlock::new(HashlockType::Sha256, &[8u8; 32])
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_check_hashlocks_are_equal() {
         let secp = Secp256k1::new();
         let priv_1 =
             PrivateKey::from_wif("cVt4o7BGAig1UXywgGSmARhxMdzP5qvQsxKkSsc1XEkw3tDTQFpy").unwrap();
@@ -1269,8 +1924,12 @@ mod test {
         // Use an u16 to strictly positive 2 byte integer
         let locktime = random::<u16>();
 
-        let contract_script_1 =
-            create_contract_redeemscript(&pub_hashlock, &pub_timelock, &hash_value_1, &locktime);
+        let contract_script_1 = create_contract_redeemscript(
+            &pub_hashlock,
+            &pub_timelock,
+            &hash_value_1.into(),
+            &locktime,
+        );
 
         let funding_info_1 = FundingTxInfo {
             funding_tx: funding_tx.clone(),
@@ -1292,14 +1951,18 @@ mod test {
         };
 
         // case with same hash value
-        let hash_value_from_fn = check_hashvalues_are_equal(&funding_proof).unwrap();
-        assert_eq!(hash_value_from_fn, hash_value_1);
+        let hash_value_from_fn = check_hashlocks_are_equal(&funding_proof).unwrap();
+        assert_eq!(hash_value_from_fn, Hashlock::Hash160(hash_value_1));
 
         // case with different hash value
         let hash_value_2 = Hash160::from_slice(&thread_rng().gen::<[u8; 20]>()).unwrap();
 
-        let contract_script_2 =
-            create_contract_redeemscript(&pub_hashlock, &pub_timelock, &hash_value_2, &locktime);
+        let contract_script_2 = create_contract_redeemscript(
+            &pub_hashlock,
+            &pub_timelock,
+            &hash_value_2.into(),
+            &locktime,
+        );
 
         let funding_info_2 = FundingTxInfo {
             funding_tx,
@@ -1320,7 +1983,7 @@ mod test {
             next_fee_rate: u64::default(),
         };
 
-        let hash_value_from_fn = check_hashvalues_are_equal(&funding_proof).unwrap_err();
+        let hash_value_from_fn = check_hashlocks_are_equal(&funding_proof).unwrap_err();
 
         let error_message_invalid_length = match hash_value_from_fn {
             ContractError::Protocol(msg) => msg,
@@ -1328,7 +1991,7 @@ mod test {
         };
         assert_eq!(
             error_message_invalid_length,
-            "contract reedemscript doesn't have equal hashvalues"
+            "contract reedemscript doesn't have equal hashlocks"
         );
     }
 }
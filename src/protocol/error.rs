@@ -1,6 +1,6 @@
 //! All Contract related errors.
 
-use bitcoin::secp256k1;
+use bitcoin::{secp256k1, Amount};
 
 /// Enum for handling contract-related errors.
 #[derive(Debug)]
@@ -11,6 +11,11 @@ pub enum ContractError {
     Hash(bitcoin::hashes::FromSliceError),
     Key(bitcoin::key::FromSliceError),
     Sighash(bitcoin::transaction::InputsIndexError),
+    SwapAmountOutOfBounds {
+        amount: Amount,
+        min: Amount,
+        max: Amount,
+    },
 }
 
 impl From<secp256k1::Error> for ContractError {
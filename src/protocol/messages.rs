@@ -17,10 +17,24 @@
 //! Taker -----> Maker1 -----> Maker2 ------> Taker
 //!
 //! ```shell
+//! ********* Fee Negotiation *********
+//! (With each Maker when it is picked, before any transaction it funds is built, if both speak
+//! PROTOCOL_VERSION_FEE_NEGOTIATION)
+//! Taker -> Maker: [TakerToMakerMessage::ReqFeeNegotiation]
+//! Maker -> Taker: [MakerToTakerMessage::RespFeeNegotiation] (Accept, or counter within its bounds)
+//!
 //! ********* Initiate First Hop *********
 //! (Sender: Taker, Receiver: Maker1)
 //! Taker -> Maker1: [TakerToMakerMessage::ReqContractSigsForSender]
 //! Maker1 -> Taker: [MakerToTakerMessage::RespContractSigsForSender]
+//!
+//! ********* Collaborative Funding (optional) *********
+//! (On the same connection, if both speak PROTOCOL_VERSION_COLLAB_FUNDING and the Taker opts in)
+//! Taker -> Maker1: [TakerToMakerMessage::ReqCollabFunding] (Unsigned funding txs of the first hop)
+//! Maker1 -> Taker: [MakerToTakerMessage::RespCollabFunding] (Joined with Maker1's coins, signed by Maker1)
+//! Taker -> Maker1: [TakerToMakerMessage::ReqContractSigsForSender] (Contracts rebuilt on the joined txs)
+//! Maker1 -> Taker: [MakerToTakerMessage::RespContractSigsForSender]
+//!
 //! Taker -> Maker1: [TakerToMakerMessage::RespProofOfFunding] (Funding Tx of the hop Taker-Maker1)
 //!
 //! ********* Initiate Second Hop *********
@@ -58,8 +72,10 @@
 use std::fmt::Display;
 
 use bitcoin::{
-    ecdsa::Signature, hashes::sha256d::Hash, secp256k1::SecretKey, Amount, PublicKey, ScriptBuf,
-    Transaction,
+    ecdsa::Signature,
+    hashes::{sha256, sha256d::Hash, Hash as _},
+    secp256k1::{self, Message, Secp256k1, SecretKey},
+    Amount, FeeRate, Network, Psbt, PublicKey, ScriptBuf, Transaction,
 };
 
 use serde::{Deserialize, Serialize};
@@ -68,12 +84,44 @@ use bitcoin::hashes::hash160::Hash as Hash160;
 
 use crate::wallet::FidelityBond;
 
+use super::{
+    contract::{Hashlock, HashlockType, MakerFeePolicy},
+    error::ContractError,
+};
+
 /// Defines the length of the Preimage.
 pub const PREIMAGE_LEN: usize = 32;
 
 /// Type for Preimage.
 pub type Preimage = [u8; PREIMAGE_LEN];
 
+/// Version of the protocol with P2WSH multisig swapcoins.
+pub const PROTOCOL_VERSION_P2WSH: u32 = 1;
+
+/// Version of the protocol adding the fee rate negotiation, see [FeeNegotiation]. Swaps with
+/// makers of older versions pay the taker's fee.
+pub const PROTOCOL_VERSION_FEE_NEGOTIATION: u32 = 2;
+
+/// Version of the protocol adding the collaborative funding of the first hop, see
+/// [CollabFunding].
+pub const PROTOCOL_VERSION_COLLAB_FUNDING: u32 = 3;
+
+/// Version of the [Offer] format. Takers ignore offers of other versions: the version 1 offers
+/// had flat `absolute_fee_sat`, `amount_relative_fee_ppb`, `min_size` and `max_size` fields where
+/// the [MakerFeePolicy] now is.
+pub const OFFER_VERSION: u32 = 2;
+
+/// Range of protocol versions this implementation speaks, advertised in the hello messages.
+pub const SUPPORTED_PROTOCOL_VERSIONS: (u32, u32) =
+    (PROTOCOL_VERSION_P2WSH, PROTOCOL_VERSION_COLLAB_FUNDING);
+
+/// The highest protocol version in both `(min, max)` ranges, if they overlap.
+pub fn negotiate_protocol_version(ours: (u32, u32), theirs: (u32, u32)) -> Option<u32> {
+    let min = ours.0.max(theirs.0);
+    let max = ours.1.min(theirs.1);
+    (min <= max).then_some(max)
+}
+
 /// Represents the initial handshake message sent from Taker to Maker.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TakerHello {
@@ -81,10 +129,76 @@ pub struct TakerHello {
     pub protocol_version_max: u32,
 }
 
+/// Fee rate proposal of the Taker for the transactions a Maker funds, sent before any of them
+/// is built.
+///
+/// The Taker settles for any fee rate within `min_accept..=max_accept`, which includes
+/// `proposed`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FeeNegotiation {
+    pub proposed: FeeRate,
+    pub min_accept: FeeRate,
+    pub max_accept: FeeRate,
+}
+
+impl FeeNegotiation {
+    /// The Maker's answer, for the fee rates within `min..=max` it is willing to pay.
+    ///
+    /// The Maker accepts the proposed rate if it is within its bounds, or else counters with the
+    /// closest rate it would pay. It rejects if that rate isn't acceptable to the Taker either.
+    pub fn respond(&self, min: FeeRate, max: FeeRate) -> FeeNegotiationResponse {
+        if (min..=max).contains(&self.proposed) {
+            return FeeNegotiationResponse::Accept;
+        }
+        let counter = self.proposed.max(min).min(max);
+        if (self.min_accept..=self.max_accept).contains(&counter) {
+            FeeNegotiationResponse::Counter(counter)
+        } else {
+            FeeNegotiationResponse::Reject
+        }
+    }
+
+    /// The fee rate both sides agreed on, if any. Counters outside the Taker's bounds are
+    /// not agreed on.
+    pub fn agreed_fee_rate(&self, response: &FeeNegotiationResponse) -> Option<FeeRate> {
+        match *response {
+            FeeNegotiationResponse::Accept => Some(self.proposed),
+            FeeNegotiationResponse::Counter(counter) => (self.min_accept..=self.max_accept)
+                .contains(&counter)
+                .then_some(counter),
+            FeeNegotiationResponse::Reject => None,
+        }
+    }
+}
+
+/// A Maker's answer to a [FeeNegotiation].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum FeeNegotiationResponse {
+    /// The proposed fee rate is within the Maker's bounds.
+    Accept,
+    /// The fee rate closest to the proposal the Maker would pay.
+    Counter(FeeRate),
+    /// The bounds of the Maker and the Taker don't overlap.
+    Reject,
+}
+
 /// Represents a request to give an offer.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GiveOffer;
 
+/// Funding transactions of the first hop, as PSBTs, for the Maker to join.
+///
+/// The Taker sends its funding transactions unsigned, right after the Maker signed their
+/// contracts. The Maker only joins transactions paying the 2-of-2 multisigs of those contracts,
+/// at most one each: it adds an input and an output of its own to each, paying `fee_rate` for
+/// them, and signs its inputs. The Taker then signs its own inputs, has the contracts signed again
+/// on the joined txids, and broadcasts. Only for Makers speaking [PROTOCOL_VERSION_COLLAB_FUNDING].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CollabFunding {
+    pub psbts: Vec<Psbt>,
+    pub fee_rate: FeeRate,
+}
+
 /// Contract Sigs requesting information for the Sender side of the hop.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ContractTxInfoForSender {
@@ -100,10 +214,53 @@ pub struct ContractTxInfoForSender {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ReqContractSigsForSender {
     pub txs_info: Vec<ContractTxInfoForSender>,
+    /// HASH160 of the preimage, identifying the swap.
     pub hashvalue: Hash160,
+    /// Hash function of the contract hashlocks, picked by the Taker. Takers that don't send it
+    /// lock with HASH160.
+    #[serde(default)]
+    pub hashlock_type: HashlockType,
+    /// SHA256 of the preimage, locking the contracts with [HashlockType::Sha256].
+    #[serde(default)]
+    pub sha256_hashvalue: Option<sha256::Hash>,
     pub locktime: u16,
 }
 
+impl ReqContractSigsForSender {
+    /// Request for contracts locked with `hashlock`.
+    pub fn new(txs_info: Vec<ContractTxInfoForSender>, hashlock: Hashlock, locktime: u16) -> Self {
+        Self {
+            txs_info,
+            hashvalue: hashlock.hashvalue(),
+            hashlock_type: hashlock.hashlock_type(),
+            sha256_hashvalue: match hashlock {
+                Hashlock::Sha256(hash) => Some(hash),
+                Hashlock::Hash160(_) => None,
+            },
+            locktime,
+        }
+    }
+
+    /// The hashlock the contracts are requested with, checked against the hashvalue.
+    pub fn hashlock(&self) -> Result<Hashlock, ContractError> {
+        let hashlock = match (self.hashlock_type, self.sha256_hashvalue) {
+            (HashlockType::Hash160, None) => Hashlock::Hash160(self.hashvalue),
+            (HashlockType::Sha256, Some(hash)) => Hashlock::Sha256(hash),
+            _ => {
+                return Err(ContractError::Protocol(
+                    "hashlock type doesn't match the hashes sent",
+                ))
+            }
+        };
+        if hashlock.hashvalue() != self.hashvalue {
+            return Err(ContractError::Protocol(
+                "sha256 hashvalue doesn't match the hashvalue",
+            ));
+        }
+        Ok(hashlock)
+    }
+}
+
 /// Contract Sigs requesting information for the Receiver side of the hop.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ContractTxInfoForRecvr {
@@ -189,6 +346,10 @@ pub enum TakerToMakerMessage {
     TakerHello(TakerHello),
     /// Request the Maker's Offer advertisement.
     ReqGiveOffer(GiveOffer),
+    /// Propose the fee rate of the transactions funded by the Maker.
+    ReqFeeNegotiation(FeeNegotiation),
+    /// Request the Maker to join the funding transactions of the first hop.
+    ReqCollabFunding(CollabFunding),
     /// Request Contract Sigs **for** the Sender side of the hop. The Maker receiving this message is the Receiver of the hop.
     ReqContractSigsForSender(ReqContractSigsForSender),
     /// Respond with the [ProofOfFunding] message. This is sent when the funding transaction gets confirmed.
@@ -208,6 +369,8 @@ impl Display for TakerToMakerMessage {
         match self {
             Self::TakerHello(_) => write!(f, "TakerHello"),
             Self::ReqGiveOffer(_) => write!(f, "ReqGiveOffer"),
+            Self::ReqFeeNegotiation(_) => write!(f, "ReqFeeNegotiation"),
+            Self::ReqCollabFunding(_) => write!(f, "ReqCollabFunding"),
             Self::ReqContractSigsForSender(_) => write!(f, "ReqContractSigsForSender"),
             Self::RespProofOfFunding(_) => write!(f, "RespProofOfFunding"),
             Self::RespContractSigsForRecvrAndSender(_) => {
@@ -220,7 +383,8 @@ impl Display for TakerToMakerMessage {
     }
 }
 
-/// Represents the initial handshake message sent from Maker to Taker.
+/// Represents the initial handshake message sent from Maker to Taker. Both bounds are the
+/// protocol version the Maker negotiated, within the range of the [TakerHello].
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct MakerHello {
     pub protocol_version_min: u32,
@@ -238,15 +402,58 @@ pub struct FidelityProof {
 /// Represents an offer in the context of the Coinswap protocol.
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Hash)]
 pub struct Offer {
-    pub absolute_fee_sat: Amount,
-    pub amount_relative_fee_ppb: Amount,
+    /// Format of the offer, [OFFER_VERSION] for the offers made by this implementation.
+    pub version: u32,
+    /// The maker's fee policy. Its maximum swap amount is capped by the maker's liquidity.
+    pub fee_policy: MakerFeePolicy,
     pub time_relative_fee_ppb: Amount,
     pub required_confirms: u64,
     pub minimum_locktime: u16,
-    pub max_size: u64,
-    pub min_size: u64,
     pub tweakable_point: PublicKey,
     pub fidelity: FidelityProof,
+    /// Network the maker swaps on.
+    pub network: Network,
+    /// Signature of the [Offer::terms_hash] by the fidelity bond key. Offers relayed by the
+    /// directory servers can't be altered without it.
+    pub offer_sig: secp256k1::ecdsa::Signature,
+}
+
+impl Offer {
+    /// Hash of everything in the offer but its signature.
+    pub fn terms_hash(&self) -> Hash {
+        let terms = (
+            self.version,
+            &self.fee_policy,
+            self.time_relative_fee_ppb,
+            self.required_confirms,
+            self.minimum_locktime,
+            &self.tweakable_point,
+            &self.fidelity,
+            self.network,
+        );
+        Hash::hash(&serde_cbor::to_vec(&terms).expect("offer terms are serializable"))
+    }
+
+    /// Verifies the offer is signed by the key of its fidelity bond.
+    pub fn verify_signature(&self) -> Result<(), secp256k1::Error> {
+        Secp256k1::verification_only().verify_ecdsa(
+            &Message::from_digest(self.terms_hash().to_byte_array()),
+            &self.offer_sig,
+            &self.fidelity.bond.pubkey.inner,
+        )
+    }
+
+    /// Verifies the fidelity proof of the offer, short of looking the bond up on chain: the bond
+    /// key certified `maker_address`, and signed the offer.
+    pub fn verify_fidelity_cert(&self, maker_address: &str) -> Result<(), secp256k1::Error> {
+        let bond = &self.fidelity.bond;
+        Secp256k1::verification_only().verify_ecdsa(
+            &Message::from_digest(bond.generate_cert_hash(maker_address).to_byte_array()),
+            &self.fidelity.cert_sig,
+            &bond.pubkey.inner,
+        )?;
+        self.verify_signature()
+    }
 }
 
 /// Contract Tx signatures provided by a Sender of a Coinswap.
@@ -289,6 +496,10 @@ pub enum MakerToTakerMessage {
     MakerHello(MakerHello),
     /// Send the Maker's offer advertisement.
     RespOffer(Box<Offer>), // Add box as Offer has large size due to fidelity bond
+    /// Accept or counter the Taker's fee rate proposal.
+    RespFeeNegotiation(FeeNegotiationResponse),
+    /// Send the Taker's funding transactions back, joined and signed by the Maker.
+    RespCollabFunding(CollabFunding),
     /// Send Contract Sigs **for** the Sender side of the hop. The Maker sending this message is the Receiver of the hop.
     RespContractSigsForSender(ContractSigsForSender),
     /// Request Contract Sigs, **as** both the Sending and Receiving side of the hop.
@@ -304,6 +515,8 @@ impl Display for MakerToTakerMessage {
         match self {
             Self::MakerHello(_) => write!(f, "MakerHello"),
             Self::RespOffer(_) => write!(f, "RespOffer"),
+            Self::RespFeeNegotiation(_) => write!(f, "RespFeeNegotiation"),
+            Self::RespCollabFunding(_) => write!(f, "RespCollabFunding"),
             Self::RespContractSigsForSender(_) => write!(f, "RespContractSigsForSender"),
             Self::ReqContractSigsAsRecvrAndSender(_) => {
                 write!(f, "ReqContractSigsAsRecvrAndSender")
@@ -315,3 +528,115 @@ impl Display for MakerToTakerMessage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_protocol_version() {
+        assert_eq!(negotiate_protocol_version((1, 1), (1, 1)), Some(1));
+        assert_eq!(negotiate_protocol_version((1, 2), (1, 1)), Some(1));
+        assert_eq!(negotiate_protocol_version((1, 2), (1, 100)), Some(2));
+        assert_eq!(negotiate_protocol_version((1, 1), (2, 2)), None);
+        assert_eq!(negotiate_protocol_version((2, 2), (1, 1)), None);
+
+        // Makers speaking up to fee negotiation negotiate fees, without collaborative funding.
+        assert_eq!(
+            negotiate_protocol_version(
+                SUPPORTED_PROTOCOL_VERSIONS,
+                (PROTOCOL_VERSION_P2WSH, PROTOCOL_VERSION_FEE_NEGOTIATION)
+            ),
+            Some(PROTOCOL_VERSION_FEE_NEGOTIATION)
+        );
+
+        // Makers speaking only P2WSH still swap, without fee negotiation nor collaborative
+        // funding.
+        assert_eq!(
+            negotiate_protocol_version(
+                SUPPORTED_PROTOCOL_VERSIONS,
+                (PROTOCOL_VERSION_P2WSH, PROTOCOL_VERSION_P2WSH)
+            ),
+            Some(PROTOCOL_VERSION_P2WSH)
+        );
+        assert_eq!(
+            negotiate_protocol_version(SUPPORTED_PROTOCOL_VERSIONS, SUPPORTED_PROTOCOL_VERSIONS),
+            Some(PROTOCOL_VERSION_COLLAB_FUNDING)
+        );
+    }
+
+    #[test]
+    fn test_fee_negotiation() {
+        let rate = FeeRate::from_sat_per_vb_u32;
+        let negotiation = FeeNegotiation {
+            proposed: rate(5),
+            min_accept: rate(2),
+            max_accept: rate(10),
+        };
+
+        // Within the maker's bounds.
+        let response = negotiation.respond(rate(1), rate(5));
+        assert_eq!(response, FeeNegotiationResponse::Accept);
+        assert_eq!(negotiation.agreed_fee_rate(&response), Some(rate(5)));
+
+        // The maker counters with the closest rate it pays.
+        let response = negotiation.respond(rate(8), rate(20));
+        assert_eq!(response, FeeNegotiationResponse::Counter(rate(8)));
+        assert_eq!(negotiation.agreed_fee_rate(&response), Some(rate(8)));
+        let response = negotiation.respond(rate(1), rate(3));
+        assert_eq!(response, FeeNegotiationResponse::Counter(rate(3)));
+        assert_eq!(negotiation.agreed_fee_rate(&response), Some(rate(3)));
+
+        // No overlap.
+        let response = negotiation.respond(rate(11), rate(20));
+        assert_eq!(response, FeeNegotiationResponse::Reject);
+        assert_eq!(negotiation.agreed_fee_rate(&response), None);
+
+        // A counter outside the taker's bounds isn't agreed on.
+        assert_eq!(
+            negotiation.agreed_fee_rate(&FeeNegotiationResponse::Counter(rate(11))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_req_contract_sigs_hashlock() {
+        let preimage = [7u8; 32];
+        for hashlock_type in [HashlockType::Hash160, HashlockType::Sha256] {
+            let hashlock = Hashlock::new(hashlock_type, &preimage);
+            let req = ReqContractSigsForSender::new(Vec::new(), hashlock, 100);
+            assert_eq!(req.hashvalue, Hash160::hash(&preimage));
+            assert_eq!(req.hashlock().unwrap(), hashlock);
+        }
+
+        // The SHA256 hash of another preimage.
+        let mut req = ReqContractSigsForSender::new(
+            Vec::new(),
+            Hashlock::new(HashlockType::Sha256, &preimage),
+            100,
+        );
+        req.sha256_hashvalue = Some(sha256::Hash::hash(&[8u8; 32]));
+        assert!(req.hashlock().is_err());
+        req.sha256_hashvalue = None;
+        assert!(req.hashlock().is_err());
+
+        // Sent by a Taker predating the hashlock type.
+        #[derive(Serialize)]
+        struct OldReqContractSigsForSender {
+            txs_info: Vec<ContractTxInfoForSender>,
+            hashvalue: Hash160,
+            locktime: u16,
+        }
+        let old_req = serde_cbor::to_vec(&OldReqContractSigsForSender {
+            txs_info: Vec::new(),
+            hashvalue: Hash160::hash(&preimage),
+            locktime: 100,
+        })
+        .unwrap();
+        let req: ReqContractSigsForSender = serde_cbor::from_slice(&old_req).unwrap();
+        assert_eq!(
+            req.hashlock().unwrap(),
+            Hashlock::Hash160(Hash160::hash(&preimage))
+        );
+    }
+}
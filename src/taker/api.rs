@@ -13,44 +13,53 @@ use std::{
     fs,
     io::{self, Read},
     net::TcpStream,
-    path::{Path, PathBuf},
+    path::PathBuf,
     thread::{self, sleep},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use bip39::Mnemonic;
 use bitcoind::bitcoincore_rpc::RpcApi;
-use socks::Socks5Stream;
+use serde::{Deserialize, Serialize};
 
 use bitcoin::{
-    consensus::encode::deserialize,
-    hashes::{hash160::Hash as Hash160, Hash},
     secp256k1::{
         rand::{rngs::OsRng, RngCore},
-        SecretKey,
+        Secp256k1, SecretKey,
     },
-    Amount, BlockHash, OutPoint, PublicKey, ScriptBuf, Transaction, Txid,
+    Amount, BlockHash, FeeRate, OutPoint, PublicKey, ScriptBuf, Transaction, TxOut, Txid,
 };
 
 use super::{
     error::TakerError,
+    maker_selection::{filter_by_fee_limit, MakerInfo, MakerSelector, RandomizedWeighted},
     offers::{fetch_addresses_from_dns, fetch_offer_from_makers, MakerAddress, OfferAndAddress},
+    reputation::{ReputationStats, ReputationStore},
     routines::*,
+    split::{split_amount, SwapResult},
+    swap_cost::{estimate_swap_cost, min_economical_amount, SwapCostBreakdown},
+    swap_state::{can_reveal_preimage, SwapPhase, SwapState},
 };
 use crate::{
     error::{NetError, ProtocolError},
     protocol::{
+        contract::{
+            calculate_coinswap_fee, create_senders_contract_tx, funding_tx_fee,
+            funding_tx_fee_rate, read_hashvalue_from_contract, Hashlock,
+        },
         error::ContractError,
         messages::{
-            ContractSigsAsRecvrAndSender, ContractSigsForRecvr, ContractSigsForRecvrAndSender,
-            ContractSigsForSender, FundingTxInfo, MultisigPrivkey, Preimage, PrivKeyHandover,
-            TakerToMakerMessage,
+            CollabFunding, ContractSigsAsRecvrAndSender, ContractSigsForRecvr,
+            ContractSigsForRecvrAndSender, ContractSigsForSender, FeeNegotiation, FundingTxInfo,
+            MultisigPrivkey, Preimage, PrivKeyHandover, TakerToMakerMessage,
+            PROTOCOL_VERSION_COLLAB_FUNDING,
         },
     },
     taker::{config::TakerConfig, offers::OfferBook},
     utill::*,
     wallet::{
-        IncomingSwapCoin, OutgoingSwapCoin, RPCConfig, SwapCoin, Wallet, WalletSwapCoin,
+        ExposureKind, ExposureReport, IncomingSwapCoin, OutgoingSwapCoin, RPCConfig, SwapCoin,
+        SwapOutcome, SwapRecord, UtxoExposure, Wallet, WalletError, WalletSwapCoin,
         WatchOnlySwapCoin,
     },
 };
@@ -59,7 +68,7 @@ use crate::{
 /// SwapParams govern the criteria to find suitable set of makers from the offerbook.
 ///
 /// If no maker matches with a given SwapParam, that coinswap round will fail.
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct SwapParams {
     /// Total Amount to Swap.
     pub send_amount: Amount,
@@ -110,6 +119,17 @@ struct OngoingSwapState {
     pub active_preimage: Preimage,
     /// Enum defining the position of the Taker at each steps of a multihop swap.
     pub taker_position: TakerPosition,
+    /// Refund locktime of each hop, from the Taker's funding to the hop paying back the Taker.
+    pub hop_locktimes: Vec<u16>,
+    /// Phase of the swap round, as last saved.
+    pub phase: Option<SwapPhase>,
+    /// Number of makers that got the preimage and handed over their keys.
+    pub settled_hops: usize,
+    /// The only utxos the funding txs may spend, see [Taker::swap_from_utxos].
+    pub funding_utxos: Option<Vec<OutPoint>>,
+    /// Addresses of the makers of a swap resumed from its [SwapState], whose `peer_infos` are
+    /// lost with the restart.
+    pub resumed_route: Vec<String>,
 }
 
 /// Information for the next maker in the hop.
@@ -120,6 +140,11 @@ struct NextPeerInfo {
     multisig_nonces: Vec<SecretKey>,
     hashlock_nonces: Vec<SecretKey>,
     contract_reedemscripts: Vec<ScriptBuf>,
+    /// Miner fee of each transaction the peer funds, as agreed in the fee negotiation.
+    fee_rate: Amount,
+    /// Swap key indices of the multisig and hashlock keys, when the peer is the Taker itself and
+    /// the "nonces" are its keys. Empty for the Makers.
+    my_key_indices: Vec<(u32, u32)>,
 }
 
 /// Enum representing different behaviors of the Taker in a coinswap protocol.
@@ -132,6 +157,8 @@ pub enum TakerBehavior {
     DropConnectionAfterFullSetup,
     /// Behavior to broadcast the contract after the full coinswap setup.
     BroadcastContractAfterFullSetup,
+    /// This depicts the behavior when the taker drops connections once settling started.
+    DropConnectionWhileSettling,
 }
 
 /// The Taker structure that performs bulk of the coinswap protocol. Taker connects
@@ -144,6 +171,11 @@ pub struct Taker {
     offerbook: OfferBook,
     ongoing_swap_state: OngoingSwapState,
     behavior: TakerBehavior,
+    maker_selector: Box<dyn MakerSelector + Send + Sync>,
+    /// Where the [SwapState] of the round in progress is saved.
+    swap_state_path: PathBuf,
+    shutdown_handle: ShutdownHandle,
+    reputation: ReputationStore,
 }
 
 impl Taker {
@@ -154,7 +186,7 @@ impl Taker {
     /// The `data_dir` and `wallet_name_path` can be selectively provided to perform wallet load/creation.
     ///
     /// data_dir: Some(value) = Create data directory at given value.
-    /// data_dir: None = Create default data directory. For linux "~/.coinswap"
+    /// data_dir: None = Use the `data_dir` of the config at the default data directory. For linux "~/.coinswap/taker"
     /// wallet_file_name: Some(value) = Try loading wallet file with name "value". If doesn't exist create a new wallet with the given name.
     /// wallet_file_name: None = Create a new default wallet file. Ex: "9d317f933-taker".
     ///
@@ -175,9 +207,21 @@ impl Taker {
             TakerBehavior::Normal
         };
 
-        // Get provided data directory or the default data directory.
-        let data_dir = data_dir.unwrap_or(get_taker_dir());
-        let wallets_dir = data_dir.join("wallets");
+        // The config is in the provided data directory or the default data directory.
+        // If config file doesn't exist, default config will be loaded.
+        let mut config = TakerConfig::new(Some(
+            &data_dir
+                .clone()
+                .unwrap_or(get_taker_dir())
+                .join("config.toml"),
+        ))?;
+
+        // The provided data directory overrides the configured one.
+        if let Some(data_dir) = data_dir {
+            config.data_dir = data_dir;
+        }
+
+        let wallets_dir = config.data_dir.join("wallets");
 
         let mut rpc_config = rpc_config.unwrap_or_default();
 
@@ -215,9 +259,6 @@ impl Taker {
             wallet
         };
 
-        // If config file doesn't exist, default config will be loaded.
-        let mut config = TakerConfig::new(Some(&data_dir.join("config.toml")))?;
-
         if let Some(connection_type) = connection_type {
             config.connection_type = connection_type;
         }
@@ -226,15 +267,57 @@ impl Taker {
         wallet.sync()?;
         log::info!("Completed wallet sync");
 
+        let swap_state_path =
+            SwapState::path_for_wallet(&wallets_dir.join(&rpc_config.wallet_name));
+        if swap_state_path.exists() {
+            log::warn!(
+                "Found an unfinished swap at {:?}. Call Taker::resume to recover it.",
+                swap_state_path
+            );
+        }
+
+        let reputation = ReputationStore::load(ReputationStore::path_for_wallet(
+            &wallets_dir.join(&rpc_config.wallet_name),
+        ))?;
+
         Ok(Self {
             wallet,
             config,
             offerbook: OfferBook::default(),
             ongoing_swap_state: OngoingSwapState::default(),
             behavior,
+            maker_selector: Box::new(RandomizedWeighted),
+            swap_state_path,
+            shutdown_handle: ShutdownHandle::default(),
+            reputation,
         })
     }
 
+    /// Requests a shutdown.
+    ///
+    /// No new swap round is started. A [ShutdownMode::Graceful] shutdown lets the round in
+    /// progress finish. A [ShutdownMode::Immediate] one stops it at the next checkpoint with
+    /// [TakerError::ShuttingDown], its [SwapState] saved for [Taker::resume].
+    pub fn shutdown(&self, mode: ShutdownMode) {
+        self.shutdown_handle.shutdown(mode);
+    }
+
+    /// A handle requesting the shutdown from another thread, while a swap round runs.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown_handle.clone()
+    }
+
+    /// Swap outcomes of the maker with the fidelity bond at `bond_outpoint`, as recorded by this
+    /// wallet. Makers below [TakerConfig::min_maker_success_rate] are avoided.
+    pub fn maker_reputation(&self, bond_outpoint: &OutPoint) -> ReputationStats {
+        self.reputation.stats(bond_outpoint)
+    }
+
+    /// Sets the policy picking the makers of the swap routes. Defaults to [RandomizedWeighted].
+    pub fn set_maker_selector(&mut self, selector: impl MakerSelector + Send + Sync + 'static) {
+        self.maker_selector = Box::new(selector);
+    }
+
     pub fn get_wallet(&self) -> &Wallet {
         &self.wallet
     }
@@ -243,8 +326,34 @@ impl Taker {
         &mut self.wallet
     }
 
+    /// Estimate what swapping `amount` through `route` at `fee_rate` costs, before committing to
+    /// it. See [SwapCostBreakdown].
+    pub fn estimate_swap_cost(
+        &self,
+        amount: Amount,
+        route: &[MakerInfo],
+        fee_rate: FeeRate,
+    ) -> Result<SwapCostBreakdown, TakerError> {
+        estimate_swap_cost(amount, route, fee_rate, &self.config.timelock_policy)
+    }
+
+    /// Whether swapping `amount` through `route` at `fee_rate` is worth it: the amount is at least
+    /// the [min_economical_amount] under [TakerConfig::max_tx_fee_ppm], and covers the fees and
+    /// the minimums of the makers.
+    pub fn is_swap_economical(
+        &self,
+        amount: Amount,
+        route: &[MakerInfo],
+        fee_rate: FeeRate,
+    ) -> bool {
+        min_economical_amount(route.len(), fee_rate, self.config.max_tx_fee_ppm)
+            .is_ok_and(|minimum| amount >= minimum)
+            && estimate_swap_cost(amount, route, fee_rate, &self.config.timelock_policy).is_ok()
+    }
+
     pub fn do_coinswap(&mut self, swap_params: SwapParams) -> Result<(), TakerError> {
-        let tor_log_dir = "/tmp/tor-rust-taker/log".to_string();
+        let tor_dir = self.config.data_dir.join("tor");
+        let tor_log_dir = tor_dir.join("log");
 
         let taker_port = self.config.port;
 
@@ -254,10 +363,10 @@ impl Taker {
             ConnectionType::CLEARNET => {}
             ConnectionType::TOR => {
                 if cfg!(feature = "tor") {
-                    let taker_socks_port = self.config.socks_port;
+                    let taker_socks_port = self.config.tor_config.proxy.port();
 
-                    if Path::new(tor_log_dir.as_str()).exists() {
-                        match fs::remove_file(Path::new(tor_log_dir.clone().as_str())) {
+                    if tor_log_dir.exists() {
+                        match fs::remove_file(&tor_log_dir) {
                             Ok(_) => log::info!("Previous taker log file deleted successfully"),
                             Err(_) => log::error!("Error deleting taker log file "),
                         }
@@ -266,13 +375,12 @@ impl Taker {
                     handle = Some(crate::tor::spawn_tor(
                         taker_socks_port,
                         taker_port,
-                        "/tmp/tor-rust-taker".to_string(),
+                        tor_dir.display().to_string(),
                     ));
 
                     thread::sleep(Duration::from_secs(10));
 
-                    if let Err(e) = monitor_log_for_completion(&PathBuf::from(tor_log_dir), "100%")
-                    {
+                    if let Err(e) = monitor_log_for_completion(&tor_log_dir, "100%") {
                         log::error!("Error monitoring taker log file: {}", e);
                     }
 
@@ -288,6 +396,97 @@ impl Taker {
         Ok(())
     }
 
+    /// Swaps `total` in `num_splits` swaps of about equal amounts, each through a route of
+    /// `swap_params.maker_count` makers. The `send_amount` of `swap_params` is ignored.
+    ///
+    /// The swaps run one after the other. The makers of a swap aren't picked again for the next
+    /// ones, so the routes share no maker, and the offerbook needs enough of them. A failed swap
+    /// doesn't stop the next ones, the outcome of each is in its [SwapResult].
+    ///
+    /// Errors before any swap if the splits are below the [min_economical_amount].
+    pub fn swap_split(
+        &mut self,
+        total: Amount,
+        num_splits: u32,
+        swap_params: SwapParams,
+    ) -> Result<Vec<SwapResult>, TakerError> {
+        let amounts = split_amount(total, num_splits);
+        // The last split is the smallest.
+        if let Some(&smallest) = amounts.last() {
+            let minimum = min_economical_amount(
+                swap_params.maker_count,
+                funding_tx_fee_rate(swap_params.fee_rate)
+                    .ok_or(ProtocolError::FeeRateOutOfBounds(FeeRate::MAX))?,
+                self.config.max_tx_fee_ppm,
+            )?;
+            if smallest < minimum {
+                return Err(TakerError::UneconomicalSwap {
+                    amount: smallest,
+                    minimum,
+                });
+            }
+        }
+
+        let mut results = Vec::with_capacity(amounts.len());
+        for (split, amount) in amounts.into_iter().enumerate() {
+            log::info!("Starting swap {}/{} of {}", split + 1, num_splits, amount);
+            let result = self.run_swap(SwapParams {
+                send_amount: amount,
+                ..swap_params
+            });
+            if let Some(e) = &result.error {
+                log::error!("Swap {}/{} failed: {:?}", split + 1, num_splits, e);
+            }
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    /// Swaps `swap_params.send_amount` funded only from `utxos`, bypassing coin selection. Their
+    /// change comes back to the wallet.
+    ///
+    /// Errors before contacting any maker with [WalletError::UtxoNotSpendable] if one of them
+    /// isn't a spendable coin of the wallet, or with [WalletError::InsufficientFund] if they
+    /// don't cover the amount. The outcome of the swap itself is in the [SwapResult].
+    pub fn swap_from_utxos(
+        &mut self,
+        utxos: Vec<OutPoint>,
+        swap_params: SwapParams,
+    ) -> Result<SwapResult, TakerError> {
+        let available = self
+            .wallet
+            .select_utxos(&utxos)?
+            .iter()
+            .map(|(utxo, _)| utxo.amount)
+            .sum::<Amount>();
+        if available < swap_params.send_amount {
+            return Err(WalletError::InsufficientFund {
+                available: available.to_sat(),
+                required: swap_params.send_amount.to_sat(),
+            }
+            .into());
+        }
+
+        self.ongoing_swap_state.funding_utxos = Some(utxos);
+        let result = self.run_swap(swap_params);
+        self.ongoing_swap_state.funding_utxos = None;
+        Ok(result)
+    }
+
+    /// Runs a swap round, and looks up its record in the swap history.
+    fn run_swap(&mut self, swap_params: SwapParams) -> SwapResult {
+        // The swap's record is the one appended to the history, if any.
+        let recorded = self.wallet.swap_history().ok().map(|history| history.len());
+        let error = self.send_coinswap(swap_params).err();
+        let record = recorded
+            .and_then(|recorded| self.wallet.swap_history().ok()?.into_iter().nth(recorded));
+        SwapResult {
+            amount: swap_params.send_amount,
+            record,
+            error,
+        }
+    }
+
     /// Perform a coinswap round with given [SwapParams]. The Taker will try to perform swap with makers
     /// in it's [OfferBook] sequentially as per the maker_count given in swap params.
     /// If [SwapParams] doesn't fit suitably with any available offers, or not enough makers
@@ -298,6 +497,29 @@ impl Taker {
     ///
     /// If that fails too. Open an issue at [our github](https://github.com/citadel-tech/coinswap/issues)
     pub fn send_coinswap(&mut self, swap_params: SwapParams) -> Result<(), TakerError> {
+        if self.shutdown_handle.requested().is_some() {
+            return Err(TakerError::ShuttingDown);
+        }
+
+        // Refuse swaps the miner fees would eat up, before contacting anyone.
+        let minimum = min_economical_amount(
+            swap_params.maker_count,
+            funding_tx_fee_rate(swap_params.fee_rate)
+                .ok_or(ProtocolError::FeeRateOutOfBounds(FeeRate::MAX))?,
+            self.config.max_tx_fee_ppm,
+        )?;
+        if swap_params.send_amount < minimum {
+            return Err(TakerError::UneconomicalSwap {
+                amount: swap_params.send_amount,
+                minimum,
+            });
+        }
+
+        let hop_locktimes = self
+            .config
+            .timelock_policy
+            .hop_locktimes(swap_params.maker_count)?;
+
         log::info!("Syncing Offerbook");
         let config = self.config.clone();
         self.sync_offerbook(&config, swap_params.maker_count)?;
@@ -308,6 +530,12 @@ impl Taker {
 
         self.ongoing_swap_state.active_preimage = preimage;
         self.ongoing_swap_state.swap_params = swap_params;
+        self.ongoing_swap_state.hop_locktimes = hop_locktimes;
+
+        // The offerbook sync takes a while. Nothing is at stake yet.
+        if self.shutdown_handle.requested().is_some() {
+            return Err(TakerError::ShuttingDown);
+        }
 
         // Try first hop. Abort if error happens.
         if let Err(e) = self.init_first_hop() {
@@ -318,6 +546,7 @@ impl Taker {
 
         // Iterate until `maker_count` numbers of Makers are found and initiate swap between them sequentially.
         for maker_index in 0..self.ongoing_swap_state.swap_params.maker_count {
+            self.check_immediate_shutdown()?;
             if maker_index == 0 {
                 self.ongoing_swap_state.taker_position = TakerPosition::FirstPeer;
             } else if maker_index == self.ongoing_swap_state.swap_params.maker_count - 1 {
@@ -326,10 +555,8 @@ impl Taker {
                 self.ongoing_swap_state.taker_position = TakerPosition::WatchOnly;
             }
 
-            // Refund lock time decreases by `refund_locktime_step` for each hop.
-            let maker_refund_locktime = self.config.refund_locktime
-                + self.config.refund_locktime_step
-                    * (self.ongoing_swap_state.swap_params.maker_count - maker_index - 1) as u16;
+            // Refund lock time decreases by `per_hop_increment` for each hop.
+            let maker_refund_locktime = self.ongoing_swap_state.hop_locktimes[maker_index + 1];
 
             let funding_tx_infos = self.funding_info_for_next_maker();
 
@@ -372,13 +599,17 @@ impl Taker {
             // For the timeout case also ban the Peer.
             let txids_to_watch = funding_outpoints.iter().map(|op| op.txid).collect();
             match self.watch_for_txs(&txids_to_watch) {
-                Ok(r) => self.ongoing_swap_state.funding_txs.push(r),
+                Ok(r) => {
+                    self.ongoing_swap_state.funding_txs.push(r);
+                    self.save_swap_state(SwapPhase::Funding)?;
+                }
                 Err(e) => {
                     log::error!("Error: {:?}", e);
                     log::warn!("Starting recovery from existing swap");
                     if let TakerError::FundingTxWaitTimeOut = e {
-                        let bad_maker = &self.ongoing_swap_state.peer_infos[maker_index].peer;
-                        self.offerbook.add_bad_maker(bad_maker);
+                        let bad_maker =
+                            self.ongoing_swap_state.peer_infos[maker_index].peer.clone();
+                        self.ban_maker(&bad_maker);
                     }
                     self.recover_from_swap()?;
                     return Ok(());
@@ -402,6 +633,7 @@ impl Taker {
                 }
             }
         } // Contract establishment completed.
+        self.save_swap_state(SwapPhase::ContractsEstablished)?;
 
         if self.behavior == TakerBehavior::DropConnectionAfterFullSetup {
             log::error!("Dropping Swap Process after full setup");
@@ -414,18 +646,59 @@ impl Taker {
             return Ok(());
         }
 
+        self.check_immediate_shutdown()?;
+        self.save_swap_state(SwapPhase::Settling)?;
+
+        if self.behavior == TakerBehavior::DropConnectionWhileSettling {
+            log::error!("Dropping Swap Process while settling");
+            return Ok(());
+        }
+
         match self.settle_all_swaps() {
             Ok(_) => (),
             Err(e) => {
                 log::error!("Swap Settlement Failed : {:?}", e);
-                log::warn!("Starting recovery from existing swap");
-                self.recover_from_swap()?;
+                log::warn!("Claiming the incoming swapcoins through the hashlock");
+                // The preimage may be out already, the timelock refunds would race the makers.
+                if let Some(state) = SwapState::read_from_disk(&self.swap_state_path)? {
+                    self.claim_settling_swap(&state)?;
+                }
+                self.clear_ongoing_swaps();
                 return Ok(());
             }
         }
 
+        let route = self
+            .ongoing_swap_state
+            .peer_infos
+            .iter()
+            .take(self.ongoing_swap_state.swap_params.maker_count)
+            .map(|pi| pi.peer.clone())
+            .collect::<Vec<_>>();
+        for maker in &route {
+            self.record_maker_reputation(maker, true);
+        }
+
         log::info!("Initializing Sync and Save.");
         self.wallet.sync()?;
+        let sent = self
+            .ongoing_swap_state
+            .outgoing_swapcoins
+            .iter()
+            .map(|sc| sc.funding_amount)
+            .sum::<Amount>();
+        let received = self
+            .ongoing_swap_state
+            .incoming_swapcoins
+            .iter()
+            .map(|sc| sc.funding_amount)
+            .sum::<Amount>();
+        self.record_swap_round(
+            self.get_hashlock().hashvalue().to_string(),
+            self.ongoing_swap_state.swap_params.send_amount,
+            sent.checked_sub(received).unwrap_or(Amount::ZERO),
+            SwapOutcome::Completed,
+        );
         self.save_and_reset_swap_round()?;
         log::info!("Completed Sync and Save.");
         log::info!("Successfully Completed Coinswap.");
@@ -443,9 +716,7 @@ impl Taker {
         self.ongoing_swap_state.taker_position = TakerPosition::FirstPeer;
 
         // Locktime to be used for this swap.
-        let swap_locktime = self.config.refund_locktime
-            + self.config.refund_locktime_step
-                * self.ongoing_swap_state.swap_params.maker_count as u16;
+        let swap_locktime = self.ongoing_swap_state.hop_locktimes[0];
 
         // Loop until we find a live maker who responded to our signature request.
         let (maker, funding_txs) = loop {
@@ -456,7 +727,19 @@ impl Taker {
                 log::error!("Not enough makers to satisfy swap requirements.");
                 return Err(TakerError::NotEnoughMakersInOfferBook);
             }
-            let maker = self.choose_next_maker()?.clone();
+            let maker = self.choose_next_maker()?;
+            let maker_fee_rate = match self.negotiate_fee_rate(&maker) {
+                Ok(fee_rate) => fee_rate,
+                Err(e) => {
+                    self.ban_maker(&maker);
+                    log::error!(
+                        "Failed to agree on a fee rate with first_maker {}: {:?}",
+                        maker.address,
+                        e
+                    );
+                    continue;
+                }
+            };
             let (multisig_pubkeys, multisig_nonces, hashlock_pubkeys, hashlock_nonces) =
                 generate_maker_keys(
                     &maker.offer.tweakable_point,
@@ -467,28 +750,48 @@ impl Taker {
                     self.ongoing_swap_state.swap_params.send_amount,
                     &multisig_pubkeys,
                     &hashlock_pubkeys,
-                    self.get_preimage_hash(),
+                    self.get_hashlock(),
                     swap_locktime,
                     self.ongoing_swap_state.swap_params.fee_rate,
+                    self.config.output_shaping,
+                    self.ongoing_swap_state.funding_utxos.as_deref(),
                 )?;
+            let (funding_txs, joined_contract_sigs) = if self.config.collaborative_funding {
+                self.collab_funding(
+                    &maker,
+                    funding_txs,
+                    &mut outgoing_swapcoins,
+                    &multisig_nonces,
+                    &hashlock_nonces,
+                    swap_locktime,
+                )
+            } else {
+                (funding_txs, None)
+            };
 
             let contract_reedemscripts = outgoing_swapcoins
                 .iter()
                 .map(|swapcoin| swapcoin.contract_redeemscript.clone())
                 .collect();
 
-            // Request for Sender's Signatures
-            let contract_sigs = match self.req_sigs_for_sender(
-                &maker.address,
-                &outgoing_swapcoins,
-                &multisig_nonces,
-                &hashlock_nonces,
-                swap_locktime,
+            // Request for Sender's Signatures, unless the maker signed the contracts of the
+            // joined funding txs already.
+            let contract_sigs = match joined_contract_sigs.map_or_else(
+                || {
+                    self.req_sigs_for_sender(
+                        &maker.address,
+                        &outgoing_swapcoins,
+                        &multisig_nonces,
+                        &hashlock_nonces,
+                        swap_locktime,
+                    )
+                },
+                Ok,
             ) {
                 Ok(contract_sigs) => contract_sigs,
                 Err(e) => {
                     // Bad maker, mark it, and try next one.
-                    self.offerbook.add_bad_maker(&maker);
+                    self.ban_maker(&maker);
                     log::error!(
                         "Failed to obtain sender's contract signatures from first_maker {}: {:?}",
                         maker.address,
@@ -506,6 +809,8 @@ impl Taker {
                 multisig_nonces,
                 hashlock_nonces,
                 contract_reedemscripts,
+                fee_rate: maker_fee_rate,
+                my_key_indices: Vec::new(),
             });
 
             contract_sigs
@@ -533,16 +838,14 @@ impl Taker {
             self.ongoing_swap_state.outgoing_swapcoins
         );
 
-        // Broadcast and wait for funding txs to confirm
-        let funding_txids = funding_txs
-            .iter()
-            .map(|tx| {
-                let txid = self.wallet.rpc.send_raw_transaction(tx)?;
-                log::info!("Funding Txid: {}", txid);
-                assert_eq!(txid, tx.compute_txid());
-                Ok(txid)
-            })
-            .collect::<Result<_, TakerError>>()?;
+        // Saved before the broadcast, a crash right after it must not go unnoticed.
+        self.save_swap_state(SwapPhase::Funding)?;
+
+        // Broadcast and wait for funding txs to confirm. None goes out if the mempool rejects one.
+        let funding_txids = self.wallet.broadcast_all_checked(&funding_txs)?;
+        for txid in &funding_txids {
+            log::info!("Funding Txid: {}", txid);
+        }
 
         // Watch for the funding transactions to be confirmed.
         // This errors in two cases.
@@ -557,7 +860,7 @@ impl Taker {
             Err(e) => {
                 log::error!("Error: {:?}", e);
                 if let TakerError::ContractsBroadcasted(_) = e {
-                    self.offerbook.add_bad_maker(&maker);
+                    self.ban_maker(&maker);
                 }
                 return Err(e);
             }
@@ -576,7 +879,7 @@ impl Taker {
         let mut txid_tx_map = HashMap::<Txid, Transaction>::new();
         let mut txid_blockhash_map = HashMap::<Txid, BlockHash>::new();
 
-        // Required confirmation target for the funding txs.
+        // Required confirmation target for the funding txs, at least the configured minimum.
         let required_confirmations =
             if self.ongoing_swap_state.taker_position == TakerPosition::LastPeer {
                 self.ongoing_swap_state.swap_params.required_confirms
@@ -588,7 +891,8 @@ impl Taker {
                     .peer
                     .offer
                     .required_confirms
-            };
+            }
+            .max(self.config.min_confirmations as u64);
         log::info!(
             "Waiting for funding transaction confirmations ({} conf required)",
             required_confirmations
@@ -621,10 +925,10 @@ impl Taker {
                 if txid_tx_map.contains_key(txid) {
                     continue;
                 }
-                let gettx = match self.wallet.rpc.get_raw_transaction_info(txid, None) {
-                    Ok(r) => r,
+                let (tx, conf_height) = match self.wallet.backend.get_transaction(txid) {
+                    Ok(Some(r)) => r,
                     // Transaction haven't arrived in our mempool, keep looping.
-                    Err(_e) => {
+                    _ => {
                         let elapsed = start_time.elapsed().as_secs();
                         log::info!("Waiting for mempool transaction for {}secs", elapsed);
                         if elapsed > wait_time {
@@ -635,10 +939,19 @@ impl Taker {
                 };
                 if !txids_seen_once.contains(txid) {
                     txids_seen_once.insert(*txid);
-                    if gettx.confirmations.is_none() {
-                        let mempool_tx = match self.wallet.rpc.get_mempool_entry(txid) {
-                            Ok(m) => m,
+                    if conf_height.is_none() {
+                        // Only a core node tells the fee rate.
+                        let mempool_tx = match self
+                            .wallet
+                            .core_rpc()
+                            .map(|rpc| rpc.get_mempool_entry(txid))
+                        {
+                            Ok(Ok(m)) => m,
+                            Ok(Err(_e)) => {
+                                continue;
+                            }
                             Err(_e) => {
+                                log::info!("Tx {} Seen in Mempool", txid);
                                 continue;
                             }
                         };
@@ -650,9 +963,17 @@ impl Taker {
                     }
                 }
                 //TODO handle confirm<0
-                if gettx.confirmations >= Some(required_confirmations as u32) {
-                    txid_tx_map.insert(*txid, deserialize::<Transaction>(&gettx.hex).unwrap());
-                    txid_blockhash_map.insert(*txid, gettx.blockhash.unwrap());
+                // The merkle proof needs the block, so wait for one confirmation even if none
+                // is required.
+                let Some(conf_height) = conf_height else {
+                    continue;
+                };
+                let confirmations =
+                    (self.wallet.backend.get_block_count()? + 1).saturating_sub(conf_height);
+                if confirmations >= required_confirmations {
+                    txid_tx_map.insert(*txid, tx);
+                    txid_blockhash_map
+                        .insert(*txid, self.wallet.backend.get_block_hash(conf_height)?);
                     log::info!("Tx {} | Confirmed at {}", txid, required_confirmations);
                 }
             }
@@ -664,12 +985,12 @@ impl Taker {
                 let merkleproofs = funding_txids
                     .iter()
                     .map(|&txid| {
-                        self.wallet
-                            .rpc
-                            .get_tx_out_proof(&[txid], Some(txid_blockhash_map.get(&txid).unwrap()))
-                            .map(|gettxoutproof_result| to_hex(&gettxoutproof_result))
+                        Ok(to_hex(&self.wallet.core_rpc()?.get_tx_out_proof(
+                            &[txid],
+                            Some(txid_blockhash_map.get(&txid).unwrap()),
+                        )?))
                     })
-                    .collect::<Result<Vec<String>, _>>()?;
+                    .collect::<Result<Vec<String>, WalletError>>()?;
                 return Ok((txes, merkleproofs));
             }
             sleep(Duration::from_millis(1000));
@@ -821,7 +1142,7 @@ impl Taker {
                                 continue;
                             } else {
                                 log::warn!("Timeout Reattempt exceeded. Adding malicious Maker");
-                                self.offerbook.add_bad_maker(&maker_oa);
+                                self.ban_maker(&maker_oa);
                                 return Err(NetError::ConnectionTimedOut.into());
                             }
                         }
@@ -843,7 +1164,7 @@ impl Taker {
                             ));
                             continue;
                         } else {
-                            self.offerbook.add_bad_maker(&maker_oa);
+                            self.ban_maker(&maker_oa);
                             return Err(e);
                         }
                     }
@@ -858,25 +1179,26 @@ impl Taker {
         maker_refund_locktime: u16,
         funding_tx_infos: &[FundingTxInfo],
     ) -> Result<(NextPeerInfo, ContractSigsAsRecvrAndSender), TakerError> {
-        let this_maker = &self
+        let this_maker_info = self
             .ongoing_swap_state
             .peer_infos
             .last()
-            .expect("at least one active maker expected")
-            .peer;
+            .expect("at least one active maker expected");
+        let (this_maker, this_maker_fee_rate) =
+            (this_maker_info.peer.clone(), this_maker_info.fee_rate);
 
-        let previous_maker = self.ongoing_swap_state.peer_infos.iter().rev().nth(1);
+        // Cloned, as banning a failing next maker needs the Taker mutably.
+        let previous_maker = self
+            .ongoing_swap_state
+            .peer_infos
+            .iter()
+            .rev()
+            .nth(1)
+            .cloned();
 
         log::info!("Connecting to {}", this_maker.address);
         let address = this_maker.address.to_string();
-        let mut socket = match self.config.connection_type {
-            ConnectionType::CLEARNET => TcpStream::connect(address)?,
-            ConnectionType::TOR => Socks5Stream::connect(
-                format!("127.0.0.1:{}", self.config.socks_port).as_str(),
-                address.as_str(),
-            )?
-            .into_inner(),
-        };
+        let mut socket = connect_to_maker(&address, &self.config)?;
 
         let reconnect_timeout = Duration::from_secs(self.config.reconnect_attempt_timeout_sec);
 
@@ -885,6 +1207,22 @@ impl Taker {
 
         handshake_maker(&mut socket)?;
         let mut next_maker = this_maker.clone();
+        // The Taker doesn't fund anything as the last peer.
+        let mut next_maker_fee_rate = self.ongoing_swap_state.swap_params.fee_rate;
+        // As the last peer, the Taker receives with keys derived from its seed.
+        let (my_multisig_keys, my_hashlock_keys) =
+            if self.ongoing_swap_state.taker_position == TakerPosition::LastPeer {
+                let tx_count = self.ongoing_swap_state.swap_params.tx_count;
+                let wallet = &mut self.wallet;
+                let mut next_swap_keys = || {
+                    (0..tx_count)
+                        .map(|_| wallet.next_swap_key())
+                        .collect::<Result<Vec<_>, _>>()
+                };
+                (next_swap_keys()?, next_swap_keys()?)
+            } else {
+                (Vec::new(), Vec::new())
+            };
         let (
             next_peer_multisig_pubkeys,
             next_peer_multisig_keys_or_nonces,
@@ -901,14 +1239,15 @@ impl Taker {
                 next_peer_hashlock_pubkeys,
                 next_peer_hashlock_keys_or_nonces,
             ) = if self.ongoing_swap_state.taker_position == TakerPosition::LastPeer {
-                let (my_recv_ms_pubkeys, my_recv_ms_nonce): (Vec<_>, Vec<_>) =
-                    (0..self.ongoing_swap_state.swap_params.tx_count)
-                        .map(|_| generate_keypair())
-                        .unzip();
-                let (my_recv_hashlock_pubkeys, my_recv_hashlock_nonce): (Vec<_>, Vec<_>) = (0
-                    ..self.ongoing_swap_state.swap_params.tx_count)
-                    .map(|_| generate_keypair())
-                    .unzip();
+                let secp = Secp256k1::new();
+                let keypairs = |keys: &[(u32, SecretKey)]| -> (Vec<PublicKey>, Vec<SecretKey>) {
+                    keys.iter()
+                        .map(|(_, key)| (PublicKey::new(key.public_key(&secp)), *key))
+                        .unzip()
+                };
+                let (my_recv_ms_pubkeys, my_recv_ms_nonce) = keypairs(&my_multisig_keys);
+                let (my_recv_hashlock_pubkeys, my_recv_hashlock_nonce) =
+                    keypairs(&my_hashlock_keys);
                 (
                     my_recv_ms_pubkeys,
                     my_recv_ms_nonce,
@@ -916,7 +1255,19 @@ impl Taker {
                     my_recv_hashlock_nonce,
                 )
             } else {
-                next_maker = self.choose_next_maker()?.clone();
+                next_maker = self.choose_next_maker()?;
+                next_maker_fee_rate = match self.negotiate_fee_rate(&next_maker) {
+                    Ok(fee_rate) => fee_rate,
+                    Err(e) => {
+                        self.ban_maker(&next_maker);
+                        log::info!(
+                            "Failed to agree on a fee rate with next_maker {}, Banning Maker: {:?}",
+                            next_maker.address,
+                            e
+                        );
+                        continue;
+                    }
+                };
                 //next_maker is only ever accessed when the next peer is a maker, not a taker
                 //i.e. if its ever used when is_taker_next_peer == true, then thats a bug
                 generate_maker_keys(
@@ -956,7 +1307,7 @@ impl Taker {
                 next_peer_multisig_pubkeys: next_peer_multisig_pubkeys.clone(),
                 next_peer_hashlock_pubkeys: next_peer_hashlock_pubkeys.clone(),
                 next_maker_refund_locktime: maker_refund_locktime,
-                next_maker_fee_rate: self.ongoing_swap_state.swap_params.fee_rate,
+                next_maker_fee_rate: this_maker_fee_rate,
             };
 
             let this_maker_info = ThisMakerInfo {
@@ -969,7 +1320,7 @@ impl Taker {
                     &mut socket,
                     this_maker_info,
                     next_maker_info,
-                    self.get_preimage_hash(),
+                    self.get_hashlock(),
                 )?;
             log::info!(
                 "<=== Recieved ContractSigsAsRecvrAndSender from {}",
@@ -1019,7 +1370,7 @@ impl Taker {
                         r
                     }
                     Err(e) => {
-                        self.offerbook.add_bad_maker(&next_maker);
+                        self.ban_maker(&next_maker);
                         log::info!(
                             "Failed to obtain sender's contract tx signature from next_maker {}, Banning Maker: {:?}",
                             next_maker.address,
@@ -1081,7 +1432,7 @@ impl Taker {
                 Err(e) => {
                     log::error!("Could not get Receiver's signatures : {:?}", e);
                     log::warn!("Banning Maker : {}", previous_maker.peer.address);
-                    self.offerbook.add_bad_maker(&previous_maker.peer);
+                    self.ban_maker(&previous_maker.peer);
                     return Err(e);
                 }
             }
@@ -1106,6 +1457,14 @@ impl Taker {
             multisig_nonces: next_peer_multisig_keys_or_nonces,
             hashlock_nonces: next_peer_hashlock_keys_or_nonces,
             contract_reedemscripts: next_swap_contract_redeemscripts,
+            fee_rate: next_maker_fee_rate,
+            my_key_indices: my_multisig_keys
+                .iter()
+                .zip(my_hashlock_keys.iter())
+                .map(|(&(multisig_index, _), &(hashlock_index, _))| {
+                    (multisig_index, hashlock_index)
+                })
+                .collect(),
         };
         Ok((next_swap_info, contract_sigs_as_recvr_sender))
     }
@@ -1134,8 +1493,6 @@ impl Taker {
                 },
             )
             .collect::<Result<Vec<WatchOnlySwapCoin>, _>>()?;
-        //TODO error handle here the case where next_swapcoin.contract_tx script pubkey
-        // is not equal to p2wsh(next_swap_contract_redeemscripts)
         for swapcoin in &next_swapcoins {
             self.wallet
                 .import_watchonly_redeemscript(&swapcoin.get_multisig_redeemscript())?;
@@ -1156,6 +1513,16 @@ impl Taker {
             .last()
             .expect("funding transactions expected");
 
+        // The last maker funds the incoming swapcoins, at the fee rate it agreed on.
+        let last_maker_fee_rate = self
+            .ongoing_swap_state
+            .peer_infos
+            .iter()
+            .rev()
+            .nth(1)
+            .expect("last maker expected")
+            .fee_rate;
+
         let last_makers_funding_tx_values = funding_txs
             .iter()
             .zip(multisig_redeemscripts.iter())
@@ -1196,7 +1563,7 @@ impl Taker {
                         previous_funding_output,
                         maker_funding_tx_value,
                         next_contract_redeemscript,
-                        self.ongoing_swap_state.swap_params.fee_rate,
+                        last_maker_fee_rate,
                     )
                 },
             )
@@ -1209,26 +1576,29 @@ impl Taker {
             .last()
             .expect("next swap info expected");
         for (
+            index,
             (
                 (
                     (
                         (
                             (
                                 (
-                                    (multisig_redeemscript, &maker_funded_multisig_pubkey),
-                                    &maker_funded_multisig_privkey,
+                                    (
+                                        (multisig_redeemscript, &maker_funded_multisig_pubkey),
+                                        &maker_funded_multisig_privkey,
+                                    ),
+                                    my_receivers_contract_tx,
                                 ),
-                                my_receivers_contract_tx,
+                                next_contract_redeemscript,
                             ),
-                            next_contract_redeemscript,
+                            &hashlock_privkey,
                         ),
-                        &hashlock_privkey,
+                        &maker_funding_tx_value,
                     ),
-                    &maker_funding_tx_value,
+                    _,
                 ),
                 _,
             ),
-            _,
         ) in multisig_redeemscripts
             .iter()
             .zip(next_swap_info.multisig_pubkeys.iter())
@@ -1239,6 +1609,7 @@ impl Taker {
             .zip(last_makers_funding_tx_values.iter())
             .zip(funding_txs.iter())
             .zip(funding_txs_merkleproofs.iter())
+            .enumerate()
         {
             let (o_ms_pubkey1, o_ms_pubkey2) =
                 crate::protocol::contract::read_pubkeys_from_multisig_redeemscript(
@@ -1269,6 +1640,12 @@ impl Taker {
                 maker_funding_tx_value,
             );
             incoming_swapcoin.hash_preimage = Some(self.ongoing_swap_state.active_preimage);
+            if let Some(&(multisig_index, hashlock_index)) =
+                next_swap_info.my_key_indices.get(index)
+            {
+                incoming_swapcoin.my_key_index = Some(multisig_index);
+                incoming_swapcoin.hashlock_key_index = Some(hashlock_index);
+            }
             incoming_swapcoins.push(incoming_swapcoin);
         }
 
@@ -1304,7 +1681,7 @@ impl Taker {
             Ok(s) => s,
             Err(e) => {
                 log::warn!("Banning Maker : {}", last_maker.address);
-                self.offerbook.add_bad_maker(&last_maker);
+                self.ban_maker(&last_maker);
                 return Err(e);
             }
         };
@@ -1326,7 +1703,7 @@ impl Taker {
     }
 
     /// Request signatures for sender side of the swap.
-    /// Keep trying until `first_connect_attempts` limit, with time delay of `first_connect_sleep_delay_sec`.
+    /// Transport errors are retried on a new connection, following the `first_connect_retry` config.
     fn req_sigs_for_sender<S: SwapCoin>(
         &self,
         maker_address: &MakerAddress,
@@ -1336,74 +1713,30 @@ impl Taker {
         locktime: u16,
     ) -> Result<ContractSigsForSender, TakerError> {
         let reconnect_time_out = Duration::from_secs(self.config.first_connect_attempt_timeout_sec);
-        // Configurable reconnection attempts for testing
-        let first_connect_attempts = if cfg!(feature = "integration-test") {
-            10
-        } else {
-            self.config.first_connect_attempts
-        };
-
-        // Custom sleep delay for testing.
-        let sleep_delay = if cfg!(feature = "integration-test") {
-            1
-        } else {
-            self.config.first_connect_sleep_delay_sec
-        };
-
-        let mut ii = 0;
 
         let maker_addr_str = maker_address.to_string();
-        log::info!("Connecting to {}", maker_addr_str);
-        let mut socket = match self.config.connection_type {
-            ConnectionType::CLEARNET => TcpStream::connect(maker_addr_str.clone())?,
-            ConnectionType::TOR => Socks5Stream::connect(
-                format!("127.0.0.1:{}", self.config.socks_port).as_str(),
-                &*maker_addr_str,
-            )?
-            .into_inner(),
-        };
-
-        socket.set_read_timeout(Some(reconnect_time_out))?;
-        socket.set_write_timeout(Some(reconnect_time_out))?;
+        retry_with_backoff(
+            &self.config.first_connect_retry,
+            &format!(
+                "request signatures for sender from maker {}",
+                maker_addr_str
+            ),
+            || {
+                log::info!("Connecting to {}", maker_addr_str);
+                let mut socket = connect_to_maker(&maker_addr_str, &self.config)?;
 
-        loop {
-            ii += 1;
+                socket.set_read_timeout(Some(reconnect_time_out))?;
+                socket.set_write_timeout(Some(reconnect_time_out))?;
 
-            match req_sigs_for_sender_once(
-                &mut socket,
-                outgoing_swapcoins,
-                maker_multisig_nonces,
-                maker_hashlock_nonces,
-                locktime,
-            ) {
-                Ok(ret) => return Ok(ret),
-                Err(e) => {
-                    log::warn!(
-                        "Failed to connect to maker {} to request signatures for receiver, \
-                                reattempting... error={:?}",
-                        &maker_addr_str,
-                        e
-                    );
-                    if ii <= first_connect_attempts {
-                        sleep(Duration::from_secs(
-                            if ii <= self.config.short_long_sleep_delay_transition {
-                                sleep_delay
-                            } else {
-                                self.config.reconnect_long_sleep_delay
-                            },
-                        ));
-                        continue;
-                    } else {
-                        log::warn!(
-                            "Failed to connect to maker {} to request signatures for receiver, \
-                                    reattempt limit exceeded",
-                            &maker_addr_str,
-                        );
-                        return Err(e);
-                    }
-                }
-            }
-        }
+                req_sigs_for_sender_once(
+                    &mut socket,
+                    outgoing_swapcoins,
+                    maker_multisig_nonces,
+                    maker_hashlock_nonces,
+                    locktime,
+                )
+            },
+        )
     }
 
     /// Request signatures for receiver side of the swap.
@@ -1436,14 +1769,7 @@ impl Taker {
 
         let maker_addr_str = maker_address.to_string();
         log::info!("Connecting to {}", maker_addr_str);
-        let mut socket = match self.config.connection_type {
-            ConnectionType::CLEARNET => TcpStream::connect(maker_addr_str.clone())?,
-            ConnectionType::TOR => Socks5Stream::connect(
-                format!("127.0.0.1:{}", self.config.socks_port).as_str(),
-                &*maker_addr_str,
-            )?
-            .into_inner(),
-        };
+        let mut socket = connect_to_maker(&maker_addr_str, &self.config)?;
 
         socket.set_read_timeout(Some(reconnect_time_out))?;
         socket.set_write_timeout(Some(reconnect_time_out))?;
@@ -1537,20 +1863,26 @@ impl Taker {
                         .collect::<Vec<_>>()
                 };
 
+            let ongoing_swap_state = &self.ongoing_swap_state;
+            if !ongoing_swap_state.phase.is_some_and(|phase| {
+                can_reveal_preimage(phase, index, ongoing_swap_state.settled_hops)
+            }) {
+                log::error!(
+                    "Refusing to reveal the preimage to hop {} at phase {:?}, {} hops settled",
+                    index,
+                    ongoing_swap_state.phase,
+                    ongoing_swap_state.settled_hops
+                );
+                return Err(ProtocolError::IllegalPreimageReveal.into());
+            }
+
             let reconnect_time_out = Duration::from_secs(self.config.reconnect_attempt_timeout_sec);
 
             let mut ii = 0;
 
             let maker_addr_str = maker_address.address.to_string();
             log::info!("Connecting to {}", maker_addr_str);
-            let mut socket = match self.config.connection_type {
-                ConnectionType::CLEARNET => TcpStream::connect(maker_addr_str.clone())?,
-                ConnectionType::TOR => Socks5Stream::connect(
-                    format!("127.0.0.1:{}", self.config.socks_port).as_str(),
-                    &*maker_addr_str,
-                )?
-                .into_inner(),
-            };
+            let mut socket = connect_to_maker(&maker_addr_str, &self.config)?;
 
             socket.set_read_timeout(Some(reconnect_time_out))?;
             socket.set_write_timeout(Some(reconnect_time_out))?;
@@ -1578,7 +1910,10 @@ impl Taker {
                     &senders_multisig_redeemscripts,
                     &receivers_multisig_redeemscripts,
                 ) {
-                    Ok(()) => break,
+                    Ok(()) => {
+                        self.ongoing_swap_state.settled_hops += 1;
+                        break;
+                    }
                     Err(e) => {
                         log::warn!(
                             "Failed to connect to maker {} to settle coinswap, \
@@ -1601,7 +1936,7 @@ impl Taker {
                                         reattempt limit exceeded",
                                 &maker_address.address,
                             );
-                            self.offerbook.add_bad_maker(maker_address);
+                            self.ban_maker(maker_address);
                             return Err(e);
                         }
                     }
@@ -1678,28 +2013,265 @@ impl Taker {
     // ######## UTILITY AND HELPERS ############
 
     /// Choose a suitable **untried** maker address from the offerbook that fits the swap params.
-    fn choose_next_maker(&self) -> Result<&OfferAndAddress, TakerError> {
+    fn choose_next_maker(&self) -> Result<OfferAndAddress, TakerError> {
         let send_amount = self.ongoing_swap_state.swap_params.send_amount;
         if send_amount == Amount::ZERO {
             return Err(TakerError::SendAmountNotSet);
         }
 
+        let tip_height = self.wallet.backend.get_block_count()?;
+        let current_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time before unix epoch")
+            .as_secs();
+
+        let quote = |oa: &OfferAndAddress| {
+            calculate_coinswap_fee(
+                &oa.offer.fee_policy,
+                oa.offer.time_relative_fee_ppb,
+                send_amount,
+                self.config.timelock_policy.base as u64,
+            )
+        };
+
         // Ensure that we don't select a maker we are already swaping with.
-        Ok(self
+        let candidates = self
             .offerbook
             .get_all_untried()
-            .iter()
-            .find(|oa| {
-                send_amount > Amount::from_sat(oa.offer.min_size)
-                    && send_amount < Amount::from_sat(oa.offer.max_size)
-                    && !self
-                        .ongoing_swap_state
-                        .peer_infos
-                        .iter()
-                        .map(|pi| &pi.peer)
-                        .any(|noa| noa == **oa)
+            .into_iter()
+            .filter(|oa| {
+                !self
+                    .ongoing_swap_state
+                    .peer_infos
+                    .iter()
+                    .any(|pi| pi.peer == **oa)
             })
-            .ok_or(TakerError::NotEnoughMakersInOfferBook)?)
+            // Makers that keep failing our swaps are avoided.
+            .filter(|oa| {
+                !self
+                    .reputation
+                    .stats(&oa.offer.fidelity.bond.outpoint)
+                    .is_avoided(
+                        self.config.min_maker_success_rate,
+                        self.config.reputation_min_swaps,
+                    )
+            })
+            // Makers whose fee policy doesn't cover the amount are skipped.
+            .filter_map(|oa| {
+                let swap_fee = quote(oa).ok()?;
+                Some(MakerInfo {
+                    offer_and_address: oa.clone(),
+                    bond_value: oa.offer.fidelity.bond.score(tip_height, current_time),
+                    swap_fee: Amount::from_sat(swap_fee),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // The route must stay within the maker fee limit, with the makers already in it.
+        let route = self
+            .ongoing_swap_state
+            .peer_infos
+            .iter()
+            .take(self.ongoing_swap_state.swap_params.maker_count);
+        let quoted = route
+            .clone()
+            .filter_map(|pi| quote(&pi.peer).ok())
+            .map(Amount::from_sat)
+            .sum::<Amount>();
+        let remaining = self.ongoing_swap_state.swap_params.maker_count - route.count();
+        let candidates = filter_by_fee_limit(
+            candidates,
+            quoted,
+            remaining,
+            self.config.max_maker_fee(send_amount),
+        )?;
+
+        self.maker_selector
+            .select(&candidates, 1)
+            .into_iter()
+            .next()
+            .map(|maker| maker.offer_and_address)
+            .ok_or(TakerError::NotEnoughMakersInOfferBook)
+    }
+
+    /// Marks `maker` as bad for the rest of the session. The failure also counts against its
+    /// reputation if the maker is in the swap route, where its abort forces us to recover. A
+    /// candidate that failed to join the route is only skipped.
+    fn ban_maker(&mut self, maker: &OfferAndAddress) {
+        self.offerbook.add_bad_maker(maker);
+        let in_route = self
+            .ongoing_swap_state
+            .peer_infos
+            .iter()
+            .any(|peer_info| peer_info.peer.address == maker.address);
+        if self.ongoing_swap_state.phase.is_some() && in_route {
+            self.record_maker_reputation(maker, false);
+        }
+    }
+
+    /// Counts a swap outcome of `maker`. The swap doesn't fail if it can't be saved.
+    fn record_maker_reputation(&mut self, maker: &OfferAndAddress, success: bool) {
+        if let Err(e) = self
+            .reputation
+            .record(maker.offer.fidelity.bond.outpoint, success)
+        {
+            log::error!(
+                "Failed to record the reputation of maker {}: {:?}",
+                maker.address,
+                e
+            );
+        }
+    }
+
+    /// Asks `maker` to join our first hop `funding_txs`, see [CollabFunding], and rebuilds the
+    /// contracts of `outgoing_swapcoins` on the joined txs. Returns the funding txs, with the
+    /// maker's signatures of the rebuilt contracts if it joined them. If it can't, we fund alone.
+    fn collab_funding(
+        &self,
+        maker: &OfferAndAddress,
+        funding_txs: Vec<Transaction>,
+        outgoing_swapcoins: &mut [OutgoingSwapCoin],
+        multisig_nonces: &[SecretKey],
+        hashlock_nonces: &[SecretKey],
+        swap_locktime: u16,
+    ) -> (Vec<Transaction>, Option<ContractSigsForSender>) {
+        match self.try_collab_funding(
+            maker,
+            &funding_txs,
+            outgoing_swapcoins,
+            multisig_nonces,
+            hashlock_nonces,
+            swap_locktime,
+        ) {
+            Ok(Some((joined_txs, contract_sigs))) => {
+                log::info!("Maker {} joined the funding txs", maker.address);
+                (joined_txs, Some(contract_sigs))
+            }
+            Ok(None) => {
+                log::info!(
+                    "Maker {} doesn't support collaborative funding",
+                    maker.address
+                );
+                (funding_txs, None)
+            }
+            Err(e) => {
+                log::warn!(
+                    "Collaborative funding with maker {} failed, funding alone: {:?}",
+                    maker.address,
+                    e
+                );
+                (funding_txs, None)
+            }
+        }
+    }
+
+    /// Runs the collaborative funding on a single connection. The maker signs the contracts of
+    /// our `funding_txs` first, so it only joins txs paying the multisigs it agreed on. Then it
+    /// joins them, and signs the contracts again, rebuilt on the joined txids.
+    ///
+    /// `outgoing_swapcoins` are only updated once the maker signed the rebuilt contracts.
+    fn try_collab_funding(
+        &self,
+        maker: &OfferAndAddress,
+        funding_txs: &[Transaction],
+        outgoing_swapcoins: &mut [OutgoingSwapCoin],
+        multisig_nonces: &[SecretKey],
+        hashlock_nonces: &[SecretKey],
+        swap_locktime: u16,
+    ) -> Result<Option<(Vec<Transaction>, ContractSigsForSender)>, TakerError> {
+        let mut socket = connect_to_maker(&maker.address.to_string(), &self.config)?;
+        let timeout = Some(Duration::from_secs(
+            self.config.first_connect_attempt_timeout_sec,
+        ));
+        socket.set_read_timeout(timeout)?;
+        socket.set_write_timeout(timeout)?;
+        if handshake_maker(&mut socket)? < PROTOCOL_VERSION_COLLAB_FUNDING {
+            return Ok(None);
+        }
+        req_contract_sigs_for_sender(
+            &mut socket,
+            outgoing_swapcoins,
+            multisig_nonces,
+            hashlock_nonces,
+            swap_locktime,
+        )?;
+
+        let psbts = funding_txs
+            .iter()
+            .map(|tx| self.wallet.collab_funding_psbt(tx))
+            .collect::<Result<Vec<_>, _>>()?;
+        // The maker's part must not slow down any of the txs.
+        let fee_rate = psbts
+            .iter()
+            .zip(funding_txs)
+            .filter_map(|(psbt, tx)| Some(psbt.fee().ok()? / tx.weight()))
+            .max()
+            .unwrap_or(FeeRate::BROADCAST_MIN);
+
+        let joined = req_collab_funding(&mut socket, CollabFunding { psbts, fee_rate })?;
+        if joined.psbts.len() != funding_txs.len() {
+            return Err(ProtocolError::WrongMessage {
+                expected: format!("{} joined funding txs", funding_txs.len()),
+                received: format!("{}", joined.psbts.len()),
+            }
+            .into());
+        }
+
+        let joined_txs = funding_txs
+            .iter()
+            .zip(&joined.psbts)
+            .map(|(tx, psbt)| self.wallet.sign_collab_funding(tx, psbt, fee_rate))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut joined_swapcoins = outgoing_swapcoins.to_vec();
+        rebuild_contracts_on_joined_txs(
+            &mut joined_swapcoins,
+            &joined_txs,
+            self.ongoing_swap_state.swap_params.fee_rate,
+        )?;
+        let contract_sigs = req_contract_sigs_for_sender(
+            &mut socket,
+            &joined_swapcoins,
+            multisig_nonces,
+            hashlock_nonces,
+            swap_locktime,
+        )?;
+        outgoing_swapcoins.clone_from_slice(&joined_swapcoins);
+        Ok(Some((joined_txs, contract_sigs)))
+    }
+
+    /// Agree on the fee rate of the transactions `maker` funds, before any of them is built.
+    ///
+    /// Proposes the fee rate of the swap's `fee_rate`, and settles for a counter within the
+    /// configured bounds. Returns the miner fee of each transaction the maker funds, which stays
+    /// the swap's `fee_rate` with makers that don't negotiate.
+    fn negotiate_fee_rate(&self, maker: &OfferAndAddress) -> Result<Amount, TakerError> {
+        let fee = self.ongoing_swap_state.swap_params.fee_rate;
+        let proposed =
+            funding_tx_fee_rate(fee).ok_or(ProtocolError::FeeRateOutOfBounds(FeeRate::MAX))?;
+        let negotiation = FeeNegotiation {
+            proposed,
+            min_accept: self.config.min_fee_rate.min(proposed),
+            max_accept: self.config.max_fee_rate.max(proposed),
+        };
+        let Some(agreed) = negotiate_fee_rate(maker, &self.config, negotiation)? else {
+            log::info!(
+                "Maker {} doesn't negotiate fee rates, using the fixed fee",
+                maker.address
+            );
+            return Ok(fee);
+        };
+        if agreed == proposed {
+            Ok(fee)
+        } else {
+            log::info!(
+                "Maker {} countered the fee rate {} sat/kwu with {} sat/kwu",
+                maker.address,
+                proposed,
+                agreed
+            );
+            Ok(funding_tx_fee(agreed))
+        }
     }
 
     /// Get the [Preimage] of the ongoing swap. If no swap is in progress will return a `[0u8; 32]`.
@@ -1707,14 +2279,168 @@ impl Taker {
         &self.ongoing_swap_state.active_preimage
     }
 
-    /// Get the [Preimage] hash for the ongoing swap. If no swap is in progress will return `hash160([0u8; 32])`.
-    fn get_preimage_hash(&self) -> Hash160 {
-        Hash160::hash(self.get_preimage())
+    /// Get the [Hashlock] of the [Preimage] for the ongoing swap, with the configured
+    /// [HashlockType](crate::protocol::contract::HashlockType).
+    fn get_hashlock(&self) -> Hashlock {
+        Hashlock::new(self.config.hashlock_type, self.get_preimage())
     }
 
-    /// Clear the [OngoingSwapState].
+    /// Clear the [OngoingSwapState], and its [SwapState] on disk.
     fn clear_ongoing_swaps(&mut self) {
         self.ongoing_swap_state = OngoingSwapState::default();
+        if let Err(e) = SwapState::remove_from_disk(&self.swap_state_path) {
+            log::error!("Failed to remove the swap state file: {:?}", e);
+        }
+    }
+
+    /// Saves the [SwapState] of the ongoing swap, at the given phase.
+    fn save_swap_state(&mut self, phase: SwapPhase) -> Result<(), TakerError> {
+        self.ongoing_swap_state.phase = Some(phase);
+        let state = SwapState {
+            phase,
+            swap_params: self.ongoing_swap_state.swap_params,
+            preimage: self.ongoing_swap_state.active_preimage,
+            // The last peer info is the Taker, once the route is complete.
+            maker_route: self
+                .ongoing_swap_state
+                .peer_infos
+                .iter()
+                .take(self.ongoing_swap_state.swap_params.maker_count)
+                .map(|pi| pi.peer.address.to_string())
+                .collect(),
+            outgoing_swapcoins: self
+                .ongoing_swap_state
+                .outgoing_swapcoins
+                .iter()
+                .map(|sc| sc.get_multisig_redeemscript())
+                .collect(),
+            incoming_swapcoins: self
+                .ongoing_swap_state
+                .incoming_swapcoins
+                .iter()
+                .map(|sc| sc.get_multisig_redeemscript())
+                .collect(),
+        };
+        log::debug!("Saving swap state at phase {:?}", phase);
+        state.write_to_disk(&self.swap_state_path)
+    }
+
+    /// Stops the swap round on an immediate shutdown request. Called where the [SwapState] is
+    /// saved, so [Taker::resume] recovers the round on the next start.
+    fn check_immediate_shutdown(&self) -> Result<(), TakerError> {
+        if self.shutdown_handle.requested() == Some(ShutdownMode::Immediate) {
+            log::warn!("Immediate shutdown requested, stopping the swap round");
+            return Err(TakerError::ShuttingDown);
+        }
+        Ok(())
+    }
+
+    /// Recovers the swap round left unfinished by a previous run, if any, and returns its [SwapState].
+    ///
+    /// The connections with the makers are gone, so the round can't go on. If the taker already
+    /// holds both keys of all its incoming swapcoins the swap did complete, and only the state is
+    /// cleared. Interrupted while settling, the preimage may already be out: the incoming swapcoins
+    /// are claimed through the hashlock with [Taker::claim_settling_swap], and the outgoing ones
+    /// are left to the makers. Before that, the funds are recovered through the contracts with
+    /// [Taker::recover_from_swap], which waits for the timelocks to mature.
+    pub fn resume(&mut self) -> Result<Option<SwapState>, TakerError> {
+        let state = match SwapState::read_from_disk(&self.swap_state_path)? {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+        log::warn!(
+            "Resuming unfinished swap at phase {:?}, route {:?}",
+            state.phase,
+            state.maker_route
+        );
+
+        let swap_completed = state.phase == SwapPhase::Settling
+            && !state.incoming_swapcoins.is_empty()
+            && state.incoming_swapcoins.iter().all(|multisig| {
+                self.wallet
+                    .find_incoming_swapcoin(multisig)
+                    .is_some_and(|sc| sc.other_privkey.is_some())
+            });
+        if swap_completed {
+            log::info!("The swap completed before the interruption");
+            self.clear_ongoing_swaps();
+        } else if state.phase == SwapPhase::Settling {
+            self.claim_settling_swap(&state)?;
+            self.clear_ongoing_swaps();
+        } else {
+            self.restore_ongoing_swap(&state);
+            self.recover_from_swap()?;
+            // Nothing to recover returns early, without clearing.
+            self.clear_ongoing_swaps();
+        }
+        Ok(Some(state))
+    }
+
+    /// Restores what the swap history records of a swap from its [SwapState]: the parameters,
+    /// the route and the swapcoins.
+    fn restore_ongoing_swap(&mut self, state: &SwapState) {
+        self.ongoing_swap_state.swap_params = state.swap_params;
+        self.ongoing_swap_state.resumed_route = state.maker_route.clone();
+        self.ongoing_swap_state.outgoing_swapcoins = state
+            .outgoing_swapcoins
+            .iter()
+            .filter_map(|multisig| self.wallet.find_outgoing_swapcoin(multisig).cloned())
+            .collect();
+        self.ongoing_swap_state.incoming_swapcoins = state
+            .incoming_swapcoins
+            .iter()
+            .filter_map(|multisig| self.wallet.find_incoming_swapcoin(multisig).cloned())
+            .collect();
+    }
+
+    /// Claims the incoming swapcoins of a swap interrupted while settling, through the hashlock
+    /// branch of their contracts, with the preimage of the swap state.
+    ///
+    /// The makers may already know the preimage, and learn it from the claims anyway, so the
+    /// outgoing contracts are theirs to claim. Refunding them through the timelock would race the
+    /// makers' hashlock spends, so the outgoing swapcoins are dropped instead. Waits for the
+    /// contracts to confirm.
+    pub fn claim_settling_swap(&mut self, state: &SwapState) -> Result<(), TakerError> {
+        let mut pending = Vec::new();
+        for multisig in &state.incoming_swapcoins {
+            let Some(incoming) = self.wallet.find_incoming_swapcoin_mut(multisig) else {
+                continue;
+            };
+            if incoming.other_privkey.is_none() {
+                incoming.hash_preimage = Some(state.preimage);
+                pending.push(multisig.clone());
+            }
+        }
+        self.wallet.save_to_disk()?;
+
+        loop {
+            let mut unclaimed = Vec::new();
+            for multisig in pending {
+                let Some(incoming) = self.wallet.find_incoming_swapcoin(&multisig).cloned() else {
+                    continue;
+                };
+                if self.wallet.claim_incoming_swapcoin(&incoming)?.is_none() {
+                    unclaimed.push(multisig);
+                }
+            }
+            pending = unclaimed;
+
+            if pending.is_empty() {
+                for multisig in &state.outgoing_swapcoins {
+                    self.wallet.remove_outgoing_swapcoin(multisig)?;
+                }
+                log::info!("All incoming contracts claimed through the hashlock");
+                self.wallet.sync()?;
+                return Ok(());
+            }
+            // Block wait time is varied between prod. and test builds.
+            let block_wait_time = if cfg!(feature = "integration-test") {
+                Duration::from_secs(10)
+            } else {
+                Duration::from_secs(10 * 60)
+            };
+            std::thread::sleep(block_wait_time);
+        }
     }
 
     pub fn get_bad_makers(&self) -> Vec<&OfferAndAddress> {
@@ -1736,6 +2462,124 @@ impl Taker {
         Ok(())
     }
 
+    /// Appends the swap round to the wallet's swap history. A failure to record doesn't fail the swap.
+    fn record_swap_round(
+        &self,
+        swap_id: String,
+        amount: Amount,
+        fee: Amount,
+        outcome: SwapOutcome,
+    ) {
+        let counterparties = (0..self.ongoing_swap_state.swap_params.maker_count)
+            .map_while(|index| self.route_maker(index))
+            .collect();
+        let mut record = SwapRecord::new(swap_id, amount, counterparties, fee, outcome);
+        if self.config.record_utxo_exposure {
+            record.exposures = self.utxo_exposures();
+        }
+        if let Err(e) = self.wallet.record_swap(record) {
+            log::error!("Failed to record the swap in the swap history: {:?}", e);
+        }
+    }
+
+    /// The UTXOs of the swap round revealed to the Makers.
+    ///
+    /// The first Maker sees the Taker's funding transactions, so their inputs and change. The last
+    /// Maker funds the Taker's incoming swapcoins. The Makers in between see none of them.
+    fn utxo_exposures(&self) -> Vec<UtxoExposure> {
+        let state = &self.ongoing_swap_state;
+        let mut exposures = Vec::new();
+
+        // A resumed swap lost its funding txs, they're in the wallet's history.
+        let funding_txs = match state.funding_txs.first() {
+            Some((funding_txs, _)) => funding_txs.clone(),
+            None => state
+                .outgoing_swapcoins
+                .iter()
+                .map(|sc| sc.contract_tx.input[0].previous_output.txid)
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .filter_map(
+                    |txid| match self.wallet.backend.get_wallet_transaction(&txid) {
+                        Ok(tx) => tx.map(|(tx, _)| tx),
+                        Err(e) => {
+                            log::warn!("Failed to look up the funding tx {}: {:?}", txid, e);
+                            None
+                        }
+                    },
+                )
+                .collect(),
+        };
+
+        if let Some(maker) = self.route_maker(0).filter(|_| !funding_txs.is_empty()) {
+            let swap_outpoints = state
+                .outgoing_swapcoins
+                .iter()
+                .map(|sc| sc.contract_tx.input[0].previous_output)
+                .collect::<HashSet<_>>();
+            for funding_tx in &funding_txs {
+                let txid = funding_tx.compute_txid();
+                exposures.extend(funding_tx.input.iter().map(|input| UtxoExposure {
+                    maker: maker.clone(),
+                    outpoint: input.previous_output,
+                    kind: ExposureKind::FundingInput,
+                }));
+                exposures.extend(
+                    (0..funding_tx.output.len() as u32)
+                        .map(|vout| OutPoint { txid, vout })
+                        .filter(|outpoint| !swap_outpoints.contains(outpoint))
+                        .map(|outpoint| UtxoExposure {
+                            maker: maker.clone(),
+                            outpoint,
+                            kind: ExposureKind::FundingChange,
+                        }),
+                );
+            }
+        }
+
+        if let Some(maker) = state
+            .swap_params
+            .maker_count
+            .checked_sub(1)
+            .and_then(|index| self.route_maker(index))
+        {
+            exposures.extend(state.incoming_swapcoins.iter().map(|sc| UtxoExposure {
+                maker: maker.clone(),
+                outpoint: sc.contract_tx.input[0].previous_output,
+                kind: ExposureKind::Received,
+            }));
+        }
+
+        exposures
+    }
+
+    /// Address of the maker at `index` in the route of the swap, live or resumed.
+    fn route_maker(&self, index: usize) -> Option<String> {
+        let state = &self.ongoing_swap_state;
+        if state.peer_infos.is_empty() {
+            state.resumed_route.get(index).cloned()
+        } else {
+            state
+                .peer_infos
+                .get(index)
+                .map(|peer_info| peer_info.peer.address.to_string())
+        }
+    }
+
+    /// Which of the Taker's UTXOs were revealed to which Maker, in the recorded swap `swap_id`.
+    ///
+    /// Only recorded with [TakerConfig::record_utxo_exposure]. The swap ids are listed in the
+    /// [swap history](Wallet::swap_history).
+    pub fn exposure_report(&self, swap_id: &str) -> Result<ExposureReport, TakerError> {
+        self.wallet
+            .swap_history()?
+            .into_iter()
+            .rev()
+            .find(|record| record.swap_id == swap_id)
+            .map(|record| record.exposure_report())
+            .ok_or_else(|| TakerError::SwapNotFound(swap_id.to_string()))
+    }
+
     /// Checks if any contreact transactions have been broadcasted.
     /// Returns the txid list of all the broadcasted contract transaction.
     /// Empty vector if nothing is nothing is broadcasted. (usual case).
@@ -1764,7 +2608,7 @@ impl Taker {
         // This requires -txindex to be enabled in the node.
         let seen_txids = contract_txids
             .iter()
-            .filter(|txid| self.wallet.rpc.get_raw_transaction_info(txid, None).is_ok())
+            .filter(|txid| matches!(self.wallet.get_tx_confirmations(txid), Ok(Some(_))))
             .cloned()
             .collect::<Vec<Txid>>();
 
@@ -1773,6 +2617,21 @@ impl Taker {
 
     pub fn recover_from_swap(&mut self) -> Result<(), TakerError> {
         let (incomings, outgoings) = self.wallet.find_unfinished_swapcoins();
+        if !incomings.is_empty() || !outgoings.is_empty() {
+            let swap_id = outgoings
+                .iter()
+                .map(|sc| &sc.contract_redeemscript)
+                .chain(incomings.iter().map(|sc| &sc.contract_redeemscript))
+                .find_map(|redeemscript| read_hashvalue_from_contract(redeemscript).ok())
+                .map(|hashvalue| hashvalue.to_string())
+                .unwrap_or_default();
+            self.record_swap_round(
+                swap_id,
+                outgoings.iter().map(|sc| sc.funding_amount).sum(),
+                Amount::ZERO,
+                SwapOutcome::Failed,
+            );
+        }
 
         let incoming_contracts = incomings
             .iter()
@@ -1788,13 +2647,12 @@ impl Taker {
         for (contract_tx, redeemscript) in &incoming_contracts {
             if self
                 .wallet
-                .rpc
-                .get_raw_transaction_info(&contract_tx.compute_txid(), None)
-                .is_ok()
+                .get_tx_confirmations(&contract_tx.compute_txid())?
+                .is_some()
             {
                 log::info!("Incoming Contract already broadacsted");
             } else {
-                self.wallet.rpc.send_raw_transaction(contract_tx)?;
+                self.wallet.backend.broadcast(contract_tx)?;
                 log::info!(
                     "Broadcasted Incoming Contract. Removing from wallet. Contract Txid {}",
                     contract_tx.compute_txid()
@@ -1807,97 +2665,25 @@ impl Taker {
             self.wallet.remove_incoming_swapcoin(redeemscript)?;
         }
 
-        let mut outgoing_infos = Vec::new();
-
-        // Broadcast the Outgoing Contracts
-        for outgoing in outgoings {
-            let contract_tx = outgoing.get_fully_signed_contract_tx()?;
-            if self
-                .wallet
-                .rpc
-                .get_raw_transaction_info(&contract_tx.compute_txid(), None)
-                .is_ok()
-            {
-                log::info!("Outgoing Contract already broadcasted");
-            } else {
-                self.wallet.rpc.send_raw_transaction(&contract_tx)?;
-                log::info!(
-                    "Broadcasted Outgoing Contract, Contract txid : {}",
-                    contract_tx.compute_txid()
-                );
-            }
-            let reedemscript = outgoing.get_multisig_redeemscript();
-            let timelock = outgoing.get_timelock();
-            let next_internal = &self.wallet.get_next_internal_addresses(1)?[0];
-            let timelock_spend = outgoing.create_timelock_spend(next_internal);
-            outgoing_infos.push(((reedemscript, contract_tx), (timelock, timelock_spend)));
+        // Break early if nothing to broadcast.
+        // This happens only when init_first_hop() fails at `NotEnoughMakersInOfferBook`
+        if outgoings.is_empty() {
+            return Ok(());
         }
 
-        // Check for contract confirmations and broadcast timelocked transaction
-        let mut timelock_boardcasted = Vec::new();
-
         // Start the loop to keep checking for timelock maturity, and spend from the contract asap.
         loop {
-            // Break early if nothing to broadcast.
-            // This happens only when init_first_hop() fails at `NotEnoughMakersInOfferBook`
-            if outgoing_infos.is_empty() {
+            self.recover_from_incomplete_swap()?;
+
+            // Everything is broadcasted. Clear the connectionstate and break the loop
+            if self.wallet.find_unfinished_swapcoins().1.is_empty() {
+                log::info!("All outgoing contracts reedemed. Cleared ongoing swap state");
+                self.clear_ongoing_swaps(); // This could be a bug if Taker is in middle of multiple swaps. For now we assume Taker will only do one swap at a time.
+                log::info!("Initializing Wallet sync and save");
+                self.wallet.sync()?;
+                log::info!("Completed wallet sync and save");
                 return Ok(());
             }
-            for ((reedemscript, contract), (timelock, timelocked_tx)) in outgoing_infos.iter() {
-                // We have already broadcasted this tx, so skip
-                if timelock_boardcasted.contains(&timelocked_tx) {
-                    continue;
-                }
-                // Check if the contract tx has reached required maturity
-                // Failure here means the transaction hasn't been broadcasted yet. So do nothing and try again.
-                if let Ok(result) = self
-                    .wallet
-                    .rpc
-                    .get_raw_transaction_info(&contract.compute_txid(), None)
-                {
-                    log::info!(
-                        "Contract Tx : {}, reached confirmation : {:?}, required : {}",
-                        contract.compute_txid(),
-                        result.confirmations,
-                        timelock
-                    );
-                    if let Some(confirmation) = result.confirmations {
-                        // Now the transaction is confirmed in a block, check for required maturity
-                        if confirmation > (*timelock as u32) {
-                            log::info!(
-                                "Timelock maturity of {} blocks for Contract Tx is reached : {}",
-                                timelock,
-                                contract.compute_txid()
-                            );
-                            log::info!(
-                                "Broadcasting timelocked tx: {}",
-                                timelocked_tx.compute_txid()
-                            );
-                            self.wallet.rpc.send_raw_transaction(timelocked_tx).unwrap();
-                            timelock_boardcasted.push(timelocked_tx);
-
-                            let outgoing_removed = self
-                                .wallet
-                                .remove_outgoing_swapcoin(reedemscript)
-                                .unwrap()
-                                .expect("outgoing swapcoin expected");
-                            log::info!(
-                                "Removed Outgoing Swapcoin from Wallet, Contract Txid: {}",
-                                outgoing_removed.contract_tx.compute_txid()
-                            );
-                        }
-                    }
-                }
-                // Everything is broadcasted. Clear the connectionstate and break the loop
-                if timelock_boardcasted.len() == outgoing_infos.len() {
-                    log::info!("All outgoing contracts reedemed. Cleared ongoing swap state");
-                    self.clear_ongoing_swaps(); // This could be a bug if Taker is in middle of multiple swaps. For now we assume Taker will only do one swap at a time.
-                    log::info!("Initializing Wallet sync and save");
-                    self.wallet.sync()?;
-                    log::info!("Completed wallet sync and save");
-                    return Ok(());
-                }
-            }
             // Block wait time is varied between prod. and test builds.
             let block_wait_time = if cfg!(feature = "integration-test") {
                 Duration::from_secs(10)
@@ -1908,6 +2694,30 @@ impl Taker {
         }
     }
 
+    /// Refunds the outgoing swapcoins of unfinished swaps whose timelock has matured, and returns
+    /// the txids of the broadcasted refunds.
+    ///
+    /// Broadcasts the contract txs not seen on chain yet, so their timelock starts running. A refund
+    /// is broadcasted once its contract tx has more confirmations than the timelock, and the
+    /// swapcoin is then removed from the wallet. Unlike [Taker::recover_from_swap] this doesn't
+    /// wait: call it again on new blocks until no unfinished swapcoin is left.
+    pub fn recover_from_incomplete_swap(&mut self) -> Result<Vec<Txid>, TakerError> {
+        let (_, outgoings) = self.wallet.find_unfinished_swapcoins();
+        let mut refund_txids = Vec::new();
+
+        for outgoing in outgoings {
+            if let Some(refund_txid) = self.wallet.refund_outgoing_swapcoin(&outgoing)? {
+                refund_txids.push(refund_txid);
+            }
+        }
+        // The refunded swapcoins are gone, a restarted Taker mustn't refund them again.
+        if !refund_txids.is_empty() {
+            self.wallet.save_to_disk()?;
+        }
+
+        Ok(refund_txids)
+    }
+
     /// Synchronizes the offer book with addresses obtained from directory servers and local configurations.
     pub fn sync_offerbook(
         &mut self,
@@ -1925,9 +2735,8 @@ impl Taker {
             ConnectionType::TOR => {
                 let mut address = config.directory_server_onion_address.clone();
                 if cfg!(feature = "integration-test") {
-                    let directory_hs_path_str =
-                        "/tmp/tor-rust-directory/hs-dir/hostname".to_string();
-                    let directory_hs_path = PathBuf::from(directory_hs_path_str);
+                    // The test directory server runs from the default data directory.
+                    let directory_hs_path = get_dns_dir().join("tor/hs-dir/hostname");
                     let mut directory_file =
                         fs::File::open(directory_hs_path).map_err(TakerError::IO)?;
                     let mut directory_onion_addr = String::new();
@@ -1942,7 +2751,7 @@ impl Taker {
         };
 
         let socks_port = if self.config.connection_type == ConnectionType::TOR {
-            Some(self.config.socks_port)
+            Some(self.config.tor_config.proxy.port())
         } else {
             None
         };
@@ -1966,9 +2775,15 @@ impl Taker {
                 offer.address.to_string()
             );
             log::debug!("{:?}", offer);
-            if let Err(e) = self
+            if offer.offer.network != self.wallet.store.network {
+                log::warn!(
+                    "Offer is for {}. Rejecting Offer from Maker : {}",
+                    offer.offer.network,
+                    offer.address
+                );
+            } else if let Err(e) = self
                 .wallet
-                .verify_fidelity_proof(&offer.offer.fidelity, &offer.address.to_string())
+                .verify_fidelity_proof(&offer.offer, &offer.address.to_string())
             {
                 log::warn!(
                     "Fidelity Proof Verification failed with error: {:?}. Rejecting Offer from Maker : {}",
@@ -1983,3 +2798,133 @@ impl Taker {
         Ok(())
     }
 }
+
+/// Rebuilds the contracts of `outgoing_swapcoins` on the `joined_txs` of a collaborative funding,
+/// see [CollabFunding]. The funding outputs moved to the joined txids.
+fn rebuild_contracts_on_joined_txs(
+    outgoing_swapcoins: &mut [OutgoingSwapCoin],
+    joined_txs: &[Transaction],
+    fee_rate: Amount,
+) -> Result<(), TakerError> {
+    for (swapcoin, joined_tx) in outgoing_swapcoins.iter_mut().zip(joined_txs) {
+        let funding_output = TxOut {
+            value: swapcoin.funding_amount,
+            script_pubkey: swapcoin.get_multisig_redeemscript().to_p2wsh(),
+        };
+        let Some(vout) = joined_tx
+            .output
+            .iter()
+            .position(|output| *output == funding_output)
+        else {
+            return Err(ProtocolError::WrongMessage {
+                expected: "A joined funding tx paying our contract".to_string(),
+                received: format!("{}", joined_tx.compute_txid()),
+            }
+            .into());
+        };
+        swapcoin.contract_tx = create_senders_contract_tx(
+            OutPoint {
+                txid: joined_tx.compute_txid(),
+                vout: vout as u32,
+            },
+            swapcoin.funding_amount,
+            &swapcoin.contract_redeemscript,
+            fee_rate,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::contract::{create_contract_redeemscript, HashlockType};
+    use bitcoin::{absolute::LockTime, transaction::Version, TxIn};
+
+    fn outgoing_swapcoin(seed: u8) -> OutgoingSwapCoin {
+        let secp = Secp256k1::new();
+        let my_privkey = SecretKey::from_slice(&[seed; 32]).unwrap();
+        let other_privkey = SecretKey::from_slice(&[seed + 1; 32]).unwrap();
+        let timelock_privkey = SecretKey::from_slice(&[seed + 2; 32]).unwrap();
+        let contract_redeemscript = create_contract_redeemscript(
+            &PublicKey::new(other_privkey.public_key(&secp)),
+            &PublicKey::new(timelock_privkey.public_key(&secp)),
+            &Hashlock::new(HashlockType::Sha256, &[seed; 32]),
+            &20,
+        );
+        let funding_amount = Amount::from_sat(100_000);
+        let contract_tx = create_senders_contract_tx(
+            OutPoint::null(),
+            funding_amount,
+            &contract_redeemscript,
+            Amount::from_sat(1000),
+        );
+        OutgoingSwapCoin::new(
+            my_privkey,
+            PublicKey::new(other_privkey.public_key(&secp)),
+            contract_tx,
+            contract_redeemscript,
+            timelock_privkey,
+            funding_amount,
+        )
+    }
+
+    fn joined_tx(outputs: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: outputs,
+        }
+    }
+
+    fn funding_output(swapcoin: &OutgoingSwapCoin) -> TxOut {
+        TxOut {
+            value: swapcoin.funding_amount,
+            script_pubkey: swapcoin.get_multisig_redeemscript().to_p2wsh(),
+        }
+    }
+
+    #[test]
+    fn test_rebuild_contracts_on_joined_txs() {
+        let mut swapcoins = vec![outgoing_swapcoin(1), outgoing_swapcoin(10)];
+        let maker_change = TxOut {
+            value: Amount::from_sat(50_000),
+            script_pubkey: ScriptBuf::new(),
+        };
+        // The Maker's output may come before ours.
+        let joined_txs = vec![
+            joined_tx(vec![maker_change.clone(), funding_output(&swapcoins[0])]),
+            joined_tx(vec![funding_output(&swapcoins[1]), maker_change.clone()]),
+        ];
+
+        rebuild_contracts_on_joined_txs(&mut swapcoins, &joined_txs, Amount::from_sat(1000))
+            .unwrap();
+        for ((swapcoin, joined_tx), vout) in swapcoins.iter().zip(&joined_txs).zip([1, 0]) {
+            assert_eq!(
+                swapcoin.contract_tx.input[0].previous_output,
+                OutPoint {
+                    txid: joined_tx.compute_txid(),
+                    vout,
+                }
+            );
+            assert_eq!(
+                swapcoin.contract_tx.output[0].script_pubkey,
+                swapcoin.contract_redeemscript.to_p2wsh()
+            );
+        }
+
+        // A joined tx not paying the agreed amount to our multisig.
+        let mut swapcoins = vec![outgoing_swapcoin(1)];
+        let original_contract_tx = swapcoins[0].contract_tx.clone();
+        let mut shortchanged = funding_output(&swapcoins[0]);
+        shortchanged.value -= Amount::from_sat(1);
+        assert!(rebuild_contracts_on_joined_txs(
+            &mut swapcoins,
+            &[joined_tx(vec![shortchanged, maker_change])],
+            Amount::from_sat(1000),
+        )
+        .is_err());
+        assert_eq!(swapcoins[0].contract_tx, original_contract_tx);
+    }
+}
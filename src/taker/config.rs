@@ -3,18 +3,98 @@
 //!  Represents the configuration options for the Taker module, controlling behaviors
 //! such as refund locktime, connection attempts, sleep delays, and timeouts.
 
-use std::{io, path::PathBuf};
+use std::{convert::TryFrom, io, path::PathBuf, time::Duration};
+
+use bitcoin::{
+    secp256k1::rand::{rngs::OsRng, Rng},
+    Amount, FeeRate,
+};
+
+use crate::{
+    protocol::contract::HashlockType,
+    utill::{
+        get_taker_dir, parse_field, parse_toml, write_default_config, ConnectionType, TorConfig,
+    },
+    wallet::OutputShaping,
+};
+
+use super::error::TakerError;
+
+/// Refund locktimes of the contracts of a swap route, in blocks.
+///
+/// The contract paying the Taker at the end of the route gets `base`, and every hop before it
+/// `per_hop_increment` more. Each hop must time out strictly after the next one: a Maker refunded
+/// on its outgoing contract only after its incoming one times out could lose both.
+///
+/// Read from the `refund_locktime` and `refund_locktime_step` config fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimelockPolicy {
+    pub base: u16,
+    pub per_hop_increment: u16,
+}
+
+impl TimelockPolicy {
+    /// Locktimes of the hops of a route through `maker_count` makers, from the Taker's funding to
+    /// the hop paying back the Taker.
+    pub fn hop_locktimes(&self, maker_count: usize) -> Result<Vec<u16>, TakerError> {
+        if self.per_hop_increment == 0 {
+            return Err(TakerError::InvalidTimelockPolicy(
+                "per hop increment must be positive".to_string(),
+            ));
+        }
+        let locktimes = (0..=maker_count)
+            .rev()
+            .map(|hops_after| {
+                u16::try_from(hops_after)
+                    .ok()
+                    .and_then(|hops| self.per_hop_increment.checked_mul(hops))
+                    .and_then(|increment| self.base.checked_add(increment))
+                    .ok_or_else(|| {
+                        TakerError::InvalidTimelockPolicy(format!(
+                            "locktime overflows for {} makers",
+                            maker_count
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        debug_assert!(locktimes.windows(2).all(|hops| hops[0] > hops[1]));
+        Ok(locktimes)
+    }
+}
+
+/// Retries of the Taker's first requests to a Maker, before any of its funds are at stake.
+///
+/// The n-th retry waits `initial_delay * 2^(n-1)`, capped at `max_delay`, less a random jitter of
+/// up to half of it so that retries to the same Maker don't synchronise. Only transport errors are
+/// retried, see [TakerError::is_retriable].
+///
+/// Read from the `first_connect_attempts`, `first_connect_sleep_delay_sec` and
+/// `first_connect_max_delay_sec` config fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryConfig {
+    /// Delay before the `retry`-th retry, counting from 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_delay
+            .checked_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        backoff.mul_f64(OsRng.gen_range(0.5..=1.0))
+    }
+}
 
-use crate::utill::{get_taker_dir, parse_field, parse_toml, write_default_config, ConnectionType};
 /// Taker configuration with refund, connection, and sleep settings.
 #[derive(Debug, Clone, PartialEq)]
 pub struct TakerConfig {
     // TODO: Move all of these to global constants.
-    pub refund_locktime: u16,
-    pub refund_locktime_step: u16,
+    pub timelock_policy: TimelockPolicy,
 
-    pub first_connect_attempts: u32,
-    pub first_connect_sleep_delay_sec: u64,
+    pub first_connect_retry: RetryConfig,
     pub first_connect_attempt_timeout_sec: u64,
 
     pub reconnect_attempts: u32,
@@ -25,20 +105,67 @@ pub struct TakerConfig {
 
     // TODO: Only these should be user facing configs.
     pub port: u16,
-    pub socks_port: u16,
+    /// Read from the `socks_port` and `isolate_tor_streams` config fields.
+    pub tor_config: TorConfig,
     pub directory_server_onion_address: String,
     pub directory_server_clearnet_address: String,
     pub connection_type: ConnectionType,
     pub rpc_port: u16,
+    /// Confirmations required on the funding txs of every hop before the swap proceeds, on top of
+    /// what each Maker's offer asks for. The preimage is only revealed once all of them are met.
+    ///
+    /// A low count is faster, but a reorg of an unconfirmed or shallow funding tx can double-spend
+    /// it after the preimage is out, leaving the other side of the swap claimable without its
+    /// counterpart. Raise it for large amounts.
+    pub min_confirmations: u32,
+    /// Range of fee rates the Taker settles for when a Maker counters its proposal, read in
+    /// sat/vB from the `min_fee_rate` and `max_fee_rate` config fields. The proposed fee rate is
+    /// always acceptable.
+    pub min_fee_rate: FeeRate,
+    pub max_fee_rate: FeeRate,
+    /// Hash function locking the contracts of the swaps, sent to the Makers with the contracts.
+    /// Makers predating [HashlockType::Sha256] only accept the default [HashlockType::Hash160].
+    pub hashlock_type: HashlockType,
+    /// Whether the swap history records which UTXOs of the Taker each Maker saw, see
+    /// [Taker::exposure_report](super::Taker::exposure_report). Off by default: the record links
+    /// the Taker's UTXOs to its swaps, and the swap history isn't encrypted.
+    pub record_utxo_exposure: bool,
+    /// Most of a swap's amount its worst case miner fees may take, in ppm. Smaller swaps are
+    /// refused, see [min_economical_amount](super::min_economical_amount).
+    pub max_tx_fee_ppm: u64,
+    /// Most the makers of a route may charge in total, in sats and in ppm of the swap amount. Routes
+    /// quoting more are refused, see [TakerConfig::max_maker_fee].
+    pub max_maker_fee_sats: u64,
+    pub max_maker_fee_ppm: u64,
+    /// Makers which completed less than this share of their swaps with the Taker are avoided,
+    /// once they took part in `reputation_min_swaps` of them, see
+    /// [ReputationStats](super::ReputationStats).
+    pub min_maker_success_rate: f64,
+    pub reputation_min_swaps: u32,
+    /// How the amounts of the Taker's funding outputs are rounded, read from `output_shaping` as
+    /// `none`, `randomize` or `round_down:<sats>`. The last funding output carries what's rounded
+    /// off of the others, see [OutputShaping].
+    pub output_shaping: OutputShaping,
+    /// Whether the first maker is asked to join the funding transactions with coins of its own,
+    /// so they don't only spend ours. Makers that don't support it are funded alone.
+    pub collaborative_funding: bool,
+    /// Directory of the wallet files, with their swap history and unfinished swap state. A data
+    /// directory given to [Taker::init](super::Taker::init) takes precedence.
+    pub data_dir: PathBuf,
 }
 
 impl Default for TakerConfig {
     fn default() -> Self {
         Self {
-            refund_locktime: 48,
-            refund_locktime_step: 48,
-            first_connect_attempts: 5,
-            first_connect_sleep_delay_sec: 1,
+            timelock_policy: TimelockPolicy {
+                base: 48,
+                per_hop_increment: 48,
+            },
+            first_connect_retry: RetryConfig {
+                max_retries: 5,
+                initial_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(60),
+            },
             first_connect_attempt_timeout_sec: 60,
             reconnect_attempts: 3200,
             reconnect_short_sleep_delay: 10,
@@ -46,16 +173,38 @@ impl Default for TakerConfig {
             short_long_sleep_delay_transition: 60,
             reconnect_attempt_timeout_sec: 300,
             port: 8000,
-            socks_port: 19050,
+            tor_config: TorConfig::local(19050, true),
             directory_server_onion_address: "directoryhiddenserviceaddress.onion:8080".to_string(),
             directory_server_clearnet_address: "127.0.0.1:8080".to_string(),
             connection_type: ConnectionType::TOR,
             rpc_port: 8081,
+            min_confirmations: 1,
+            min_fee_rate: FeeRate::from_sat_per_vb_u32(1),
+            max_fee_rate: FeeRate::from_sat_per_vb_u32(50),
+            hashlock_type: HashlockType::Hash160,
+            record_utxo_exposure: false,
+            max_tx_fee_ppm: 50_000,
+            max_maker_fee_sats: 1_000_000,
+            max_maker_fee_ppm: 100_000,
+            min_maker_success_rate: 0.5,
+            reputation_min_swaps: 3,
+            output_shaping: OutputShaping::None,
+            collaborative_funding: false,
+            data_dir: get_taker_dir(),
         }
     }
 }
 
 impl TakerConfig {
+    /// Most the makers of a route swapping `amount` may charge in total, the lower of
+    /// `max_maker_fee_sats` and `max_maker_fee_ppm` of the amount.
+    pub fn max_maker_fee(&self, amount: Amount) -> Amount {
+        let ppm_limit =
+            u64::try_from(amount.to_sat() as u128 * self.max_maker_fee_ppm as u128 / 1_000_000)
+                .unwrap_or(u64::MAX);
+        Amount::from_sat(self.max_maker_fee_sats.min(ppm_limit))
+    }
+
     /// Constructs a [TakerConfig] from a specified data directory. Or create default configs and load them.
     ///
     /// The maker(/taker).toml file should exist at the provided data-dir location.
@@ -89,26 +238,39 @@ impl TakerConfig {
         let taker_config_section = section.get("taker_config").cloned().unwrap_or_default();
 
         Ok(Self {
-            refund_locktime: parse_field(
-                taker_config_section.get("refund_locktime"),
-                default_config.refund_locktime,
-            )
-            .unwrap_or(default_config.refund_locktime),
-            refund_locktime_step: parse_field(
-                taker_config_section.get("refund_locktime_step"),
-                default_config.refund_locktime_step,
-            )
-            .unwrap_or(default_config.refund_locktime_step),
-            first_connect_attempts: parse_field(
-                taker_config_section.get("first_connect_attempts"),
-                default_config.first_connect_attempts,
-            )
-            .unwrap_or(default_config.first_connect_attempts),
-            first_connect_sleep_delay_sec: parse_field(
-                taker_config_section.get("first_connect_sleep_delay_sec"),
-                default_config.first_connect_sleep_delay_sec,
-            )
-            .unwrap_or(default_config.first_connect_sleep_delay_sec),
+            timelock_policy: TimelockPolicy {
+                base: parse_field(
+                    taker_config_section.get("refund_locktime"),
+                    default_config.timelock_policy.base,
+                )
+                .unwrap_or(default_config.timelock_policy.base),
+                per_hop_increment: parse_field(
+                    taker_config_section.get("refund_locktime_step"),
+                    default_config.timelock_policy.per_hop_increment,
+                )
+                .unwrap_or(default_config.timelock_policy.per_hop_increment),
+            },
+            first_connect_retry: RetryConfig {
+                max_retries: parse_field(
+                    taker_config_section.get("first_connect_attempts"),
+                    default_config.first_connect_retry.max_retries,
+                )
+                .unwrap_or(default_config.first_connect_retry.max_retries),
+                initial_delay: Duration::from_secs(
+                    parse_field(
+                        taker_config_section.get("first_connect_sleep_delay_sec"),
+                        default_config.first_connect_retry.initial_delay.as_secs(),
+                    )
+                    .unwrap_or(default_config.first_connect_retry.initial_delay.as_secs()),
+                ),
+                max_delay: Duration::from_secs(
+                    parse_field(
+                        taker_config_section.get("first_connect_max_delay_sec"),
+                        default_config.first_connect_retry.max_delay.as_secs(),
+                    )
+                    .unwrap_or(default_config.first_connect_retry.max_delay.as_secs()),
+                ),
+            },
             first_connect_attempt_timeout_sec: parse_field(
                 taker_config_section.get("first_connect_attempt_timeout_sec"),
                 default_config.first_connect_attempt_timeout_sec,
@@ -141,11 +303,18 @@ impl TakerConfig {
             .unwrap_or(default_config.reconnect_attempt_timeout_sec),
             port: parse_field(taker_config_section.get("port"), default_config.port)
                 .unwrap_or(default_config.port),
-            socks_port: parse_field(
-                taker_config_section.get("socks_port"),
-                default_config.socks_port,
-            )
-            .unwrap_or(default_config.socks_port),
+            tor_config: TorConfig::local(
+                parse_field(
+                    taker_config_section.get("socks_port"),
+                    default_config.tor_config.proxy.port(),
+                )
+                .unwrap_or(default_config.tor_config.proxy.port()),
+                parse_field(
+                    taker_config_section.get("isolate_tor_streams"),
+                    default_config.tor_config.isolate_streams,
+                )
+                .unwrap_or(default_config.tor_config.isolate_streams),
+            ),
             directory_server_onion_address: taker_config_section
                 .get("directory_server_onion_address")
                 .map(|s| s.to_string())
@@ -164,6 +333,74 @@ impl TakerConfig {
                 default_config.rpc_port,
             )
             .unwrap_or(default_config.rpc_port),
+            min_confirmations: parse_field(
+                taker_config_section.get("min_confirmations"),
+                default_config.min_confirmations,
+            )
+            .unwrap_or(default_config.min_confirmations),
+            min_fee_rate: FeeRate::from_sat_per_vb_u32(
+                parse_field(
+                    taker_config_section.get("min_fee_rate"),
+                    default_config.min_fee_rate.to_sat_per_vb_floor() as u32,
+                )
+                .unwrap_or(default_config.min_fee_rate.to_sat_per_vb_floor() as u32),
+            ),
+            max_fee_rate: FeeRate::from_sat_per_vb_u32(
+                parse_field(
+                    taker_config_section.get("max_fee_rate"),
+                    default_config.max_fee_rate.to_sat_per_vb_floor() as u32,
+                )
+                .unwrap_or(default_config.max_fee_rate.to_sat_per_vb_floor() as u32),
+            ),
+            hashlock_type: parse_field(
+                taker_config_section.get("hashlock_type"),
+                default_config.hashlock_type,
+            )
+            .unwrap_or(default_config.hashlock_type),
+            record_utxo_exposure: parse_field(
+                taker_config_section.get("record_utxo_exposure"),
+                default_config.record_utxo_exposure,
+            )
+            .unwrap_or(default_config.record_utxo_exposure),
+            max_tx_fee_ppm: parse_field(
+                taker_config_section.get("max_tx_fee_ppm"),
+                default_config.max_tx_fee_ppm,
+            )
+            .unwrap_or(default_config.max_tx_fee_ppm),
+            max_maker_fee_sats: parse_field(
+                taker_config_section.get("max_maker_fee_sats"),
+                default_config.max_maker_fee_sats,
+            )
+            .unwrap_or(default_config.max_maker_fee_sats),
+            max_maker_fee_ppm: parse_field(
+                taker_config_section.get("max_maker_fee_ppm"),
+                default_config.max_maker_fee_ppm,
+            )
+            .unwrap_or(default_config.max_maker_fee_ppm),
+            min_maker_success_rate: parse_field(
+                taker_config_section.get("min_maker_success_rate"),
+                default_config.min_maker_success_rate,
+            )
+            .unwrap_or(default_config.min_maker_success_rate),
+            reputation_min_swaps: parse_field(
+                taker_config_section.get("reputation_min_swaps"),
+                default_config.reputation_min_swaps,
+            )
+            .unwrap_or(default_config.reputation_min_swaps),
+            output_shaping: parse_field(
+                taker_config_section.get("output_shaping"),
+                default_config.output_shaping,
+            )
+            .unwrap_or(default_config.output_shaping),
+            collaborative_funding: parse_field(
+                taker_config_section.get("collaborative_funding"),
+                default_config.collaborative_funding,
+            )
+            .unwrap_or(default_config.collaborative_funding),
+            data_dir: taker_config_section
+                .get("data_dir")
+                .map(|s| PathBuf::from(s.trim_matches('"')))
+                .unwrap_or(default_config.data_dir),
         })
     }
 }
@@ -176,6 +413,7 @@ fn write_default_taker_config(config_path: &PathBuf) {
                         refund_locktime_step = 48\n\
                         first_connect_attempts = 5\n\
                         first_connect_sleep_delay_sec = 1\n\
+                        first_connect_max_delay_sec = 60\n\
                         first_connect_attempt_timeout_sec = 60\n\
                         reconnect_attempts = 3200\n\
                         reconnect_short_sleep_delay = 10\n\
@@ -184,10 +422,23 @@ fn write_default_taker_config(config_path: &PathBuf) {
                         reconnect_attempt_timeout_sec = 300\n\
                         port = 8000\n\
                         socks_port = 19050\n\
+                        isolate_tor_streams = true\n\
                         directory_server_onion_address = directoryhiddenserviceaddress.onion:8080\n\
                         directory_server_clearnet_address = 127.0.0.1:8080\n\
                         connection_type = tor\n\
-                        rpc_port = 8081\n
+                        rpc_port = 8081\n\
+                        min_confirmations = 1\n\
+                        min_fee_rate = 1\n\
+                        max_fee_rate = 50\n\
+                        hashlock_type = hash160\n\
+                        record_utxo_exposure = false\n\
+                        max_tx_fee_ppm = 50000\n\
+                        max_maker_fee_sats = 1000000\n\
+                        max_maker_fee_ppm = 100000\n\
+                        min_maker_success_rate = 0.5\n\
+                        reputation_min_swaps = 3\n\
+                        output_shaping = none\n\
+                        collaborative_funding = false\n
                         ",
     );
     write_default_config(config_path, config_string).unwrap();
@@ -221,6 +472,7 @@ mod tests {
         refund_locktime_step = 48
         first_connect_attempts = 5
         first_connect_sleep_delay_sec = 1
+        first_connect_max_delay_sec = 60
         first_connect_attempt_timeout_sec = 60
         reconnect_attempts = 3200
         reconnect_short_sleep_delay = 10
@@ -229,6 +481,10 @@ mod tests {
         reconnect_attempt_timeout_sec = 300
         port = 8000
         socks_port = 19050
+        min_confirmations = 1
+        min_fee_rate = 1
+        max_fee_rate = 50
+        hashlock_type = hash160
         "#;
         let config_path = create_temp_config(contents, "valid_taker_config.toml");
         let config = TakerConfig::new(Some(&config_path)).unwrap();
@@ -248,7 +504,7 @@ mod tests {
         let config = TakerConfig::new(Some(&config_path)).unwrap();
         remove_temp_config(&config_path);
 
-        assert_eq!(config.refund_locktime, 48);
+        assert_eq!(config.timelock_policy.base, 48);
         assert_eq!(config, TakerConfig::default());
     }
 
@@ -274,16 +530,69 @@ mod tests {
         let config_path = create_temp_config(contents, "different_data_taker_config.toml");
         let config = TakerConfig::new(Some(&config_path)).unwrap();
         remove_temp_config(&config_path);
-        assert_eq!(config.refund_locktime, 49);
+        assert_eq!(config.timelock_policy.base, 49);
         assert_eq!(
             TakerConfig {
-                refund_locktime: 48,
+                timelock_policy: TakerConfig::default().timelock_policy,
                 ..config
             },
             TakerConfig::default()
         )
     }
 
+    #[test]
+    fn test_min_confirmations() {
+        let contents = r#"
+            [taker_config]
+            min_confirmations = 6
+        "#;
+        let config_path = create_temp_config(contents, "min_confirmations_taker_config.toml");
+        let config = TakerConfig::new(Some(&config_path)).unwrap();
+        remove_temp_config(&config_path);
+        assert_eq!(config.min_confirmations, 6);
+    }
+
+    #[test]
+    fn test_hashlock_type() {
+        let contents = r#"
+            [taker_config]
+            hashlock_type = sha256
+        "#;
+        let config_path = create_temp_config(contents, "hashlock_type_taker_config.toml");
+        let config = TakerConfig::new(Some(&config_path)).unwrap();
+        remove_temp_config(&config_path);
+        assert_eq!(config.hashlock_type, HashlockType::Sha256);
+    }
+
+    #[test]
+    fn test_max_maker_fee() {
+        let config = TakerConfig {
+            max_maker_fee_sats: 50_000,
+            max_maker_fee_ppm: 20_000,
+            ..TakerConfig::default()
+        };
+        assert_eq!(
+            config.max_maker_fee(Amount::from_sat(1_000_000)),
+            Amount::from_sat(20_000)
+        );
+        assert_eq!(
+            config.max_maker_fee(Amount::from_sat(10_000_000)),
+            Amount::from_sat(50_000)
+        );
+    }
+
+    #[test]
+    fn test_data_dir() {
+        let contents = r#"
+            [taker_config]
+            data_dir = "/var/lib/coinswap/taker"
+        "#;
+        let config_path = create_temp_config(contents, "data_dir_taker_config.toml");
+        let config = TakerConfig::new(Some(&config_path)).unwrap();
+        remove_temp_config(&config_path);
+        assert_eq!(config.data_dir, PathBuf::from("/var/lib/coinswap/taker"));
+    }
+
     #[test]
     fn test_missing_file() {
         let config_path = get_taker_dir().join("taker.toml");
@@ -291,4 +600,47 @@ mod tests {
         remove_temp_config(&config_path);
         assert_eq!(config, TakerConfig::default());
     }
+
+    #[test]
+    fn test_timelock_policy() {
+        let policy = TimelockPolicy {
+            base: 20,
+            per_hop_increment: 10,
+        };
+        assert_eq!(policy.hop_locktimes(2).unwrap(), [40, 30, 20]);
+        assert_eq!(policy.hop_locktimes(0).unwrap(), [20]);
+
+        assert!(matches!(
+            TimelockPolicy {
+                per_hop_increment: 0,
+                ..policy
+            }
+            .hop_locktimes(2),
+            Err(TakerError::InvalidTimelockPolicy(_))
+        ));
+        assert!(matches!(
+            TimelockPolicy {
+                base: u16::MAX - 15,
+                ..policy
+            }
+            .hop_locktimes(2),
+            Err(TakerError::InvalidTimelockPolicy(_))
+        ));
+    }
+
+    #[test]
+    fn test_retry_config_backoff() {
+        let retry = RetryConfig {
+            max_retries: 5,
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(10),
+        };
+        for _ in 0..20 {
+            for (n, expected) in [(1, 2), (2, 4), (3, 8), (4, 10), (u32::MAX, 10)] {
+                let delay = retry.delay(n);
+                let expected = Duration::from_secs(expected);
+                assert!(delay <= expected && delay >= expected / 2);
+            }
+        }
+    }
 }
@@ -23,9 +23,34 @@ pub enum TakerError {
     Protocol(ProtocolError),
     SendAmountNotSet,
     FundingTxWaitTimeOut,
+    InvalidTimelockPolicy(String),
+    SwapNotFound(String),
+    UneconomicalSwap {
+        amount: bitcoin::Amount,
+        minimum: bitcoin::Amount,
+    },
+    /// The maker fees of the cheapest route exceed the configured maximum.
+    FeesTooHigh {
+        quoted: bitcoin::Amount,
+        limit: bitcoin::Amount,
+    },
+    /// A shutdown was requested. An interrupted swap round is resumed with `Taker::resume`.
+    ShuttingDown,
     Deserialize(serde_cbor::Error),
 }
 
+impl TakerError {
+    /// Whether the error is a transport failure, worth retrying on a new connection. Protocol
+    /// errors would fail the same way again.
+    pub fn is_retriable(&self) -> bool {
+        matches!(
+            self,
+            Self::IO(_)
+                | Self::Net(NetError::IO(_) | NetError::ReachedEOF | NetError::ConnectionTimedOut)
+        )
+    }
+}
+
 impl From<serde_cbor::Error> for TakerError {
     fn from(value: serde_cbor::Error) -> Self {
         Self::Deserialize(value)
@@ -0,0 +1,279 @@
+//! Policies to pick the makers of a swap route out of the offerbook.
+//!
+//! The taker scores every eligible offer into a [MakerInfo] and hands them to its [MakerSelector].
+//! Set a policy with [Taker::set_maker_selector](super::Taker::set_maker_selector). The default is
+//! [RandomizedWeighted].
+//!
+//! The fidelity bond is the only sybil resistance of the offerbook. Its value, [FidelityBond::score](crate::wallet::FidelityBond::score),
+//! grows superlinearly with the amount and the locktime of the bond. So sybil makers splitting the
+//! same coins over many bonds are worth less than one maker with a single bond, and weighting the
+//! selection by bond value makes such attacks expensive.
+
+use std::cmp::Reverse;
+
+use bitcoin::{
+    secp256k1::rand::{rngs::OsRng, Rng},
+    Amount,
+};
+
+use super::{error::TakerError, offers::OfferAndAddress};
+
+/// A maker offer, scored by the taker for the swap being routed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MakerInfo {
+    pub offer_and_address: OfferAndAddress,
+    /// Value of the maker's fidelity bond, at the current chain tip.
+    pub bond_value: Amount,
+    /// Fee the maker asks for the swap amount, at the base refund locktime of the taker's [TimelockPolicy](super::TimelockPolicy).
+    pub swap_fee: Amount,
+}
+
+/// Picks makers for a swap route.
+pub trait MakerSelector {
+    /// Returns at most `count` distinct makers out of `candidates`, in order of preference.
+    fn select(&self, candidates: &[MakerInfo], count: usize) -> Vec<MakerInfo>;
+}
+
+/// Makers with the most valuable fidelity bonds first.
+///
+/// Every taker using it picks the same makers, which helps an observer link the hops of a swap.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HighestBondFirst;
+
+impl MakerSelector for HighestBondFirst {
+    fn select(&self, candidates: &[MakerInfo], count: usize) -> Vec<MakerInfo> {
+        let mut candidates = candidates.to_vec();
+        candidates.sort_by_key(|m| Reverse(m.bond_value));
+        candidates.truncate(count);
+        candidates
+    }
+}
+
+/// Cheapest makers first. Ties go to the most valuable fidelity bond.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LowestFee;
+
+impl MakerSelector for LowestFee {
+    fn select(&self, candidates: &[MakerInfo], count: usize) -> Vec<MakerInfo> {
+        let mut candidates = candidates.to_vec();
+        candidates.sort_by(|a, b| {
+            a.swap_fee
+                .cmp(&b.swap_fee)
+                .then(b.bond_value.cmp(&a.bond_value))
+        });
+        candidates.truncate(count);
+        candidates
+    }
+}
+
+/// Random makers, each picked with a probability proportional to its bond value.
+///
+/// This is how JoinMarket picks its makers. Makers without bond value are only picked once all
+/// the others are, uniformly at random.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomizedWeighted;
+
+impl MakerSelector for RandomizedWeighted {
+    fn select(&self, candidates: &[MakerInfo], count: usize) -> Vec<MakerInfo> {
+        let mut remaining = candidates.to_vec();
+        let mut selected = Vec::new();
+        while selected.len() < count && !remaining.is_empty() {
+            let total_weight = remaining
+                .iter()
+                .map(|m| m.bond_value.to_sat())
+                .fold(0u64, u64::saturating_add);
+            let index = if total_weight == 0 {
+                OsRng.gen_range(0..remaining.len())
+            } else {
+                let mut target = OsRng.gen_range(0..total_weight);
+                remaining
+                    .iter()
+                    .position(|m| match target.checked_sub(m.bond_value.to_sat()) {
+                        Some(rest) => {
+                            target = rest;
+                            false
+                        }
+                        None => true,
+                    })
+                    .expect("target is below the total weight")
+            };
+            selected.push(remaining.remove(index));
+        }
+        selected
+    }
+}
+
+/// Keeps the `candidates` a swap route can go on with, without its maker fees exceeding `limit`.
+///
+/// `quoted` is the fee of the makers already in the route, and `remaining` the count of makers it
+/// still needs, the next one included. A candidate is kept if its fee and the cheapest fees of
+/// `remaining - 1` other candidates fit. Errors with [TakerError::FeesTooHigh] if even the cheapest
+/// route doesn't.
+pub(crate) fn filter_by_fee_limit(
+    candidates: Vec<MakerInfo>,
+    quoted: Amount,
+    remaining: usize,
+    limit: Amount,
+) -> Result<Vec<MakerInfo>, TakerError> {
+    let mut fees = candidates.iter().map(|m| m.swap_fee).collect::<Vec<_>>();
+    // Not enough makers is reported by the caller.
+    if remaining == 0 || fees.len() < remaining {
+        return Ok(candidates);
+    }
+    fees.sort();
+    let cheapest_others = fees[..remaining - 1].iter().copied().sum::<Amount>();
+    let cheapest_route = quoted + cheapest_others + fees[remaining - 1];
+    if cheapest_route > limit {
+        return Err(TakerError::FeesTooHigh {
+            quoted: cheapest_route,
+            limit,
+        });
+    }
+    // A candidate among the cheapest others is within the cheapest route, which fits.
+    Ok(candidates
+        .into_iter()
+        .filter(|m| quoted + cheapest_others + m.swap_fee <= limit)
+        .collect())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        protocol::{
+            contract::MakerFeePolicy,
+            messages::{FidelityProof, Offer, OFFER_VERSION},
+        },
+        taker::offers::MakerAddress,
+        wallet::FidelityBond,
+    };
+    use bitcoin::{
+        absolute::LockTime,
+        hashes::Hash,
+        secp256k1::{Message, Secp256k1, SecretKey},
+        Network, OutPoint, PublicKey,
+    };
+
+    pub(crate) fn maker(port: u16, bond_value: u64, swap_fee: u64) -> MakerInfo {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let pubkey = PublicKey::new(secret_key.public_key(&secp));
+        let cert_sig = secp.sign_ecdsa(&Message::from_digest([0; 32]), &secret_key);
+        let offer = Offer {
+            version: OFFER_VERSION,
+            fee_policy: MakerFeePolicy::default(),
+            time_relative_fee_ppb: Amount::from_sat(100_000),
+            required_confirms: 1,
+            minimum_locktime: 48,
+            tweakable_point: pubkey,
+            fidelity: FidelityProof {
+                bond: FidelityBond {
+                    outpoint: OutPoint::null(),
+                    amount: Amount::from_sat(1_000_000),
+                    lock_time: LockTime::from_height(500).unwrap(),
+                    pubkey,
+                    conf_height: 100,
+                    cert_expiry: 1,
+                },
+                cert_hash: Hash::all_zeros(),
+                cert_sig,
+            },
+            network: Network::Regtest,
+            offer_sig: cert_sig,
+        };
+        MakerInfo {
+            offer_and_address: OfferAndAddress {
+                offer,
+                address: MakerAddress::new(&format!("127.0.0.1:{}", port)).unwrap(),
+            },
+            bond_value: Amount::from_sat(bond_value),
+            swap_fee: Amount::from_sat(swap_fee),
+        }
+    }
+
+    fn ports(makers: &[MakerInfo]) -> Vec<String> {
+        makers
+            .iter()
+            .map(|m| m.offer_and_address.address.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_offer_version_is_signed() {
+        let mut offer = maker(6102, 1_000_000, 1000).offer_and_address.offer;
+        assert_eq!(offer.version, OFFER_VERSION);
+
+        // The test offer's keys are derived from this secret.
+        let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        offer.offer_sig = Secp256k1::new().sign_ecdsa(
+            &Message::from_digest(offer.terms_hash().to_byte_array()),
+            &secret_key,
+        );
+        offer.verify_signature().unwrap();
+
+        offer.version = 1;
+        assert!(offer.verify_signature().is_err());
+    }
+
+    #[test]
+    fn test_highest_bond_first_and_lowest_fee() {
+        let candidates = [maker(1, 10, 300), maker(2, 30, 100), maker(3, 20, 100)];
+
+        assert_eq!(
+            ports(&HighestBondFirst.select(&candidates, 2)),
+            ["127.0.0.1:2", "127.0.0.1:3"]
+        );
+        assert_eq!(
+            ports(&LowestFee.select(&candidates, 3)),
+            ["127.0.0.1:2", "127.0.0.1:3", "127.0.0.1:1"]
+        );
+        assert_eq!(LowestFee.select(&candidates, 5).len(), 3);
+        assert!(HighestBondFirst.select(&[], 1).is_empty());
+    }
+
+    #[test]
+    fn test_randomized_weighted() {
+        let candidates = [maker(1, 0, 100), maker(2, 1_000, 100), maker(3, 0, 100)];
+
+        for _ in 0..20 {
+            // The only maker with bond value always goes first.
+            let selected = RandomizedWeighted.select(&candidates, 3);
+            assert_eq!(selected[0], candidates[1]);
+            assert_eq!(selected.len(), 3);
+            assert!(selected.contains(&candidates[0]) && selected.contains(&candidates[2]));
+        }
+        assert_eq!(RandomizedWeighted.select(&candidates, 1).len(), 1);
+    }
+
+    #[test]
+    fn test_filter_by_fee_limit() {
+        let candidates = vec![maker(1, 0, 100), maker(2, 0, 300), maker(3, 0, 500)];
+        let filter = |quoted, remaining, limit| {
+            filter_by_fee_limit(
+                candidates.clone(),
+                Amount::from_sat(quoted),
+                remaining,
+                Amount::from_sat(limit),
+            )
+        };
+
+        // Routes of two makers: 1 and 2 fit together, 3 fits with none.
+        assert_eq!(
+            ports(&filter(0, 2, 400).unwrap()),
+            ["127.0.0.1:1", "127.0.0.1:2"]
+        );
+        assert_eq!(filter(0, 2, 1_000).unwrap(), candidates);
+        // The makers already in the route count.
+        assert_eq!(
+            ports(&filter(150, 1, 450).unwrap()),
+            ["127.0.0.1:1", "127.0.0.1:2"]
+        );
+        assert!(matches!(
+            filter(0, 2, 399),
+            Err(TakerError::FeesTooHigh { quoted, limit })
+                if quoted == Amount::from_sat(400) && limit == Amount::from_sat(399)
+        ));
+        // Too few candidates is left to the caller.
+        assert_eq!(filter(0, 4, 0).unwrap().len(), 3);
+    }
+}
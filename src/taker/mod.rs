@@ -7,9 +7,21 @@
 mod api;
 mod config;
 pub mod error;
+mod maker_selection;
 pub mod offers;
+mod reputation;
 mod routines;
+mod split;
+mod swap_cost;
+mod swap_state;
 
 pub use self::api::TakerBehavior;
 pub use api::{SwapParams, Taker};
-pub use config::TakerConfig;
+pub use config::{RetryConfig, TakerConfig, TimelockPolicy};
+pub use maker_selection::{
+    HighestBondFirst, LowestFee, MakerInfo, MakerSelector, RandomizedWeighted,
+};
+pub use reputation::ReputationStats;
+pub use split::{split_amount, SwapResult};
+pub use swap_cost::{estimate_swap_cost, min_economical_amount, SwapCostBreakdown};
+pub use swap_state::{SwapPhase, SwapState};
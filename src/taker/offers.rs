@@ -13,12 +13,13 @@ use std::{
     thread::{self, Builder},
 };
 
+use bitcoin::hex::FromHex;
 use serde::{Deserialize, Serialize};
 use socks::Socks5Stream;
 
 use crate::{
     error::NetError,
-    protocol::messages::Offer,
+    protocol::messages::{Offer, OFFER_VERSION},
     utill::{ConnectionType, GLOBAL_PAUSE, NET_TIMEOUT},
 };
 
@@ -232,3 +233,49 @@ pub fn fetch_addresses_from_dns(
         }
     }
 }
+
+/// Retrieves the offers the makers published to a directory server.
+///
+/// The offers aren't verified, check their signatures and fidelity proofs before use.
+pub fn fetch_offers_from_dns(
+    socks_port: Option<u16>,
+    directory_server_address: String,
+    connection_type: ConnectionType,
+) -> Result<Vec<OfferAndAddress>, TakerError> {
+    let mut stream = match connection_type {
+        ConnectionType::CLEARNET => TcpStream::connect(directory_server_address.as_str())?,
+        ConnectionType::TOR => {
+            let socket_addrs = format!("127.0.0.1:{}", socks_port.expect("Tor port expected"));
+            Socks5Stream::connect(socket_addrs, directory_server_address.as_str())?.into_inner()
+        }
+    };
+
+    stream.set_read_timeout(Some(NET_TIMEOUT))?;
+    stream.set_write_timeout(Some(NET_TIMEOUT))?;
+    stream.write_all("GET_OFFERS\n".as_bytes())?;
+    stream.flush()?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    Ok(response
+        .lines()
+        .filter_map(|line| {
+            let (address, offer) = line.split_once(' ')?;
+            let offer = Vec::<u8>::from_hex(offer)
+                .ok()
+                .and_then(|bytes| serde_cbor::from_slice::<Offer>(&bytes).ok())
+                .filter(|offer| offer.version == OFFER_VERSION);
+            match (MakerAddress::new(address), offer) {
+                (Ok(address), Some(offer)) => Some(OfferAndAddress { offer, address }),
+                _ => {
+                    log::warn!(
+                        "Malformed or unsupported offer from directory for maker: {}",
+                        address
+                    );
+                    None
+                }
+            }
+        })
+        .collect())
+}
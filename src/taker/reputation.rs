@@ -0,0 +1,174 @@
+//! Local reputation of the makers, to avoid those that keep aborting swaps.
+//!
+//! A maker aborting a swap after the taker funded it wastes the taker's miner fees, and locks its
+//! funds until the refund. The taker counts, per maker, the swaps that completed and the ones the
+//! maker failed once the taker's funds were committed. Makers are known by the outpoint of their
+//! fidelity bond: unlike an address, a new one costs a new bond.
+//!
+//! Makers whose success rate falls below the configured
+//! [`min_maker_success_rate`](super::TakerConfig::min_maker_success_rate) are avoided. The counts
+//! are local to the wallet, in a file next to it, and never shared.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bitcoin::OutPoint;
+use serde::{Deserialize, Serialize};
+
+use super::error::TakerError;
+
+/// Swap outcomes of a maker, as seen by this taker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReputationStats {
+    /// Swaps the maker completed.
+    pub successes: u32,
+    /// Swaps the maker failed after the taker committed its funds.
+    pub failures: u32,
+}
+
+impl ReputationStats {
+    pub fn total(&self) -> u32 {
+        self.successes.saturating_add(self.failures)
+    }
+
+    /// Share of the swaps that completed, `None` before any swap.
+    pub fn success_rate(&self) -> Option<f64> {
+        match self.total() {
+            0 => None,
+            total => Some(self.successes as f64 / total as f64),
+        }
+    }
+
+    /// Whether the maker is avoided: it took part in at least `min_swaps` swaps, and completed
+    /// less than `min_success_rate` of them.
+    pub fn is_avoided(&self, min_success_rate: f64, min_swaps: u32) -> bool {
+        self.total() >= min_swaps
+            && self
+                .success_rate()
+                .is_some_and(|rate| rate < min_success_rate)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReputationEntry {
+    bond_outpoint: OutPoint,
+    stats: ReputationStats,
+}
+
+/// The reputation file of a wallet, see the [module docs](self).
+#[derive(Debug)]
+pub(super) struct ReputationStore {
+    path: PathBuf,
+    makers: HashMap<OutPoint, ReputationStats>,
+}
+
+impl ReputationStore {
+    /// Path of the reputation file of the wallet at `wallet_path`.
+    pub(super) fn path_for_wallet(wallet_path: &Path) -> PathBuf {
+        let mut file_name = wallet_path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".reputation");
+        wallet_path.with_file_name(file_name)
+    }
+
+    /// Reads the store at `path`. Empty if there's no file yet.
+    pub(super) fn load(path: PathBuf) -> Result<Self, TakerError> {
+        let makers = if path.exists() {
+            serde_cbor::from_slice::<Vec<ReputationEntry>>(&fs::read(&path)?)?
+                .into_iter()
+                .map(|entry| (entry.bond_outpoint, entry.stats))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        Ok(Self { path, makers })
+    }
+
+    pub(super) fn stats(&self, bond_outpoint: &OutPoint) -> ReputationStats {
+        self.makers.get(bond_outpoint).copied().unwrap_or_default()
+    }
+
+    /// Counts a swap of the maker with the bond at `bond_outpoint`, and saves the store.
+    pub(super) fn record(
+        &mut self,
+        bond_outpoint: OutPoint,
+        success: bool,
+    ) -> Result<(), TakerError> {
+        let stats = self.makers.entry(bond_outpoint).or_default();
+        if success {
+            stats.successes = stats.successes.saturating_add(1);
+        } else {
+            stats.failures = stats.failures.saturating_add(1);
+        }
+        self.write_to_disk()
+    }
+
+    /// Writes the store through a temporary file, so a crash never leaves it half written.
+    fn write_to_disk(&self) -> Result<(), TakerError> {
+        let entries = self
+            .makers
+            .iter()
+            .map(|(bond_outpoint, stats)| ReputationEntry {
+                bond_outpoint: *bond_outpoint,
+                stats: *stats,
+            })
+            .collect::<Vec<_>>();
+        let mut tmp_path = self.path.as_os_str().to_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, serde_cbor::to_vec(&entries)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoind::tempfile::tempdir;
+
+    #[test]
+    fn test_reputation_stats() {
+        let stats = ReputationStats {
+            successes: 1,
+            failures: 3,
+        };
+        assert_eq!(stats.success_rate(), Some(0.25));
+        assert!(stats.is_avoided(0.5, 3));
+        // Not enough swaps to judge.
+        assert!(!stats.is_avoided(0.5, 5));
+        assert!(!stats.is_avoided(0.2, 3));
+        assert_eq!(ReputationStats::default().success_rate(), None);
+        assert!(!ReputationStats::default().is_avoided(0.5, 0));
+    }
+
+    #[test]
+    fn test_reputation_store() {
+        let temp_dir = tempdir().unwrap();
+        let path = ReputationStore::path_for_wallet(&temp_dir.path().join("taker-wallet"));
+        assert_eq!(path, temp_dir.path().join("taker-wallet.reputation"));
+
+        let bond = OutPoint {
+            txid: "d2b4d1c3a1e1c6b8b4f3a1d3b2c1a0f9e8d7c6b5a4f3e2d1c0b9a8f7e6d5c4b3"
+                .parse()
+                .unwrap(),
+            vout: 1,
+        };
+        let mut store = ReputationStore::load(path.clone()).unwrap();
+        assert_eq!(store.stats(&bond), ReputationStats::default());
+        store.record(bond, true).unwrap();
+        store.record(bond, false).unwrap();
+        store.record(bond, false).unwrap();
+
+        let store = ReputationStore::load(path).unwrap();
+        assert_eq!(
+            store.stats(&bond),
+            ReputationStats {
+                successes: 1,
+                failures: 2,
+            }
+        );
+        assert_eq!(store.stats(&OutPoint::null()), ReputationStats::default());
+    }
+}
@@ -7,31 +7,30 @@
 //! for communication between taker and maker.
 
 use serde::{Deserialize, Serialize};
-use socks::Socks5Stream;
-use std::{io::ErrorKind, net::TcpStream, thread::sleep, time::Duration};
+use std::{net::TcpStream, thread::sleep, time::Duration};
 
 use crate::{
     error::ProtocolError,
     protocol::{
         contract::{
             calculate_coinswap_fee, create_contract_redeemscript, find_funding_output_index,
-            validate_contract_tx, FUNDING_TX_VBYTE_SIZE,
+            validate_contract_tx, Hashlock, FUNDING_TX_VBYTE_SIZE,
         },
         messages::{
-            ContractSigsAsRecvrAndSender, ContractSigsForRecvr, ContractSigsForSender,
-            ContractTxInfoForRecvr, ContractTxInfoForSender, FundingTxInfo, GiveOffer,
-            HashPreimage, MakerToTakerMessage, NextHopInfo, Offer, Preimage, PrivKeyHandover,
-            ProofOfFunding, ReqContractSigsForRecvr, ReqContractSigsForSender, TakerHello,
-            TakerToMakerMessage,
+            negotiate_protocol_version, CollabFunding, ContractSigsAsRecvrAndSender,
+            ContractSigsForRecvr, ContractSigsForSender, ContractTxInfoForRecvr,
+            ContractTxInfoForSender, FeeNegotiation, FundingTxInfo, GiveOffer, HashPreimage,
+            MakerToTakerMessage, NextHopInfo, Offer, Preimage, PrivKeyHandover, ProofOfFunding,
+            ReqContractSigsForRecvr, ReqContractSigsForSender, TakerHello, TakerToMakerMessage,
+            OFFER_VERSION, PROTOCOL_VERSION_FEE_NEGOTIATION, SUPPORTED_PROTOCOL_VERSIONS,
         },
-        Hash160,
     },
     utill::{read_message, send_message, ConnectionType},
 };
-use bitcoin::{secp256k1::SecretKey, Amount, PublicKey, ScriptBuf, Transaction};
+use bitcoin::{secp256k1::SecretKey, Amount, FeeRate, PublicKey, ScriptBuf, Transaction};
 
 use super::{
-    config::TakerConfig,
+    config::{RetryConfig, TakerConfig},
     error::TakerError,
     offers::{MakerAddress, OfferAndAddress},
 };
@@ -54,34 +53,37 @@ pub struct ContractsInfo {
 }
 
 /// Make a handshake with a maker.
-/// Ensures that the Maker is alive and responding.
-///
-// In future, handshake can be used to find protocol compatibility across multiple versions.
-pub fn handshake_maker(socket: &mut TcpStream) -> Result<(), TakerError> {
+/// Ensures that the Maker is alive and responding, and speaks a protocol version we support.
+/// Returns the protocol version agreed on.
+pub fn handshake_maker(socket: &mut TcpStream) -> Result<u32, TakerError> {
     send_message(
         socket,
         &TakerToMakerMessage::TakerHello(TakerHello {
-            protocol_version_min: 1,
-            protocol_version_max: 1,
+            protocol_version_min: SUPPORTED_PROTOCOL_VERSIONS.0,
+            protocol_version_max: SUPPORTED_PROTOCOL_VERSIONS.1,
         }),
     )?;
     let msg_bytes = read_message(socket)?;
     let msg: MakerToTakerMessage = serde_cbor::from_slice(&msg_bytes)?;
 
-    // Check that protocol version is always 1.
     match msg {
         MakerToTakerMessage::MakerHello(m) => {
-            if m.protocol_version_max == 1 && m.protocol_version_min == 1 {
-                Ok(())
-            } else {
-                Err(ProtocolError::WrongMessage {
-                    expected: "Only protocol version 1 is allowed".to_string(),
+            match negotiate_protocol_version(
+                SUPPORTED_PROTOCOL_VERSIONS,
+                (m.protocol_version_min, m.protocol_version_max),
+            ) {
+                Some(protocol_version) => Ok(protocol_version),
+                None => Err(ProtocolError::WrongMessage {
+                    expected: format!(
+                        "A protocol version within {}/{}",
+                        SUPPORTED_PROTOCOL_VERSIONS.0, SUPPORTED_PROTOCOL_VERSIONS.1
+                    ),
                     received: format!(
                         "min/max version  = {}/{}",
                         m.protocol_version_min, m.protocol_version_max
                     ),
                 }
-                .into())
+                .into()),
             }
         }
         any => Err((ProtocolError::WrongMessage {
@@ -102,6 +104,23 @@ pub(crate) fn req_sigs_for_sender_once<S: SwapCoin>(
 ) -> Result<ContractSigsForSender, TakerError> {
     log::info!("Connecting to {}", socket.peer_addr()?);
     handshake_maker(socket)?;
+    req_contract_sigs_for_sender(
+        socket,
+        outgoing_swapcoins,
+        maker_multisig_nonces,
+        maker_hashlock_nonces,
+        locktime,
+    )
+}
+
+/// Request signatures for sender side of the hop, on a connection past the handshake.
+pub(crate) fn req_contract_sigs_for_sender<S: SwapCoin>(
+    socket: &mut TcpStream,
+    outgoing_swapcoins: &[S],
+    maker_multisig_nonces: &[SecretKey],
+    maker_hashlock_nonces: &[SecretKey],
+    locktime: u16,
+) -> Result<ContractSigsForSender, TakerError> {
     log::info!(
         "===> Sending ReqContractSigsForSender to {}",
         socket.peer_addr()?
@@ -128,11 +147,11 @@ pub(crate) fn req_sigs_for_sender_once<S: SwapCoin>(
 
     send_message(
         socket,
-        &TakerToMakerMessage::ReqContractSigsForSender(ReqContractSigsForSender {
+        &TakerToMakerMessage::ReqContractSigsForSender(ReqContractSigsForSender::new(
             txs_info,
-            hashvalue: outgoing_swapcoins[0].get_hashvalue(),
+            outgoing_swapcoins[0].get_hashlock(),
             locktime,
-        }),
+        )),
     )?;
 
     let msg_bytes = read_message(socket)?;
@@ -257,7 +276,7 @@ pub(crate) fn send_proof_of_funding_and_init_next_hop(
     socket: &mut TcpStream,
     tmi: ThisMakerInfo,
     npi: NextPeerInfoArgs,
-    hashvalue: Hash160,
+    hashlock: Hashlock,
 ) -> Result<(ContractSigsAsRecvrAndSender, Vec<ScriptBuf>), TakerError> {
     // Send POF
     let next_coinswap_info = npi
@@ -335,12 +354,12 @@ pub(crate) fn send_proof_of_funding_and_init_next_hop(
         .map(|i| i.funding_amount)
         .sum::<Amount>();
     let coinswap_fees = calculate_coinswap_fee(
-        tmi.this_maker.offer.absolute_fee_sat,
-        tmi.this_maker.offer.amount_relative_fee_ppb,
+        &tmi.this_maker.offer.fee_policy,
         tmi.this_maker.offer.time_relative_fee_ppb,
         Amount::from_sat(this_amount),
         1, //time_in_blocks just 1 for now
-    );
+    )
+    .map_err(ProtocolError::Contract)?;
     let miner_fees_paid_by_taker = (FUNDING_TX_VBYTE_SIZE
         * npi.next_maker_fee_rate.to_sat()
         * (npi.next_peer_multisig_pubkeys.len() as u64))
@@ -391,11 +410,24 @@ pub(crate) fn send_proof_of_funding_and_init_next_hop(
             create_contract_redeemscript(
                 hashlock_pubkey,
                 &senders_contract_tx_info.timelock_pubkey,
-                &hashvalue,
+                &hashlock,
                 &npi.next_maker_refund_locktime,
             )
         })
         .collect::<Vec<_>>();
+    // The maker's contracts must pay the next peer's hashlock key, and the maker's timelock key.
+    for (senders_contract_tx_info, contract_redeemscript) in contract_sigs_as_recvr_and_sender
+        .senders_contract_txs_info
+        .iter()
+        .zip(next_swap_contract_redeemscripts.iter())
+    {
+        validate_contract_tx(
+            &senders_contract_tx_info.contract_tx,
+            None,
+            contract_redeemscript,
+        )
+        .map_err(ProtocolError::Contract)?;
+    }
     Ok((
         contract_sigs_as_recvr_and_sender,
         next_swap_contract_redeemscripts,
@@ -443,19 +475,79 @@ pub(crate) fn send_hash_preimage_and_get_private_keys(
     Ok(privkey_handover)
 }
 
+/// Negotiate the fee rate of the transactions a maker funds, before any of them is built.
+/// Returns the agreed fee rate, or `None` if the maker doesn't speak
+/// [PROTOCOL_VERSION_FEE_NEGOTIATION].
+pub(crate) fn negotiate_fee_rate(
+    maker: &OfferAndAddress,
+    config: &TakerConfig,
+    negotiation: FeeNegotiation,
+) -> Result<Option<FeeRate>, TakerError> {
+    let address = maker.address.to_string();
+    let mut socket = connect_to_maker(&address, config)?;
+
+    socket.set_read_timeout(Some(Duration::from_secs(
+        config.first_connect_attempt_timeout_sec,
+    )))?;
+    socket.set_write_timeout(Some(Duration::from_secs(
+        config.first_connect_attempt_timeout_sec,
+    )))?;
+
+    if handshake_maker(&mut socket)? < PROTOCOL_VERSION_FEE_NEGOTIATION {
+        return Ok(None);
+    }
+
+    send_message(
+        &mut socket,
+        &TakerToMakerMessage::ReqFeeNegotiation(negotiation),
+    )?;
+
+    let msg_bytes = read_message(&mut socket)?;
+    let msg: MakerToTakerMessage = serde_cbor::from_slice(&msg_bytes)?;
+    let response = match msg {
+        MakerToTakerMessage::RespFeeNegotiation(response) => response,
+        msg => {
+            return Err(TakerError::Protocol(ProtocolError::WrongMessage {
+                expected: "RespFeeNegotiation".to_string(),
+                received: format!("{}", msg),
+            }));
+        }
+    };
+
+    negotiation
+        .agreed_fee_rate(&response)
+        .map(Some)
+        .ok_or_else(|| ProtocolError::FeeRateOutOfBounds(negotiation.proposed).into())
+}
+
+/// Request the maker to join the funding transactions of the first hop, see [CollabFunding].
+/// Sent on the connection the maker just signed the contracts of those transactions on.
+pub(crate) fn req_collab_funding(
+    socket: &mut TcpStream,
+    collab_funding: CollabFunding,
+) -> Result<CollabFunding, TakerError> {
+    send_message(
+        socket,
+        &TakerToMakerMessage::ReqCollabFunding(collab_funding),
+    )?;
+
+    let msg_bytes = read_message(socket)?;
+    let msg: MakerToTakerMessage = serde_cbor::from_slice(&msg_bytes)?;
+    match msg {
+        MakerToTakerMessage::RespCollabFunding(joined) => Ok(joined),
+        msg => Err(TakerError::Protocol(ProtocolError::WrongMessage {
+            expected: "RespCollabFunding".to_string(),
+            received: format!("{}", msg),
+        })),
+    }
+}
+
 fn download_maker_offer_attempt_once(
     addr: &MakerAddress,
     config: &TakerConfig,
 ) -> Result<Offer, TakerError> {
     let address = addr.to_string();
-    let mut socket = match config.connection_type {
-        ConnectionType::CLEARNET => TcpStream::connect(address)?,
-        ConnectionType::TOR => Socks5Stream::connect(
-            format!("127.0.0.1:{}", config.socks_port).as_str(),
-            address.as_ref(),
-        )?
-        .into_inner(),
-    };
+    let mut socket = connect_to_maker(&address, config)?;
 
     socket.set_read_timeout(Some(Duration::from_secs(
         config.first_connect_attempt_timeout_sec,
@@ -479,52 +571,65 @@ fn download_maker_offer_attempt_once(
             }));
         }
     };
+    if offer.version != OFFER_VERSION {
+        return Err(ProtocolError::UnsupportedOfferVersion(offer.version).into());
+    }
 
     Ok(*offer)
 }
 
+/// Connects to a maker, through Tor for [ConnectionType::TOR].
+pub(crate) fn connect_to_maker(
+    address: &str,
+    config: &TakerConfig,
+) -> Result<TcpStream, TakerError> {
+    Ok(match config.connection_type {
+        ConnectionType::CLEARNET => TcpStream::connect(address)?,
+        ConnectionType::TOR => config.tor_config.connect(address)?,
+    })
+}
+
 pub fn download_maker_offer(address: MakerAddress, config: TakerConfig) -> Option<OfferAndAddress> {
-    let mut ii = 0;
+    match retry_with_backoff(
+        &config.first_connect_retry,
+        &format!("request offer from maker {}", address),
+        || download_maker_offer_attempt_once(&address, &config),
+    ) {
+        Ok(offer) => Some(OfferAndAddress { offer, address }),
+        Err(e) => {
+            log::error!(
+                "Failed to request offer from maker {}, error={:?}",
+                address,
+                e
+            );
+            None
+        }
+    }
+}
 
+/// Runs `attempt` until it succeeds, retrying transport errors with the backoff of `retry`.
+///
+/// `attempt` must open its own connection, the previous one may be broken.
+pub(crate) fn retry_with_backoff<T>(
+    retry: &RetryConfig,
+    description: &str,
+    mut attempt: impl FnMut() -> Result<T, TakerError>,
+) -> Result<T, TakerError> {
+    let mut retries = 0;
     loop {
-        ii += 1;
-        match download_maker_offer_attempt_once(&address, &config) {
-            Ok(offer) => return Some(OfferAndAddress { offer, address }),
-            Err(TakerError::IO(e)) => {
-                if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut {
-                    if ii <= config.first_connect_attempts {
-                        log::warn!(
-                            "Timeout for request offer from maker {}, reattempting...",
-                            address
-                        );
-                        continue;
-                    } else {
-                        log::error!(
-                            "Timeout attempt exceeded for request offer from maker {}, ",
-                            address
-                        );
-                        return None;
-                    }
-                }
-            }
-
-            Err(e) => {
-                if ii <= config.first_connect_attempts {
-                    log::warn!(
-                        "Failed to request offer from maker {}, reattempting... error={:?}",
-                        address,
-                        e
-                    );
-                    sleep(Duration::from_secs(config.first_connect_sleep_delay_sec));
-                    continue;
-                } else {
-                    log::error!(
-                        "Connection attempt exceeded for request offer from maker {}",
-                        address
-                    );
-                    return None;
-                }
+        match attempt() {
+            Err(e) if e.is_retriable() && retries < retry.max_retries => {
+                retries += 1;
+                let delay = retry.delay(retries);
+                log::warn!(
+                    "Failed to {}, retrying in {:?}... error={:?}",
+                    description,
+                    delay,
+                    e
+                );
+                sleep(delay);
             }
+            result => return result,
         }
     }
 }
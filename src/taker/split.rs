@@ -0,0 +1,76 @@
+//! Splitting a large swap into smaller ones, see [Taker::swap_split](super::Taker::swap_split).
+//!
+//! A single swap of a large amount pays the Taker a large output, which stands out on chain.
+//! Split in several swaps, the amount comes back in outputs of more common values.
+
+use bitcoin::Amount;
+
+use crate::wallet::{SwapOutcome, SwapRecord};
+
+use super::error::TakerError;
+
+/// Result of a swap round, as run by [Taker::swap_split](super::Taker::swap_split) or
+/// [Taker::swap_from_utxos](super::Taker::swap_from_utxos).
+#[derive(Debug)]
+pub struct SwapResult {
+    /// Amount sent into the swap.
+    pub amount: Amount,
+    /// The swap history record of the swap, with its outcome and Makers. `None` if the swap
+    /// failed before any funds were committed, or if it couldn't be recorded.
+    pub record: Option<SwapRecord>,
+    /// The error the swap failed with, if any. A swap aborted with its contracts recovered
+    /// doesn't error, its record is [SwapOutcome::Failed].
+    pub error: Option<TakerError>,
+}
+
+impl SwapResult {
+    /// Whether the swap completed.
+    pub fn is_completed(&self) -> bool {
+        self.error.is_none()
+            && self
+                .record
+                .as_ref()
+                .is_some_and(|record| record.outcome == SwapOutcome::Completed)
+    }
+}
+
+/// Splits `total` into `num_splits` amounts, differing by at most a sat. Largest first.
+pub fn split_amount(total: Amount, num_splits: u32) -> Vec<Amount> {
+    if num_splits == 0 {
+        return Vec::new();
+    }
+    let base = total.to_sat() / num_splits as u64;
+    let remainder = total.to_sat() % num_splits as u64;
+    (0..num_splits as u64)
+        .map(|split| Amount::from_sat(base + u64::from(split < remainder)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_amount() {
+        assert_eq!(
+            split_amount(Amount::from_sat(1_000_002), 4),
+            [
+                Amount::from_sat(250_001),
+                Amount::from_sat(250_001),
+                Amount::from_sat(250_000),
+                Amount::from_sat(250_000),
+            ]
+        );
+        assert_eq!(
+            split_amount(Amount::from_sat(500_000), 1),
+            [Amount::from_sat(500_000)]
+        );
+        assert!(split_amount(Amount::from_sat(500_000), 0).is_empty());
+
+        let splits = split_amount(Amount::from_sat(123_456_789), 7);
+        assert_eq!(
+            splits.iter().copied().sum::<Amount>(),
+            Amount::from_sat(123_456_789)
+        );
+    }
+}
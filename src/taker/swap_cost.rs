@@ -0,0 +1,195 @@
+//! Cost of a swap, estimated before committing to it.
+//!
+//! A swap costs the fees of its makers and the miner fees of its transactions. The taker pays the
+//! miner fees of every funding transaction of the route: its own directly, the makers' ones out
+//! of the coins it swaps, like the maker fees. Contract transactions are only broadcast if the swap
+//! is aborted, so their fees aren't part of the cost.
+//!
+//! Below [min_economical_amount], the miner fees of a swap take too large a share of it.
+
+use bitcoin::{Amount, FeeRate};
+
+use crate::{
+    protocol::contract::{
+        calculate_coinswap_fee, CONTRACT_TX_VBYTE_SIZE, FUNDING_TX_VBYTE_SIZE, REDEEM_TX_VBYTE_SIZE,
+    },
+    wallet::WalletError,
+};
+
+use super::{config::TimelockPolicy, error::TakerError, maker_selection::MakerInfo};
+
+/// Estimated cost of a swap, per component. Every hop is estimated with one funding transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapCostBreakdown {
+    /// Fee of each maker, in route order.
+    pub maker_fees: Vec<Amount>,
+    /// Miner fees of the funding transactions of every hop, the taker's included.
+    pub funding_fees: Amount,
+    /// Miner fee of spending the received swapcoin.
+    pub redeem_fee: Amount,
+    /// Miner fees of the contract transactions of every hop, only paid if the swap is aborted.
+    /// Not part of [SwapCostBreakdown::total].
+    pub contract_fees: Amount,
+    /// Amount received at the end of the route.
+    pub received: Amount,
+}
+
+impl SwapCostBreakdown {
+    /// Total cost of a completed swap.
+    pub fn total(&self) -> Amount {
+        self.maker_fees.iter().copied().sum::<Amount>() + self.funding_fees + self.redeem_fee
+    }
+}
+
+/// Estimate the cost of swapping `amount` through `route` at `fee_rate`, with the contract
+/// locktimes of `timelock_policy`.
+///
+/// Every maker charges its fee on the amount it receives, as the previous makers of the route
+/// took theirs, and takes the miner fee of its funding transaction out of it too. The time
+/// relative part of the fee is charged on the locktime of the contract it receives.
+pub fn estimate_swap_cost(
+    amount: Amount,
+    route: &[MakerInfo],
+    fee_rate: FeeRate,
+    timelock_policy: &TimelockPolicy,
+) -> Result<SwapCostBreakdown, TakerError> {
+    let tx_fee = |vbytes: u64| {
+        fee_rate
+            .fee_vb(vbytes)
+            .ok_or_else(|| WalletError::FeeEstimation(format!("fee rate {} overflows", fee_rate)))
+    };
+    let funding_tx_fee = tx_fee(FUNDING_TX_VBYTE_SIZE)?;
+    let hop_count = route.len() as u64 + 1;
+    let hop_locktimes = timelock_policy.hop_locktimes(route.len())?;
+
+    let mut maker_fees = Vec::with_capacity(route.len());
+    let mut hop_amount = amount;
+    for (maker, locktime) in route.iter().zip(hop_locktimes) {
+        let offer = &maker.offer_and_address.offer;
+        let maker_fee = Amount::from_sat(
+            calculate_coinswap_fee(
+                &offer.fee_policy,
+                offer.time_relative_fee_ppb,
+                hop_amount,
+                locktime as u64,
+            )
+            .map_err(WalletError::Contract)?,
+        );
+        hop_amount = hop_amount.checked_sub(maker_fee + funding_tx_fee).ok_or(
+            WalletError::InsufficientFund {
+                available: hop_amount.to_sat(),
+                required: (maker_fee + funding_tx_fee).to_sat(),
+            },
+        )?;
+        maker_fees.push(maker_fee);
+    }
+
+    Ok(SwapCostBreakdown {
+        maker_fees,
+        funding_fees: funding_tx_fee * hop_count,
+        redeem_fee: tx_fee(REDEEM_TX_VBYTE_SIZE)?,
+        contract_fees: tx_fee(CONTRACT_TX_VBYTE_SIZE)? * hop_count,
+        received: hop_amount,
+    })
+}
+
+/// Smallest amount worth swapping through `maker_count` makers at `fee_rate`.
+///
+/// Every hop of the route costs a funding, a contract and a redeem transaction in the worst case.
+/// Their miner fees must stay within `max_tx_fee_ppm` of the amount.
+pub fn min_economical_amount(
+    maker_count: usize,
+    fee_rate: FeeRate,
+    max_tx_fee_ppm: u64,
+) -> Result<Amount, WalletError> {
+    let hop_vbytes = FUNDING_TX_VBYTE_SIZE + CONTRACT_TX_VBYTE_SIZE + REDEEM_TX_VBYTE_SIZE;
+    let overflow = || WalletError::FeeEstimation(format!("fee rate {} overflows", fee_rate));
+    let tx_fees = (maker_count as u64 + 1)
+        .checked_mul(hop_vbytes)
+        .and_then(|vbytes| fee_rate.fee_vb(vbytes))
+        .ok_or_else(overflow)?;
+    if max_tx_fee_ppm == 0 {
+        return Ok(Amount::MAX_MONEY);
+    }
+    tx_fees
+        .to_sat()
+        .checked_mul(1_000_000)
+        .map(|fees| Amount::from_sat(fees.div_ceil(max_tx_fee_ppm)))
+        .ok_or_else(overflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{protocol::error::ContractError, taker::maker_selection::tests::maker};
+
+    #[test]
+    fn test_estimate_swap_cost() {
+        let fee_rate = FeeRate::from_sat_per_vb_u32(2);
+        let timelock_policy = TimelockPolicy {
+            base: 48,
+            per_hop_increment: 48,
+        };
+        // A time relative fee of 1 sat per block of locktime.
+        let mut route = [maker(6102, 0, 0), maker(16102, 0, 0)];
+        for maker in &mut route {
+            maker.offer_and_address.offer.time_relative_fee_ppb = Amount::from_sat(1_000_000_000);
+        }
+        let cost = estimate_swap_cost(
+            Amount::from_sat(1_000_000),
+            &route,
+            fee_rate,
+            &timelock_policy,
+        )
+        .unwrap();
+
+        // 1000 base fee + 1% relative fee, on what is left, + the locktime of the received
+        // contract: 144 blocks for the first maker, 96 for the second.
+        assert_eq!(
+            cost.maker_fees,
+            vec![Amount::from_sat(11_144), Amount::from_sat(10_977)]
+        );
+        assert_eq!(cost.funding_fees, Amount::from_sat(3 * 744));
+        assert_eq!(cost.redeem_fee, Amount::from_sat(276));
+        assert_eq!(cost.contract_fees, Amount::from_sat(3 * 300));
+        assert_eq!(
+            cost.received,
+            Amount::from_sat(1_000_000 - 11_144 - 744 - 10_977 - 744)
+        );
+        assert_eq!(
+            cost.total(),
+            Amount::from_sat(11_144 + 10_977 + 3 * 744 + 276)
+        );
+
+        // A direct swap only pays the miner fees.
+        let cost = estimate_swap_cost(Amount::from_sat(1_000_000), &[], fee_rate, &timelock_policy)
+            .unwrap();
+        assert_eq!(cost.total(), Amount::from_sat(744 + 276));
+
+        // What is left after the first maker is below the second maker's minimum.
+        assert!(matches!(
+            estimate_swap_cost(Amount::from_sat(11_000), &route, fee_rate, &timelock_policy),
+            Err(TakerError::Wallet(WalletError::Contract(
+                ContractError::SwapAmountOutOfBounds { .. }
+            )))
+        ));
+    }
+
+    #[test]
+    fn test_min_economical_amount() {
+        let fee_rate = FeeRate::from_sat_per_vb_u32(2);
+        // 3 hops of 660 vbytes, at most 5% of the amount.
+        let minimum = min_economical_amount(2, fee_rate, 50_000).unwrap();
+        assert_eq!(minimum, Amount::from_sat(3 * 660 * 2 * 20));
+        // Every hop and every sat/vB raises it.
+        assert!(min_economical_amount(3, fee_rate, 50_000).unwrap() > minimum);
+        assert!(
+            min_economical_amount(2, FeeRate::from_sat_per_vb_u32(3), 50_000).unwrap() > minimum
+        );
+        // No share allowed, nothing is economical.
+        assert_eq!(
+            min_economical_amount(2, fee_rate, 0).unwrap(),
+            Amount::MAX_MONEY
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! On-disk record of the swap round in progress, so a crashed taker can recover its funds.
+//!
+//! The swapcoins themselves are saved in the wallet file as soon as they're created. The
+//! [SwapState] records what the wallet can't tell: how far the protocol went, the makers of the
+//! route and the preimage. It's written next to the wallet file at each phase transition, and
+//! removed once the round is over.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use bitcoin::ScriptBuf;
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::messages::Preimage;
+
+use super::{api::SwapParams, error::TakerError};
+
+/// Phase of a swap round, past the point where funds are at stake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SwapPhase {
+    /// The taker's funding transactions are broadcasted, or about to be. Contracts are being set
+    /// up with the makers of the route.
+    Funding,
+    /// All the hops are funded. The taker holds its incoming swapcoins.
+    ContractsEstablished,
+    /// The preimage and the multisig private keys are being exchanged with the makers.
+    Settling,
+}
+
+/// The swap round in progress, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SwapState {
+    pub phase: SwapPhase,
+    pub swap_params: SwapParams,
+    pub preimage: Preimage,
+    /// Addresses of the makers of the route so far, in hop order.
+    pub maker_route: Vec<String>,
+    /// Multisig redeemscripts of the swapcoins created so far. The swapcoins are in the wallet.
+    pub outgoing_swapcoins: Vec<ScriptBuf>,
+    pub incoming_swapcoins: Vec<ScriptBuf>,
+}
+
+impl SwapState {
+    /// Path of the swap state of the wallet at `wallet_path`.
+    pub(super) fn path_for_wallet(wallet_path: &Path) -> PathBuf {
+        let mut file_name = wallet_path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".swap");
+        wallet_path.with_file_name(file_name)
+    }
+
+    /// Reads the swap state at `path`, `None` if there's no swap in progress.
+    pub fn read_from_disk(path: &Path) -> Result<Option<Self>, TakerError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let reader = fs::read(path)?;
+        Ok(Some(serde_cbor::from_slice(&reader)?))
+    }
+
+    /// Writes the swap state at `path`, through a temporary file so a crash never leaves it half
+    /// written. The file and its directory are synced before returning, so the state survives a
+    /// power loss once saved.
+    pub fn write_to_disk(&self, path: &Path) -> Result<(), TakerError> {
+        let mut tmp_path = path.as_os_str().to_os_string();
+        tmp_path.push(".tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&serde_cbor::to_vec(self)?)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Removes the swap state at `path`, if any.
+    pub fn remove_from_disk(path: &Path) -> Result<(), TakerError> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether the preimage can be revealed to the maker at `hop_index` of the route, once
+/// `settled_hops` makers of the route got it.
+///
+/// The preimage lets a maker claim its incoming contract, so it's only revealed once every hop is
+/// funded, in the [SwapPhase::Settling] phase. The reveals then follow the route from its first
+/// hop, one at a time: each maker gets the multisig keys of its incoming swapcoins along with the
+/// preimage, and those are handed over by the maker before it in exchange for the preimage.
+pub fn can_reveal_preimage(phase: SwapPhase, hop_index: usize, settled_hops: usize) -> bool {
+    phase == SwapPhase::Settling && hop_index == settled_hops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::Amount;
+
+    #[test]
+    fn test_swap_state_roundtrip() {
+        let temp_dir = bitcoind::tempfile::tempdir().unwrap();
+        let path = SwapState::path_for_wallet(&temp_dir.path().join("wallet-taker"));
+        assert_eq!(path, temp_dir.path().join("wallet-taker.swap"));
+        SwapState::remove_from_disk(&path).unwrap();
+        assert_eq!(SwapState::read_from_disk(&path).unwrap(), None);
+
+        let state = SwapState {
+            phase: SwapPhase::ContractsEstablished,
+            swap_params: SwapParams {
+                send_amount: Amount::from_sat(500_000),
+                maker_count: 2,
+                tx_count: 3,
+                required_confirms: 1,
+                fee_rate: Amount::from_sat(1000),
+            },
+            preimage: [7; 32],
+            maker_route: vec!["127.0.0.1:6102".to_string(), "127.0.0.1:16102".to_string()],
+            outgoing_swapcoins: vec![ScriptBuf::from_bytes(vec![1, 2, 3])],
+            incoming_swapcoins: vec![],
+        };
+        state.write_to_disk(&path).unwrap();
+        assert_eq!(SwapState::read_from_disk(&path).unwrap(), Some(state));
+
+        SwapState::remove_from_disk(&path).unwrap();
+        SwapState::remove_from_disk(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_can_reveal_preimage() {
+        assert!(can_reveal_preimage(SwapPhase::Settling, 0, 0));
+        assert!(can_reveal_preimage(SwapPhase::Settling, 2, 2));
+
+        // Not before every hop is funded.
+        assert!(!can_reveal_preimage(SwapPhase::Funding, 0, 0));
+        assert!(!can_reveal_preimage(SwapPhase::ContractsEstablished, 0, 0));
+        // Not out of the route order, nor twice.
+        assert!(!can_reveal_preimage(SwapPhase::Settling, 1, 0));
+        assert!(!can_reveal_preimage(SwapPhase::Settling, 0, 1));
+    }
+}
@@ -1,6 +1,8 @@
-//! A Framework to write functional tests for the Coinswap Protocol.
+//! A Framework to write functional tests for the Coinswap Protocol, enabled with the `test-utils` feature.
 //!
-//! This framework uses [bitcoind] to automatically spawn regtest node in the background.
+//! This framework uses [bitcoind] to automatically spawn regtest node in the background. The
+//! `bitcoind` binary is taken from `bin/bitcoind` of the working directory if present, else from the
+//! `BITCOIND_EXE` environment variable.
 //!
 //! Spawns one Taker and multiple Makers, with/without special behavior, connect them to bitcoind regtest node,
 //! and initializes the database.
@@ -10,31 +12,32 @@
 //!
 //! The test data also includes the backend bitcoind data-directory, which is useful for observing the blockchain states after a swap.
 //!
+//! [TestFramework::fund_wallet] and [TestFramework::run_swap] cover the usual funding and swap steps.
 //! Checkout `tests/standard_swap.rs` for example of simple coinswap simulation test between 1 Taker and 2 Makers.
 use bitcoin::{
     secp256k1::rand::{distributions::Alphanumeric, thread_rng, Rng},
-    Address, Amount,
+    Address, Amount, BlockHash, Network, Txid,
 };
 use std::{
     collections::HashMap,
     fs,
     path::PathBuf,
     sync::{Arc, RwLock},
-    thread,
+    thread::{self, JoinHandle},
     time::Duration,
 };
 
+use crate::{
+    maker::{start_maker_server, Maker, MakerBehavior},
+    market::directory::{start_directory_server, DirectoryServer},
+    taker::{error::TakerError, SwapParams, Taker, TakerBehavior},
+    utill::{setup_logger, ConnectionType},
+    wallet::{RPCConfig, Wallet},
+};
 use bitcoind::{
     bitcoincore_rpc::{Auth, Client, RpcApi},
     BitcoinD, Conf,
 };
-use coinswap::{
-    maker::{Maker, MakerBehavior},
-    market::directory::{start_directory_server, DirectoryServer},
-    taker::{Taker, TakerBehavior},
-    utill::{setup_logger, ConnectionType},
-    wallet::RPCConfig,
-};
 
 fn get_random_tmp_dir() -> PathBuf {
     let s: String = thread_rng()
@@ -46,6 +49,31 @@ fn get_random_tmp_dir() -> PathBuf {
     PathBuf::from(path)
 }
 
+/// Blocks for a coinbase output to become spendable.
+const COINBASE_MATURITY: u64 = 101;
+
+/// Mine `n` blocks to a new address of the node's wallet. Returns the block hashes.
+pub fn mine_blocks(client: &Client, n: u64) -> Vec<BlockHash> {
+    let mining_address = client
+        .get_new_address(None, None)
+        .unwrap()
+        .require_network(Network::Regtest)
+        .unwrap();
+    client.generate_to_address(n, &mining_address).unwrap()
+}
+
+/// Mine enough blocks for the first coinbase output of the node's wallet to be spendable.
+pub fn mine_to_maturity(client: &Client) -> Vec<BlockHash> {
+    mine_blocks(client, COINBASE_MATURITY)
+}
+
+/// Send coins from the node's wallet to an address. The tx is left unconfirmed.
+pub fn fund_address(client: &Client, address: &Address, amount: Amount) -> Txid {
+    client
+        .send_to_address(address, amount, None, None, None, None, None, None)
+        .unwrap()
+}
+
 /// The Test Framework.
 ///
 /// Handles initializing, operating and cleaning up of all backend processes. Bitcoind, Taker and Makers.
@@ -79,7 +107,7 @@ impl TestFramework {
         Arc<DirectoryServer>,
     ) {
         if cfg!(feature = "tor") && connection_type == ConnectionType::TOR {
-            coinswap::tor::setup_mitosis();
+            crate::tor::setup_mitosis();
         }
         setup_logger();
         // Setup directory
@@ -105,7 +133,9 @@ impl TestFramework {
             ("macos", "aarch64") => curr_dir_path.join("bin").join("bitcoind_macos"),
             _ => curr_dir_path.join("bin").join("bitcoind"),
         };
-        std::env::set_var(key, bitcoind_path);
+        if bitcoind_path.exists() {
+            std::env::set_var(key, bitcoind_path);
+        }
         let exe_path = bitcoind::exe_path().unwrap();
 
         log::info!("Executable path: {:?}", exe_path);
@@ -113,16 +143,7 @@ impl TestFramework {
         let bitcoind = BitcoinD::with_conf(exe_path, &conf).unwrap();
 
         // Generate initial 101 blocks
-        let mining_address = bitcoind
-            .client
-            .get_new_address(None, None)
-            .unwrap()
-            .require_network(bitcoind::bitcoincore_rpc::bitcoin::Network::Regtest)
-            .unwrap();
-        bitcoind
-            .client
-            .generate_to_address(101, &mining_address)
-            .unwrap();
+        mine_to_maturity(&bitcoind.client);
         log::info!("bitcoind initiated!!");
         let shutdown = Arc::new(RwLock::new(false));
         let test_framework = Arc::new(Self {
@@ -203,25 +224,12 @@ impl TestFramework {
 
     /// Generate Blocks in regtest node.
     pub fn generate_blocks(&self, n: u64) {
-        let mining_address = self
-            .bitcoind
-            .client
-            .get_new_address(None, None)
-            .unwrap()
-            .require_network(bitcoind::bitcoincore_rpc::bitcoin::Network::Regtest)
-            .unwrap();
-        self.bitcoind
-            .client
-            .generate_to_address(n, &mining_address)
-            .unwrap();
+        mine_blocks(&self.bitcoind.client, n);
     }
 
     /// Send coins to a bitcoin address.
     pub fn send_to_address(&self, addrs: &Address, amount: Amount) {
-        self.bitcoind
-            .client
-            .send_to_address(addrs, amount, None, None, None, None, None, None)
-            .unwrap();
+        fund_address(&self.bitcoind.client, addrs, amount);
     }
 
     /// Stop bitcoind and clean up all test data.
@@ -236,6 +244,70 @@ impl TestFramework {
     pub fn get_block_count(&self) -> u64 {
         self.bitcoind.client.get_block_count().unwrap()
     }
+
+    /// Send `utxo_count` utxos of `utxo_value` each to fresh external addresses of the wallet, and
+    /// confirm them.
+    pub fn fund_wallet(&self, wallet: &mut Wallet, utxo_count: u32, utxo_value: Amount) {
+        for _ in 0..utxo_count {
+            let address = wallet.get_next_external_address().unwrap();
+            self.send_to_address(&address, utxo_value);
+        }
+        self.generate_blocks(1);
+    }
+
+    /// Fund the taker with 3 utxos of 0.05 btc each, and the makers with 4: 3 to swap and 1 for
+    /// their fidelity bond.
+    pub fn fund_swap_wallets(&self, taker: &Arc<RwLock<Taker>>, makers: &[Arc<Maker>]) {
+        let utxo_value = Amount::from_btc(0.05).unwrap();
+        self.fund_wallet(taker.write().unwrap().get_wallet_mut(), 3, utxo_value);
+        for maker in makers {
+            self.fund_wallet(&mut maker.get_wallet().write().unwrap(), 4, utxo_value);
+        }
+    }
+
+    /// Start the servers of the makers, and wait until they have completed their setup.
+    pub fn start_makers(makers: &[Arc<Maker>]) -> Vec<JoinHandle<()>> {
+        let maker_threads = makers
+            .iter()
+            .map(|maker| {
+                let maker_clone = maker.clone();
+                thread::spawn(move || {
+                    start_maker_server(maker_clone).unwrap();
+                })
+            })
+            .collect();
+
+        // Makers take time to fully setup.
+        for maker in makers {
+            while !*maker.is_setup_complete.read().unwrap() {
+                log::info!("Waiting for maker setup completion");
+                // Introduce a delay of 10 seconds to prevent write lock starvation.
+                thread::sleep(Duration::from_secs(10));
+            }
+        }
+        maker_threads
+    }
+
+    /// Run a full coinswap of the funded taker through the makers, then shut the makers down.
+    ///
+    /// Returns the result of the taker's swap. The taker and makers stay available for
+    /// assertions on their wallets.
+    pub fn run_swap(
+        taker: &Arc<RwLock<Taker>>,
+        makers: &[Arc<Maker>],
+        swap_params: SwapParams,
+    ) -> Result<(), TakerError> {
+        let maker_threads = Self::start_makers(makers);
+
+        log::info!("Initiating coinswap protocol");
+        let result = taker.write().unwrap().do_coinswap(swap_params);
+
+        makers.iter().for_each(|maker| maker.shutdown().unwrap());
+        maker_threads
+            .into_iter()
+            .for_each(|thread| thread.join().unwrap());
+        result
+    }
 }
 
 /// Initializes a [TestFramework] given a [RPCConfig].
@@ -3,10 +3,13 @@
 use std::{
     env,
     io::{ErrorKind, Read},
-    net::TcpStream,
+    net::{Ipv4Addr, SocketAddr, TcpStream},
     path::{Path, PathBuf},
     str::FromStr,
-    sync::Once,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Once,
+    },
 };
 
 use bitcoin::{
@@ -15,13 +18,14 @@ use bitcoin::{
         rand::{rngs::OsRng, RngCore},
         Secp256k1, SecretKey,
     },
-    Network, PublicKey, ScriptBuf, WitnessProgram, WitnessVersion,
+    Amount, Denomination, Network, PublicKey, ScriptBuf, WitnessProgram, WitnessVersion,
 };
 use log4rs::{
     append::{console::ConsoleAppender, file::FileAppender},
     config::{Appender, Logger, Root},
     Config,
 };
+use socks::Socks5Stream;
 
 use std::{
     collections::HashMap,
@@ -73,6 +77,114 @@ impl FromStr for ConnectionType {
     }
 }
 
+/// Tor SOCKS proxy settings.
+///
+/// Tor puts streams opened with different SOCKS credentials on different circuits. With
+/// `isolate_streams`, every target gets its own credentials, so the makers of a swap can't
+/// correlate the taker by the circuit it connects through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TorConfig {
+    pub proxy: SocketAddr,
+    pub isolate_streams: bool,
+}
+
+impl TorConfig {
+    /// Tor proxy listening on localhost at `socks_port`.
+    pub fn local(socks_port: u16, isolate_streams: bool) -> Self {
+        Self {
+            proxy: SocketAddr::from((Ipv4Addr::LOCALHOST, socks_port)),
+            isolate_streams,
+        }
+    }
+
+    /// Connects to `target` through the Tor proxy.
+    pub fn connect(&self, target: &str) -> io::Result<TcpStream> {
+        let stream = if self.isolate_streams {
+            Socks5Stream::connect_with_password(self.proxy, target, target, "coinswap")?
+        } else {
+            Socks5Stream::connect(self.proxy, target)?
+        };
+        Ok(stream.into_inner())
+    }
+}
+
+/// How to stop a Maker or a Taker with swaps in flight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ShutdownMode {
+    /// Refuse new swaps, and stop once the in-flight swaps are over.
+    Graceful,
+    /// Stop at the next safe checkpoint. The swap state is on disk, and in-flight swaps are
+    /// recovered on the next start.
+    Immediate,
+}
+
+impl ShutdownMode {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => None,
+            1 => Some(ShutdownMode::Graceful),
+            _ => Some(ShutdownMode::Immediate),
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            ShutdownMode::Graceful => 1,
+            ShutdownMode::Immediate => 2,
+        }
+    }
+}
+
+/// Count of the SIGINT and SIGTERM received since [register_shutdown_signals].
+static SHUTDOWN_SIGNALS: AtomicU8 = AtomicU8::new(0);
+
+#[cfg(unix)]
+extern "C" fn on_shutdown_signal(_: libc::c_int) {
+    // Only an atomic update and `signal`, the handler must be async-signal-safe.
+    let previous = SHUTDOWN_SIGNALS.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+        Some(count.saturating_add(1))
+    });
+    // From the second signal on, a stuck process can still be killed by another one.
+    if previous.is_ok_and(|count| count >= 1) {
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::signal(libc::SIGTERM, libc::SIG_DFL);
+        }
+    }
+}
+
+/// Turns SIGINT and SIGTERM into shutdown requests of every [ShutdownHandle] of the process.
+///
+/// The first signal requests a [ShutdownMode::Graceful] shutdown, the second one an
+/// [ShutdownMode::Immediate] one, and a third one kills the process. Does nothing on non-unix
+/// platforms.
+pub fn register_shutdown_signals() {
+    #[cfg(unix)]
+    unsafe {
+        let handler = on_shutdown_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// A shared request to shut down, escalating from [ShutdownMode::Graceful] to
+/// [ShutdownMode::Immediate]. Clones share the request.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle(Arc<AtomicU8>);
+
+impl ShutdownHandle {
+    /// Requests a shutdown. A request can't be downgraded from immediate to graceful.
+    pub fn shutdown(&self, mode: ShutdownMode) {
+        self.0.fetch_max(mode.to_u8(), Ordering::SeqCst);
+    }
+
+    /// The requested shutdown, by [ShutdownHandle::shutdown] or by a signal.
+    pub fn requested(&self) -> Option<ShutdownMode> {
+        let signals = SHUTDOWN_SIGNALS.load(Ordering::SeqCst);
+        ShutdownMode::from_u8(self.0.load(Ordering::SeqCst).max(signals))
+    }
+}
+
 /// Read the tor address given an hidden_service directory path
 pub fn get_tor_addrs(hs_dir: &Path) -> String {
     let hostname_file_path = hs_dir.join("hs-dir").join("hostname");
@@ -459,6 +571,29 @@ pub fn compute_checksum(descriptor: &str) -> Result<String, WalletError> {
     Ok(checksum_chars)
 }
 
+/// Appends the checksum to a descriptor, computed locally. Errors with the offending descriptor
+/// if it's invalid.
+pub fn append_descriptor_checksum(descriptor: &str) -> Result<String, WalletError> {
+    let checksum =
+        compute_checksum(descriptor).map_err(|e| WalletError::descriptor_import(descriptor, e))?;
+    Ok(format!("{}#{}", descriptor, checksum))
+}
+
+/// Checks the checksum of a `<descriptor>#<checksum>`, computed locally.
+pub fn validate_descriptor_checksum(descriptor: &str) -> Result<(), WalletError> {
+    let (descriptor, checksum) = descriptor
+        .rsplit_once('#')
+        .ok_or_else(|| WalletError::Protocol("Descriptor has no checksum".to_string()))?;
+    let expected = compute_checksum(descriptor)?;
+    if checksum != expected {
+        return Err(WalletError::Protocol(format!(
+            "Invalid descriptor checksum {}, expected {}",
+            checksum, expected
+        )));
+    }
+    Ok(())
+}
+
 /// Parse the proxy (Socket:Port) argument from the cli input.
 pub fn parse_proxy_auth(s: &str) -> Result<(String, String), String> {
     let parts: Vec<_> = s.split(':').collect();
@@ -472,6 +607,19 @@ pub fn parse_proxy_auth(s: &str) -> Result<(String, String), String> {
     Ok((user, passwd))
 }
 
+/// Parse an amount argument from the cli input, like `0.01btc`, `0.01 BTC` or `500000sat`.
+/// Amounts without a denomination are in sats.
+pub fn parse_amount(s: &str) -> Result<Amount, String> {
+    let s = s.trim();
+    let (value, denomination) =
+        s.split_at(s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len()));
+    let denomination = match denomination.trim() {
+        "" => Denomination::Satoshi,
+        denomination => Denomination::from_str(denomination).map_err(|e| e.to_string())?,
+    };
+    Amount::from_str_in(value.trim(), denomination).map_err(|e| e.to_string())
+}
+
 /// Parse the network string for Bitcoin Backend. Used in CLI apps.
 pub fn read_bitcoin_network_string(network: &str) -> Result<Network, String> {
     match network {
@@ -544,6 +692,58 @@ mod tests {
         send_message(&mut stream, &message).unwrap();
     }
 
+    fn read_bytes(socket: &mut TcpStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        socket.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    /// Accepts one SOCKS5 connection and returns the username it authenticated with, if any.
+    fn mock_socks_proxy(listener: TcpListener) -> thread::JoinHandle<Option<String>> {
+        thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let nmethods = read_bytes(&mut socket, 2)[1] as usize;
+            let methods = read_bytes(&mut socket, nmethods);
+            let username = if methods.contains(&2) {
+                socket.write_all(&[5, 2]).unwrap();
+                let ulen = read_bytes(&mut socket, 2)[1] as usize;
+                let username = String::from_utf8(read_bytes(&mut socket, ulen)).unwrap();
+                let plen = read_bytes(&mut socket, 1)[0] as usize;
+                read_bytes(&mut socket, plen);
+                socket.write_all(&[1, 0]).unwrap();
+                Some(username)
+            } else {
+                socket.write_all(&[5, 0]).unwrap();
+                None
+            };
+            // Connect request to a domain name target.
+            let domain_len = read_bytes(&mut socket, 5)[4] as usize;
+            read_bytes(&mut socket, domain_len + 2);
+            socket
+                .write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 80])
+                .unwrap();
+            username
+        })
+    }
+
+    #[test]
+    fn test_tor_stream_isolation() {
+        for isolate_streams in [true, false] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let tor_config =
+                TorConfig::local(listener.local_addr().unwrap().port(), isolate_streams);
+            let proxy = mock_socks_proxy(listener);
+
+            tor_config.connect("makeraddress.onion:6102").unwrap();
+            let username = proxy.join().unwrap();
+            if isolate_streams {
+                assert_eq!(username.as_deref(), Some("makeraddress.onion:6102"));
+            } else {
+                assert_eq!(username, None);
+            }
+        }
+    }
+
     #[test]
     fn test_convert_json_rpc_bitcoin_to_satoshis() {
         // Test with an integer value
@@ -706,6 +906,39 @@ mod tests {
         assert_eq!(returned_pubkey.to_string(), tweaked_pubkey.to_string());
     }
 
+    #[test]
+    fn test_descriptor_checksum() {
+        // BIP380 test vector.
+        assert_eq!(
+            append_descriptor_checksum("raw(deadbeef)").unwrap(),
+            "raw(deadbeef)#89f8spxm"
+        );
+        assert!(validate_descriptor_checksum("raw(deadbeef)#89f8spxm").is_ok());
+
+        let descriptor = append_descriptor_checksum(
+            "wpkh(tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp/0/*)",
+        )
+        .unwrap();
+        assert!(validate_descriptor_checksum(&descriptor).is_ok());
+        // A single character changed.
+        assert!(validate_descriptor_checksum(&descriptor.replacen("/0/", "/1/", 1)).is_err());
+        assert!(validate_descriptor_checksum("raw(deadbeef)#89f8spxn").is_err());
+        assert!(validate_descriptor_checksum("raw(deadbeef)").is_err());
+        // Not in the descriptor character set.
+        assert!(append_descriptor_checksum("raw(dead\u{e9}beef)").is_err());
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("0.01btc"), Ok(Amount::from_sat(1_000_000)));
+        assert_eq!(parse_amount("0.01 BTC"), Ok(Amount::from_sat(1_000_000)));
+        assert_eq!(parse_amount("500000sat"), Ok(Amount::from_sat(500_000)));
+        assert_eq!(parse_amount("500000"), Ok(Amount::from_sat(500_000)));
+        assert!(parse_amount("0.5sat").is_err());
+        assert!(parse_amount("1 doge").is_err());
+        assert!(parse_amount("btc").is_err());
+    }
+
     #[test]
     fn test_parse_toml() {
         let file_content = r#"
@@ -743,4 +976,20 @@ mod tests {
 
         remove_temp_config(&file_path);
     }
+
+    #[test]
+    fn test_shutdown_handle() {
+        let handle = ShutdownHandle::default();
+        let clone = handle.clone();
+        assert_eq!(handle.requested(), None);
+
+        clone.shutdown(ShutdownMode::Graceful);
+        assert_eq!(handle.requested(), Some(ShutdownMode::Graceful));
+
+        handle.shutdown(ShutdownMode::Immediate);
+        // Not downgraded by a later graceful request.
+        clone.shutdown(ShutdownMode::Graceful);
+        assert_eq!(clone.requested(), Some(ShutdownMode::Immediate));
+        assert_eq!(ShutdownHandle::default().requested(), None);
+    }
 }
@@ -0,0 +1,332 @@
+//! Introspection into the swaps in progress of the wallet.
+//!
+//! The swapcoins of a swap all share the hashvalue of its preimage. [Wallet::active_swaps] groups
+//! them by hashvalue, and reports the swaps not completed yet. A swap completes once we hold the
+//! counterparty's multisig private keys of all its incoming swapcoins. The outgoing swapcoins
+//! can't tell: a maker never learns the preimage on them, and keeps them once handed over.
+
+use std::collections::BTreeMap;
+
+use bitcoin::{Amount, PublicKey, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::Hash160;
+
+use super::{
+    swapcoin::{IncomingSwapCoin, OutgoingSwapCoin, SwapCoin, WalletSwapCoin},
+    Wallet,
+};
+
+/// How far an active swap went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ActiveSwapPhase {
+    /// Some contract txs still miss the counterparty's signature, so the funding txs aren't
+    /// broadcasted yet.
+    ContractsPending,
+    /// All the contract txs are signed, the preimage isn't known yet.
+    Funded,
+    /// The preimage is known. The multisig private keys are being exchanged.
+    PreimageRevealed,
+}
+
+/// A contract of an active swap.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveContractInfo {
+    pub contract_txid: Txid,
+    /// The counterparty's key in the funding multisig.
+    pub counterparty: PublicKey,
+    pub funding_amount: Amount,
+    /// Relative timelock of the contract, in blocks after the contract tx confirms.
+    pub timelock: u16,
+}
+
+/// A swap in progress, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveSwapInfo {
+    /// Hashvalue of the swap's preimage, shared by all its contracts.
+    pub swap_id: Hash160,
+    pub phase: ActiveSwapPhase,
+    pub incoming: Vec<ActiveContractInfo>,
+    pub outgoing: Vec<ActiveContractInfo>,
+}
+
+impl ActiveSwapInfo {
+    /// Total amount coming in.
+    pub fn incoming_amount(&self) -> Amount {
+        self.incoming.iter().map(|c| c.funding_amount).sum()
+    }
+
+    /// Total amount going out.
+    pub fn outgoing_amount(&self) -> Amount {
+        self.outgoing.iter().map(|c| c.funding_amount).sum()
+    }
+
+    /// The earliest timelock of the swap. Past it, the outgoing funds can be refunded.
+    pub fn min_timelock(&self) -> Option<u16> {
+        self.incoming
+            .iter()
+            .chain(self.outgoing.iter())
+            .map(|c| c.timelock)
+            .min()
+    }
+}
+
+impl Wallet {
+    /// Lists the swaps in progress, ordered by swap id.
+    pub fn active_swaps(&self) -> Vec<ActiveSwapInfo> {
+        group_active_swaps(
+            self.store.incoming_swapcoins.values(),
+            self.store.outgoing_swapcoins.values(),
+        )
+    }
+}
+
+fn contract_info<S: WalletSwapCoin>(swapcoin: &S) -> ActiveContractInfo {
+    ActiveContractInfo {
+        contract_txid: swapcoin.get_contract_tx().compute_txid(),
+        counterparty: *swapcoin.get_other_pubkey(),
+        funding_amount: swapcoin.get_funding_amount(),
+        timelock: swapcoin.get_timelock(),
+    }
+}
+
+/// The phase of a swap, given the phase so far and one more of its swapcoins.
+fn next_phase(phase: ActiveSwapPhase, signed: bool, preimage_known: bool) -> ActiveSwapPhase {
+    if preimage_known || phase == ActiveSwapPhase::PreimageRevealed {
+        ActiveSwapPhase::PreimageRevealed
+    } else if !signed || phase == ActiveSwapPhase::ContractsPending {
+        ActiveSwapPhase::ContractsPending
+    } else {
+        ActiveSwapPhase::Funded
+    }
+}
+
+/// The swap with id `swap_id`, and whether all its incoming swapcoins are settled. `None` before
+/// any incoming swapcoin.
+fn swap_entry(
+    swaps: &mut BTreeMap<Hash160, (ActiveSwapInfo, Option<bool>)>,
+    swap_id: Hash160,
+) -> &mut (ActiveSwapInfo, Option<bool>) {
+    swaps.entry(swap_id).or_insert_with(|| {
+        (
+            ActiveSwapInfo {
+                swap_id,
+                phase: ActiveSwapPhase::Funded,
+                incoming: Vec::new(),
+                outgoing: Vec::new(),
+            },
+            None,
+        )
+    })
+}
+
+fn group_active_swaps<'a>(
+    incomings: impl Iterator<Item = &'a IncomingSwapCoin>,
+    outgoings: impl Iterator<Item = &'a OutgoingSwapCoin>,
+) -> Vec<ActiveSwapInfo> {
+    let mut swaps = BTreeMap::<Hash160, (ActiveSwapInfo, Option<bool>)>::new();
+    for incoming in incomings {
+        let (swap, settled) = swap_entry(&mut swaps, incoming.get_hashvalue());
+        swap.phase = next_phase(
+            swap.phase,
+            incoming.others_contract_sig.is_some(),
+            incoming.is_hash_preimage_known(),
+        );
+        swap.incoming.push(contract_info(incoming));
+        *settled = Some(settled.unwrap_or(true) && incoming.other_privkey.is_some());
+    }
+    for outgoing in outgoings {
+        let (swap, _) = swap_entry(&mut swaps, outgoing.get_hashvalue());
+        swap.phase = next_phase(
+            swap.phase,
+            outgoing.others_contract_sig.is_some(),
+            outgoing.is_hash_preimage_known(),
+        );
+        swap.outgoing.push(contract_info(outgoing));
+    }
+
+    swaps
+        .into_values()
+        .filter_map(|(swap, settled)| (settled != Some(true)).then_some(swap))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::contract::create_contract_redeemscript;
+    use bitcoin::{
+        absolute::LockTime,
+        ecdsa::Signature,
+        hashes::Hash,
+        secp256k1::{Message, Secp256k1, SecretKey},
+        transaction::Version,
+        ScriptBuf, Transaction,
+    };
+
+    struct Coin {
+        hashvalue: u8,
+        timelock: u16,
+        amount: u64,
+        signed: bool,
+        preimage_known: bool,
+    }
+
+    fn key(i: u8) -> (SecretKey, PublicKey) {
+        let secret_key = SecretKey::from_slice(&[i; 32]).unwrap();
+        let pubkey = PublicKey::new(secret_key.public_key(&Secp256k1::new()));
+        (secret_key, pubkey)
+    }
+
+    fn parts(coin: &Coin) -> (Transaction, ScriptBuf, Option<Signature>, Option<[u8; 32]>) {
+        let contract_redeemscript = create_contract_redeemscript(
+            &key(1).1,
+            &key(2).1,
+            &Hash160::from_byte_array([coin.hashvalue; 20]).into(),
+            &coin.timelock,
+        );
+        let contract_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::from_consensus(coin.amount as u32),
+            input: vec![],
+            output: vec![],
+        };
+        let sig = coin.signed.then(|| Signature {
+            signature: Secp256k1::new().sign_ecdsa(&Message::from_digest([0; 32]), &key(3).0),
+            sighash_type: bitcoin::EcdsaSighashType::All,
+        });
+        let preimage = coin.preimage_known.then_some([coin.hashvalue; 32]);
+        (contract_tx, contract_redeemscript, sig, preimage)
+    }
+
+    fn incoming(coin: Coin, other_privkey_known: bool) -> IncomingSwapCoin {
+        let (contract_tx, contract_redeemscript, others_contract_sig, hash_preimage) = parts(&coin);
+        IncomingSwapCoin {
+            my_privkey: key(4).0,
+            other_pubkey: key(5).1,
+            other_privkey: other_privkey_known.then(|| key(5).0),
+            contract_tx,
+            contract_redeemscript,
+            hashlock_privkey: key(1).0,
+            funding_amount: Amount::from_sat(coin.amount),
+            others_contract_sig,
+            hash_preimage,
+            my_key_index: None,
+            hashlock_key_index: None,
+        }
+    }
+
+    fn outgoing(coin: Coin) -> OutgoingSwapCoin {
+        let (contract_tx, contract_redeemscript, others_contract_sig, hash_preimage) = parts(&coin);
+        OutgoingSwapCoin {
+            my_privkey: key(6).0,
+            other_pubkey: key(7).1,
+            contract_tx,
+            contract_redeemscript,
+            timelock_privkey: key(2).0,
+            funding_amount: Amount::from_sat(coin.amount),
+            others_contract_sig,
+            hash_preimage,
+            my_key_index: None,
+            timelock_key_index: None,
+        }
+    }
+
+    #[test]
+    fn test_group_active_swaps() {
+        let incomings = [
+            // Swap 1: preimage known, waiting for the private keys.
+            incoming(
+                Coin {
+                    hashvalue: 1,
+                    timelock: 40,
+                    amount: 1_000,
+                    signed: true,
+                    preimage_known: true,
+                },
+                false,
+            ),
+            // Swap 3: completed.
+            incoming(
+                Coin {
+                    hashvalue: 3,
+                    timelock: 40,
+                    amount: 3_000,
+                    signed: true,
+                    preimage_known: true,
+                },
+                true,
+            ),
+            // Swap 4: completed on the maker side, where the preimage is only set on the
+            // incoming swapcoins.
+            incoming(
+                Coin {
+                    hashvalue: 4,
+                    timelock: 40,
+                    amount: 4_000,
+                    signed: true,
+                    preimage_known: true,
+                },
+                true,
+            ),
+        ];
+        let outgoings = [
+            outgoing(Coin {
+                hashvalue: 1,
+                timelock: 20,
+                amount: 990,
+                signed: true,
+                preimage_known: true,
+            }),
+            // Swap 2: one contract isn't signed yet.
+            outgoing(Coin {
+                hashvalue: 2,
+                timelock: 20,
+                amount: 2_000,
+                signed: true,
+                preimage_known: false,
+            }),
+            outgoing(Coin {
+                hashvalue: 2,
+                timelock: 20,
+                amount: 500,
+                signed: false,
+                preimage_known: false,
+            }),
+            outgoing(Coin {
+                hashvalue: 3,
+                timelock: 20,
+                amount: 2_990,
+                signed: true,
+                preimage_known: true,
+            }),
+            outgoing(Coin {
+                hashvalue: 4,
+                timelock: 20,
+                amount: 3_990,
+                signed: true,
+                preimage_known: false,
+            }),
+        ];
+
+        let swaps = group_active_swaps(incomings.iter(), outgoings.iter());
+        assert_eq!(swaps.len(), 2);
+
+        assert_eq!(swaps[0].swap_id, Hash160::from_byte_array([1; 20]));
+        assert_eq!(swaps[0].phase, ActiveSwapPhase::PreimageRevealed);
+        assert_eq!(swaps[0].incoming_amount(), Amount::from_sat(1_000));
+        assert_eq!(swaps[0].outgoing_amount(), Amount::from_sat(990));
+        assert_eq!(swaps[0].min_timelock(), Some(20));
+        assert_eq!(swaps[0].incoming[0].counterparty, key(5).1);
+        assert_eq!(
+            swaps[0].incoming[0].contract_txid,
+            incomings[0].contract_tx.compute_txid()
+        );
+
+        assert_eq!(swaps[1].swap_id, Hash160::from_byte_array([2; 20]));
+        assert_eq!(swaps[1].phase, ActiveSwapPhase::ContractsPending);
+        assert!(swaps[1].incoming.is_empty());
+        assert_eq!(swaps[1].outgoing_amount(), Amount::from_sat(2_500));
+        assert_eq!(swaps[1].outgoing[1].counterparty, key(7).1);
+    }
+}
@@ -3,7 +3,13 @@
 //! Currently, wallet synchronization is exclusively performed through RPC for makers.
 //! In the future, takers might adopt alternative synchronization methods, such as lightweight wallet solutions.
 
-use std::{convert::TryFrom, fs, path::PathBuf, str::FromStr};
+use std::{
+    convert::TryFrom,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
 
 use std::collections::{HashMap, HashSet};
 
@@ -13,23 +19,24 @@ use bitcoin::{
     secp256k1,
     secp256k1::{Secp256k1, SecretKey},
     sighash::{EcdsaSighashType, SighashCache},
-    Address, Amount, OutPoint, PublicKey, Script, ScriptBuf, Transaction, Txid,
+    Address, Amount, BlockHash, OutPoint, PublicKey, Script, ScriptBuf, Transaction, TxIn, Txid,
 };
 
 use bitcoind::bitcoincore_rpc::{bitcoincore_rpc_json::ListUnspentResultEntry, Client, RpcApi};
 
 use crate::{
-    protocol::contract,
+    protocol::contract::{self, Hashlock},
     utill::{
-        compute_checksum, generate_keypair, get_hd_path_from_descriptor,
-        redeemscript_to_scriptpubkey,
+        append_descriptor_checksum, get_hd_path_from_descriptor, redeemscript_to_scriptpubkey,
     },
 };
 
 use super::{
+    backend::{ChainBackend, CoreRpcBackend},
     error::WalletError,
-    rpc::RPCConfig,
-    storage::WalletStore,
+    funding::OutputShaping,
+    rpc::{strip_checksum, RPCConfig},
+    storage::{StoreKey, WalletStore},
     swapcoin::{IncomingSwapCoin, OutgoingSwapCoin, SwapCoin, WalletSwapCoin},
 };
 
@@ -37,13 +44,32 @@ use super::{
 // data in the bitcoin core wallet
 // for example which privkey corresponds to a scriptpubkey is stored in hd paths
 
-const HARDENDED_DERIVATION: &str = "m/84'/1'/0'";
+pub(super) const HARDENDED_DERIVATION: &str = "m/84'/1'/0'";
+
+/// Hardened child of [`HARDENDED_DERIVATION`] the swap keys are derived under, see
+/// [`Wallet::derive_swap_key`]. Next to the unhardened [`KeychainKind`]s of the same account.
+const SWAP_KEYCHAIN_INDEX: u32 = 3;
+
+/// Derivation path of the swap key at `swap_index`, see [`Wallet::derive_swap_key`].
+pub(super) fn swap_key_path(swap_index: u32) -> Result<DerivationPath, WalletError> {
+    Ok(DerivationPath::from_str(HARDENDED_DERIVATION)?
+        .child(ChildNumber::from_hardened_idx(SWAP_KEYCHAIN_INDEX)?)
+        .child(ChildNumber::from_hardened_idx(swap_index)?))
+}
 
 /// Represents a Bitcoin wallet with associated functionality and data.
 pub struct Wallet {
-    pub(crate) rpc: Client,
-    wallet_file_path: PathBuf,
+    /// The core node's client, for what only a core wallet offers, see [`Wallet::core_rpc`].
+    /// Shared with the other wallets of a [`super::WalletManager`]. `None` for wallets on another
+    /// [`ChainBackend`], see [`Wallet::init_with_backend`].
+    pub(crate) rpc: Option<Arc<Client>>,
+    pub(crate) rpc_config: RPCConfig,
+    /// Syncs the wallet and broadcasts its transactions. The core node by default.
+    pub(crate) backend: Arc<dyn ChainBackend>,
+    pub(super) wallet_file_path: PathBuf,
     pub(crate) store: WalletStore,
+    /// Set if the wallet file is encrypted, see [`Wallet::save_encrypted`].
+    pub(super) store_key: Option<StoreKey>,
 }
 
 /// Speicfy the keychain derivation path from [`HARDENDED_DERIVATION`]
@@ -55,7 +81,7 @@ pub enum KeychainKind {
 }
 
 impl KeychainKind {
-    fn index_num(&self) -> u32 {
+    pub(super) fn index_num(&self) -> u32 {
         match self {
             Self::External => 0,
             Self::Internal => 1,
@@ -134,6 +160,41 @@ pub enum UTXOSpendInfo {
         index: u32,
         input_value: Amount,
     },
+    /// Coin of the descriptor the wallet was set up from, see [`Wallet::from_existing_descriptor`].
+    /// `index` is `None` for a fixed descriptor.
+    ExistingDescriptorCoin {
+        index: Option<u32>,
+        input_value: Amount,
+    },
+}
+
+/// Where a spendable UTXO came from. See [`Wallet::list_utxo_with_origin`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UtxoOrigin {
+    /// Output of a swap, identified by the swap's hashvalue. Either a swapcoin, a contract
+    /// output, or a sweep of one of those.
+    SwapOutput { swap_id: Hash160 },
+    /// Change of a transaction made by the wallet.
+    Change,
+    /// Deposit to one of the wallet's receiving addresses.
+    External,
+    /// Proceeds of a redeemed fidelity bond.
+    FidelityRedeem,
+}
+
+/// Wallet balance, broken down by category. See [`Wallet::get_balances`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Balances {
+    /// Confirmed coins the wallet can spend: seed coins, completed swapcoins and spendable contracts.
+    pub spendable: Amount,
+    /// Locked in incoming swapcoins and their contracts, until the swap completes.
+    pub incoming_swapcoins: Amount,
+    /// Locked in outgoing swapcoins and their contracts, until the swap completes or times out.
+    pub outgoing_swapcoins: Amount,
+    /// Locked in fidelity bonds.
+    pub fidelity: Amount,
+    /// Unconfirmed coins, and immature coinbase outputs.
+    pub unconfirmed: Amount,
 }
 
 // Custom type to handle complex return values.
@@ -144,11 +205,90 @@ type SwapCoinsInfo<'a> = (
 
 impl Wallet {
     /// Initialize the wallet at a given path.
+    /// The wallet birthday is set to the node's current tip.
     pub fn init(
         path: &PathBuf,
         rpc_config: &RPCConfig,
         seedphrase: String,
         passphrase: String,
+    ) -> Result<Self, WalletError> {
+        Self::init_with_birthday(path, rpc_config, seedphrase, passphrase, None)
+    }
+
+    /// Initialize the wallet at a given path, with a known wallet birthday.
+    ///
+    /// Use this when restoring from a seed whose creation height is known. Blocks below the
+    /// birthday are never rescanned. If `None`, the birthday is set to the node's current tip.
+    pub fn init_with_birthday(
+        path: &PathBuf,
+        rpc_config: &RPCConfig,
+        seedphrase: String,
+        passphrase: String,
+        wallet_birthday: Option<u64>,
+    ) -> Result<Self, WalletError> {
+        let rpc = Arc::new(Client::try_from(rpc_config)?);
+        Self::init_with_client(
+            path,
+            rpc_config,
+            rpc,
+            seedphrase,
+            passphrase,
+            wallet_birthday,
+        )
+    }
+
+    /// Same as [`Wallet::init_with_birthday`], over an already connected client.
+    pub(super) fn init_with_client(
+        path: &PathBuf,
+        rpc_config: &RPCConfig,
+        rpc: Arc<Client>,
+        seedphrase: String,
+        passphrase: String,
+        wallet_birthday: Option<u64>,
+    ) -> Result<Self, WalletError> {
+        let backend = Arc::new(CoreRpcBackend::new(rpc.clone(), rpc_config.clone()));
+        Self::init_store(
+            path,
+            rpc_config,
+            Some(rpc),
+            backend,
+            seedphrase,
+            passphrase,
+            wallet_birthday,
+        )
+    }
+
+    /// Same as [`Wallet::init_with_birthday`], syncing with `backend` instead of a core node.
+    ///
+    /// Only the network and the sync settings of `rpc_config` are used, and the wallet name must
+    /// match the file name. Features needing a core wallet error, see [`Wallet::core_rpc`].
+    pub fn init_with_backend(
+        path: &PathBuf,
+        rpc_config: &RPCConfig,
+        backend: Arc<dyn ChainBackend>,
+        seedphrase: String,
+        passphrase: String,
+        wallet_birthday: Option<u64>,
+    ) -> Result<Self, WalletError> {
+        Self::init_store(
+            path,
+            rpc_config,
+            None,
+            backend,
+            seedphrase,
+            passphrase,
+            wallet_birthday,
+        )
+    }
+
+    fn init_store(
+        path: &PathBuf,
+        rpc_config: &RPCConfig,
+        rpc: Option<Arc<Client>>,
+        backend: Arc<dyn ChainBackend>,
+        seedphrase: String,
+        passphrase: String,
+        wallet_birthday: Option<u64>,
     ) -> Result<Self, WalletError> {
         // Xpriv Derivation from seedphrase
         let mnemonic = bip39::Mnemonic::parse(seedphrase.clone())?;
@@ -162,8 +302,10 @@ impl Wallet {
             .to_str()
             .expect("expected")
             .to_string();
-        let rpc = Client::try_from(rpc_config)?;
-        let wallet_birthday = rpc.get_block_count()?;
+        let wallet_birthday = match wallet_birthday {
+            Some(height) => height,
+            None => backend.get_block_count()?,
+        };
         let store = WalletStore::init(
             file_name,
             path,
@@ -172,9 +314,12 @@ impl Wallet {
             Some(wallet_birthday),
         )?;
         Ok(Self {
+            backend,
             rpc,
+            rpc_config: rpc_config.clone(),
             wallet_file_path: path.clone(),
             store,
+            store_key: None,
         })
     }
 
@@ -182,13 +327,58 @@ impl Wallet {
     /// The core rpc wallet name, and wallet_id field in the file should match.
     pub fn load(rpc_config: &RPCConfig, path: &PathBuf) -> Result<Wallet, WalletError> {
         let store = WalletStore::read_from_disk(path)?;
+        let rpc = Arc::new(Client::try_from(rpc_config)?);
+        Self::load_store(rpc_config, rpc, path, store, None)
+    }
+
+    /// Same as [`Wallet::load`], for a wallet file encrypted with [`Wallet::save_encrypted`].
+    /// Later saves stay encrypted with the same passphrase.
+    pub fn load_encrypted(
+        rpc_config: &RPCConfig,
+        path: &PathBuf,
+        passphrase: &str,
+    ) -> Result<Wallet, WalletError> {
+        let (store, store_key) = WalletStore::read_from_disk_encrypted(path, passphrase)?;
+        let rpc = Arc::new(Client::try_from(rpc_config)?);
+        Self::load_store(rpc_config, rpc, path, store, Some(store_key))
+    }
+
+    /// Same as [`Wallet::load`], syncing with `backend` instead of a core node. See
+    /// [`Wallet::init_with_backend`].
+    pub fn load_with_backend(
+        rpc_config: &RPCConfig,
+        path: &PathBuf,
+        backend: Arc<dyn ChainBackend>,
+    ) -> Result<Wallet, WalletError> {
+        let store = WalletStore::read_from_disk(path)?;
+        Self::from_store(rpc_config, None, backend, path, store, None)
+    }
+
+    pub(super) fn load_store(
+        rpc_config: &RPCConfig,
+        rpc: Arc<Client>,
+        path: &Path,
+        store: WalletStore,
+        store_key: Option<StoreKey>,
+    ) -> Result<Wallet, WalletError> {
+        let backend = Arc::new(CoreRpcBackend::new(rpc.clone(), rpc_config.clone()));
+        Self::from_store(rpc_config, Some(rpc), backend, path, store, store_key)
+    }
+
+    fn from_store(
+        rpc_config: &RPCConfig,
+        rpc: Option<Arc<Client>>,
+        backend: Arc<dyn ChainBackend>,
+        path: &Path,
+        store: WalletStore,
+        store_key: Option<StoreKey>,
+    ) -> Result<Wallet, WalletError> {
         if rpc_config.wallet_name != store.file_name {
             return Err(WalletError::Protocol(format!(
                 "Wallet name of database file and core missmatch, expected {}, found {}",
                 rpc_config.wallet_name, store.file_name
             )));
         }
-        let rpc = Client::try_from(rpc_config)?;
         log::info!(
             "Loaded wallet file {} | External Index = {} | Incoming Swapcoins = {} | Outgoing Swapcoins = {}",
             store.file_name,
@@ -197,9 +387,12 @@ impl Wallet {
             store.outgoing_swapcoins.len()
         );
         let wallet = Self {
+            backend,
             rpc,
-            wallet_file_path: path.clone(),
+            rpc_config: rpc_config.clone(),
+            wallet_file_path: path.to_path_buf(),
             store,
+            store_key,
         };
         Ok(wallet)
     }
@@ -209,6 +402,51 @@ impl Wallet {
         Ok(fs::remove_file(&self.wallet_file_path)?)
     }
 
+    /// Replaces the [`ChainBackend`] the wallet syncs with and broadcasts to.
+    pub fn set_backend(&mut self, backend: Arc<dyn ChainBackend>) {
+        self.backend = backend;
+    }
+
+    /// The core node's client. Errors for wallets without one, see [`Wallet::init_with_backend`].
+    pub(crate) fn core_rpc(&self) -> Result<&Client, WalletError> {
+        self.rpc.as_deref().ok_or_else(|| {
+            WalletError::Protocol(
+                "This needs a wallet connected to a Bitcoin Core node".to_string(),
+            )
+        })
+    }
+
+    /// Mirrors coin locks to the core wallet, if the wallet has one. Coin selection only relies on
+    /// the locks kept in the wallet file.
+    pub(super) fn core_lock_unspent(&self, outpoints: &[OutPoint]) -> Result<(), WalletError> {
+        if let Some(rpc) = &self.rpc {
+            rpc.lock_unspent(outpoints)?;
+        }
+        Ok(())
+    }
+
+    /// Releases the coin locks of the core wallet, if the wallet has one.
+    pub(super) fn core_unlock_unspent_all(&self) -> Result<(), WalletError> {
+        if let Some(rpc) = &self.rpc {
+            rpc.unlock_unspent_all()?;
+        }
+        Ok(())
+    }
+
+    /// Broadcasts `tx` if the backend's mempool accepts it. Otherwise nothing is
+    /// broadcasted, and the reject reason is returned as [`WalletError::MempoolRejected`].
+    pub fn broadcast_checked(&self, tx: &Transaction) -> Result<Txid, WalletError> {
+        Ok(self.broadcast_all_checked(std::slice::from_ref(tx))?[0])
+    }
+
+    /// Same as [`Wallet::broadcast_checked`], for transactions going out together like the
+    /// funding transactions of a swap. None is broadcasted unless all of them are accepted.
+    /// Parents must come before their children.
+    pub fn broadcast_all_checked(&self, txs: &[Transaction]) -> Result<Vec<Txid>, WalletError> {
+        self.backend.test_mempool_accept(txs)?;
+        txs.iter().map(|tx| self.backend.broadcast(tx)).collect()
+    }
+
     /// Returns a reference to the file path of the wallet.
     pub fn get_file_path(&self) -> &PathBuf {
         &self.wallet_file_path
@@ -220,13 +458,45 @@ impl Wallet {
         self.save_to_disk()
     }
 
+    /// Sets the wallet birthday and saves to disk. Rescans never go below this height.
+    pub fn set_birthday(&mut self, height: u64) -> Result<(), WalletError> {
+        self.store.wallet_birthday = Some(height);
+        self.save_to_disk()
+    }
+
+    /// Gets the wallet birthday, if known.
+    pub fn get_birthday(&self) -> Option<u64> {
+        self.store.wallet_birthday
+    }
+
+    /// Gets the height and hash of the block the wallet last synced to, if any.
+    pub fn get_last_synced_block(&self) -> Option<(u64, BlockHash)> {
+        self.store
+            .last_synced_height
+            .zip(self.store.last_synced_hash)
+    }
+
     // pub fn get_external_index(&self) -> u32 {
     //     self.external_index
     // }
 
     /// Update the existing file. Error if path does not exist.
     pub fn save_to_disk(&self) -> Result<(), WalletError> {
-        self.store.write_to_disk(&self.wallet_file_path)
+        match &self.store_key {
+            Some(key) => self
+                .store
+                .write_to_disk_encrypted(&self.wallet_file_path, key),
+            None => self.store.write_to_disk(&self.wallet_file_path),
+        }
+    }
+
+    /// Encrypts the wallet file with a key derived from `passphrase`. This and every later save
+    /// are encrypted, load the file back with [`Wallet::load_encrypted`].
+    ///
+    /// Calling it again changes the passphrase.
+    pub fn save_encrypted(&mut self, passphrase: &str) -> Result<(), WalletError> {
+        self.store_key = Some(StoreKey::new(passphrase)?);
+        self.save_to_disk()
     }
 
     /// Finds an incoming swap coin with the specified multisig redeem script.
@@ -358,6 +628,50 @@ impl Wallet {
             .fold(Amount::ZERO, |sum, (utxo, _)| sum + utxo.amount))
     }
 
+    /// Calculates the wallet balance of each category in one pass over the UTXOs.
+    pub fn get_balances(&self) -> Result<Balances, WalletError> {
+        let (contract_scriptpubkeys_outgoing, contract_scriptpubkeys_incoming) = (
+            self.create_contract_scriptpubkey_outgoing_swapcoin_hashmap(),
+            self.create_contract_scriptpubkey_incoming_swapcoin_hashmap(),
+        );
+
+        let mut balances = Balances::default();
+        for utxo in self.get_all_utxo()? {
+            let multisig_redeemscript = utxo.witness_script.as_ref();
+            let bucket = match self.utxo_spend_info(&utxo) {
+                Some(UTXOSpendInfo::FidelityBondCoin { .. }) => &mut balances.fidelity,
+                Some(_) if utxo.confirmations == 0 => &mut balances.unconfirmed,
+                Some(_) => &mut balances.spendable,
+                // Swapcoins and contracts of unfinished swaps aren't spendable yet.
+                None if multisig_redeemscript
+                    .is_some_and(|rs| self.store.incoming_swapcoins.contains_key(rs))
+                    || contract_scriptpubkeys_incoming.contains_key(&utxo.script_pub_key) =>
+                {
+                    &mut balances.incoming_swapcoins
+                }
+                None if multisig_redeemscript
+                    .is_some_and(|rs| self.store.outgoing_swapcoins.contains_key(rs))
+                    || contract_scriptpubkeys_outgoing.contains_key(&utxo.script_pub_key) =>
+                {
+                    &mut balances.outgoing_swapcoins
+                }
+                None => continue,
+            };
+            *bucket += utxo.amount;
+        }
+
+        // Immature coinbase outputs aren't listed by `listunspent`.
+        if let Some(rpc) = &self.rpc {
+            let core_balances = rpc.get_balances()?;
+            balances.unconfirmed += core_balances.mine.immature
+                + core_balances
+                    .watchonly
+                    .map_or(Amount::ZERO, |watchonly| watchonly.immature);
+        }
+
+        Ok(balances)
+    }
+
     /// Checks if the previous output (prevout) matches the cached contract in the wallet.
     ///
     /// This function is used in two scenarios:
@@ -387,13 +701,21 @@ impl Wallet {
         })
     }
 
-    /// Dynamic address import count function. 10 for tests, 5000 for production.
+    /// Number of addresses imported on each keychain, at least [`RPCConfig::gap_limit`] past the next unused index.
+    /// A ranged existing descriptor counts as a keychain, see [`Wallet::from_existing_descriptor`].
     pub fn get_addrss_import_count(&self) -> u32 {
-        if cfg!(feature = "integration-test") {
-            10
-        } else {
-            5000
-        }
+        let existing_index = self
+            .store
+            .existing_descriptor
+            .as_ref()
+            .map_or(0, |existing_descriptor| existing_descriptor.next_index);
+        import_count(
+            self.store
+                .external_index
+                .max(self.store.internal_index)
+                .max(existing_index),
+            self.rpc_config.gap_limit,
+        )
     }
 
     /// Stores an entry into [`WalletStore`]'s prevout-to-contract map.
@@ -415,7 +737,9 @@ impl Wallet {
     //pub fn get_recovery_phrase_from_file()
 
     /// Wallet descriptors are derivable. Currently only supports two KeychainKind. Internal and External.
-    fn get_wallet_descriptors(&self) -> Result<HashMap<KeychainKind, String>, WalletError> {
+    pub(super) fn get_wallet_descriptors(
+        &self,
+    ) -> Result<HashMap<KeychainKind, String>, WalletError> {
         let secp = Secp256k1::new();
         let wallet_xpub = Xpub::from_priv(
             &secp,
@@ -435,52 +759,35 @@ impl Wallet {
             .map(|keychain| {
                 let descriptor_without_checksum =
                     format!("wpkh({}/{}/*)", wallet_xpub, keychain.index_num());
-                let decriptor = format!(
-                    "{}#{}",
-                    descriptor_without_checksum,
-                    compute_checksum(&descriptor_without_checksum).unwrap()
-                );
-                (*keychain, decriptor)
+                let decriptor = append_descriptor_checksum(&descriptor_without_checksum)?;
+                Ok((*keychain, decriptor))
             })
-            .collect::<HashMap<KeychainKind, String>>();
+            .collect::<Result<HashMap<KeychainKind, String>, WalletError>>()?;
 
         Ok(x)
         //descriptors.map_err(|e| TeleportError::Rpc(e))
     }
 
-    /// Checks if the addresses derived from the wallet descriptor is imported upto full index range.
+    /// Checks if the wallet descriptors are imported upto full index range, given the
+    /// descriptors currently imported in the core wallet.
     /// Returns the list of descriptors not imported yet
-    /// Index range depend on [`WalletMode`].
-    /// Normal => 5000
-    /// Test => 6
-    pub(super) fn get_unimported_wallet_desc(&self) -> Result<Vec<String>, WalletError> {
-        let mut unimported = Vec::new();
-        for (_, descriptor) in self.get_wallet_descriptors()? {
-            let first_addr = self.rpc.derive_addresses(&descriptor, Some([0, 0]))?[0].clone();
-
-            let last_index = self.get_addrss_import_count() - 1;
-            let last_addr = self
-                .rpc
-                .derive_addresses(&descriptor, Some([last_index, last_index]))?[0]
-                .clone();
-
-            let first_addr_imported = self
-                .rpc
-                .get_address_info(&first_addr.assume_checked())?
-                .is_watchonly
-                .unwrap_or(false);
-            let last_addr_imported = self
-                .rpc
-                .get_address_info(&last_addr.assume_checked())?
-                .is_watchonly
-                .unwrap_or(false);
-
-            if !first_addr_imported || !last_addr_imported {
-                unimported.push(descriptor);
-            }
-        }
-
-        Ok(unimported)
+    /// Index range is given by [`Wallet::get_addrss_import_count`].
+    pub(super) fn get_unimported_wallet_desc(
+        &self,
+        imported: &HashMap<String, Option<u32>>,
+    ) -> Result<Vec<String>, WalletError> {
+        let last_index = self.get_addrss_import_count() - 1;
+        Ok(self
+            .get_wallet_descriptors()?
+            .into_values()
+            .filter(|descriptor| {
+                !matches!(
+                    imported.get(strip_checksum(descriptor)),
+                    Some(Some(range_end)) if *range_end >= last_index
+                )
+            })
+            .chain(self.unimported_existing_descriptor(imported))
+            .collect())
     }
 
     /// Gets the external index from the wallet.
@@ -526,24 +833,74 @@ impl Wallet {
     }
 
     /// Locks the fidelity and live_contract utxos which are not considered for spending from the wallet.
+    /// Also locks the utxos reserved with [`Wallet::lock_utxo`].
     pub fn lock_unspendable_utxos(&self) -> Result<(), WalletError> {
-        self.rpc.unlock_unspent_all()?;
+        self.core_unlock_unspent_all()?;
 
-        let all_unspents = self
-            .rpc
-            .list_unspent(Some(0), Some(9999999), None, None, None)?;
+        let all_unspents = self.backend.list_unspent()?;
         let utxos_to_lock = &all_unspents
             .into_iter()
-            .filter(|u| self.check_descriptor_utxo_or_swap_coin(u).is_none())
+            .filter(|u| {
+                self.check_descriptor_utxo_or_swap_coin(u).is_none()
+                    || self.is_utxo_locked(&OutPoint::new(u.txid, u.vout))
+            })
             .map(|u| OutPoint {
                 txid: u.txid,
                 vout: u.vout,
             })
             .collect::<Vec<OutPoint>>();
-        self.rpc.lock_unspent(utxos_to_lock)?;
+        self.core_lock_unspent(utxos_to_lock)?;
         Ok(())
     }
 
+    /// Reserves a utxo, so that coin selection never spends it. Useful when the utxo is committed
+    /// to an ongoing swap. The lock is saved in the wallet file, and mirrored to the core wallet with `lockunspent`.
+    pub fn lock_utxo(&mut self, outpoint: OutPoint) -> Result<(), WalletError> {
+        self.core_lock_unspent(&[outpoint])?;
+        self.store.locked_utxos.insert(outpoint);
+        self.save_to_disk()
+    }
+
+    /// Releases a utxo reserved with [`Wallet::lock_utxo`].
+    pub fn unlock_utxo(&mut self, outpoint: OutPoint) -> Result<(), WalletError> {
+        self.store.locked_utxos.remove(&outpoint);
+        self.save_to_disk()?;
+        // Core's locks are cleared regularly, so re-derive them instead of unlocking the single outpoint.
+        self.lock_unspendable_utxos()
+    }
+
+    /// Sends the change of the funding transactions created from now on to fresh internal
+    /// addresses, and keeps it apart from swap coins: coin selection never spends that change
+    /// together with coins received from a swap, which would link the swap to the wallet.
+    ///
+    /// Change isolated while the flag was set stays isolated once it is cleared.
+    pub fn set_avoid_change_reuse(&mut self, avoid_change_reuse: bool) -> Result<(), WalletError> {
+        self.store.avoid_change_reuse = avoid_change_reuse;
+        self.save_to_disk()
+    }
+
+    /// Checks if [`Wallet::set_avoid_change_reuse`] is set.
+    pub fn avoid_change_reuse(&self) -> bool {
+        self.store.avoid_change_reuse
+    }
+
+    /// Checks if the utxo is change isolated by [`Wallet::set_avoid_change_reuse`].
+    pub fn is_isolated_change(&self, utxo: &ListUnspentResultEntry) -> bool {
+        self.store.isolated_change.contains(&utxo.script_pub_key)
+    }
+
+    /// Checks if the utxo is reserved with [`Wallet::lock_utxo`].
+    pub fn is_utxo_locked(&self, outpoint: &OutPoint) -> bool {
+        self.store.locked_utxos.contains(outpoint)
+    }
+
+    /// Returns the UTXOSpendInfo of a UTXO, or None if the wallet can't spend it.
+    fn utxo_spend_info(&self, utxo: &ListUnspentResultEntry) -> Option<UTXOSpendInfo> {
+        self.check_if_fidelity(utxo)
+            .or_else(|| self.check_if_live_contract(utxo))
+            .or_else(|| self.check_descriptor_utxo_or_swap_coin(utxo))
+    }
+
     /// Checks if a UTXO belongs to fidelity bonds, and then returns corresponding UTXOSpendInfo
     fn check_if_fidelity(&self, utxo: &ListUnspentResultEntry) -> Option<UTXOSpendInfo> {
         self.store
@@ -588,32 +945,49 @@ impl Wallet {
         None
     }
 
+    /// Spend info of a coin of the wallet's HD keychains, from the descriptor Core reports for it.
+    /// `None` if the descriptor isn't derived from the wallet's master key.
+    pub(super) fn seed_coin_spend_info(
+        &self,
+        descriptor: &str,
+        input_value: Amount,
+    ) -> Option<UTXOSpendInfo> {
+        let (fingerprint, addr_type, index) = get_hd_path_from_descriptor(descriptor)?;
+
+        let secp = Secp256k1::new();
+        let master_private_key = self
+            .store
+            .master_key
+            .derive_priv(
+                &secp,
+                &DerivationPath::from_str(HARDENDED_DERIVATION).unwrap(),
+            )
+            .unwrap();
+        if fingerprint == master_private_key.fingerprint(&secp).to_string() {
+            Some(UTXOSpendInfo::SeedCoin {
+                path: format!("m/{}/{}", addr_type, index),
+                input_value,
+            })
+        } else {
+            None
+        }
+    }
+
     /// Checks if a UTXO belongs to descriptor or swap coin, and then returns corresponding UTXOSpendInfo
     fn check_descriptor_utxo_or_swap_coin(
         &self,
         utxo: &ListUnspentResultEntry,
     ) -> Option<UTXOSpendInfo> {
         if let Some(descriptor) = &utxo.descriptor {
+            if let Some(spend_info) =
+                self.existing_descriptor_spend_info(descriptor, &utxo.script_pub_key, utxo.amount)
+            {
+                return Some(spend_info);
+            }
             // Descriptor logic here
-            if let Some(ret) = get_hd_path_from_descriptor(descriptor) {
+            if get_hd_path_from_descriptor(descriptor).is_some() {
                 //utxo is in a hd wallet
-                let (fingerprint, addr_type, index) = ret;
-
-                let secp = Secp256k1::new();
-                let master_private_key = self
-                    .store
-                    .master_key
-                    .derive_priv(
-                        &secp,
-                        &DerivationPath::from_str(HARDENDED_DERIVATION).unwrap(),
-                    )
-                    .unwrap();
-                if fingerprint == master_private_key.fingerprint(&secp).to_string() {
-                    return Some(UTXOSpendInfo::SeedCoin {
-                        path: format!("m/{}/{}", addr_type, index),
-                        input_value: utxo.amount,
-                    });
-                }
+                return self.seed_coin_spend_info(descriptor, utxo.amount);
             } else {
                 //utxo might be one of our swapcoins
                 let found = self
@@ -640,19 +1014,122 @@ impl Wallet {
         None
     }
 
+    /// Lists the spendable UTXOs, labelled with their origin.
+    ///
+    /// The origin comes from the descriptor owning the output. Outputs on the internal keychain
+    /// are told apart by what their transaction spends: a fidelity bond, a swapcoin or contract
+    /// still in the wallet, or else plain change.
+    pub fn list_utxo_with_origin(
+        &self,
+    ) -> Result<Vec<(ListUnspentResultEntry, UtxoOrigin)>, WalletError> {
+        self.list_all_utxo_spend_info(None)?
+            .into_iter()
+            .filter_map(|(utxo, spend_info)| {
+                self.utxo_origin(&utxo, &spend_info)
+                    .transpose()
+                    .map(|origin| origin.map(|origin| (utxo, origin)))
+            })
+            .collect()
+    }
+
+    /// The origin of a UTXO, see [`Wallet::list_utxo_with_origin`]. None for fidelity bonds.
+    pub(super) fn utxo_origin(
+        &self,
+        utxo: &ListUnspentResultEntry,
+        spend_info: &UTXOSpendInfo,
+    ) -> Result<Option<UtxoOrigin>, WalletError> {
+        let internal_path = format!("m/{}/", KeychainKind::Internal.index_num());
+        let origin = match spend_info {
+            UTXOSpendInfo::FidelityBondCoin { .. } => return Ok(None),
+            UTXOSpendInfo::SwapCoin {
+                multisig_redeemscript,
+            }
+            | UTXOSpendInfo::TimelockContract {
+                swapcoin_multisig_redeemscript: multisig_redeemscript,
+                ..
+            }
+            | UTXOSpendInfo::HashlockContract {
+                swapcoin_multisig_redeemscript: multisig_redeemscript,
+                ..
+            } => self
+                .swap_hashvalue(multisig_redeemscript)
+                .map_or(UtxoOrigin::Change, |swap_id| UtxoOrigin::SwapOutput {
+                    swap_id,
+                }),
+            UTXOSpendInfo::SeedCoin { path, .. } if path.starts_with(&internal_path) => {
+                self.internal_utxo_origin(utxo)?
+            }
+            UTXOSpendInfo::SeedCoin { .. } | UTXOSpendInfo::ExistingDescriptorCoin { .. } => {
+                UtxoOrigin::External
+            }
+        };
+        Ok(Some(origin))
+    }
+
+    /// Labels an internal keychain UTXO by the inputs of its transaction.
+    fn internal_utxo_origin(
+        &self,
+        utxo: &ListUnspentResultEntry,
+    ) -> Result<UtxoOrigin, WalletError> {
+        let Some((tx, _)) = self.backend.get_wallet_transaction(&utxo.txid)? else {
+            return Err(WalletError::Protocol(format!(
+                "Transaction {} of a utxo isn't in the wallet history",
+                utxo.txid
+            )));
+        };
+
+        for input in &tx.input {
+            let spent = input.previous_output;
+            if self
+                .store
+                .fidelity_bond
+                .values()
+                .any(|(bond, _, _)| bond.outpoint == spent)
+            {
+                return Ok(UtxoOrigin::FidelityRedeem);
+            }
+            // A swapcoin is spent through its contract tx, whose output is then swept.
+            let swap = self
+                .store
+                .incoming_swapcoins
+                .values()
+                .map(|sc| (&sc.contract_tx, sc.get_hashvalue()))
+                .chain(
+                    self.store
+                        .outgoing_swapcoins
+                        .values()
+                        .map(|sc| (&sc.contract_tx, sc.get_hashvalue())),
+                )
+                .find(|(contract_tx, _)| {
+                    contract_tx.input[0].previous_output == spent
+                        || contract_tx.compute_txid() == spent.txid
+                });
+            if let Some((_, swap_id)) = swap {
+                return Ok(UtxoOrigin::SwapOutput { swap_id });
+            }
+        }
+        Ok(UtxoOrigin::Change)
+    }
+
+    /// The hashvalue of the swap a swapcoin belongs to.
+    pub(super) fn swap_hashvalue(&self, multisig_redeemscript: &ScriptBuf) -> Option<Hash160> {
+        self.find_incoming_swapcoin(multisig_redeemscript)
+            .map(|sc| sc.get_hashvalue())
+            .or_else(|| {
+                self.find_outgoing_swapcoin(multisig_redeemscript)
+                    .map(|sc| sc.get_hashvalue())
+            })
+    }
+
     /// Returns a list of all UTXOs tracked by the wallet. Including fidelity, live_contracts and swap coins.
     pub fn get_all_utxo(&self) -> Result<Vec<ListUnspentResultEntry>, WalletError> {
-        self.rpc.unlock_unspent_all()?;
-        let all_utxos = self
-            .rpc
-            .list_unspent(Some(0), Some(9999999), None, None, None)?;
+        self.core_unlock_unspent_all()?;
+        let all_utxos = self.backend.list_unspent()?;
         Ok(all_utxos)
     }
 
     pub fn get_all_locked_utxo(&self) -> Result<Vec<ListUnspentResultEntry>, WalletError> {
-        let all_utxos = self
-            .rpc
-            .list_unspent(Some(0), Some(9999999), None, None, None)?;
+        let all_utxos = self.backend.list_unspent()?;
         Ok(all_utxos)
     }
     /// Returns a list all utxos with their spend info tracked by the wallet.
@@ -672,16 +1149,7 @@ impl Wallet {
 
         let processed_utxos = all_utxos
             .iter()
-            .filter_map(|utxo| {
-                let mut spend_info = self.check_if_fidelity(utxo);
-                if spend_info.is_none() {
-                    spend_info = self.check_if_live_contract(utxo);
-                }
-                if spend_info.is_none() {
-                    spend_info = self.check_descriptor_utxo_or_swap_coin(utxo);
-                }
-                spend_info.map(|info| (utxo.clone(), info))
-            })
+            .filter_map(|utxo| self.utxo_spend_info(utxo).map(|info| (utxo.clone(), info)))
             .collect::<Vec<(ListUnspentResultEntry, UTXOSpendInfo)>>();
 
         Ok(processed_utxos)
@@ -726,7 +1194,12 @@ impl Wallet {
         let all_valid_utxo = self.list_all_utxo_spend_info(all_utxos)?;
         let filtered_utxos: Vec<_> = all_valid_utxo
             .iter()
-            .filter(|x| matches!(x.1, UTXOSpendInfo::SeedCoin { .. }))
+            .filter(|x| {
+                matches!(
+                    x.1,
+                    UTXOSpendInfo::SeedCoin { .. } | UTXOSpendInfo::ExistingDescriptorCoin { .. }
+                )
+            })
             .cloned()
             .collect();
         Ok(filtered_utxos)
@@ -750,7 +1223,7 @@ impl Wallet {
     pub fn find_incomplete_coinswaps(
         &self,
     ) -> Result<HashMap<Hash160, SwapCoinsInfo>, WalletError> {
-        self.rpc.unlock_unspent_all()?;
+        self.core_unlock_unspent_all()?;
 
         let completed_coinswap_hashvalues = self
             .store
@@ -768,10 +1241,7 @@ impl Wallet {
             }
             Some(swapcoin_hashvalue)
         };
-        for utxo in self
-            .rpc
-            .list_unspent(Some(0), Some(9999999), None, None, None)?
-        {
+        for utxo in self.backend.list_unspent()? {
             if utxo.descriptor.is_none() {
                 continue;
             }
@@ -839,6 +1309,107 @@ impl Wallet {
         (unfinished_incomins, unfinished_outgoings)
     }
 
+    /// Refunds an outgoing swapcoin through the timelock branch of its contract, and returns the
+    /// refund txid. `None` if the timelock hasn't matured yet.
+    ///
+    /// Broadcasts the contract tx if it's not seen on chain yet, so its timelock starts running. The
+    /// refund is broadcasted once the contract tx has more confirmations than the timelock, and the
+    /// swapcoin is then removed from the wallet.
+    pub fn refund_outgoing_swapcoin(
+        &mut self,
+        outgoing: &OutgoingSwapCoin,
+    ) -> Result<Option<Txid>, WalletError> {
+        let contract_tx = outgoing.get_fully_signed_contract_tx()?;
+        let contract_txid = contract_tx.compute_txid();
+        // Unknown means the contract tx hasn't been broadcasted yet.
+        let confirmations = match self.get_tx_confirmations(&contract_txid)? {
+            Some(confirmations) => confirmations,
+            None => {
+                self.backend.broadcast(&contract_tx)?;
+                log::info!(
+                    "Broadcasted Outgoing Contract, Contract txid : {}",
+                    contract_txid
+                );
+                None
+            }
+        };
+
+        let timelock = outgoing.get_timelock();
+        log::info!(
+            "Contract Tx : {}, reached confirmation : {:?}, required : {}",
+            contract_txid,
+            confirmations,
+            timelock
+        );
+        // Now the transaction is confirmed in a block, check for required maturity
+        if !matches!(confirmations, Some(confirmation) if confirmation > timelock as u32) {
+            return Ok(None);
+        }
+        log::info!(
+            "Timelock maturity of {} blocks for Contract Tx is reached : {}",
+            timelock,
+            contract_txid
+        );
+
+        let next_internal = &self.get_next_internal_addresses(1)?[0];
+        let timelocked_tx = outgoing.create_timelock_spend(next_internal);
+        log::info!(
+            "Broadcasting timelocked tx: {}",
+            timelocked_tx.compute_txid()
+        );
+        let refund_txid = self.backend.broadcast(&timelocked_tx)?;
+
+        self.remove_outgoing_swapcoin(&outgoing.get_multisig_redeemscript())?;
+        log::info!(
+            "Removed Outgoing Swapcoin from Wallet, Contract Txid: {}",
+            contract_txid
+        );
+        Ok(Some(refund_txid))
+    }
+
+    /// Claims an incoming swapcoin through the hashlock branch of its contract, and returns the
+    /// claim txid. `None` if the contract tx isn't confirmed yet. The preimage must be known.
+    ///
+    /// Broadcasts the contract tx if it's not seen on chain yet. The claim is broadcasted once the
+    /// contract tx is confirmed, and the swapcoin is then removed from the wallet.
+    pub fn claim_incoming_swapcoin(
+        &mut self,
+        incoming: &IncomingSwapCoin,
+    ) -> Result<Option<Txid>, WalletError> {
+        let contract_tx = incoming.get_fully_signed_contract_tx()?;
+        let contract_txid = contract_tx.compute_txid();
+        let confirmations = match self.get_tx_confirmations(&contract_txid)? {
+            Some(confirmations) => confirmations,
+            None => {
+                self.backend.broadcast(&contract_tx)?;
+                log::info!(
+                    "Broadcasted Incoming Contract, Contract txid : {}",
+                    contract_txid
+                );
+                None
+            }
+        };
+        // The hashlock branch has an `OP_CSV 1`.
+        if !matches!(confirmations, Some(confirmation) if confirmation >= 1) {
+            return Ok(None);
+        }
+
+        let next_internal = &self.get_next_internal_addresses(1)?[0];
+        let hashlocked_tx = incoming.create_hashlock_spend(next_internal);
+        log::info!(
+            "Broadcasting hashlocked tx: {}",
+            hashlocked_tx.compute_txid()
+        );
+        let claim_txid = self.backend.broadcast(&hashlocked_tx)?;
+
+        self.remove_incoming_swapcoin(&incoming.get_multisig_redeemscript())?;
+        log::info!(
+            "Removed Incoming Swapcoin from Wallet, Contract Txid: {}",
+            contract_txid
+        );
+        Ok(Some(claim_txid))
+    }
+
     /// Finds live contract unspent outputs in the wallet.
     // live contract refers to a contract tx which has been broadcast
     // i.e. where there are UTXOs protected by contract_redeemscript's that we know about
@@ -849,10 +1420,8 @@ impl Wallet {
         let contract_scriptpubkeys_outgoing_swapcoins =
             self.create_contract_scriptpubkey_outgoing_swapcoin_hashmap();
 
-        self.rpc.unlock_unspent_all()?;
-        let listunspent = self
-            .rpc
-            .list_unspent(Some(0), Some(9999999), None, None, None)?;
+        self.core_unlock_unspent_all()?;
+        let listunspent = self.backend.list_unspent()?;
 
         let (incoming_swapcoins_utxos, outgoing_swapcoins_utxos): (Vec<_>, Vec<_>) = listunspent
             .iter()
@@ -886,8 +1455,11 @@ impl Wallet {
         let mut swap_coin_utxo = self.list_swap_coin_utxo_spend_info(Some(&all_utxos))?;
         utxos.append(&mut swap_coin_utxo);
 
-        for (utxo, _) in utxos {
-            if utxo.descriptor.is_none() {
+        for (utxo, spend_info) in utxos {
+            // Coins of an existing descriptor may have an origin path that looks alike.
+            if utxo.descriptor.is_none()
+                || matches!(spend_info, UTXOSpendInfo::ExistingDescriptorCoin { .. })
+            {
                 continue;
             }
             let descriptor = utxo.descriptor.expect("its not none");
@@ -910,31 +1482,100 @@ impl Wallet {
         let receive_branch_descriptor = descriptors
             .get(&KeychainKind::External)
             .expect("external keychain expected");
-        let receive_address = self.rpc.derive_addresses(
+        let receive_address = self.derive_addresses(
             receive_branch_descriptor,
-            Some([self.store.external_index, self.store.external_index]),
+            self.store.external_index,
+            self.store.external_index,
         )?[0]
             .clone();
         self.update_external_index(self.store.external_index + 1)?;
-        Ok(receive_address.assume_checked())
+        Ok(receive_address)
+    }
+
+    /// Recalculate the next external and internal indices from the addresses that already received
+    /// funds, without rescanning the chain. Indices only move forward, so addresses already handed out
+    /// are never reused.
+    pub fn refresh_address_indices(&mut self) -> Result<(), WalletError> {
+        let used_spks = self
+            .core_rpc()?
+            .list_received_by_address(None, Some(0), Some(false), Some(true))?
+            .into_iter()
+            .map(|r| r.address.assume_checked().script_pubkey())
+            .collect::<HashSet<_>>();
+
+        let descriptors = self.get_wallet_descriptors()?;
+        let last_index = self.get_addrss_import_count() - 1;
+        for keychain in [KeychainKind::External, KeychainKind::Internal] {
+            let derived_spks = self
+                .backend
+                .derive_scriptpubkeys(&descriptors[&keychain], last_index)?;
+            let next_index = next_unused_index(&derived_spks, &used_spks);
+            let stored_index = match keychain {
+                KeychainKind::External => &mut self.store.external_index,
+                KeychainKind::Internal => &mut self.store.internal_index,
+            };
+            *stored_index = (*stored_index).max(next_index);
+        }
+
+        log::debug!(
+            "Refreshed address indices | External = {} | Internal = {}",
+            self.store.external_index,
+            self.store.internal_index
+        );
+        self.save_to_disk()
     }
 
     /// Gets the next internal addresses from the HD keychain.
     pub fn get_next_internal_addresses(&self, count: u32) -> Result<Vec<Address>, WalletError> {
-        let next_change_addr_index = self.find_hd_next_index(KeychainKind::Internal)?;
+        let next_change_addr_index = self
+            .find_hd_next_index(KeychainKind::Internal)?
+            .max(self.store.internal_index);
         let descriptors = self.get_wallet_descriptors()?;
         let change_branch_descriptor = descriptors
             .get(&KeychainKind::Internal)
             .expect("Internal Keychain expected");
-        let addresses = self.rpc.derive_addresses(
+        self.derive_addresses(
             change_branch_descriptor,
-            Some([next_change_addr_index, next_change_addr_index + count]),
-        )?;
+            next_change_addr_index,
+            next_change_addr_index + count,
+        )
+    }
 
-        Ok(addresses
+    /// Addresses of the ranged `descriptor`, from index `start` to `end`, both included.
+    fn derive_addresses(
+        &self,
+        descriptor: &str,
+        start: u32,
+        end: u32,
+    ) -> Result<Vec<Address>, WalletError> {
+        self.backend
+            .derive_scriptpubkeys(descriptor, end)?
             .into_iter()
-            .map(|addrs| addrs.assume_checked())
-            .collect())
+            .skip(start as usize)
+            .map(|spk| {
+                Address::from_script(&spk, self.store.network)
+                    .map_err(|e| WalletError::Protocol(e.to_string()))
+            })
+            .collect()
+    }
+
+    /// Confirmations of the transaction `txid`, as seen by the backend. `Some(None)` if it's in
+    /// the mempool, `None` if the backend doesn't know it.
+    pub(crate) fn get_tx_confirmations(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<Option<u32>>, WalletError> {
+        let height = match self.backend.get_transaction(txid)? {
+            Some((_, height)) => height,
+            None => return Ok(None),
+        };
+        let confirmations = match height {
+            Some(height) => {
+                Some((self.backend.get_block_count()? + 1).saturating_sub(height) as u32)
+            }
+            None => None,
+        };
+        Ok(Some(confirmations))
     }
 
     /// Refreshes the offer maximum size cache based on the current wallet's unspent transaction outputs (UTXOs).
@@ -970,11 +1611,47 @@ impl Wallet {
         (privkey, public_key)
     }
 
+    /// Derives the swap key at `swap_index` from the master key, at
+    /// `<HARDENDED_DERIVATION>/<SWAP_KEYCHAIN_INDEX>'/<swap_index>'`.
+    ///
+    /// The multisig and contract keys of the swapcoins the wallet creates are swap keys, and the
+    /// swapcoins record their index. A wallet restored from its seed can derive them back from the
+    /// swapcoins' metadata.
+    pub fn derive_swap_key(&self, swap_index: u32) -> Result<SecretKey, WalletError> {
+        let secp = Secp256k1::new();
+        Ok(self
+            .store
+            .master_key
+            .derive_priv(&secp, &swap_key_path(swap_index)?)?
+            .private_key)
+    }
+
+    /// Derives the next unused swap key, and returns it along with its index.
+    ///
+    /// The index is saved to disk before the key is handed out. The key's pubkey gets sent to the
+    /// makers right away, and a swap that fails before the wallet is saved again must not reuse it.
+    pub(crate) fn next_swap_key(&mut self) -> Result<(u32, SecretKey), WalletError> {
+        let swap_index = self.store.swap_key_index;
+        let swap_key = self.derive_swap_key(swap_index)?;
+        self.store.swap_key_index += 1;
+        self.save_to_disk()?;
+        Ok((swap_index, swap_key))
+    }
+
     /// Signs a transaction corresponding to the provided UTXO spend information.
     pub fn sign_transaction(
         &self,
         tx: &mut Transaction,
         inputs_info: impl Iterator<Item = UTXOSpendInfo>,
+    ) -> Result<(), WalletError> {
+        self.sign_transaction_inputs(tx, inputs_info.enumerate())
+    }
+
+    /// Signs the inputs of `tx` at the given indices, leaving the others to their owners.
+    pub(crate) fn sign_transaction_inputs(
+        &self,
+        tx: &mut Transaction,
+        inputs_info: impl Iterator<Item = (usize, UTXOSpendInfo)>,
     ) -> Result<(), WalletError> {
         let secp = Secp256k1::new();
         let master_private_key = self
@@ -987,7 +1664,10 @@ impl Wallet {
             .unwrap();
         let tx_clone = tx.clone();
 
-        for (ix, (input, input_info)) in tx.input.iter_mut().zip(inputs_info).enumerate() {
+        for (ix, input_info) in inputs_info {
+            let Some(input) = tx.input.get_mut(ix) else {
+                continue;
+            };
             match input_info {
                 UTXOSpendInfo::SwapCoin {
                     multisig_redeemscript,
@@ -1002,24 +1682,20 @@ impl Wallet {
                         .derive_priv(&secp, &DerivationPath::from_str(&path).unwrap())
                         .unwrap()
                         .private_key;
-                    let pubkey = PublicKey {
-                        compressed: true,
-                        inner: privkey.public_key(&secp),
-                    };
-                    let scriptcode = ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash().unwrap());
-                    let sighash = SighashCache::new(&tx_clone)
-                        .p2wpkh_signature_hash(ix, &scriptcode, input_value, EcdsaSighashType::All)
-                        .unwrap();
-                    //use low-R value signatures for privacy
-                    //https://en.bitcoin.it/wiki/Privacy#Wallet_fingerprinting
-                    let signature = secp.sign_ecdsa_low_r(
-                        &secp256k1::Message::from_digest_slice(&sighash[..]).unwrap(),
-                        &privkey,
-                    );
-                    let mut sig_serialised = signature.serialize_der().to_vec();
-                    sig_serialised.push(EcdsaSighashType::All as u8);
-                    input.witness.push(sig_serialised);
-                    input.witness.push(pubkey.to_bytes());
+                    sign_p2wpkh_input(&secp, &tx_clone, ix, input, &privkey, input_value);
+                }
+                UTXOSpendInfo::ExistingDescriptorCoin { index, input_value } => {
+                    let privkey = self
+                        .store
+                        .existing_descriptor
+                        .as_ref()
+                        .ok_or_else(|| {
+                            WalletError::Protocol(
+                                "The wallet has no existing descriptor".to_string(),
+                            )
+                        })?
+                        .privkey(index)?;
+                    sign_p2wpkh_input(&secp, &tx_clone, ix, input, &privkey, input_value);
                 }
                 UTXOSpendInfo::TimelockContract {
                     swapcoin_multisig_redeemscript,
@@ -1058,9 +1734,9 @@ impl Wallet {
         Ok(())
     }
 
-    pub fn coin_select(
+    /// Lists the utxos coin selection can pick from: everything but fidelity bonds and locked utxos.
+    pub(super) fn list_selectable_utxo_spend_info(
         &self,
-        amount: Amount,
     ) -> Result<Vec<(ListUnspentResultEntry, UTXOSpendInfo)>, WalletError> {
         let all_utxos = self.get_all_locked_utxo()?;
 
@@ -1068,11 +1744,63 @@ impl Wallet {
         let mut swap_coin_utxo = self.list_swap_coin_utxo_spend_info(Some(&all_utxos))?;
         seed_coin_utxo.append(&mut swap_coin_utxo);
 
-        // Fetch utxos, filter out existing fidelity coins
-        let mut unspents = seed_coin_utxo
+        // Fetch utxos, filter out existing fidelity coins and locked utxos
+        Ok(seed_coin_utxo
             .into_iter()
             .filter(|(_, spend_info)| !matches!(spend_info, UTXOSpendInfo::FidelityBondCoin { .. }))
-            .collect::<Vec<_>>();
+            .filter(|(utxo, _)| !self.is_utxo_locked(&OutPoint::new(utxo.txid, utxo.vout)))
+            .collect())
+    }
+
+    pub fn coin_select(
+        &self,
+        amount: Amount,
+    ) -> Result<Vec<(ListUnspentResultEntry, UTXOSpendInfo)>, WalletError> {
+        self.coin_select_within(amount, None)
+    }
+
+    /// Looks up the given utxos for coin control. Each must be a coin selection could pick:
+    /// owned by the wallet, not locked and not a fidelity bond.
+    pub fn select_utxos(
+        &self,
+        utxos: &[OutPoint],
+    ) -> Result<Vec<(ListUnspentResultEntry, UTXOSpendInfo)>, WalletError> {
+        let selectable = self.list_selectable_utxo_spend_info()?;
+        if let Some(unspendable) = utxos.iter().find(|outpoint| {
+            !selectable
+                .iter()
+                .any(|(utxo, _)| OutPoint::new(utxo.txid, utxo.vout) == **outpoint)
+        }) {
+            return Err(WalletError::UtxoNotSpendable(*unspendable));
+        }
+        Ok(selectable
+            .into_iter()
+            .filter(|(utxo, _)| utxos.contains(&OutPoint::new(utxo.txid, utxo.vout)))
+            .collect())
+    }
+
+    /// Coin selection, only among `funding_utxos` if given. These bypass change isolation, the
+    /// user picked them.
+    pub(crate) fn coin_select_within(
+        &self,
+        amount: Amount,
+        funding_utxos: Option<&[OutPoint]>,
+    ) -> Result<Vec<(ListUnspentResultEntry, UTXOSpendInfo)>, WalletError> {
+        let mut unspents = if let Some(funding_utxos) = funding_utxos {
+            // Those already spent by a previous funding tx aren't listed anymore.
+            self.list_selectable_utxo_spend_info()?
+                .into_iter()
+                .filter(|(utxo, _)| funding_utxos.contains(&OutPoint::new(utxo.txid, utxo.vout)))
+                .collect()
+        } else {
+            // Isolated change is never combined with swap coins. Use the first pool covering the amount.
+            let pools = self.change_isolation_pools(self.list_selectable_utxo_spend_info()?)?;
+            let pool_index = pools
+                .iter()
+                .position(|pool| pool.iter().map(|(utxo, _)| utxo.amount).sum::<Amount>() >= amount)
+                .unwrap_or(0);
+            pools.into_iter().nth(pool_index).unwrap_or_default()
+        };
 
         unspents.sort_by(|a, b| b.0.amount.cmp(&a.0.amount));
 
@@ -1114,50 +1842,76 @@ impl Wallet {
     fn create_and_import_coinswap_address(
         &mut self,
         other_pubkey: &PublicKey,
-    ) -> (Address, SecretKey) {
-        let (my_pubkey, my_privkey) = generate_keypair();
-
-        let descriptor = self
-            .rpc
-            .get_descriptor_info(&format!(
-                "wsh(sortedmulti(2,{},{}))",
-                my_pubkey, other_pubkey
-            ))
-            .unwrap()
-            .descriptor;
-        self.import_descriptors(&[descriptor.clone()], None)
-            .unwrap();
-
-        //redeemscript and descriptor show up in `getaddressinfo` only after
-        // the address gets outputs on it-
-        (
-            //TODO should completely avoid derive_addresses
-            //because its slower and provides no benefit over using rust-bitcoin
-            self.rpc.derive_addresses(&descriptor[..], None).unwrap()[0]
-                .clone()
-                .assume_checked(),
-            my_privkey,
-        )
+    ) -> Result<(Address, (u32, SecretKey)), WalletError> {
+        let (my_key_index, my_privkey) = self.next_swap_key()?;
+        let my_pubkey = PublicKey::new(my_privkey.public_key(&Secp256k1::new()));
+
+        // Same as the swapcoin's `multisig_descriptor`, so that sync finds it imported.
+        let descriptor = append_descriptor_checksum(&format!(
+            "wsh(sortedmulti(2,{},[{}/{}]{}))",
+            other_pubkey,
+            self.store.master_key.fingerprint(&Secp256k1::new()),
+            swap_key_path(my_key_index)?,
+            my_pubkey
+        ))?;
+        self.import_descriptors(&[descriptor], None)?;
+
+        let redeemscript = contract::create_multisig_redeemscript(&my_pubkey, other_pubkey);
+        let address = Address::p2wsh(&redeemscript, self.rpc_config.network);
+        Ok((address, (my_key_index, my_privkey)))
     }
 
     /// Initialize a Coinswap with the Other party.
     /// Returns, the Funding Transactions, [`OutgoingSwapCoin`]s and the Total Miner fees.
+    /// The funding txs only spend `funding_utxos`, if given.
+    #[allow(clippy::too_many_arguments)]
     pub fn initalize_coinswap(
         &mut self,
         total_coinswap_amount: Amount,
         other_multisig_pubkeys: &[PublicKey],
         hashlock_pubkeys: &[PublicKey],
-        hashvalue: Hash160,
+        hashlock: Hashlock,
         locktime: u16,
         fee_rate: Amount,
+        output_shaping: OutputShaping,
+        funding_utxos: Option<&[OutPoint]>,
     ) -> Result<(Vec<Transaction>, Vec<OutgoingSwapCoin>, Amount), WalletError> {
         let (coinswap_addresses, my_multisig_privkeys): (Vec<_>, Vec<_>) = other_multisig_pubkeys
             .iter()
             .map(|other_key| self.create_and_import_coinswap_address(other_key))
+            .collect::<Result<Vec<_>, WalletError>>()?
+            .into_iter()
             .unzip();
 
-        let create_funding_txes_result =
-            self.create_funding_txes(total_coinswap_amount, &coinswap_addresses, fee_rate)?;
+        let next_change_index = self
+            .find_hd_next_index(KeychainKind::Internal)?
+            .max(self.store.internal_index);
+        let create_funding_txes_result = self.create_funding_txes(
+            total_coinswap_amount,
+            &coinswap_addresses,
+            fee_rate,
+            output_shaping,
+            funding_utxos,
+        )?;
+
+        if self.store.avoid_change_reuse {
+            // Every funding method derives at most one change address per funding tx. Skip past
+            // them, so that the next funding txs don't reuse any before they get mined.
+            self.store.internal_index = next_change_index + coinswap_addresses.len() as u32;
+            let change_spks = create_funding_txes_result
+                .funding_txes
+                .iter()
+                .zip(create_funding_txes_result.payment_output_positions.iter())
+                .flat_map(|(tx, &payment_index)| {
+                    tx.output
+                        .iter()
+                        .enumerate()
+                        .filter(move |(index, _)| *index != payment_index as usize)
+                        .map(|(_, output)| output.script_pubkey.clone())
+                })
+                .collect::<Vec<_>>();
+            self.store.isolated_change.extend(change_spks);
+        }
         //for sweeping there would be another function, probably
         //probably have an enum called something like SendAmount which can be
         // an integer but also can be Sweep
@@ -1169,7 +1923,10 @@ impl Wallet {
 
         let mut outgoing_swapcoins = Vec::<OutgoingSwapCoin>::new();
         for (
-            (((my_funding_tx, &utxo_index), &my_multisig_privkey), &other_multisig_pubkey),
+            (
+                ((my_funding_tx, &utxo_index), &(my_multisig_key_index, my_multisig_privkey)),
+                &other_multisig_pubkey,
+            ),
             hashlock_pubkey,
         ) in create_funding_txes_result
             .funding_txes
@@ -1179,11 +1936,12 @@ impl Wallet {
             .zip(other_multisig_pubkeys.iter())
             .zip(hashlock_pubkeys.iter())
         {
-            let (timelock_pubkey, timelock_privkey) = generate_keypair();
+            let (timelock_key_index, timelock_privkey) = self.next_swap_key()?;
+            let timelock_pubkey = PublicKey::new(timelock_privkey.public_key(&Secp256k1::new()));
             let contract_redeemscript = contract::create_contract_redeemscript(
                 hashlock_pubkey,
                 &timelock_pubkey,
-                &hashvalue,
+                &hashlock,
                 &locktime,
             );
             let funding_amount = my_funding_tx.output[utxo_index as usize].value;
@@ -1198,14 +1956,17 @@ impl Wallet {
             );
 
             // self.import_wallet_contract_redeemscript(&contract_redeemscript)?;
-            outgoing_swapcoins.push(OutgoingSwapCoin::new(
+            let mut outgoing_swapcoin = OutgoingSwapCoin::new(
                 my_multisig_privkey,
                 other_multisig_pubkey,
                 my_senders_contract_tx,
                 contract_redeemscript,
                 timelock_privkey,
                 funding_amount,
-            ));
+            );
+            outgoing_swapcoin.my_key_index = Some(my_multisig_key_index);
+            outgoing_swapcoin.timelock_key_index = Some(timelock_key_index);
+            outgoing_swapcoins.push(outgoing_swapcoin);
         }
 
         Ok((
@@ -1221,96 +1982,231 @@ impl Wallet {
         redeemscript: &ScriptBuf,
     ) -> Result<(), WalletError> {
         let spk = redeemscript_to_scriptpubkey(redeemscript);
-        let descriptor = self
-            .rpc
-            .get_descriptor_info(&format!("raw({:x})", spk))
-            .unwrap()
-            .descriptor;
+        let descriptor = append_descriptor_checksum(&format!("raw({:x})", spk))?;
         self.import_descriptors(&[descriptor], Some(WATCH_ONLY_SWAPCOIN_LABEL.to_string()))
     }
 
+    /// Lists the wallet, swapcoin and fidelity descriptors which are not imported into the core wallet yet.
+    /// Uses a single `listdescriptors` call to check what's already imported.
     pub fn descriptors_to_import(&self) -> Result<Vec<String>, WalletError> {
-        let mut descriptors_to_import = Vec::new();
-
-        descriptors_to_import.extend(self.get_unimported_wallet_desc()?);
+        let imported = self.backend.imported_descriptors()?;
+        let mut descriptors_to_import =
+            retain_unimported(self.swap_and_fidelity_descriptors()?, &imported);
+        descriptors_to_import.extend(self.get_unimported_wallet_desc(&imported)?);
+        Ok(descriptors_to_import)
+    }
 
-        descriptors_to_import.extend(
+    /// The unranged descriptors of the swapcoins, their live contracts and the fidelity bonds.
+    pub(super) fn swap_and_fidelity_descriptors(&self) -> Result<Vec<String>, WalletError> {
+        let incoming = self
+            .store
+            .incoming_swapcoins
+            .values()
+            .map(|sc| sc as &dyn WalletSwapCoin);
+        let outgoing = self
+            .store
+            .outgoing_swapcoins
+            .values()
+            .map(|sc| sc as &dyn WalletSwapCoin);
+
+        let master_fingerprint = self.store.master_key.fingerprint(&Secp256k1::new());
+        let mut descriptors = incoming
+            .clone()
+            .chain(outgoing.clone())
+            .map(|sc| append_descriptor_checksum(&sc.multisig_descriptor(master_fingerprint)?))
+            .collect::<Result<Vec<_>, WalletError>>()?;
+        descriptors.extend(self.contract_descriptors_to_import(incoming)?);
+        descriptors.extend(self.contract_descriptors_to_import(outgoing)?);
+        descriptors.extend(
             self.store
-                .incoming_swapcoins
+                .fidelity_bond
                 .values()
-                .map(|sc| {
-                    let descriptor_without_checksum = format!(
-                        "wsh(sortedmulti(2,{},{}))",
-                        sc.get_other_pubkey(),
-                        sc.get_my_pubkey()
-                    );
-                    format!(
-                        "{}#{}",
-                        descriptor_without_checksum,
-                        compute_checksum(&descriptor_without_checksum).unwrap()
-                    )
-                })
-                .collect::<Vec<String>>(),
+                .map(|(_, spk, _)| append_descriptor_checksum(&format!("raw({:x})", spk)))
+                .collect::<Result<Vec<_>, WalletError>>()?,
         );
+        Ok(descriptors)
+    }
 
-        descriptors_to_import.extend(
-            self.store
-                .outgoing_swapcoins
-                .values()
-                .map(|sc| {
-                    let descriptor_without_checksum = format!(
-                        "wsh(sortedmulti(2,{},{}))",
-                        sc.get_other_pubkey(),
-                        sc.get_my_pubkey()
-                    );
-                    format!(
-                        "{}#{}",
-                        descriptor_without_checksum,
-                        compute_checksum(&descriptor_without_checksum).unwrap()
-                    )
-                })
-                .collect::<Vec<String>>(),
-        );
+    /// The contract descriptors of the given swapcoins, skipping swaps that are complete.
+    ///
+    /// A swap is complete once the privkey of any of its incoming swapcoins is known, which also
+    /// covers the outgoing swapcoins of the same hashvalue.
+    fn contract_descriptors_to_import<'a>(
+        &self,
+        coins: impl Iterator<Item = &'a dyn WalletSwapCoin>,
+    ) -> Result<Vec<String>, WalletError> {
+        let completed_hashvalues = self
+            .store
+            .incoming_swapcoins
+            .values()
+            .filter(|sc| !sc.is_contract_relevant())
+            .map(|sc| sc.get_hashvalue())
+            .collect::<HashSet<_>>();
 
-        descriptors_to_import.extend(
-            self.store
-                .incoming_swapcoins
-                .values()
-                .map(|sc| {
-                    let contract_spk = redeemscript_to_scriptpubkey(&sc.contract_redeemscript);
-                    let descriptor_without_checksum = format!("raw({:x})", contract_spk);
-                    format!(
-                        "{}#{}",
-                        descriptor_without_checksum,
-                        compute_checksum(&descriptor_without_checksum).unwrap()
-                    )
-                })
-                .collect::<Vec<_>>(),
-        );
-        descriptors_to_import.extend(
-            self.store
-                .outgoing_swapcoins
-                .values()
-                .map(|sc| {
-                    let contract_spk = redeemscript_to_scriptpubkey(&sc.contract_redeemscript);
-                    let descriptor_without_checksum = format!("raw({:x})", contract_spk);
-                    format!(
-                        "{}#{}",
-                        descriptor_without_checksum,
-                        compute_checksum(&descriptor_without_checksum).unwrap()
-                    )
-                })
-                .collect::<Vec<_>>(),
+        coins
+            .filter(|sc| {
+                sc.is_contract_relevant() && !completed_hashvalues.contains(&sc.get_hashvalue())
+            })
+            .map(|sc| append_descriptor_checksum(&sc.contract_descriptor()))
+            .collect()
+    }
+}
+
+/// Signs the p2wpkh input `ix` of `tx` with `privkey`, filling the witness of `input`.
+fn sign_p2wpkh_input(
+    secp: &Secp256k1<secp256k1::All>,
+    tx: &Transaction,
+    ix: usize,
+    input: &mut TxIn,
+    privkey: &SecretKey,
+    input_value: Amount,
+) {
+    let pubkey = PublicKey {
+        compressed: true,
+        inner: privkey.public_key(secp),
+    };
+    let scriptcode = ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash().unwrap());
+    let sighash = SighashCache::new(tx)
+        .p2wpkh_signature_hash(ix, &scriptcode, input_value, EcdsaSighashType::All)
+        .unwrap();
+    //use low-R value signatures for privacy
+    //https://en.bitcoin.it/wiki/Privacy#Wallet_fingerprinting
+    let signature = secp.sign_ecdsa_low_r(
+        &secp256k1::Message::from_digest_slice(&sighash[..]).unwrap(),
+        privkey,
+    );
+    let mut sig_serialised = signature.serialize_der().to_vec();
+    sig_serialised.push(EcdsaSighashType::All as u8);
+    input.witness.push(sig_serialised);
+    input.witness.push(pubkey.to_bytes());
+}
+
+/// Keeps the unranged `descriptors` missing from the core wallet's `imported` descriptors.
+///
+/// Swapcoin and fidelity descriptors are unranged, so presence is enough. Each one is checked on
+/// its own, so a gap anywhere in the list gets imported.
+fn retain_unimported(
+    mut descriptors: Vec<String>,
+    imported: &HashMap<String, Option<u32>>,
+) -> Vec<String> {
+    descriptors.retain(|d| !imported.contains_key(strip_checksum(d)));
+    descriptors
+}
+
+/// Rounds `next_index + gap_limit` up to a multiple of the gap limit. Handing out addresses then
+/// only grows the import range, and triggers a reimport, once every `gap_limit` addresses.
+fn import_count(next_index: u32, gap_limit: u32) -> u32 {
+    let gap_limit = gap_limit.max(1);
+    (next_index + gap_limit).div_ceil(gap_limit) * gap_limit
+}
+
+/// The index right after the last `derived_spks` entry found in `used_spks`, or 0 if none was used.
+fn next_unused_index(derived_spks: &[ScriptBuf], used_spks: &HashSet<ScriptBuf>) -> u32 {
+    derived_spks
+        .iter()
+        .rposition(|spk| used_spks.contains(spk))
+        .map_or(0, |index| index as u32 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::FidelityBond;
+    use bitcoin::{absolute::LockTime, PrivateKey};
+
+    #[test]
+    fn test_retain_unimported_fidelity_gap() {
+        let secp = Secp256k1::new();
+        let descriptors = (1..=3u8)
+            .map(|i| {
+                let bond = FidelityBond {
+                    outpoint: OutPoint::null(),
+                    amount: Amount::from_sat(100_000),
+                    lock_time: LockTime::from_height(100 * i as u32).unwrap(),
+                    pubkey: PublicKey::from_private_key(
+                        &secp,
+                        &PrivateKey::new(
+                            SecretKey::from_slice(&[i; 32]).unwrap(),
+                            bitcoin::Network::Regtest,
+                        ),
+                    ),
+                    conf_height: 0,
+                    cert_expiry: 0,
+                };
+                append_descriptor_checksum(&format!("raw({:x})", bond.script_pub_key())).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        // First and last bonds are imported, the middle one is not.
+        let imported = [&descriptors[0], &descriptors[2]]
+            .iter()
+            .map(|d| (strip_checksum(d).to_string(), None))
+            .collect::<HashMap<_, _>>();
+
+        assert_eq!(
+            retain_unimported(descriptors.clone(), &imported),
+            vec![descriptors[1].clone()]
         );
+        assert!(retain_unimported(vec![descriptors[0].clone()], &imported).is_empty());
+    }
 
-        descriptors_to_import.extend(self.store.fidelity_bond.iter().map(|(_, (_, spk, _))| {
-            let descriptor_without_checksum = format!("raw({:x})", spk);
-            format!(
-                "{}#{}",
-                descriptor_without_checksum,
-                compute_checksum(&descriptor_without_checksum).unwrap()
-            )
-        }));
-        Ok(descriptors_to_import)
+    #[test]
+    fn test_next_swap_key_is_persisted() {
+        let temp_dir = bitcoind::tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("taker-wallet");
+        let rpc_config = RPCConfig::default();
+        let rpc = Arc::new(Client::new(&rpc_config.url, rpc_config.auth.clone()).unwrap());
+        let store = WalletStore::init(
+            "taker-wallet".to_string(),
+            &path,
+            bitcoin::Network::Regtest,
+            Xpriv::new_master(bitcoin::Network::Regtest, &[1u8; 32]).unwrap(),
+            None,
+        )
+        .unwrap();
+        let mut wallet = Wallet {
+            backend: Arc::new(CoreRpcBackend::new(rpc.clone(), rpc_config.clone())),
+            rpc: Some(rpc),
+            rpc_config,
+            wallet_file_path: path.clone(),
+            store,
+            store_key: None,
+        };
+
+        // The swap aborts right after the key exchange, and the wallet is reloaded from disk.
+        let (index, key) = wallet.next_swap_key().unwrap();
+        wallet.store = WalletStore::read_from_disk(&path).unwrap();
+
+        let (next_index, next_key) = wallet.next_swap_key().unwrap();
+        assert_eq!(next_index, index + 1);
+        assert_ne!(next_key, key);
+    }
+
+    #[test]
+    fn test_import_count() {
+        assert_eq!(import_count(0, 10), 10);
+        assert_eq!(import_count(1, 10), 20);
+        assert_eq!(import_count(10, 10), 20);
+        assert_eq!(import_count(11, 10), 30);
+        assert_eq!(import_count(0, 5000), 5000);
+        assert_eq!(import_count(3, 0), 4);
+    }
+
+    #[test]
+    fn test_next_unused_index() {
+        let derived_spks = (0..5u8)
+            .map(|i| ScriptBuf::from_bytes(vec![i]))
+            .collect::<Vec<_>>();
+
+        assert_eq!(next_unused_index(&derived_spks, &HashSet::new()), 0);
+
+        // Gaps before the last used address don't matter.
+        let used_spks = vec![derived_spks[0].clone(), derived_spks[3].clone()]
+            .into_iter()
+            .collect();
+        assert_eq!(next_unused_index(&derived_spks, &used_spks), 4);
+
+        let unrelated = vec![ScriptBuf::from_bytes(vec![42])].into_iter().collect();
+        assert_eq!(next_unused_index(&derived_spks, &unrelated), 0);
     }
 }
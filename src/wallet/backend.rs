@@ -0,0 +1,498 @@
+//! The wallet's view of the chain.
+//!
+//! [`Wallet::sync`](super::Wallet::sync), including the reorg detection and the
+//! [`SyncMode::Incremental`](super::SyncMode) block walk, the listing of the wallet's utxos, its
+//! transaction history, transaction and block lookups, broadcasts and their mempool checks go
+//! through a [`ChainBackend`]. [`CoreRpcBackend`] serves them from a Bitcoin Core node, and is what
+//! the wallets connecting to a node start with. A light client backend, like Electrum or Esplora,
+//! can be set with [`Wallet::set_backend`](super::Wallet::set_backend), or used without any node
+//! with [`Wallet::init_with_backend`](super::Wallet::init_with_backend).
+//!
+//! A few features only a core wallet offers still need the node: fee bumps, the merkle proofs of
+//! the taker's funding transactions, and the listing of the node's wallets. Coin locks are mirrored
+//! to the core wallet with `lockunspent` when there is one.
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use bitcoin::{Block, BlockHash, OutPoint, ScriptBuf, Transaction, TxOut, Txid};
+use bitcoind::bitcoincore_rpc::{
+    bitcoincore_rpc_json::{GetBlockHeaderResult, ListUnspentResultEntry, ScanningDetails},
+    jsonrpc, Client, Error as RpcError, RawTx, RpcApi,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{
+    error::WalletError,
+    rescan_lock::RescanLock,
+    rpc::{
+        list_imported_descriptors, load_or_create_core_wallet, send_import_requests, RPCConfig,
+        RPC_INVALID_ADDRESS_OR_KEY,
+    },
+};
+
+/// Interval at which `getwalletinfo` is polled while a rescan is reported with progress.
+const SCAN_PROGRESS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Source of the chain data needed to sync a wallet, and sink of its transactions.
+pub trait ChainBackend: Send + Sync {
+    /// Gets the backend ready to track the scripts of the wallet named `wallet_name`.
+    fn open_wallet(&self, wallet_name: &str) -> Result<(), WalletError>;
+
+    /// Height of the chain tip.
+    fn get_block_count(&self) -> Result<u64, WalletError>;
+
+    /// Hash of the block at `height` in the active chain.
+    fn get_block_hash(&self, height: u64) -> Result<BlockHash, WalletError>;
+
+    /// The block at `height` in the active chain.
+    fn get_block(&self, height: u64) -> Result<Block, WalletError>;
+
+    /// The header of the block `hash`, in the active chain or not. `None` if the block is unknown.
+    /// Blocks out of the active chain have -1 confirmations.
+    fn get_block_header(
+        &self,
+        hash: &BlockHash,
+    ) -> Result<Option<GetBlockHeaderResult>, WalletError>;
+
+    /// Scriptpubkeys of the ranged `descriptor`, derived from index 0 to `range_end`.
+    fn derive_scriptpubkeys(
+        &self,
+        descriptor: &str,
+        range_end: u32,
+    ) -> Result<Vec<ScriptBuf>, WalletError>;
+
+    /// The descriptors tracked so far, without their checksums, along with the end of the range
+    /// they were derived to. The range end is `None` for unranged descriptors.
+    fn imported_descriptors(&self) -> Result<HashMap<String, Option<u32>>, WalletError>;
+
+    /// Starts tracking the scripts of `descriptors`. Ranged descriptors are derived from index 0
+    /// to `range_end`, the others are tagged with `label`.
+    fn import_descriptors(
+        &self,
+        descriptors: &[String],
+        range_end: u32,
+        label: &str,
+    ) -> Result<(), WalletError>;
+
+    /// Looks up the history of the tracked scripts in the blocks from `start_height` to
+    /// `end_height`. If given, `progress` is called from the calling thread with the height
+    /// reached so far.
+    fn rescan(
+        &self,
+        start_height: u64,
+        end_height: u64,
+        progress: Option<&dyn Fn(u64)>,
+    ) -> Result<(), WalletError>;
+
+    /// The unspent output at `outpoint`, and the height it confirmed at. `None` if it's spent,
+    /// unknown or unconfirmed.
+    fn get_tx_out(&self, outpoint: &OutPoint) -> Result<Option<(TxOut, u64)>, WalletError>;
+
+    /// The unspent outputs paying to the tracked scripts, confirmed or not. Outputs reserved with
+    /// `lockunspent` in the core wallet may be left out.
+    fn list_unspent(&self) -> Result<Vec<ListUnspentResultEntry>, WalletError>;
+
+    /// A transaction of the tracked scripts' history, and the height it confirmed at. `None` if
+    /// it isn't in the history.
+    fn get_wallet_transaction(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<(Transaction, Option<u64>)>, WalletError>;
+
+    /// A transaction in the mempool or the chain, of the tracked scripts or not, and the height it
+    /// confirmed at. `None` if the backend doesn't know it.
+    fn get_transaction(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<(Transaction, Option<u64>)>, WalletError>;
+
+    /// The transaction spending `outpoint`, and the height it confirmed at. `None` if the output
+    /// is unspent.
+    ///
+    /// `start_height` is the height the output confirmed at, confirmed spends are looked up from
+    /// there. If `None`, the output is unconfirmed, and only the mempool is looked at.
+    fn get_spending_transaction(
+        &self,
+        outpoint: &OutPoint,
+        start_height: Option<u64>,
+    ) -> Result<Option<(Transaction, Option<u64>)>, WalletError>;
+
+    /// Checks that the mempool accepts `txs`, without broadcasting them. Parents must come before
+    /// their children. Errors with [`WalletError::MempoolRejected`] on the first rejection.
+    fn test_mempool_accept(&self, txs: &[Transaction]) -> Result<(), WalletError>;
+
+    /// Broadcasts a signed transaction.
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, WalletError>;
+}
+
+/// A Bitcoin Core node, tracking the scripts in a watch-only core wallet.
+///
+/// Rescans of the node wait for each other, even from other processes of the machine.
+pub struct CoreRpcBackend {
+    rpc: Arc<Client>,
+    rpc_config: RPCConfig,
+}
+
+impl CoreRpcBackend {
+    /// Serves the wallet configured in `rpc_config`, over `rpc`.
+    pub fn new(rpc: Arc<Client>, rpc_config: RPCConfig) -> Self {
+        Self { rpc, rpc_config }
+    }
+}
+
+impl ChainBackend for CoreRpcBackend {
+    fn open_wallet(&self, wallet_name: &str) -> Result<(), WalletError> {
+        load_or_create_core_wallet(&self.rpc, wallet_name)
+    }
+
+    fn get_block_count(&self) -> Result<u64, WalletError> {
+        Ok(self.rpc.get_block_count()?)
+    }
+
+    fn get_block_hash(&self, height: u64) -> Result<BlockHash, WalletError> {
+        Ok(self.rpc.get_block_hash(height)?)
+    }
+
+    fn get_block(&self, height: u64) -> Result<Block, WalletError> {
+        Ok(self.rpc.get_block(&self.rpc.get_block_hash(height)?)?)
+    }
+
+    fn get_block_header(
+        &self,
+        hash: &BlockHash,
+    ) -> Result<Option<GetBlockHeaderResult>, WalletError> {
+        match self.rpc.get_block_header_info(hash) {
+            Ok(header) => Ok(Some(header)),
+            Err(RpcError::JsonRpc(jsonrpc::Error::Rpc(ref e)))
+                if e.code == RPC_INVALID_ADDRESS_OR_KEY =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn derive_scriptpubkeys(
+        &self,
+        descriptor: &str,
+        range_end: u32,
+    ) -> Result<Vec<ScriptBuf>, WalletError> {
+        Ok(self
+            .rpc
+            .derive_addresses(descriptor, Some([0, range_end]))?
+            .into_iter()
+            .map(|addr| addr.assume_checked().script_pubkey())
+            .collect())
+    }
+
+    fn imported_descriptors(&self) -> Result<HashMap<String, Option<u32>>, WalletError> {
+        list_imported_descriptors(&self.rpc)
+    }
+
+    /// Sent in chunks of [`RPCConfig::import_batch_size`], to keep each `importdescriptors`
+    /// request within the node's limits. Stops at the first failing chunk.
+    fn import_descriptors(
+        &self,
+        descriptors: &[String],
+        range_end: u32,
+        label: &str,
+    ) -> Result<(), WalletError> {
+        let requests = descriptors
+            .iter()
+            .map(|desc| {
+                if desc.contains("/*") {
+                    return json!({
+                        "timestamp": "now",
+                        "desc": desc,
+                        "range": range_end
+                    });
+                }
+                json!({
+                    "timestamp": "now",
+                    "desc": desc,
+                    "label": label
+                })
+            })
+            .collect::<Vec<Value>>();
+        send_import_requests(
+            &self.rpc,
+            descriptors,
+            &requests,
+            self.rpc_config.import_batch_size,
+        )
+    }
+
+    /// Runs `rescanblockchain`. With a progress callback, the rescan runs on a separate RPC
+    /// connection from a background thread, and `getwalletinfo` is polled to report how far it got.
+    fn rescan(
+        &self,
+        start_height: u64,
+        end_height: u64,
+        progress: Option<&dyn Fn(u64)>,
+    ) -> Result<(), WalletError> {
+        let _rescan_lock = RescanLock::acquire(&self.rpc_config)?;
+        let progress = match progress {
+            Some(progress) => progress,
+            None => {
+                self.rpc
+                    .rescan_blockchain(Some(start_height as usize), Some(end_height as usize))?;
+                return Ok(());
+            }
+        };
+
+        let scan_client = Client::try_from(&self.rpc_config)?;
+        let (sender, receiver) = mpsc::channel();
+        let scan_thread = thread::spawn(move || {
+            let result = scan_client
+                .rescan_blockchain(Some(start_height as usize), Some(end_height as usize));
+            let _ = sender.send(result);
+        });
+
+        loop {
+            match receiver.recv_timeout(SCAN_PROGRESS_POLL_INTERVAL) {
+                Ok(result) => {
+                    let _ = scan_thread.join();
+                    result?;
+                    break;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let scanning = match self.rpc.get_wallet_info() {
+                        Ok(info) => info.scanning,
+                        Err(e) => {
+                            // The rescan goes on in the node. Hold the lock until it's done, so
+                            // that the next one doesn't start over it.
+                            let _ = scan_thread.join();
+                            return Err(e.into());
+                        }
+                    };
+                    if let Some(ScanningDetails::Scanning {
+                        progress: scanned, ..
+                    }) = scanning
+                    {
+                        let scanned_blocks = ((end_height - start_height) as f32 * scanned) as u64;
+                        progress(start_height + scanned_blocks);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(WalletError::Protocol(
+                        "Rescan thread terminated unexpectedly".to_string(),
+                    ));
+                }
+            }
+        }
+
+        progress(end_height);
+        Ok(())
+    }
+
+    fn get_tx_out(&self, outpoint: &OutPoint) -> Result<Option<(TxOut, u64)>, WalletError> {
+        let result = match self
+            .rpc
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?
+        {
+            Some(result) if result.confirmations > 0 => result,
+            _ => return Ok(None),
+        };
+        // Counted from the tip the node answered at, a block may have been mined since.
+        let tip_height = self.rpc.get_block_header_info(&result.bestblock)?.height as u64;
+        let txout = TxOut {
+            value: result.value,
+            script_pubkey: ScriptBuf::from(result.script_pub_key.hex),
+        };
+        Ok(Some((
+            txout,
+            (tip_height + 1).saturating_sub(result.confirmations as u64),
+        )))
+    }
+
+    fn list_unspent(&self) -> Result<Vec<ListUnspentResultEntry>, WalletError> {
+        Ok(self
+            .rpc
+            .list_unspent(Some(0), Some(9999999), None, None, None)?)
+    }
+
+    fn get_wallet_transaction(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<(Transaction, Option<u64>)>, WalletError> {
+        match self.rpc.get_transaction(txid, Some(true)) {
+            Ok(result) => {
+                let height = result.info.blockheight.map(|height| height as u64);
+                Ok(Some((result.transaction()?, height)))
+            }
+            Err(RpcError::JsonRpc(jsonrpc::Error::Rpc(ref e)))
+                if e.code == RPC_INVALID_ADDRESS_OR_KEY =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Looks in the core wallet first, then with `getrawtransaction`. Transactions of other
+    /// scripts are found in the chain only if the node keeps a transaction index.
+    fn get_transaction(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<(Transaction, Option<u64>)>, WalletError> {
+        if let Some(wallet_tx) = self.get_wallet_transaction(txid)? {
+            return Ok(Some(wallet_tx));
+        }
+        let result = match self.rpc.get_raw_transaction_info(txid, None) {
+            Ok(result) => result,
+            Err(RpcError::JsonRpc(jsonrpc::Error::Rpc(ref e)))
+                if e.code == RPC_INVALID_ADDRESS_OR_KEY =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let height = match result.blockhash {
+            Some(hash) => Some(self.rpc.get_block_header_info(&hash)?.height as u64),
+            None => None,
+        };
+        Ok(Some((result.transaction()?, height)))
+    }
+
+    /// Core has no index of spends. The spend is looked up in the blocks mined since
+    /// `start_height`, and then in the mempool.
+    fn get_spending_transaction(
+        &self,
+        outpoint: &OutPoint,
+        start_height: Option<u64>,
+    ) -> Result<Option<(Transaction, Option<u64>)>, WalletError> {
+        if self
+            .rpc
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(true))?
+            .is_some()
+        {
+            return Ok(None);
+        }
+        let spends = |tx: &Transaction| {
+            tx.input
+                .iter()
+                .any(|input| input.previous_output == *outpoint)
+        };
+
+        if let Some(start_height) = start_height {
+            for height in start_height..=self.rpc.get_block_count()? {
+                let block = self.get_block(height)?;
+                if let Some(tx) = block.txdata.into_iter().find(spends) {
+                    return Ok(Some((tx, Some(height))));
+                }
+            }
+        }
+
+        // Transactions may leave the mempool while it's walked.
+        Ok(self
+            .rpc
+            .get_raw_mempool()?
+            .iter()
+            .filter_map(|txid| self.rpc.get_raw_transaction(txid, None).ok())
+            .find(spends)
+            .map(|tx| (tx, None)))
+    }
+
+    fn test_mempool_accept(&self, txs: &[Transaction]) -> Result<(), WalletError> {
+        let raw_txs = txs.iter().map(|tx| tx.raw_hex()).collect::<Vec<_>>();
+        let results: Vec<MempoolAcceptResult> = self
+            .rpc
+            .call("testmempoolaccept", &[Value::from(raw_txs)])?;
+        check_mempool_accept(&results)
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, WalletError> {
+        Ok(self.rpc.send_raw_transaction(tx)?)
+    }
+}
+
+/// One transaction's entry in the `testmempoolaccept` result.
+#[derive(Debug, Deserialize)]
+struct MempoolAcceptResult {
+    txid: Txid,
+    /// Missing for the transactions of a package left unchecked after another one failed.
+    allowed: Option<bool>,
+    #[serde(rename = "reject-reason")]
+    reject_reason: Option<String>,
+    #[serde(rename = "package-error")]
+    package_error: Option<String>,
+}
+
+/// Errors with the first rejection among the `testmempoolaccept` results.
+///
+/// A transaction already in the mempool counts as accepted, broadcasting it again is a no-op.
+fn check_mempool_accept(results: &[MempoolAcceptResult]) -> Result<(), WalletError> {
+    let mut rejected = results.iter().filter(|result| {
+        result.allowed != Some(true)
+            && result.reject_reason.as_deref() != Some("txn-already-in-mempool")
+    });
+    // The failing transaction of a package carries the reason, the others only the package error.
+    let rejection = rejected
+        .clone()
+        .find(|result| result.reject_reason.is_some())
+        .or_else(|| rejected.next());
+    match rejection {
+        Some(result) => Err(WalletError::MempoolRejected {
+            txid: result.txid,
+            reason: result
+                .reject_reason
+                .clone()
+                .or_else(|| result.package_error.clone())
+                .unwrap_or_else(|| "rejected with its package".to_string()),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_check_mempool_accept() {
+        let txid = |i: u8| Txid::from_str(&format!("{:064x}", i)).unwrap();
+        let results =
+            |json: Value| serde_json::from_value::<Vec<MempoolAcceptResult>>(json).unwrap();
+
+        assert!(check_mempool_accept(&results(json!([
+            { "txid": txid(1), "allowed": true, "vsize": 141 },
+            { "txid": txid(2), "allowed": false, "reject-reason": "txn-already-in-mempool" },
+        ])))
+        .is_ok());
+
+        // The second transaction of the package fails, the first one is left unchecked.
+        match check_mempool_accept(&results(json!([
+            { "txid": txid(1), "package-error": "transaction failed" },
+            { "txid": txid(2), "allowed": false, "reject-reason": "min relay fee not met" },
+        ]))) {
+            Err(WalletError::MempoolRejected {
+                txid: rejected,
+                reason,
+            }) => {
+                assert_eq!(rejected, txid(2));
+                assert_eq!(reason, "min relay fee not met");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        match check_mempool_accept(&results(json!([
+            { "txid": txid(1), "package-error": "package-too-many-transactions" },
+        ]))) {
+            Err(WalletError::MempoolRejected { reason, .. }) => {
+                assert_eq!(reason, "package-too-many-transactions")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Wallet backups, for disaster recovery.
+//!
+//! A [`WalletBackup`] serializes to JSON with `serde_json`. It holds private keys, and must be
+//! stored as carefully as the seed phrase.
+
+use std::{collections::HashSet, convert::TryFrom, path::PathBuf, sync::Arc};
+
+use bitcoin::{bip32::Xpriv, Network, OutPoint, ScriptBuf};
+use bitcoind::bitcoincore_rpc::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::utill::validate_descriptor_checksum;
+
+use super::{
+    backend::CoreRpcBackend,
+    error::WalletError,
+    existing_descriptor::ExistingDescriptor,
+    fidelity::FidelityBond,
+    rpc::RPCConfig,
+    storage::WalletStore,
+    swapcoin::{IncomingSwapCoin, OutgoingSwapCoin, SwapCoin},
+    Wallet,
+};
+
+/// Everything needed to restore a wallet on another node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalletBackup {
+    /// Network the wallet operates on.
+    pub network: Network,
+    /// The master key. Origin of the HD keychains and of the fidelity bond keys.
+    pub master_key: Xpriv,
+    /// All descriptors the core wallet watches: the HD keychains, the swapcoins and their live
+    /// contracts, the fidelity bonds, and the existing descriptor if any. With checksums, ready
+    /// for `importdescriptors`. The restored wallet must derive the same ones.
+    pub descriptors: Vec<String>,
+    /// The next unused indices of the external and internal keychains, so the restored wallet
+    /// doesn't hand out used addresses again.
+    #[serde(default)]
+    pub external_index: u32,
+    #[serde(default)]
+    pub internal_index: u32,
+    /// Swapcoins hold the other parties' keys, and keys tweaked by the other party, which aren't
+    /// derived from the master key.
+    pub incoming_swapcoins: Vec<IncomingSwapCoin>,
+    pub outgoing_swapcoins: Vec<OutgoingSwapCoin>,
+    /// Contract redeemscripts of the swapcoin prevouts.
+    #[serde(default)]
+    pub prevout_to_contract_map: Vec<(OutPoint, ScriptBuf)>,
+    /// The next unused swap key index, so the restored wallet doesn't reuse swap keys.
+    #[serde(default)]
+    pub swap_key_index: u32,
+    /// The descriptor the wallet was set up from, if it was created outside coinswap.
+    #[serde(default)]
+    pub existing_descriptor: Option<ExistingDescriptor>,
+    /// Fidelity bonds by index, with their spent flag.
+    pub fidelity_bonds: Vec<(u32, FidelityBond, bool)>,
+    /// Rescans of a restored wallet never go below this height.
+    pub wallet_birthday: Option<u64>,
+    /// Height the wallet was synced to. Kept by the restored wallet if the node already watches
+    /// all the descriptors.
+    pub last_synced_height: Option<u64>,
+}
+
+impl Wallet {
+    /// Exports the keys, descriptors and sync state of the wallet.
+    pub fn export_backup(&self) -> Result<WalletBackup, WalletError> {
+        Ok(WalletBackup {
+            network: self.store.network,
+            master_key: self.store.master_key,
+            descriptors: self.backup_descriptors()?,
+            external_index: self.store.external_index,
+            internal_index: self.store.internal_index,
+            incoming_swapcoins: self.store.incoming_swapcoins.values().cloned().collect(),
+            outgoing_swapcoins: self.store.outgoing_swapcoins.values().cloned().collect(),
+            prevout_to_contract_map: self
+                .store
+                .prevout_to_contract_map
+                .iter()
+                .map(|(prevout, contract)| (*prevout, contract.clone()))
+                .collect(),
+            swap_key_index: self.store.swap_key_index,
+            existing_descriptor: self.store.existing_descriptor.clone(),
+            fidelity_bonds: self
+                .store
+                .fidelity_bond
+                .iter()
+                .map(|(index, (bond, _, is_spent))| (*index, bond.clone(), *is_spent))
+                .collect(),
+            wallet_birthday: self.store.wallet_birthday,
+            last_synced_height: self.store.last_synced_height,
+        })
+    }
+
+    /// All the descriptors the wallet watches, see [`WalletBackup::descriptors`].
+    fn backup_descriptors(&self) -> Result<Vec<String>, WalletError> {
+        let mut descriptors = self
+            .get_wallet_descriptors()?
+            .into_values()
+            .collect::<Vec<_>>();
+        descriptors.extend(self.swap_and_fidelity_descriptors()?);
+        descriptors.extend(
+            self.store
+                .existing_descriptor
+                .as_ref()
+                .map(|existing_descriptor| existing_descriptor.public_descriptor.clone()),
+        );
+        Ok(descriptors)
+    }
+
+    /// Restores a wallet from a backup, into a new wallet file at `path`.
+    ///
+    /// If the node's core wallet misses any of the backed up descriptors, they get imported and
+    /// the chain is rescanned from the wallet birthday. Otherwise the backup's last synced height is kept.
+    /// Either way, the address indices then move past every address found holding funds, like in
+    /// [`Wallet::sync`].
+    pub fn import_backup(
+        path: &PathBuf,
+        rpc_config: &RPCConfig,
+        backup: &WalletBackup,
+    ) -> Result<Wallet, WalletError> {
+        if backup.network != rpc_config.network {
+            return Err(WalletError::NetworkMismatch {
+                expected: rpc_config.network,
+                found: backup.network,
+            });
+        }
+
+        // A corrupted backup is caught before anything gets written.
+        for descriptor in &backup.descriptors {
+            validate_descriptor_checksum(descriptor)
+                .map_err(|e| WalletError::descriptor_import(descriptor, e))?;
+        }
+
+        let file_name = path
+            .file_name()
+            .expect("file name expected")
+            .to_str()
+            .expect("expected")
+            .to_string();
+        if rpc_config.wallet_name != file_name {
+            return Err(WalletError::Protocol(format!(
+                "Wallet name of database file and core missmatch, expected {}, found {}",
+                rpc_config.wallet_name, file_name
+            )));
+        }
+        let mut store = WalletStore::init(
+            file_name,
+            path,
+            backup.network,
+            backup.master_key,
+            backup.wallet_birthday,
+        )?;
+        store.external_index = backup.external_index;
+        store.internal_index = backup.internal_index;
+        store.incoming_swapcoins = backup
+            .incoming_swapcoins
+            .iter()
+            .map(|sc| (sc.get_multisig_redeemscript(), sc.clone()))
+            .collect();
+        store.outgoing_swapcoins = backup
+            .outgoing_swapcoins
+            .iter()
+            .map(|sc| (sc.get_multisig_redeemscript(), sc.clone()))
+            .collect();
+        store.prevout_to_contract_map = backup.prevout_to_contract_map.iter().cloned().collect();
+        // Backups of older versions have no swap key index. Skip past every index in use.
+        store.swap_key_index = backup
+            .incoming_swapcoins
+            .iter()
+            .flat_map(|sc| [sc.my_key_index, sc.hashlock_key_index])
+            .chain(
+                backup
+                    .outgoing_swapcoins
+                    .iter()
+                    .flat_map(|sc| [sc.my_key_index, sc.timelock_key_index]),
+            )
+            .flatten()
+            .map(|index| index + 1)
+            .fold(backup.swap_key_index, u32::max);
+        store.existing_descriptor = backup.existing_descriptor.clone();
+        store.fidelity_bond = backup
+            .fidelity_bonds
+            .iter()
+            .map(|(index, bond, is_spent)| {
+                (*index, (bond.clone(), bond.script_pub_key(), *is_spent))
+            })
+            .collect();
+        store.fidelity_confirmations = store.fidelity_bond.keys().map(|i| (*i, None)).collect();
+
+        let rpc = Arc::new(Client::try_from(rpc_config)?);
+        let mut wallet = Self {
+            backend: Arc::new(CoreRpcBackend::new(rpc.clone(), rpc_config.clone())),
+            rpc: Some(rpc),
+            rpc_config: rpc_config.clone(),
+            wallet_file_path: path.clone(),
+            store,
+            store_key: None,
+        };
+
+        // The keys and swapcoins of the backup must give back the descriptors it lists.
+        let restored_descriptors = wallet.backup_descriptors()?;
+        if let Some(descriptor) = backup
+            .descriptors
+            .iter()
+            .collect::<HashSet<_>>()
+            .symmetric_difference(&restored_descriptors.iter().collect())
+            .next()
+        {
+            let _ = std::fs::remove_file(path);
+            return Err(WalletError::Protocol(format!(
+                "Backup descriptors don't match its keys, {} differs",
+                descriptor
+            )));
+        }
+
+        wallet.backend.open_wallet(&wallet.store.file_name)?;
+        let descriptors_to_import = wallet.descriptors_to_import()?;
+        if descriptors_to_import.is_empty() {
+            if let Some(height) = backup.last_synced_height {
+                wallet.set_last_synced_height(height)?;
+            }
+        } else {
+            log::info!(
+                "Importing {} backed up descriptors",
+                descriptors_to_import.len()
+            );
+            wallet.import_descriptors(&descriptors_to_import, None)?;
+            wallet.rescan_from(wallet.store.wallet_birthday.unwrap_or(0))?;
+        }
+        wallet.discover_hd_indices()?;
+        wallet.refresh_fidelity_confirmations()?;
+        wallet.save_to_disk()?;
+
+        log::info!("Restored wallet {} from backup", wallet.store.file_name);
+        Ok(wallet)
+    }
+}
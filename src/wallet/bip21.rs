@@ -0,0 +1,147 @@
+//! BIP21 payment URIs, like `bitcoin:<address>?amount=0.01&label=Alice`.
+//!
+//! Only the `amount`, `label` and `message` parameters are read. Other parameters are ignored,
+//! unless they are prefixed with `req-`, which the payer must understand to pay.
+
+use std::str::FromStr;
+
+use bitcoin::{Address, Amount, Denomination};
+
+use super::error::WalletError;
+
+/// A payment target, parsed from a BIP21 URI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaymentRequest {
+    pub address: Address,
+    /// Requested amount, if any.
+    pub amount: Option<Amount>,
+    /// Label of the receiver.
+    pub label: Option<String>,
+    /// Note describing the payment.
+    pub message: Option<String>,
+}
+
+/// Parses a `bitcoin:` URI. The scheme is case insensitive, the amount is in BTC.
+///
+/// Like [crate::wallet::Destination], the address isn't checked against a network here. The wallet
+/// checks it when spending to it.
+pub fn parse_bip21(uri: &str) -> Result<PaymentRequest, WalletError> {
+    let invalid = |reason: String| WalletError::Protocol(format!("Invalid BIP21 URI: {}", reason));
+
+    let rest = uri
+        .get(..8)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("bitcoin:"))
+        .map(|_| &uri[8..])
+        .ok_or_else(|| invalid("missing bitcoin: scheme".to_string()))?;
+    let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let address = Address::from_str(address)
+        .map_err(|e| invalid(format!("{} for address {}", e, address)))?
+        .assume_checked();
+
+    let mut request = PaymentRequest {
+        address,
+        amount: None,
+        label: None,
+        message: None,
+    };
+
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (key, value) = param.split_once('=').unwrap_or((param, ""));
+        let value =
+            percent_decode(value).ok_or_else(|| invalid(format!("bad encoding of {}", key)))?;
+        let field = match key {
+            "amount" => {
+                let amount = Amount::from_str_in(&value, Denomination::Bitcoin)
+                    .map_err(|e| invalid(format!("{} for amount {}", e, value)))?;
+                if request.amount.replace(amount).is_some() {
+                    return Err(invalid("duplicate amount".to_string()));
+                }
+                continue;
+            }
+            "label" => &mut request.label,
+            "message" => &mut request.message,
+            _ if key.starts_with("req-") => {
+                return Err(invalid(format!("unsupported required parameter {}", key)))
+            }
+            _ => continue,
+        };
+        if field.replace(value).is_some() {
+            return Err(invalid(format!("duplicate {}", key)));
+        }
+    }
+
+    Ok(request)
+}
+
+impl FromStr for PaymentRequest {
+    type Err = WalletError;
+
+    /// Parses a `bitcoin:` URI, or a plain address.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(':') {
+            return parse_bip21(s);
+        }
+        let address = Address::from_str(s)
+            .map_err(|e| WalletError::Protocol(format!("Invalid address {}: {}", s, e)))?
+            .assume_checked();
+        Ok(PaymentRequest {
+            address,
+            amount: None,
+            label: None,
+            message: None,
+        })
+    }
+}
+
+/// Decodes the `%XX` escapes of a URI component. None if an escape is malformed, or the result
+/// isn't UTF-8.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    #[test]
+    fn test_parse_bip21() {
+        let request = parse_bip21(&format!(
+            "BITCOIN:{}?amount=0.015&label=Alice%20%26%20Bob&message=Coffee&foo=bar",
+            ADDRESS
+        ))
+        .unwrap();
+        assert_eq!(request.address.to_string(), ADDRESS);
+        assert_eq!(request.amount, Some(Amount::from_sat(1_500_000)));
+        assert_eq!(request.label.as_deref(), Some("Alice & Bob"));
+        assert_eq!(request.message.as_deref(), Some("Coffee"));
+
+        let request = parse_bip21(&format!("bitcoin:{}", ADDRESS)).unwrap();
+        assert_eq!(request.amount, None);
+        assert_eq!(request.label, None);
+        assert_eq!(request, PaymentRequest::from_str(ADDRESS).unwrap());
+
+        for uri in [
+            ADDRESS.to_string(),
+            "bitcoin:notanaddress".to_string(),
+            format!("bitcoin:{}?amount=-1", ADDRESS),
+            format!("bitcoin:{}?amount=1,5", ADDRESS),
+            format!("bitcoin:{}?amount=1&amount=2", ADDRESS),
+            format!("bitcoin:{}?label=%4", ADDRESS),
+            format!("bitcoin:{}?req-somethingnew=1", ADDRESS),
+        ] {
+            assert!(parse_bip21(&uri).is_err(), "{}", uri);
+        }
+    }
+}
@@ -0,0 +1,450 @@
+//! Coin selection strategies.
+//!
+//! Decides which wallet utxos fund a transaction. Takers funding a swap pick a strategy to control
+//! which coins get exposed to the makers.
+
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use bitcoin::{hashes::hash160::Hash as Hash160, Amount, ScriptBuf};
+use bitcoind::bitcoincore_rpc::bitcoincore_rpc_json::ListUnspentResultEntry;
+
+use super::{
+    api::{UTXOSpendInfo, UtxoOrigin},
+    error::WalletError,
+    Wallet,
+};
+
+/// Largest excess over the target accepted by [`CoinSelectionStrategy::BranchAndBound`] as a
+/// changeless match. Anything above is better sent back as change.
+const BNB_MAX_EXCESS: Amount = Amount::from_sat(1_000);
+
+/// Number of branches [`CoinSelectionStrategy::BranchAndBound`] explores before giving up.
+const BNB_MAX_TRIES: usize = 100_000;
+
+/// Bitcoin Core's default `-dustrelayfee`, in sats/vB. Utxos are never valued below this rate.
+const DUST_RELAY_FEE_RATE: f64 = 3.0;
+
+/// Weight of a p2wpkh input: 41 non-witness bytes and a signature with a pubkey.
+const P2WPKH_INPUT_WEIGHT: usize = 272;
+
+/// Weight of a 2-of-2 multisig swapcoin input: 41 non-witness bytes, the dummy element, two
+/// signatures and the 71 byte redeemscript.
+const MULTISIG_INPUT_WEIGHT: usize = 384;
+
+/// Weight of a contract input spent through the hashlock: 41 non-witness bytes, a signature,
+/// the 32 byte preimage and the contract redeemscript. The timelock branch is lighter.
+const CONTRACT_INPUT_WEIGHT: usize = 380;
+
+/// Strategy used by [`Wallet::select_coins`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CoinSelectionStrategy {
+    /// Spend the biggest utxos first. Uses the fewest inputs.
+    #[default]
+    LargestFirst,
+    /// Spend the smallest utxos first. Consolidates small coins, at the cost of more inputs.
+    SmallestFirst,
+    /// Search for utxos matching the target closely enough to not need a change output.
+    /// Falls back to [`CoinSelectionStrategy::LargestFirst`] if there is no such match.
+    BranchAndBound,
+    /// Avoid linking coins on-chain. Utxos paid to the same address are spent together, and
+    /// coins from different swaps are never combined.
+    PrivacyOptimized,
+}
+
+/// What ties a utxo to other utxos of the wallet.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Link {
+    /// A wallet coin, tied to every other coin paid to the same address.
+    Address(ScriptBuf),
+    /// A swapcoin or contract output, tied to the rest of its swap by the hashvalue.
+    Swap(Hash160),
+}
+
+impl Wallet {
+    /// Selects utxos worth at least `target`, following `strategy`.
+    ///
+    /// Fidelity bonds and utxos locked with [`Wallet::lock_utxo`] are never selected. Neither are
+    /// utxos costing more than their value to spend at `fee_rate` (sats/vB), floored to the dust relay fee.
+    /// Change isolated by [`Wallet::set_avoid_change_reuse`] is never selected with swap coins.
+    pub fn select_coins(
+        &self,
+        target: Amount,
+        strategy: CoinSelectionStrategy,
+        fee_rate: f64,
+    ) -> Result<Vec<ListUnspentResultEntry>, WalletError> {
+        let fee_rate = fee_rate.max(DUST_RELAY_FEE_RATE);
+        let utxos = self
+            .list_selectable_utxo_spend_info()?
+            .into_iter()
+            .filter(|(utxo, spend_info)| {
+                is_economical(utxo.amount, input_weight(spend_info), fee_rate)
+            })
+            .collect::<Vec<_>>();
+
+        let mut available = Amount::ZERO;
+        for utxos in self.change_isolation_pools(utxos)? {
+            let candidates = utxos
+                .iter()
+                .map(|(utxo, spend_info)| (utxo.amount, self.utxo_link(utxo, spend_info)))
+                .collect::<Vec<_>>();
+            if let Some(selected) = select(&candidates, target, strategy) {
+                return Ok(selected.into_iter().map(|i| utxos[i].0.clone()).collect());
+            }
+            available = available.max(candidates.iter().map(|(amount, _)| *amount).sum());
+        }
+
+        Err(WalletError::InsufficientFund {
+            available: available.to_sat(),
+            required: target.to_sat(),
+        })
+    }
+
+    /// Splits `utxos` into pools that never spend change isolated by
+    /// [`Wallet::set_avoid_change_reuse`] together with coins received from a swap. In order of
+    /// preference: without the isolated change, then without the swap coins. A single pool of
+    /// every utxo if none of them is isolated change.
+    pub(super) fn change_isolation_pools(
+        &self,
+        utxos: Vec<(ListUnspentResultEntry, UTXOSpendInfo)>,
+    ) -> Result<Vec<Vec<(ListUnspentResultEntry, UTXOSpendInfo)>>, WalletError> {
+        let (isolated_change, others): (Vec<_>, Vec<_>) = utxos
+            .into_iter()
+            .partition(|(utxo, _)| self.is_isolated_change(utxo));
+        if isolated_change.is_empty() {
+            return Ok(vec![others]);
+        }
+
+        let mut without_swap_coins = isolated_change;
+        for (utxo, spend_info) in &others {
+            if !matches!(
+                self.utxo_origin(utxo, spend_info)?,
+                Some(UtxoOrigin::SwapOutput { .. })
+            ) {
+                without_swap_coins.push((utxo.clone(), spend_info.clone()));
+            }
+        }
+        Ok(vec![others, without_swap_coins])
+    }
+
+    fn utxo_link(&self, utxo: &ListUnspentResultEntry, spend_info: &UTXOSpendInfo) -> Link {
+        let multisig_redeemscript = match spend_info {
+            UTXOSpendInfo::SwapCoin {
+                multisig_redeemscript,
+            } => Some(multisig_redeemscript),
+            UTXOSpendInfo::TimelockContract {
+                swapcoin_multisig_redeemscript,
+                ..
+            }
+            | UTXOSpendInfo::HashlockContract {
+                swapcoin_multisig_redeemscript,
+                ..
+            } => Some(swapcoin_multisig_redeemscript),
+            UTXOSpendInfo::SeedCoin { .. }
+            | UTXOSpendInfo::FidelityBondCoin { .. }
+            | UTXOSpendInfo::ExistingDescriptorCoin { .. } => None,
+        };
+
+        multisig_redeemscript
+            .and_then(|redeemscript| self.swap_hashvalue(redeemscript))
+            .map_or_else(|| Link::Address(utxo.script_pub_key.clone()), Link::Swap)
+    }
+}
+
+/// Checks if a utxo of `value` is worth more than the fee to spend it at `fee_rate` (sats/vB).
+fn is_economical(value: Amount, input_weight: usize, fee_rate: f64) -> bool {
+    let input_vsize = input_weight.div_ceil(4);
+    value.to_sat() as f64 > input_vsize as f64 * fee_rate
+}
+
+/// Weight of the input spending a utxo.
+fn input_weight(spend_info: &UTXOSpendInfo) -> usize {
+    match spend_info {
+        UTXOSpendInfo::SeedCoin { .. } | UTXOSpendInfo::ExistingDescriptorCoin { .. } => {
+            P2WPKH_INPUT_WEIGHT
+        }
+        // Fidelity bonds are never selected, the multisig weight is a safe upper bound.
+        UTXOSpendInfo::SwapCoin { .. } | UTXOSpendInfo::FidelityBondCoin { .. } => {
+            MULTISIG_INPUT_WEIGHT
+        }
+        UTXOSpendInfo::TimelockContract { .. } | UTXOSpendInfo::HashlockContract { .. } => {
+            CONTRACT_INPUT_WEIGHT
+        }
+    }
+}
+
+/// Picks the indices of the `utxos` to spend for `target`, or `None` if they can't cover it.
+fn select(
+    utxos: &[(Amount, Link)],
+    target: Amount,
+    strategy: CoinSelectionStrategy,
+) -> Option<Vec<usize>> {
+    let mut by_amount = (0..utxos.len()).collect::<Vec<_>>();
+    by_amount.sort_by_key(|&i| Reverse(utxos[i].0));
+
+    match strategy {
+        CoinSelectionStrategy::LargestFirst => {
+            accumulate(by_amount.into_iter().map(|i| (utxos[i].0, vec![i])), target)
+        }
+        CoinSelectionStrategy::SmallestFirst => accumulate(
+            by_amount.into_iter().rev().map(|i| (utxos[i].0, vec![i])),
+            target,
+        ),
+        CoinSelectionStrategy::BranchAndBound => {
+            let amounts = by_amount.iter().map(|&i| utxos[i].0).collect::<Vec<_>>();
+            BranchAndBound::new(amounts, target)
+                .search()
+                .map(|positions| positions.into_iter().map(|p| by_amount[p]).collect())
+                .or_else(|| select(utxos, target, CoinSelectionStrategy::LargestFirst))
+        }
+        CoinSelectionStrategy::PrivacyOptimized => privacy_optimized(utxos, target),
+    }
+}
+
+/// Takes `groups` of utxos in order until their total covers `target`.
+fn accumulate(
+    groups: impl IntoIterator<Item = (Amount, Vec<usize>)>,
+    target: Amount,
+) -> Option<Vec<usize>> {
+    let mut total = Amount::ZERO;
+    let mut selected = Vec::new();
+    for (amount, indices) in groups {
+        if total >= target {
+            break;
+        }
+        total += amount;
+        selected.extend(indices);
+    }
+    (total >= target).then_some(selected)
+}
+
+fn privacy_optimized(utxos: &[(Amount, Link)], target: Amount) -> Option<Vec<usize>> {
+    // Utxos sharing an address or a swap are already linked, so they're spent as one group.
+    let mut groups = BTreeMap::<&Link, (Amount, Vec<usize>)>::new();
+    for (i, (amount, link)) in utxos.iter().enumerate() {
+        let group = groups.entry(link).or_insert((Amount::ZERO, Vec::new()));
+        group.0 += *amount;
+        group.1.push(i);
+    }
+
+    // A single group links nothing new.
+    if let Some((_, indices)) = groups
+        .values()
+        .filter(|(total, _)| *total >= target)
+        .min_by_key(|(total, _)| *total)
+    {
+        return Some(indices.clone());
+    }
+
+    // Otherwise combine address groups, largest first, with at most one swap.
+    let (swaps, mut addresses): (Vec<_>, Vec<_>) = groups
+        .into_iter()
+        .partition(|(link, _)| matches!(link, Link::Swap(_)));
+    addresses.sort_by_key(|(_, (total, _))| Reverse(*total));
+    let largest_swap = swaps.into_iter().max_by_key(|(_, (total, _))| *total);
+
+    accumulate(addresses.iter().map(|(_, group)| group.clone()), target).or_else(|| {
+        let (_, swap) = largest_swap?;
+        accumulate(
+            std::iter::once(swap).chain(addresses.into_iter().map(|(_, group)| group)),
+            target,
+        )
+    })
+}
+
+/// Depth first search for a subset of `amounts` within [`BNB_MAX_EXCESS`] of the target.
+struct BranchAndBound {
+    /// Sorted in descending order, so that big overshoots get pruned early.
+    amounts: Vec<Amount>,
+    /// `remaining[i]` is the sum of `amounts[i..]`.
+    remaining: Vec<Amount>,
+    target: Amount,
+    tries: usize,
+}
+
+impl BranchAndBound {
+    fn new(amounts: Vec<Amount>, target: Amount) -> Self {
+        let mut remaining = vec![Amount::ZERO; amounts.len() + 1];
+        for i in (0..amounts.len()).rev() {
+            remaining[i] = remaining[i + 1] + amounts[i];
+        }
+        Self {
+            amounts,
+            remaining,
+            target,
+            tries: 0,
+        }
+    }
+
+    /// Returns the positions in `amounts` of the match, if one is found.
+    fn search(&mut self) -> Option<Vec<usize>> {
+        let mut selected = Vec::new();
+        self.explore(0, Amount::ZERO, &mut selected)
+            .then_some(selected)
+    }
+
+    fn explore(&mut self, depth: usize, total: Amount, selected: &mut Vec<usize>) -> bool {
+        self.tries += 1;
+        if self.tries > BNB_MAX_TRIES || total > self.target + BNB_MAX_EXCESS {
+            return false;
+        }
+        if total >= self.target {
+            return true;
+        }
+        if depth == self.amounts.len() || total + self.remaining[depth] < self.target {
+            return false;
+        }
+
+        selected.push(depth);
+        if self.explore(depth + 1, total + self.amounts[depth], selected) {
+            return true;
+        }
+        selected.pop();
+        self.explore(depth + 1, total, selected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    fn address(byte: u8) -> Link {
+        Link::Address(ScriptBuf::from_bytes(vec![byte]))
+    }
+
+    fn swap(byte: u8) -> Link {
+        Link::Swap(Hash160::hash(&[byte]))
+    }
+
+    fn amounts(utxos: &[(Amount, Link)], mut selected: Vec<usize>) -> Vec<u64> {
+        selected.sort();
+        selected.iter().map(|&i| utxos[i].0.to_sat()).collect()
+    }
+
+    #[test]
+    fn test_is_economical() {
+        // A p2wpkh input is 68 vB.
+        assert!(is_economical(
+            Amount::from_sat(300),
+            P2WPKH_INPUT_WEIGHT,
+            3.0
+        ));
+        assert!(!is_economical(
+            Amount::from_sat(300),
+            P2WPKH_INPUT_WEIGHT,
+            5.0
+        ));
+        assert!(!is_economical(
+            Amount::from_sat(204),
+            P2WPKH_INPUT_WEIGHT,
+            3.0
+        ));
+
+        // The same value isn't worth spending from a heavier contract input.
+        assert!(is_economical(
+            Amount::from_sat(250),
+            P2WPKH_INPUT_WEIGHT,
+            3.0
+        ));
+        assert!(!is_economical(
+            Amount::from_sat(250),
+            CONTRACT_INPUT_WEIGHT,
+            3.0
+        ));
+    }
+
+    #[test]
+    fn test_select_largest_and_smallest_first() {
+        let utxos = [1_000, 5_000, 3_000]
+            .iter()
+            .enumerate()
+            .map(|(i, &sat)| (Amount::from_sat(sat), address(i as u8)))
+            .collect::<Vec<_>>();
+        let target = Amount::from_sat(4_000);
+
+        let largest = select(&utxos, target, CoinSelectionStrategy::LargestFirst).unwrap();
+        assert_eq!(amounts(&utxos, largest), vec![5_000]);
+
+        let smallest = select(&utxos, target, CoinSelectionStrategy::SmallestFirst).unwrap();
+        assert_eq!(amounts(&utxos, smallest), vec![1_000, 3_000]);
+
+        assert!(select(
+            &utxos,
+            Amount::from_sat(10_000),
+            CoinSelectionStrategy::SmallestFirst
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_select_branch_and_bound() {
+        let utxos = [10_000, 6_000, 4_000, 2_500]
+            .iter()
+            .enumerate()
+            .map(|(i, &sat)| (Amount::from_sat(sat), address(i as u8)))
+            .collect::<Vec<_>>();
+
+        // 6_000 + 2_500 is within the allowed excess, no change needed.
+        let exact = select(
+            &utxos,
+            Amount::from_sat(8_000),
+            CoinSelectionStrategy::BranchAndBound,
+        )
+        .unwrap();
+        assert_eq!(amounts(&utxos, exact), vec![6_000, 2_500]);
+
+        // No changeless match, falls back to largest first.
+        let fallback = select(
+            &utxos,
+            Amount::from_sat(1_000),
+            CoinSelectionStrategy::BranchAndBound,
+        )
+        .unwrap();
+        assert_eq!(amounts(&utxos, fallback), vec![10_000]);
+    }
+
+    #[test]
+    fn test_select_privacy_optimized() {
+        let utxos = vec![
+            (Amount::from_sat(4_000), address(0)),
+            (Amount::from_sat(2_000), address(0)),
+            (Amount::from_sat(5_000), address(1)),
+            (Amount::from_sat(7_000), swap(0)),
+            (Amount::from_sat(8_000), swap(1)),
+        ];
+
+        // Both coins on the reused address get spent, even though one of them is enough.
+        let reused = select(
+            &utxos,
+            Amount::from_sat(5_500),
+            CoinSelectionStrategy::PrivacyOptimized,
+        )
+        .unwrap();
+        assert_eq!(amounts(&utxos, reused), vec![4_000, 2_000]);
+
+        // Wallet coins are combined before reaching for a swap.
+        let combined = select(
+            &utxos,
+            Amount::from_sat(10_000),
+            CoinSelectionStrategy::PrivacyOptimized,
+        )
+        .unwrap();
+        assert_eq!(amounts(&utxos, combined), vec![4_000, 2_000, 5_000]);
+
+        // Only one of the two swaps is ever spent.
+        let with_swap = select(
+            &utxos,
+            Amount::from_sat(15_000),
+            CoinSelectionStrategy::PrivacyOptimized,
+        )
+        .unwrap();
+        assert_eq!(amounts(&utxos, with_swap), vec![4_000, 2_000, 5_000, 8_000]);
+
+        assert!(select(
+            &utxos,
+            Amount::from_sat(25_000),
+            CoinSelectionStrategy::PrivacyOptimized
+        )
+        .is_none());
+    }
+}
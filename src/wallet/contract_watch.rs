@@ -0,0 +1,119 @@
+//! Watching the contract outputs of the swaps for spends.
+//!
+//! A counterparty redeeming a contract through its hashlock branch reveals the preimage in the
+//! witness of the spending transaction. [Wallet::watch_contract_spend] finds that transaction and
+//! reads the preimage from it, so the swap can be completed without the counterparty sending it.
+//!
+//! The spending transaction is looked up through the wallet's
+//! [`ChainBackend::get_spending_transaction`](super::ChainBackend::get_spending_transaction).
+
+use bitcoin::{OutPoint, Script, Transaction, TxIn, Txid};
+
+use crate::protocol::{
+    contract::{extract_preimage_from_witness, read_hashlock_from_contract},
+    messages::Preimage,
+};
+
+use super::{error::WalletError, Wallet};
+
+/// A spend of a contract output, found by [Wallet::watch_contract_spend].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractSpendInfo {
+    pub spending_txid: Txid,
+    /// Height the spending transaction confirmed at. `None` while it's in the mempool.
+    pub confirmation_height: Option<u64>,
+    /// Preimage revealed by a hashlock spend. `None` for a timelock spend.
+    pub preimage: Option<Preimage>,
+}
+
+impl Wallet {
+    /// Checks whether the contract output at `outpoint` is spent. `None` if it's unspent, or if
+    /// the contract transaction isn't broadcasted yet.
+    ///
+    /// If the output got spent through the hashlock branch, the preimage is extracted from the
+    /// spending input's witness, and checked against the contract's hashlock.
+    pub fn watch_contract_spend(
+        &self,
+        outpoint: OutPoint,
+    ) -> Result<Option<ContractSpendInfo>, WalletError> {
+        let contract_height = match self.backend.get_transaction(&outpoint.txid)? {
+            Some((_, height)) => height,
+            None => return Ok(None),
+        };
+
+        Ok(self
+            .backend
+            .get_spending_transaction(&outpoint, contract_height)?
+            .and_then(|(tx, height)| find_spend(&[tx], outpoint, height)))
+    }
+}
+
+/// The spend of `outpoint` among `txs`, if any.
+fn find_spend(
+    txs: &[Transaction],
+    outpoint: OutPoint,
+    confirmation_height: Option<u64>,
+) -> Option<ContractSpendInfo> {
+    txs.iter().find_map(|tx| {
+        let input = tx
+            .input
+            .iter()
+            .find(|input| input.previous_output == outpoint)?;
+        Some(ContractSpendInfo {
+            spending_txid: tx.compute_txid(),
+            confirmation_height,
+            preimage: extract_preimage(input),
+        })
+    })
+}
+
+/// The preimage in the witness of a hashlock spend: `<sig> <preimage> <contract redeemscript>`.
+/// Timelock spends have an empty element in place of the preimage.
+fn extract_preimage(input: &TxIn) -> Option<Preimage> {
+    let contract_redeemscript = Script::from_bytes(input.witness.last()?);
+    let hashlock = read_hashlock_from_contract(contract_redeemscript).ok()?;
+    extract_preimage_from_witness(&input.witness, &hashlock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::contract::{create_contract_redeemscript, Hashlock, HashlockType};
+    use bitcoin::{PublicKey, Witness};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_extract_preimage() {
+        let pubkey = PublicKey::from_str(
+            "032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af",
+        )
+        .unwrap();
+        let preimage: Preimage = [7; 32];
+
+        for hashlock_type in [HashlockType::Hash160, HashlockType::Sha256] {
+            let contract_redeemscript = create_contract_redeemscript(
+                &pubkey,
+                &pubkey,
+                &Hashlock::new(hashlock_type, &preimage),
+                &100,
+            );
+
+            let input = |middle: &[u8]| TxIn {
+                witness: Witness::from_slice(&[
+                    &[0x30; 72][..],
+                    middle,
+                    contract_redeemscript.as_bytes(),
+                ]),
+                ..Default::default()
+            };
+
+            assert_eq!(extract_preimage(&input(&preimage)), Some(preimage));
+            // Timelock spend.
+            assert_eq!(extract_preimage(&input(&[])), None);
+            // Not the contract's preimage.
+            assert_eq!(extract_preimage(&input(&[8; 32])), None);
+        }
+        // Not a contract spend.
+        assert_eq!(extract_preimage(&TxIn::default()), None);
+    }
+}
@@ -7,14 +7,18 @@
 use std::{num::ParseIntError, str::FromStr};
 
 use bitcoin::{
-    absolute::LockTime, transaction::Version, Address, Amount, Network, OutPoint, ScriptBuf,
-    Sequence, Transaction, TxIn, TxOut, Witness,
+    absolute::LockTime, address::NetworkUnchecked, transaction::Version, Address, Amount, Network,
+    OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
 };
-use bitcoind::bitcoincore_rpc::{json::ListUnspentResultEntry, RawTx, RpcApi};
+use bitcoind::bitcoincore_rpc::{json::ListUnspentResultEntry, RawTx};
 
 use crate::wallet::{api::UTXOSpendInfo, SwapCoin};
 
-use super::{error::WalletError, Wallet};
+use super::{
+    error::WalletError,
+    fee_estimation::{FeeEstimator, DEFAULT_CONFIRMATION_TARGET},
+    Wallet,
+};
 
 /// Enum representing different options for the amount to be sent in a transaction.
 #[derive(Debug, Clone, PartialEq)]
@@ -106,7 +110,41 @@ impl FromStr for CoinToSpend {
     }
 }
 
+/// Checks that `address` is valid on `network`, like [Wallet::validate_address].
+fn check_address_network(
+    address: Address<NetworkUnchecked>,
+    network: Network,
+) -> Result<Address, WalletError> {
+    if address.is_valid_for_network(network) {
+        return Ok(address.assume_checked());
+    }
+    // Testnet and signet addresses look alike, the first match is reported.
+    let found = [
+        Network::Bitcoin,
+        Network::Testnet,
+        Network::Signet,
+        Network::Regtest,
+    ]
+    .iter()
+    .copied()
+    .find(|n| address.is_valid_for_network(*n))
+    .ok_or_else(|| WalletError::Protocol("Address of unknown network".to_string()))?;
+    Err(WalletError::WrongNetworkAddress {
+        expected: network,
+        found,
+    })
+}
+
 impl Wallet {
+    /// Parses `addr` and checks it belongs to the wallet's network, before anything is built
+    /// around it. Transactions spending to an address of another network would only be rejected
+    /// by the node on broadcast.
+    pub fn validate_address(&self, addr: &str) -> Result<Address, WalletError> {
+        let address = Address::from_str(addr)
+            .map_err(|e| WalletError::Protocol(format!("Invalid address {}: {}", addr, e)))?;
+        check_address_network(address, self.store.network)
+    }
+
     /// API to perform spending from wallet utxos, Including descriptor coins, swap coins or contract outputs (timelock/hashlock).
     /// This should not be used to spend the Fidelity Bond. Check [Wallet::redeem_fidelity] for fidelity spending.
     ///
@@ -124,6 +162,9 @@ impl Wallet {
         coins_to_spend: &[(ListUnspentResultEntry, UTXOSpendInfo)],
     ) -> Result<Transaction, WalletError> {
         log::info!("Creating Direct-Spend from Wallet.");
+        if let Destination::Address(a) = &destination {
+            check_address_network(a.as_unchecked().clone(), self.store.network)?;
+        }
         let mut tx_inputs = Vec::<TxIn>::new();
         let mut spend_infos = Vec::new();
         let mut total_input_value = Amount::ZERO;
@@ -142,7 +183,8 @@ impl Wallet {
                     swapcoin_multisig_redeemscript: _,
                     input_value: _,
                 } => 1, //hashlock spends must have 1 because of the `OP_CSV 1`
-                _ => 0,
+                // Opt-in RBF (BIP125), so the spend can be fee bumped.
+                _ => Sequence::ENABLE_RBF_NO_LOCKTIME.0,
             };
 
             tx_inputs.push(TxIn {
@@ -167,22 +209,7 @@ impl Wallet {
 
         let dest_addr = match destination {
             Destination::Wallet => self.get_next_external_address()?,
-            Destination::Address(a) => {
-                //testnet and signet addresses have the same vbyte
-                //so a.network is always testnet even if the address is signet
-                let testnet_signet_type = (a.as_unchecked().is_valid_for_network(Network::Testnet)
-                    || a.as_unchecked().is_valid_for_network(Network::Signet))
-                    && (self.store.network == Network::Testnet
-                        || self.store.network == Network::Signet);
-                if !a.as_unchecked().is_valid_for_network(self.store.network)
-                    && !testnet_signet_type
-                {
-                    return Err(WalletError::Protocol(
-                        "Wrong address type in destinations.".to_string(),
-                    ));
-                }
-                a
-            }
+            Destination::Address(a) => a,
         };
 
         let mut output = Vec::<TxOut>::new();
@@ -215,7 +242,7 @@ impl Wallet {
         }
 
         // Set the Anti-Fee-Snipping locktime
-        let lock_time = LockTime::from_height(self.rpc.get_block_count().unwrap() as u32).unwrap();
+        let lock_time = LockTime::from_height(self.backend.get_block_count()? as u32).unwrap();
 
         let mut tx = Transaction {
             input: tx_inputs,
@@ -230,6 +257,100 @@ impl Wallet {
         log::debug!("Signed Transaction : {:?}", tx.raw_hex());
         Ok(tx)
     }
+
+    /// Sends `amount` to `destination` paying the rate `fee_estimator` gives for the
+    /// [`DEFAULT_CONFIRMATION_TARGET`], and broadcasts it. The rest goes to a change address,
+    /// unless it's dust.
+    pub fn send_to(
+        &mut self,
+        destination: Address,
+        amount: Amount,
+        fee_estimator: &dyn FeeEstimator,
+    ) -> Result<Txid, WalletError> {
+        let fee_rate = fee_estimator.estimate_sat_per_vb(DEFAULT_CONFIRMATION_TARGET)?;
+        // The fee depends on the selected coins, which depend on the fee. Raise it until it covers
+        // the size of the signed transaction.
+        let mut fee = Amount::ZERO;
+        loop {
+            let coins = self.coin_select(amount + fee)?;
+            let total_input_value = coins.iter().map(|(utxo, _)| utxo.amount).sum::<Amount>();
+            if total_input_value < amount + fee {
+                return Err(WalletError::InsufficientFund {
+                    available: total_input_value.to_sat(),
+                    required: (amount + fee).to_sat(),
+                });
+            }
+            let tx = self.spend_from_wallet(
+                fee,
+                SendAmount::Amount(amount),
+                Destination::Address(destination.clone()),
+                &coins,
+            )?;
+            let required_fee = Amount::from_sat((tx.vsize() as f64 * fee_rate).ceil() as u64);
+            if fee >= required_fee {
+                let txid = self.backend.broadcast(&tx)?;
+                log::info!(
+                    "Sent {} to {} | fee: {} | txid: {}",
+                    amount,
+                    destination,
+                    fee,
+                    txid
+                );
+                return Ok(txid);
+            }
+            fee = required_fee;
+        }
+    }
+
+    /// Sweeps every spendable utxo of the wallet to `destination` in a single transaction paying
+    /// the rate `fee_estimator` gives for the [`DEFAULT_CONFIRMATION_TARGET`], and broadcasts it.
+    /// Useful to migrate the wallet.
+    ///
+    /// Fidelity bonds, live contracts and utxos reserved with [Wallet::lock_utxo] aren't swept.
+    pub fn sweep_all(
+        &mut self,
+        destination: Address,
+        fee_estimator: &dyn FeeEstimator,
+    ) -> Result<Txid, WalletError> {
+        let fee_rate = fee_estimator.estimate_sat_per_vb(DEFAULT_CONFIRMATION_TARGET)?;
+        let coins = self.list_selectable_utxo_spend_info()?;
+        let total_input_value = coins.iter().map(|(utxo, _)| utxo.amount).sum::<Amount>();
+
+        // Sign once with no fee to get the final size, then again with the actual fee.
+        let vsize = self
+            .spend_from_wallet(
+                Amount::ZERO,
+                SendAmount::Max,
+                Destination::Address(destination.clone()),
+                &coins,
+            )?
+            .vsize();
+        let fee = Amount::from_sat((vsize as f64 * fee_rate).ceil() as u64);
+        let required = fee + destination.script_pubkey().minimal_non_dust();
+        if total_input_value < required {
+            return Err(WalletError::InsufficientFund {
+                available: total_input_value.to_sat(),
+                required: required.to_sat(),
+            });
+        }
+
+        let tx = self.spend_from_wallet(
+            fee,
+            SendAmount::Max,
+            Destination::Address(destination),
+            &coins,
+        )?;
+        let txid = self.backend.broadcast(&tx)?;
+        log::info!(
+            "Swept {} utxos | amount: {} | fee: {} | txid: {}",
+            coins.len(),
+            total_input_value - fee,
+            fee,
+            txid
+        );
+
+        Ok(txid)
+    }
 }
 
 #[cfg(test)]
@@ -277,6 +398,39 @@ mod tests {
         assert!(Destination::from_str("invalid address").is_err());
     }
 
+    #[test]
+    fn test_check_address_network() {
+        let mainnet = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        let testnet = Address::from_str("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap();
+        let regtest = Address::from_str("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080").unwrap();
+        let legacy_testnet = Address::from_str("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn").unwrap();
+
+        assert!(check_address_network(mainnet.clone(), Network::Bitcoin).is_ok());
+        assert!(check_address_network(testnet.clone(), Network::Signet).is_ok());
+        assert!(check_address_network(legacy_testnet, Network::Regtest).is_ok());
+        assert!(matches!(
+            check_address_network(testnet, Network::Bitcoin),
+            Err(WalletError::WrongNetworkAddress {
+                expected: Network::Bitcoin,
+                found: Network::Testnet
+            })
+        ));
+        assert!(matches!(
+            check_address_network(mainnet, Network::Regtest),
+            Err(WalletError::WrongNetworkAddress {
+                expected: Network::Regtest,
+                found: Network::Bitcoin
+            })
+        ));
+        assert!(matches!(
+            check_address_network(regtest, Network::Testnet),
+            Err(WalletError::WrongNetworkAddress {
+                expected: Network::Testnet,
+                found: Network::Regtest
+            })
+        ));
+    }
+
     #[test]
     fn test_coin_to_spend_long_form_and_short_form_parsing() {
         let valid_outpoint_str =
@@ -1,5 +1,7 @@
 //! All Wallet-related errors.
 
+use std::{error::Error, fmt};
+
 use super::fidelity::FidelityError;
 use crate::protocol::error::ContractError;
 
@@ -8,6 +10,8 @@ use crate::protocol::error::ContractError;
 pub enum WalletError {
     File(std::io::Error),
     Cbor(serde_cbor::Error),
+    Json(serde_json::Error),
+    Consensus(bitcoin::consensus::encode::Error),
     Rpc(bitcoind::bitcoincore_rpc::Error),
     Protocol(String),
     BIP32(bitcoin::bip32::Error),
@@ -16,6 +20,145 @@ pub enum WalletError {
     Fidelity(FidelityError),
     Locktime(bitcoin::blockdata::locktime::absolute::ConversionError),
     Secp(bitcoin::secp256k1::Error),
+    InvalidWalletName(String),
+    UnsupportedCoreVersion {
+        found: u32,
+        minimum: u32,
+    },
+    NetworkMismatch {
+        expected: bitcoin::Network,
+        found: bitcoin::Network,
+    },
+    WrongNetworkAddress {
+        expected: bitcoin::Network,
+        found: bitcoin::Network,
+    },
+    DescriptorImport {
+        descriptor: String,
+        error: Box<WalletError>,
+    },
+    ImportBatch {
+        chunk: usize,
+        error: Box<WalletError>,
+    },
+    InsufficientFund {
+        available: u64,
+        required: u64,
+    },
+    MempoolRejected {
+        txid: bitcoin::Txid,
+        reason: String,
+    },
+    Encryption(String),
+    FeeEstimation(String),
+    UnsupportedDescriptor(String),
+    UtxoNotSpendable(bitcoin::OutPoint),
+}
+
+impl WalletError {
+    /// Wraps an error that occurred while processing the given descriptor.
+    pub(crate) fn descriptor_import(descriptor: &str, error: impl Into<WalletError>) -> Self {
+        Self::DescriptorImport {
+            descriptor: descriptor.to_string(),
+            error: Box::new(error.into()),
+        }
+    }
+}
+
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(e) => write!(f, "wallet file error: {}", e),
+            Self::Cbor(e) => write!(f, "wallet file encoding error: {}", e),
+            Self::Json(e) => write!(f, "json error: {}", e),
+            Self::Consensus(e) => write!(f, "transaction decoding error: {}", e),
+            Self::Rpc(e) => write!(f, "bitcoin core rpc error: {}", e),
+            Self::Protocol(msg) => write!(f, "{}", msg),
+            Self::BIP32(e) => write!(f, "bip32 error: {}", e),
+            Self::BIP39(e) => write!(f, "bip39 error: {}", e),
+            Self::Contract(e) => write!(f, "contract error: {:?}", e),
+            Self::Fidelity(e) => write!(f, "fidelity bond error: {:?}", e),
+            Self::Locktime(e) => write!(f, "locktime error: {}", e),
+            Self::Secp(e) => write!(f, "secp256k1 error: {}", e),
+            Self::InvalidWalletName(name) => write!(f, "invalid wallet name: {}", name),
+            Self::UnsupportedCoreVersion { found, minimum } => write!(
+                f,
+                "unsupported bitcoin core version {}, minimum is {}",
+                found, minimum
+            ),
+            Self::NetworkMismatch { expected, found } => {
+                write!(
+                    f,
+                    "network mismatch, expected {}, found {}",
+                    expected, found
+                )
+            }
+            Self::WrongNetworkAddress { expected, found } => write!(
+                f,
+                "address is for {}, but the wallet is on {}",
+                found, expected
+            ),
+            Self::DescriptorImport { descriptor, .. } => {
+                write!(f, "failed to import descriptor {}", descriptor)
+            }
+            Self::ImportBatch { chunk, .. } => {
+                write!(f, "failed to import descriptor chunk {}", chunk)
+            }
+            Self::InsufficientFund {
+                available,
+                required,
+            } => write!(
+                f,
+                "insufficient funds, available {} sats, required {} sats",
+                available, required
+            ),
+            Self::MempoolRejected { txid, reason } => {
+                write!(
+                    f,
+                    "transaction {} rejected by the mempool: {}",
+                    txid, reason
+                )
+            }
+            Self::Encryption(msg) => write!(f, "wallet encryption error: {}", msg),
+            Self::FeeEstimation(msg) => write!(f, "fee estimation error: {}", msg),
+            Self::UnsupportedDescriptor(msg) => write!(f, "unsupported descriptor: {}", msg),
+            Self::UtxoNotSpendable(outpoint) => {
+                write!(f, "utxo {} isn't a spendable coin of the wallet", outpoint)
+            }
+        }
+    }
+}
+
+impl Error for WalletError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::File(e) => Some(e),
+            Self::Cbor(e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::Consensus(e) => Some(e),
+            Self::Rpc(e) => Some(e),
+            Self::BIP32(e) => Some(e),
+            Self::BIP39(e) => Some(e),
+            Self::Locktime(e) => Some(e),
+            Self::Secp(e) => Some(e),
+            Self::DescriptorImport { error, .. } | Self::ImportBatch { error, .. } => {
+                Some(error.as_ref())
+            }
+            Self::Protocol(_)
+            | Self::Contract(_)
+            | Self::Fidelity(_)
+            | Self::InvalidWalletName(_)
+            | Self::UnsupportedCoreVersion { .. }
+            | Self::NetworkMismatch { .. }
+            | Self::WrongNetworkAddress { .. }
+            | Self::InsufficientFund { .. }
+            | Self::MempoolRejected { .. }
+            | Self::Encryption(_)
+            | Self::FeeEstimation(_)
+            | Self::UnsupportedDescriptor(_)
+            | Self::UtxoNotSpendable(_) => None,
+        }
+    }
 }
 
 impl From<std::io::Error> for WalletError {
@@ -30,6 +173,18 @@ impl From<bitcoind::bitcoincore_rpc::Error> for WalletError {
     }
 }
 
+impl From<serde_json::Error> for WalletError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+impl From<bitcoin::consensus::encode::Error> for WalletError {
+    fn from(value: bitcoin::consensus::encode::Error) -> Self {
+        Self::Consensus(value)
+    }
+}
+
 impl From<bitcoin::bip32::Error> for WalletError {
     fn from(value: bitcoin::bip32::Error) -> Self {
         Self::BIP32(value)
@@ -71,3 +226,29 @@ impl From<bitcoin::secp256k1::Error> for WalletError {
         Self::Secp(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_chain() {
+        let io_error = std::io::Error::new(std::io::ErrorKind::NotFound, "missing");
+        let error = WalletError::ImportBatch {
+            chunk: 2,
+            error: Box::new(WalletError::descriptor_import("wpkh(...)", io_error)),
+        };
+        assert_eq!(error.to_string(), "failed to import descriptor chunk 2");
+
+        let descriptor_error = error.source().unwrap();
+        assert_eq!(
+            descriptor_error.to_string(),
+            "failed to import descriptor wpkh(...)"
+        );
+        let cause = descriptor_error.source().unwrap();
+        assert_eq!(cause.to_string(), "wallet file error: missing");
+        assert!(cause
+            .downcast_ref::<WalletError>()
+            .is_some_and(|e| matches!(e, WalletError::File(_))));
+    }
+}
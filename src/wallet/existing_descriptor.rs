@@ -0,0 +1,378 @@
+//! Wallets set up over the descriptor of a wallet created outside coinswap.
+//!
+//! [`Wallet::from_existing_descriptor`] takes the descriptor of an existing Bitcoin Core wallet, and
+//! spends its coins where they are, instead of moving them to keys derived from a coinswap seed.
+//! The wallet signs the inputs itself, so only `wpkh` descriptors holding their private key are
+//! supported, either ranged (`wpkh(xprv/0/*)`) or fixed (`wpkh(xprv/0/1)`, `wpkh(WIF)`).
+//!
+//! Change, swap and fidelity bond keys still come from coinswap's own keychains. Their master key
+//! is seeded with the descriptor's private key, so the descriptor alone restores the whole wallet.
+
+use std::{collections::HashMap, convert::TryFrom, path::PathBuf, str::FromStr, sync::Arc};
+
+use bitcoin::{
+    bip32::{ChildNumber, Xpriv, Xpub},
+    secp256k1::{Secp256k1, SecretKey},
+    Amount, Network, NetworkKind, PrivateKey, Script, ScriptBuf,
+};
+use bitcoind::bitcoincore_rpc::{Client, RpcApi};
+use serde::{Deserialize, Serialize};
+
+use crate::utill::{append_descriptor_checksum, validate_descriptor_checksum};
+
+use super::{
+    backend::CoreRpcBackend, error::WalletError, rpc::strip_checksum, storage::WalletStore,
+    RPCConfig, UTXOSpendInfo, Wallet,
+};
+
+/// The descriptor a wallet was set up from, see [`Wallet::from_existing_descriptor`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExistingDescriptor {
+    /// The descriptor without private keys, with checksum: `wpkh(xpub/*)` or `wpkh(pubkey)`.
+    /// This is what the core wallet imports.
+    pub(super) public_descriptor: String,
+    pub(super) key: DescriptorKey,
+    /// The next unused index of a ranged descriptor, as last seen by sync.
+    pub(super) next_index: u32,
+}
+
+/// Private key of an [`ExistingDescriptor`], derived down to the address keys: the parent
+/// key of a ranged descriptor, or the key of a fixed one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) enum DescriptorKey {
+    Ranged(Xpriv),
+    Single(PrivateKey),
+}
+
+impl ExistingDescriptor {
+    /// Parses the private key out of `descriptor`. `is_range` comes from `getdescriptorinfo`.
+    fn new(descriptor: &str, is_range: bool, network: Network) -> Result<Self, WalletError> {
+        let key = parse_wpkh_key(descriptor)?;
+
+        let key_network = match &key {
+            DescriptorKey::Ranged(xpriv) => xpriv.network,
+            DescriptorKey::Single(privkey) => privkey.network,
+        };
+        if key_network != NetworkKind::from(network) {
+            return Err(WalletError::UnsupportedDescriptor(format!(
+                "the descriptor's key isn't for {}",
+                network
+            )));
+        }
+        if is_range != matches!(key, DescriptorKey::Ranged(_)) {
+            return Err(WalletError::UnsupportedDescriptor(
+                "unexpected derivation steps".to_string(),
+            ));
+        }
+
+        // Hardened steps can't be derived from an xpub, the public descriptor starts below them.
+        let secp = Secp256k1::new();
+        let public_descriptor = match &key {
+            DescriptorKey::Ranged(xpriv) => format!("wpkh({}/*)", Xpub::from_priv(&secp, xpriv)),
+            DescriptorKey::Single(privkey) => format!("wpkh({})", privkey.public_key(&secp)),
+        };
+        Ok(Self {
+            public_descriptor: append_descriptor_checksum(&public_descriptor)?,
+            key,
+            next_index: 0,
+        })
+    }
+
+    pub(super) fn is_ranged(&self) -> bool {
+        matches!(self.key, DescriptorKey::Ranged(_))
+    }
+
+    /// The private key at `index`, which is `None` for a fixed descriptor.
+    pub(super) fn privkey(&self, index: Option<u32>) -> Result<SecretKey, WalletError> {
+        match (&self.key, index) {
+            (DescriptorKey::Ranged(xpriv), Some(index)) => Ok(xpriv
+                .derive_priv(&Secp256k1::new(), &[ChildNumber::from_normal_idx(index)?])?
+                .private_key),
+            (DescriptorKey::Single(privkey), None) => Ok(privkey.inner),
+            _ => Err(WalletError::Protocol(format!(
+                "No key at index {:?} of the existing descriptor",
+                index
+            ))),
+        }
+    }
+
+    /// The p2wpkh script pubkey of the key at `index`.
+    pub(super) fn script_pubkey(&self, index: Option<u32>) -> Result<ScriptBuf, WalletError> {
+        let pubkey =
+            bitcoin::CompressedPublicKey(self.privkey(index)?.public_key(&Secp256k1::new()));
+        Ok(ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash()))
+    }
+
+    /// Index of the coin paying to `script_pubkey`, which Core reports with `descriptor`.
+    /// `None` if the coin isn't of this descriptor, `Some(None)` for the key of a fixed descriptor.
+    fn coin_index(&self, descriptor: &str, script_pubkey: &Script) -> Option<Option<u32>> {
+        // Core reports the derived key with its origin, ending with the index: `wpkh([fp/5]02..)`.
+        let index = if self.is_ranged() {
+            let origin = &descriptor[descriptor.find('[')? + 1..descriptor.find(']')?];
+            Some(origin.rsplit('/').next()?.parse::<u32>().ok()?)
+        } else {
+            None
+        };
+        (self.script_pubkey(index).ok()?.as_script() == script_pubkey).then_some(index)
+    }
+
+    /// Seed of the wallet's master key.
+    fn master_seed(&self) -> [u8; 32] {
+        match &self.key {
+            DescriptorKey::Ranged(xpriv) => xpriv.private_key.secret_bytes(),
+            DescriptorKey::Single(privkey) => privkey.inner.secret_bytes(),
+        }
+    }
+}
+
+/// Parses the key of a `wpkh` descriptor: an xprv with its derivation steps, or a WIF key.
+/// An xprv is derived along its steps, up to the wildcard if any.
+fn parse_wpkh_key(descriptor: &str) -> Result<DescriptorKey, WalletError> {
+    let key_expression = strip_checksum(descriptor)
+        .trim()
+        .strip_prefix("wpkh(")
+        .and_then(|rest| rest.strip_suffix(')'))
+        .ok_or_else(|| {
+            WalletError::UnsupportedDescriptor("only wpkh descriptors are supported".to_string())
+        })?;
+    // The key origin is informational only.
+    let key_expression = match key_expression.strip_prefix('[') {
+        Some(rest) => rest.split_once(']').map(|(_, key)| key).ok_or_else(|| {
+            WalletError::UnsupportedDescriptor("unterminated key origin".to_string())
+        })?,
+        None => key_expression,
+    };
+
+    let mut steps = key_expression.split('/');
+    let key = steps.next().unwrap_or_default();
+    let steps = steps.collect::<Vec<_>>();
+
+    if let Ok(privkey) = PrivateKey::from_wif(key) {
+        if !steps.is_empty() {
+            return Err(WalletError::UnsupportedDescriptor(
+                "a WIF key has no derivation steps".to_string(),
+            ));
+        }
+        return Ok(DescriptorKey::Single(privkey));
+    }
+    let xpriv = Xpriv::from_str(key).map_err(|_| {
+        WalletError::UnsupportedDescriptor(
+            "the descriptor holds no private key, coinswap signs its inputs itself".to_string(),
+        )
+    })?;
+    let (ranged, steps) = match steps.split_last() {
+        Some((&"*", steps)) => (true, steps),
+        Some((last, _)) if last.starts_with('*') => {
+            return Err(WalletError::UnsupportedDescriptor(
+                "hardened wildcards aren't supported".to_string(),
+            ))
+        }
+        _ => (false, &steps[..]),
+    };
+    let path = steps
+        .iter()
+        .map(|step| ChildNumber::from_str(step))
+        .collect::<Result<Vec<_>, _>>()?;
+    let xpriv = xpriv.derive_priv(&Secp256k1::new(), &path)?;
+    Ok(if ranged {
+        DescriptorKey::Ranged(xpriv)
+    } else {
+        DescriptorKey::Single(PrivateKey::new(xpriv.private_key, xpriv.network))
+    })
+}
+
+impl Wallet {
+    /// Sets up a wallet at `path` over `descriptor`, the descriptor of a wallet created outside
+    /// coinswap. It must be a `wpkh` descriptor with its private key, see the
+    /// [module docs](self).
+    ///
+    /// The descriptor is validated with `getdescriptorinfo`, and imported into the core wallet
+    /// by the next [`Wallet::sync`]. The wallet has no birthday, so that sync rescans the whole
+    /// chain. Set one with [`Wallet::set_birthday`] if the descriptor's first use is known.
+    pub fn from_existing_descriptor(
+        path: &PathBuf,
+        descriptor: &str,
+        rpc_config: &RPCConfig,
+    ) -> Result<Wallet, WalletError> {
+        let file_name = path
+            .file_name()
+            .expect("file name expected")
+            .to_str()
+            .expect("expected")
+            .to_string();
+        if rpc_config.wallet_name != file_name {
+            return Err(WalletError::Protocol(format!(
+                "Wallet name of database file and core missmatch, expected {}, found {}",
+                rpc_config.wallet_name, file_name
+            )));
+        }
+
+        // Checked without the node. Not wrapped in a descriptor error, as it holds private keys.
+        if descriptor.contains('#') {
+            validate_descriptor_checksum(descriptor)?;
+        }
+        let rpc = Arc::new(Client::try_from(rpc_config)?);
+        let info = rpc.get_descriptor_info(descriptor)?;
+        if !info.has_private_keys {
+            return Err(WalletError::UnsupportedDescriptor(
+                "the descriptor holds no private key, coinswap signs its inputs itself".to_string(),
+            ));
+        }
+        let existing_descriptor =
+            ExistingDescriptor::new(descriptor, info.is_range, rpc_config.network)?;
+
+        let master_key = Xpriv::new_master(rpc_config.network, &existing_descriptor.master_seed())?;
+        let mut store = WalletStore::init(file_name, path, rpc_config.network, master_key, None)?;
+        store.existing_descriptor = Some(existing_descriptor);
+
+        let wallet = Self {
+            backend: Arc::new(CoreRpcBackend::new(rpc.clone(), rpc_config.clone())),
+            rpc: Some(rpc),
+            rpc_config: rpc_config.clone(),
+            wallet_file_path: path.clone(),
+            store,
+            store_key: None,
+        };
+        wallet.save_to_disk()?;
+        Ok(wallet)
+    }
+
+    /// Spend info of a coin of the existing descriptor, which Core reports with `descriptor`.
+    pub(super) fn existing_descriptor_spend_info(
+        &self,
+        descriptor: &str,
+        script_pubkey: &Script,
+        input_value: Amount,
+    ) -> Option<UTXOSpendInfo> {
+        let index = self
+            .store
+            .existing_descriptor
+            .as_ref()?
+            .coin_index(descriptor, script_pubkey)?;
+        Some(UTXOSpendInfo::ExistingDescriptorCoin { index, input_value })
+    }
+
+    /// The existing descriptor to import into the core wallet, if it isn't imported up to the
+    /// full index range yet. `imported` as in [`Wallet::get_unimported_wallet_desc`].
+    pub(super) fn unimported_existing_descriptor(
+        &self,
+        imported: &HashMap<String, Option<u32>>,
+    ) -> Option<String> {
+        let existing_descriptor = self.store.existing_descriptor.as_ref()?;
+        let last_index = self.get_addrss_import_count() - 1;
+        let is_imported = match imported.get(strip_checksum(&existing_descriptor.public_descriptor))
+        {
+            Some(Some(range_end)) => *range_end >= last_index,
+            Some(None) => !existing_descriptor.is_ranged(),
+            None => false,
+        };
+        (!is_imported).then(|| existing_descriptor.public_descriptor.clone())
+    }
+
+    /// Moves the next unused index of a ranged existing descriptor past its coins.
+    pub(super) fn update_existing_descriptor_index(&mut self) -> Result<(), WalletError> {
+        let next_index = self
+            .list_descriptor_utxo_spend_info(None)?
+            .iter()
+            .filter_map(|(_, spend_info)| match spend_info {
+                UTXOSpendInfo::ExistingDescriptorCoin {
+                    index: Some(index), ..
+                } => Some(index + 1),
+                _ => None,
+            })
+            .max();
+        if let (Some(existing_descriptor), Some(next_index)) =
+            (self.store.existing_descriptor.as_mut(), next_index)
+        {
+            existing_descriptor.next_index = existing_descriptor.next_index.max(next_index);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::bip32::DerivationPath;
+
+    fn tprv() -> Xpriv {
+        Xpriv::new_master(Network::Regtest, &[1; 32]).unwrap()
+    }
+
+    fn derive(path: &str) -> Xpriv {
+        tprv()
+            .derive_priv(&Secp256k1::new(), &DerivationPath::from_str(path).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_wpkh_key() {
+        let ranged = parse_wpkh_key(&format!(
+            "wpkh([d34db33f/84h/1h/0h]{}/84'/1'/0'/0/*)#abcdefgh",
+            tprv()
+        ))
+        .unwrap();
+        assert_eq!(ranged, DescriptorKey::Ranged(derive("m/84'/1'/0'/0")));
+
+        let fixed = parse_wpkh_key(&format!("wpkh({}/1h/2)", tprv())).unwrap();
+        let privkey = PrivateKey::new(derive("m/1'/2").private_key, NetworkKind::Test);
+        assert_eq!(fixed, DescriptorKey::Single(privkey));
+        assert_eq!(
+            parse_wpkh_key(&format!("wpkh({})", privkey.to_wif())).unwrap(),
+            DescriptorKey::Single(privkey)
+        );
+
+        for unsupported in [
+            format!("pkh({}/0/*)", tprv()),
+            format!("wpkh({}/0/*')", tprv()),
+            format!("wpkh({}/0)", privkey.to_wif()),
+            format!("wpkh({}/0/*)", Xpub::from_priv(&Secp256k1::new(), &tprv())),
+        ] {
+            assert!(matches!(
+                parse_wpkh_key(&unsupported),
+                Err(WalletError::UnsupportedDescriptor(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_existing_descriptor() {
+        let ranged =
+            ExistingDescriptor::new(&format!("wpkh({}/0/*)", tprv()), true, Network::Regtest)
+                .unwrap();
+        let account_xpub = Xpub::from_priv(&Secp256k1::new(), &derive("m/0"));
+        assert!(ranged
+            .public_descriptor
+            .starts_with(&format!("wpkh({}/*)#", account_xpub)));
+
+        let spk = ranged.script_pubkey(Some(5)).unwrap();
+        assert_eq!(
+            ranged.coin_index("wpkh([d34db33f/5]02aa)#checksum", &spk),
+            Some(Some(5))
+        );
+        assert_eq!(
+            ranged.coin_index("wpkh([d34db33f/6]02aa)#checksum", &spk),
+            None
+        );
+
+        // Same key as the ranged descriptor's index 5.
+        let fixed =
+            ExistingDescriptor::new(&format!("wpkh({}/0/5)", tprv()), false, Network::Regtest)
+                .unwrap();
+        assert_eq!(
+            fixed.coin_index("wpkh([d34db33f/0/5]02aa)", &spk),
+            Some(None)
+        );
+        assert!(fixed.privkey(Some(5)).is_err());
+
+        // Test key on mainnet, and ranged descriptor reported as fixed.
+        assert!(
+            ExistingDescriptor::new(&format!("wpkh({}/0/*)", tprv()), true, Network::Bitcoin)
+                .is_err()
+        );
+        assert!(
+            ExistingDescriptor::new(&format!("wpkh({}/0/*)", tprv()), false, Network::Regtest)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! Fee bumping of stuck wallet transactions.
+//!
+//! Only transactions the wallet can rebuild on its own are replaced (RBF): spends of seed coins and
+//! of completed swapcoins. Swap funding transactions are never replaced, as the contract transactions
+//! pre-signed by the counterparties spend them by txid. Contract and fidelity bond spends aren't
+//! either, their timelocks are fixed when they're signed.
+//!
+//! Any transaction paying to the wallet can be accelerated with a child instead (CPFP), which
+//! leaves its txid untouched.
+
+use bitcoin::{
+    absolute::LockTime, transaction::Version, Address, Amount, OutPoint, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid, Witness,
+};
+use bitcoind::bitcoincore_rpc::RpcApi;
+use serde_json::Value;
+
+use crate::utill::redeemscript_to_scriptpubkey;
+
+use super::{
+    api::KeychainKind,
+    error::WalletError,
+    fee_estimation::{FeeEstimator, FEE_BUMP_CONFIRMATION_TARGET},
+    UTXOSpendInfo, Wallet,
+};
+
+/// Minimum fee rate increase of a replacement, in sats/vbyte. Core's default `-incrementalrelayfee`.
+const INCREMENTAL_RELAY_FEE_RATE: f64 = 1.0;
+
+impl Wallet {
+    /// Replaces an unconfirmed wallet transaction with one paying the rate `fee_estimator` gives
+    /// for the [`FEE_BUMP_CONFIRMATION_TARGET`]. The extra fee is taken from the transaction's change, or else from another output paying to
+    /// the wallet. Returns the txid of the broadcasted replacement.
+    ///
+    /// Errors if the transaction doesn't signal RBF, funds a swap or a fidelity bond, or spends
+    /// anything else than seed coins and completed swapcoins.
+    pub fn bump_fee(
+        &mut self,
+        txid: Txid,
+        fee_estimator: &dyn FeeEstimator,
+    ) -> Result<Txid, WalletError> {
+        let new_fee_rate = fee_estimator.estimate_sat_per_vb(FEE_BUMP_CONFIRMATION_TARGET)?;
+        let wallet_tx = self.core_rpc()?.get_transaction(&txid, None)?;
+        if wallet_tx.info.confirmations != 0 {
+            return Err(WalletError::Protocol(format!(
+                "Transaction {} is already confirmed",
+                txid
+            )));
+        }
+        let mut tx = wallet_tx.transaction()?;
+        if !tx.is_explicitly_rbf() {
+            return Err(WalletError::Protocol(format!(
+                "Transaction {} doesn't signal RBF",
+                txid
+            )));
+        }
+        self.check_replaceable_outputs(&tx)?;
+
+        let (prevouts, spend_infos) = self.replaceable_inputs(&tx)?;
+        let input_value = prevouts.iter().map(|o| o.value).sum::<Amount>();
+        let output_value = tx.output.iter().map(|o| o.value).sum::<Amount>();
+        let old_fee = input_value - output_value;
+
+        let vsize = tx.vsize();
+        let new_fee = Amount::from_sat((vsize as f64 * new_fee_rate).ceil() as u64);
+        let min_fee =
+            old_fee + Amount::from_sat((vsize as f64 * INCREMENTAL_RELAY_FEE_RATE).ceil() as u64);
+        if new_fee < min_fee {
+            return Err(WalletError::Protocol(format!(
+                "Replacement fee {} is below the minimum of {}",
+                new_fee, min_fee
+            )));
+        }
+
+        let change_index = self.fee_paying_output(&tx)?;
+        let change = &mut tx.output[change_index];
+        let extra_fee = new_fee - old_fee;
+        if change.value < extra_fee + change.script_pubkey.minimal_non_dust() {
+            return Err(WalletError::InsufficientFund {
+                available: change.value.to_sat(),
+                required: (extra_fee + change.script_pubkey.minimal_non_dust()).to_sat(),
+            });
+        }
+        change.value -= extra_fee;
+
+        for input in tx.input.iter_mut() {
+            input.witness = Witness::new();
+        }
+        self.sign_transaction(&mut tx, spend_infos.into_iter())?;
+        let new_txid = self.backend.broadcast(&tx)?;
+        log::info!(
+            "Replaced {} by {} | fee: {} -> {}",
+            txid,
+            new_txid,
+            old_fee,
+            new_fee
+        );
+
+        Ok(new_txid)
+    }
+
+    /// Accelerates an unconfirmed transaction by spending one of its outputs paying to the wallet,
+    /// in a child paying enough fee for the whole package to reach the rate `fee_estimator` gives
+    /// for the [`FEE_BUMP_CONFIRMATION_TARGET`].
+    /// The package includes the parent's own unconfirmed ancestors. Returns the child's txid.
+    ///
+    /// The largest output the wallet can spend alone is used: our change, or our side of a swap
+    /// once we hold both of its keys.
+    pub fn create_cpfp_child(
+        &mut self,
+        parent_txid: Txid,
+        fee_estimator: &dyn FeeEstimator,
+    ) -> Result<Txid, WalletError> {
+        let target_fee_rate = fee_estimator.estimate_sat_per_vb(FEE_BUMP_CONFIRMATION_TARGET)?;
+        let parent_entry = self.core_rpc()?.get_mempool_entry(&parent_txid)?;
+        let parent = self.core_rpc()?.get_raw_transaction(&parent_txid, None)?;
+
+        let mut owned_outputs = Vec::new();
+        for (vout, output) in parent.output.iter().enumerate() {
+            if let Some(spend_info) = self.owned_output_spend_info(output)? {
+                owned_outputs.push((vout as u32, output.value, spend_info));
+            }
+        }
+        let (vout, value, spend_info) = owned_outputs
+            .into_iter()
+            .max_by_key(|(_, value, _)| *value)
+            .ok_or_else(|| {
+                WalletError::Protocol(format!(
+                    "Transaction {} has no output the wallet can spend",
+                    parent_txid
+                ))
+            })?;
+
+        let destination = self.get_next_internal_addresses(1)?[0].script_pubkey();
+        let child = |fee: Amount| -> Result<Transaction, WalletError> {
+            let mut tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(parent_txid, vout),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                }],
+                output: vec![TxOut {
+                    script_pubkey: destination.clone(),
+                    value: value.checked_sub(fee).unwrap_or(Amount::ZERO),
+                }],
+            };
+            self.sign_transaction(&mut tx, std::iter::once(spend_info.clone()))?;
+            Ok(tx)
+        };
+
+        // Sign once with no fee to get the final size, then again with the actual fee.
+        let child_vsize = child(Amount::ZERO)?.vsize() as u64;
+        let fee = cpfp_child_fee(
+            parent_entry.fees.ancestor,
+            parent_entry.ancestor_size,
+            child_vsize,
+            target_fee_rate,
+        );
+        if value < fee + destination.minimal_non_dust() {
+            return Err(WalletError::InsufficientFund {
+                available: value.to_sat(),
+                required: (fee + destination.minimal_non_dust()).to_sat(),
+            });
+        }
+
+        let child_tx = child(fee)?;
+        let child_txid = self.backend.broadcast(&child_tx)?;
+        log::info!(
+            "Broadcasted CPFP child {} of {} | fee: {} | package fee rate: {:.2} sats/vbyte",
+            child_txid,
+            parent_txid,
+            fee,
+            package_fee_rate(
+                parent_entry.fees.ancestor + fee,
+                parent_entry.ancestor_size + child_vsize,
+            )
+        );
+
+        Ok(child_txid)
+    }
+
+    /// Errors if replacing `tx` would invalidate a swap contract or a fidelity bond.
+    fn check_replaceable_outputs(&self, tx: &Transaction) -> Result<(), WalletError> {
+        for output in &tx.output {
+            let funds_swap = self
+                .store
+                .outgoing_swapcoins
+                .keys()
+                .any(|multisig| redeemscript_to_scriptpubkey(multisig) == output.script_pubkey);
+            if funds_swap {
+                return Err(WalletError::Protocol(format!(
+                    "Transaction {} funds a swap, replacing it would invalidate the contracts",
+                    tx.compute_txid()
+                )));
+            }
+            let funds_bond = self
+                .store
+                .fidelity_bond
+                .values()
+                .any(|(_, spk, _)| *spk == output.script_pubkey);
+            if funds_bond {
+                return Err(WalletError::Protocol(format!(
+                    "Transaction {} funds a fidelity bond",
+                    tx.compute_txid()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The outputs spent by `tx`, and their spend info. Errors unless they're all seed coins or
+    /// completed swapcoins.
+    fn replaceable_inputs(
+        &self,
+        tx: &Transaction,
+    ) -> Result<(Vec<TxOut>, Vec<UTXOSpendInfo>), WalletError> {
+        let mut prevouts = Vec::new();
+        let mut spend_infos = Vec::new();
+        for input in &tx.input {
+            if input.sequence.is_relative_lock_time() && input.sequence != Sequence::ZERO {
+                return Err(WalletError::Protocol(format!(
+                    "Input {} is timelocked",
+                    input.previous_output
+                )));
+            }
+            let prevout = self
+                .core_rpc()?
+                .get_transaction(&input.previous_output.txid, None)?
+                .transaction()?
+                .output
+                .get(input.previous_output.vout as usize)
+                .cloned()
+                .ok_or_else(|| {
+                    WalletError::Protocol(format!("Unknown input {}", input.previous_output))
+                })?;
+            let spend_info = self.owned_output_spend_info(&prevout)?.ok_or_else(|| {
+                WalletError::Protocol(format!(
+                    "Input {} is not a seed coin or a completed swapcoin",
+                    input.previous_output
+                ))
+            })?;
+            prevouts.push(prevout);
+            spend_infos.push(spend_info);
+        }
+        Ok((prevouts, spend_infos))
+    }
+
+    /// Index of the output paying the extra fee: the change, or else any output paying to the wallet.
+    fn fee_paying_output(&self, tx: &Transaction) -> Result<usize, WalletError> {
+        let internal_path = format!("m/{}/", KeychainKind::Internal.index_num());
+        let mut owned = None;
+        for (index, output) in tx.output.iter().enumerate() {
+            match self.owned_output_spend_info(output)? {
+                Some(UTXOSpendInfo::SeedCoin { path, .. }) if path.starts_with(&internal_path) => {
+                    return Ok(index)
+                }
+                Some(_) => owned = owned.or(Some(index)),
+                None => {}
+            }
+        }
+        owned.ok_or_else(|| {
+            WalletError::Protocol(format!(
+                "Transaction {} has no output paying to the wallet",
+                tx.compute_txid()
+            ))
+        })
+    }
+
+    /// Spend info of an output, if the wallet can spend it alone: a seed coin, or an incoming
+    /// swapcoin with both keys.
+    pub(super) fn owned_output_spend_info(
+        &self,
+        output: &TxOut,
+    ) -> Result<Option<UTXOSpendInfo>, WalletError> {
+        let swapcoin = self.store.incoming_swapcoins.iter().find(|(multisig, sc)| {
+            redeemscript_to_scriptpubkey(multisig) == output.script_pubkey
+                && sc.other_privkey.is_some()
+        });
+        if let Some((multisig, _)) = swapcoin {
+            return Ok(Some(UTXOSpendInfo::SwapCoin {
+                multisig_redeemscript: multisig.clone(),
+            }));
+        }
+
+        let address = match Address::from_script(&output.script_pubkey, self.store.network) {
+            Ok(address) => address,
+            Err(_) => return Ok(None),
+        };
+        let info: Value = self
+            .core_rpc()?
+            .call("getaddressinfo", &[address.to_string().into()])?;
+        if info["ismine"].as_bool() != Some(true) {
+            return Ok(None);
+        }
+        Ok(info["desc"].as_str().and_then(|desc| {
+            self.seed_coin_spend_info(desc, output.value).or_else(|| {
+                self.existing_descriptor_spend_info(desc, &output.script_pubkey, output.value)
+            })
+        }))
+    }
+}
+
+/// Fee rate of a package of transactions, in sats/vbyte.
+pub(super) fn package_fee_rate(total_fee: Amount, total_vsize: u64) -> f64 {
+    total_fee.to_sat() as f64 / total_vsize.max(1) as f64
+}
+
+/// Fee a child of `child_vsize` must pay to bring its unconfirmed ancestors, paying `ancestors_fee`
+/// over `ancestors_vsize`, to `target_fee_rate` (sats/vbyte). Never below the minimum relay fee of
+/// the child alone.
+pub(super) fn cpfp_child_fee(
+    ancestors_fee: Amount,
+    ancestors_vsize: u64,
+    child_vsize: u64,
+    target_fee_rate: f64,
+) -> Amount {
+    let package_fee =
+        Amount::from_sat(((ancestors_vsize + child_vsize) as f64 * target_fee_rate).ceil() as u64);
+    let min_relay_fee =
+        Amount::from_sat((child_vsize as f64 * INCREMENTAL_RELAY_FEE_RATE).ceil() as u64);
+    package_fee
+        .checked_sub(ancestors_fee)
+        .unwrap_or(Amount::ZERO)
+        .max(min_relay_fee)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpfp_child_fee() {
+        // A 200 vbyte parent paying 1 sat/vbyte, bumped to 10 sats/vbyte with a 110 vbyte child.
+        let fee = cpfp_child_fee(Amount::from_sat(200), 200, 110, 10.0);
+        assert_eq!(fee, Amount::from_sat(2900));
+        assert_eq!(package_fee_rate(Amount::from_sat(200) + fee, 310), 10.0);
+
+        // A parent already above the target still needs the child to pay its own relay fee.
+        let fee = cpfp_child_fee(Amount::from_sat(10_000), 200, 110, 10.0);
+        assert_eq!(fee, Amount::from_sat(110));
+
+        assert_eq!(package_fee_rate(Amount::from_sat(500), 0), 500.0);
+    }
+}
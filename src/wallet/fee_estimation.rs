@@ -0,0 +1,237 @@
+//! Fee rate estimation, from the node or from an external fee API.
+//!
+//! The wallet's transaction builders ask a [`FeeEstimator`] for their fee rate. Sends, sweeps and
+//! fidelity bond redeems target [`DEFAULT_CONFIRMATION_TARGET`] blocks, fee bumps and CPFP children
+//! [`FEE_BUMP_CONFIRMATION_TARGET`]. A [`StaticFeeEstimator`] pays a given rate. Swap funding
+//! transactions pay the absolute fees negotiated with the makers instead, and don't use an estimator.
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use bitcoin::FeeRate;
+use bitcoind::bitcoincore_rpc::{Client, RpcApi};
+use serde::Deserialize;
+use socks::Socks5Stream;
+
+use super::error::WalletError;
+
+/// Confirmation target of the transactions built by the wallet, in blocks.
+pub const DEFAULT_CONFIRMATION_TARGET: u16 = 6;
+
+/// Confirmation target of fee bumps and CPFP children, in blocks. They're built to get a stuck
+/// transaction confirmed in the next block.
+pub const FEE_BUMP_CONFIRMATION_TARGET: u16 = 1;
+
+/// Fee rate to fall back to when no estimate is available, 2 sats/vbyte.
+pub const FALLBACK_FEE_RATE: FeeRate = FeeRate::from_sat_per_kwu(500);
+
+/// Source of fee rates.
+pub trait FeeEstimator {
+    /// Fee rate for a transaction to confirm within `target_blocks` blocks.
+    fn estimate(&self, target_blocks: u16) -> Result<FeeRate, WalletError>;
+
+    /// Same as [`FeeEstimator::estimate`], in sats/vbyte.
+    fn estimate_sat_per_vb(&self, target_blocks: u16) -> Result<f64, WalletError> {
+        Ok(self.estimate(target_blocks)?.to_sat_per_kwu() as f64 * 4.0 / 1000.0)
+    }
+}
+
+/// Bitcoin Core's `estimatesmartfee`.
+///
+/// Errors if the node has no estimate yet, which is always the case on regtest. Combine it with a
+/// [`StaticFeeEstimator`] through a [`FallbackFeeEstimator`] to always get a rate.
+impl FeeEstimator for Client {
+    fn estimate(&self, target_blocks: u16) -> Result<FeeRate, WalletError> {
+        let result = self.estimate_smart_fee(target_blocks, None)?;
+        match result.fee_rate {
+            // Core reports BTC/kvB. 1 vbyte is 4 weight units.
+            Some(per_kvb) => Ok(FeeRate::from_sat_per_kwu(per_kvb.to_sat() / 4)),
+            None => Err(WalletError::FeeEstimation(format!(
+                "estimatesmartfee has no estimate for {} blocks: {:?}",
+                target_blocks,
+                result.errors.unwrap_or_default()
+            ))),
+        }
+    }
+}
+
+/// Always returns the same fee rate, whatever the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaticFeeEstimator(pub FeeRate);
+
+impl StaticFeeEstimator {
+    /// A rate of `sat_per_vb` sats/vbyte, rounded up to the next sat per 1000 weight units.
+    pub fn from_sat_per_vb(sat_per_vb: f64) -> Self {
+        // 1 vbyte is 4 weight units.
+        Self(FeeRate::from_sat_per_kwu((sat_per_vb * 250.0).ceil() as u64))
+    }
+}
+
+impl FeeEstimator for StaticFeeEstimator {
+    fn estimate(&self, _target_blocks: u16) -> Result<FeeRate, WalletError> {
+        Ok(self.0)
+    }
+}
+
+/// Asks each estimator in turn, and returns the first estimate found.
+pub struct FallbackFeeEstimator<'a>(pub Vec<&'a dyn FeeEstimator>);
+
+impl FeeEstimator for FallbackFeeEstimator<'_> {
+    fn estimate(&self, target_blocks: u16) -> Result<FeeRate, WalletError> {
+        let mut last_error = None;
+        for estimator in &self.0 {
+            match estimator.estimate(target_blocks) {
+                Ok(fee_rate) => return Ok(fee_rate),
+                Err(e) => {
+                    log::debug!("Fee estimator failed, trying the next one: {:?}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            WalletError::FeeEstimation("No fee estimator configured".to_string())
+        }))
+    }
+}
+
+/// Path of the recommended fees in the mempool.space API.
+const RECOMMENDED_FEES_PATH: &str = "/api/v1/fees/recommended";
+
+/// The mempool.space recommended fees API, or any instance of it.
+///
+/// Requests are plain HTTP, optionally through the Tor socks proxy. Use an onion service, or a
+/// local instance, as HTTPS is not supported.
+#[derive(Debug, Clone)]
+pub struct MempoolSpaceFeeEstimator {
+    /// `host:port` of the API.
+    pub host: String,
+    /// Socks port of the Tor proxy to connect through. Connects directly if `None`.
+    pub socks_port: Option<u16>,
+    /// Timeout for reading the response.
+    pub timeout: Duration,
+}
+
+/// Response of the recommended fees API, in sats/vbyte.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecommendedFees {
+    fastest_fee: u64,
+    half_hour_fee: u64,
+    hour_fee: u64,
+    economy_fee: u64,
+}
+
+impl RecommendedFees {
+    /// The recommendation covering `target_blocks`, with blocks 10 minutes apart.
+    fn for_target(&self, target_blocks: u16) -> FeeRate {
+        let sat_per_vb = match target_blocks {
+            0..=1 => self.fastest_fee,
+            2..=3 => self.half_hour_fee,
+            4..=6 => self.hour_fee,
+            _ => self.economy_fee,
+        };
+        // 1 vbyte is 4 weight units.
+        FeeRate::from_sat_per_kwu(sat_per_vb.saturating_mul(250))
+    }
+}
+
+impl MempoolSpaceFeeEstimator {
+    /// Fetches the recommended fees with an HTTP/1.0 GET, so the response is never chunked.
+    fn fetch(&self) -> Result<RecommendedFees, WalletError> {
+        let mut stream = match self.socks_port {
+            Some(port) => Socks5Stream::connect(format!("127.0.0.1:{}", port), self.host.as_str())?
+                .into_inner(),
+            None => TcpStream::connect(&self.host)?,
+        };
+        stream.set_read_timeout(Some(self.timeout))?;
+        write!(
+            stream,
+            "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\n\r\n",
+            RECOMMENDED_FEES_PATH, self.host
+        )?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        parse_http_response(&response)
+    }
+}
+
+impl FeeEstimator for MempoolSpaceFeeEstimator {
+    fn estimate(&self, target_blocks: u16) -> Result<FeeRate, WalletError> {
+        Ok(self.fetch()?.for_target(target_blocks))
+    }
+}
+
+fn parse_http_response(response: &[u8]) -> Result<RecommendedFees, WalletError> {
+    let response = String::from_utf8_lossy(response);
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| WalletError::FeeEstimation("Malformed fee API response".to_string()))?;
+    let status_line = head.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(WalletError::FeeEstimation(format!(
+            "Fee API request failed: {}",
+            status_line
+        )));
+    }
+    Ok(serde_json::from_str(body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoEstimate;
+
+    impl FeeEstimator for NoEstimate {
+        fn estimate(&self, _target_blocks: u16) -> Result<FeeRate, WalletError> {
+            Err(WalletError::FeeEstimation("no estimate".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_static_and_fallback_estimators() {
+        let fallback = StaticFeeEstimator(FALLBACK_FEE_RATE);
+        assert_eq!(fallback.estimate_sat_per_vb(1).unwrap(), 2.0);
+        assert_eq!(StaticFeeEstimator::from_sat_per_vb(2.0), fallback);
+        assert_eq!(
+            StaticFeeEstimator::from_sat_per_vb(1.5)
+                .estimate_sat_per_vb(6)
+                .unwrap(),
+            1.5
+        );
+
+        let estimator = FallbackFeeEstimator(vec![&NoEstimate, &fallback]);
+        assert_eq!(estimator.estimate(6).unwrap(), fallback.0);
+
+        assert!(matches!(
+            FallbackFeeEstimator(vec![&NoEstimate]).estimate(6),
+            Err(WalletError::FeeEstimation(_))
+        ));
+        assert!(FallbackFeeEstimator(vec![]).estimate(6).is_err());
+    }
+
+    #[test]
+    fn test_parse_recommended_fees() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n\
+            {\"fastestFee\":25,\"halfHourFee\":20,\"hourFee\":12,\"economyFee\":4,\"minimumFee\":1}";
+        let fees = parse_http_response(response).unwrap();
+        let sat_per_vb = |target| fees.for_target(target).to_sat_per_vb_ceil();
+        assert_eq!(sat_per_vb(1), 25);
+        assert_eq!(sat_per_vb(3), 20);
+        assert_eq!(sat_per_vb(6), 12);
+        assert_eq!(sat_per_vb(144), 4);
+
+        assert!(matches!(
+            parse_http_response(b"HTTP/1.1 503 Service Unavailable\r\n\r\n"),
+            Err(WalletError::FeeEstimation(_))
+        ));
+        assert!(matches!(
+            parse_http_response(b"HTTP/1.1 200 OK\r\n\r\nnot json"),
+            Err(WalletError::Json(_))
+        ));
+    }
+}
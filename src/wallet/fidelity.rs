@@ -2,7 +2,7 @@ use std::{
     collections::HashMap,
     str::FromStr,
     thread,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use bitcoin::{
@@ -16,16 +16,20 @@ use bitcoin::{
     Address, Amount, OutPoint, PublicKey, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
     Witness,
 };
-use bitcoind::bitcoincore_rpc::RpcApi;
+use bitcoind::bitcoincore_rpc::bitcoincore_rpc_json::GetBlockHeaderResult;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    protocol::messages::FidelityProof,
+    protocol::messages::{FidelityProof, Offer},
     utill::redeemscript_to_scriptpubkey,
     wallet::{UTXOSpendInfo, Wallet},
 };
 
-use super::WalletError;
+use super::{
+    backend::ChainBackend,
+    fee_estimation::{FeeEstimator, DEFAULT_CONFIRMATION_TARGET},
+    WalletError,
+};
 
 // To (strongly) disincentivize Sybil behavior, the value assessment of the bond
 // is based on the (time value of the bond)^x here x is the bond_value_exponent,
@@ -42,6 +46,12 @@ const BOND_VALUE_INTEREST_RATE: f64 = 0.015;
 /// Constant representing the derivation path for fidelity addresses.
 const FIDELITY_DERIVATION_PATH: &str = "m/84'/0'/0'/2";
 
+/// Expected time between two blocks, used to turn block heights into timestamps.
+const BLOCK_INTERVAL_SECS: u64 = 10 * 60;
+
+/// How long to wait for a bond's spending transaction to confirm, see [`wait_for_fidelity_spend`].
+pub(crate) const FIDELITY_SPEND_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
 /// Error structure defining possible fidelity related errors
 #[derive(Debug)]
 pub enum FidelityError {
@@ -49,8 +59,19 @@ pub enum FidelityError {
     BondAlreadyExists(u32),
     BondDoesNotExist,
     BondAlreadySpent,
+    /// The bond on-chain doesn't match the claimed one.
+    BondMismatch(&'static str),
     CertExpired,
-    InsufficientFund { available: u64, required: u64 },
+    InsufficientFund {
+        available: u64,
+        required: u64,
+    },
+    BondNotMatured {
+        lock_time: LockTime,
+        tip_height: u64,
+    },
+    /// The bond's spending transaction didn't confirm in time.
+    SpendNotConfirmed(Txid),
 }
 
 // ------- Fidelity Helper Scripts -------------
@@ -142,6 +163,50 @@ impl FidelityBond {
         redeemscript_to_scriptpubkey(&self.redeem_script())
     }
 
+    /// Score of the bond at the given chain tip height and current unix time, used to pick the bond to advertise.
+    ///
+    /// The score is [`calculate_fidelity_value`], the value takers assign to the bond. Heights are turned into
+    /// timestamps assuming a block every 10 minutes:
+    /// - confirmation time = current_time - (tip_height - conf_height) * 600
+    /// - locktime = current_time + (locktime_height - tip_height) * 600, or the locktime itself if time based.
+    ///
+    /// So the score grows with the bond amount and the remaining locktime, and drops to zero once the locktime passed.
+    pub fn score(&self, tip_height: u64, current_time: u64) -> Amount {
+        let blocks_to_secs = |blocks: u64| blocks.saturating_mul(BLOCK_INTERVAL_SECS);
+        let confirmation_time = current_time.saturating_sub(blocks_to_secs(
+            tip_height.saturating_sub(self.conf_height as u64),
+        ));
+        let locktime = match self.lock_time {
+            LockTime::Blocks(height) => {
+                let height = height.to_consensus_u32() as u64;
+                if height >= tip_height {
+                    current_time + blocks_to_secs(height - tip_height)
+                } else {
+                    current_time.saturating_sub(blocks_to_secs(tip_height - height))
+                }
+            }
+            LockTime::Seconds(time) => time.to_consensus_u32() as u64,
+        };
+
+        calculate_fidelity_value(
+            self.amount,
+            locktime.max(confirmation_time),
+            confirmation_time,
+            current_time,
+        )
+    }
+
+    /// Checks if the bond's locktime has passed, given the chain tip height and the current unix time.
+    ///
+    /// A spend of the bond is final in the next block, at `tip_height + 1`, for a height locktime.
+    /// A time locktime has to be strictly below the median time past (BIP113).
+    pub fn is_locktime_passed(&self, tip_height: u64, current_time: u64) -> bool {
+        match self.lock_time {
+            LockTime::Blocks(height) => tip_height >= height.to_consensus_u32() as u64,
+            LockTime::Seconds(time) => current_time > time.to_consensus_u32() as u64,
+        }
+    }
+
     /// Generate the bond's certificate hash.
     pub fn generate_cert_hash(&self, onion_addr: &str) -> sha256d::Hash {
         let cert_msg_str = format!(
@@ -164,6 +229,79 @@ impl Wallet {
         &self.store.fidelity_bond
     }
 
+    /// Total amount locked in the unspent fidelity bonds.
+    pub fn total_fidelity_value(&self) -> Amount {
+        self.store
+            .fidelity_bond
+            .values()
+            .filter(|(_, _, is_spent)| !is_spent)
+            .map(|(bond, _, _)| bond.amount)
+            .sum()
+    }
+
+    /// Unspent fidelity bonds whose locktime has passed at the chain tip, and can be redeemed.
+    /// Time based locktimes are compared against the tip's median time past, as consensus does.
+    pub fn expired_fidelity_bonds(&self) -> Result<Vec<&FidelityBond>, WalletError> {
+        let (tip_height, median_time) = self.chain_tip()?;
+        Ok(self
+            .store
+            .fidelity_bond
+            .values()
+            .filter(|(bond, _, is_spent)| {
+                !is_spent && bond.is_locktime_passed(tip_height, median_time)
+            })
+            .map(|(bond, _, _)| bond)
+            .collect())
+    }
+
+    /// Index of the fidelity bond to advertise at `tip_height`. This is the bond set with
+    /// [`Wallet::set_advertised_fidelity_bond`] if any, or else the unspent bond with the highest [`FidelityBond::score`].
+    pub fn best_fidelity_index(&self, tip_height: u64) -> Option<u32> {
+        if let Some(index) = self.store.advertised_fidelity_index {
+            if matches!(self.store.fidelity_bond.get(&index), Some((_, _, false))) {
+                return Some(index);
+            }
+            log::warn!(
+                "Advertised fidelity bond {} is spent or missing, picking the best bond instead",
+                index
+            );
+        }
+
+        let current_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("This can't error")
+            .as_secs();
+        self.store
+            .fidelity_bond
+            .iter()
+            .filter(|(_, (_, _, is_spent))| !is_spent)
+            .max_by_key(|(_, (bond, _, _))| bond.score(tip_height, current_time))
+            .map(|(index, _)| *index)
+    }
+
+    /// The fidelity bond to advertise at `tip_height`. See [`Wallet::best_fidelity_index`].
+    pub fn best_fidelity_bond(&self, tip_height: u64) -> Option<&FidelityBond> {
+        self.best_fidelity_index(tip_height)
+            .and_then(|index| self.store.fidelity_bond.get(&index))
+            .map(|(bond, _, _)| bond)
+    }
+
+    /// Advertise the fidelity bond at `index` instead of the highest scoring one. `None` removes the override.
+    pub fn set_advertised_fidelity_bond(&mut self, index: Option<u32>) -> Result<(), WalletError> {
+        if let Some(index) = index {
+            let (_, _, is_spent) = self
+                .store
+                .fidelity_bond
+                .get(&index)
+                .ok_or(FidelityError::BondDoesNotExist)?;
+            if *is_spent {
+                return Err(FidelityError::BondAlreadySpent.into());
+            }
+        }
+        self.store.advertised_fidelity_index = index;
+        self.save_to_disk()
+    }
+
     /// Get the highest value fidelity bond. Returns None, if no bond exists.
     pub fn get_highest_fidelity_index(&self) -> Result<Option<u32>, WalletError> {
         Ok(self
@@ -251,17 +389,12 @@ impl Wallet {
             .expect("This can't error")
             .as_secs();
 
-        let hash = self.rpc.get_block_hash(bond.conf_height as u64)?;
-
-        let confirmation_time = self.rpc.get_block_header_info(&hash)?.time as u64;
+        let confirmation_time = self.block_header_at(bond.conf_height as u64)?.time as u64;
 
         let locktime = match bond.lock_time {
             LockTime::Blocks(blocks) => {
-                let tip_hash = self.rpc.get_blockchain_info()?.best_block_hash;
-                let (tip_height, tip_time) = {
-                    let info = self.rpc.get_block_header_info(&tip_hash)?;
-                    (info.height, info.time as u64)
-                };
+                let tip_height = self.backend.get_block_count()?;
+                let tip_time = self.block_header_at(tip_height)?.time as u64;
                 // Estimated locktime from block height = [current-time + (maturity-height - block-count) * 10 * 60] sec
                 tip_time + (((blocks.to_consensus_u32() - (tip_height as u32)) * 10 * 60) as u64)
             }
@@ -290,10 +423,11 @@ impl Wallet {
         let mut swap_coin_utxo = self.list_swap_coin_utxo_spend_info(Some(&all_utxos))?;
         seed_coin_utxo.append(&mut swap_coin_utxo);
 
-        // Fetch utxos, filter out existing fidelity coins
+        // Fetch utxos, filter out existing fidelity coins and locked utxos
         let mut unspents = seed_coin_utxo
             .into_iter()
             .filter(|(_, spend_info)| !matches!(spend_info, UTXOSpendInfo::FidelityBondCoin { .. }))
+            .filter(|(utxo, _)| !self.is_utxo_locked(&OutPoint::new(utxo.txid, utxo.vout)))
             .collect::<Vec<_>>();
 
         unspents.sort_by(|a, b| b.0.amount.cmp(&a.0.amount));
@@ -350,7 +484,7 @@ impl Wallet {
                 script_pubkey: change_addrs,
             });
         }
-        let current_height = self.rpc.get_block_count()?;
+        let current_height = self.backend.get_block_count()?;
         let anti_fee_snipping_locktime = LockTime::from_height(current_height as u32)?;
 
         let mut tx = Transaction {
@@ -365,11 +499,11 @@ impl Wallet {
             .map(|(_, spend_info)| spend_info.clone());
         self.sign_transaction(&mut tx, &mut input_info)?;
 
-        let txid = self.rpc.send_raw_transaction(&tx)?;
+        let txid = self.backend.broadcast(&tx)?;
 
         let conf_height = loop {
-            if let Ok(get_tx_result) = self.rpc.get_transaction(&txid, None) {
-                if let Some(ht) = get_tx_result.info.blockheight {
+            if let Ok(Some((_, height))) = self.backend.get_wallet_transaction(&txid) {
+                if let Some(ht) = height {
                     log::info!("Fidelity Bond confirmed at blockheight: {}", ht);
                     break ht;
                 } else {
@@ -398,7 +532,7 @@ impl Wallet {
             amount,
             lock_time: locktime,
             pubkey: fidelity_pubkey,
-            conf_height,
+            conf_height: conf_height as u32,
             cert_expiry,
         };
 
@@ -407,6 +541,9 @@ impl Wallet {
         self.store
             .fidelity_bond
             .insert(index, (bond, bond_spk, false));
+        self.store
+            .fidelity_confirmations
+            .insert(index, Some(conf_height));
 
         Ok(index)
     }
@@ -415,6 +552,82 @@ impl Wallet {
     /// This functions creates a spending transaction, signs and broadcasts it.
     /// Upon confirmation it marks the bond as `spent` in the wallet data.
     pub fn redeem_fidelity(&mut self, index: u32) -> Result<Txid, WalletError> {
+        // Fixed fee. Use `redeem_fidelity_bond` to pay a given fee rate.
+        let fee = Amount::from_sat(1000);
+
+        let tx = self.create_fidelity_spend(index, fee)?;
+        let txid = self.backend.broadcast(&tx)?;
+        self.confirm_fidelity_spend(index, txid)?;
+
+        Ok(txid)
+    }
+
+    /// Redeem a matured Fidelity Bond back to an internal address, paying the rate `fee_estimator`
+    /// gives for the [`DEFAULT_CONFIRMATION_TARGET`].
+    /// Errors with [`FidelityError::BondNotMatured`] if the bond's locktime hasn't passed yet.
+    /// Upon confirmation it marks the bond as `spent` in the wallet data.
+    pub fn redeem_fidelity_bond(
+        &mut self,
+        bond_index: u32,
+        fee_estimator: &dyn FeeEstimator,
+    ) -> Result<Txid, WalletError> {
+        let txid = self.broadcast_fidelity_redemption(bond_index, fee_estimator)?;
+        self.confirm_fidelity_spend(bond_index, txid)?;
+
+        Ok(txid)
+    }
+
+    /// Broadcast the redemption of [`Wallet::redeem_fidelity_bond`], without waiting for it to
+    /// confirm. The bond is left unspent in the wallet data.
+    pub(crate) fn broadcast_fidelity_redemption(
+        &mut self,
+        bond_index: u32,
+        fee_estimator: &dyn FeeEstimator,
+    ) -> Result<Txid, WalletError> {
+        let (bond, _, _) = self
+            .store
+            .fidelity_bond
+            .get(&bond_index)
+            .ok_or(FidelityError::BondDoesNotExist)?;
+
+        let (tip_height, median_time) = self.chain_tip()?;
+        if !bond.is_locktime_passed(tip_height, median_time) {
+            return Err(FidelityError::BondNotMatured {
+                lock_time: bond.lock_time,
+                tip_height,
+            }
+            .into());
+        }
+        let bond_amount = bond.amount;
+        let fee_rate = fee_estimator.estimate_sat_per_vb(DEFAULT_CONFIRMATION_TARGET)?;
+
+        // Sign once with no fee to get the final size, then again with the actual fee.
+        let vsize = self
+            .create_fidelity_spend(bond_index, Amount::ZERO)?
+            .vsize();
+        let fee = Amount::from_sat((vsize as f64 * fee_rate).ceil() as u64);
+        if fee >= bond_amount {
+            return Err(FidelityError::InsufficientFund {
+                available: bond_amount.to_sat(),
+                required: fee.to_sat(),
+            }
+            .into());
+        }
+
+        let tx = self.create_fidelity_spend(bond_index, fee)?;
+        let txid = self.backend.broadcast(&tx)?;
+        log::info!(
+            "Broadcasted fidelity redemption {} | fee: {} | vsize: {}",
+            txid,
+            fee,
+            vsize
+        );
+
+        Ok(txid)
+    }
+
+    /// Create and sign the transaction spending the fidelity bond at `index` to an internal address.
+    fn create_fidelity_spend(&self, index: u32, fee: Amount) -> Result<Transaction, WalletError> {
         let (bond, _, is_spent) = self
             .store
             .fidelity_bond
@@ -433,9 +646,6 @@ impl Wallet {
             witness: Witness::new(),
         };
 
-        // TODO take feerate as user input
-        let fee = Amount::from_sat(1000);
-
         let change_addr = &self.get_next_internal_addresses(1)?[0];
 
         let txout = TxOut {
@@ -457,51 +667,25 @@ impl Wallet {
 
         self.sign_transaction(&mut tx, vec![utxo_spend_info].into_iter())?;
 
-        let txid = self.rpc.send_raw_transaction(&tx)?;
-
-        let conf_height = loop {
-            if let Ok(get_tx_result) = self.rpc.get_transaction(&txid, None) {
-                if let Some(ht) = get_tx_result.info.blockheight {
-                    log::info!("Fidelity Bond confirmed at blockheight: {}", ht);
-                    break ht;
-                } else {
-                    log::info!(
-                        "Fildelity Transaction {} seen in mempool, waiting for confirmation.",
-                        txid
-                    );
-
-                    if cfg!(feature = "integration-test") {
-                        thread::sleep(Duration::from_secs(1)); // wait for 1 sec in tests
-                    } else {
-                        thread::sleep(Duration::from_secs(60 * 10)); // wait for 10 mins in prod
-                    }
-
-                    continue;
-                }
-            } else {
-                log::info!("Waiting for {} in mempool", txid);
-                continue;
-            }
-        };
-
-        log::info!(
-            "Fidleity spend txid: {}, confirmed at height : {}",
-            txid,
-            conf_height
-        );
+        Ok(tx)
+    }
 
-        // mark is_spent
-        {
-            let (_, _, is_spent) = self
-                .store
-                .fidelity_bond
-                .get_mut(&index)
-                .ok_or(FidelityError::BondDoesNotExist)?;
+    /// Wait for the fidelity spending transaction to confirm, and mark the bond as `spent`.
+    fn confirm_fidelity_spend(&mut self, index: u32, txid: Txid) -> Result<(), WalletError> {
+        wait_for_fidelity_spend(self.backend.as_ref(), txid, FIDELITY_SPEND_TIMEOUT)?;
+        self.mark_fidelity_bond_spent(index)
+    }
 
-            *is_spent = true;
-        }
+    /// Mark the bond at `index` as `spent`.
+    pub(crate) fn mark_fidelity_bond_spent(&mut self, index: u32) -> Result<(), WalletError> {
+        let (_, _, is_spent) = self
+            .store
+            .fidelity_bond
+            .get_mut(&index)
+            .ok_or(FidelityError::BondDoesNotExist)?;
+        *is_spent = true;
 
-        Ok(txid)
+        Ok(())
     }
 
     /// Generate a [FidelityProof] for bond at a given index and a specific onion address.
@@ -538,27 +722,133 @@ impl Wallet {
         })
     }
 
-    /// Verify a [FidelityProof] received from the directory servers.
+    /// Sign the terms of an [Offer] with the key of its fidelity bond.
+    pub fn sign_offer(&self, offer: &mut Offer) -> Result<(), WalletError> {
+        let index = self
+            .store
+            .fidelity_bond
+            .iter()
+            .find(|(_, (bond, _, _))| *bond == offer.fidelity.bond)
+            .map(|(index, _)| *index)
+            .ok_or(FidelityError::BondDoesNotExist)?;
+
+        let fidelity_privkey = self.get_fidelity_keypair(index)?.secret_key();
+
+        let secp = Secp256k1::new();
+        offer.offer_sig = secp.sign_ecdsa(
+            &Message::from_digest(offer.terms_hash().to_byte_array()),
+            &fidelity_privkey,
+        );
+        Ok(())
+    }
+
+    /// Verify the [FidelityProof] of a maker's [Offer], advertised at `maker_addr`, against the
+    /// chain, and return the bond's value in sats.
+    ///
+    /// The offer must be signed by the bond's key, and the certificate unexpired and signed for
+    /// `maker_addr`. The bond output must be unspent with the claimed amount, script and
+    /// confirmation height. The value is the bond's timelock weighted [score](FidelityBond::score).
     pub fn verify_fidelity_proof(
         &self,
-        proof: &FidelityProof,
-        onion_addr: &str,
-    ) -> Result<(), WalletError> {
+        offer: &Offer,
+        maker_addr: &str,
+    ) -> Result<u64, WalletError> {
+        offer.verify_signature()?;
+
+        let proof = &offer.fidelity;
         if self.is_fidelity_expired(&proof.bond)? {
             return Err(FidelityError::CertExpired.into());
         }
 
         let cert_message =
-            Message::from_digest_slice(proof.bond.generate_cert_hash(onion_addr).as_byte_array())?;
-
+            Message::from_digest_slice(proof.bond.generate_cert_hash(maker_addr).as_byte_array())?;
         let secp = Secp256k1::new();
+        secp.verify_ecdsa(&cert_message, &proof.cert_sig, &proof.bond.pubkey.inner)?;
+
+        let bond = &proof.bond;
+        let (txout, conf_height) = self
+            .backend
+            .get_tx_out(&bond.outpoint)?
+            .ok_or(FidelityError::BondAlreadySpent)?;
+        if txout.value != bond.amount {
+            return Err(FidelityError::BondMismatch("amount").into());
+        }
+        if txout.script_pubkey != bond.script_pub_key() {
+            return Err(FidelityError::BondMismatch("script pubkey").into());
+        }
+        if conf_height != bond.conf_height as u64 {
+            return Err(FidelityError::BondMismatch("confirmation height").into());
+        }
 
-        Ok(secp.verify_ecdsa(&cert_message, &proof.cert_sig, &proof.bond.pubkey.inner)?)
+        let tip_height = self.backend.get_block_count()?;
+        let current_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("This can't error")
+            .as_secs();
+        Ok(bond.score(tip_height, current_time).to_sat())
+    }
+
+    /// Number of confirmations of the fidelity bond's funding transaction at `tip` height, as of the last sync.
+    /// Returns None if the bond is unknown, unconfirmed or got reorged out.
+    pub fn fidelity_bond_confirmations(&self, index: u32, tip: u64) -> Option<u64> {
+        let conf_height = (*self.store.fidelity_confirmations.get(&index)?)?;
+        tip.checked_sub(conf_height).map(|depth| depth + 1)
+    }
+
+    /// Refresh the confirmation heights of all unspent fidelity bonds from the node.
+    /// A bond whose funding transaction got reorged out is marked unconfirmed again.
+    pub(crate) fn refresh_fidelity_confirmations(&mut self) -> Result<(), WalletError> {
+        let conf_heights = self
+            .store
+            .fidelity_bond
+            .iter()
+            .filter(|(_, (_, _, is_spent))| !is_spent)
+            .map(|(index, (bond, _, _))| Ok((*index, self.confirmation_height(&bond.outpoint)?)))
+            .collect::<Result<Vec<_>, WalletError>>()?;
+
+        for (index, conf_height) in conf_heights {
+            if conf_height.is_none() {
+                log::warn!("Fidelity bond {} is not confirmed", index);
+            }
+            self.store.fidelity_confirmations.insert(index, conf_height);
+        }
+        Ok(())
+    }
+
+    /// Height the transaction of `outpoint` confirmed at, if it is still in the active chain.
+    fn confirmation_height(&self, outpoint: &OutPoint) -> Result<Option<u64>, WalletError> {
+        // Wallet transactions lose their block once reorged out.
+        if let Some((_, height)) = self.backend.get_wallet_transaction(&outpoint.txid)? {
+            return Ok(height);
+        }
+        // Not a wallet transaction, look at the unspent output instead.
+        Ok(self
+            .backend
+            .get_tx_out(outpoint)?
+            .map(|(_, conf_height)| conf_height))
+    }
+
+    /// Height and median time past of the chain tip. Timelocks are checked against the latter.
+    pub(crate) fn chain_tip(&self) -> Result<(u64, u64), WalletError> {
+        let tip_height = self.backend.get_block_count()?;
+        let median_time = self
+            .block_header_at(tip_height)?
+            .median_time
+            .ok_or_else(|| WalletError::Protocol("Tip header has no median time".to_string()))?;
+        Ok((tip_height, median_time as u64))
+    }
+
+    /// Header of the block at `height` in the active chain.
+    fn block_header_at(&self, height: u64) -> Result<GetBlockHeaderResult, WalletError> {
+        let hash = self.backend.get_block_hash(height)?;
+        self.backend
+            .get_block_header(&hash)?
+            .ok_or_else(|| WalletError::Protocol(format!("Block {} is unknown", hash)))
     }
 
     /// Calculate the expiry value. This depends on the current block height.
     pub fn get_fidelity_expiry(&self) -> Result<u64, WalletError> {
-        let current_height = self.rpc.get_block_count()?;
+        let current_height = self.backend.get_block_count()?;
         Ok((current_height + 2) /* safety buffer */ / 2016 + 5)
     }
 
@@ -580,7 +870,7 @@ impl Wallet {
     pub fn is_fidelity_expired(&self, bond: &FidelityBond) -> Result<bool, WalletError> {
         // Certificate has expired if current height more than the expiry difficulty period target
         // 1 difficulty period = 2016 blocks
-        let current_height = self.rpc.get_block_count()?;
+        let current_height = self.backend.get_block_count()?;
         if current_height > bond.cert_expiry * 2016 {
             Ok(true)
         } else {
@@ -589,9 +879,72 @@ impl Wallet {
     }
 }
 
+/// Wait for the fidelity spending transaction `txid` to confirm, returning its block height.
+///
+/// Only needs the wallet's [`ChainBackend`], so callers sharing the wallet don't have to lock it
+/// meanwhile. Errors with [`FidelityError::SpendNotConfirmed`] if it isn't confirmed within
+/// `timeout`.
+pub(crate) fn wait_for_fidelity_spend(
+    backend: &dyn ChainBackend,
+    txid: Txid,
+    timeout: Duration,
+) -> Result<u64, WalletError> {
+    let poll_interval = if cfg!(feature = "integration-test") {
+        Duration::from_secs(1) // wait for 1 sec in tests
+    } else {
+        Duration::from_secs(60 * 10) // wait for 10 mins in prod
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        match backend.get_wallet_transaction(&txid) {
+            Ok(Some((_, Some(conf_height)))) => {
+                log::info!(
+                    "Fidleity spend txid: {}, confirmed at height : {}",
+                    txid,
+                    conf_height
+                );
+                return Ok(conf_height);
+            }
+            Ok(Some((_, None))) => log::info!(
+                "Fildelity Transaction {} seen in mempool, waiting for confirmation.",
+                txid
+            ),
+            Ok(None) => log::info!("Waiting for {} in mempool", txid),
+            Err(e) => log::warn!("Error looking up fidelity spend {}: {:?}", txid, e),
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(FidelityError::SpendNotConfirmed(txid).into());
+        }
+        thread::sleep(poll_interval.min(remaining));
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::wallet::{CoreRpcBackend, RPCConfig};
+    use bitcoind::bitcoincore_rpc::Client;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_wait_for_fidelity_spend_times_out() {
+        // No node listens there, every lookup errors.
+        let rpc_config = RPCConfig::default();
+        let rpc = Arc::new(Client::new(&rpc_config.url, rpc_config.auth.clone()).unwrap());
+        let backend = CoreRpcBackend::new(rpc, rpc_config);
+        let txid = Txid::all_zeros();
+
+        let timeout = Duration::from_secs(2);
+        let start = Instant::now();
+        let result = wait_for_fidelity_spend(&backend, txid, timeout);
+        assert!(matches!(
+            result,
+            Err(WalletError::Fidelity(FidelityError::SpendNotConfirmed(t))) if t == txid
+        ));
+        assert!(start.elapsed() >= timeout);
+    }
 
     #[test]
     fn test_fidelity_bond_value_function_behavior() {
@@ -715,6 +1068,58 @@ mod test {
             );
         }
     }
+    #[test]
+    fn test_fidelity_bond_score() {
+        let pubkey = PublicKey::from_str(
+            "03ffe2b8b46eb21eadc3b535e9f57054213a1775b035faba6c5b3368b3a0ab5a5c",
+        )
+        .unwrap();
+        let bond = |amount, lock_height| FidelityBond {
+            outpoint: OutPoint::null(),
+            amount: Amount::from_sat(amount),
+            lock_time: LockTime::from_height(lock_height).unwrap(),
+            pubkey,
+            conf_height: 1000,
+            cert_expiry: 0,
+        };
+        let (tip_height, current_time) = (1100, 1_700_000_000);
+
+        let short = bond(100_000_000, 10_000).score(tip_height, current_time);
+        let long = bond(100_000_000, 50_000).score(tip_height, current_time);
+        let long_small = bond(10_000_000, 50_000).score(tip_height, current_time);
+        let expired = bond(100_000_000, 1050).score(tip_height, current_time);
+
+        // Longer remaining locktime and bigger amounts score higher.
+        assert!(long > short);
+        assert!(long > long_small);
+        assert_eq!(expired, Amount::ZERO);
+    }
+
+    #[test]
+    fn test_fidelity_bond_locktime_passed() {
+        let pubkey = PublicKey::from_str(
+            "03ffe2b8b46eb21eadc3b535e9f57054213a1775b035faba6c5b3368b3a0ab5a5c",
+        )
+        .unwrap();
+        let bond = |lock_time| FidelityBond {
+            outpoint: OutPoint::null(),
+            amount: Amount::from_sat(100_000),
+            lock_time,
+            pubkey,
+            conf_height: 0,
+            cert_expiry: 0,
+        };
+
+        let height_locked = bond(LockTime::from_height(500).unwrap());
+        assert!(!height_locked.is_locktime_passed(499, u64::MAX));
+        assert!(height_locked.is_locktime_passed(500, 0));
+
+        let time_locked = bond(LockTime::from_time(1_700_000_000).unwrap());
+        assert!(!time_locked.is_locktime_passed(u64::MAX, 1_699_999_999));
+        // Not final yet at equality.
+        assert!(!time_locked.is_locktime_passed(u64::MAX, 1_700_000_000));
+        assert!(time_locked.is_locktime_passed(0, 1_700_000_001));
+    }
 }
 
 #[test]
@@ -2,15 +2,18 @@
 //!
 //! This module contains routines for creating funding transactions within a wallet. It leverages
 //! Bitcoin Core's RPC methods for wallet interactions, including `walletcreatefundedpsbt`
+//!
+//! Funding transactions don't signal RBF: the contract transactions pre-signed with the makers
+//! spend them by txid, a replacement would invalidate them.
 
-use std::{collections::HashMap, iter};
+use std::{collections::HashMap, iter, str::FromStr};
 
 use bitcoin::{
     absolute::LockTime, transaction::Version, Address, Amount, OutPoint, ScriptBuf, Sequence,
     Transaction, TxIn, TxOut, Txid, Witness,
 };
 
-use bitcoind::bitcoincore_rpc::{json::CreateRawTransactionInput, RpcApi};
+use bitcoind::bitcoincore_rpc::json::CreateRawTransactionInput;
 
 use bitcoin::secp256k1::rand::{rngs::OsRng, RngCore};
 
@@ -18,6 +21,89 @@ use super::Wallet;
 
 use super::error::WalletError;
 
+/// How the Taker shapes the amounts of the funding outputs it sends into the swap, so they blend
+/// with the values of typical payments rather than look like random fractions of the swap amount.
+///
+/// Only the Taker's own funding outputs of the first hop are shaped, the makers pick the amounts
+/// of the next hops, up to the one paying the Taker.
+///
+/// The whole swap amount is still sent: every funding output but the last one is rounded down,
+/// and the last one carries the remainder. So a swap needs two funding txs or more to be shaped.
+/// Funding txs that have to fully spend some utxos can't be shaped, and aren't used then.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputShaping {
+    /// Leave the amounts as they are.
+    #[default]
+    None,
+    /// Round down to a multiple of the given denomination.
+    RoundDown(Amount),
+    /// Round down to a random count of 2 to 4 significant digits, like the amounts of payments
+    /// of varying precision.
+    Randomize,
+}
+
+impl OutputShaping {
+    /// The shaped `amount`. An amount the shaping would round down to zero is left as it is.
+    pub fn shape(&self, amount: Amount) -> Amount {
+        let shaped = match self {
+            Self::None => amount,
+            Self::RoundDown(denomination) if *denomination > Amount::ZERO => {
+                Amount::from_sat(amount.to_sat() - amount.to_sat() % denomination.to_sat())
+            }
+            Self::RoundDown(_) => amount,
+            Self::Randomize => round_to_significant_digits(amount, 2 + OsRng.next_u32() % 3),
+        };
+        if shaped == Amount::ZERO {
+            amount
+        } else {
+            shaped
+        }
+    }
+
+    /// Shapes the funding output `values`, keeping their total: the last value takes what's
+    /// rounded off of the others.
+    pub fn shape_all(&self, values: &[u64]) -> Vec<u64> {
+        let total = values.iter().sum::<u64>();
+        let Some((_, shaped_values)) = values.split_last() else {
+            return Vec::new();
+        };
+        let mut shaped = shaped_values
+            .iter()
+            .map(|&value| self.shape(Amount::from_sat(value)).to_sat())
+            .collect::<Vec<_>>();
+        shaped.push(total - shaped.iter().sum::<u64>());
+        shaped
+    }
+}
+
+/// Parses `none`, `randomize` or `round_down:<denomination in sats>`.
+impl FromStr for OutputShaping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(OutputShaping::None),
+            "randomize" => Ok(OutputShaping::Randomize),
+            s => s
+                .strip_prefix("round_down:")
+                .and_then(|sats| sats.trim().parse().ok())
+                .map(|sats| OutputShaping::RoundDown(Amount::from_sat(sats)))
+                .ok_or_else(|| "Invalid output shaping".to_string()),
+        }
+    }
+}
+
+/// Rounds `amount` down to its `digits` most significant decimal digits.
+fn round_to_significant_digits(amount: Amount, digits: u32) -> Amount {
+    let sats = amount.to_sat();
+    let len = sats.checked_ilog10().map_or(1, |log| log + 1);
+    if len <= digits {
+        return amount;
+    }
+    let unit = 10u64.pow(len - digits);
+    Amount::from_sat(sats - sats % unit)
+}
+
 #[derive(Debug)]
 pub struct CreateFundingTxesResult {
     pub funding_txes: Vec<Transaction>,
@@ -28,26 +114,50 @@ pub struct CreateFundingTxesResult {
 impl Wallet {
     // Attempts to create the funding transactions.
     /// Returns Ok(None) if there was no error but the wallet was unable to create funding txes
+    ///
+    /// If `funding_utxos` is given, no other utxo is spent.
     pub fn create_funding_txes(
         &self,
         coinswap_amount: Amount,
         destinations: &[Address],
         fee_rate: Amount,
+        output_shaping: OutputShaping,
+        funding_utxos: Option<&[OutPoint]>,
     ) -> Result<CreateFundingTxesResult, WalletError> {
-        let ret = self.create_funding_txes_random_amounts(coinswap_amount, destinations, fee_rate);
+        let ret = self.create_funding_txes_random_amounts(
+            coinswap_amount,
+            destinations,
+            fee_rate,
+            output_shaping,
+            funding_utxos,
+        );
         if ret.is_ok() {
             log::info!(target: "wallet", "created funding txes with random amounts");
             return ret;
         }
+        if output_shaping != OutputShaping::None {
+            // The other methods fully spend utxos, their output amounts can't be shaped.
+            log::info!(target: "wallet", "failed to create shaped funding txes");
+            return ret;
+        }
 
-        let ret = self.create_funding_txes_utxo_max_sends(coinswap_amount, destinations, fee_rate);
+        let ret = self.create_funding_txes_utxo_max_sends(
+            coinswap_amount,
+            destinations,
+            fee_rate,
+            funding_utxos,
+        );
         if ret.is_ok() {
             log::info!(target: "wallet", "created funding txes with fully-spending utxos");
             return ret;
         }
 
-        let ret =
-            self.create_funding_txes_use_biggest_utxos(coinswap_amount, destinations, fee_rate);
+        let ret = self.create_funding_txes_use_biggest_utxos(
+            coinswap_amount,
+            destinations,
+            fee_rate,
+            funding_utxos,
+        );
         if ret.is_ok() {
             log::info!(target: "wallet", "created funding txes with using the biggest utxos");
             return ret;
@@ -125,10 +235,21 @@ impl Wallet {
         coinswap_amount: Amount,
         destinations: &[Address],
         fee_rate: Amount,
+        output_shaping: OutputShaping,
+        funding_utxos: Option<&[OutPoint]>,
     ) -> Result<CreateFundingTxesResult, WalletError> {
         let change_addresses = self.get_next_internal_addresses(destinations.len() as u32)?;
 
-        let output_values = Wallet::generate_amount_fractions(destinations.len(), coinswap_amount)?;
+        if output_shaping != OutputShaping::None && destinations.len() < 2 {
+            log::warn!(
+                target: "wallet",
+                "A single funding output can't be shaped, the remainder would have nowhere to go"
+            );
+        }
+        let output_values = output_shaping.shape_all(&Wallet::generate_amount_fractions(
+            destinations.len(),
+            coinswap_amount,
+        )?);
 
         self.lock_unspendable_utxos()?;
 
@@ -145,7 +266,7 @@ impl Wallet {
 
             let fee = fee_rate;
             let remaining = Amount::from_sat(output_value);
-            let selected_utxo = self.coin_select(remaining)?;
+            let selected_utxo = self.coin_select_within(remaining, funding_utxos)?;
             let total_input_amount = selected_utxo.iter().fold(Amount::ZERO, |acc, (unspet, _)| {
                 acc.checked_add(unspet.amount)
                     .expect("Amount sum overflowed")
@@ -166,7 +287,7 @@ impl Wallet {
                 .iter()
                 .map(|(unspent, _)| TxIn {
                     previous_output: OutPoint::new(unspent.txid, unspent.vout),
-                    sequence: Sequence(0),
+                    sequence: Sequence::MAX,
                     witness: Witness::new(),
                     script_sig: ScriptBuf::new(),
                 })
@@ -182,7 +303,7 @@ impl Wallet {
                 .map(|(_, spend_info)| spend_info.clone());
             self.sign_transaction(&mut funding_tx, &mut input_info)?;
 
-            self.rpc.lock_unspent(
+            self.core_lock_unspent(
                 &funding_tx
                     .input
                     .iter()
@@ -228,7 +349,7 @@ impl Wallet {
             outputs.insert(destinations_iter.next().unwrap(), value);
             let tx_inputs = vec![TxIn {
                 previous_output: OutPoint::new(txid, vout),
-                sequence: Sequence(0),
+                sequence: Sequence::MAX,
                 witness: Witness::new(),
                 script_sig: ScriptBuf::new(),
             }];
@@ -262,7 +383,7 @@ impl Wallet {
             .map(|(txid, vout, value)| {
                 tx_inputs.push(TxIn {
                     previous_output: OutPoint::new(txid, vout),
-                    sequence: Sequence(0),
+                    sequence: Sequence::MAX,
                     witness: Witness::new(),
                     script_sig: ScriptBuf::new(),
                 });
@@ -317,7 +438,7 @@ impl Wallet {
         let mut change_amount = first_value;
         tx_inputs.push(TxIn {
             previous_output: OutPoint::new(first_txid, first_vout),
-            sequence: Sequence(0),
+            sequence: Sequence::MAX,
             witness: Witness::new(),
             script_sig: ScriptBuf::new(),
         });
@@ -358,6 +479,7 @@ impl Wallet {
         coinswap_amount: Amount,
         destinations: &[Address],
         fee_rate: Amount,
+        funding_utxos: Option<&[OutPoint]>,
     ) -> Result<CreateFundingTxesResult, WalletError> {
         //this function creates funding txes by
         //using walletcreatefundedpsbt for the total amount, and if
@@ -374,7 +496,7 @@ impl Wallet {
 
         let remaining = coinswap_amount;
 
-        let selected_utxo = self.coin_select(remaining + fee)?;
+        let selected_utxo = self.coin_select_within(remaining + fee, funding_utxos)?;
 
         let total_input_amount = selected_utxo.iter().fold(Amount::ZERO, |acc, (unspet, _)| {
             acc.checked_add(unspet.amount)
@@ -399,7 +521,7 @@ impl Wallet {
             .iter()
             .map(|(unspent, _)| TxIn {
                 previous_output: OutPoint::new(unspent.txid, unspent.vout),
-                sequence: Sequence(0),
+                sequence: Sequence::MAX,
                 witness: Witness::new(),
                 script_sig: ScriptBuf::new(),
             })
@@ -440,6 +562,7 @@ impl Wallet {
         coinswap_amount: Amount,
         destinations: &[Address],
         fee_rate: Amount,
+        funding_utxos: Option<&[OutPoint]>,
     ) -> Result<CreateFundingTxesResult, WalletError> {
         //this function will pick the top most valuable UTXOs and use them
         //to create funding transactions
@@ -450,7 +573,15 @@ impl Wallet {
         let mut swap_coin_utxo = self.list_swap_coin_utxo_spend_info(Some(&all_utxos))?;
         seed_coin_utxo.append(&mut swap_coin_utxo);
 
-        let mut list_unspent_result = seed_coin_utxo;
+        let mut list_unspent_result = seed_coin_utxo
+            .into_iter()
+            .filter(|(utxo, _)| !self.is_utxo_locked(&OutPoint::new(utxo.txid, utxo.vout)))
+            .filter(|(utxo, _)| {
+                funding_utxos.is_none_or(|funding_utxos| {
+                    funding_utxos.contains(&OutPoint::new(utxo.txid, utxo.vout))
+                })
+            })
+            .collect::<Vec<_>>();
         if list_unspent_result.len() < destinations.len() {
             return Err(WalletError::Protocol(
                 "Not enough UTXOs to create this many funding txes".to_string(),
@@ -510,3 +641,62 @@ impl Wallet {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_shaping() {
+        let amount = Amount::from_sat(1_234_567);
+        assert_eq!(OutputShaping::None.shape(amount), amount);
+        assert_eq!(
+            OutputShaping::RoundDown(Amount::from_sat(100_000)).shape(amount),
+            Amount::from_sat(1_200_000)
+        );
+        // Too small to round down, or no denomination.
+        assert_eq!(
+            OutputShaping::RoundDown(Amount::from_sat(10_000_000)).shape(amount),
+            amount
+        );
+        assert_eq!(OutputShaping::RoundDown(Amount::ZERO).shape(amount), amount);
+
+        for _ in 0..20 {
+            let shaped = OutputShaping::Randomize.shape(amount);
+            assert!([1_230_000, 1_234_000, 1_200_000].contains(&shaped.to_sat()));
+        }
+        assert_eq!(
+            round_to_significant_digits(Amount::from_sat(987), 2),
+            Amount::from_sat(980)
+        );
+        assert_eq!(
+            round_to_significant_digits(Amount::from_sat(98), 2),
+            Amount::from_sat(98)
+        );
+
+        assert_eq!("none".parse(), Ok(OutputShaping::None));
+        assert_eq!("Randomize".parse(), Ok(OutputShaping::Randomize));
+        assert_eq!(
+            "round_down:100000".parse(),
+            Ok(OutputShaping::RoundDown(Amount::from_sat(100_000)))
+        );
+        assert!("round_down:many".parse::<OutputShaping>().is_err());
+
+        // The total is kept, the last output carries the remainder.
+        let shaping = OutputShaping::RoundDown(Amount::from_sat(100_000));
+        assert_eq!(
+            shaping.shape_all(&[1_234_567, 345_678, 20_000]),
+            vec![1_200_000, 300_000, 100_245]
+        );
+        assert_eq!(shaping.shape_all(&[1_234_567]), vec![1_234_567]);
+        assert!(shaping.shape_all(&[]).is_empty());
+        let values = [1_234_567, 7_654_321];
+        assert_eq!(
+            OutputShaping::Randomize
+                .shape_all(&values)
+                .iter()
+                .sum::<u64>(),
+            values.iter().sum::<u64>()
+        );
+    }
+}
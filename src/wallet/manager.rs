@@ -0,0 +1,150 @@
+//! Several independent wallets in a single process, like one per strategy or risk bucket, on one node.
+//!
+//! The wallets share an [RpcPool], and the manager syncs them one at a time: rescans of a node
+//! running concurrently fail. Wallets held by a [WalletManager] should only be synced through it.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use super::{
+    api::{Balances, Wallet},
+    error::WalletError,
+    rpc::{RPCConfig, RpcPool},
+    storage::WalletStore,
+};
+
+/// Holds wallets keyed by name. The wallet name is the wallet file name, like for a single [Wallet].
+pub struct WalletManager {
+    rpc_config: RPCConfig,
+    rpc_pool: RpcPool,
+    wallets: BTreeMap<String, Arc<RwLock<Wallet>>>,
+    sync_lock: Mutex<()>,
+}
+
+impl WalletManager {
+    /// Creates an empty manager. `rpc_config` is the node connection of every wallet, its
+    /// `wallet_name` is replaced by the name of each wallet.
+    pub fn new(rpc_config: RPCConfig) -> Self {
+        Self {
+            rpc_config,
+            rpc_pool: RpcPool::new(),
+            wallets: BTreeMap::new(),
+            sync_lock: Mutex::new(()),
+        }
+    }
+
+    /// Loads the wallet file at `path`, see [Wallet::load].
+    pub fn load(&mut self, path: &PathBuf) -> Result<Arc<RwLock<Wallet>>, WalletError> {
+        let rpc_config = self.wallet_rpc_config(path)?;
+        let store = WalletStore::read_from_disk(path)?;
+        let rpc = self.rpc_pool.get(&rpc_config)?;
+        let wallet = Wallet::load_store(&rpc_config, rpc, path, store, None)?;
+        Ok(self.insert(rpc_config.wallet_name, wallet))
+    }
+
+    /// Creates a new wallet file at `path`, see [Wallet::init].
+    pub fn create(
+        &mut self,
+        path: &PathBuf,
+        seedphrase: String,
+        passphrase: String,
+    ) -> Result<Arc<RwLock<Wallet>>, WalletError> {
+        let rpc_config = self.wallet_rpc_config(path)?;
+        let rpc = self.rpc_pool.get(&rpc_config)?;
+        let wallet =
+            Wallet::init_with_client(path, &rpc_config, rpc, seedphrase, passphrase, None)?;
+        Ok(self.insert(rpc_config.wallet_name, wallet))
+    }
+
+    /// Removes a wallet from the manager, and drops its pooled client.
+    pub fn remove(&mut self, name: &str) -> Result<Option<Arc<RwLock<Wallet>>>, WalletError> {
+        self.rpc_pool.evict(&RPCConfig {
+            wallet_name: name.to_string(),
+            ..self.rpc_config.clone()
+        })?;
+        Ok(self.wallets.remove(name))
+    }
+
+    /// Gets a wallet by name.
+    pub fn get(&self, name: &str) -> Option<Arc<RwLock<Wallet>>> {
+        self.wallets.get(name).cloned()
+    }
+
+    /// Iterates over the wallets, ordered by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Arc<RwLock<Wallet>>)> {
+        self.wallets
+            .iter()
+            .map(|(name, wallet)| (name.as_str(), wallet))
+    }
+
+    /// Syncs a wallet, once no other wallet of the manager is syncing.
+    pub fn sync(&self, name: &str) -> Result<(), WalletError> {
+        let wallet = self
+            .wallets
+            .get(name)
+            .ok_or_else(|| WalletError::InvalidWalletName(name.to_string()))?;
+        let _sync_guard = self.sync_lock.lock().map_err(|_| poisoned())?;
+        wallet.write().map_err(|_| poisoned())?.sync()
+    }
+
+    /// Syncs every wallet, one after the other.
+    pub fn sync_all(&self) -> Result<(), WalletError> {
+        self.wallets.keys().try_for_each(|name| self.sync(name))
+    }
+
+    /// Balances of every wallet, by name.
+    pub fn balances(&self) -> Result<BTreeMap<String, Balances>, WalletError> {
+        self.wallets
+            .iter()
+            .map(|(name, wallet)| {
+                let balances = wallet.read().map_err(|_| poisoned())?.get_balances()?;
+                Ok((name.clone(), balances))
+            })
+            .collect()
+    }
+
+    /// Balances of all the wallets summed up, per category.
+    pub fn total_balances(&self) -> Result<Balances, WalletError> {
+        Ok(self
+            .balances()?
+            .values()
+            .fold(Balances::default(), |total, balances| Balances {
+                spendable: total.spendable + balances.spendable,
+                incoming_swapcoins: total.incoming_swapcoins + balances.incoming_swapcoins,
+                outgoing_swapcoins: total.outgoing_swapcoins + balances.outgoing_swapcoins,
+                fidelity: total.fidelity + balances.fidelity,
+                unconfirmed: total.unconfirmed + balances.unconfirmed,
+            }))
+    }
+
+    fn wallet_rpc_config(&self, path: &Path) -> Result<RPCConfig, WalletError> {
+        let wallet_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| WalletError::InvalidWalletName(path.display().to_string()))?
+            .to_string();
+        if self.wallets.contains_key(&wallet_name) {
+            return Err(WalletError::Protocol(format!(
+                "Wallet {} is already loaded",
+                wallet_name
+            )));
+        }
+        Ok(RPCConfig {
+            wallet_name,
+            ..self.rpc_config.clone()
+        })
+    }
+
+    fn insert(&mut self, name: String, wallet: Wallet) -> Arc<RwLock<Wallet>> {
+        let wallet = Arc::new(RwLock::new(wallet));
+        self.wallets.insert(name, wallet.clone());
+        wallet
+    }
+}
+
+fn poisoned() -> WalletError {
+    WalletError::Protocol("Wallet manager lock poisoned".to_string())
+}
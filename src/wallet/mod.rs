@@ -1,20 +1,54 @@
 //! The Coinswap Wallet (unsecured). Used by both the Taker and Maker.
 
+mod active_swaps;
 mod api;
+mod backend;
+mod backup;
+mod bip21;
+mod coin_selection;
+mod contract_watch;
 mod direct_send;
 mod error;
+mod existing_descriptor;
+mod fee_bump;
+mod fee_estimation;
 mod fidelity;
 mod funding;
+mod manager;
+mod psbt;
+mod rescan_lock;
 mod rpc;
 mod storage;
+mod swap_history;
 mod swapcoin;
 
-pub use api::{DisplayAddressType, UTXOSpendInfo, Wallet};
+pub use active_swaps::{ActiveContractInfo, ActiveSwapInfo, ActiveSwapPhase};
+pub use api::{Balances, DisplayAddressType, UTXOSpendInfo, UtxoOrigin, Wallet};
+pub use backend::{ChainBackend, CoreRpcBackend};
+pub use backup::WalletBackup;
+pub use bip21::{parse_bip21, PaymentRequest};
+pub use coin_selection::CoinSelectionStrategy;
+pub use contract_watch::ContractSpendInfo;
 pub use direct_send::{CoinToSpend, Destination, SendAmount};
 pub use error::WalletError;
+pub use existing_descriptor::ExistingDescriptor;
+pub use fee_estimation::{
+    FallbackFeeEstimator, FeeEstimator, MempoolSpaceFeeEstimator, StaticFeeEstimator,
+    DEFAULT_CONFIRMATION_TARGET, FALLBACK_FEE_RATE, FEE_BUMP_CONFIRMATION_TARGET,
+};
+#[cfg(feature = "control-server")]
+pub(crate) use fidelity::{wait_for_fidelity_spend, FIDELITY_SPEND_TIMEOUT};
 pub use fidelity::{FidelityBond, FidelityError};
-pub use rpc::RPCConfig;
-pub use storage::WalletStore;
+pub use funding::OutputShaping;
+pub use manager::WalletManager;
+pub use rpc::{
+    HealthStatus, RPCConfig, RescanRetryPolicy, RpcPool, ScanProgress, SyncMode, SyncPlan,
+    WalletDirEntry, DEFAULT_GAP_LIMIT, MINIMUM_CORE_VERSION, SYNC_LOG_TARGET,
+};
+pub use storage::{WalletStore, WALLET_STORE_VERSION};
+pub use swap_history::{
+    ExposureKind, ExposureReport, MakerExposure, SwapHistory, SwapOutcome, SwapRecord, UtxoExposure,
+};
 pub use swapcoin::{
     IncomingSwapCoin, OutgoingSwapCoin, SwapCoin, WalletSwapCoin, WatchOnlySwapCoin,
 };
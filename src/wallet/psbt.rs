@@ -0,0 +1,381 @@
+//! PSBT export and import, for signing swap funding transactions on an offline signer.
+//!
+//! Only seed coins are spent, as the swapcoin keys aren't derived from the master key and an
+//! external signer can't find them. Every input and the change output carry the BIP32 derivation
+//! of their key from the master key: the master fingerprint, and the full path below
+//! `m/84'/1'/0'`.
+//!
+//! Also builds and joins the PSBTs of collaborative funding, see [CollabFunding]. A funding
+//! transaction joined by the Maker spends coins of both parties, so its inputs don't all belong to
+//! the Taker.
+
+use std::{cmp::Reverse, collections::BTreeMap, str::FromStr};
+
+use bitcoin::{
+    absolute::LockTime,
+    bip32::{DerivationPath, KeySource},
+    psbt::{Input, Output},
+    secp256k1::{self, Secp256k1},
+    transaction::Version,
+    Address, Amount, FeeRate, OutPoint, Psbt, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Weight, Witness,
+};
+
+#[cfg(doc)]
+use crate::protocol::messages::CollabFunding;
+
+use super::{api::HARDENDED_DERIVATION, error::WalletError, UTXOSpendInfo, Wallet};
+
+/// Weight the Maker adds to a funding transaction it joins: a p2wpkh input, and a p2wpkh output
+/// of 31 bytes.
+const COLLAB_CONTRIBUTION_WEIGHT: Weight = Weight::from_wu(272 + 31 * 4);
+
+impl Wallet {
+    /// Creates an unsigned PSBT paying `amount` to a swap funding `destination`, with `fee` as the
+    /// absolute miner fee. The change goes to the next internal address.
+    ///
+    /// The inputs are the biggest unlocked seed coins. They aren't locked by this call, so don't
+    /// spend them before the PSBT is broadcasted with [`Wallet::finalize_and_broadcast_psbt`].
+    pub fn create_funding_psbt(
+        &self,
+        destination: &Address,
+        amount: Amount,
+        fee: Amount,
+    ) -> Result<Psbt, WalletError> {
+        let mut seed_coins = self
+            .list_descriptor_utxo_spend_info(None)?
+            .into_iter()
+            .filter(|(utxo, spend_info)| {
+                matches!(spend_info, UTXOSpendInfo::SeedCoin { .. })
+                    && !self.is_utxo_locked(&OutPoint::new(utxo.txid, utxo.vout))
+            })
+            .collect::<Vec<_>>();
+        seed_coins.sort_by_key(|(utxo, _)| Reverse(utxo.amount));
+
+        let target = amount + fee;
+        let mut selected = Vec::new();
+        let mut total_input_value = Amount::ZERO;
+        for coin in seed_coins {
+            if total_input_value >= target {
+                break;
+            }
+            total_input_value += coin.0.amount;
+            selected.push(coin);
+        }
+        if total_input_value < target {
+            return Err(WalletError::Protocol(format!(
+                "Insufficient seed coins to fund {} with {} fee, available {}",
+                amount, fee, total_input_value
+            )));
+        }
+
+        let mut output = vec![TxOut {
+            value: amount,
+            script_pubkey: destination.script_pubkey(),
+        }];
+        let change_address = self.get_next_internal_addresses(1)?[0].clone();
+        let change_value = total_input_value - target;
+        if change_value >= change_address.script_pubkey().minimal_non_dust() {
+            output.push(TxOut {
+                value: change_value,
+                script_pubkey: change_address.script_pubkey(),
+            });
+        }
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: selected
+                .iter()
+                .map(|(utxo, _)| TxIn {
+                    previous_output: OutPoint::new(utxo.txid, utxo.vout),
+                    script_sig: ScriptBuf::new(),
+                    // No RBF, like the wallet's own funding transactions: the contract
+                    // transactions spend the funding outputs by txid.
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output,
+        };
+
+        let mut psbt = Psbt::from_unsigned_tx(tx)
+            .map_err(|e| WalletError::Protocol(format!("Failed to create PSBT: {}", e)))?;
+        for (psbt_input, (utxo, spend_info)) in psbt.inputs.iter_mut().zip(&selected) {
+            let UTXOSpendInfo::SeedCoin { path, .. } = spend_info else {
+                unreachable!("only seed coins are selected");
+            };
+            psbt_input.witness_utxo = Some(TxOut {
+                value: utxo.amount,
+                script_pubkey: utxo.script_pub_key.clone(),
+            });
+            psbt_input.bip32_derivation = self.seed_coin_key_source(path)?;
+        }
+        if let Some(change) = psbt.unsigned_tx.output.get(1).cloned() {
+            if let Some(UTXOSpendInfo::SeedCoin { path, .. }) =
+                self.owned_output_spend_info(&change)?
+            {
+                psbt.outputs[1].bip32_derivation = self.seed_coin_key_source(&path)?;
+            }
+        }
+
+        log::info!(
+            "Created funding PSBT spending {} inputs, total {}",
+            selected.len(),
+            total_input_value
+        );
+        Ok(psbt)
+    }
+
+    /// Finalizes a PSBT signed by an external signer, and broadcasts the transaction.
+    ///
+    /// Inputs already finalized by the signer are kept as they are. The others must be P2WPKH, with
+    /// the signature of the key paid by their witness UTXO.
+    pub fn finalize_and_broadcast_psbt(&self, mut psbt: Psbt) -> Result<Txid, WalletError> {
+        for (ix, input) in psbt.inputs.iter_mut().enumerate() {
+            if input.final_script_witness.is_some() {
+                continue;
+            }
+            let script_pubkey = input
+                .witness_utxo
+                .as_ref()
+                .map(|txout| &txout.script_pubkey)
+                .ok_or_else(|| {
+                    WalletError::Protocol(format!("PSBT input {} has no witness UTXO", ix))
+                })?;
+            let (pubkey, signature) = input
+                .partial_sigs
+                .iter()
+                .find(|(pubkey, _)| {
+                    pubkey
+                        .wpubkey_hash()
+                        .map(|hash| ScriptBuf::new_p2wpkh(&hash) == *script_pubkey)
+                        .unwrap_or(false)
+                })
+                .ok_or_else(|| WalletError::Protocol(format!("PSBT input {} is not signed", ix)))?;
+
+            input.final_script_witness = Some(Witness::p2wpkh(signature, &pubkey.inner));
+            // Finalized inputs only keep the UTXO and the final scripts (BIP174).
+            input.partial_sigs.clear();
+            input.sighash_type = None;
+            input.bip32_derivation.clear();
+        }
+
+        let tx = psbt
+            .extract_tx()
+            .map_err(|e| WalletError::Protocol(format!("Failed to extract PSBT: {}", e)))?;
+        let txid = self.backend.broadcast(&tx)?;
+        log::info!("Broadcasted PSBT transaction {}", txid);
+        Ok(txid)
+    }
+
+    /// The unsigned PSBT of one of our funding transactions, for the Maker to join. Its inputs
+    /// carry their witness UTXO.
+    pub fn collab_funding_psbt(&self, funding_tx: &Transaction) -> Result<Psbt, WalletError> {
+        let mut unsigned_tx = funding_tx.clone();
+        for input in &mut unsigned_tx.input {
+            input.witness.clear();
+        }
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx)
+            .map_err(|e| WalletError::Protocol(format!("Failed to create PSBT: {}", e)))?;
+        for (psbt_input, input) in psbt.inputs.iter_mut().zip(&funding_tx.input) {
+            psbt_input.witness_utxo = Some(self.prevout(&input.previous_output)?);
+        }
+        Ok(psbt)
+    }
+
+    /// Joins each PSBT with one of our seed coins, sent back to a fresh change address minus the
+    /// fee for its weight at `fee_rate`, and signs that input only. Returns the joined coins.
+    ///
+    /// The coins aren't locked here. Spending them before the Taker broadcasts invalidates its
+    /// funding transactions, so the Maker locks them until the swap is over.
+    pub fn join_collab_funding(
+        &self,
+        psbts: &mut [Psbt],
+        fee_rate: FeeRate,
+    ) -> Result<Vec<OutPoint>, WalletError> {
+        if psbts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let fee = fee_rate.fee_wu(COLLAB_CONTRIBUTION_WEIGHT).ok_or_else(|| {
+            WalletError::Protocol("Collaborative funding fee overflow".to_string())
+        })?;
+        let change_addresses = self.get_next_internal_addresses(psbts.len() as u32)?;
+
+        // The smallest coins that pay for themselves, a different one for each PSBT.
+        let mut coins = self
+            .list_selectable_utxo_spend_info()?
+            .into_iter()
+            .filter(|(utxo, spend_info)| {
+                matches!(spend_info, UTXOSpendInfo::SeedCoin { .. })
+                    && utxo.amount.checked_sub(fee).is_some_and(|value| {
+                        value >= change_addresses[0].script_pubkey().minimal_non_dust()
+                    })
+            })
+            .collect::<Vec<_>>();
+        coins.sort_by_key(|(utxo, _)| utxo.amount);
+        if coins.len() < psbts.len() {
+            return Err(WalletError::Protocol(format!(
+                "Not enough coins to join {} funding transactions",
+                psbts.len()
+            )));
+        }
+
+        let mut joined_coins = Vec::new();
+        for ((psbt, (utxo, spend_info)), change_address) in
+            psbts.iter_mut().zip(coins).zip(&change_addresses)
+        {
+            let outpoint = OutPoint::new(utxo.txid, utxo.vout);
+            if psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .any(|input| input.previous_output == outpoint)
+            {
+                return Err(WalletError::Protocol(format!(
+                    "Funding PSBT already spends our coin {}",
+                    outpoint
+                )));
+            }
+
+            let ix = psbt.unsigned_tx.input.len();
+            psbt.unsigned_tx.input.push(TxIn {
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            });
+            psbt.unsigned_tx.output.push(TxOut {
+                value: utxo.amount - fee,
+                script_pubkey: change_address.script_pubkey(),
+            });
+            psbt.inputs.push(Input {
+                witness_utxo: Some(TxOut {
+                    value: utxo.amount,
+                    script_pubkey: utxo.script_pub_key.clone(),
+                }),
+                ..Default::default()
+            });
+            psbt.outputs.push(Output::default());
+
+            let mut tx = psbt.unsigned_tx.clone();
+            self.sign_transaction_inputs(&mut tx, std::iter::once((ix, spend_info)))?;
+            psbt.inputs[ix].final_script_witness = Some(tx.input[ix].witness.clone());
+            joined_coins.push(outpoint);
+        }
+        Ok(joined_coins)
+    }
+
+    /// Signs our inputs of `psbt`, our funding transaction `funding_tx` joined by the Maker, and
+    /// returns the complete transaction.
+    ///
+    /// The Maker may only append inputs it signed and outputs. These must pay for their weight at
+    /// `fee_rate`, so the joined transaction costs us nothing and confirms as fast.
+    pub fn sign_collab_funding(
+        &self,
+        funding_tx: &Transaction,
+        psbt: &Psbt,
+        fee_rate: FeeRate,
+    ) -> Result<Transaction, WalletError> {
+        let joined = &psbt.unsigned_tx;
+        let own_inputs = funding_tx.input.len();
+        let own_outputs = funding_tx.output.len();
+        if joined.version != funding_tx.version
+            || joined.lock_time != funding_tx.lock_time
+            || joined.input.len() < own_inputs
+            || joined.output.len() < own_outputs
+            || joined.input[..own_inputs]
+                .iter()
+                .zip(&funding_tx.input)
+                .any(|(joined, own)| {
+                    joined.previous_output != own.previous_output || joined.sequence != own.sequence
+                })
+            || joined.output[..own_outputs] != funding_tx.output[..]
+        {
+            return Err(WalletError::Protocol(
+                "Joined funding PSBT altered our inputs or outputs".to_string(),
+            ));
+        }
+
+        let mut tx = joined.clone();
+        let mut maker_input_value = Amount::ZERO;
+        for (ix, psbt_input) in psbt.inputs.iter().enumerate().skip(own_inputs) {
+            let (Some(witness_utxo), Some(witness)) =
+                (&psbt_input.witness_utxo, &psbt_input.final_script_witness)
+            else {
+                return Err(WalletError::Protocol(format!(
+                    "Joined funding PSBT input {} isn't signed",
+                    ix
+                )));
+            };
+            maker_input_value += witness_utxo.value;
+            tx.input[ix].witness = witness.clone();
+        }
+
+        let own_inputs_info = funding_tx
+            .input
+            .iter()
+            .map(|input| {
+                let prevout = self.prevout(&input.previous_output)?;
+                self.owned_output_spend_info(&prevout)?.ok_or_else(|| {
+                    WalletError::Protocol(format!(
+                        "Funding input {} isn't ours",
+                        input.previous_output
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, WalletError>>()?;
+        self.sign_transaction_inputs(&mut tx, own_inputs_info.into_iter().enumerate())?;
+
+        let maker_output_value = tx.output[own_outputs..]
+            .iter()
+            .map(|output| output.value)
+            .sum::<Amount>();
+        // Our signatures may be a byte shorter or longer than those of `funding_tx`.
+        let maker_weight = tx
+            .weight()
+            .checked_sub(funding_tx.weight() + Weight::from_wu(own_inputs as u64))
+            .unwrap_or(Weight::ZERO);
+        let maker_fee = fee_rate.fee_wu(maker_weight).unwrap_or(Amount::MAX_MONEY);
+        if maker_output_value + maker_fee > maker_input_value {
+            return Err(WalletError::Protocol(format!(
+                "Joined funding PSBT underpays the fee of the Maker's part, inputs {} outputs {}",
+                maker_input_value, maker_output_value
+            )));
+        }
+        Ok(tx)
+    }
+
+    /// The output spent by `outpoint`, from a transaction of the wallet.
+    fn prevout(&self, outpoint: &OutPoint) -> Result<TxOut, WalletError> {
+        let (tx, _) = self
+            .backend
+            .get_wallet_transaction(&outpoint.txid)?
+            .ok_or_else(|| {
+                WalletError::Protocol(format!("Unknown wallet transaction {}", outpoint.txid))
+            })?;
+        tx.output
+            .get(outpoint.vout as usize)
+            .cloned()
+            .ok_or_else(|| WalletError::Protocol(format!("No output at {}", outpoint)))
+    }
+
+    /// Public key of a seed coin, with its derivation from the master key.
+    /// `path` is relative to [`HARDENDED_DERIVATION`], as in [`UTXOSpendInfo::SeedCoin`].
+    fn seed_coin_key_source(
+        &self,
+        path: &str,
+    ) -> Result<BTreeMap<secp256k1::PublicKey, KeySource>, WalletError> {
+        let secp = Secp256k1::new();
+        let full_path =
+            DerivationPath::from_str(HARDENDED_DERIVATION)?.extend(DerivationPath::from_str(path)?);
+        let pubkey = self
+            .store
+            .master_key
+            .derive_priv(&secp, &full_path)?
+            .private_key
+            .public_key(&secp);
+        let fingerprint = self.store.master_key.fingerprint(&secp);
+        Ok(BTreeMap::from([(pubkey, (fingerprint, full_path))]))
+    }
+}
@@ -0,0 +1,210 @@
+//! Serializes the `rescanblockchain` calls made to a node.
+//!
+//! Concurrent rescans of a node fail, even when they are for different wallets. A [RescanLock] is
+//! held for the duration of every rescan, so the others queue instead of failing and retrying.
+//!
+//! The lock is keyed by the node url. Within the process it's a mutex. Across the processes of the
+//! machine it's a lock file in the temp directory, holding the pid of its owner and a random nonce.
+//! A process dying mid-rescan leaves its lock file behind, which is removed once its pid is no
+//! longer running. However long a rescan takes, the lock of a live process is never taken over,
+//! and a lock is only ever released by the [RescanLock] holding its nonce.
+
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use bitcoin::{
+    hashes::{sha256, Hash},
+    secp256k1::rand::{rngs::OsRng, RngCore},
+};
+
+use super::{error::WalletError, rpc::RPCConfig};
+
+/// Interval at which a lock file held by another process is checked.
+const LOCK_FILE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Mutexes of the nodes rescanned by this process, by url. Never freed, there are only a few nodes.
+static NODE_MUTEXES: OnceLock<Mutex<HashMap<String, &'static Mutex<()>>>> = OnceLock::new();
+
+/// Held while a rescan of a node runs. Released on drop.
+pub(super) struct RescanLock {
+    _guard: MutexGuard<'static, ()>,
+    lock_file: PathBuf,
+    /// Content of the lock file: the pid and the nonce of this lock.
+    owner: String,
+}
+
+impl RescanLock {
+    /// Blocks until no other rescan of the configured node runs.
+    pub(super) fn acquire(rpc_config: &RPCConfig) -> Result<Self, WalletError> {
+        Self::acquire_with(&rpc_config.url)
+    }
+
+    fn acquire_with(url: &str) -> Result<Self, WalletError> {
+        let node_mutex = {
+            let mut mutexes = NODE_MUTEXES
+                .get_or_init(Default::default)
+                .lock()
+                .map_err(|_| poisoned())?;
+            *mutexes
+                .entry(url.to_string())
+                .or_insert_with(|| Box::leak(Box::default()))
+        };
+        let guard = node_mutex.lock().map_err(|_| poisoned())?;
+
+        let lock_file = lock_file_path(url);
+        let owner = format!("{} {:016x}", std::process::id(), OsRng.next_u64());
+        loop {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock_file)
+            {
+                Ok(mut file) => {
+                    file.write_all(owner.as_bytes())?;
+                    break;
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    match fs::read_to_string(&lock_file) {
+                        // A file being written by its owner is empty for a moment, so only a
+                        // dead pid makes it stale.
+                        Ok(other) if lock_owner_pid(&other).is_some_and(|pid| !is_running(pid)) => {
+                            log::warn!("Removing stale rescan lock {}", lock_file.display());
+                            remove_if_owned(&lock_file, &other);
+                        }
+                        // Released meanwhile.
+                        Err(e) if e.kind() == ErrorKind::NotFound => {}
+                        Err(e) => return Err(e.into()),
+                        Ok(_) => thread::sleep(LOCK_FILE_POLL_INTERVAL),
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(Self {
+            _guard: guard,
+            lock_file,
+            owner,
+        })
+    }
+}
+
+impl Drop for RescanLock {
+    fn drop(&mut self) {
+        if !remove_if_owned(&self.lock_file, &self.owner) {
+            log::warn!(
+                "Rescan lock {} was taken over, leaving it to its new owner",
+                self.lock_file.display()
+            );
+        }
+    }
+}
+
+/// Removes the lock file if it still holds `owner`. Whether it did.
+fn remove_if_owned(lock_file: &Path, owner: &str) -> bool {
+    if fs::read_to_string(lock_file).is_ok_and(|content| content == owner) {
+        if let Err(e) = fs::remove_file(lock_file) {
+            log::warn!(
+                "Failed to release rescan lock {}: {}",
+                lock_file.display(),
+                e
+            );
+        }
+        true
+    } else {
+        false
+    }
+}
+
+/// The pid in the content of a lock file.
+fn lock_owner_pid(content: &str) -> Option<libc::pid_t> {
+    content.split_whitespace().next()?.parse().ok()
+}
+
+/// Whether the process `pid` is running. A process of another user can't be signalled, but runs.
+fn is_running(pid: libc::pid_t) -> bool {
+    // SAFETY: Signal 0 only checks the process exists, nothing is delivered.
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Lock file of the node at `url`, shared by every process of the machine.
+fn lock_file_path(url: &str) -> PathBuf {
+    let url_hash = sha256::Hash::hash(url.as_bytes()).to_string();
+    std::env::temp_dir().join(format!("coinswap-rescan-{}.lock", &url_hash[..16]))
+}
+
+fn poisoned() -> WalletError {
+    WalletError::Protocol("Rescan lock poisoned".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        process::Command,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Instant,
+    };
+
+    #[test]
+    fn test_rescan_lock() {
+        let url = "127.0.0.1:1/test_rescan_lock";
+
+        // Held in process: the other thread waits for the release.
+        let lock = RescanLock::acquire_with(url).unwrap();
+        assert!(lock_file_path(url).exists());
+        let acquired = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let acquired = acquired.clone();
+            thread::spawn(move || {
+                let _lock = RescanLock::acquire_with(url).unwrap();
+                acquired.store(true, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(200));
+        assert!(!acquired.load(Ordering::SeqCst));
+        drop(lock);
+        waiter.join().unwrap();
+        assert!(acquired.load(Ordering::SeqCst));
+        assert!(!lock_file_path(url).exists());
+
+        // Held by another live process, however old: wait for its lock file to go.
+        fs::write(lock_file_path(url), format!("{} 0", std::process::id())).unwrap();
+        let remover = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            fs::remove_file(lock_file_path(url)).unwrap();
+        });
+        let start = Instant::now();
+        let lock = RescanLock::acquire_with(url).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        remover.join().unwrap();
+        drop(lock);
+
+        // Left over by a dead process.
+        let mut child = Command::new("true").spawn().unwrap();
+        let dead_pid = child.id();
+        child.wait().unwrap();
+        fs::write(lock_file_path(url), format!("{} 0", dead_pid)).unwrap();
+        drop(RescanLock::acquire_with(url).unwrap());
+        assert!(!lock_file_path(url).exists());
+
+        // Taken over by another process: its lock file is left alone.
+        let lock = RescanLock::acquire_with(url).unwrap();
+        let other = format!("{} 0", std::process::id());
+        fs::write(lock_file_path(url), &other).unwrap();
+        drop(lock);
+        assert_eq!(fs::read_to_string(lock_file_path(url)).unwrap(), other);
+        fs::remove_file(lock_file_path(url)).unwrap();
+    }
+}
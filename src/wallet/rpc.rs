@@ -1,16 +1,29 @@
 //! Manages connection with a Bitcoin Core RPC.
 //!
-use std::{convert::TryFrom, thread, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
 
-use bitcoin::Network;
-use bitcoind::bitcoincore_rpc::{Auth, Client, RpcApi};
-use serde_json::{json, Value};
+use bitcoin::{Block, Network, OutPoint, ScriptBuf};
+use bitcoind::bitcoincore_rpc::{
+    bitcoincore_rpc_json::ScanningDetails, jsonrpc, jsonrpc::simple_http, Auth, Client,
+    Error as RpcError, RpcApi,
+};
+use serde_json::Value;
 
-use crate::wallet::api::KeychainKind;
+use crate::{
+    utill::redeemscript_to_scriptpubkey,
+    wallet::{api::KeychainKind, SwapCoin},
+};
 
 use serde::Deserialize;
 
-use super::{error::WalletError, Wallet};
+use super::{backend::ChainBackend, error::WalletError, Wallet};
 
 /// Configuration parameters for connecting to a Bitcoin node via RPC.
 #[derive(Debug, Clone)]
@@ -23,10 +36,148 @@ pub struct RPCConfig {
     pub network: Network,
     /// The wallet name in the bitcoin node, derive this from the descriptor.
     pub wallet_name: String,
+    /// How [`Wallet::sync`] catches up with the chain.
+    pub sync_mode: SyncMode,
+    /// How failed rescans are retried.
+    pub rescan_retry_policy: RescanRetryPolicy,
+    /// Maximum number of descriptors sent in a single `importdescriptors` call.
+    pub import_batch_size: usize,
+    /// Timeout for each RPC request. `None` keeps the transport default of 15 seconds.
+    ///
+    /// Rescans run as a single request, so a short timeout makes them fail and get retried
+    /// as per [`RPCConfig::rescan_retry_policy`].
+    pub timeout: Option<Duration>,
+    /// Number of addresses imported past the next unused one, on each keychain. Funds sent further
+    /// than this past the last used address are missed. Defaults to [`DEFAULT_GAP_LIMIT`].
+    ///
+    /// Core also extends an imported range by its `-keypool` size (1000 by default) past every used
+    /// address it finds while scanning. That only bridges gaps smaller than the keypool, so the gap
+    /// limit is what covers a restored wallet with a wider gap.
+    pub gap_limit: u32,
+    /// Number of blocks below the node's tip left out of rescans, until they're buried deeper.
+    /// Keeps an actively reorging tip from being rescanned over and over.
+    ///
+    /// Outputs in those blocks are picked up by the next sync once the chain has grown past them.
+    /// 0 rescans up to the tip.
+    pub confirmation_buffer: u64,
+}
+
+/// Default [`RPCConfig::gap_limit`]. 10 for tests, 5000 for production.
+pub const DEFAULT_GAP_LIMIT: u32 = if cfg!(feature = "integration-test") {
+    10
+} else {
+    5000
+};
+
+/// Upper bound of the wait between two rescan attempts.
+const MAX_RESCAN_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// Retry policy for the `rescanblockchain` calls made while syncing.
+///
+/// The delay before the n-th retry is `base_delay * backoff^(n-1)`, capped at 5 minutes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RescanRetryPolicy {
+    /// Maximum number of rescan attempts. `0` retries forever.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+    /// Multiplier applied to the delay after each failed attempt.
+    pub backoff: f64,
+}
+
+impl Default for RescanRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(3),
+            backoff: 2.0,
+        }
+    }
+}
+
+impl RescanRetryPolicy {
+    /// Delay to wait after the given (1-indexed) failed attempt.
+    fn delay_for(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay_secs = self.base_delay.as_secs_f64() * self.backoff.powi(exponent);
+        Duration::from_secs_f64(
+            delay_secs
+                .min(MAX_RESCAN_RETRY_DELAY.as_secs_f64())
+                .max(0.0),
+        )
+    }
+
+    /// Whether no retry is left after the given (1-indexed) failed attempt.
+    fn exhausted(&self, attempt: u32) -> bool {
+        self.max_attempts != 0 && attempt >= self.max_attempts
+    }
+}
+
+/// Strategy used by [`Wallet::sync`] to bring the wallet up to the node's tip.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Rescan from the last synced height whenever new descriptors get imported. Otherwise
+    /// rely on the core wallet's own block tracking.
+    ///
+    /// Descriptors already imported, swapcoin and fidelity ones included, don't trigger a rescan:
+    /// the core wallet sees their new transactions as blocks come in. A sync with nothing new to
+    /// import only rescans after a reorg, or the blocks left in the
+    /// [`RPCConfig::confirmation_buffer`] by the previous rescan.
+    #[default]
+    Full,
+    /// Also walk the blocks mined since the last sync, and rescan only the ones touching the wallet.
+    /// Falls back to a full rescan when a new descriptor gets imported.
+    Incremental,
 }
 
 const RPC_HOSTPORT: &str = "localhost:18443";
 
+/// What [`Wallet::sync`] is going to do, as reported by [`Wallet::sync_dry_run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncPlan {
+    /// Descriptors not yet imported into the core wallet.
+    pub descriptors_to_import: Vec<String>,
+    /// Height a full rescan starts from. `None` if nothing new gets imported and no reorg was detected.
+    pub rescan_from: Option<u64>,
+}
+
+/// Error code returned by Core for an unknown block hash.
+pub(super) const RPC_INVALID_ADDRESS_OR_KEY: i32 = -5;
+
+/// Log target of the wallet sync, to filter it apart from the rest of the wallet's logs.
+pub const SYNC_LOG_TARGET: &str = "coinswap::wallet::sync";
+
+/// Progress of an ongoing blockchain rescan, reported by [`Wallet::sync_with_progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanProgress {
+    /// Approximate height the rescan has reached.
+    pub current_height: u64,
+    /// Height at which the rescan will stop.
+    pub target_height: u64,
+    /// Number of descriptors imported before the rescan started.
+    pub descriptors_imported: usize,
+}
+
+/// Liveness and readiness of the wallet's backend, reported by [`Wallet::health_check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthStatus {
+    /// Whether the node answered at all.
+    pub node_reachable: bool,
+    /// Height of the node's chain tip. `None` if the node is unreachable.
+    pub tip_height: Option<u64>,
+    /// Whether the node has the watch-only core wallet loaded.
+    pub wallet_loaded: bool,
+    /// Whether the core wallet is running a rescan. Balances and utxos are incomplete until it ends.
+    pub scanning: bool,
+}
+
+impl HealthStatus {
+    /// Whether the wallet can be used right away, e.g. to start a swap.
+    pub fn is_ready(&self) -> bool {
+        self.node_reachable && self.wallet_loaded && !self.scanning
+    }
+}
+
 impl Default for RPCConfig {
     fn default() -> Self {
         Self {
@@ -34,154 +185,1183 @@ impl Default for RPCConfig {
             auth: Auth::UserPass("regtestrpcuser".to_string(), "regtestrpcpass".to_string()),
             network: Network::Regtest,
             wallet_name: "random-wallet-name".to_string(),
+            sync_mode: SyncMode::default(),
+            rescan_retry_policy: RescanRetryPolicy::default(),
+            import_batch_size: 100,
+            timeout: None,
+            gap_limit: DEFAULT_GAP_LIMIT,
+            confirmation_buffer: 0,
+        }
+    }
+}
+
+impl RPCConfig {
+    /// Creates a config authenticating with the cookie file written by the node.
+    pub fn from_cookie(
+        url: String,
+        network: Network,
+        wallet_name: String,
+        cookie_path: PathBuf,
+    ) -> Self {
+        Self {
+            url,
+            auth: Auth::CookieFile(cookie_path),
+            network,
+            wallet_name,
+            ..Default::default()
         }
     }
 }
 
+/// Location of the cookie file in the default Bitcoin Core data directory, for a given network.
+/// `~/.bitcoin/<network>/.cookie` (`~/.bitcoin/.cookie` for mainnet).
+fn default_cookie_path(network: Network) -> Option<PathBuf> {
+    let bitcoin_dir = dirs::home_dir()?.join(".bitcoin");
+    let network_dir = match network {
+        Network::Bitcoin => bitcoin_dir,
+        Network::Testnet => bitcoin_dir.join("testnet3"),
+        Network::Signet => bitcoin_dir.join("signet"),
+        Network::Regtest => bitcoin_dir.join("regtest"),
+        _ => return None,
+    };
+    Some(network_dir.join(".cookie"))
+}
+
+/// Builds a client for the configured wallet endpoint, without querying the node.
+fn build_client(config: &RPCConfig) -> Result<Client, WalletError> {
+    // Without explicit credentials, fall back to the node's default cookie file if there is one.
+    let auth = match (&config.auth, default_cookie_path(config.network)) {
+        (Auth::None, Some(cookie_path)) if cookie_path.exists() => {
+            log::info!("Using RPC cookie file at {}", cookie_path.display());
+            Auth::CookieFile(cookie_path)
+        }
+        (auth, _) => auth.clone(),
+    };
+    let url = format!(
+        "http://{}/wallet/{}",
+        config.url.as_str(),
+        config.wallet_name.as_str()
+    );
+    let timeout = match config.timeout {
+        Some(timeout) => timeout,
+        None => return Ok(Client::new(&url, auth)?),
+    };
+
+    let (user, pass) = auth.get_user_pass()?;
+    let mut builder = simple_http::Builder::new()
+        .url(&url)
+        .map_err(|e| RpcError::JsonRpc(e.into()))?
+        .timeout(timeout);
+    if let Some(user) = user {
+        builder = builder.auth(user, pass);
+    }
+    Ok(Client::from_jsonrpc(jsonrpc::Client::with_transport(
+        builder.build(),
+    )))
+}
+
+/// Checks the node behind `rpc` runs on the configured network, with a supported version.
+fn validate_node(rpc: &Client, config: &RPCConfig) -> Result<(), WalletError> {
+    let node_network = rpc.get_blockchain_info()?.chain;
+    if config.network != node_network {
+        return Err(WalletError::NetworkMismatch {
+            expected: config.network,
+            found: node_network,
+        });
+    }
+    check_core_version(rpc.version()? as u32)
+}
+
 impl TryFrom<&RPCConfig> for Client {
     type Error = WalletError;
     fn try_from(config: &RPCConfig) -> Result<Self, WalletError> {
-        let rpc = Client::new(
-            format!(
-                "http://{}/wallet/{}",
-                config.url.as_str(),
-                config.wallet_name.as_str()
-            )
-            .as_str(),
-            config.auth.clone(),
-        )?;
-        if config.network != rpc.get_blockchain_info()?.chain {
-            return Err(WalletError::Protocol(
-                "RPC Network not mathcing with RPCConfig".to_string(),
-            ));
-        }
+        let rpc = build_client(config)?;
+        validate_node(&rpc, config)?;
         Ok(rpc)
     }
 }
 
-fn list_wallet_dir(client: &Client) -> Result<Vec<String>, WalletError> {
-    #[derive(Deserialize)]
-    struct Name {
-        name: String,
+/// A pool of reusable RPC [`Client`]s, keyed by node url, authentication and wallet name.
+///
+/// Clients are built lazily on first request. The node is validated (network and version) once per
+/// node url, instead of on every client creation.
+#[derive(Default)]
+pub struct RpcPool {
+    clients: Mutex<HashMap<PoolKey, Arc<Client>>>,
+    validated_nodes: Mutex<HashSet<(String, Network)>>,
+}
+
+impl RpcPool {
+    /// Creates an empty pool.
+    pub fn new() -> Self {
+        Self::default()
     }
+
+    /// Gets the client for the configured wallet, creating it if it's not pooled yet.
+    pub fn get(&self, config: &RPCConfig) -> Result<Arc<Client>, WalletError> {
+        let mut clients = self
+            .clients
+            .lock()
+            .map_err(|_| WalletError::Protocol("RPC pool lock poisoned".to_string()))?;
+        if let Some(client) = clients.get(&PoolKey::from(config)) {
+            return Ok(client.clone());
+        }
+
+        let client = build_client(config)?;
+        {
+            let mut validated_nodes = self
+                .validated_nodes
+                .lock()
+                .map_err(|_| WalletError::Protocol("RPC pool lock poisoned".to_string()))?;
+            let node = (config.url.clone(), config.network);
+            if !validated_nodes.contains(&node) {
+                validate_node(&client, config)?;
+                validated_nodes.insert(node);
+            }
+        }
+
+        let client = Arc::new(client);
+        clients.insert(PoolKey::from(config), client.clone());
+        Ok(client)
+    }
+
+    /// Drops the pooled client of the configured wallet, so the next [`RpcPool::get`] reconnects.
+    pub fn evict(&self, config: &RPCConfig) -> Result<(), WalletError> {
+        self.clients
+            .lock()
+            .map_err(|_| WalletError::Protocol("RPC pool lock poisoned".to_string()))?
+            .remove(&PoolKey::from(config));
+        Ok(())
+    }
+}
+
+/// What makes two [`RPCConfig`]s share a pooled client: the same wallet, on the same node, with
+/// the same credentials.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PoolKey {
+    url: String,
+    auth: Auth,
+    wallet_name: String,
+}
+
+impl From<&RPCConfig> for PoolKey {
+    fn from(config: &RPCConfig) -> Self {
+        Self {
+            url: config.url.clone(),
+            auth: config.auth.clone(),
+            wallet_name: config.wallet_name.clone(),
+        }
+    }
+}
+
+/// Minimum supported Bitcoin Core version (22.0). Coinswap needs watch-only descriptor wallets,
+/// and uses `listdescriptors` to track what's imported.
+pub const MINIMUM_CORE_VERSION: u32 = 220_000;
+
+/// Rejects Bitcoin Core versions below [`MINIMUM_CORE_VERSION`].
+fn check_core_version(version: u32) -> Result<(), WalletError> {
+    if version < MINIMUM_CORE_VERSION {
+        return Err(WalletError::UnsupportedCoreVersion {
+            found: version,
+            minimum: MINIMUM_CORE_VERSION,
+        });
+    }
+    Ok(())
+}
+
+/// Checks that a wallet name can safely be used as a Bitcoin Core wallet name.
+/// Rejects empty names, path separators, null bytes and leading dots.
+fn validate_wallet_name(wallet_name: &str) -> Result<(), WalletError> {
+    if wallet_name.is_empty()
+        || wallet_name.starts_with('.')
+        || wallet_name.contains(['/', '\\', '\0'])
+    {
+        return Err(WalletError::InvalidWalletName(wallet_name.to_string()));
+    }
+    Ok(())
+}
+
+/// Builds the `createwallet` RPC call for a watch-only descriptor wallet. Nodes below
+/// [`MINIMUM_CORE_VERSION`] are rejected before, all supported ones take these arguments.
+/// Returns the method name and its arguments.
+fn build_createwallet_args(wallet_name: &str) -> (String, Vec<Value>) {
+    // TODO: move back to api call when https://github.com/rust-bitcoin/rust-bitcoincore-rpc/issues/225 is closed
+    let args = vec![
+        Value::String(wallet_name.to_string()),
+        Value::Bool(true),  // Disable Private Keys
+        Value::Bool(false), // Create a blank wallet
+        Value::Null,        // Optional Passphrase
+        Value::Bool(false), // Avoid Reuse
+        Value::Bool(true),  // Descriptor Wallet
+    ];
+    ("createwallet".to_string(), args)
+}
+
+/// A wallet found in the node's wallet directory.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct WalletDirEntry {
+    /// The wallet name.
+    pub name: String,
+    /// Warnings about the wallet. Only reported by newer Bitcoin Core versions.
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+fn list_wallet_dir(client: &Client) -> Result<Vec<WalletDirEntry>, WalletError> {
     #[derive(Deserialize)]
     struct CallResult {
-        wallets: Vec<Name>,
+        wallets: Vec<WalletDirEntry>,
     }
 
     let result: CallResult = client.call("listwalletdir", &[])?;
-    Ok(result.wallets.into_iter().map(|n| n.name).collect())
+    Ok(result.wallets)
+}
+
+/// Checks if a wallet with the given name exists in the node's wallet directory.
+fn wallet_dir_contains(client: &Client, wallet_name: &str) -> Result<bool, WalletError> {
+    Ok(list_wallet_dir(client)?
+        .iter()
+        .any(|entry| entry.name == wallet_name))
+}
+
+/// Lists the descriptors already imported in the core wallet, stripped of their checksums.
+/// Ranged descriptors map to the end of their imported range.
+pub(super) fn list_imported_descriptors(
+    client: &Client,
+) -> Result<HashMap<String, Option<u32>>, WalletError> {
+    #[derive(Deserialize)]
+    struct Desc {
+        desc: String,
+        #[serde(default)]
+        range: Option<[u32; 2]>,
+    }
+    #[derive(Deserialize)]
+    struct CallResult {
+        descriptors: Vec<Desc>,
+    }
+
+    let result: CallResult = client.call("listdescriptors", &[])?;
+    Ok(result
+        .descriptors
+        .into_iter()
+        .map(|d| {
+            (
+                strip_checksum(&d.desc).to_string(),
+                d.range.map(|[_, end]| end),
+            )
+        })
+        .collect())
+}
+
+pub(super) fn strip_checksum(descriptor: &str) -> &str {
+    descriptor.split('#').next().unwrap_or(descriptor)
+}
+
+/// Create or load the watch-only core wallet named `wallet_name`.
+pub(super) fn load_or_create_core_wallet(
+    client: &Client,
+    wallet_name: &str,
+) -> Result<(), WalletError> {
+    validate_wallet_name(wallet_name)?;
+    if client.list_wallets()?.iter().any(|w| w == wallet_name) {
+        log::info!("wallet already loaded: {}", wallet_name);
+    } else if wallet_dir_contains(client, wallet_name)? {
+        client.load_wallet(wallet_name)?;
+        log::info!("wallet loaded: {}", wallet_name);
+    } else {
+        let (method, args) = build_createwallet_args(wallet_name);
+        let _: Value = client.call(&method, &args)?;
+
+        log::info!("wallet created: {}", wallet_name);
+    }
+    Ok(())
+}
+
+/// Send the `importdescriptors` requests built for `descriptors`, in chunks of `batch_size`.
+/// Stops at the first failing chunk.
+pub(super) fn send_import_requests(
+    client: &Client,
+    descriptors: &[String],
+    requests: &[Value],
+    batch_size: usize,
+) -> Result<(), WalletError> {
+    let batch_size = batch_size.max(1);
+    for (chunk_index, (chunk, chunk_requests)) in descriptors
+        .chunks(batch_size)
+        .zip(requests.chunks(batch_size))
+        .enumerate()
+    {
+        let results: Vec<Value> = client
+            .call("importdescriptors", &[Value::from(chunk_requests)])
+            .map_err(|e| WalletError::ImportBatch {
+                chunk: chunk_index,
+                error: Box::new(e.into()),
+            })?;
+
+        // Core reports the outcome of each descriptor separately, in the request order.
+        for (descriptor, result) in chunk.iter().zip(results.iter()) {
+            if result["success"].as_bool() != Some(true) {
+                // Surface Core's error object as an RPC error, so callers can match on its code.
+                let error: WalletError = match serde_json::from_value::<jsonrpc::error::RpcError>(
+                    result["error"].clone(),
+                ) {
+                    Ok(e) => RpcError::JsonRpc(jsonrpc::Error::Rpc(e)).into(),
+                    Err(e) => e.into(),
+                };
+                return Err(WalletError::ImportBatch {
+                    chunk: chunk_index,
+                    error: Box::new(WalletError::descriptor_import(descriptor, error)),
+                });
+            }
+        }
+        log::debug!(
+            target: SYNC_LOG_TARGET,
+            "Imported descriptor chunk {} ({} descriptors)",
+            chunk_index,
+            chunk.len()
+        );
+    }
+    Ok(())
+}
+
+/// The scripts imported into the core wallet, detached from the [`Wallet`] so the incremental
+/// sync can run on another thread.
+struct TrackedScripts {
+    /// HD descriptors, derived up to `last_index`.
+    descriptors: Vec<String>,
+    last_index: u32,
+    /// Swapcoin and fidelity bond scriptpubkeys.
+    scriptpubkeys: HashSet<ScriptBuf>,
+}
+
+impl TrackedScripts {
+    /// All the tracked scriptpubkeys, deriving the HD ones through the backend.
+    fn resolve(&self, backend: &dyn ChainBackend) -> Result<HashSet<ScriptBuf>, WalletError> {
+        let mut spks = self.scriptpubkeys.clone();
+        for descriptor in &self.descriptors {
+            spks.extend(backend.derive_scriptpubkeys(descriptor, self.last_index)?);
+        }
+        Ok(spks)
+    }
+}
+
+/// Walk the blocks mined after `last_synced_height` and rescan only those paying to, or spending from,
+/// the tracked scripts. Blocks within the [`RPCConfig::confirmation_buffer`] of the tip are left for a
+/// later walk. Returns the new synced height, or `None` if no block was mined since.
+fn rescan_new_blocks(
+    backend: &dyn ChainBackend,
+    confirmation_buffer: u64,
+    tracked: &TrackedScripts,
+    last_synced_height: u64,
+) -> Result<Option<u64>, WalletError> {
+    let node_synced = backend
+        .get_block_count()?
+        .saturating_sub(confirmation_buffer);
+    if node_synced <= last_synced_height {
+        return Ok(None);
+    }
+
+    let tracked_spks = tracked.resolve(backend)?;
+    let mut tracked_outpoints = backend
+        .list_unspent()?
+        .into_iter()
+        .map(|u| OutPoint {
+            txid: u.txid,
+            vout: u.vout,
+        })
+        .collect::<HashSet<_>>();
+
+    log::info!(
+        target: SYNC_LOG_TARGET,
+        "Incremental sync from:{} to:{}",
+        last_synced_height + 1,
+        node_synced
+    );
+    for height in (last_synced_height + 1)..=node_synced {
+        let block = backend.get_block(height)?;
+        if block_touches_wallet(&block, &tracked_spks, &mut tracked_outpoints) {
+            log::debug!(target: SYNC_LOG_TARGET, "Block {} touches the wallet, rescanning it", height);
+            backend.rescan(height, height, None)?;
+        }
+    }
+
+    Ok(Some(node_synced))
+}
+
+/// Whether `block` pays to `tracked_spks` or spends `tracked_outpoints`. The outputs it pays are
+/// added to `tracked_outpoints`, a coin received and spent within a walk is seen spent.
+fn block_touches_wallet(
+    block: &Block,
+    tracked_spks: &HashSet<ScriptBuf>,
+    tracked_outpoints: &mut HashSet<OutPoint>,
+) -> bool {
+    let mut is_relevant = false;
+    for tx in &block.txdata {
+        is_relevant |= tx
+            .input
+            .iter()
+            .any(|i| tracked_outpoints.contains(&i.previous_output));
+        let txid = tx.compute_txid();
+        for (vout, output) in tx.output.iter().enumerate() {
+            if tracked_spks.contains(&output.script_pubkey) {
+                is_relevant = true;
+                tracked_outpoints.insert(OutPoint::new(txid, vout as u32));
+            }
+        }
+    }
+    is_relevant
+}
+
+/// End of a rescan starting at `start_height`: the chain tip, minus the confirmation buffer.
+fn rescan_end(
+    backend: &dyn ChainBackend,
+    start_height: u64,
+    confirmation_buffer: u64,
+) -> Result<u64, WalletError> {
+    Ok(backend
+        .get_block_count()?
+        .saturating_sub(confirmation_buffer)
+        .max(start_height))
+}
+
+/// Run the blocking `f` on tokio's blocking thread pool, with a shared RPC connection or backend.
+#[cfg(feature = "tokio")]
+async fn run_blocking<C, T, F>(client: &Arc<C>, f: F) -> Result<T, WalletError>
+where
+    C: ?Sized + Send + Sync + 'static,
+    T: Send + 'static,
+    F: FnOnce(&C) -> Result<T, WalletError> + Send + 'static,
+{
+    let client = Arc::clone(client);
+    tokio::task::spawn_blocking(move || f(&client))
+        .await
+        .map_err(|e| WalletError::Protocol(format!("Blocking RPC task failed: {}", e)))?
 }
 
 impl Wallet {
-    /// Sync the wallet with the configured Bitcoin Core RPC. Save data to disk.
+    /// Sync the wallet with its [`ChainBackend`]. Save data to disk.
     pub fn sync(&mut self) -> Result<(), WalletError> {
-        // Create or load the watch-only bitcoin core wallet
+        self.sync_with_progress(None)
+    }
+
+    /// Same as [`Wallet::sync`], but reports the rescan progress to the given callback.
+    ///
+    /// The callback is always invoked from the calling thread, see [`ChainBackend::rescan`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "sync",
+            skip_all,
+            fields(
+                wallet_name = %self.store.file_name,
+                from_height = self.store.last_synced_height,
+                to_height = tracing::field::Empty,
+            )
+        )
+    )]
+    pub fn sync_with_progress(
+        &mut self,
+        progress: Option<&dyn Fn(ScanProgress)>,
+    ) -> Result<(), WalletError> {
+        self.backend.open_wallet(&self.store.file_name)?;
+
+        // Funds found near the end of the imported range extend it, repeat until nothing new is found.
+        loop {
+            let plan = self.plan_sync()?;
+
+            if !plan.descriptors_to_import.is_empty() {
+                log::debug!(target: SYNC_LOG_TARGET, "Importing Wallet spks/descriptors");
+                self.import_descriptors(&plan.descriptors_to_import, None)?;
+            }
+            self.refresh_fidelity_confirmations()?;
+
+            match (
+                plan.rescan_from,
+                self.rpc_config.sync_mode,
+                self.store.last_synced_height,
+            ) {
+                (Some(rescan_from), _, _) => {
+                    // Now run the scan
+                    log::debug!(target: SYNC_LOG_TARGET, "Initializing TxOut scan. This may take a while.");
+                    let node_synced = self.rescan_with_retry(
+                        rescan_from,
+                        plan.descriptors_to_import.len(),
+                        progress,
+                    )?;
+                    self.set_last_synced_height(node_synced)?;
+                }
+                (None, SyncMode::Incremental, Some(last_synced_height)) => {
+                    self.sync_new_blocks(last_synced_height)?
+                }
+                _ => return Ok(()),
+            }
+
+            if !self.update_hd_indices()? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Moves the address indices past the last addresses holding funds. Returns true if the
+    /// import range grew as a result, so the new addresses need importing and scanning.
+    fn update_hd_indices(&mut self) -> Result<bool, WalletError> {
+        let import_count = self.get_addrss_import_count();
+
+        let max_external_index = self.find_hd_next_index(KeychainKind::External)?;
+        self.update_external_index(max_external_index.max(self.store.external_index))?;
+        let max_internal_index = self.find_hd_next_index(KeychainKind::Internal)?;
+        self.store.internal_index = max_internal_index.max(self.store.internal_index);
+        self.update_existing_descriptor_index()?;
+
+        Ok(self.get_addrss_import_count() > import_count)
+    }
+
+    /// Moves the address indices past the addresses holding funds, importing and rescanning the
+    /// addresses beyond the import range from the wallet birthday, until no new funds turn up.
+    ///
+    /// Unlike [`Wallet::sync`], which only rescans the blocks it hasn't seen yet, this finds the
+    /// whole history of the newly imported addresses. For restored wallets.
+    pub(super) fn discover_hd_indices(&mut self) -> Result<(), WalletError> {
+        while self.update_hd_indices()? {
+            let descriptors_to_import = self.descriptors_to_import()?;
+            if !descriptors_to_import.is_empty() {
+                self.import_descriptors(&descriptors_to_import, None)?;
+            }
+            self.rescan_from(self.store.wallet_birthday.unwrap_or(0))?;
+        }
+        self.save_to_disk()
+    }
+
+    /// Same as [`Wallet::sync`], for tokio based applications.
+    ///
+    /// Imports, rescans and block walks run on the [`ChainBackend`], through
+    /// [`tokio::task::spawn_blocking`]. The rescan retries are awaited with [`tokio::time::sleep`].
+    /// Planning the sync, refreshing the fidelity bond confirmations and updating the address index
+    /// need the wallet itself, and still run on the calling task. These are a few short RPC calls.
+    #[cfg(feature = "tokio")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "sync",
+            skip_all,
+            fields(
+                wallet_name = %self.store.file_name,
+                from_height = self.store.last_synced_height,
+                to_height = tracing::field::Empty,
+            )
+        )
+    )]
+    pub async fn sync_async(&mut self) -> Result<(), WalletError> {
+        let wallet_name = self.store.file_name.clone();
+        run_blocking(&self.backend, move |backend| {
+            backend.open_wallet(&wallet_name)
+        })
+        .await?;
+
+        loop {
+            let plan = self.plan_sync()?;
+
+            if !plan.descriptors_to_import.is_empty() {
+                log::debug!(target: SYNC_LOG_TARGET, "Importing Wallet spks/descriptors");
+                let descriptors = plan.descriptors_to_import.clone();
+                let range_end = self.get_addrss_import_count() - 1;
+                let label = self.get_core_wallet_label();
+                run_blocking(&self.backend, move |backend| {
+                    backend.import_descriptors(&descriptors, range_end, &label)
+                })
+                .await?;
+            }
+            self.refresh_fidelity_confirmations()?;
+
+            match (
+                plan.rescan_from,
+                self.rpc_config.sync_mode,
+                self.store.last_synced_height,
+            ) {
+                (Some(rescan_from), _, _) => {
+                    log::debug!(target: SYNC_LOG_TARGET, "Initializing TxOut scan. This may take a while.");
+                    let node_synced = self.rescan_with_retry_async(rescan_from).await?;
+                    self.set_last_synced_height(node_synced)?;
+                }
+                (None, SyncMode::Incremental, Some(last_synced_height)) => {
+                    let tracked = self.tracked_scripts()?;
+                    let confirmation_buffer = self.rpc_config.confirmation_buffer;
+                    let node_synced = run_blocking(&self.backend, move |backend| {
+                        rescan_new_blocks(
+                            backend,
+                            confirmation_buffer,
+                            &tracked,
+                            last_synced_height,
+                        )
+                    })
+                    .await?;
+                    if let Some(node_synced) = node_synced {
+                        self.set_last_synced_height(node_synced)?;
+                        self.save_to_disk()?;
+                    }
+                }
+                _ => return Ok(()),
+            }
+
+            if !self.update_hd_indices()? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Checks the node and the core wallet, without syncing. Cheap enough to back a liveness
+    /// or readiness probe.
+    ///
+    /// An unreachable node is reported in the status, not as an error. Wallets on another
+    /// [`ChainBackend`] have no core wallet to load, and are never reported as scanning.
+    pub fn health_check(&self) -> Result<HealthStatus, WalletError> {
+        let tip_height = match self.backend.get_block_count() {
+            Ok(height) => height,
+            Err(WalletError::Rpc(RpcError::JsonRpc(jsonrpc::Error::Transport(e)))) => {
+                log::warn!("Health check: node unreachable: {}", e);
+                return Ok(HealthStatus {
+                    node_reachable: false,
+                    tip_height: None,
+                    wallet_loaded: false,
+                    scanning: false,
+                });
+            }
+            Err(e) => return Err(e),
+        };
+
+        let (wallet_loaded, scanning) = match &self.rpc {
+            Some(rpc) => {
+                let wallet_loaded = rpc.list_wallets()?.contains(&self.store.file_name);
+                let scanning = wallet_loaded
+                    && matches!(
+                        rpc.get_wallet_info()?.scanning,
+                        Some(ScanningDetails::Scanning { .. })
+                    );
+                (wallet_loaded, scanning)
+            }
+            None => (true, false),
+        };
+
+        Ok(HealthStatus {
+            node_reachable: true,
+            tip_height: Some(tip_height),
+            wallet_loaded,
+            scanning,
+        })
+    }
+
+    /// Lists the wallets available in the node's wallet directory.
+    pub fn list_node_wallets(&self) -> Result<Vec<WalletDirEntry>, WalletError> {
+        list_wallet_dir(self.core_rpc()?)
+    }
+
+    /// Force a rescan from `start_height` up to the node's tip, less the confirmation buffer. Useful when funds are known to have
+    /// arrived in a range the wallet missed. `start_height` is clamped to the node's current height.
+    ///
+    /// Uses the same [`RescanRetryPolicy`] as [`Wallet::sync`], and only moves the last synced height forward.
+    pub fn rescan_from(&mut self, start_height: u64) -> Result<(), WalletError> {
+        let start_height = start_height.min(self.backend.get_block_count()?);
+        let node_synced = self.rescan_with_retry(start_height, 0, None)?;
+        if self.store.last_synced_height < Some(node_synced) {
+            self.set_last_synced_height(node_synced)?;
+            self.save_to_disk()?;
+        }
+        Ok(())
+    }
+
+    /// Reports what [`Wallet::sync`] would import and rescan, without doing either.
+    ///
+    /// The core wallet is loaded if it exists on the node, but never created.
+    pub fn sync_dry_run(&self) -> Result<SyncPlan, WalletError> {
         let wallet_name = &self.store.file_name;
-        if self.rpc.list_wallets()?.contains(wallet_name) {
-            log::info!("wallet already loaded: {}", wallet_name);
-        } else if list_wallet_dir(&self.rpc)?.contains(wallet_name) {
-            self.rpc.load_wallet(wallet_name)?;
-            log::info!("wallet loaded: {}", wallet_name);
-        } else {
-            // pre-0.21 use legacy wallets
-            if self.rpc.version()? < 210_000 {
-                self.rpc
-                    .create_wallet(wallet_name, Some(true), None, None, None)?;
-            } else {
-                // TODO: move back to api call when https://github.com/rust-bitcoin/rust-bitcoincore-rpc/issues/225 is closed
-                let args = [
-                    Value::String(wallet_name.clone()),
-                    Value::Bool(true),  // Disable Private Keys
-                    Value::Bool(false), // Create a blank wallet
-                    Value::Null,        // Optional Passphrase
-                    Value::Bool(false), // Avoid Reuse
-                    Value::Bool(true),  // Descriptor Wallet
-                ];
-                let _: Value = self.rpc.call("createwallet", &args)?;
-            }
-
-            log::info!("wallet created: {}", wallet_name);
+        if let Some(rpc) = &self.rpc {
+            validate_wallet_name(wallet_name)?;
+            if !rpc.list_wallets()?.contains(wallet_name) {
+                if wallet_dir_contains(rpc, wallet_name)? {
+                    rpc.load_wallet(wallet_name)?;
+                    log::info!("wallet loaded: {}", wallet_name);
+                } else {
+                    return Err(WalletError::Protocol(format!(
+                        "Core wallet {} does not exist yet, it will be created on the first sync",
+                        wallet_name
+                    )));
+                }
+            }
         }
 
+        let plan = self.plan_sync()?;
+        log::info!(
+            target: SYNC_LOG_TARGET,
+            "Sync dry run: {} descriptors to import, rescan from: {:?}",
+            plan.descriptors_to_import.len(),
+            plan.rescan_from
+        );
+        Ok(plan)
+    }
+
+    /// Collects the descriptors to import, and decides whether a full rescan is needed.
+    fn plan_sync(&self) -> Result<SyncPlan, WalletError> {
         let descriptors_to_import = self.descriptors_to_import()?;
+        let birthday = self.store.wallet_birthday.unwrap_or(0);
+
+        // Freshly imported descriptors need a rescan to pick up their history.
+        let import_rescan_from = if descriptors_to_import.is_empty() {
+            None
+        } else {
+            Some(self.store.last_synced_height.unwrap_or(0).max(birthday))
+        };
+        // Blocks above the fork point may have been replaced, rescan them too.
+        let reorg_rescan_from = self.find_reorg_fork()?.map(|fork| fork.max(birthday));
+        // Blocks a previous rescan left in the confirmation buffer, once the chain grew past them.
+        // Incremental syncs walk them anyway.
+        let confirmation_buffer = self.rpc_config.confirmation_buffer;
+        let buffered_rescan_from = match (self.rpc_config.sync_mode, self.store.last_synced_height)
+        {
+            (SyncMode::Full, Some(synced)) if confirmation_buffer > 0 => {
+                let buried_height = self
+                    .backend
+                    .get_block_count()?
+                    .saturating_sub(confirmation_buffer);
+                (synced < buried_height).then_some(synced + 1)
+            }
+            _ => None,
+        };
+
+        let rescan_from = [import_rescan_from, reorg_rescan_from, buffered_rescan_from]
+            .iter()
+            .flatten()
+            .min()
+            .copied();
+
+        Ok(SyncPlan {
+            descriptors_to_import,
+            rescan_from,
+        })
+    }
 
-        if descriptors_to_import.is_empty() {
-            return Ok(());
+    /// Checks that the last synced block is still in the node's active chain. If it got reorged out,
+    /// walks its branch back and returns the height of the fork point.
+    ///
+    /// Falls back to the wallet birthday if the node doesn't know the last synced block at all.
+    fn find_reorg_fork(&self) -> Result<Option<u64>, WalletError> {
+        let (synced_height, synced_hash) =
+            match (self.store.last_synced_height, self.store.last_synced_hash) {
+                (Some(height), Some(hash)) => (height, hash),
+                _ => return Ok(None),
+            };
+        if self.backend.get_block_count()? >= synced_height
+            && self.backend.get_block_hash(synced_height)? == synced_hash
+        {
+            return Ok(None);
         }
 
-        log::debug!("Importing Wallet spks/descriptors");
+        let mut header = match self.backend.get_block_header(&synced_hash)? {
+            Some(header) => header,
+            None => {
+                let fork_height = self.store.wallet_birthday.unwrap_or(0);
+                log::warn!(
+                    target: SYNC_LOG_TARGET,
+                    "ReorgDetected: last synced block {} at height {} is unknown to the node, rescanning from height {}",
+                    synced_hash,
+                    synced_height,
+                    fork_height
+                );
+                return Ok(Some(fork_height));
+            }
+        };
+        // Blocks out of the active chain have -1 confirmations.
+        while header.confirmations < 0 {
+            let previous_hash = header.previous_block_hash.ok_or_else(|| {
+                WalletError::Protocol(format!("Stale block {} has no parent", header.hash))
+            })?;
+            header = self
+                .backend
+                .get_block_header(&previous_hash)?
+                .ok_or_else(|| {
+                    WalletError::Protocol(format!(
+                        "Parent {} of a stale block is unknown",
+                        previous_hash
+                    ))
+                })?;
+        }
 
-        self.import_descriptors(&descriptors_to_import, None)?;
+        let fork_height = header.height as u64;
+        log::warn!(
+            target: SYNC_LOG_TARGET,
+            "ReorgDetected: last synced block {} at height {} is no longer in the active chain, fork point at height {}",
+            synced_hash,
+            synced_height,
+            fork_height
+        );
+        Ok(Some(fork_height))
+    }
 
-        // Now run the scan
-        log::debug!("Initializing TxOut scan. This may take a while.");
+    /// Records `height` as the last synced height, along with the node's block hash at that height.
+    pub(super) fn set_last_synced_height(&mut self, height: u64) -> Result<(), WalletError> {
+        let hash = self.backend.get_block_hash(height)?;
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record("to_height", height);
+        self.store.last_synced_height = Some(height);
+        self.store.last_synced_hash = Some(hash);
+        Ok(())
+    }
 
-        // Sometimes in test multiple wallet scans can occur at same time, resulting in error.
-        // Just retry after 3 sec.
+    /// Rescan from `start_height` up to the chain tip, less the [`RPCConfig::confirmation_buffer`],
+    /// retrying as per the configured [`RescanRetryPolicy`].
+    /// Returns the height the wallet got synced to.
+    fn rescan_with_retry(
+        &self,
+        start_height: u64,
+        descriptors_imported: usize,
+        progress: Option<&dyn Fn(ScanProgress)>,
+    ) -> Result<u64, WalletError> {
+        let policy = self.rpc_config.rescan_retry_policy;
+        let mut attempt = 0;
         loop {
-            let last_synced_height = self
-                .store
-                .last_synced_height
-                .unwrap_or(0)
-                .max(self.store.wallet_birthday.unwrap_or(0));
-            let node_synced = self.rpc.get_block_count()?;
-            log::info!(
-                "rescan_blockchain from:{} to:{}",
-                last_synced_height,
-                node_synced
+            attempt += 1;
+            let node_synced = rescan_end(
+                self.backend.as_ref(),
+                start_height,
+                self.rpc_config.confirmation_buffer,
+            )?;
+            log::info!(target: SYNC_LOG_TARGET, "rescan_blockchain from:{} to:{}", start_height, node_synced);
+            let report = |current_height| {
+                if let Some(progress) = progress {
+                    progress(ScanProgress {
+                        current_height,
+                        target_height: node_synced,
+                        descriptors_imported,
+                    })
+                }
+            };
+            let result = self.backend.rescan(
+                start_height,
+                node_synced,
+                progress.map(|_| &report as &dyn Fn(u64)),
             );
-            match self.rpc.rescan_blockchain(
-                Some(last_synced_height as usize),
-                Some(node_synced as usize),
-            ) {
-                Ok(_) => {
-                    self.store.last_synced_height = Some(node_synced);
-                    break;
+            match result {
+                Ok(_) => return Ok(node_synced),
+                Err(e) => {
+                    if policy.exhausted(attempt) {
+                        return Err(WalletError::Protocol(format!(
+                            "Rescan failed after {} attempts: {:?}",
+                            attempt, e
+                        )));
+                    }
+                    let delay = policy.delay_for(attempt);
+                    log::warn!(target: SYNC_LOG_TARGET, "Sync Error, Retrying in {:?}: {:?}", delay, e);
+                    thread::sleep(delay);
                 }
+            }
+        }
+    }
 
+    /// Same as [`Wallet::rescan_with_retry`], without progress reporting, awaiting the rescan
+    /// and the retry delays instead of blocking the thread.
+    #[cfg(feature = "tokio")]
+    async fn rescan_with_retry_async(&self, start_height: u64) -> Result<u64, WalletError> {
+        let policy = self.rpc_config.rescan_retry_policy;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let confirmation_buffer = self.rpc_config.confirmation_buffer;
+            let node_synced = run_blocking(&self.backend, move |backend| {
+                rescan_end(backend, start_height, confirmation_buffer)
+            })
+            .await?;
+            log::info!(target: SYNC_LOG_TARGET, "rescan_blockchain from:{} to:{}", start_height, node_synced);
+            let result = run_blocking(&self.backend, move |backend| {
+                backend.rescan(start_height, node_synced, None)
+            })
+            .await;
+            match result {
+                Ok(_) => return Ok(node_synced),
                 Err(e) => {
-                    log::warn!("Sync Error, Retrying: {}", e);
-                    thread::sleep(Duration::from_secs(3));
-                    continue;
+                    if policy.exhausted(attempt) {
+                        return Err(WalletError::Protocol(format!(
+                            "Rescan failed after {} attempts: {:?}",
+                            attempt, e
+                        )));
+                    }
+                    let delay = policy.delay_for(attempt);
+                    log::warn!(target: SYNC_LOG_TARGET, "Sync Error, Retrying in {:?}: {:?}", delay, e);
+                    tokio::time::sleep(delay).await;
                 }
             }
         }
+    }
 
-        let max_external_index = self.find_hd_next_index(KeychainKind::External)?;
-        self.update_external_index(max_external_index)?;
+    /// Walk the blocks mined after `last_synced_height` and rescan only those paying to, or spending from,
+    /// the wallet. The matching is done locally against [`Wallet::tracked_scripts`].
+    fn sync_new_blocks(&mut self, last_synced_height: u64) -> Result<(), WalletError> {
+        let tracked = self.tracked_scripts()?;
+        if let Some(node_synced) = rescan_new_blocks(
+            self.backend.as_ref(),
+            self.rpc_config.confirmation_buffer,
+            &tracked,
+            last_synced_height,
+        )? {
+            self.set_last_synced_height(node_synced)?;
+            self.save_to_disk()?;
+        }
         Ok(())
     }
 
-    /// Import watch addresses into core wallet. Does not check if the address was already imported.
+    /// All the scripts imported into the core wallet by [`Wallet::sync`].
+    fn tracked_scripts(&self) -> Result<TrackedScripts, WalletError> {
+        let mut spks = HashSet::new();
+
+        for sc in self.store.incoming_swapcoins.values() {
+            spks.insert(redeemscript_to_scriptpubkey(
+                &sc.get_multisig_redeemscript(),
+            ));
+            spks.insert(redeemscript_to_scriptpubkey(&sc.contract_redeemscript));
+        }
+        for sc in self.store.outgoing_swapcoins.values() {
+            spks.insert(redeemscript_to_scriptpubkey(
+                &sc.get_multisig_redeemscript(),
+            ));
+            spks.insert(redeemscript_to_scriptpubkey(&sc.contract_redeemscript));
+        }
+
+        spks.extend(
+            self.store
+                .fidelity_bond
+                .values()
+                .map(|(_, spk, _)| spk.clone()),
+        );
+
+        let mut descriptors = self
+            .get_wallet_descriptors()?
+            .into_values()
+            .collect::<Vec<_>>();
+        match &self.store.existing_descriptor {
+            Some(existing_descriptor) if existing_descriptor.is_ranged() => {
+                descriptors.push(existing_descriptor.public_descriptor.clone())
+            }
+            Some(existing_descriptor) => {
+                spks.insert(existing_descriptor.script_pubkey(None)?);
+            }
+            None => {}
+        }
+
+        Ok(TrackedScripts {
+            descriptors,
+            last_index: self.get_addrss_import_count() - 1,
+            scriptpubkeys: spks,
+        })
+    }
+
+    /// Import watch addresses into the [`ChainBackend`]. Does not check if the address was already imported.
+    ///
+    /// Ranged descriptors are imported up to the current address import count, the others get
+    /// `address_label`, by default the [`Wallet::get_core_wallet_label`].
     pub fn import_descriptors(
         &self,
         descriptors_to_import: &[String],
         address_label: Option<String>,
     ) -> Result<(), WalletError> {
         let address_label = address_label.unwrap_or(self.get_core_wallet_label());
+        self.backend.import_descriptors(
+            descriptors_to_import,
+            self.get_addrss_import_count() - 1,
+            &address_label,
+        )
+    }
+}
 
-        let import_requests = descriptors_to_import
-            .iter()
-            .map(|desc| {
-                if desc.contains("/*") {
-                    return json!({
-                        "timestamp": "now",
-                        "desc": desc,
-                        "range": (self.get_addrss_import_count() - 1)
-                    });
-                }
-                json!({
-                    "timestamp": "now",
-                    "desc": desc,
-                    "label": address_label
-                })
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_build_createwallet_args() {
+        let (method, args) = build_createwallet_args("wallet");
+        assert_eq!(method, "createwallet");
+        assert_eq!(
+            args,
+            vec![
+                json!("wallet"),
+                json!(true),
+                json!(false),
+                Value::Null,
+                json!(false),
+                json!(true)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wallet_dir_entry_deserialize() {
+        let entries: Vec<WalletDirEntry> = serde_json::from_value(json!([
+            { "name": "old-core-wallet" },
+            { "name": "new-core-wallet", "warnings": ["legacy wallet"] }
+        ]))
+        .unwrap();
+        assert_eq!(entries[0].warnings, Vec::<String>::new());
+        assert_eq!(entries[1].name, "new-core-wallet");
+        assert_eq!(entries[1].warnings, vec!["legacy wallet".to_string()]);
+    }
+
+    #[test]
+    fn test_check_core_version() {
+        assert!(check_core_version(MINIMUM_CORE_VERSION).is_ok());
+        assert!(check_core_version(260_000).is_ok());
+        assert!(matches!(
+            check_core_version(210_100),
+            Err(WalletError::UnsupportedCoreVersion {
+                found: 210_100,
+                minimum: MINIMUM_CORE_VERSION
             })
-            .collect();
-        let _res: Vec<Value> = self
-            .rpc
-            .call("importdescriptors", &[import_requests])
-            .unwrap();
-        Ok(())
+        ));
+    }
+
+    #[test]
+    fn test_validate_wallet_name() {
+        assert!(validate_wallet_name("9d317f933-taker").is_ok());
+        assert!(validate_wallet_name("maker wallet_1").is_ok());
+
+        for name in [
+            "",
+            ".hidden",
+            "../wallet",
+            "dir/wallet",
+            "dir\\wallet",
+            "wal\0let",
+        ] {
+            assert!(matches!(
+                validate_wallet_name(name),
+                Err(WalletError::InvalidWalletName(n)) if n == name
+            ));
+        }
+    }
+
+    #[test]
+    fn test_default_cookie_path() {
+        let bitcoin_dir = dirs::home_dir().unwrap().join(".bitcoin");
+        assert_eq!(
+            default_cookie_path(Network::Bitcoin),
+            Some(bitcoin_dir.join(".cookie"))
+        );
+        assert_eq!(
+            default_cookie_path(Network::Regtest),
+            Some(bitcoin_dir.join("regtest").join(".cookie"))
+        );
+        assert_eq!(
+            default_cookie_path(Network::Testnet),
+            Some(bitcoin_dir.join("testnet3").join(".cookie"))
+        );
+    }
+
+    #[test]
+    fn test_rescan_retry_delay_backoff() {
+        let policy = RescanRetryPolicy::default();
+        assert_eq!(policy.delay_for(1), Duration::from_secs(3));
+        assert_eq!(policy.delay_for(2), Duration::from_secs(6));
+        assert_eq!(policy.delay_for(3), Duration::from_secs(12));
+        assert_eq!(policy.delay_for(100), MAX_RESCAN_RETRY_DELAY);
+
+        let fixed = RescanRetryPolicy {
+            max_attempts: 0,
+            base_delay: Duration::from_secs(3),
+            backoff: 1.0,
+        };
+        assert_eq!(fixed.delay_for(1), Duration::from_secs(3));
+        assert_eq!(fixed.delay_for(u32::MAX), Duration::from_secs(3));
+
+        assert!(!policy.exhausted(4));
+        assert!(policy.exhausted(5));
+        assert!(!fixed.exhausted(u32::MAX));
+    }
+
+    #[test]
+    fn test_block_touches_wallet() {
+        use bitcoin::{
+            absolute::LockTime, blockdata::constants::genesis_block, transaction::Version, Amount,
+            Sequence, Transaction, TxIn, TxOut, Witness,
+        };
+
+        let tx = |inputs: Vec<OutPoint>, spk: ScriptBuf| Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: inputs
+                .into_iter()
+                .map(|previous_output| TxIn {
+                    previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: spk,
+            }],
+        };
+        let block = |txdata| Block {
+            header: genesis_block(Network::Regtest).header,
+            txdata,
+        };
+        let ours = ScriptBuf::from_bytes(vec![0x51]);
+        let theirs = ScriptBuf::from_bytes(vec![0x52]);
+        let tracked_spks = HashSet::from([ours.clone()]);
+        let mut tracked_outpoints = HashSet::new();
+
+        let received = tx(vec![OutPoint::null()], ours);
+        assert!(block_touches_wallet(
+            &block(vec![received.clone()]),
+            &tracked_spks,
+            &mut tracked_outpoints
+        ));
+        let received_outpoint = OutPoint::new(received.compute_txid(), 0);
+        assert!(tracked_outpoints.contains(&received_outpoint));
+
+        // A later block spending the coin received in the walk is rescanned.
+        let spend = tx(vec![received_outpoint], theirs.clone());
+        assert!(block_touches_wallet(
+            &block(vec![spend]),
+            &tracked_spks,
+            &mut tracked_outpoints
+        ));
+        let unrelated = tx(vec![OutPoint::null()], theirs);
+        assert!(!block_touches_wallet(
+            &block(vec![unrelated]),
+            &tracked_spks,
+            &mut tracked_outpoints
+        ));
+    }
+
+    #[test]
+    fn test_rpc_pool_keys() {
+        let config = RPCConfig {
+            auth: Auth::UserPass("user".to_string(), "pass".to_string()),
+            ..RPCConfig::default()
+        };
+        let other_node = RPCConfig {
+            url: "localhost:18444".to_string(),
+            ..config.clone()
+        };
+        let other_auth = RPCConfig {
+            auth: Auth::UserPass("other".to_string(), "pass".to_string()),
+            ..config.clone()
+        };
+
+        let pool = RpcPool::new();
+        // Skip the node validation, no node is running.
+        for node in [&config, &other_node] {
+            pool.validated_nodes
+                .lock()
+                .unwrap()
+                .insert((node.url.clone(), node.network));
+        }
+        let client = pool.get(&config).unwrap();
+        assert!(Arc::ptr_eq(&client, &pool.get(&config).unwrap()));
+        // Same wallet name, but another node or other credentials.
+        assert!(!Arc::ptr_eq(&client, &pool.get(&other_node).unwrap()));
+        assert!(!Arc::ptr_eq(&client, &pool.get(&other_auth).unwrap()));
+
+        pool.evict(&config).unwrap();
+        assert!(!Arc::ptr_eq(&client, &pool.get(&config).unwrap()));
     }
 }
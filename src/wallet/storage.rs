@@ -1,23 +1,86 @@
 //! The Wallet Storage Interface.
 //!
-//! Wallet data is currently written in unencrypted CBOR files which are not directly human readable.
+//! Wallet data is written in CBOR files which are not directly human readable. The file is
+//! unencrypted by default, and can be encrypted at rest with a passphrase.
 
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::{TryFrom, TryInto},
+    path::{Path, PathBuf},
+};
 
-use bitcoin::{bip32::Xpriv, Network, OutPoint, ScriptBuf};
+use argon2::Argon2;
+use bitcoin::{
+    bip32::Xpriv,
+    secp256k1::rand::{rngs::OsRng, RngCore},
+    BlockHash, Network, OutPoint, ScriptBuf,
+};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
 use serde::{Deserialize, Serialize};
+use serde_cbor::Value;
 use std::{
-    fs::OpenOptions,
-    io::{BufReader, BufWriter},
+    fs::{self, OpenOptions},
+    io::{BufReader, Read, Write},
 };
 
-use super::{error::WalletError, fidelity::FidelityBond};
+use super::{error::WalletError, existing_descriptor::ExistingDescriptor, fidelity::FidelityBond};
 
 use super::swapcoin::{IncomingSwapCoin, OutgoingSwapCoin};
 
+/// Format version of the wallet store written by this crate.
+///
+/// Bump it, and add a migration to [`MIGRATIONS`], with every change that older wallet files
+/// don't deserialize into.
+pub const WALLET_STORE_VERSION: u32 = 1;
+
+/// Upgrades of the store's CBOR value. `MIGRATIONS[v]` upgrades version `v` to `v + 1`.
+const MIGRATIONS: [fn(Value) -> Result<Value, WalletError>; WALLET_STORE_VERSION as usize] =
+    [migrate_v0_to_v1];
+
+/// Prefix of encrypted wallet files. Followed by the argon2 salt, the nonce, and the encrypted CBOR store.
+const ENCRYPTED_FILE_MAGIC: &[u8; 8] = b"CSWALENC";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Key encrypting the wallet file, derived from a passphrase with argon2.
+///
+/// Kept in memory instead of the passphrase, so that every save stays encrypted.
+#[derive(Clone)]
+pub(super) struct StoreKey {
+    salt: [u8; SALT_LEN],
+    key: [u8; 32],
+}
+
+impl StoreKey {
+    /// Derives a key from `passphrase`, with a fresh random salt.
+    pub(super) fn new(passphrase: &str) -> Result<Self, WalletError> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self::derive(passphrase, salt)
+    }
+
+    fn derive(passphrase: &str, salt: [u8; SALT_LEN]) -> Result<Self, WalletError> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| WalletError::Encryption(e.to_string()))?;
+        Ok(Self { salt, key })
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.key))
+    }
+}
+
 /// Represents the internal data store for a Bitcoin wallet.
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct WalletStore {
+    /// Format version of the store. Files written before versioning are version 0.
+    #[serde(default)]
+    pub(super) version: u32,
     /// The file name associated with the wallet store.
     pub(crate) file_name: String,
     /// Network the wallet operates on.
@@ -26,6 +89,17 @@ pub struct WalletStore {
     pub(super) master_key: Xpriv,
     /// The external index for the wallet.
     pub(super) external_index: u32,
+    /// The next unused index of the internal (change) keychain, as last seen by
+    /// [`Wallet::refresh_address_indices`](super::Wallet::refresh_address_indices).
+    #[serde(default)]
+    pub(super) internal_index: u32,
+    /// The next unused index of the swap keys, see [`Wallet::derive_swap_key`](super::Wallet::derive_swap_key).
+    #[serde(default)]
+    pub(super) swap_key_index: u32,
+    /// The descriptor the wallet was set up from, if it was created outside coinswap.
+    /// See [`Wallet::from_existing_descriptor`](super::Wallet::from_existing_descriptor).
+    #[serde(default)]
+    pub(super) existing_descriptor: Option<ExistingDescriptor>,
     /// The maximum size for an offer in the wallet.
     pub(crate) offer_maxsize: u64,
     /// Map of multisig redeemscript to incoming swapcoins.
@@ -36,8 +110,29 @@ pub struct WalletStore {
     pub(super) prevout_to_contract_map: HashMap<OutPoint, ScriptBuf>,
     /// Map for all the fidelity bond information. (index, (Bond, script_pubkey, is_spent)).
     pub(super) fidelity_bond: HashMap<u32, (FidelityBond, ScriptBuf, bool)>,
+    /// Map of fidelity bond index to the height its funding transaction confirmed at, as last seen by sync.
+    /// `None` if the funding transaction is unconfirmed, or got reorged out.
+    /// Kept apart from `fidelity_bond` so existing wallet files still load.
+    #[serde(default)]
+    pub(super) fidelity_confirmations: HashMap<u32, Option<u64>>,
+    /// Fidelity bond index to advertise, overriding the highest scoring bond.
+    #[serde(default)]
+    pub(super) advertised_fidelity_index: Option<u32>,
+    /// Outpoints reserved by the user or an ongoing swap. Never picked by coin selection.
+    #[serde(default)]
+    pub(super) locked_utxos: HashSet<OutPoint>,
+    /// Send the change of funding transactions to fresh addresses, kept apart from swap coins.
+    /// See [`Wallet::set_avoid_change_reuse`](super::Wallet::set_avoid_change_reuse).
+    #[serde(default)]
+    pub(super) avoid_change_reuse: bool,
+    /// Script pubkeys of the change isolated while `avoid_change_reuse` was set.
+    #[serde(default)]
+    pub(super) isolated_change: HashSet<ScriptBuf>,
     //TODO: Add last synced height and Wallet birthday.
     pub(super) last_synced_height: Option<u64>,
+    /// Hash of the block at `last_synced_height`, used to detect reorgs below it.
+    #[serde(default)]
+    pub(super) last_synced_hash: Option<BlockHash>,
 
     pub(super) wallet_birthday: Option<u64>,
 }
@@ -52,48 +147,284 @@ impl WalletStore {
         wallet_birthday: Option<u64>,
     ) -> Result<Self, WalletError> {
         let store = Self {
+            version: WALLET_STORE_VERSION,
             file_name,
             network,
             master_key,
             external_index: 0,
+            internal_index: 0,
+            swap_key_index: 0,
+            existing_descriptor: None,
             offer_maxsize: 0,
             incoming_swapcoins: HashMap::new(),
             outgoing_swapcoins: HashMap::new(),
             prevout_to_contract_map: HashMap::new(),
             fidelity_bond: HashMap::new(),
+            fidelity_confirmations: HashMap::new(),
+            advertised_fidelity_index: None,
+            locked_utxos: HashSet::new(),
+            avoid_change_reuse: false,
+            isolated_change: HashSet::new(),
             last_synced_height: None,
+            last_synced_hash: None,
             wallet_birthday,
         };
 
         std::fs::create_dir_all(path.parent().expect("Path should NOT be root!"))?;
-        // write: overwrites existing file.
-        // create: creates new file if doesn't exist.
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(path)?;
-        let writer = BufWriter::new(file);
-        serde_cbor::to_writer(writer, &store)?;
+        persist_atomic(path, &serde_cbor::to_vec(&store)?)?;
 
         Ok(store)
     }
 
     /// Load existing file, updates it, writes it back (errors if path doesn't exist).
     pub fn write_to_disk(&self, path: &PathBuf) -> Result<(), WalletError> {
-        let wallet_file = OpenOptions::new().write(true).open(path)?;
-        let writer = BufWriter::new(wallet_file);
-        Ok(serde_cbor::to_writer(writer, &self)?)
+        fs::metadata(path)?;
+        persist_atomic(path, &serde_cbor::to_vec(&self)?)
     }
 
     /// Reads from a path (errors if path doesn't exist).
+    ///
+    /// Falls back to the backup of the previous save if the file fails to deserialize.
     pub fn read_from_disk(path: &PathBuf) -> Result<Self, WalletError> {
-        let wallet_file = OpenOptions::new().read(true).open(path)?;
-        let reader = BufReader::new(wallet_file);
-        let store: Self = serde_cbor::from_reader(reader)?;
-        Ok(store)
+        if Self::is_encrypted(path)? {
+            return Err(WalletError::Encryption(format!(
+                "Wallet file {} is encrypted",
+                path.display()
+            )));
+        }
+        let read = |path: &PathBuf| -> Result<Self, WalletError> {
+            let mut contents = Vec::new();
+            BufReader::new(OpenOptions::new().read(true).open(path)?).read_to_end(&mut contents)?;
+            Self::from_cbor_slice(&contents)
+        };
+        match read(path) {
+            Err(WalletError::Cbor(e)) => recover_from_backup(path, WalletError::Cbor(e), |bak| {
+                if Self::is_encrypted(bak)? {
+                    return Err(WalletError::Encryption(format!(
+                        "Wallet file {} is encrypted",
+                        bak.display()
+                    )));
+                }
+                read(bak)
+            }),
+            result => result,
+        }
+    }
+
+    /// Checks if the file at `path` was written by [`WalletStore::write_to_disk_encrypted`].
+    pub fn is_encrypted(path: &PathBuf) -> Result<bool, WalletError> {
+        let mut magic = [0u8; ENCRYPTED_FILE_MAGIC.len()];
+        let mut wallet_file = OpenOptions::new().read(true).open(path)?;
+        Ok(wallet_file.read_exact(&mut magic).is_ok() && &magic == ENCRYPTED_FILE_MAGIC)
+    }
+
+    /// Encrypts the store with `key`, and overwrites the file at `path` (errors if path doesn't exist).
+    pub(super) fn write_to_disk_encrypted(
+        &self,
+        path: &PathBuf,
+        key: &StoreKey,
+    ) -> Result<(), WalletError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = key
+            .cipher()
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                serde_cbor::to_vec(&self)?.as_slice(),
+            )
+            .map_err(|e| WalletError::Encryption(e.to_string()))?;
+
+        fs::metadata(path)?;
+        let mut contents = Vec::with_capacity(
+            ENCRYPTED_FILE_MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len(),
+        );
+        contents.extend_from_slice(ENCRYPTED_FILE_MAGIC);
+        contents.extend_from_slice(&key.salt);
+        contents.extend_from_slice(&nonce);
+        contents.extend_from_slice(&ciphertext);
+        persist_atomic(path, &contents)
+    }
+
+    /// Reads and decrypts an encrypted wallet file. Returns the key too, to encrypt later saves.
+    ///
+    /// Falls back to the backup of the previous save if the file fails to decrypt or deserialize.
+    pub(super) fn read_from_disk_encrypted(
+        path: &PathBuf,
+        passphrase: &str,
+    ) -> Result<(Self, StoreKey), WalletError> {
+        match Self::decrypt_file(path, passphrase) {
+            Err(e @ (WalletError::Encryption(_) | WalletError::Cbor(_))) => {
+                recover_from_backup(path, e, |bak| Self::decrypt_file(bak, passphrase))
+            }
+            result => result,
+        }
+    }
+
+    fn decrypt_file(path: &PathBuf, passphrase: &str) -> Result<(Self, StoreKey), WalletError> {
+        let mut contents = Vec::new();
+        OpenOptions::new()
+            .read(true)
+            .open(path)?
+            .read_to_end(&mut contents)?;
+
+        let header_len = ENCRYPTED_FILE_MAGIC.len() + SALT_LEN + NONCE_LEN;
+        if contents.len() < header_len || !contents.starts_with(ENCRYPTED_FILE_MAGIC) {
+            return Err(WalletError::Encryption(format!(
+                "Wallet file {} is not encrypted",
+                path.display()
+            )));
+        }
+        let (salt, rest) = contents[ENCRYPTED_FILE_MAGIC.len()..].split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let key = StoreKey::derive(passphrase, salt.try_into().expect("salt length checked"))?;
+        let plaintext = key
+            .cipher()
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                WalletError::Encryption("Wrong passphrase, or corrupted wallet file".to_string())
+            })?;
+        Ok((Self::from_cbor_slice(&plaintext)?, key))
+    }
+
+    /// Deserializes a store, migrating it first if it was written in an older format.
+    fn from_cbor_slice(contents: &[u8]) -> Result<Self, WalletError> {
+        let store: Value = serde_cbor::from_slice(contents)?;
+        let version = match &store {
+            Value::Map(fields) => match fields.get(&Value::Text("version".to_string())) {
+                Some(Value::Integer(version)) => u32::try_from(*version).map_err(|_| {
+                    WalletError::Protocol(format!("Invalid wallet store version {}", version))
+                })?,
+                _ => 0,
+            },
+            _ => 0,
+        };
+        if version > WALLET_STORE_VERSION {
+            return Err(WalletError::Protocol(format!(
+                "Wallet store version {} is newer than the supported version {}",
+                version, WALLET_STORE_VERSION
+            )));
+        }
+        Ok(serde_cbor::value::from_value(migrate(
+            store,
+            version,
+            WALLET_STORE_VERSION,
+        )?)?)
+    }
+}
+
+/// Upgrades the CBOR value of a store from format version `from` to `to`, one version at a time.
+pub(super) fn migrate(mut store: Value, from: u32, to: u32) -> Result<Value, WalletError> {
+    if from > to || to > WALLET_STORE_VERSION {
+        return Err(WalletError::Protocol(format!(
+            "Can't migrate wallet store from version {} to {}",
+            from, to
+        )));
+    }
+    for version in from..to {
+        log::info!(
+            "Migrating wallet store from version {} to {}",
+            version,
+            version + 1
+        );
+        store = MIGRATIONS[version as usize](store)?;
+    }
+    Ok(store)
+}
+
+/// Version 0 stores predate the `version` field. The fields added since all have serde defaults,
+/// so the migration only stamps the version.
+fn migrate_v0_to_v1(store: Value) -> Result<Value, WalletError> {
+    set_version(store, 1)
+}
+
+fn set_version(store: Value, version: u32) -> Result<Value, WalletError> {
+    match store {
+        Value::Map(mut fields) => {
+            fields.insert(
+                Value::Text("version".to_string()),
+                Value::Integer(version.into()),
+            );
+            Ok(Value::Map(fields))
+        }
+        _ => Err(WalletError::Protocol(
+            "Wallet store is not a CBOR map".to_string(),
+        )),
+    }
+}
+
+/// Path of the backup kept of the previous save of the wallet file at `path`.
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    backup.into()
+}
+
+/// Writes `contents` to `path` and syncs it.
+fn write_synced(path: &Path, contents: &[u8]) -> Result<(), WalletError> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Writes `contents` to `path` without ever leaving a partially written wallet file behind.
+///
+/// The contents go to a temp file first, which is synced and then renamed over `path`. The
+/// previous version of the file is kept at [`backup_path`], except when a plaintext file gets
+/// encrypted: the backup then holds the new encrypted contents, so no plaintext copy is left.
+fn persist_atomic(path: &PathBuf, contents: &[u8]) -> Result<(), WalletError> {
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    write_synced(&tmp_path, contents)?;
+
+    if path.exists() {
+        let backup = backup_path(path);
+        if contents.starts_with(ENCRYPTED_FILE_MAGIC) && !WalletStore::is_encrypted(path)? {
+            write_synced(&backup, contents)?;
+        } else {
+            fs::copy(path, backup)?;
+        }
+    }
+    fs::rename(&tmp_path, path)?;
+
+    // Sync the directory too, so the rename survives a crash.
+    if let Some(dir) = path.parent() {
+        if let Ok(dir) = fs::File::open(dir) {
+            let _ = dir.sync_all();
+        }
     }
+    Ok(())
 }
+
+/// Retries a failed read of the wallet file at `path` on its backup. Returns the original error if
+/// the backup can't be read either.
+fn recover_from_backup<T>(
+    path: &Path,
+    err: WalletError,
+    read: impl FnOnce(&PathBuf) -> Result<T, WalletError>,
+) -> Result<T, WalletError> {
+    let backup = backup_path(path);
+    if !backup.exists() {
+        return Err(err);
+    }
+    log::warn!(
+        "Failed to read wallet file {}: {:?}. Recovering from backup {}",
+        path.display(),
+        err,
+        backup.display()
+    );
+    read(&backup).map_err(|backup_err| {
+        log::error!("Failed to recover from backup: {:?}", backup_err);
+        err
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -120,4 +451,173 @@ mod tests {
         let read_wallet = WalletStore::read_from_disk(&file_path).unwrap();
         assert_eq!(original_wallet_store, read_wallet);
     }
+
+    #[test]
+    fn test_recover_corrupted_wallet_from_backup() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test_wallet.cbor");
+        let mnemonic = Mnemonic::generate(12).unwrap().to_string();
+
+        let mut wallet_store = WalletStore::init(
+            "test_wallet".to_string(),
+            &file_path,
+            Network::Bitcoin,
+            Xpriv::new_master(Network::Bitcoin, mnemonic.as_bytes()).unwrap(),
+            None,
+        )
+        .unwrap();
+        let previous_store = WalletStore::read_from_disk(&file_path).unwrap();
+        wallet_store.external_index = 5;
+        wallet_store.write_to_disk(&file_path).unwrap();
+        assert_eq!(
+            WalletStore::read_from_disk(&backup_path(&file_path)).unwrap(),
+            previous_store
+        );
+
+        // A torn write of the primary file.
+        let contents = fs::read(&file_path).unwrap();
+        fs::write(&file_path, &contents[..contents.len() / 2]).unwrap();
+        assert_eq!(
+            WalletStore::read_from_disk(&file_path).unwrap(),
+            previous_store
+        );
+
+        // Same for encrypted files.
+        let key = StoreKey::new("passphrase").unwrap();
+        wallet_store
+            .write_to_disk_encrypted(&file_path, &key)
+            .unwrap();
+        wallet_store.external_index = 6;
+        wallet_store
+            .write_to_disk_encrypted(&file_path, &key)
+            .unwrap();
+        fs::write(&file_path, b"garbage").unwrap();
+        let (read_wallet, _) =
+            WalletStore::read_from_disk_encrypted(&file_path, "passphrase").unwrap();
+        assert_eq!(read_wallet.external_index, 5);
+    }
+
+    #[test]
+    fn test_load_v0_wallet_store() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("wallet_store_v0");
+        fs::write(
+            &file_path,
+            include_bytes!("../../tests/fixtures/wallet_store_v0.cbor"),
+        )
+        .unwrap();
+
+        let store = WalletStore::read_from_disk(&file_path).unwrap();
+        assert_eq!(store.version, WALLET_STORE_VERSION);
+        assert_eq!(store.file_name, "wallet_store_v0");
+        assert_eq!(store.network, Network::Regtest);
+        assert_eq!(
+            store.master_key,
+            Xpriv::new_master(Network::Regtest, &[1u8; 32]).unwrap()
+        );
+        assert_eq!(store.external_index, 3);
+        assert_eq!(store.internal_index, 0);
+        assert_eq!(store.offer_maxsize, 5_000_000);
+        assert_eq!(store.last_synced_height, Some(150));
+        assert_eq!(store.wallet_birthday, Some(101));
+        assert!(store.locked_utxos.is_empty());
+        assert!(!store.avoid_change_reuse);
+        assert!(store.isolated_change.is_empty());
+
+        // The next save writes the current version.
+        store.write_to_disk(&file_path).unwrap();
+        let store: Value = serde_cbor::from_slice(&fs::read(&file_path).unwrap()).unwrap();
+        assert!(matches!(
+            store,
+            Value::Map(fields) if fields.get(&Value::Text("version".to_string()))
+                == Some(&Value::Integer(WALLET_STORE_VERSION.into()))
+        ));
+    }
+
+    #[test]
+    fn test_migrate() {
+        let store = Value::Map(Default::default());
+        assert!(matches!(
+            migrate(store.clone(), 0, 1).unwrap(),
+            Value::Map(fields) if fields.get(&Value::Text("version".to_string()))
+                == Some(&Value::Integer(1))
+        ));
+        assert_eq!(migrate(store.clone(), 1, 1).unwrap(), store);
+        assert!(migrate(store.clone(), 1, 0).is_err());
+        assert!(migrate(store, 0, WALLET_STORE_VERSION + 1).is_err());
+        assert!(migrate(Value::Null, 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_write_and_read_encrypted_wallet_to_disk() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test_wallet.cbor");
+        let mnemonic = Mnemonic::generate(12).unwrap().to_string();
+
+        let original_wallet_store = WalletStore::init(
+            "test_wallet".to_string(),
+            &file_path,
+            Network::Bitcoin,
+            Xpriv::new_master(Network::Bitcoin, mnemonic.as_bytes()).unwrap(),
+            None,
+        )
+        .unwrap();
+        assert!(!WalletStore::is_encrypted(&file_path).unwrap());
+
+        let key = StoreKey::new("passphrase").unwrap();
+        original_wallet_store
+            .write_to_disk_encrypted(&file_path, &key)
+            .unwrap();
+        assert!(WalletStore::is_encrypted(&file_path).unwrap());
+
+        let (read_wallet, _) =
+            WalletStore::read_from_disk_encrypted(&file_path, "passphrase").unwrap();
+        assert_eq!(original_wallet_store, read_wallet);
+
+        assert!(matches!(
+            WalletStore::read_from_disk_encrypted(&file_path, "wrong"),
+            Err(WalletError::Encryption(_))
+        ));
+        assert!(matches!(
+            WalletStore::read_from_disk(&file_path),
+            Err(WalletError::Encryption(_))
+        ));
+    }
+
+    #[test]
+    fn test_encrypting_wallet_leaves_no_plaintext_backup() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("test_wallet.cbor");
+        let mnemonic = Mnemonic::generate(12).unwrap().to_string();
+
+        let mut wallet_store = WalletStore::init(
+            "test_wallet".to_string(),
+            &file_path,
+            Network::Bitcoin,
+            Xpriv::new_master(Network::Bitcoin, mnemonic.as_bytes()).unwrap(),
+            None,
+        )
+        .unwrap();
+        // A plaintext backup from an earlier save.
+        wallet_store.write_to_disk(&file_path).unwrap();
+        assert!(!WalletStore::is_encrypted(&backup_path(&file_path)).unwrap());
+
+        // What `Wallet::save_encrypted` does on the first encrypted save.
+        let key = StoreKey::new("passphrase").unwrap();
+        wallet_store
+            .write_to_disk_encrypted(&file_path, &key)
+            .unwrap();
+        let backup = backup_path(&file_path);
+        assert!(WalletStore::is_encrypted(&backup).unwrap());
+        let (read_wallet, _) =
+            WalletStore::read_from_disk_encrypted(&backup, "passphrase").unwrap();
+        assert_eq!(read_wallet, wallet_store);
+
+        // Later saves back up the previous encrypted file.
+        wallet_store.external_index = 5;
+        wallet_store
+            .write_to_disk_encrypted(&file_path, &key)
+            .unwrap();
+        assert!(WalletStore::is_encrypted(&backup).unwrap());
+    }
 }
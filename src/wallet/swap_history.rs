@@ -0,0 +1,334 @@
+//! History of the swaps of a wallet, for accounting and privacy audits.
+//!
+//! Unlike the swapcoins of the wallet store, which are removed once a swap is over, the
+//! [`SwapRecord`]s are kept. They are appended as JSON lines to a file next to the wallet file,
+//! which is never rewritten. The file isn't encrypted, even if the wallet file is.
+//!
+//! A Taker's records can also hold the [`UtxoExposure`]s of the swap: which of its UTXOs each
+//! Maker saw. An [`ExposureReport`] groups them by Maker.
+
+use std::{
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bitcoin::{Amount, OutPoint};
+use serde::{Deserialize, Serialize};
+
+use super::{error::WalletError, Wallet};
+
+/// How a swap ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SwapOutcome {
+    /// All the contracts were settled cooperatively.
+    Completed,
+    /// The swap was aborted, and its contracts recovered.
+    Failed,
+}
+
+/// How a UTXO of the Taker was revealed to a Maker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExposureKind {
+    /// Spent by the Taker's funding transaction, which the first Maker gets to see.
+    FundingInput,
+    /// Change output of the Taker's funding transaction.
+    FundingChange,
+    /// Paid to the Taker by the last Maker.
+    Received,
+}
+
+/// A UTXO of the Taker revealed to a Maker during a swap.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UtxoExposure {
+    /// Address of the Maker.
+    pub maker: String,
+    pub outpoint: OutPoint,
+    pub kind: ExposureKind,
+}
+
+/// A finished swap.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapRecord {
+    /// Hashvalue of the swap's hashlock, the same for every participant.
+    #[serde(default)]
+    pub swap_id: String,
+    /// Unix time the swap ended at, in seconds.
+    pub timestamp: u64,
+    /// Amount sent into the swap by a taker, or received by a maker.
+    pub amount: Amount,
+    /// Addresses of the other participants of the swap.
+    pub counterparties: Vec<String>,
+    /// Fee paid by a taker, or earned by a maker.
+    pub fee: Amount,
+    pub outcome: SwapOutcome,
+    /// UTXOs of the Taker revealed to the Makers. Empty for the Makers, or if not recorded.
+    #[serde(default)]
+    pub exposures: Vec<UtxoExposure>,
+}
+
+impl SwapRecord {
+    /// A record of a swap ending now.
+    pub fn new(
+        swap_id: String,
+        amount: Amount,
+        counterparties: Vec<String>,
+        fee: Amount,
+        outcome: SwapOutcome,
+    ) -> Self {
+        Self {
+            swap_id,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            amount,
+            counterparties,
+            fee,
+            outcome,
+            exposures: Vec::new(),
+        }
+    }
+
+    /// The exposures of the swap, by Maker.
+    pub fn exposure_report(&self) -> ExposureReport {
+        ExposureReport {
+            swap_id: self.swap_id.clone(),
+            timestamp: self.timestamp,
+            makers: self
+                .counterparties
+                .iter()
+                .map(|maker| MakerExposure {
+                    maker: maker.clone(),
+                    utxos: self
+                        .exposures
+                        .iter()
+                        .filter(|exposure| &exposure.maker == maker)
+                        .map(|exposure| (exposure.outpoint, exposure.kind))
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The UTXOs of the Taker a Maker saw during a swap.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MakerExposure {
+    pub maker: String,
+    pub utxos: Vec<(OutPoint, ExposureKind)>,
+}
+
+/// Which UTXOs of the Taker were revealed to which Maker in a swap.
+///
+/// Every Maker of the route is listed, in route order, including those which saw none.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExposureReport {
+    pub swap_id: String,
+    /// Unix time the swap ended at, in seconds.
+    pub timestamp: u64,
+    pub makers: Vec<MakerExposure>,
+}
+
+/// The append-only swap history file.
+#[derive(Debug, Clone)]
+pub struct SwapHistory {
+    path: PathBuf,
+}
+
+impl SwapHistory {
+    /// The history of the wallet file at `wallet_path`.
+    pub fn new(wallet_path: &Path) -> Self {
+        let mut path = OsString::from(wallet_path);
+        path.push(".history");
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `record` to the file, and syncs it.
+    pub fn append(&self, record: &SwapRecord) -> Result<(), WalletError> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        // Terminate a line torn by a crash mid-append, so it doesn't swallow this record.
+        let len = file.metadata()?.len();
+        if len > 0 {
+            let mut last_byte = [0u8];
+            file.seek(SeekFrom::Start(len - 1))?;
+            file.read_exact(&mut last_byte)?;
+            if last_byte[0] != b'\n' {
+                line.insert(0, '\n');
+            }
+        }
+        file.write_all(line.as_bytes())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// All the records, oldest first. Empty if nothing was recorded yet.
+    ///
+    /// Lines that don't parse, like one torn by a crash mid-append, are skipped.
+    pub fn records(&self) -> Result<Vec<SwapRecord>, WalletError> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut records = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(record) => records.push(record),
+                Err(e) => log::warn!(
+                    "Skipping malformed line {} of swap history {}: {}",
+                    number + 1,
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+        Ok(records)
+    }
+}
+
+impl Wallet {
+    /// The swap history of the wallet.
+    pub fn swap_history_store(&self) -> SwapHistory {
+        SwapHistory::new(&self.wallet_file_path)
+    }
+
+    /// Appends a finished swap to the [SwapHistory].
+    pub fn record_swap(&self, record: SwapRecord) -> Result<(), WalletError> {
+        self.swap_history_store().append(&record)
+    }
+
+    /// The recorded swaps, oldest first.
+    pub fn swap_history(&self) -> Result<Vec<SwapRecord>, WalletError> {
+        self.swap_history_store().records()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoind::tempfile::tempdir;
+    use std::fs;
+
+    #[test]
+    fn test_swap_history() {
+        let temp_dir = tempdir().unwrap();
+        let history = SwapHistory::new(&temp_dir.path().join("test_wallet"));
+        assert_eq!(history.path(), temp_dir.path().join("test_wallet.history"));
+        assert!(history.records().unwrap().is_empty());
+
+        let completed = SwapRecord::new(
+            "5c7f3a1e".to_string(),
+            Amount::from_sat(500_000),
+            vec!["127.0.0.1:6102".to_string(), "127.0.0.1:16102".to_string()],
+            Amount::from_sat(12_000),
+            SwapOutcome::Completed,
+        );
+        let failed = SwapRecord {
+            timestamp: 1_700_000_000,
+            outcome: SwapOutcome::Failed,
+            ..completed.clone()
+        };
+        history.append(&completed).unwrap();
+        history.append(&failed).unwrap();
+        assert_eq!(
+            history.records().unwrap(),
+            vec![completed.clone(), failed.clone()]
+        );
+
+        // A torn append is skipped, and later records still read.
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(history.path())
+            .unwrap();
+        file.write_all(b"{\"timestamp\":17").unwrap();
+        history.append(&completed).unwrap();
+        assert_eq!(
+            history.records().unwrap(),
+            vec![completed.clone(), failed, completed]
+        );
+        assert_eq!(
+            fs::read_to_string(history.path()).unwrap().lines().count(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_exposure_report() {
+        let first_maker = "127.0.0.1:6102".to_string();
+        let last_maker = "127.0.0.1:16102".to_string();
+        let outpoint = |vout| OutPoint {
+            txid: "d2b4d1c3a1e1c6b8b4f3a1d3b2c1a0f9e8d7c6b5a4f3e2d1c0b9a8f7e6d5c4b3"
+                .parse()
+                .unwrap(),
+            vout,
+        };
+        let mut record = SwapRecord::new(
+            "5c7f3a1e".to_string(),
+            Amount::from_sat(500_000),
+            vec![first_maker.clone(), last_maker.clone()],
+            Amount::from_sat(12_000),
+            SwapOutcome::Completed,
+        );
+        record.exposures = vec![
+            UtxoExposure {
+                maker: first_maker.clone(),
+                outpoint: outpoint(0),
+                kind: ExposureKind::FundingInput,
+            },
+            UtxoExposure {
+                maker: last_maker.clone(),
+                outpoint: outpoint(1),
+                kind: ExposureKind::Received,
+            },
+            UtxoExposure {
+                maker: first_maker.clone(),
+                outpoint: outpoint(2),
+                kind: ExposureKind::FundingChange,
+            },
+        ];
+
+        let report = record.exposure_report();
+        assert_eq!(report.swap_id, "5c7f3a1e");
+        assert_eq!(
+            report.makers,
+            vec![
+                MakerExposure {
+                    maker: first_maker,
+                    utxos: vec![
+                        (outpoint(0), ExposureKind::FundingInput),
+                        (outpoint(2), ExposureKind::FundingChange),
+                    ],
+                },
+                MakerExposure {
+                    maker: last_maker,
+                    utxos: vec![(outpoint(1), ExposureKind::Received)],
+                },
+            ]
+        );
+
+        // Records without exposures still list the route.
+        record.exposures.clear();
+        assert!(record
+            .exposure_report()
+            .makers
+            .iter()
+            .all(|maker| maker.utxos.is_empty()));
+    }
+}
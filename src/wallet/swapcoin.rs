@@ -10,6 +10,7 @@
 
 use bitcoin::{
     absolute::LockTime,
+    bip32::Fingerprint,
     ecdsa::Signature,
     secp256k1::{self, Secp256k1, SecretKey},
     sighash::{EcdsaSighashType, SighashCache},
@@ -21,16 +22,18 @@ use bitcoin::{
 use crate::protocol::{
     contract::{
         apply_two_signatures_to_2of2_multisig_spend, create_multisig_redeemscript,
-        read_contract_locktime, read_hashlock_pubkey_from_contract, read_hashvalue_from_contract,
-        read_pubkeys_from_multisig_redeemscript, read_timelock_pubkey_from_contract,
-        sign_contract_tx, verify_contract_tx_sig,
+        read_contract_locktime, read_hashlock_from_contract, read_hashlock_pubkey_from_contract,
+        read_hashvalue_from_contract, read_pubkeys_from_multisig_redeemscript,
+        read_timelock_pubkey_from_contract, sign_contract_tx, verify_contract_tx_sig, Hashlock,
     },
     error::ContractError,
     messages::Preimage,
     Hash160,
 };
 
-use super::WalletError;
+use crate::utill::redeemscript_to_scriptpubkey;
+
+use super::{api::swap_key_path, WalletError};
 
 /// Defines an incoming swapcoin, which can either be currently active or successfully completed.
 ///
@@ -56,6 +59,14 @@ pub struct IncomingSwapCoin {
     pub funding_amount: Amount,
     pub others_contract_sig: Option<Signature>,
     pub hash_preimage: Option<Preimage>,
+    /// Index `my_privkey` was derived at, see [Wallet::derive_swap_key](super::Wallet::derive_swap_key).
+    /// `None` if it wasn't derived from the seed, like a Maker's keys tweaked with the Taker's
+    /// nonces.
+    #[serde(default)]
+    pub my_key_index: Option<u32>,
+    /// Index `hashlock_privkey` was derived at, like `my_key_index`.
+    #[serde(default)]
+    pub hashlock_key_index: Option<u32>,
 }
 
 /// Describes an outgoing swapcoin, which can either be currently active or successfully completed.
@@ -81,6 +92,13 @@ pub struct OutgoingSwapCoin {
     pub funding_amount: Amount,
     pub others_contract_sig: Option<Signature>,
     pub hash_preimage: Option<Preimage>,
+    /// Index `my_privkey` was derived at, see [Wallet::derive_swap_key](super::Wallet::derive_swap_key).
+    /// `None` for swapcoins created before swap keys were derived from the seed.
+    #[serde(default)]
+    pub my_key_index: Option<u32>,
+    /// Index `timelock_privkey` was derived at, like `my_key_index`.
+    #[serde(default)]
+    pub timelock_key_index: Option<u32>,
 }
 
 /// Represents a watch-only view of a coinswap between two makers.
@@ -116,6 +134,8 @@ pub trait SwapCoin {
     fn get_hashlock_pubkey(&self) -> PublicKey;
     /// Get the hash value.
     fn get_hashvalue(&self) -> Hash160;
+    /// Get the hashlock.
+    fn get_hashlock(&self) -> Hashlock;
     /// Get the funding amount.
     fn get_funding_amount(&self) -> Amount;
     /// Verify the receiver's signature on the contract transaction.
@@ -124,14 +144,54 @@ pub trait SwapCoin {
     fn verify_contract_tx_sender_sig(&self, sig: &Signature) -> Result<(), WalletError>;
     /// Apply a private key to the swap coin.
     fn apply_privkey(&mut self, privkey: SecretKey) -> Result<(), WalletError>;
+    /// Whether the contract output may still show up on chain and needs watching.
+    fn is_contract_relevant(&self) -> bool;
 }
 
 /// Trait representing swap coin functionality specific to a wallet.
 pub trait WalletSwapCoin: SwapCoin {
     fn get_my_pubkey(&self) -> PublicKey;
+    /// Index our multisig key was derived at, if it's a swap key.
+    fn get_my_key_index(&self) -> Option<u32>;
     fn get_other_pubkey(&self) -> &PublicKey;
     fn get_fully_signed_contract_tx(&self) -> Result<Transaction, WalletError>;
     fn is_hash_preimage_known(&self) -> bool;
+
+    /// The `wsh(sortedmulti(..))` descriptor of the 2-of-2 funding multisig, without checksum.
+    ///
+    /// There's one such descriptor per swapcoin, not a ranged one for all of them: the multisig
+    /// holds the counterparty's key of that swap, which no range of a single descriptor covers.
+    /// Our side couldn't be ranged either. Swap keys are hardened children, see
+    /// [Wallet::derive_swap_key](super::Wallet::derive_swap_key), and a Maker's incoming keys are
+    /// its tweakable key tweaked with the Taker's nonce. Importing them still costs no RPC call per
+    /// swapcoin, their checksums are computed locally.
+    ///
+    /// Our key carries its `[fingerprint/84'/1'/0'/3'/i']` origin if it's a swap key, so external
+    /// signers can derive it from the seed. The counterparty's key has no origin.
+    fn multisig_descriptor(&self, master_fingerprint: Fingerprint) -> Result<String, WalletError> {
+        let my_key = match self.get_my_key_index() {
+            Some(index) => format!(
+                "[{}/{}]{}",
+                master_fingerprint,
+                swap_key_path(index)?,
+                self.get_my_pubkey()
+            ),
+            None => self.get_my_pubkey().to_string(),
+        };
+        Ok(format!(
+            "wsh(sortedmulti(2,{},{}))",
+            self.get_other_pubkey(),
+            my_key
+        ))
+    }
+
+    /// The `raw(..)` descriptor of the contract scriptpubkey, without checksum.
+    fn contract_descriptor(&self) -> String {
+        format!(
+            "raw({:x})",
+            redeemscript_to_scriptpubkey(&self.get_contract_redeemscript())
+        )
+    }
 }
 
 macro_rules! impl_walletswapcoin {
@@ -145,6 +205,10 @@ macro_rules! impl_walletswapcoin {
                 }
             }
 
+            fn get_my_key_index(&self) -> Option<u32> {
+                self.my_key_index
+            }
+
             fn get_other_pubkey(&self) -> &PublicKey {
                 &self.other_pubkey
             }
@@ -214,6 +278,10 @@ macro_rules! impl_swapcoin_getters {
             read_hashvalue_from_contract(&self.contract_redeemscript).unwrap()
         }
 
+        fn get_hashlock(&self) -> Hashlock {
+            read_hashlock_from_contract(&self.contract_redeemscript).unwrap()
+        }
+
         fn get_contract_tx(&self) -> Transaction {
             self.contract_tx.clone()
         }
@@ -255,6 +323,8 @@ impl IncomingSwapCoin {
             funding_amount,
             others_contract_sig: None,
             hash_preimage: None,
+            my_key_index: None,
+            hashlock_key_index: None,
         }
     }
 
@@ -354,6 +424,38 @@ impl IncomingSwapCoin {
         )
     }
 
+    /// Spends the contract through its hashlock branch to `destination_address`. The preimage must
+    /// be known.
+    pub fn create_hashlock_spend(&self, destination_address: &Address) -> Transaction {
+        let miner_fee = 144 * 2; //144 vbytes x 2 sat/vb, the spend without preimage plus the 32 bytes preimage
+        let mut tx = Transaction {
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: self.contract_tx.compute_txid(),
+                    vout: 0, //contract_tx is one-input-one-output
+                },
+                sequence: Sequence(1), //hashlock spends must have 1 because of the `OP_CSV 1`
+                witness: Witness::new(),
+                script_sig: ScriptBuf::new(),
+            }],
+            output: vec![TxOut {
+                script_pubkey: destination_address.script_pubkey(),
+                value: Amount::from_sat(self.contract_tx.output[0].value.to_sat() - miner_fee),
+            }],
+            lock_time: LockTime::ZERO,
+            version: Version::TWO,
+        };
+        let index = 0;
+        self.sign_hashlocked_transaction_input(
+            index,
+            &tx.clone(),
+            &mut tx.input[0],
+            self.contract_tx.output[0].value,
+        )
+        .unwrap();
+        tx
+    }
+
     pub fn create_hashlock_spend_without_preimage(
         &self,
         destination_address: &Address,
@@ -426,6 +528,8 @@ impl OutgoingSwapCoin {
             funding_amount,
             others_contract_sig: None,
             hash_preimage: None,
+            my_key_index: None,
+            timelock_key_index: None,
         }
     }
 
@@ -580,6 +684,12 @@ impl SwapCoin for IncomingSwapCoin {
         self.other_privkey = Some(privkey);
         Ok(())
     }
+
+    /// Once the other party's privkey is known the swap is complete, and the funding output is
+    /// spent directly instead of through the contract.
+    fn is_contract_relevant(&self) -> bool {
+        self.other_privkey.is_none()
+    }
 }
 
 impl SwapCoin for OutgoingSwapCoin {
@@ -616,6 +726,12 @@ impl SwapCoin for OutgoingSwapCoin {
             Err(WalletError::Protocol("not correct privkey".to_string()))
         }
     }
+
+    /// The outgoing side doesn't learn when the swap completes. See [`Wallet::descriptors_to_import`](super::Wallet::descriptors_to_import)
+    /// for how completed swaps are detected wallet-wide.
+    fn is_contract_relevant(&self) -> bool {
+        true
+    }
 }
 
 impl SwapCoin for WatchOnlySwapCoin {
@@ -638,6 +754,10 @@ impl SwapCoin for WatchOnlySwapCoin {
         create_multisig_redeemscript(&self.sender_pubkey, &self.receiver_pubkey)
     }
 
+    fn is_contract_relevant(&self) -> bool {
+        true
+    }
+
     /*
     Potential confusion here:
         verify sender sig uses the receiver_pubkey
@@ -756,6 +876,8 @@ mod tests {
             funding_amount: Amount::ZERO,
             others_contract_sig: None,
             hash_preimage: None,
+            my_key_index: None,
+            hashlock_key_index: None,
         };
 
         let secret_key_1 =
@@ -766,6 +888,8 @@ mod tests {
                 .unwrap();
         // Test for applying the correct privkey
         assert!(incoming_swapcoin.apply_privkey(secret_key_1).is_ok());
+        // The swap is complete, the contract is not watched anymore
+        assert!(!incoming_swapcoin.is_contract_relevant());
         // Test for applying the incorrect privkey
         assert!(incoming_swapcoin.apply_privkey(secret_key_2).is_err());
         // Test get_other_pubkey
@@ -775,6 +899,87 @@ mod tests {
         assert!(!incoming_swapcoin.is_hash_preimage_known());
     }
 
+    #[test]
+    fn test_swapcoin_descriptors() {
+        let secp = Secp256k1::new();
+        let key = |k: &str| SecretKey::from_str(k).unwrap();
+        let my_privkey = key("0000000000000000000000000000000000000000000000000000000000000003");
+        let other_privkey = key("0000000000000000000000000000000000000000000000000000000000000002");
+        let other_pubkey = PublicKey {
+            compressed: true,
+            inner: secp256k1::PublicKey::from_secret_key(&secp, &other_privkey),
+        };
+        let contract_tx = Transaction {
+            input: vec![],
+            output: vec![],
+            lock_time: LockTime::ZERO,
+            version: Version::TWO,
+        };
+
+        let incoming_swapcoin = IncomingSwapCoin {
+            my_privkey,
+            other_privkey: None,
+            other_pubkey,
+            contract_tx: contract_tx.clone(),
+            contract_redeemscript: ScriptBuf::default(),
+            hashlock_privkey: my_privkey,
+            funding_amount: Amount::ZERO,
+            others_contract_sig: None,
+            hash_preimage: None,
+            my_key_index: None,
+            hashlock_key_index: None,
+        };
+        let outgoing_swapcoin = OutgoingSwapCoin {
+            my_privkey,
+            other_pubkey,
+            contract_tx,
+            contract_redeemscript: ScriptBuf::default(),
+            timelock_privkey: my_privkey,
+            funding_amount: Amount::ZERO,
+            others_contract_sig: None,
+            hash_preimage: None,
+            my_key_index: None,
+            timelock_key_index: None,
+        };
+
+        let multisig_descriptor = "wsh(sortedmulti(2,\
+            02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5,\
+            02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9))";
+        let contract_descriptor =
+            "raw(0020e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855)";
+
+        let fingerprint = Fingerprint::from_str("deadbeef").unwrap();
+
+        // Both sides of the swap must describe the same scripts.
+        assert_eq!(
+            incoming_swapcoin.multisig_descriptor(fingerprint).unwrap(),
+            multisig_descriptor
+        );
+        assert_eq!(
+            outgoing_swapcoin.multisig_descriptor(fingerprint).unwrap(),
+            multisig_descriptor
+        );
+        assert_eq!(incoming_swapcoin.contract_descriptor(), contract_descriptor);
+        assert_eq!(outgoing_swapcoin.contract_descriptor(), contract_descriptor);
+
+        // A swap key gets its origin, the counterparty's key never does.
+        let outgoing_swapcoin = OutgoingSwapCoin {
+            my_key_index: Some(5),
+            ..outgoing_swapcoin
+        };
+        assert_eq!(
+            outgoing_swapcoin.multisig_descriptor(fingerprint).unwrap(),
+            "wsh(sortedmulti(2,\
+            02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5,\
+            [deadbeef/84'/1'/0'/3'/5']\
+            02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9))"
+        );
+
+        // Ongoing swap, the contracts are still watched.
+        assert!(incoming_swapcoin.is_contract_relevant());
+        assert!(outgoing_swapcoin.is_contract_relevant());
+    }
+
     #[test]
 
     fn test_apply_privkey_outgoing_swapcoin() {
@@ -807,6 +1012,8 @@ mod tests {
             funding_amount: Amount::ZERO,
             others_contract_sig: None,
             hash_preimage: None,
+            my_key_index: None,
+            timelock_key_index: None,
         };
         let secret_key_1 =
             SecretKey::from_str("0000000000000000000000000000000000000000000000000000000000000001")
@@ -876,6 +1083,8 @@ mod tests {
             funding_amount: Amount::from_sat(100_000),
             others_contract_sig: None,
             hash_preimage: None,
+            my_key_index: None,
+            hashlock_key_index: None,
         };
         // Intentionally failing to sign with incomplete swapcoin
         assert!(incoming_swapcoin
@@ -931,6 +1140,8 @@ mod tests {
             funding_amount: Amount::from_sat(100_000),
             others_contract_sig: None,
             hash_preimage: Some(Preimage::from([0; 32])),
+            my_key_index: None,
+            hashlock_key_index: None,
         };
         let destination_address: Address = Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf")
             .unwrap()
@@ -1011,6 +1222,8 @@ mod tests {
             funding_amount: Amount::from_sat(100_000),
             others_contract_sig: None,
             hash_preimage: Some(Preimage::from([0; 32])),
+            my_key_index: None,
+            hashlock_key_index: None,
         };
         let destination_address: Address = Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf")
             .unwrap()
@@ -6,10 +6,9 @@ use coinswap::{
     utill::ConnectionType,
 };
 
-mod test_framework;
+use coinswap::test_framework::*;
 use log::{info, warn};
 use std::{assert_eq, thread, time::Duration};
-use test_framework::*;
 
 /// Abort 1: TAKER Drops After Full Setup.
 /// This test demonstrates the situation where the Taker drops connection after broadcasting all the
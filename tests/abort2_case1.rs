@@ -6,8 +6,7 @@ use coinswap::{
     utill::ConnectionType,
 };
 
-mod test_framework;
-use test_framework::*;
+use coinswap::test_framework::*;
 
 use log::{info, warn};
 use std::{thread, time::Duration};
@@ -142,12 +141,12 @@ fn test_abort_case_2_move_on_with_other_makers() {
 
     // Maker might not get banned as Taker may not try 6102 for swap. If it does then check its 6102.
     if !taker.read().unwrap().get_bad_makers().is_empty() {
-        assert_eq!(
-            format!("127.0.0.1:{}", 6102),
-            taker.read().unwrap().get_bad_makers()[0]
-                .address
-                .to_string()
-        );
+        let taker = taker.read().unwrap();
+        let bad_maker = taker.get_bad_makers()[0];
+        assert_eq!(format!("127.0.0.1:{}", 6102), bad_maker.address.to_string());
+        // It never joined the route, skipping it cost nothing but a retry.
+        let reputation = taker.maker_reputation(&bad_maker.offer.fidelity.bond.outpoint);
+        assert_eq!(reputation.failures, 0);
     }
 
     test_framework.stop();
@@ -6,11 +6,10 @@ use coinswap::{
     utill::ConnectionType,
 };
 
-mod test_framework;
-use test_framework::*;
+use coinswap::test_framework::*;
 
 use log::{info, warn};
-use std::{fs::File, io::Read, path::PathBuf, thread, time::Duration};
+use std::{fs::File, io::Read, thread, time::Duration};
 
 /// ABORT 2: Maker Drops Before Setup
 /// This test demonstrates the situation where a Maker prematurely drops connections after doing
@@ -214,8 +213,13 @@ fn test_abort_case_2_recover_if_no_makers_found() {
             );
         }
         ConnectionType::TOR => {
-            let onion_addr_path =
-                PathBuf::from(format!("/tmp/tor-rust-maker{}/hs-dir/hostname", 6102));
+            let onion_addr_path = makers
+                .iter()
+                .find(|maker| maker.config.port == 6102)
+                .unwrap()
+                .config
+                .data_dir
+                .join("tor/hs-dir/hostname");
             let mut file = File::open(onion_addr_path).unwrap();
             let mut onion_addr: String = String::new();
             file.read_to_string(&mut onion_addr).unwrap();
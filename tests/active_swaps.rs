@@ -0,0 +1,60 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::Amount;
+use coinswap::{maker::MakerBehavior, taker::SwapParams, utill::ConnectionType};
+
+use coinswap::test_framework::*;
+
+use log::{info, warn};
+use std::{thread, time::Duration};
+
+/// Test the active swaps reported by the makers after a completed swap
+///
+/// The makers keep the swapcoins of the completed swap, but don't report it as active.
+#[test]
+fn test_completed_swap_not_active() {
+    // ---- Setup ----
+
+    let makers_config_map = [
+        ((6102, None), MakerBehavior::Normal),
+        ((16102, None), MakerBehavior::Normal),
+    ];
+
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        None,
+        ConnectionType::CLEARNET,
+    );
+
+    warn!("Running Test: Active swaps after a completed swap");
+
+    test_framework.fund_swap_wallets(&taker, &makers);
+
+    // ----- Test -----
+
+    let swap_params = SwapParams {
+        send_amount: Amount::from_sat(500000),
+        maker_count: 2,
+        tx_count: 3,
+        required_confirms: 1,
+        fee_rate: Amount::from_sat(1000),
+    };
+
+    info!("Initiating Makers and the coinswap protocol");
+    TestFramework::run_swap(&taker, &makers, swap_params).unwrap();
+
+    makers.iter().for_each(|maker| {
+        let wallet = maker.get_wallet().read().unwrap();
+        assert_eq!(wallet.get_swapcoins_count(), 6);
+        assert!(wallet.active_swaps().is_empty());
+    });
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,77 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::Amount;
+use bitcoind::bitcoincore_rpc::RpcApi;
+use coinswap::{
+    utill::ConnectionType,
+    wallet::{Destination, SendAmount, WalletError},
+};
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, thread, time::Duration};
+
+/// Test broadcasting with a mempool acceptance precheck
+///
+/// A transaction the mempool rejects isn't broadcasted, and neither are the transactions sent
+/// along with it.
+#[test]
+fn test_broadcast_checked() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let client = test_framework.get_client();
+    let mut taker = taker.write().unwrap();
+    let wallet = taker.get_wallet_mut();
+
+    test_framework.fund_wallet(wallet, 2, Amount::from_btc(0.05).unwrap());
+    wallet.sync().unwrap();
+
+    let utxos = wallet.list_descriptor_utxo_spend_info(None).unwrap();
+    assert_eq!(utxos.len(), 2);
+    let destination = client.get_new_address(None, None).unwrap().assume_checked();
+    let mut spend = |fee: u64, utxo_index: usize| {
+        wallet
+            .spend_from_wallet(
+                Amount::from_sat(fee),
+                SendAmount::Max,
+                Destination::Address(destination.clone()),
+                &utxos[utxo_index..=utxo_index],
+            )
+            .unwrap()
+    };
+    let zero_fee_tx = spend(0, 0);
+    let paying_tx = spend(1000, 1);
+
+    // ----- Test -----
+
+    // Not enough fee: rejected, and nothing gets out.
+    match wallet.broadcast_all_checked(&[paying_tx.clone(), zero_fee_tx.clone()]) {
+        Err(WalletError::MempoolRejected { txid, reason }) => {
+            assert_eq!(txid, zero_fee_tx.compute_txid());
+            assert!(reason.contains("fee"), "{}", reason);
+        }
+        other => panic!("expected a mempool rejection, got {:?}", other),
+    }
+    for tx in [&paying_tx, &zero_fee_tx] {
+        assert!(client
+            .get_raw_transaction(&tx.compute_txid(), None)
+            .is_err());
+    }
+
+    // Accepted, and broadcasting it twice is fine.
+    let txid = wallet.broadcast_checked(&paying_tx).unwrap();
+    assert_eq!(txid, paying_tx.compute_txid());
+    assert!(client.get_raw_transaction(&txid, None).is_ok());
+    assert_eq!(wallet.broadcast_checked(&paying_tx).unwrap(), txid);
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,272 @@
+#![cfg(feature = "integration-test")]
+use bip39::Mnemonic;
+use bitcoin::{Amount, Block, BlockHash, OutPoint, ScriptBuf, Transaction, TxOut, Txid};
+use bitcoind::bitcoincore_rpc::{
+    json::{GetBlockHeaderResult, ListUnspentResultEntry},
+    Client, RpcApi,
+};
+use coinswap::{
+    utill::ConnectionType,
+    wallet::{ChainBackend, CoreRpcBackend, RPCConfig, StaticFeeEstimator, Wallet, WalletError},
+};
+
+use coinswap::test_framework::*;
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Forwards to the core node, and records what went through it.
+struct RecordingBackend {
+    inner: CoreRpcBackend,
+    rescans: Mutex<Vec<(u64, u64)>>,
+    broadcasts: Mutex<Vec<Txid>>,
+    utxo_listings: Mutex<usize>,
+}
+
+impl RecordingBackend {
+    fn new(rpc_config: RPCConfig) -> Self {
+        let rpc = Arc::new(Client::try_from(&rpc_config).unwrap());
+        RecordingBackend {
+            inner: CoreRpcBackend::new(rpc, rpc_config),
+            rescans: Mutex::new(Vec::new()),
+            broadcasts: Mutex::new(Vec::new()),
+            utxo_listings: Mutex::new(0),
+        }
+    }
+}
+
+impl ChainBackend for RecordingBackend {
+    fn open_wallet(&self, wallet_name: &str) -> Result<(), WalletError> {
+        self.inner.open_wallet(wallet_name)
+    }
+
+    fn get_block_count(&self) -> Result<u64, WalletError> {
+        self.inner.get_block_count()
+    }
+
+    fn get_block_hash(&self, height: u64) -> Result<BlockHash, WalletError> {
+        self.inner.get_block_hash(height)
+    }
+
+    fn get_block(&self, height: u64) -> Result<Block, WalletError> {
+        self.inner.get_block(height)
+    }
+
+    fn get_block_header(
+        &self,
+        hash: &BlockHash,
+    ) -> Result<Option<GetBlockHeaderResult>, WalletError> {
+        self.inner.get_block_header(hash)
+    }
+
+    fn derive_scriptpubkeys(
+        &self,
+        descriptor: &str,
+        range_end: u32,
+    ) -> Result<Vec<ScriptBuf>, WalletError> {
+        self.inner.derive_scriptpubkeys(descriptor, range_end)
+    }
+
+    fn imported_descriptors(&self) -> Result<HashMap<String, Option<u32>>, WalletError> {
+        self.inner.imported_descriptors()
+    }
+
+    fn import_descriptors(
+        &self,
+        descriptors: &[String],
+        range_end: u32,
+        label: &str,
+    ) -> Result<(), WalletError> {
+        self.inner.import_descriptors(descriptors, range_end, label)
+    }
+
+    fn rescan(
+        &self,
+        start_height: u64,
+        end_height: u64,
+        progress: Option<&dyn Fn(u64)>,
+    ) -> Result<(), WalletError> {
+        self.rescans
+            .lock()
+            .unwrap()
+            .push((start_height, end_height));
+        self.inner.rescan(start_height, end_height, progress)
+    }
+
+    fn get_tx_out(&self, outpoint: &OutPoint) -> Result<Option<(TxOut, u64)>, WalletError> {
+        self.inner.get_tx_out(outpoint)
+    }
+
+    fn list_unspent(&self) -> Result<Vec<ListUnspentResultEntry>, WalletError> {
+        *self.utxo_listings.lock().unwrap() += 1;
+        self.inner.list_unspent()
+    }
+
+    fn get_wallet_transaction(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<(Transaction, Option<u64>)>, WalletError> {
+        self.inner.get_wallet_transaction(txid)
+    }
+
+    fn get_transaction(
+        &self,
+        txid: &Txid,
+    ) -> Result<Option<(Transaction, Option<u64>)>, WalletError> {
+        self.inner.get_transaction(txid)
+    }
+
+    fn get_spending_transaction(
+        &self,
+        outpoint: &OutPoint,
+        start_height: Option<u64>,
+    ) -> Result<Option<(Transaction, Option<u64>)>, WalletError> {
+        self.inner.get_spending_transaction(outpoint, start_height)
+    }
+
+    fn test_mempool_accept(&self, txs: &[Transaction]) -> Result<(), WalletError> {
+        self.inner.test_mempool_accept(txs)
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<Txid, WalletError> {
+        let txid = self.inner.broadcast(tx)?;
+        self.broadcasts.lock().unwrap().push(txid);
+        Ok(txid)
+    }
+}
+
+/// Test syncing and spending through a custom chain backend
+///
+/// Rescans, utxo listings and broadcasts of the wallet go through the backend set on it.
+#[test]
+fn test_chain_backend() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let client = test_framework.get_client();
+    let mut taker = taker.write().unwrap();
+    let wallet = taker.get_wallet_mut();
+
+    let rpc_config = RPCConfig {
+        wallet_name: wallet
+            .get_file_path()
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string(),
+        ..RPCConfig::from(test_framework.as_ref())
+    };
+    let backend = Arc::new(RecordingBackend::new(rpc_config));
+    wallet.set_backend(backend.clone());
+
+    // ----- Test -----
+
+    test_framework.fund_wallet(wallet, 1, Amount::from_btc(0.05).unwrap());
+    wallet.sync().unwrap();
+    let tip_height = test_framework.get_block_count();
+    wallet.rescan_from(0).unwrap();
+    assert!(backend
+        .rescans
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(start, end)| *start == 0 && *end >= tip_height));
+    let utxo_listings = *backend.utxo_listings.lock().unwrap();
+    assert_eq!(
+        wallet.get_balances().unwrap().spendable,
+        Amount::from_btc(0.05).unwrap()
+    );
+    assert!(*backend.utxo_listings.lock().unwrap() > utxo_listings);
+
+    let destination = client.get_new_address(None, None).unwrap().assume_checked();
+    let txid = wallet
+        .send_to(
+            destination,
+            Amount::from_sat(1_000_000),
+            &StaticFeeEstimator::from_sat_per_vb(2.0),
+        )
+        .unwrap();
+    assert_eq!(*backend.broadcasts.lock().unwrap(), vec![txid]);
+    assert!(client.get_raw_transaction(&txid, None).is_ok());
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
+
+/// Test a wallet running on a chain backend alone, without a core client
+///
+/// Syncing and spending go through the backend. Features only a core wallet
+/// provides error.
+#[test]
+fn test_wallet_without_core_client() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let client = test_framework.get_client();
+    let wallet_path = taker
+        .read()
+        .unwrap()
+        .get_wallet()
+        .get_file_path()
+        .with_file_name("backend-wallet");
+    let rpc_config = RPCConfig {
+        wallet_name: "backend-wallet".to_string(),
+        ..RPCConfig::from(test_framework.as_ref())
+    };
+    let backend = Arc::new(RecordingBackend::new(rpc_config.clone()));
+    let mut wallet = Wallet::init_with_backend(
+        &wallet_path,
+        &rpc_config,
+        backend.clone(),
+        Mnemonic::generate(12).unwrap().to_string(),
+        "".to_string(),
+        Some(0),
+    )
+    .unwrap();
+
+    // ----- Test -----
+
+    test_framework.fund_wallet(&mut wallet, 1, Amount::from_btc(0.05).unwrap());
+    wallet.sync().unwrap();
+    assert!(!backend.rescans.lock().unwrap().is_empty());
+    assert_eq!(
+        wallet.get_balances().unwrap().spendable,
+        Amount::from_btc(0.05).unwrap()
+    );
+
+    let destination = client.get_new_address(None, None).unwrap().assume_checked();
+    let fee_estimator = StaticFeeEstimator::from_sat_per_vb(2.0);
+    let txid = wallet
+        .send_to(destination, Amount::from_sat(1_000_000), &fee_estimator)
+        .unwrap();
+    assert_eq!(*backend.broadcasts.lock().unwrap(), vec![txid]);
+    assert!(client.get_raw_transaction(&txid, None).is_ok());
+
+    // Fee bumping looks the transaction up in the core wallet.
+    assert!(wallet.bump_fee(txid, &fee_estimator).is_err());
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,136 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::Amount;
+use coinswap::{
+    maker::MakerBehavior,
+    taker::SwapParams,
+    utill::ConnectionType,
+    wallet::{CoinSelectionStrategy, UtxoOrigin, WalletError},
+};
+
+use coinswap::test_framework::*;
+
+use log::{info, warn};
+use std::{thread, time::Duration};
+
+/// Test the `avoid_change_reuse` policy
+///
+/// The Taker swaps with the policy set. The change of its funding transactions goes to fresh
+/// addresses, and coin selection never spends that change together with the swapcoins it received.
+#[test]
+fn test_change_isolation() {
+    // ---- Setup ----
+
+    let makers_config_map = [
+        ((6102, None), MakerBehavior::Normal),
+        ((16102, None), MakerBehavior::Normal),
+    ];
+
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        None,
+        ConnectionType::CLEARNET,
+    );
+
+    warn!("Running Test: Change isolation");
+
+    test_framework.fund_wallet(
+        taker.write().unwrap().get_wallet_mut(),
+        3,
+        Amount::from_btc(0.05).unwrap(),
+    );
+    makers.iter().for_each(|maker| {
+        test_framework.fund_wallet(
+            &mut maker.get_wallet().write().unwrap(),
+            4,
+            Amount::from_btc(0.05).unwrap(),
+        );
+    });
+
+    {
+        let mut taker = taker.write().unwrap();
+        let wallet = taker.get_wallet_mut();
+        wallet.set_avoid_change_reuse(true).unwrap();
+        assert!(wallet.avoid_change_reuse());
+        wallet.lock_unspendable_utxos().unwrap();
+    }
+
+    // ---- Swap ----
+
+    let swap_params = SwapParams {
+        send_amount: Amount::from_sat(500000),
+        maker_count: 2,
+        tx_count: 3,
+        required_confirms: 1,
+        fee_rate: Amount::from_sat(1000),
+    };
+
+    info!("Initiating Makers and the coinswap protocol");
+    TestFramework::run_swap(&taker, &makers, swap_params).unwrap();
+
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    // ---- Checks ----
+
+    let mut taker = taker.write().unwrap();
+    let wallet = taker.get_wallet_mut();
+    wallet.sync().unwrap();
+
+    let utxos = wallet.list_utxo_with_origin().unwrap();
+    let isolated_change = utxos
+        .iter()
+        .filter(|(utxo, _)| wallet.is_isolated_change(utxo))
+        .map(|(utxo, _)| (utxo.txid, utxo.vout))
+        .collect::<Vec<_>>();
+    let swap_coins = utxos
+        .iter()
+        .filter(|(_, origin)| matches!(origin, UtxoOrigin::SwapOutput { .. }))
+        .map(|(utxo, _)| (utxo.txid, utxo.vout))
+        .collect::<Vec<_>>();
+    assert!(!isolated_change.is_empty());
+    assert!(!swap_coins.is_empty());
+
+    // Every funding tx got its own change address.
+    let mut change_spks = utxos
+        .iter()
+        .filter(|(utxo, _)| wallet.is_isolated_change(utxo))
+        .map(|(utxo, _)| utxo.script_pub_key.clone())
+        .collect::<Vec<_>>();
+    change_spks.sort();
+    change_spks.dedup();
+    assert_eq!(change_spks.len(), isolated_change.len());
+
+    for strategy in [
+        CoinSelectionStrategy::LargestFirst,
+        CoinSelectionStrategy::SmallestFirst,
+        CoinSelectionStrategy::BranchAndBound,
+        CoinSelectionStrategy::PrivacyOptimized,
+    ] {
+        for target in [100_000, 1_000_000, 5_000_000] {
+            let selected = wallet
+                .select_coins(Amount::from_sat(target), strategy, 1.0)
+                .unwrap()
+                .into_iter()
+                .map(|utxo| (utxo.txid, utxo.vout))
+                .collect::<Vec<_>>();
+            assert!(
+                !(selected.iter().any(|o| isolated_change.contains(o))
+                    && selected.iter().any(|o| swap_coins.contains(o))),
+                "{:?} combined isolated change with swapcoins for {} sats",
+                strategy,
+                target
+            );
+        }
+    }
+
+    // Spending everything would combine them.
+    let total = utxos.iter().map(|(utxo, _)| utxo.amount).sum::<Amount>();
+    assert!(matches!(
+        wallet.select_coins(total, CoinSelectionStrategy::LargestFirst, 1.0),
+        Err(WalletError::InsufficientFund { .. })
+    ));
+
+    test_framework.stop();
+}
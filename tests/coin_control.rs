@@ -0,0 +1,95 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::{Amount, OutPoint};
+use coinswap::{
+    utill::ConnectionType,
+    wallet::{OutputShaping, WalletError},
+};
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, thread, time::Duration};
+
+/// Test funding from a chosen set of utxos
+///
+/// Only owned, unlocked utxos can be chosen, and the funding txs spend nothing else, even when
+/// coin selection would have picked a bigger utxo.
+#[test]
+fn test_coin_control() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let mut taker = taker.write().unwrap();
+    let wallet = taker.get_wallet_mut();
+
+    for btc in [0.05, 0.01, 0.02] {
+        let addrs = wallet.get_next_external_address().unwrap();
+        test_framework.send_to_address(&addrs, Amount::from_btc(btc).unwrap());
+    }
+    test_framework.generate_blocks(1);
+    wallet.sync().unwrap();
+
+    let outpoint_of = |btc: f64| {
+        let utxo = wallet
+            .get_all_utxo()
+            .unwrap()
+            .into_iter()
+            .find(|utxo| utxo.amount == Amount::from_btc(btc).unwrap())
+            .unwrap();
+        OutPoint::new(utxo.txid, utxo.vout)
+    };
+    let (big, small, medium) = (outpoint_of(0.05), outpoint_of(0.01), outpoint_of(0.02));
+
+    // ----- Test -----
+
+    let chosen = [small, medium];
+    let selected = wallet.select_utxos(&chosen).unwrap();
+    assert_eq!(
+        selected.iter().map(|(u, _)| u.amount).sum::<Amount>(),
+        Amount::from_btc(0.03).unwrap()
+    );
+
+    // Locked or unknown utxos can't be chosen.
+    wallet.lock_utxo(big).unwrap();
+    assert!(matches!(
+        wallet.select_utxos(&[small, big]),
+        Err(WalletError::UtxoNotSpendable(outpoint)) if outpoint == big
+    ));
+    wallet.unlock_utxo(big).unwrap();
+    assert!(matches!(
+        wallet.select_utxos(&[OutPoint::null()]),
+        Err(WalletError::UtxoNotSpendable(_))
+    ));
+
+    // Largest first selection would spend the 0.05 BTC utxo.
+    let destination = wallet.get_next_external_address().unwrap();
+    let funding = wallet
+        .create_funding_txes(
+            Amount::from_btc(0.015).unwrap(),
+            &[destination],
+            Amount::from_sat(1000),
+            OutputShaping::None,
+            Some(&chosen),
+        )
+        .unwrap();
+    assert!(funding
+        .funding_txes
+        .iter()
+        .flat_map(|tx| tx.input.iter())
+        .all(|input| chosen.contains(&input.previous_output)));
+    // Contracts are pre-signed against the funding txids, funding txs can't be replaced.
+    assert!(funding
+        .funding_txes
+        .iter()
+        .all(|tx| !tx.is_explicitly_rbf()));
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,89 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::Amount;
+use bitcoind::bitcoincore_rpc::RpcApi;
+use coinswap::{maker::MakerBehavior, utill::ConnectionType, wallet::OutputShaping};
+
+use coinswap::test_framework::*;
+
+use std::{thread, time::Duration};
+
+/// Test a funding transaction joined by a Maker
+///
+/// The Maker adds a coin of its own and signs it, the Taker signs its inputs of the joined
+/// transaction. A Maker taking more than its coin, less the fee of its part, is refused.
+#[test]
+fn test_collab_funding() {
+    // ---- Setup ----
+
+    let makers_config_map = [((6102, None), MakerBehavior::Normal)];
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        None,
+        ConnectionType::CLEARNET,
+    );
+
+    let client = test_framework.get_client();
+    let mut taker = taker.write().unwrap();
+    let taker_wallet = taker.get_wallet_mut();
+    let mut maker_wallet = makers[0].get_wallet().write().unwrap();
+
+    test_framework.fund_wallet(taker_wallet, 1, Amount::from_btc(0.05).unwrap());
+    test_framework.fund_wallet(&mut maker_wallet, 2, Amount::from_btc(0.01).unwrap());
+    taker_wallet.sync().unwrap();
+    maker_wallet.sync().unwrap();
+
+    let destination = client.get_new_address(None, None).unwrap().assume_checked();
+    let destination_spk = destination.script_pubkey();
+    let amount = Amount::from_btc(0.02).unwrap();
+    let funding_tx = taker_wallet
+        .create_funding_txes(
+            amount,
+            &[destination],
+            Amount::from_sat(1000),
+            OutputShaping::None,
+            None,
+        )
+        .unwrap()
+        .funding_txes
+        .remove(0);
+
+    // ----- Test -----
+
+    let psbt = taker_wallet.collab_funding_psbt(&funding_tx).unwrap();
+    let fee_rate = psbt.fee().unwrap() / funding_tx.weight();
+    let mut psbts = [psbt];
+    maker_wallet
+        .join_collab_funding(&mut psbts, fee_rate)
+        .unwrap();
+    let [joined] = psbts;
+    assert_eq!(joined.unsigned_tx.input.len(), funding_tx.input.len() + 1);
+    assert_eq!(joined.unsigned_tx.output.len(), funding_tx.output.len() + 1);
+
+    // The Maker's output can't take from the Taker.
+    let mut greedy = joined.clone();
+    greedy.unsigned_tx.output.last_mut().unwrap().value += Amount::from_sat(1000);
+    assert!(taker_wallet
+        .sign_collab_funding(&funding_tx, &greedy, fee_rate)
+        .is_err());
+
+    let joined_tx = taker_wallet
+        .sign_collab_funding(&funding_tx, &joined, fee_rate)
+        .unwrap();
+    let txid = client.send_raw_transaction(&joined_tx).unwrap();
+    test_framework.generate_blocks(1);
+    let tx = client.get_raw_transaction(&txid, None).unwrap();
+    assert!(tx
+        .output
+        .iter()
+        .any(|o| o.script_pubkey == destination_spk && o.value == amount));
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,170 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::{Amount, FeeRate};
+use bitcoind::bitcoincore_rpc::RpcApi;
+use coinswap::{
+    maker::MakerBehavior,
+    protocol::messages::{
+        CollabFunding, MakerHello, MakerToTakerMessage, TakerHello, TakerToMakerMessage,
+    },
+    taker::SwapParams,
+    utill::{read_message, send_message, ConnectionType},
+    wallet::{ExposureKind, SwapOutcome},
+};
+
+use coinswap::test_framework::*;
+
+use log::{info, warn};
+use std::{net::TcpStream, thread, time::Duration};
+
+fn say_hello(socket: &mut TcpStream, min: u32, max: u32) -> MakerHello {
+    send_message(
+        socket,
+        &TakerToMakerMessage::TakerHello(TakerHello {
+            protocol_version_min: min,
+            protocol_version_max: max,
+        }),
+    )
+    .unwrap();
+    match serde_cbor::from_slice(&read_message(socket).unwrap()).unwrap() {
+        MakerToTakerMessage::MakerHello(hello) => hello,
+        msg => panic!("Expected MakerHello, got {}", msg),
+    }
+}
+
+/// A coinswap whose first hop funding is joined by the first Maker.
+///
+/// A Taker of an older protocol version still gets a hello it understands, and a Maker only joins
+/// funding txs once it has signed their contracts.
+#[test]
+fn test_collab_funding_swap() {
+    // ---- Setup ----
+
+    let makers_config_map = [
+        ((6102, None), MakerBehavior::Normal),
+        ((16102, None), MakerBehavior::Normal),
+    ];
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        None,
+        ConnectionType::CLEARNET,
+    );
+
+    warn!("Running Test: Collaborative Funding Coinswap");
+
+    taker.write().unwrap().config.collaborative_funding = true;
+    taker.write().unwrap().config.record_utxo_exposure = true;
+
+    // The Makers need a coin to join the funding with, on top of their swap liquidity.
+    test_framework.fund_wallet(
+        taker.write().unwrap().get_wallet_mut(),
+        3,
+        Amount::from_btc(0.05).unwrap(),
+    );
+    makers.iter().for_each(|maker| {
+        test_framework.fund_wallet(
+            &mut maker.get_wallet().write().unwrap(),
+            7,
+            Amount::from_btc(0.05).unwrap(),
+        );
+    });
+
+    let maker_threads = TestFramework::start_makers(&makers);
+
+    // ---- Message exchange ----
+
+    // A Taker only speaking the first version.
+    let mut socket = TcpStream::connect("127.0.0.1:6102").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .unwrap();
+    assert_eq!(
+        say_hello(&mut socket, 1, 1),
+        MakerHello {
+            protocol_version_min: 1,
+            protocol_version_max: 1,
+        }
+    );
+    drop(socket);
+
+    // No funding is joined before the contracts are signed.
+    let mut socket = TcpStream::connect("127.0.0.1:6102").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(30)))
+        .unwrap();
+    let hello = say_hello(&mut socket, 1, 3);
+    assert_eq!(hello.protocol_version_max, 3);
+    send_message(
+        &mut socket,
+        &TakerToMakerMessage::ReqCollabFunding(CollabFunding {
+            psbts: Vec::new(),
+            fee_rate: FeeRate::BROADCAST_MIN,
+        }),
+    )
+    .unwrap();
+    assert!(read_message(&mut socket).is_err());
+    drop(socket);
+
+    // ---- Swap ----
+
+    let swap_params = SwapParams {
+        send_amount: Amount::from_sat(500000),
+        maker_count: 2,
+        tx_count: 3,
+        required_confirms: 1,
+        fee_rate: Amount::from_sat(1000),
+    };
+
+    info!("Initiating coinswap protocol");
+    let result = taker.write().unwrap().do_coinswap(swap_params);
+
+    makers.iter().for_each(|maker| maker.shutdown().unwrap());
+    maker_threads
+        .into_iter()
+        .for_each(|thread| thread.join().unwrap());
+    result.unwrap();
+
+    // ---- After Swap Asserts ----
+
+    let taker_history = taker.read().unwrap().get_wallet().swap_history().unwrap();
+    assert_eq!(taker_history.len(), 1);
+    assert_eq!(taker_history[0].outcome, SwapOutcome::Completed);
+
+    // Each funding tx got an input and a change output of the first Maker.
+    let report = taker
+        .read()
+        .unwrap()
+        .exposure_report(&taker_history[0].swap_id)
+        .unwrap();
+    let client = test_framework.get_client();
+    let funding_txs = report.makers[0]
+        .utxos
+        .iter()
+        .filter(|(_, kind)| *kind == ExposureKind::FundingChange)
+        .map(|(outpoint, _)| client.get_raw_transaction(&outpoint.txid, None).unwrap())
+        .collect::<Vec<_>>();
+    assert!(!funding_txs.is_empty());
+    for funding_tx in &funding_txs {
+        assert_eq!(funding_tx.output.len(), 3);
+        assert!(funding_tx.input.len() >= 2);
+    }
+
+    // The joined coins were spent, none of them is left locked.
+    makers.iter().for_each(|maker| {
+        let wallet = maker.get_wallet().read().unwrap();
+        for funding_tx in &funding_txs {
+            assert!(funding_tx
+                .input
+                .iter()
+                .all(|input| !wallet.is_utxo_locked(&input.previous_output)));
+        }
+    });
+
+    info!("Collaborative funding swap completed successfully.");
+
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
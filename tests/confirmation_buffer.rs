@@ -0,0 +1,72 @@
+#![cfg(feature = "integration-test")]
+use bip39::Mnemonic;
+use bitcoin::Amount;
+use coinswap::{
+    utill::ConnectionType,
+    wallet::{RPCConfig, Wallet},
+};
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, thread, time::Duration};
+
+/// Test Wallet sync with a confirmation buffer
+///
+/// Rescans stop `confirmation_buffer` blocks below the tip, and the next sync catches up once
+/// the chain has grown.
+#[test]
+fn test_wallet_sync_with_confirmation_buffer() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let taker = taker.read().unwrap();
+    let wallet_path = taker
+        .get_wallet()
+        .get_file_path()
+        .with_file_name("buffered-wallet");
+    let rpc_config = RPCConfig {
+        wallet_name: "buffered-wallet".to_string(),
+        confirmation_buffer: 3,
+        ..RPCConfig::from(test_framework.as_ref())
+    };
+    let mut wallet = Wallet::init_with_birthday(
+        &wallet_path,
+        &rpc_config,
+        Mnemonic::generate(12).unwrap().to_string(),
+        "".to_string(),
+        Some(0),
+    )
+    .unwrap();
+
+    // ----- Test -----
+
+    wallet.sync().unwrap();
+    let (synced_height, _) = wallet.get_last_synced_block().unwrap();
+    assert_eq!(synced_height, test_framework.get_block_count() - 3);
+
+    // Nothing got buried since.
+    wallet.sync().unwrap();
+    assert_eq!(
+        wallet.get_last_synced_block().unwrap().0,
+        test_framework.get_block_count() - 3
+    );
+
+    let addrs = wallet.get_next_external_address().unwrap();
+    test_framework.send_to_address(&addrs, Amount::from_btc(0.05).unwrap());
+    test_framework.generate_blocks(4);
+    wallet.sync().unwrap();
+    let (synced_height, _) = wallet.get_last_synced_block().unwrap();
+    assert_eq!(synced_height, test_framework.get_block_count() - 3);
+    assert_eq!(wallet.balance().unwrap(), Amount::from_btc(0.05).unwrap());
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,152 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::{Address, Amount, CompressedPublicKey, Network, PrivateKey};
+use bitcoind::bitcoincore_rpc::{Client, RpcApi};
+use coinswap::{
+    utill::{generate_keypair, ConnectionType},
+    wallet::{RPCConfig, StaticFeeEstimator, Wallet, WalletError},
+};
+use serde_json::Value;
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, convert::TryFrom, thread, time::Duration};
+
+/// Test setting up wallets over descriptors of wallets created outside coinswap
+///
+/// The ranged descriptor of a Bitcoin Core wallet, and a fixed descriptor of a single key. Their
+/// coins are found by sync, and spendable. Descriptors without private keys are refused.
+#[test]
+fn test_existing_descriptor_wallet() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let client = test_framework.get_client();
+    let wallets_dir = taker
+        .read()
+        .unwrap()
+        .get_wallet()
+        .get_file_path()
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    let rpc_config = |wallet_name: &str| RPCConfig {
+        wallet_name: wallet_name.to_string(),
+        ..RPCConfig::from(test_framework.as_ref())
+    };
+
+    // A Core wallet with private keys, funded twice.
+    client
+        .create_wallet("external-core", None, None, None, None)
+        .unwrap();
+    let external_core = Client::try_from(&rpc_config("external-core")).unwrap();
+    for _ in 0..2 {
+        let address = external_core
+            .get_new_address(None, None)
+            .unwrap()
+            .assume_checked();
+        test_framework.send_to_address(&address, Amount::from_btc(0.05).unwrap());
+    }
+    let listed: Value = external_core
+        .call("listdescriptors", &[true.into()])
+        .unwrap();
+    let ranged_descriptor = listed["descriptors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|d| d["desc"].as_str().unwrap().starts_with("wpkh(") && d["internal"] == false)
+        .unwrap()["desc"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // A single key.
+    let (pubkey, privkey) = generate_keypair();
+    let fixed_descriptor = format!(
+        "wpkh({})",
+        PrivateKey::new(privkey, Network::Regtest).to_wif()
+    );
+    let fixed_address = Address::p2wpkh(
+        &CompressedPublicKey::try_from(pubkey).unwrap(),
+        Network::Regtest,
+    );
+    test_framework.send_to_address(&fixed_address, Amount::from_btc(0.02).unwrap());
+    test_framework.generate_blocks(1);
+
+    // ----- Test -----
+
+    let mut ranged = Wallet::from_existing_descriptor(
+        &wallets_dir.join("existing-ranged"),
+        &ranged_descriptor,
+        &rpc_config("existing-ranged"),
+    )
+    .unwrap();
+    ranged.sync().unwrap();
+    assert_eq!(
+        ranged.get_balances().unwrap().spendable,
+        Amount::from_btc(0.1).unwrap()
+    );
+    // The descriptor stays imported.
+    assert!(ranged
+        .sync_dry_run()
+        .unwrap()
+        .descriptors_to_import
+        .is_empty());
+
+    let mut fixed = Wallet::from_existing_descriptor(
+        &wallets_dir.join("existing-fixed"),
+        &fixed_descriptor,
+        &rpc_config("existing-fixed"),
+    )
+    .unwrap();
+    fixed.sync().unwrap();
+    assert_eq!(
+        fixed.get_balances().unwrap().spendable,
+        Amount::from_btc(0.02).unwrap()
+    );
+    assert!(fixed
+        .sync_dry_run()
+        .unwrap()
+        .descriptors_to_import
+        .is_empty());
+
+    // Coins of both descriptors are spendable.
+    for wallet in [&mut ranged, &mut fixed] {
+        let destination = client.get_new_address(None, None).unwrap().assume_checked();
+        let txid = wallet
+            .send_to(
+                destination,
+                Amount::from_btc(0.015).unwrap(),
+                &StaticFeeEstimator::from_sat_per_vb(2.0),
+            )
+            .unwrap();
+        assert!(client.get_raw_transaction(&txid, None).is_ok());
+    }
+    test_framework.generate_blocks(1);
+    ranged.sync().unwrap();
+    assert!(ranged.get_balances().unwrap().spendable < Amount::from_btc(0.085).unwrap());
+
+    // The descriptor's private key is needed.
+    let public_descriptor = client
+        .get_descriptor_info(&ranged_descriptor)
+        .unwrap()
+        .descriptor;
+    assert!(matches!(
+        Wallet::from_existing_descriptor(
+            &wallets_dir.join("existing-public"),
+            &public_descriptor,
+            &rpc_config("existing-public"),
+        ),
+        Err(WalletError::UnsupportedDescriptor(_))
+    ));
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,74 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::Amount;
+use coinswap::{
+    maker::{refund_expired_swaps, MakerBehavior},
+    taker::SwapParams,
+    utill::ConnectionType,
+};
+
+use bitcoind::bitcoincore_rpc::RpcApi;
+
+use coinswap::test_framework::*;
+
+use log::{info, warn};
+use std::{thread, time::Duration};
+
+/// Test the expired swap watchdog after a completed swap
+///
+/// The makers know the preimage of their incoming swapcoins, so the swap is complete. The
+/// watchdog must not broadcast the contracts of their outgoing swapcoins.
+#[test]
+fn test_watchdog_leaves_completed_swaps() {
+    // ---- Setup ----
+
+    let makers_config_map = [
+        ((6102, None), MakerBehavior::Normal),
+        ((16102, None), MakerBehavior::Normal),
+    ];
+
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        None,
+        ConnectionType::CLEARNET,
+    );
+
+    warn!("Running Test: Expired swap watchdog after a completed swap");
+
+    test_framework.fund_swap_wallets(&taker, &makers);
+
+    // ----- Test -----
+
+    let swap_params = SwapParams {
+        send_amount: Amount::from_sat(500000),
+        maker_count: 2,
+        tx_count: 3,
+        required_confirms: 1,
+        fee_rate: Amount::from_sat(1000),
+    };
+
+    info!("Initiating Makers and the coinswap protocol");
+    TestFramework::run_swap(&taker, &makers, swap_params).unwrap();
+
+    makers.iter().for_each(|maker| {
+        assert!(refund_expired_swaps(maker).unwrap().is_empty());
+        let wallet = maker.get_wallet().read().unwrap();
+        assert_eq!(wallet.get_swapcoins_count(), 6);
+        for outgoing in wallet.get_outgoing_swapcoin_list().unwrap().values() {
+            let contract_txid = outgoing.contract_tx.compute_txid();
+            assert!(test_framework
+                .get_client()
+                .get_raw_transaction_info(&contract_txid, None)
+                .is_err());
+        }
+    });
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,130 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::{Address, Amount, Txid};
+use bitcoind::bitcoincore_rpc::{Client, RpcApi};
+use coinswap::{
+    utill::ConnectionType,
+    wallet::{Destination, SendAmount, StaticFeeEstimator, Wallet, WalletError},
+};
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, thread, time::Duration};
+
+/// Sends from the wallet paying `fee`, and runs `accelerate` on the unconfirmed send.
+///
+/// The framework mines blocks every few seconds. Retries if one confirms the send first.
+fn accelerate_unconfirmed_send<T>(
+    client: &Client,
+    wallet: &mut Wallet,
+    destination: &Address,
+    fee: Amount,
+    accelerate: impl Fn(&mut Wallet, Txid) -> Result<T, WalletError>,
+) -> (Txid, T) {
+    (0..5)
+        .find_map(|_| {
+            let coins = wallet.list_descriptor_utxo_spend_info(None).unwrap();
+            let tx = wallet
+                .spend_from_wallet(
+                    fee,
+                    SendAmount::Amount(Amount::from_sat(1_000_000)),
+                    Destination::Address(destination.clone()),
+                    &coins,
+                )
+                .unwrap();
+            assert!(tx.is_explicitly_rbf());
+            let txid = client.send_raw_transaction(&tx).unwrap();
+            match accelerate(wallet, txid) {
+                Ok(accelerated) => Some((txid, accelerated)),
+                Err(e) => {
+                    assert!(
+                        client
+                            .get_raw_transaction_info(&txid, None)
+                            .unwrap()
+                            .confirmations
+                            .is_some(),
+                        "{:?}",
+                        e
+                    );
+                    wallet.sync().unwrap();
+                    None
+                }
+            }
+        })
+        .unwrap()
+}
+
+/// Test fee bumping of wallet transactions
+///
+/// A direct send signals RBF, and gets replaced by a transaction paying a higher fee out of its
+/// change. Confirmed transactions can't be bumped.
+///
+/// A low fee send gets a CPFP child spending its change instead, and the parent and child together
+/// pay the target fee rate.
+#[test]
+fn test_fee_bump() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let client = test_framework.get_client();
+    let mut taker = taker.write().unwrap();
+    let wallet = taker.get_wallet_mut();
+
+    let addrs = wallet.get_next_external_address().unwrap();
+    test_framework.send_to_address(&addrs, Amount::from_btc(0.05).unwrap());
+    test_framework.generate_blocks(1);
+    wallet.sync().unwrap();
+
+    let destination = client.get_new_address(None, None).unwrap().assume_checked();
+
+    // ----- Test -----
+
+    // RBF
+    let (txid, replacement_txid) = accelerate_unconfirmed_send(
+        client,
+        wallet,
+        &destination,
+        Amount::from_sat(1000),
+        |wallet, txid| wallet.bump_fee(txid, &StaticFeeEstimator::from_sat_per_vb(20.0)),
+    );
+
+    assert_ne!(txid, replacement_txid);
+    let mempool = client.get_raw_mempool().unwrap();
+    assert!(mempool.contains(&replacement_txid));
+    assert!(!mempool.contains(&txid));
+
+    let entry = client.get_mempool_entry(&replacement_txid).unwrap();
+    assert!(entry.fees.base.to_sat() >= 20 * entry.vsize);
+
+    test_framework.generate_blocks(1);
+    assert!(wallet
+        .bump_fee(replacement_txid, &StaticFeeEstimator::from_sat_per_vb(40.0))
+        .is_err());
+    wallet.sync().unwrap();
+
+    // CPFP
+    let (parent_txid, child_txid) = accelerate_unconfirmed_send(
+        client,
+        wallet,
+        &destination,
+        Amount::from_sat(200),
+        |wallet, txid| wallet.create_cpfp_child(txid, &StaticFeeEstimator::from_sat_per_vb(20.0)),
+    );
+
+    let child = client.get_raw_transaction(&child_txid, None).unwrap();
+    assert_eq!(child.input[0].previous_output.txid, parent_txid);
+
+    let entry = client.get_mempool_entry(&child_txid).unwrap();
+    assert_eq!(entry.ancestor_count, 2);
+    assert!(entry.fees.ancestor.to_sat() as f64 / entry.ancestor_size as f64 >= 20.0);
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,120 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::{absolute::LockTime, Amount};
+use bitcoind::bitcoincore_rpc::RpcApi;
+use coinswap::{
+    maker::MakerBehavior,
+    utill::ConnectionType,
+    wallet::{FidelityError, StaticFeeEstimator, UtxoOrigin, WalletError},
+};
+
+use coinswap::test_framework::*;
+
+use std::{thread, time::Duration};
+
+/// Test Fidelity Bond redemption with a fee rate
+///
+/// A bond can't be redeemed before its locktime. Once the chain is mined past it, the bond is spent
+/// back to the wallet and marked as spent.
+#[test]
+fn test_fidelity_redeem_with_fee_rate() {
+    // ---- Setup ----
+
+    let makers_config_map = [((6103, None), MakerBehavior::Normal)];
+
+    let (test_framework, _, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        None,
+        ConnectionType::CLEARNET,
+    );
+
+    let maker = makers.first().unwrap();
+    let mut wallet = maker.get_wallet().write().unwrap();
+
+    let maker_addrs = wallet.get_next_external_address().unwrap();
+    test_framework.send_to_address(&maker_addrs, Amount::from_btc(0.05).unwrap());
+    test_framework.generate_blocks(1);
+    wallet.sync().unwrap();
+
+    // ----- Test -----
+
+    let maturity_height = test_framework.get_block_count() as u32 + 500;
+    let index = wallet
+        .create_fidelity(
+            Amount::from_sat(1000000),
+            LockTime::from_height(maturity_height).unwrap(),
+        )
+        .unwrap();
+
+    // The bond is confirmed, and its depth follows the tip.
+    let tip = test_framework.get_block_count();
+    let confirmations = wallet.fidelity_bond_confirmations(index, tip).unwrap();
+    assert!(confirmations >= 1);
+    assert_eq!(
+        wallet.fidelity_bond_confirmations(index, tip + 10),
+        Some(confirmations + 10)
+    );
+
+    // Not matured yet.
+    assert!(matches!(
+        wallet.redeem_fidelity_bond(index, &StaticFeeEstimator::from_sat_per_vb(2.0)),
+        Err(WalletError::Fidelity(FidelityError::BondNotMatured { .. }))
+    ));
+
+    test_framework.generate_blocks(500);
+    wallet.sync().unwrap();
+    let balance_before = wallet.balance_descriptor_utxo(None).unwrap();
+
+    // The synced bond shows up in its own category.
+    let balances = wallet.get_balances().unwrap();
+    assert_eq!(balances.fidelity, Amount::from_sat(1000000));
+    assert_eq!(balances.spendable, balance_before);
+    assert_eq!(balances.incoming_swapcoins, Amount::ZERO);
+    assert_eq!(balances.outgoing_swapcoins, Amount::ZERO);
+
+    let txid = wallet
+        .redeem_fidelity_bond(index, &StaticFeeEstimator::from_sat_per_vb(2.0))
+        .unwrap();
+
+    let (_, _, is_spent) = wallet.get_fidelity_bonds().get(&index).unwrap();
+    assert!(is_spent);
+    assert!(test_framework
+        .get_client()
+        .get_raw_transaction_info(&txid, None)
+        .unwrap()
+        .blockhash
+        .is_some());
+
+    // The bond's amount, minus a small fee, is back in the wallet.
+    let balance_after = wallet.balance_descriptor_utxo(None).unwrap();
+    let redeemed = balance_after - balance_before;
+    assert!(redeemed < Amount::from_sat(1000000));
+    assert!(redeemed > Amount::from_sat(1000000 - 1000));
+
+    // The redemption is told apart from the deposit and the bond creation's change.
+    let origins = wallet.list_utxo_with_origin().unwrap();
+    let origin_of = |txid| {
+        origins
+            .iter()
+            .filter(|(utxo, _)| utxo.txid == txid)
+            .map(|(_, origin)| origin.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(origin_of(txid), vec![UtxoOrigin::FidelityRedeem]);
+    let bond_txid = wallet.get_fidelity_bonds()[&index].0.outpoint.txid;
+    assert_eq!(origin_of(bond_txid), vec![UtxoOrigin::Change]);
+    assert!(origins.iter().all(|(utxo, origin)| utxo.txid == txid
+        || utxo.txid == bond_txid
+        || *origin == UtxoOrigin::External));
+
+    // ---- Cleanup ----
+
+    drop(wallet);
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
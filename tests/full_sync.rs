@@ -0,0 +1,50 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::Amount;
+use coinswap::utill::ConnectionType;
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, thread, time::Duration};
+
+/// Test the rescans of [`SyncMode::Full`](coinswap::wallet::SyncMode::Full)
+///
+/// Once the descriptors are imported, a sync doesn't rescan: coins received later are seen by
+/// the core wallet as their blocks come in.
+#[test]
+fn test_full_sync_rescans_only_new_imports() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let mut taker = taker.write().unwrap();
+    let wallet = taker.get_wallet_mut();
+    wallet.sync().unwrap();
+
+    // ----- Test -----
+
+    let plan = wallet.sync_dry_run().unwrap();
+    assert!(plan.descriptors_to_import.is_empty());
+    assert_eq!(plan.rescan_from, None);
+
+    let address = wallet.get_next_external_address().unwrap();
+    test_framework.send_to_address(&address, Amount::from_btc(0.05).unwrap());
+    test_framework.generate_blocks(1);
+
+    // The new block alone doesn't call for a rescan.
+    assert_eq!(wallet.sync_dry_run().unwrap().rescan_from, None);
+    wallet.sync().unwrap();
+    assert_eq!(
+        wallet.get_balances().unwrap().spendable,
+        Amount::from_btc(0.05).unwrap()
+    );
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
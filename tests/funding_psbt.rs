@@ -0,0 +1,78 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::{secp256k1::Secp256k1, Amount};
+use bitcoind::bitcoincore_rpc::RpcApi;
+use coinswap::utill::ConnectionType;
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, thread, time::Duration};
+
+/// Test funding through a PSBT signed outside of the wallet
+///
+/// The PSBT carries the BIP32 derivations of its inputs from the master key, which is all an
+/// offline signer holding the master key needs to sign it.
+#[test]
+fn test_funding_psbt() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let client = test_framework.get_client();
+    let mut taker = taker.write().unwrap();
+    let wallet = taker.get_wallet_mut();
+
+    for _ in 0..2 {
+        let addrs = wallet.get_next_external_address().unwrap();
+        test_framework.send_to_address(&addrs, Amount::from_btc(0.05).unwrap());
+    }
+    test_framework.generate_blocks(1);
+    wallet.sync().unwrap();
+
+    let master_key = wallet.export_backup().unwrap().master_key;
+    let destination = client.get_new_address(None, None).unwrap().assume_checked();
+
+    // ----- Test -----
+
+    let amount = Amount::from_btc(0.07).unwrap();
+    let fee = Amount::from_sat(2000);
+    let mut psbt = wallet
+        .create_funding_psbt(&destination, amount, fee)
+        .unwrap();
+
+    assert_eq!(psbt.inputs.len(), 2);
+    assert_eq!(psbt.unsigned_tx.output.len(), 2);
+    assert_eq!(psbt.fee().unwrap(), fee);
+    let fingerprint = master_key.fingerprint(&Secp256k1::new());
+    for input in &psbt.inputs {
+        assert!(input.witness_utxo.is_some());
+        let (_, (input_fingerprint, path)) = input.bip32_derivation.iter().next().unwrap();
+        assert_eq!(*input_fingerprint, fingerprint);
+        assert!(path.to_string().starts_with("84'/1'/0'/0/"));
+    }
+    let (_, (_, change_path)) = psbt.outputs[1].bip32_derivation.iter().next().unwrap();
+    assert!(change_path.to_string().starts_with("84'/1'/0'/1/"));
+
+    // Unsigned PSBTs can't be broadcasted.
+    assert!(wallet.finalize_and_broadcast_psbt(psbt.clone()).is_err());
+
+    // The offline signer only has the master key.
+    let signed = psbt.sign(&master_key, &Secp256k1::new()).unwrap();
+    assert_eq!(signed.len(), 2);
+
+    let txid = wallet.finalize_and_broadcast_psbt(psbt).unwrap();
+    let tx = client.get_raw_transaction(&txid, None).unwrap();
+    assert!(tx
+        .output
+        .iter()
+        .any(|o| o.script_pubkey == destination.script_pubkey() && o.value == amount));
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,155 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::{
+    hashes::Hash,
+    secp256k1::{Message, Secp256k1},
+    Amount, Network,
+};
+use coinswap::{
+    maker::{start_maker_server, MakerBehavior},
+    taker::offers::fetch_offers_from_dns,
+    utill::ConnectionType,
+};
+
+use coinswap::test_framework::*;
+
+use std::{thread, time::Duration};
+
+/// Makers publish their offers to the directory server
+///
+/// At startup each Maker publishes its offer, signed with its fidelity bond key. The Taker fetches
+/// the offers from the directory and verifies the signatures and the fidelity bonds on-chain.
+#[test]
+fn test_maker_publish_offer() {
+    // ---- Setup ----
+
+    let makers_config_map = [
+        ((6102, None), MakerBehavior::Normal),
+        ((16102, None), MakerBehavior::Normal),
+    ];
+
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        None,
+        ConnectionType::CLEARNET,
+    );
+
+    // Fund the Makers with their bond and some liquidity.
+    for _ in 0..2 {
+        makers.iter().for_each(|maker| {
+            let maker_addrs = maker
+                .get_wallet()
+                .write()
+                .unwrap()
+                .get_next_external_address()
+                .unwrap();
+            test_framework.send_to_address(&maker_addrs, Amount::from_btc(0.05).unwrap());
+        });
+    }
+    test_framework.generate_blocks(1);
+
+    let maker_threads = makers
+        .iter()
+        .map(|maker| {
+            let maker_clone = maker.clone();
+            thread::spawn(move || {
+                start_maker_server(maker_clone).unwrap();
+            })
+        })
+        .collect::<Vec<_>>();
+
+    makers.iter().for_each(|maker| {
+        while !*maker.is_setup_complete.read().unwrap() {
+            log::info!("Waiting for maker setup completion");
+            thread::sleep(Duration::from_secs(10));
+        }
+    });
+
+    // ----- Test -----
+
+    let mut offers = Vec::new();
+    for _ in 0..10 {
+        offers =
+            fetch_offers_from_dns(None, "127.0.0.1:8080".to_string(), ConnectionType::CLEARNET)
+                .unwrap();
+        if offers.len() == makers.len() {
+            break;
+        }
+        thread::sleep(Duration::from_secs(5));
+    }
+    assert_eq!(offers.len(), 2);
+
+    for maker in &makers {
+        let address = format!("127.0.0.1:{}", maker.config.port);
+        let offer_and_address = offers
+            .iter()
+            .find(|oa| oa.address.to_string() == address)
+            .expect("offer of every maker");
+        let offer = &offer_and_address.offer;
+
+        assert_eq!(offer.network, Network::Regtest);
+        assert_eq!(
+            offer.fee_policy.min_swap_amount,
+            maker.config.fee_policy.min_swap_amount
+        );
+        assert_eq!(
+            offer.fidelity,
+            maker
+                .highest_fidelity_proof
+                .read()
+                .unwrap()
+                .clone()
+                .unwrap()
+        );
+        offer.verify_signature().unwrap();
+        let bond_value = taker
+            .read()
+            .unwrap()
+            .get_wallet()
+            .verify_fidelity_proof(offer, &address)
+            .unwrap();
+        assert!(bond_value > 0);
+
+        // A bond that isn't on-chain is rejected, even when signed by the bond key.
+        let mut fake_bond = offer.clone();
+        fake_bond.fidelity.bond.amount = Amount::from_btc(1.0).unwrap();
+        let fidelity_key = maker
+            .get_wallet()
+            .read()
+            .unwrap()
+            .get_fidelity_keypair(0)
+            .unwrap()
+            .secret_key();
+        fake_bond.offer_sig = Secp256k1::new().sign_ecdsa(
+            &Message::from_digest(fake_bond.terms_hash().to_byte_array()),
+            &fidelity_key,
+        );
+        fake_bond.verify_signature().unwrap();
+        assert!(taker
+            .read()
+            .unwrap()
+            .get_wallet()
+            .verify_fidelity_proof(&fake_bond, &address)
+            .is_err());
+
+        // Tampered offers are rejected.
+        let mut tampered = offer.clone();
+        tampered.fee_policy.base_fee_sats = 0;
+        assert!(tampered.verify_signature().is_err());
+    }
+
+    // ---- Cleanup ----
+
+    makers.iter().for_each(|maker| maker.shutdown().unwrap());
+
+    maker_threads
+        .into_iter()
+        .for_each(|thread| thread.join().unwrap());
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -6,8 +6,7 @@ use coinswap::{
     utill::ConnectionType,
 };
 
-mod test_framework;
-use test_framework::*;
+use coinswap::test_framework::*;
 
 use std::{collections::BTreeSet, thread, time::Duration};
 
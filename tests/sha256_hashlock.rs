@@ -0,0 +1,132 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::{
+    hashes::{hash160::Hash as Hash160, Hash},
+    Amount,
+};
+use coinswap::{
+    maker::MakerBehavior,
+    protocol::contract::{read_hashlock_from_contract, HashlockType},
+    taker::SwapParams,
+    utill::ConnectionType,
+    wallet::{Destination, SendAmount, SwapCoin},
+};
+
+use bitcoind::bitcoincore_rpc::RpcApi;
+
+use coinswap::test_framework::*;
+
+use log::{info, warn};
+use std::{thread, time::Duration};
+
+/// Test a coinswap round with SHA256 hashlocks
+///
+/// The Taker locks the contracts with SHA256 instead of the default HASH160. The Makers follow
+/// it on their outgoing contracts, the swap completes, and the swapcoins are spendable.
+#[test]
+fn test_sha256_hashlock_coinswap() {
+    // ---- Setup ----
+
+    let makers_config_map = [
+        ((6102, None), MakerBehavior::Normal),
+        ((16102, None), MakerBehavior::Normal),
+    ];
+
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        None,
+        ConnectionType::CLEARNET,
+    );
+
+    warn!("Running Test: Coinswap with SHA256 hashlocks");
+
+    taker.write().unwrap().config.hashlock_type = HashlockType::Sha256;
+
+    test_framework.fund_wallet(
+        taker.write().unwrap().get_wallet_mut(),
+        3,
+        Amount::from_btc(0.05).unwrap(),
+    );
+    makers.iter().for_each(|maker| {
+        test_framework.fund_wallet(
+            &mut maker.get_wallet().write().unwrap(),
+            4,
+            Amount::from_btc(0.05).unwrap(),
+        );
+    });
+
+    // ----- Test -----
+
+    let swap_params = SwapParams {
+        send_amount: Amount::from_sat(500000),
+        maker_count: 2,
+        tx_count: 3,
+        required_confirms: 1,
+        fee_rate: Amount::from_sat(1000),
+    };
+
+    info!("Initiating Makers and the coinswap protocol");
+    TestFramework::run_swap(&taker, &makers, swap_params).unwrap();
+
+    // Every contract of every party is locked with SHA256.
+    let assert_sha256_contracts = |wallet: &coinswap::wallet::Wallet| {
+        let incoming = wallet.get_incoming_swapcoin_list().unwrap();
+        let outgoing = wallet.get_outgoing_swapcoin_list().unwrap();
+        assert_eq!(incoming.len() + outgoing.len(), 6);
+        let preimage = incoming.values().next().unwrap().hash_preimage.unwrap();
+        let contract_redeemscripts = incoming
+            .values()
+            .map(|swapcoin| swapcoin.get_contract_redeemscript())
+            .chain(
+                outgoing
+                    .values()
+                    .map(|swapcoin| swapcoin.get_contract_redeemscript()),
+            );
+        for contract_redeemscript in contract_redeemscripts {
+            let hashlock = read_hashlock_from_contract(&contract_redeemscript).unwrap();
+            assert_eq!(hashlock.hashlock_type(), HashlockType::Sha256);
+            assert_eq!(hashlock.hashvalue(), Hash160::hash(&preimage));
+        }
+    };
+    assert_sha256_contracts(taker.read().unwrap().get_wallet());
+    makers.iter().for_each(|maker| {
+        assert_sha256_contracts(&maker.get_wallet().read().unwrap());
+    });
+
+    // The received swapcoins are spendable.
+    let swap_coins = taker
+        .read()
+        .unwrap()
+        .get_wallet()
+        .list_swap_coin_utxo_spend_info(None)
+        .unwrap();
+    assert_eq!(swap_coins.len(), 3);
+    let tx = taker
+        .write()
+        .unwrap()
+        .get_wallet_mut()
+        .spend_from_wallet(
+            Amount::from_sat(1000),
+            SendAmount::Max,
+            Destination::Wallet,
+            &swap_coins,
+        )
+        .unwrap();
+    let txid = test_framework
+        .get_client()
+        .send_raw_transaction(&tx)
+        .unwrap();
+    assert!(test_framework
+        .get_client()
+        .get_raw_transaction(&txid, None)
+        .is_ok());
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -9,8 +9,7 @@ use coinswap::{
 
 use bitcoind::bitcoincore_rpc::RpcApi;
 
-mod test_framework;
-use test_framework::*;
+use coinswap::test_framework::*;
 
 use log::{info, warn};
 use std::{assert_eq, thread, time::Duration};
@@ -0,0 +1,70 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::Amount;
+use coinswap::{
+    maker::MakerBehavior, taker::SwapParams, utill::ConnectionType, wallet::SwapOutcome,
+};
+
+use coinswap::test_framework::*;
+
+use log::{info, warn};
+use std::{thread, time::Duration};
+
+/// Test the swap history after a completed swap
+///
+/// The Taker and both Makers record the swap as completed, under the same swap id.
+#[test]
+fn test_swap_history() {
+    // ---- Setup ----
+
+    let makers_config_map = [
+        ((6102, None), MakerBehavior::Normal),
+        ((16102, None), MakerBehavior::Normal),
+    ];
+
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        None,
+        ConnectionType::CLEARNET,
+    );
+
+    warn!("Running Test: Swap history of a completed swap");
+
+    test_framework.fund_swap_wallets(&taker, &makers);
+
+    // ----- Test -----
+
+    let swap_params = SwapParams {
+        send_amount: Amount::from_sat(500000),
+        maker_count: 2,
+        tx_count: 3,
+        required_confirms: 1,
+        fee_rate: Amount::from_sat(1000),
+    };
+
+    info!("Initiating Makers and the coinswap protocol");
+    TestFramework::run_swap(&taker, &makers, swap_params).unwrap();
+
+    let taker_history = taker.read().unwrap().get_wallet().swap_history().unwrap();
+    assert_eq!(taker_history.len(), 1);
+    assert_eq!(taker_history[0].outcome, SwapOutcome::Completed);
+    assert_eq!(taker_history[0].amount, Amount::from_sat(500000));
+    assert_eq!(taker_history[0].counterparties.len(), 2);
+    assert!(taker_history[0].fee > Amount::ZERO);
+    makers.iter().for_each(|maker| {
+        let maker_history = maker.get_wallet().read().unwrap().swap_history().unwrap();
+        assert_eq!(maker_history.len(), 1);
+        assert_eq!(maker_history[0].outcome, SwapOutcome::Completed);
+        assert_eq!(maker_history[0].swap_id, taker_history[0].swap_id);
+        assert!(maker_history[0].fee > Amount::ZERO);
+    });
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,155 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::{bip32::DerivationPath, secp256k1::Secp256k1, Amount};
+use bitcoind::bitcoincore_rpc::RpcApi;
+use coinswap::{
+    maker::MakerBehavior,
+    taker::SwapParams,
+    utill::ConnectionType,
+    wallet::{RPCConfig, Wallet, WalletSwapCoin},
+};
+
+use coinswap::test_framework::*;
+
+use log::{info, warn};
+use std::{collections::HashSet, str::FromStr, thread, time::Duration};
+
+/// Test deterministic derivation of the swap keys
+///
+/// The keys of the Taker's swapcoins derive back from the wallet seed at the indices recorded in
+/// the swapcoins. A wallet restored from a backup derives the same keys, and doesn't reuse them.
+/// The multisig descriptors carry the origin of our key, and Core keeps it.
+#[test]
+fn test_swap_key_derivation() {
+    // ---- Setup ----
+
+    let makers_config_map = [
+        ((6102, None), MakerBehavior::Normal),
+        ((16102, None), MakerBehavior::Normal),
+    ];
+
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        None,
+        ConnectionType::CLEARNET,
+    );
+
+    warn!("Running Test: Deterministic swap keys");
+
+    test_framework.fund_wallet(
+        taker.write().unwrap().get_wallet_mut(),
+        3,
+        Amount::from_btc(0.05).unwrap(),
+    );
+    makers.iter().for_each(|maker| {
+        test_framework.fund_wallet(
+            &mut maker.get_wallet().write().unwrap(),
+            4,
+            Amount::from_btc(0.05).unwrap(),
+        );
+    });
+
+    // ----- Test -----
+
+    let swap_params = SwapParams {
+        send_amount: Amount::from_sat(500000),
+        maker_count: 2,
+        tx_count: 3,
+        required_confirms: 1,
+        fee_rate: Amount::from_sat(1000),
+    };
+
+    info!("Initiating Makers and the coinswap protocol");
+    TestFramework::run_swap(&taker, &makers, swap_params).unwrap();
+
+    let taker = taker.read().unwrap();
+    let wallet = taker.get_wallet();
+
+    // (index, key) of every key of the Taker's swapcoins.
+    let incoming = wallet.get_incoming_swapcoin_list().unwrap();
+    let outgoing = wallet.get_outgoing_swapcoin_list().unwrap();
+    assert_eq!(incoming.len(), 3);
+    assert_eq!(outgoing.len(), 3);
+    let swap_keys = incoming
+        .values()
+        .flat_map(|sc| {
+            [
+                (sc.my_key_index.unwrap(), sc.my_privkey),
+                (sc.hashlock_key_index.unwrap(), sc.hashlock_privkey),
+            ]
+        })
+        .chain(outgoing.values().flat_map(|sc| {
+            [
+                (sc.my_key_index.unwrap(), sc.my_privkey),
+                (sc.timelock_key_index.unwrap(), sc.timelock_privkey),
+            ]
+        }))
+        .collect::<Vec<_>>();
+
+    // No index is used twice.
+    let indices = swap_keys
+        .iter()
+        .map(|(index, _)| *index)
+        .collect::<HashSet<_>>();
+    assert_eq!(indices.len(), swap_keys.len());
+
+    for (index, privkey) in &swap_keys {
+        assert_eq!(wallet.derive_swap_key(*index).unwrap(), *privkey);
+    }
+
+    // A restored wallet derives the same keys, and continues past them.
+    let backup = wallet.export_backup().unwrap();
+    // The keys are under the wallet's own account, not the mainnet coin type.
+    let secp = Secp256k1::new();
+    for (index, privkey) in &swap_keys {
+        let path = DerivationPath::from_str(&format!("m/84'/1'/0'/3'/{}'", index)).unwrap();
+        assert_eq!(
+            backup
+                .master_key
+                .derive_priv(&secp, &path)
+                .unwrap()
+                .private_key,
+            *privkey
+        );
+    }
+
+    // Core parses the multisig descriptors with the origin of our key intact.
+    let fingerprint = backup.master_key.fingerprint(&secp);
+    for sc in outgoing.values() {
+        let descriptor = sc.multisig_descriptor(fingerprint).unwrap();
+        let origin = format!(
+            "[{}/84'/1'/0'/3'/{}']",
+            fingerprint,
+            sc.my_key_index.unwrap()
+        );
+        assert!(descriptor.contains(&origin));
+        let info = test_framework
+            .get_client()
+            .get_descriptor_info(&descriptor)
+            .unwrap();
+        // Core may write hardened steps with `h`.
+        let parsed = info.descriptor.replace("h/", "'/").replace("h]", "']");
+        assert_eq!(parsed.split('#').next().unwrap(), descriptor);
+    }
+
+    let restored_path = wallet.get_file_path().with_file_name("restored-taker");
+    let rpc_config = RPCConfig {
+        wallet_name: "restored-taker".to_string(),
+        ..RPCConfig::from(test_framework.as_ref())
+    };
+    let restored = Wallet::import_backup(&restored_path, &rpc_config, &backup).unwrap();
+    for (index, privkey) in &swap_keys {
+        assert_eq!(restored.derive_swap_key(*index).unwrap(), *privkey);
+    }
+    let next_index = restored.export_backup().unwrap().swap_key_index;
+    assert!(indices.iter().all(|index| *index < next_index));
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::{absolute::LockTime, Amount, OutPoint};
+use bitcoind::bitcoincore_rpc::RpcApi;
+use coinswap::{
+    utill::ConnectionType,
+    wallet::{FallbackFeeEstimator, StaticFeeEstimator, WalletError},
+};
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, thread, time::Duration};
+
+/// Test sweeping the whole wallet to an external address
+///
+/// Every spendable utxo is spent in a single transaction, but locked utxos and fidelity bonds
+/// stay in the wallet.
+#[test]
+fn test_sweep_all() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let client = test_framework.get_client();
+    let mut taker = taker.write().unwrap();
+    let wallet = taker.get_wallet_mut();
+
+    test_framework.fund_wallet(wallet, 4, Amount::from_btc(0.05).unwrap());
+    wallet.sync().unwrap();
+
+    wallet
+        .create_fidelity(
+            Amount::from_sat(1_000_000),
+            LockTime::from_height(test_framework.get_block_count() as u32 + 100).unwrap(),
+        )
+        .unwrap();
+    test_framework.generate_blocks(1);
+    wallet.sync().unwrap();
+
+    let locked = {
+        let utxo = &wallet.list_descriptor_utxo_spend_info(None).unwrap()[0].0;
+        OutPoint::new(utxo.txid, utxo.vout)
+    };
+    wallet.lock_utxo(locked).unwrap();
+
+    let fidelity_balance = wallet.balance_fidelity_bonds(None).unwrap();
+    let destination = client.get_new_address(None, None).unwrap().assume_checked();
+
+    // ----- Test -----
+
+    // Regtest nodes have no fee estimate, the sweep falls back to 10 sats/vbyte.
+    let fallback = StaticFeeEstimator::from_sat_per_vb(10.0);
+    let txid = wallet
+        .sweep_all(
+            destination.clone(),
+            &FallbackFeeEstimator(vec![client, &fallback]),
+        )
+        .unwrap();
+
+    let tx = client.get_raw_transaction(&txid, None).unwrap();
+    assert_eq!(tx.output.len(), 1);
+    assert_eq!(tx.output[0].script_pubkey, destination.script_pubkey());
+    assert!(tx.input.iter().all(|input| input.previous_output != locked));
+
+    // The framework mines blocks every few seconds, look up the fee from the inputs.
+    let input_value = tx
+        .input
+        .iter()
+        .map(|input| {
+            client
+                .get_raw_transaction(&input.previous_output.txid, None)
+                .unwrap()
+                .output[input.previous_output.vout as usize]
+                .value
+        })
+        .sum::<Amount>();
+    let fee = input_value - tx.output[0].value;
+    assert!(fee.to_sat() >= 10 * tx.vsize() as u64);
+
+    test_framework.generate_blocks(1);
+    wallet.sync().unwrap();
+
+    // Only the locked utxo and the fidelity bond are left.
+    let descriptor_utxos = wallet.list_descriptor_utxo_spend_info(None).unwrap();
+    assert_eq!(descriptor_utxos.len(), 1);
+    assert_eq!(
+        OutPoint::new(descriptor_utxos[0].0.txid, descriptor_utxos[0].0.vout),
+        locked
+    );
+    assert_eq!(
+        wallet.balance_fidelity_bonds(None).unwrap(),
+        fidelity_balance
+    );
+
+    // Nothing left to sweep.
+    assert!(matches!(
+        wallet.sweep_all(destination, &StaticFeeEstimator::from_sat_per_vb(10.0)),
+        Err(WalletError::InsufficientFund { .. })
+    ));
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
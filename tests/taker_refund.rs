@@ -0,0 +1,159 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::Amount;
+use bitcoind::bitcoincore_rpc::RpcApi;
+use coinswap::{
+    maker::MakerBehavior,
+    taker::{SwapParams, SwapPhase, Taker, TakerBehavior},
+    utill::ConnectionType,
+    wallet::{ExposureKind, RPCConfig, SwapOutcome},
+};
+
+use coinswap::test_framework::*;
+use log::warn;
+use std::{path::Path, thread, time::Duration};
+
+/// Taker refunds its outgoing swapcoins after a stalled swap
+///
+/// The Taker drops the swap after broadcasting its funding transactions. It then calls
+/// `recover_from_incomplete_swap` on every new block: the first call broadcasts the outgoing
+/// contracts, and the refunds go out once the contract timelocks mature. Resuming the swap from
+/// its saved state then clears what's left.
+#[test]
+fn test_taker_refund_incomplete_swap() {
+    // ---- Setup ----
+
+    let makers_config_map = [
+        ((6102, None), MakerBehavior::Normal),
+        ((16102, None), MakerBehavior::Normal),
+    ];
+
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        Some(TakerBehavior::DropConnectionAfterFullSetup),
+        ConnectionType::CLEARNET,
+    );
+
+    test_framework.fund_swap_wallets(&taker, &makers);
+
+    let swap_params = SwapParams {
+        send_amount: Amount::from_sat(500000),
+        maker_count: 2,
+        tx_count: 3,
+        required_confirms: 1,
+        fee_rate: Amount::from_sat(1000),
+    };
+    TestFramework::run_swap(&taker, &makers, swap_params).unwrap();
+
+    // ----- Test -----
+
+    let (_, outgoings) = taker
+        .read()
+        .unwrap()
+        .get_wallet()
+        .find_unfinished_swapcoins();
+    assert_eq!(outgoings.len(), 3);
+
+    warn!("Refunding Taker's outgoing swapcoins");
+    let mut refund_txids = Vec::new();
+    for _ in 0..60 {
+        refund_txids.extend(
+            taker
+                .write()
+                .unwrap()
+                .recover_from_incomplete_swap()
+                .unwrap(),
+        );
+        if refund_txids.len() == outgoings.len() {
+            break;
+        }
+        thread::sleep(Duration::from_secs(5));
+    }
+
+    assert_eq!(refund_txids.len(), 3);
+    let (_, outgoings) = taker
+        .read()
+        .unwrap()
+        .get_wallet()
+        .find_unfinished_swapcoins();
+    assert!(outgoings.is_empty());
+
+    let client = test_framework.get_client();
+    for txid in &refund_txids {
+        assert!(client.get_raw_transaction_info(txid, None).is_ok());
+    }
+
+    // Nothing left to refund.
+    assert!(taker
+        .write()
+        .unwrap()
+        .recover_from_incomplete_swap()
+        .unwrap()
+        .is_empty());
+
+    // The saved swap state records how far the swap went, resuming it after a restart drops the
+    // incoming swapcoins and clears the state.
+    warn!("Restarting the taker, and resuming the interrupted swap");
+    {
+        let mut taker = taker.write().unwrap();
+        let wallet_path = taker.get_wallet().get_file_path().clone();
+        *taker = Taker::init(
+            wallet_path
+                .parent()
+                .and_then(Path::parent)
+                .map(Path::to_path_buf),
+            wallet_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string()),
+            Some(RPCConfig::from(test_framework.as_ref())),
+            TakerBehavior::Normal,
+            Some(ConnectionType::CLEARNET),
+        )
+        .unwrap();
+        taker.config.record_utxo_exposure = true;
+    }
+    let state = taker.write().unwrap().resume().unwrap().unwrap();
+    assert_eq!(state.phase, SwapPhase::ContractsEstablished);
+    assert_eq!(state.swap_params, swap_params);
+    assert_eq!(state.maker_route.len(), 2);
+    assert_eq!(state.outgoing_swapcoins.len(), 3);
+    assert_eq!(state.incoming_swapcoins.len(), 3);
+
+    assert_eq!(taker.read().unwrap().get_wallet().get_swapcoins_count(), 0);
+    assert!(taker.write().unwrap().resume().unwrap().is_none());
+
+    // The swap history still knows the route, and what the last maker saw.
+    let record = taker
+        .read()
+        .unwrap()
+        .get_wallet()
+        .swap_history()
+        .unwrap()
+        .pop()
+        .unwrap();
+    assert_eq!(record.outcome, SwapOutcome::Failed);
+    assert_eq!(record.counterparties, state.maker_route);
+    let report = taker
+        .read()
+        .unwrap()
+        .exposure_report(&record.swap_id)
+        .unwrap();
+    assert_eq!(
+        report
+            .makers
+            .iter()
+            .flat_map(|maker| &maker.utxos)
+            .filter(|(_, kind)| *kind == ExposureKind::Received)
+            .count(),
+        3
+    );
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,100 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::Amount;
+use bitcoind::bitcoincore_rpc::RpcApi;
+use coinswap::{
+    maker::MakerBehavior,
+    taker::{SwapParams, SwapPhase, TakerBehavior},
+    utill::ConnectionType,
+};
+
+use coinswap::test_framework::*;
+use log::warn;
+use std::{thread, time::Duration};
+
+/// Taker resumes a swap interrupted while settling
+///
+/// The Taker drops the swap once it started settling, as if it crashed. The makers may know the
+/// preimage by then, so `resume` claims the incoming contracts through the hashlock, and leaves
+/// the outgoing contracts to the makers instead of refunding them through the timelock.
+#[test]
+fn test_taker_resume_settling_swap() {
+    // ---- Setup ----
+
+    let makers_config_map = [
+        ((6102, None), MakerBehavior::Normal),
+        ((16102, None), MakerBehavior::Normal),
+    ];
+
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        Some(TakerBehavior::DropConnectionWhileSettling),
+        ConnectionType::CLEARNET,
+    );
+
+    test_framework.fund_swap_wallets(&taker, &makers);
+
+    let swap_params = SwapParams {
+        send_amount: Amount::from_sat(500000),
+        maker_count: 2,
+        tx_count: 3,
+        required_confirms: 1,
+        fee_rate: Amount::from_sat(1000),
+    };
+    TestFramework::run_swap(&taker, &makers, swap_params).unwrap();
+
+    // ----- Test -----
+
+    let (incoming_contracts, outgoing_contracts) = {
+        let taker = taker.read().unwrap();
+        let wallet = taker.get_wallet();
+        assert_eq!(wallet.get_swapcoins_count(), 6);
+        (
+            wallet
+                .get_incoming_swapcoin_list()
+                .unwrap()
+                .values()
+                .map(|sc| sc.contract_tx.compute_txid())
+                .collect::<Vec<_>>(),
+            wallet
+                .get_outgoing_swapcoin_list()
+                .unwrap()
+                .values()
+                .map(|sc| sc.contract_tx.compute_txid())
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    warn!("Resuming the interrupted swap");
+    let state = taker.write().unwrap().resume().unwrap().unwrap();
+    assert_eq!(state.phase, SwapPhase::Settling);
+    assert_eq!(state.swap_params, swap_params);
+    assert_eq!(state.maker_route.len(), 2);
+    assert_eq!(state.outgoing_swapcoins.len(), 3);
+    assert_eq!(state.incoming_swapcoins.len(), 3);
+
+    // The incoming contracts are spent by the hashlock claims, the outgoing ones aren't even
+    // broadcasted.
+    test_framework.generate_blocks(1);
+    let client = test_framework.get_client();
+    for txid in &incoming_contracts {
+        assert!(client.get_raw_transaction_info(txid, None).is_ok());
+        assert!(client.get_tx_out(txid, 0, Some(false)).unwrap().is_none());
+    }
+    for txid in &outgoing_contracts {
+        assert!(client.get_raw_transaction_info(txid, None).is_err());
+    }
+
+    // All pending swapcoins are cleared, and so is the swap state.
+    assert_eq!(taker.read().unwrap().get_wallet().get_swapcoins_count(), 0);
+    assert!(taker.write().unwrap().resume().unwrap().is_none());
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
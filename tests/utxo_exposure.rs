@@ -0,0 +1,77 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::Amount;
+use coinswap::{
+    maker::MakerBehavior, taker::SwapParams, utill::ConnectionType, wallet::ExposureKind,
+};
+
+use coinswap::test_framework::*;
+
+use log::{info, warn};
+use std::{thread, time::Duration};
+
+/// Test the utxo exposure report of a completed swap
+///
+/// The first Maker saw the Taker's funding inputs, the last one the coins the Taker received.
+#[test]
+fn test_utxo_exposure_report() {
+    // ---- Setup ----
+
+    let makers_config_map = [
+        ((6102, None), MakerBehavior::Normal),
+        ((16102, None), MakerBehavior::Normal),
+    ];
+
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        None,
+        ConnectionType::CLEARNET,
+    );
+
+    warn!("Running Test: Utxo exposure report of a completed swap");
+
+    // Off by default, the record is itself sensitive.
+    taker.write().unwrap().config.record_utxo_exposure = true;
+
+    test_framework.fund_swap_wallets(&taker, &makers);
+
+    // ----- Test -----
+
+    let swap_params = SwapParams {
+        send_amount: Amount::from_sat(500000),
+        maker_count: 2,
+        tx_count: 3,
+        required_confirms: 1,
+        fee_rate: Amount::from_sat(1000),
+    };
+
+    info!("Initiating Makers and the coinswap protocol");
+    TestFramework::run_swap(&taker, &makers, swap_params).unwrap();
+
+    let swap_id = taker.read().unwrap().get_wallet().swap_history().unwrap()[0]
+        .swap_id
+        .clone();
+    let report = taker.read().unwrap().exposure_report(&swap_id).unwrap();
+    assert_eq!(report.makers.len(), 2);
+    assert!(report.makers[0]
+        .utxos
+        .iter()
+        .any(|(_, kind)| *kind == ExposureKind::FundingInput));
+    assert_eq!(
+        report.makers[1]
+            .utxos
+            .iter()
+            .filter(|(_, kind)| *kind == ExposureKind::Received)
+            .count(),
+        3
+    );
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::{Amount, OutPoint};
+use coinswap::utill::ConnectionType;
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, thread, time::Duration};
+
+/// Test the UTXO lock API
+///
+/// A locked utxo must never be picked by coin selection, even when it's the best candidate,
+/// and must be selectable again once unlocked.
+#[test]
+fn test_utxo_lock() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let mut taker = taker.write().unwrap();
+    let wallet = taker.get_wallet_mut();
+
+    for btc in [0.05, 0.01] {
+        let addrs = wallet.get_next_external_address().unwrap();
+        test_framework.send_to_address(&addrs, Amount::from_btc(btc).unwrap());
+    }
+    test_framework.generate_blocks(1);
+    wallet.sync().unwrap();
+
+    // ----- Test -----
+
+    // Largest first selection picks the 0.05 BTC utxo.
+    let selected = wallet.coin_select(Amount::from_sat(500_000)).unwrap();
+    assert_eq!(selected.len(), 1);
+    let big = OutPoint::new(selected[0].0.txid, selected[0].0.vout);
+    assert_eq!(selected[0].0.amount, Amount::from_btc(0.05).unwrap());
+
+    wallet.lock_utxo(big).unwrap();
+    assert!(wallet.is_utxo_locked(&big));
+
+    let selected = wallet.coin_select(Amount::from_sat(500_000)).unwrap();
+    assert!(selected
+        .iter()
+        .all(|(u, _)| OutPoint::new(u.txid, u.vout) != big));
+
+    // Core's locks get reset on every utxo listing, the wallet's lock must survive it.
+    wallet.lock_unspendable_utxos().unwrap();
+    let selected = wallet.coin_select(Amount::from_sat(500_000)).unwrap();
+    assert!(selected
+        .iter()
+        .all(|(u, _)| OutPoint::new(u.txid, u.vout) != big));
+
+    wallet.unlock_utxo(big).unwrap();
+    assert!(!wallet.is_utxo_locked(&big));
+    let selected = wallet.coin_select(Amount::from_sat(500_000)).unwrap();
+    assert_eq!(OutPoint::new(selected[0].0.txid, selected[0].0.vout), big);
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,67 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::Amount;
+use coinswap::{
+    utill::ConnectionType,
+    wallet::{RPCConfig, Wallet, WalletBackup},
+};
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, thread, time::Duration};
+
+/// Test Wallet backup and restore
+///
+/// A backup exported to JSON restores into a new wallet, on a new core wallet, holding the same funds.
+#[test]
+fn test_wallet_backup_restore() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let mut taker = taker.write().unwrap();
+    let wallet = taker.get_wallet_mut();
+
+    let addrs = wallet.get_next_external_address().unwrap();
+    test_framework.send_to_address(&addrs, Amount::from_btc(0.05).unwrap());
+    test_framework.generate_blocks(1);
+    wallet.sync().unwrap();
+
+    // ----- Test -----
+
+    let json = serde_json::to_string(&wallet.export_backup().unwrap()).unwrap();
+    let backup: WalletBackup = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        backup.last_synced_height,
+        wallet.get_last_synced_block().map(|(height, _)| height)
+    );
+
+    let restored_path = wallet.get_file_path().with_file_name("restored-taker");
+    let rpc_config = RPCConfig {
+        wallet_name: "restored-taker".to_string(),
+        ..RPCConfig::from(test_framework.as_ref())
+    };
+    let mut restored = Wallet::import_backup(&restored_path, &rpc_config, &backup).unwrap();
+
+    assert_eq!(restored.balance().unwrap(), wallet.balance().unwrap());
+    assert_eq!(restored.get_external_index(), wallet.get_external_index());
+    // The funded address isn't handed out again.
+    assert_ne!(restored.get_next_external_address().unwrap(), addrs);
+    assert_eq!(
+        restored.export_backup().unwrap().master_key,
+        backup.master_key
+    );
+
+    // The restored file loads like any other wallet.
+    drop(restored);
+    Wallet::load(&rpc_config, &restored_path).unwrap();
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
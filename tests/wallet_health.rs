@@ -0,0 +1,66 @@
+#![cfg(feature = "integration-test")]
+use bitcoind::bitcoincore_rpc::{Client, RpcApi};
+use coinswap::{utill::ConnectionType, wallet::RPCConfig};
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, convert::TryFrom, thread, time::Duration};
+
+/// Test the wallet health check
+///
+/// The health check must report the node's tip and the core wallet state, and report an
+/// unreachable node in the status instead of failing.
+#[test]
+fn test_wallet_health_check() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let taker = taker.read().unwrap();
+    let wallet = taker.get_wallet();
+
+    // ----- Test -----
+
+    let status = wallet.health_check().unwrap();
+    assert!(status.node_reachable);
+    assert_eq!(status.tip_height, Some(test_framework.get_block_count()));
+    assert!(status.wallet_loaded);
+    assert!(!status.scanning);
+    assert!(status.is_ready());
+
+    // Default taker wallets are named "<unique id>-taker".
+    let wallet_name = test_framework
+        .get_client()
+        .list_wallets()
+        .unwrap()
+        .into_iter()
+        .find(|name| name.ends_with("-taker"))
+        .unwrap();
+    let mut rpc_config = RPCConfig::from(test_framework.as_ref());
+    rpc_config.wallet_name = wallet_name;
+    Client::try_from(&rpc_config)
+        .unwrap()
+        .unload_wallet(None)
+        .unwrap();
+    let status = wallet.health_check().unwrap();
+    assert!(status.node_reachable);
+    assert!(!status.wallet_loaded);
+    assert!(!status.is_ready());
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+
+    // Give bitcoind time to shut down.
+    thread::sleep(Duration::from_secs(5));
+    let status = wallet.health_check().unwrap();
+    assert!(!status.node_reachable);
+    assert_eq!(status.tip_height, None);
+    assert!(!status.is_ready());
+}
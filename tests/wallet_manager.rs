@@ -0,0 +1,86 @@
+#![cfg(feature = "integration-test")]
+use bip39::Mnemonic;
+use bitcoin::Amount;
+use coinswap::{
+    utill::ConnectionType,
+    wallet::{RPCConfig, WalletManager},
+};
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, thread, time::Duration};
+
+/// Test managing several wallets in one process
+///
+/// Wallets created through the manager sync one after the other, and their balances add up.
+#[test]
+fn test_wallet_manager() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let wallets_dir = taker
+        .read()
+        .unwrap()
+        .get_wallet()
+        .get_file_path()
+        .parent()
+        .unwrap()
+        .to_path_buf();
+
+    let mut manager = WalletManager::new(RPCConfig::from(test_framework.as_ref()));
+    for name in ["cautious", "bold"] {
+        let seedphrase = Mnemonic::generate(12).unwrap().to_string();
+        manager
+            .create(&wallets_dir.join(name), seedphrase, "".to_string())
+            .unwrap();
+    }
+
+    // A wallet can't be held twice.
+    assert!(manager.load(&wallets_dir.join("bold")).is_err());
+
+    // ----- Test -----
+
+    manager.sync_all().unwrap();
+    for (name, btc) in [("cautious", 0.01), ("bold", 0.04)] {
+        let wallet = manager.get(name).unwrap();
+        let address = wallet.write().unwrap().get_next_external_address().unwrap();
+        test_framework.send_to_address(&address, Amount::from_btc(btc).unwrap());
+    }
+    test_framework.generate_blocks(1);
+    manager.sync_all().unwrap();
+
+    let names = manager.iter().map(|(name, _)| name).collect::<Vec<_>>();
+    assert_eq!(names, ["bold", "cautious"]);
+
+    let balances = manager.balances().unwrap();
+    assert_eq!(
+        balances["cautious"].spendable,
+        Amount::from_btc(0.01).unwrap()
+    );
+    assert_eq!(balances["bold"].spendable, Amount::from_btc(0.04).unwrap());
+    assert_eq!(
+        manager.total_balances().unwrap().spendable,
+        Amount::from_btc(0.05).unwrap()
+    );
+
+    // A removed wallet loads back from its file.
+    manager.remove("bold").unwrap().unwrap();
+    assert!(manager.get("bold").is_none());
+    manager.load(&wallets_dir.join("bold")).unwrap();
+    manager.sync("bold").unwrap();
+    assert_eq!(
+        manager.total_balances().unwrap().spendable,
+        Amount::from_btc(0.05).unwrap()
+    );
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
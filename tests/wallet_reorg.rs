@@ -0,0 +1,53 @@
+#![cfg(feature = "integration-test")]
+use bitcoind::bitcoincore_rpc::RpcApi;
+use coinswap::utill::ConnectionType;
+
+use coinswap::test_framework::*;
+
+use std::{collections::HashMap, thread, time::Duration};
+
+/// Test Wallet sync across a reorg
+///
+/// The last synced block is invalidated with `invalidateblock` and replaced by a new chain.
+/// The next sync must notice the stale block and rescan from the fork point, ending up on a
+/// block of the new active chain.
+#[test]
+fn test_wallet_sync_after_reorg() {
+    // ---- Setup ----
+
+    let (test_framework, taker, _, directory_server_instance) =
+        TestFramework::init(None, HashMap::new(), None, ConnectionType::CLEARNET);
+
+    let client = test_framework.get_client();
+    let mut taker = taker.write().unwrap();
+    let wallet = taker.get_wallet_mut();
+
+    // ----- Test -----
+
+    // Taker init syncs the wallet once.
+    let (synced_height, synced_hash) = wallet.get_last_synced_block().unwrap();
+    assert_eq!(client.get_block_hash(synced_height).unwrap(), synced_hash);
+
+    // Replace the last synced block, and everything above it, with a longer chain.
+    client.invalidate_block(&synced_hash).unwrap();
+    test_framework.generate_blocks(5);
+    assert_ne!(client.get_block_hash(synced_height).unwrap(), synced_hash);
+
+    wallet.sync().unwrap();
+
+    let (resynced_height, resynced_hash) = wallet.get_last_synced_block().unwrap();
+    assert!(resynced_height >= synced_height);
+    assert_eq!(
+        client.get_block_hash(resynced_height).unwrap(),
+        resynced_hash
+    );
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}
@@ -0,0 +1,163 @@
+#![cfg(feature = "integration-test")]
+use bitcoin::Amount;
+use coinswap::{
+    maker::{refund_expired_swaps, MakerBehavior},
+    taker::SwapParams,
+    utill::ConnectionType,
+    wallet::SwapCoin,
+};
+
+use bitcoind::bitcoincore_rpc::RpcApi;
+
+use coinswap::test_framework::*;
+
+use log::{info, warn};
+use std::{collections::HashSet, thread, time::Duration};
+
+/// Test the expired swap watchdog claiming through the hashlock
+///
+/// The last Maker never got the preimage, and the Taker claims its incoming contracts through
+/// their hashlock instead. The Maker's watchdog reads the preimage from the Taker's claims, and
+/// claims its own incoming contracts the same way rather than refunding its outgoing ones.
+#[test]
+fn test_watchdog_claims_with_revealed_preimage() {
+    // ---- Setup ----
+
+    let makers_config_map = [
+        ((6102, None), MakerBehavior::Normal),
+        ((16102, None), MakerBehavior::Normal),
+    ];
+
+    let (test_framework, taker, makers, directory_server_instance) = TestFramework::init(
+        None,
+        makers_config_map.into(),
+        None,
+        ConnectionType::CLEARNET,
+    );
+
+    warn!("Running Test: Expired swap watchdog claiming through the hashlock");
+
+    test_framework.fund_swap_wallets(&taker, &makers);
+
+    let swap_params = SwapParams {
+        send_amount: Amount::from_sat(500000),
+        maker_count: 2,
+        tx_count: 3,
+        required_confirms: 1,
+        fee_rate: Amount::from_sat(1000),
+    };
+
+    info!("Initiating Makers and the coinswap protocol");
+    TestFramework::run_swap(&taker, &makers, swap_params).unwrap();
+
+    // The last Maker pays the Taker.
+    let taker_incomings = taker
+        .read()
+        .unwrap()
+        .get_wallet()
+        .get_incoming_swapcoin_list()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+    let taker_multisigs = taker_incomings
+        .iter()
+        .map(|ic| ic.get_multisig_redeemscript())
+        .collect::<HashSet<_>>();
+    let last_maker = makers
+        .iter()
+        .find(|maker| {
+            maker
+                .get_wallet()
+                .read()
+                .unwrap()
+                .get_outgoing_swapcoin_list()
+                .unwrap()
+                .values()
+                .any(|oc| taker_multisigs.contains(&oc.get_multisig_redeemscript()))
+        })
+        .unwrap();
+
+    // As if the last Maker never got the preimage, nor the keys of its incoming swapcoins.
+    let maker_incoming_contracts = {
+        let mut wallet = last_maker.get_wallet().write().unwrap();
+        let multisigs = wallet
+            .get_incoming_swapcoin_list()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        multisigs
+            .iter()
+            .map(|multisig| {
+                let incoming = wallet.find_incoming_swapcoin_mut(multisig).unwrap();
+                incoming.hash_preimage = None;
+                incoming.other_privkey = None;
+                incoming.contract_tx.compute_txid()
+            })
+            .collect::<HashSet<_>>()
+    };
+    assert_eq!(maker_incoming_contracts.len(), 3);
+
+    // ----- Test -----
+
+    info!("Claiming the Taker's incoming contracts through the hashlock");
+    for incoming in &taker_incomings {
+        while taker
+            .write()
+            .unwrap()
+            .get_wallet_mut()
+            .claim_incoming_swapcoin(incoming)
+            .unwrap()
+            .is_none()
+        {
+            thread::sleep(Duration::from_secs(5));
+        }
+    }
+
+    info!("Running the watchdog of the last Maker");
+    let mut claim_txids = Vec::new();
+    for _ in 0..20 {
+        claim_txids.extend(refund_expired_swaps(last_maker).unwrap());
+        if claim_txids.len() == maker_incoming_contracts.len() {
+            break;
+        }
+        thread::sleep(Duration::from_secs(5));
+    }
+
+    // Each claim spends one of the Maker's incoming contracts, nothing got refunded.
+    assert_eq!(claim_txids.len(), maker_incoming_contracts.len());
+    let spent_contracts = claim_txids
+        .iter()
+        .map(|txid| {
+            test_framework
+                .get_client()
+                .get_raw_transaction(txid, None)
+                .unwrap()
+                .input[0]
+                .previous_output
+                .txid
+        })
+        .collect::<HashSet<_>>();
+    assert_eq!(spent_contracts, maker_incoming_contracts);
+
+    // The swap is done for the Maker: the incoming swapcoins are claimed, and the outgoing ones
+    // paying the Taker are spent.
+    let wallet = last_maker.get_wallet().read().unwrap();
+    assert!(wallet.get_incoming_swapcoin_list().unwrap().is_empty());
+    assert!(!wallet
+        .get_outgoing_swapcoin_list()
+        .unwrap()
+        .values()
+        .any(|oc| taker_multisigs.contains(&oc.get_multisig_redeemscript())));
+    drop(wallet);
+
+    // ---- Cleanup ----
+
+    // stop directory server
+    let _ = directory_server_instance.shutdown();
+
+    thread::sleep(Duration::from_secs(10));
+
+    test_framework.stop();
+}